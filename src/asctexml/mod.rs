@@ -0,0 +1,509 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Informe XML de CTE y CEE
+========================
+
+Conversión de los datos y resultados al formato XML de CTE y CEE.
+
+Los elementos del informe se describen en el módulo [`xmlmodel`] como estructuras serializables,
+que se obtienen a partir de los tipos de la librería y se serializan con quick-xml.
+*/
+
+pub mod registro;
+pub mod xmlmodel;
+
+use crate::types::*;
+use crate::refs;
+use crate::Components;
+use crate::Factors;
+
+use xmlmodel::*;
+
+// ==================== Conversión a XML de CTE y CEE
+
+/// Muestra en formato XML de CTE y CEE
+///
+/// Esta función usa un formato compatible con el formato XML del certificado de eficiencia
+/// energética del edificio definido en el documento de apoyo de la certificación energética
+/// correspondiente.
+pub trait AsCteXml {
+    /// Get list of values
+    fn to_xml(&self) -> String;
+
+    /// Helper function -> XML escape symbols
+    fn escape_xml(unescaped: &str) -> String {
+        crate::escape::escape_xml(unescaped)
+    }
+
+    /// Convert list of numbers to string of comma separated values (2 decimal digits)
+    fn format_values_2f(values: &[f32]) -> String {
+        values
+            .iter()
+            .map(|v| format!("{:.2}", v))
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+}
+
+/// Comentario opcional de un elemento XML
+fn comentario(comment: &str) -> Option<String> {
+    if comment.is_empty() {
+        None
+    } else {
+        Some(comment.to_string())
+    }
+}
+
+// ================= Conversión a los elementos del modelo XML ====================
+
+impl From<&EnergyPerformance> for BalanceEpbXml {
+    fn from(ep: &EnergyPerformance) -> Self {
+        let RenNrenCo2 { ren, nren, .. } = ep.balance_m2.we.b;
+        Self {
+            factores: (&ep.wfactors).into(),
+            componentes: (&ep.components).into(),
+            kexp: MagnitudXml::new(ep.k_exp, 2, refs::K_EXP),
+            arearef: MagnitudXml::new(ep.arearef, 2, refs::AREAREF),
+            epm2: Epm2Xml {
+                tot: MagnitudXml::new(ren + nren, 1, refs::C_EP_TOT),
+                nren: MagnitudXml::new(nren, 1, refs::C_EP_NREN),
+            },
+        }
+    }
+}
+
+impl From<&Meta> for MetadatoXml {
+    fn from(meta: &Meta) -> Self {
+        Self {
+            clave: meta.key.clone(),
+            valor: meta.value.clone(),
+        }
+    }
+}
+
+impl From<&Factor> for FactorXml {
+    fn from(factor: &Factor) -> Self {
+        let Factor {
+            carrier,
+            source,
+            dest,
+            step,
+            ren,
+            nren,
+            co2,
+            comment,
+            ..
+        } = factor;
+        Self {
+            vector: *carrier,
+            origen: *source,
+            destino: *dest,
+            paso: *step,
+            ren: *ren,
+            nren: *nren,
+            co2: *co2,
+            comentario: comentario(comment),
+        }
+    }
+}
+
+impl From<&Factors> for FactoresDePasoXml {
+    fn from(factors: &Factors) -> Self {
+        Self {
+            meta: factors.wmeta.iter().map(Into::into).collect(),
+            factores: factors.wdata.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<&Components> for ComponentesXml {
+    fn from(components: &Components) -> Self {
+        let Components {
+            meta,
+            data,
+            needs,
+            embodied,
+            systems,
+            efficiencies,
+            peak_powers,
+            storages,
+            ..
+        } = components;
+        let demanda = [Service::ACS, Service::CAL, Service::REF]
+            .iter()
+            .filter_map(|&service| {
+                needs.get(service).map(|values| DemandaXml {
+                    servicio: service,
+                    valores: values.clone(),
+                })
+            })
+            .collect();
+        let mut demanda_zonas = vec![];
+        for (id, zone) in &needs.zones {
+            for service in [Service::ACS, Service::CAL, Service::REF] {
+                if let Some(values) = zone.get(service) {
+                    demanda_zonas.push(DemandaZonaXml {
+                        id: *id,
+                        servicio: service,
+                        valores: values.clone(),
+                    })
+                }
+            }
+        }
+        Self {
+            meta: meta.iter().map(Into::into).collect(),
+            energia: data.iter().map(Into::into).collect(),
+            demanda,
+            demanda_zonas,
+            embebida: embodied.iter().map(Into::into).collect(),
+            sistemas: systems.iter().map(Into::into).collect(),
+            rendimientos: efficiencies.iter().map(Into::into).collect(),
+            potencias_pico: peak_powers.iter().map(Into::into).collect(),
+            almacenamientos: storages.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<&Embodied> for EnergiaEmbebidaXml {
+    fn from(embodied: &Embodied) -> Self {
+        let Embodied { value, comment } = embodied;
+        Self {
+            ren: value.ren,
+            nren: value.nren,
+            co2: value.co2,
+            comentario: comentario(comment),
+        }
+    }
+}
+
+impl From<&SystemEfficiency> for RendimientoSistemaXml {
+    fn from(efficiency: &SystemEfficiency) -> Self {
+        let SystemEfficiency {
+            id,
+            service,
+            value,
+            comment,
+        } = efficiency;
+        Self {
+            id: *id,
+            servicio: *service,
+            valor: *value,
+            comentario: comentario(comment),
+        }
+    }
+}
+
+impl From<&System> for SistemaXml {
+    fn from(system: &System) -> Self {
+        let System {
+            id,
+            kind,
+            power,
+            comment,
+        } = system;
+        Self {
+            id: *id,
+            equipo: kind.clone(),
+            potencia: power.map(|power| power.0),
+            comentario: comentario(comment),
+        }
+    }
+}
+
+impl From<&SystemPeakPower> for PotenciaPicoSistemaXml {
+    fn from(peak_power: &SystemPeakPower) -> Self {
+        let SystemPeakPower { id, value, comment } = peak_power;
+        Self {
+            id: *id,
+            valor: *value,
+            comentario: comentario(comment),
+        }
+    }
+}
+
+impl From<&Storage> for AlmacenamientoXml {
+    fn from(storage: &Storage) -> Self {
+        let Storage {
+            id,
+            capacity,
+            eff_charge,
+            eff_discharge,
+            losses,
+            comment,
+        } = storage;
+        Self {
+            id: *id,
+            capacidad: capacity.0,
+            rend_carga: *eff_charge,
+            rend_descarga: *eff_discharge,
+            perdidas: *losses,
+            comentario: comentario(comment),
+        }
+    }
+}
+
+impl From<&Energy> for EnergiaXml {
+    fn from(energy: &Energy) -> Self {
+        match energy {
+            Energy::Used(e) => EnergiaXml::Consumo(e.into()),
+            Energy::Prod(e) => EnergiaXml::Produccion(e.into()),
+            Energy::Aux(e) => EnergiaXml::EAux(e.into()),
+            Energy::Out(e) => EnergiaXml::Salida(e.into()),
+        }
+    }
+}
+
+impl From<&EProd> for ProduccionXml {
+    fn from(e: &EProd) -> Self {
+        Self {
+            id: e.id,
+            origen: e.source,
+            valores: e.values.clone(),
+            comentario: comentario(&e.comment),
+        }
+    }
+}
+
+impl From<&EUsed> for ConsumoXml {
+    fn from(e: &EUsed) -> Self {
+        Self {
+            id: e.id,
+            vector: e.carrier,
+            servicio: e.service,
+            valores: e.values.clone(),
+            comentario: comentario(&e.comment),
+        }
+    }
+}
+
+impl From<&EAux> for EAuxXml {
+    fn from(e: &EAux) -> Self {
+        Self {
+            id: e.id,
+            servicio: e.service,
+            valores: e.values.clone(),
+            comentario: comentario(&e.comment),
+        }
+    }
+}
+
+impl From<&EOut> for SalidaXml {
+    fn from(e: &EOut) -> Self {
+        Self {
+            id: e.id,
+            servicio: e.service,
+            valores: e.values.clone(),
+            comentario: comentario(&e.comment),
+        }
+    }
+}
+
+// ================= Implementaciones ====================
+
+impl AsCteXml for EnergyPerformance {
+    fn to_xml(&self) -> String {
+        to_xml_string(&BalanceEpbXml::from(self))
+    }
+}
+
+impl AsCteXml for Meta {
+    fn to_xml(&self) -> String {
+        to_xml_string(&MetadatoXml::from(self))
+    }
+}
+
+impl AsCteXml for Factor {
+    fn to_xml(&self) -> String {
+        to_xml_string(&FactorXml::from(self))
+    }
+}
+
+impl AsCteXml for Factors {
+    fn to_xml(&self) -> String {
+        to_xml_string(&FactoresDePasoXml::from(self))
+    }
+}
+
+impl AsCteXml for Components {
+    fn to_xml(&self) -> String {
+        to_xml_string(&ComponentesXml::from(self))
+    }
+}
+
+impl AsCteXml for Embodied {
+    fn to_xml(&self) -> String {
+        to_xml_string(&EnergiaEmbebidaXml::from(self))
+    }
+}
+
+impl AsCteXml for System {
+    fn to_xml(&self) -> String {
+        to_xml_string(&SistemaXml::from(self))
+    }
+}
+
+impl AsCteXml for SystemEfficiency {
+    fn to_xml(&self) -> String {
+        to_xml_string(&RendimientoSistemaXml::from(self))
+    }
+}
+
+impl AsCteXml for SystemPeakPower {
+    fn to_xml(&self) -> String {
+        to_xml_string(&PotenciaPicoSistemaXml::from(self))
+    }
+}
+
+impl AsCteXml for Storage {
+    fn to_xml(&self) -> String {
+        to_xml_string(&AlmacenamientoXml::from(self))
+    }
+}
+
+impl AsCteXml for Energy {
+    fn to_xml(&self) -> String {
+        match self {
+            Energy::Used(e) => e.to_xml(),
+            Energy::Prod(e) => e.to_xml(),
+            Energy::Aux(e) => e.to_xml(),
+            Energy::Out(e) => e.to_xml(),
+        }
+    }
+}
+
+impl AsCteXml for EProd {
+    /// Convierte componente de energía producida a XML
+    fn to_xml(&self) -> String {
+        to_xml_string(&ProduccionXml::from(self))
+    }
+}
+
+impl AsCteXml for EUsed {
+    /// Convierte componente de energía consumida a XML
+    fn to_xml(&self) -> String {
+        to_xml_string(&ConsumoXml::from(self))
+    }
+}
+
+impl AsCteXml for EAux {
+    /// Convierte componente de energía auxiliar a XML
+    fn to_xml(&self) -> String {
+        to_xml_string(&EAuxXml::from(self))
+    }
+}
+
+impl AsCteXml for EOut {
+    /// Convierte componente de energía saliente a XML
+    fn to_xml(&self) -> String {
+        to_xml_string(&SalidaXml::from(self))
+    }
+}
+
+impl AsCteXml for Needs {
+    /// Convierte elementos de demanda del edificio o de zona a XML
+    fn to_xml(&self) -> String {
+        let Self {
+            id,
+            service,
+            values,
+        } = self;
+        if *id == 0 {
+            to_xml_string(&DemandaEdificioXml {
+                servicio: *service,
+                valores: values.clone(),
+            })
+        } else {
+            to_xml_string(&DemandaZonaXml {
+                id: *id,
+                servicio: *service,
+                valores: values.clone(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const TCOMPS: &str = "#META CTE_AREAREF: 100.5
+1, CONSUMO, CAL, ELECTRICIDAD, 1, 2 # BdC <exterior> & \"sala\"
+1, CONSUMO, CAL, EAMBIENTE, 3, 4
+1, PRODUCCION, EL_INSITU, 5, 6
+1, AUX, 0.5, 0.5
+1, SALIDA, CAL, 3.5, 5.5
+0, DEMANDA, CAL, 3.5, 5.5
+2, DEMANDA, ACS, 1, 1
+EMBEBIDA, 1, 2, 3 # Materiales
+ALMACENAMIENTO, 1, 5, 0.95, 0.95, 0.001 # Batería
+SISTEMA, 1, EQUIPO, BDC, 8.5 # Bomba de calor
+";
+
+    #[test]
+    fn xml_escapado_comentarios() {
+        let comps: Components = TCOMPS.parse().unwrap();
+        let xml = comps.to_xml();
+        assert!(xml.contains("<Comentario>BdC &lt;exterior&gt; &amp; \"sala\"</Comentario>"));
+        assert!(xml.contains("<Demanda>\n        <Servicio>CAL</Servicio>"));
+        assert!(xml.contains("</Demanda>"));
+    }
+
+    #[test]
+    fn xml_componentes_round_trip() {
+        let comps: Components = TCOMPS.parse().unwrap();
+        let model = ComponentesXml::from(&comps);
+        let xml = to_xml_string(&model);
+        let parsed: ComponentesXml = from_xml_str(&xml).unwrap();
+        assert_eq!(parsed, model);
+        // Incluye la producción de EAMBIENTE generada al equilibrar el consumo
+        assert_eq!(parsed.energia.len(), 6);
+        assert_eq!(parsed.demanda_zonas[0].id, 2);
+        assert_eq!(parsed.almacenamientos[0].capacidad, 5.0);
+        assert_eq!(parsed.sistemas[0].potencia, Some(8.5));
+        assert_eq!(to_xml_string(&parsed), xml);
+    }
+
+    #[test]
+    fn xml_factores_round_trip() {
+        let factors: Factors = "#META CTE_FUENTE: CTE2019
+ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331 # Recursos usados para suministrar electricidad desde la red
+GASNATURAL, RED, SUMINISTRO, A, 0.005, 1.190, 0.252"
+            .parse()
+            .unwrap();
+        let xml = factors.to_xml();
+        let parsed: FactoresDePasoXml = from_xml_str(&xml).unwrap();
+        assert_eq!(parsed, FactoresDePasoXml::from(&factors));
+        assert_eq!(parsed.factores[1].comentario, None);
+        assert_eq!(to_xml_string(&parsed), xml);
+    }
+
+    #[test]
+    fn xml_incorrecto() {
+        assert!(from_xml_str::<ComponentesXml>("<Componentes><Consumo></Componentes>").is_err());
+    }
+}
@@ -0,0 +1,391 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Exportación XML para registros autonómicos
+==========================================
+
+Generación de documentos XML con estructura propia (p.e. los de las plataformas de registro
+autonómico de certificados de eficiencia energética) a partir de una plantilla de exportación.
+
+La plantilla asigna a cada elemento o atributo del documento un valor procedente de los
+metadatos (administrativos) del archivo de componentes, de los indicadores de eficiencia
+energética o un texto literal. Se define en formato de texto, con una línea por campo:
+
+```text
+#RAIZ RegistroCEE
+# ruta, origen[, decimales]
+@version, "1.0"
+DatosEdificio/ReferenciaCatastral, meta:CTE_REFERENCIA_CATASTRAL
+Indicadores/ConsumoEPNoRenovable, ep:C_ep_nren, 1
+Indicadores/ConsumoEPNoRenovable@unidad, "kWh/m2.an"
+```
+
+- la ruta está formada por los nombres de los elementos separados por `/`, con un atributo
+  opcional final tras `@` (una ruta `@atributo` corresponde al elemento raíz);
+- los elementos con la misma ruta se comparten entre campos, en el orden de su primera aparición;
+- el origen puede ser `meta:CLAVE` (metadato de los componentes), `ep:INDICADOR`, `misc:CLAVE`
+  (resultado adicional de `EnergyPerformance.misc`) o un literal entre comillas;
+- los valores numéricos se muestran con el número de decimales indicado (2 por defecto).
+
+Los indicadores disponibles son `arearef`, `k_exp`, `C_ep_ren`, `C_ep_nren`, `C_ep_tot`, `E_CO2`,
+`RER`, `RER_nrb`, `demanda_ACS`, `demanda_CAL` y `demanda_REF` (por m2 de área de referencia).
+*/
+
+use std::str::FromStr;
+
+use crate::{
+    error::{EpbdError, Result},
+    escape::escape_xml,
+    types::{EnergyPerformance, MetaVec},
+};
+
+/// Origen del valor de un campo de la plantilla de exportación
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrigenCampo {
+    /// Metadato de los componentes energéticos
+    Metadato(String),
+    /// Indicador de eficiencia energética
+    Indicador(String),
+    /// Resultado adicional (`EnergyPerformance.misc`)
+    Misc(String),
+    /// Texto literal
+    Literal(String),
+}
+
+/// Campo de la plantilla de exportación
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CampoRegistro {
+    /// Elementos de la ruta, desde el elemento raíz (excluido)
+    pub elementos: Vec<String>,
+    /// Atributo del último elemento de la ruta, si el valor no es el contenido del elemento
+    pub atributo: Option<String>,
+    /// Origen del valor
+    pub origen: OrigenCampo,
+    /// Número de decimales de los valores numéricos
+    pub decimales: usize,
+}
+
+/// Plantilla de exportación de resultados a XML para registros autonómicos
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlantillaRegistro {
+    /// Nombre del elemento raíz
+    pub raiz: String,
+    /// Campos del documento
+    pub campos: Vec<CampoRegistro>,
+}
+
+impl FromStr for PlantillaRegistro {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut raiz = None;
+        let mut campos = Vec::new();
+        for (num, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix("#RAIZ") {
+                raiz = Some(name.trim().to_string());
+                continue;
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            campos.push(parse_campo(line).map_err(|e| e.at_line(num + 1))?);
+        }
+        let raiz = raiz.filter(|r| is_xml_name(r)).ok_or_else(|| {
+            EpbdError::ParseError(
+                "Plantilla de registro sin nombre válido del elemento raíz (#RAIZ nombre)".into(),
+            )
+        })?;
+        Ok(PlantillaRegistro { raiz, campos })
+    }
+}
+
+/// Interpreta una línea de campo de la plantilla: ruta, origen[, decimales]
+fn parse_campo(line: &str) -> Result<CampoRegistro> {
+    let items: Vec<&str> = line.splitn(3, ',').map(str::trim).collect();
+    let (ruta, origen, decimales) = match items.as_slice() {
+        [ruta, origen] => (*ruta, *origen, None),
+        [ruta, origen, decimales] => (*ruta, *origen, Some(*decimales)),
+        _ => {
+            return Err(EpbdError::ParseError(format!(
+                "Campo de plantilla de registro sin origen del valor: \"{}\"",
+                line
+            )))
+        }
+    };
+
+    let (ruta, atributo) = match ruta.split_once('@') {
+        Some((ruta, atributo)) => (ruta, Some(atributo.to_string())),
+        None => (ruta, None),
+    };
+    let elementos: Vec<String> = ruta
+        .split('/')
+        .filter(|e| !e.is_empty())
+        .map(str::to_string)
+        .collect();
+    if elementos.is_empty() && atributo.is_none()
+        || !elementos.iter().all(|e| is_xml_name(e))
+        || !atributo.as_deref().is_none_or(is_xml_name)
+    {
+        return Err(EpbdError::ParseError(format!(
+            "Ruta incorrecta en la plantilla de registro: \"{}\"",
+            line
+        )));
+    }
+
+    let origen = if let Some(key) = origen.strip_prefix("meta:") {
+        OrigenCampo::Metadato(key.trim().to_string())
+    } else if let Some(key) = origen.strip_prefix("ep:") {
+        let key = key.trim();
+        if !INDICADORES.contains(&key) {
+            return Err(EpbdError::ParseError(format!(
+                "Indicador desconocido en la plantilla de registro: \"{}\" (disponibles: {})",
+                key,
+                INDICADORES.join(", ")
+            )));
+        }
+        OrigenCampo::Indicador(key.to_string())
+    } else if let Some(key) = origen.strip_prefix("misc:") {
+        OrigenCampo::Misc(key.trim().to_string())
+    } else if origen.len() >= 2 && origen.starts_with('"') && origen.ends_with('"') {
+        OrigenCampo::Literal(origen[1..origen.len() - 1].to_string())
+    } else {
+        return Err(EpbdError::ParseError(format!(
+            "Origen del valor desconocido en la plantilla de registro: \"{}\" (meta:, ep:, misc: o \"literal\")",
+            origen
+        )));
+    };
+
+    let decimales = match decimales {
+        Some(d) => d.parse::<usize>().map_err(|_| {
+            EpbdError::ParseError(format!(
+                "Número de decimales incorrecto en la plantilla de registro: \"{}\"",
+                d
+            ))
+        })?,
+        None => 2,
+    };
+
+    Ok(CampoRegistro {
+        elementos,
+        atributo,
+        origen,
+        decimales,
+    })
+}
+
+/// Indicadores de eficiencia energética disponibles en las plantillas
+const INDICADORES: [&str; 11] = [
+    "arearef",
+    "k_exp",
+    "C_ep_ren",
+    "C_ep_nren",
+    "C_ep_tot",
+    "E_CO2",
+    "RER",
+    "RER_nrb",
+    "demanda_ACS",
+    "demanda_CAL",
+    "demanda_REF",
+];
+
+/// Nombre válido (simplificado) de elemento o atributo XML
+fn is_xml_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Valor de un indicador de eficiencia energética
+fn indicador(ep: &EnergyPerformance, key: &str) -> Option<f32> {
+    let we_b = ep.balance_m2.we.b;
+    let needs = &ep.balance_m2.needs;
+    match key {
+        "arearef" => Some(ep.arearef),
+        "k_exp" => Some(ep.k_exp),
+        "C_ep_ren" => Some(we_b.ren),
+        "C_ep_nren" => Some(we_b.nren),
+        "C_ep_tot" => Some(we_b.tot()),
+        "E_CO2" => Some(we_b.co2),
+        "RER" => Some(ep.rer),
+        "RER_nrb" => Some(ep.rer_nrb),
+        "demanda_ACS" => needs.ACS,
+        "demanda_CAL" => needs.CAL,
+        "demanda_REF" => needs.REF,
+        _ => None,
+    }
+}
+
+/// Elemento del documento XML generado
+#[derive(Debug, Default)]
+struct Nodo {
+    nombre: String,
+    atributos: Vec<(String, String)>,
+    texto: Option<String>,
+    hijos: Vec<Nodo>,
+}
+
+impl Nodo {
+    fn hijo(&mut self, nombre: &str) -> &mut Nodo {
+        let pos = match self.hijos.iter().position(|h| h.nombre == nombre) {
+            Some(pos) => pos,
+            None => {
+                self.hijos.push(Nodo {
+                    nombre: nombre.to_string(),
+                    ..Default::default()
+                });
+                self.hijos.len() - 1
+            }
+        };
+        &mut self.hijos[pos]
+    }
+
+    fn write(&self, out: &mut String, nivel: usize) {
+        let indent = "  ".repeat(nivel);
+        out.push_str(&indent);
+        out.push('<');
+        out.push_str(&self.nombre);
+        for (clave, valor) in &self.atributos {
+            out.push_str(&format!(" {}=\"{}\"", clave, escape_xml(valor)));
+        }
+        if self.hijos.is_empty() {
+            match &self.texto {
+                Some(texto) => out.push_str(&format!(">{}</{}>\n", escape_xml(texto), self.nombre)),
+                None => out.push_str("/>\n"),
+            }
+            return;
+        }
+        out.push_str(">\n");
+        if let Some(texto) = &self.texto {
+            out.push_str(&format!("{}  {}\n", indent, escape_xml(texto)));
+        }
+        for hijo in &self.hijos {
+            hijo.write(out, nivel + 1);
+        }
+        out.push_str(&format!("{}</{}>\n", indent, self.nombre));
+    }
+}
+
+impl PlantillaRegistro {
+    /// Genera el documento XML con los datos y resultados de eficiencia energética
+    ///
+    /// Los metadatos se toman de los componentes energéticos usados en el cálculo.
+    ///
+    /// # Errors
+    ///
+    /// * Metadatos, indicadores o resultados adicionales no disponibles, con la lista de todos ellos
+    pub fn render(&self, ep: &EnergyPerformance) -> Result<String> {
+        let mut raiz = Nodo {
+            nombre: self.raiz.clone(),
+            ..Default::default()
+        };
+        let mut faltan = Vec::new();
+        for campo in &self.campos {
+            let valor = match &campo.origen {
+                OrigenCampo::Metadato(key) => ep.components.get_meta(key),
+                OrigenCampo::Indicador(key) => {
+                    indicador(ep, key).map(|v| format!("{:.*}", campo.decimales, v))
+                }
+                OrigenCampo::Misc(key) => ep.misc.as_ref().and_then(|m| m.get(key)).map(|v| {
+                    v.parse::<f32>()
+                        .map(|n| format!("{:.*}", campo.decimales, n))
+                        .unwrap_or_else(|_| v.clone())
+                }),
+                OrigenCampo::Literal(text) => Some(text.clone()),
+            };
+            let Some(valor) = valor else {
+                faltan.push(match &campo.origen {
+                    OrigenCampo::Metadato(key) => format!("meta:{}", key),
+                    OrigenCampo::Indicador(key) => format!("ep:{}", key),
+                    OrigenCampo::Misc(key) => format!("misc:{}", key),
+                    OrigenCampo::Literal(_) => unreachable!(),
+                });
+                continue;
+            };
+            let mut nodo = &mut raiz;
+            for elemento in &campo.elementos {
+                nodo = nodo.hijo(elemento);
+            }
+            match &campo.atributo {
+                Some(atributo) => nodo.atributos.push((atributo.clone(), valor)),
+                None => nodo.texto = Some(valor),
+            }
+        }
+        if !faltan.is_empty() {
+            return Err(EpbdError::WrongInput(format!(
+                "Valores no disponibles para la plantilla de registro: {}",
+                faltan.join(", ")
+            )));
+        }
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        raiz.write(&mut out, 0);
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plantilla_registro_parse() {
+        let plantilla = "#RAIZ RegistroCEE
+# Comentario
+@version, \"1.0\"
+DatosEdificio/ReferenciaCatastral, meta:CTE_REFERENCIA_CATASTRAL
+Indicadores/ConsumoEPNoRenovable, ep:C_ep_nren, 1
+Indicadores/ConsumoEPNoRenovable@unidad, \"kWh/m2.an\""
+            .parse::<PlantillaRegistro>()
+            .unwrap();
+        assert_eq!(plantilla.raiz, "RegistroCEE");
+        assert_eq!(plantilla.campos.len(), 4);
+        assert_eq!(
+            plantilla.campos[2],
+            CampoRegistro {
+                elementos: vec!["Indicadores".into(), "ConsumoEPNoRenovable".into()],
+                atributo: None,
+                origen: OrigenCampo::Indicador("C_ep_nren".into()),
+                decimales: 1,
+            }
+        );
+        assert_eq!(plantilla.campos[0].atributo.as_deref(), Some("version"));
+
+        // Errores
+        assert!("A, \"1\"".parse::<PlantillaRegistro>().is_err());
+        assert!("#RAIZ R\nA, ep:desconocido"
+            .parse::<PlantillaRegistro>()
+            .is_err());
+        assert!("#RAIZ R\nA/1B, \"x\"".parse::<PlantillaRegistro>().is_err());
+        assert!("#RAIZ R\nA, otro:x".parse::<PlantillaRegistro>().is_err());
+        assert_eq!(
+            "#RAIZ R\nA, ep:RER, x"
+                .parse::<PlantillaRegistro>()
+                .unwrap_err()
+                .to_string(),
+            "Error de formato: línea 2: Número de decimales incorrecto en la plantilla de registro: \"x\""
+        );
+    }
+}
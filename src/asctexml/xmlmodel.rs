@@ -0,0 +1,585 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Modelo del informe XML
+======================
+
+Estructuras serializables (serde + quick-xml) que describen los elementos del informe XML
+de CTE y CEE.
+
+El informe se genera serializando estas estructuras, de modo que el escapado de textos lo
+realiza el propio serializador, y puede volver a leerse con [`from_xml_str`] para su
+comprobación o para su uso por otras aplicaciones.
+
+Los valores numéricos se redondean al serializar con la misma precisión que se usa en el informe
+(p.e. 3 decimales en los factores de paso y 2 en los valores de los componentes).
+*/
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::{EpbdError, Result};
+use crate::types::{Carrier, Dest, ProdSource, Service, Source, Step};
+
+/// Serializa un elemento del modelo como texto XML
+///
+/// El elemento raíz toma el nombre de la estructura serializada (p.e. `<BalanceEPB>`)
+/// y los elementos anidados se indentan con 4 espacios.
+pub fn to_xml_string<T: Serialize>(value: &T) -> String {
+    let mut buffer = String::new();
+    let mut serializer = quick_xml::se::Serializer::new(&mut buffer);
+    serializer.indent(' ', 4);
+    // La serialización de estas estructuras a un String no puede fallar
+    value
+        .serialize(serializer)
+        .expect("Error inesperado al serializar a XML");
+    buffer
+}
+
+/// Interpreta un texto XML como un elemento del modelo
+pub fn from_xml_str<T: DeserializeOwned>(xml: &str) -> Result<T> {
+    quick_xml::de::from_str(xml)
+        .map_err(|e| EpbdError::ParseError(format!("XML incorrecto: {}", e)))
+}
+
+/// Balance energético del edificio (elemento `<BalanceEPB>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "BalanceEPB")]
+pub struct BalanceEpbXml {
+    /// Factores de paso
+    #[serde(rename = "FactoresDePaso")]
+    pub factores: FactoresDePasoXml,
+    /// Componentes energéticos
+    #[serde(rename = "Componentes")]
+    pub componentes: ComponentesXml,
+    /// Factor de exportación, k_exp
+    pub kexp: MagnitudXml,
+    /// Área de referencia [m2]
+    #[serde(rename = "AreaRef")]
+    pub arearef: MagnitudXml,
+    /// Consumo de energía primaria por m2, C_ep [kWh/m2.an]
+    #[serde(rename = "Epm2")]
+    pub epm2: Epm2Xml,
+}
+
+/// Consumo de energía primaria por m2, C_ep [kWh/m2.an] (elemento `<Epm2>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Epm2")]
+pub struct Epm2Xml {
+    /// Consumo de energía primaria total
+    pub tot: MagnitudXml,
+    /// Consumo de energía primaria no renovable
+    pub nren: MagnitudXml,
+}
+
+/// Magnitud de resultados con la referencia normativa de la que deriva
+///
+/// Se serializa con la referencia (fórmula de la UNE-EN ISO 52000-1 o apartado del DB-HE) como
+/// atributo `ref` y el valor redondeado al número de decimales indicado como texto,
+/// p.e. `<tot ref="DB-HE 2019, HE0, apartado 3.2">125.3</tot>`.
+///
+/// Al leerla, el número de decimales se obtiene del texto del valor.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "MagnitudXmlTexto")]
+pub struct MagnitudXml {
+    /// Referencia normativa
+    pub referencia: String,
+    /// Valor
+    pub valor: f32,
+    /// Número de decimales usados al serializar el valor
+    pub decimales: usize,
+}
+
+/// Magnitud leída del XML, con el valor como texto
+#[derive(Deserialize)]
+struct MagnitudXmlTexto {
+    #[serde(rename = "@ref", default)]
+    referencia: String,
+    #[serde(rename = "$text")]
+    valor: String,
+}
+
+impl TryFrom<MagnitudXmlTexto> for MagnitudXml {
+    type Error = String;
+
+    fn try_from(m: MagnitudXmlTexto) -> std::result::Result<Self, Self::Error> {
+        let texto = m.valor.trim();
+        let valor = texto
+            .parse::<f32>()
+            .map_err(|_| format!("se esperaba un valor numérico: {}", texto))?;
+        let decimales = texto.split_once('.').map(|(_, d)| d.len()).unwrap_or(0);
+        Ok(Self {
+            referencia: m.referencia,
+            valor,
+            decimales,
+        })
+    }
+}
+
+impl MagnitudXml {
+    /// Magnitud con valor, número de decimales y referencia normativa
+    pub fn new(valor: f32, decimales: usize, referencia: &str) -> Self {
+        Self {
+            referencia: referencia.to_string(),
+            valor,
+            decimales,
+        }
+    }
+}
+
+impl Serialize for MagnitudXml {
+    fn serialize<S: Serializer>(&self, s: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut st = s.serialize_struct("MagnitudXml", 2)?;
+        st.serialize_field("@ref", &self.referencia)?;
+        st.serialize_field("$text", &format!("{:.*}", self.decimales, self.valor))?;
+        st.end()
+    }
+}
+
+/// Factores de paso (elemento `<FactoresDePaso>`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "FactoresDePaso")]
+pub struct FactoresDePasoXml {
+    /// Metadatos
+    #[serde(rename = "Metadato", default)]
+    pub meta: Vec<MetadatoXml>,
+    /// Factores de paso
+    #[serde(rename = "Factor", default)]
+    pub factores: Vec<FactorXml>,
+}
+
+/// Metadato (elemento `<Metadato>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Metadato")]
+pub struct MetadatoXml {
+    /// Clave
+    #[serde(rename = "Clave")]
+    pub clave: String,
+    /// Valor
+    #[serde(rename = "Valor")]
+    pub valor: String,
+}
+
+/// Factor de paso (elemento `<Factor>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Factor")]
+pub struct FactorXml {
+    /// Vector energético
+    #[serde(rename = "Vector")]
+    pub vector: Carrier,
+    /// Origen de la energía
+    #[serde(rename = "Origen")]
+    pub origen: Source,
+    /// Destino de la energía
+    #[serde(rename = "Destino")]
+    pub destino: Dest,
+    /// Paso del cálculo
+    #[serde(rename = "Paso")]
+    pub paso: Step,
+    /// Factor de paso a energía primaria renovable
+    #[serde(serialize_with = "ser_3f")]
+    pub ren: f32,
+    /// Factor de paso a energía primaria no renovable
+    #[serde(serialize_with = "ser_3f")]
+    pub nren: f32,
+    /// Factor de paso a emisiones
+    #[serde(serialize_with = "ser_3f")]
+    pub co2: f32,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+/// Componentes energéticos (elemento `<Componentes>`)
+///
+/// Los componentes de energía (consumos, producciones, auxiliares y salidas) mantienen
+/// el orden de los datos de entrada.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Componentes")]
+pub struct ComponentesXml {
+    /// Metadatos
+    #[serde(rename = "Metadato", default)]
+    pub meta: Vec<MetadatoXml>,
+    /// Componentes de energía
+    #[serde(rename = "$value", default)]
+    pub energia: Vec<EnergiaXml>,
+    /// Demanda del edificio
+    #[serde(rename = "Demanda", default)]
+    pub demanda: Vec<DemandaXml>,
+    /// Demanda de las zonas
+    #[serde(rename = "DemandaZona", default)]
+    pub demanda_zonas: Vec<DemandaZonaXml>,
+    /// Energía embebida
+    #[serde(rename = "EnergiaEmbebida", default)]
+    pub embebida: Vec<EnergiaEmbebidaXml>,
+    /// Equipos de sistemas
+    #[serde(rename = "Sistema", default)]
+    pub sistemas: Vec<SistemaXml>,
+    /// Rendimientos de sistemas
+    #[serde(rename = "RendimientoSistema", default)]
+    pub rendimientos: Vec<RendimientoSistemaXml>,
+    /// Potencias pico de instalaciones fotovoltaicas
+    #[serde(rename = "PotenciaPicoSistema", default)]
+    pub potencias_pico: Vec<PotenciaPicoSistemaXml>,
+    /// Almacenamientos eléctricos (baterías)
+    #[serde(rename = "Almacenamiento", default)]
+    pub almacenamientos: Vec<AlmacenamientoXml>,
+}
+
+/// Componente de energía
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EnergiaXml {
+    /// Energía consumida
+    Consumo(ConsumoXml),
+    /// Energía producida
+    Produccion(ProduccionXml),
+    /// Energía auxiliar
+    EAux(EAuxXml),
+    /// Energía saliente del sistema
+    Salida(SalidaXml),
+}
+
+/// Energía consumida (elemento `<Consumo>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Consumo")]
+pub struct ConsumoXml {
+    /// Identificador del sistema
+    #[serde(rename = "Id")]
+    pub id: i32,
+    /// Vector energético
+    #[serde(rename = "Vector")]
+    pub vector: Carrier,
+    /// Servicio
+    #[serde(rename = "Servicio")]
+    pub servicio: Service,
+    /// Valores de los pasos de cálculo
+    #[serde(
+        rename = "Valores",
+        serialize_with = "ser_values_2f",
+        deserialize_with = "de_values"
+    )]
+    pub valores: Vec<f32>,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+/// Energía producida (elemento `<Produccion>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Produccion")]
+pub struct ProduccionXml {
+    /// Identificador del sistema
+    #[serde(rename = "Id")]
+    pub id: i32,
+    /// Origen de la producción
+    #[serde(rename = "Origen")]
+    pub origen: ProdSource,
+    /// Valores de los pasos de cálculo
+    #[serde(
+        rename = "Valores",
+        serialize_with = "ser_values_2f",
+        deserialize_with = "de_values"
+    )]
+    pub valores: Vec<f32>,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+/// Energía auxiliar (elemento `<EAux>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "EAux")]
+pub struct EAuxXml {
+    /// Identificador del sistema
+    #[serde(rename = "Id")]
+    pub id: i32,
+    /// Servicio
+    #[serde(rename = "Servicio")]
+    pub servicio: Service,
+    /// Valores de los pasos de cálculo
+    #[serde(
+        rename = "Valores",
+        serialize_with = "ser_values_2f",
+        deserialize_with = "de_values"
+    )]
+    pub valores: Vec<f32>,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+/// Energía saliente del sistema (elemento `<Salida>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Salida")]
+pub struct SalidaXml {
+    /// Identificador del sistema
+    #[serde(rename = "Id")]
+    pub id: i32,
+    /// Servicio
+    #[serde(rename = "Servicio")]
+    pub servicio: Service,
+    /// Valores de los pasos de cálculo
+    #[serde(
+        rename = "Valores",
+        serialize_with = "ser_values_2f",
+        deserialize_with = "de_values"
+    )]
+    pub valores: Vec<f32>,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+/// Demanda del edificio en los componentes (elemento `<Demanda>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Demanda")]
+pub struct DemandaXml {
+    /// Servicio
+    #[serde(rename = "Servicio")]
+    pub servicio: Service,
+    /// Valores de los pasos de cálculo
+    #[serde(
+        rename = "Valores",
+        serialize_with = "ser_values_2f",
+        deserialize_with = "de_values"
+    )]
+    pub valores: Vec<f32>,
+}
+
+/// Demanda del edificio (elemento `<DemandaEdificio>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "DemandaEdificio")]
+pub struct DemandaEdificioXml {
+    /// Servicio
+    #[serde(rename = "Servicio")]
+    pub servicio: Service,
+    /// Valores de los pasos de cálculo
+    #[serde(
+        rename = "Valores",
+        serialize_with = "ser_values_2f",
+        deserialize_with = "de_values"
+    )]
+    pub valores: Vec<f32>,
+}
+
+/// Demanda de una zona (elemento `<DemandaZona>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "DemandaZona")]
+pub struct DemandaZonaXml {
+    /// Identificador de la zona
+    #[serde(rename = "Id")]
+    pub id: i32,
+    /// Servicio
+    #[serde(rename = "Servicio")]
+    pub servicio: Service,
+    /// Valores de los pasos de cálculo
+    #[serde(
+        rename = "Valores",
+        serialize_with = "ser_values_2f",
+        deserialize_with = "de_values"
+    )]
+    pub valores: Vec<f32>,
+}
+
+/// Energía embebida (elemento `<EnergiaEmbebida>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "EnergiaEmbebida")]
+pub struct EnergiaEmbebidaXml {
+    /// Energía primaria renovable equivalente anual
+    #[serde(serialize_with = "ser_2f")]
+    pub ren: f32,
+    /// Energía primaria no renovable equivalente anual
+    #[serde(serialize_with = "ser_2f")]
+    pub nren: f32,
+    /// Emisiones equivalentes anuales
+    #[serde(serialize_with = "ser_2f")]
+    pub co2: f32,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+/// Equipo de un sistema (elemento `<Sistema>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Sistema")]
+pub struct SistemaXml {
+    /// Identificador del sistema
+    #[serde(rename = "Id")]
+    pub id: i32,
+    /// Tipo de equipo
+    #[serde(rename = "Equipo")]
+    pub equipo: String,
+    /// Potencia nominal, kW
+    #[serde(
+        rename = "Potencia",
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "ser_opt_2f"
+    )]
+    pub potencia: Option<f32>,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+/// Rendimiento estacional de un sistema (elemento `<RendimientoSistema>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "RendimientoSistema")]
+pub struct RendimientoSistemaXml {
+    /// Identificador del sistema
+    #[serde(rename = "Id")]
+    pub id: i32,
+    /// Servicio
+    #[serde(rename = "Servicio")]
+    pub servicio: Service,
+    /// Rendimiento estacional
+    #[serde(rename = "Valor", serialize_with = "ser_3f")]
+    pub valor: f32,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+/// Potencia pico de una instalación fotovoltaica (elemento `<PotenciaPicoSistema>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "PotenciaPicoSistema")]
+pub struct PotenciaPicoSistemaXml {
+    /// Identificador del sistema
+    #[serde(rename = "Id")]
+    pub id: i32,
+    /// Potencia pico, kWp
+    #[serde(rename = "Valor", serialize_with = "ser_3f")]
+    pub valor: f32,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+/// Almacenamiento eléctrico (elemento `<Almacenamiento>`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "Almacenamiento")]
+pub struct AlmacenamientoXml {
+    /// Identificador del sistema
+    #[serde(rename = "Id")]
+    pub id: i32,
+    /// Capacidad útil, kWh
+    #[serde(rename = "Capacidad", serialize_with = "ser_2f")]
+    pub capacidad: f32,
+    /// Rendimiento de carga
+    #[serde(rename = "RendimientoCarga", serialize_with = "ser_3f")]
+    pub rend_carga: f32,
+    /// Rendimiento de descarga
+    #[serde(rename = "RendimientoDescarga", serialize_with = "ser_3f")]
+    pub rend_descarga: f32,
+    /// Pérdidas por paso de cálculo (fracción de la energía almacenada)
+    #[serde(rename = "Perdidas", serialize_with = "ser_4f")]
+    pub perdidas: f32,
+    /// Comentario
+    #[serde(
+        rename = "Comentario",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub comentario: Option<String>,
+}
+
+// ================= Funciones auxiliares de serialización ====================
+
+fn ser_2f<S: Serializer>(value: &f32, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_str(&format!("{:.2}", value))
+}
+
+fn ser_opt_2f<S: Serializer>(value: &Option<f32>, s: S) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => ser_2f(value, s),
+        None => s.serialize_none(),
+    }
+}
+
+fn ser_3f<S: Serializer>(value: &f32, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_str(&format!("{:.3}", value))
+}
+
+fn ser_4f<S: Serializer>(value: &f32, s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_str(&format!("{:.4}", value))
+}
+
+/// Lista de valores separados por comas (con 2 decimales)
+fn ser_values_2f<S: Serializer>(values: &[f32], s: S) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_str(
+        &values
+            .iter()
+            .map(|v| format!("{:.2}", v))
+            .collect::<Vec<String>>()
+            .join(","),
+    )
+}
+
+/// Interpreta una lista de valores separados por comas
+fn de_values<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Vec<f32>, D::Error> {
+    let s = String::deserialize(d)?;
+    s.split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse::<f32>().map_err(serde::de::Error::custom))
+        .collect()
+}
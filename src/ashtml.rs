@@ -0,0 +1,350 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Informe HTML
+============
+
+Generación de un informe autocontenido en formato HTML (una única página, con estilos incluidos y
+sin recursos externos) con los resultados del cálculo de la eficiencia energética.
+
+El informe incluye un índice de navegación y las siguientes secciones:
+
+- indicadores: energía primaria (paso B), emisiones y fracciones renovables, por m2;
+- balance global: energía usada, producida, suministrada y exportada, por m2;
+- balance por vector energético y por servicio, por m2;
+- diagrama Sankey de flujos de energía (ver `AsSankeySvg`);
+- metadatos de entrada de los componentes y de los factores de paso;
+- avisos del cálculo.
+*/
+
+use std::fmt::Write;
+
+use crate::{
+    escape::escape_xml,
+    types::{EnergyPerformance, Meta, RenNrenCo2},
+    AsSankeySvg,
+};
+
+// ==================== Conversión a informe HTML
+
+/// Muestra como informe HTML autocontenido
+pub trait AsCteHtml {
+    /// Get as self contained HTML report
+    fn to_html(&self) -> String;
+}
+
+// ================= Implementaciones ====================
+
+/// Estilos del informe
+const STYLE: &str =
+    "body{font-family:sans-serif;margin:0 auto;max-width:960px;padding:1em;color:#222}
+nav{position:sticky;top:0;background:#f4f4f4;padding:.5em;border-bottom:1px solid #ccc}
+nav a{margin-right:1em}
+table{border-collapse:collapse;margin:.5em 0 1.5em}
+th,td{border:1px solid #ccc;padding:.25em .6em}
+td.num{text-align:right;font-variant-numeric:tabular-nums}
+th{background:#eee}
+svg{max-width:100%;height:auto}";
+
+/// Secciones del informe (ancla, título)
+const SECTIONS: [(&str, &str); 7] = [
+    ("indicadores", "Indicadores"),
+    ("balance", "Balance global"),
+    ("vectores", "Balance por vector"),
+    ("servicios", "Balance por servicio"),
+    ("sankey", "Diagrama de flujos"),
+    ("metadatos", "Metadatos"),
+    ("avisos", "Avisos"),
+];
+
+impl AsCteHtml for EnergyPerformance {
+    fn to_html(&self) -> String {
+        let mut out =
+            String::from("<!DOCTYPE html>\n<html lang=\"es\">\n<head>\n<meta charset=\"utf-8\">\n");
+        let _ = writeln!(out, "<title>Informe de eficiencia energética</title>");
+        let _ = writeln!(out, "<style>\n{}\n</style>\n</head>\n<body>", STYLE);
+        let _ = writeln!(out, "<h1>Informe de eficiencia energética</h1>");
+        let _ = writeln!(
+            out,
+            "<p>Área de referencia: {:.2} m². Factor de exportación: {:.2}</p>",
+            self.arearef, self.k_exp
+        );
+        let links = SECTIONS
+            .iter()
+            .map(|(id, title)| format!("<a href=\"#{}\">{}</a>", id, title))
+            .collect::<Vec<_>>()
+            .join("");
+        let _ = writeln!(out, "<nav>{}</nav>", links);
+
+        // Indicadores
+        let ep = &self.balance_m2.we.b;
+        section(&mut out, 0);
+        table(
+            &mut out,
+            &["Indicador", "Valor"],
+            &[
+                row("C<sub>ep,ren</sub> [kWh/m².an]", &[ep.ren]),
+                row("C<sub>ep,nren</sub> [kWh/m².an]", &[ep.nren]),
+                row("C<sub>ep,tot</sub> [kWh/m².an]", &[ep.tot()]),
+                row("E<sub>CO2</sub> [kg_CO2e/m².an]", &[ep.co2]),
+                row("RER", &[self.rer]),
+                row("RER<sub>nrb</sub>", &[self.rer_nrb]),
+                row("RER<sub>onst</sub>", &[self.rer_onst]),
+            ],
+        );
+
+        // Balance global
+        let bal = &self.balance_m2;
+        section(&mut out, 1);
+        table(
+            &mut out,
+            &["Magnitud", "kWh/m².an"],
+            &[
+                row("Energía usada en servicios EPB", &[bal.used.epus]),
+                row("Energía usada en servicios no EPB", &[bal.used.nepus]),
+                row("Energía producida", &[bal.prod.an]),
+                row("Energía suministrada", &[bal.del.an]),
+                row("Energía exportada", &[bal.exp.an]),
+            ],
+        );
+
+        // Balance por vector energético
+        let k_area = 1.0 / self.arearef;
+        section(&mut out, 2);
+        let rows = self
+            .balance_cr
+            .iter()
+            .map(|(carrier, bc)| {
+                let values = [
+                    bc.used.epus_an,
+                    bc.used.nepus_an,
+                    bc.prod.an,
+                    bc.del.grid_an,
+                    bc.del.onst_an,
+                    bc.exp.an,
+                ]
+                .map(|v| v * k_area);
+                let we = bc.we.b * k_area;
+                let mut cells = values.to_vec();
+                cells.extend(weighted(&we));
+                row(&escape_xml(&carrier.to_string()), &cells)
+            })
+            .collect::<Vec<_>>();
+        table(
+            &mut out,
+            &[
+                "Vector",
+                "Usada EPB",
+                "Usada no EPB",
+                "Producida",
+                "Suministrada red",
+                "Suministrada in situ",
+                "Exportada",
+                "E<sub>p,ren</sub>",
+                "E<sub>p,nren</sub>",
+                "E<sub>p,tot</sub>",
+                "CO2",
+            ],
+            &rows,
+        );
+        note(
+            &mut out,
+            "Energía en kWh/m².an y emisiones en kg_CO2e/m².an. Energía ponderada en el paso B.",
+        );
+
+        // Balance por servicio
+        section(&mut out, 3);
+        let rows = bal
+            .used
+            .epus_by_srv
+            .iter()
+            .map(|(service, used)| {
+                let we_a = bal.we.a_by_srv.get(service).copied().unwrap_or_default();
+                let we_b = bal.we.b_by_srv.get(service).copied().unwrap_or_default();
+                let mut cells = vec![*used];
+                cells.extend(weighted(&we_a));
+                cells.extend(weighted(&we_b));
+                row(&escape_xml(&service.to_string()), &cells)
+            })
+            .collect::<Vec<_>>();
+        table(
+            &mut out,
+            &[
+                "Servicio",
+                "Usada",
+                "E<sub>p,ren</sub> (A)",
+                "E<sub>p,nren</sub> (A)",
+                "E<sub>p,tot</sub> (A)",
+                "CO2 (A)",
+                "E<sub>p,ren</sub> (B)",
+                "E<sub>p,nren</sub> (B)",
+                "E<sub>p,tot</sub> (B)",
+                "CO2 (B)",
+            ],
+            &rows,
+        );
+        note(&mut out, "Energía en kWh/m².an y emisiones en kg_CO2e/m².an. Energía ponderada en los pasos A y B.");
+
+        // Diagrama Sankey
+        section(&mut out, 4);
+        let _ = writeln!(out, "{}", self.to_sankey_svg().trim_end());
+
+        // Metadatos
+        section(&mut out, 5);
+        let _ = writeln!(out, "<h3>Componentes</h3>");
+        meta_table(&mut out, &self.components.meta);
+        let _ = writeln!(out, "<h3>Factores de paso</h3>");
+        meta_table(&mut out, &self.wfactors.wmeta);
+
+        // Avisos
+        section(&mut out, 6);
+        if self.warnings.is_empty() {
+            note(&mut out, "Sin avisos.");
+        } else {
+            let rows = self
+                .warnings
+                .iter()
+                .map(|w| {
+                    vec![
+                        w.severity.to_string(),
+                        w.code.to_string(),
+                        escape_xml(&w.message),
+                        escape_xml(w.component.as_deref().unwrap_or("-")),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            table(
+                &mut out,
+                &["Gravedad", "Código", "Descripción", "Componente"],
+                &rows,
+            );
+        }
+
+        let _ = writeln!(out, "</body>\n</html>");
+        out
+    }
+}
+
+/// Valores ren, nren, tot y co2 de una energía ponderada
+fn weighted(value: &RenNrenCo2) -> [f32; 4] {
+    [value.ren, value.nren, value.tot(), value.co2]
+}
+
+/// Fila de una tabla con una etiqueta y valores numéricos con dos decimales
+///
+/// La etiqueta se inserta como HTML, por lo que debe ser un texto de confianza o estar escapada
+/// (ver `escape_xml`), p.e. para los nombres de vectores de usuario.
+fn row(label: &str, values: &[f32]) -> Vec<String> {
+    std::iter::once(label.to_string())
+        .chain(values.iter().map(|v| format!("{:.2}", v)))
+        .collect()
+}
+
+/// Añade el encabezado de la sección indicada (posición en `SECTIONS`)
+fn section(out: &mut String, idx: usize) {
+    let (id, title) = SECTIONS[idx];
+    let _ = writeln!(out, "<h2 id=\"{}\">{}</h2>", id, title);
+}
+
+/// Añade un párrafo de texto
+fn note(out: &mut String, text: &str) {
+    let _ = writeln!(out, "<p>{}</p>", text);
+}
+
+/// Añade una tabla con los encabezados y filas indicados
+///
+/// La primera celda de cada fila se muestra como encabezado de fila y el resto alineadas a la derecha.
+/// Los encabezados y celdas se insertan como HTML sin escapar (p.e. para usar subíndices), por lo
+/// que los textos procedentes de los datos de entrada deben escaparse antes con `escape_xml`.
+fn table(out: &mut String, headers: &[&str], rows: &[Vec<String>]) {
+    let _ = writeln!(out, "<table>");
+    let headers = headers
+        .iter()
+        .map(|h| format!("<th>{}</th>", h))
+        .collect::<String>();
+    let _ = writeln!(out, "<tr>{}</tr>", headers);
+    for cells in rows {
+        let mut line = String::from("<tr>");
+        for (i, cell) in cells.iter().enumerate() {
+            if i == 0 {
+                let _ = write!(line, "<th>{}</th>", cell);
+            } else {
+                let _ = write!(line, "<td class=\"num\">{}</td>", cell);
+            }
+        }
+        let _ = writeln!(out, "{}</tr>", line);
+    }
+    let _ = writeln!(out, "</table>");
+}
+
+/// Añade una tabla de metadatos (clave, valor)
+fn meta_table(out: &mut String, meta: &[Meta]) {
+    if meta.is_empty() {
+        note(out, "Sin metadatos.");
+        return;
+    }
+    let _ = writeln!(out, "<table>\n<tr><th>Clave</th><th>Valor</th></tr>");
+    for m in meta {
+        let _ = writeln!(
+            out,
+            "<tr><th>{}</th><td>{}</td></tr>",
+            escape_xml(&m.key),
+            escape_xml(&m.value)
+        );
+    }
+    let _ = writeln!(out, "</table>");
+}
+
+// ========================== Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_table_and_escape() {
+        let mut out = String::new();
+        table(
+            &mut out,
+            &["Vector", "Valor"],
+            &[row("ELECTRICIDAD", &[1.234]), row("GASNATURAL", &[0.0])],
+        );
+        assert_eq!(out.matches("<tr>").count(), 3);
+        assert!(out.contains("<tr><th>ELECTRICIDAD</th><td class=\"num\">1.23</td></tr>"));
+
+        let mut out = String::new();
+        table(&mut out, &["Vector"], &[row(&escape_xml("RED<3>"), &[])]);
+        assert!(out.contains("<th>RED&lt;3&gt;</th>"));
+
+        let mut out = String::new();
+        meta_table(&mut out, &[Meta::new("CTE_COMENTARIO", "a < b & \"c\"")]);
+        assert!(out.contains("a &lt; b &amp; &quot;c&quot;"));
+
+        let mut out = String::new();
+        meta_table(&mut out, &[]);
+        assert!(out.contains("Sin metadatos."));
+    }
+}
@@ -35,7 +35,12 @@ use crate::types::*;
 /// eficiencia energética del edificio, datos y balances
 pub trait AsCtePlain {
     /// Get in plan format
-    fn to_plain(&self) -> String;
+    fn to_plain(&self) -> String {
+        self.to_plain_in(BalanceUnits::default())
+    }
+
+    /// Get in plain format, with balance results in the given units
+    fn to_plain_in(&self, units: BalanceUnits) -> String;
 }
 
 // ================= Implementaciones ====================
@@ -61,16 +66,95 @@ fn value_or_dash(v: Option<f32>, precision: usize) -> String {
 
 impl AsCtePlain for EnergyPerformance {
     /// Está mostrando únicamente los resultados
-    fn to_plain(&self) -> String {
+    ///
+    /// Los indicadores reglamentarios se muestran siempre por m2 y los balances en las unidades indicadas
+    fn to_plain_in(&self, units: BalanceUnits) -> String {
         // Datos generales
-        let bal = &self.balance_m2;
+        let bal = self.balance_in(units);
+        let area = self.units_area(units);
+        let energy_units = units.energy();
+        let co2_units = units.co2();
         let k_exp = self.k_exp;
+        let kexp_by_cr = self.components.kexp_by_carrier().unwrap_or_default();
+        let kexp_cr_out = if kexp_by_cr.is_empty() {
+            String::new()
+        } else {
+            let by_cr = kexp_by_cr
+                .iter()
+                .map(|(carrier, k)| format!("{}: {:.2}", carrier, k))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(" ({})", by_cr)
+        };
         let arearef = self.arearef;
+        let uso_out = match crate::cte::uso_edificio(&self.components) {
+            Ok(Some(uso)) => {
+                let limites = crate::cte::zona_climatica_invierno(&self.components)
+                    .and_then(|zona| {
+                        let cfi = self.components.get_meta_f32("CTE_CFI").unwrap_or_default();
+                        uso.limites_he0(&zona, cfi).ok().map(|lim| (zona, lim))
+                    })
+                    .map(|(zona, (nren_lim, tot_lim))| {
+                        format!("\nC_ep,lim (zona {zona}) [kWh/m2.an]: nren = {nren_lim:.1}, tot = {tot_lim:.1}")
+                    })
+                    .unwrap_or_default();
+                format!("\nUso del edificio: {}{}", uso.desc(), limites)
+            }
+            _ => String::new(),
+        };
+
+        let nrb_opt_out = {
+            let nearby_opt = self.components.nearby_opt_carriers();
+            if nearby_opt.is_empty() {
+                String::new()
+            } else {
+                let list = nearby_opt
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!(" (incluye {list} en el perímetro próximo)")
+            }
+        };
+
+        let rer_ue_out = match &self.misc {
+            Some(map) if map.contains_key("rer_directiva") => format!(
+                "\nRER_UE (BdC con SPF > {}) = {}",
+                map.get_str_2d("rer_directiva_spf_min"),
+                map.get_str_2d("rer_directiva")
+            ),
+            _ => String::new(),
+        };
+
+        let ren_bdc_cal_out = match &self.misc {
+            Some(map) if map.contains_key("renovable_bdc_cal") => format!(
+                "\nE_RES,CAL (BdC con SPF > {}, Decisión 2013/114/UE) [kWh/m2.an]: {}{}",
+                map.get_str_2d("renovable_bdc_cal_spf_min"),
+                map.get_str_2d("renovable_bdc_cal"),
+                if map.contains_key("cobertura_renovable_bdc_cal") {
+                    format!(
+                        " (cobertura de la demanda de calefacción: {} %)",
+                        map.get_str_pct1d("cobertura_renovable_bdc_cal")
+                    )
+                } else {
+                    String::new()
+                }
+            ),
+            _ => String::new(),
+        };
 
         // Demanda
         let dhw_needs = value_or_dash(bal.needs.ACS, 1);
         let heating_needs = value_or_dash(bal.needs.CAL, 1);
         let cooling_needs = value_or_dash(bal.needs.REF, 1);
+        let zones_needs_dev = if bal.needs.zones_dev.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "\n\n* Desvío de la suma de demandas de zonas respecto a la del edificio:\n{}",
+                to_key_value_list(&bal.needs.zones_dev)
+            )
+        };
 
         // Consumos
         let epus = bal.used.epus;
@@ -80,6 +164,14 @@ impl AsCtePlain for EnergyPerformance {
 
         let used_by_srv = to_key_value_list(&bal.used.epus_by_srv);
         let used_epus_by_cr = to_key_value_list(&bal.used.epus_by_cr);
+        let used_nepus_by_srv = if bal.used.nepus_by_srv.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "\n\n* por servicio:\n{}",
+                to_key_value_list(&bal.used.nepus_by_srv)
+            )
+        };
         // Generada
         let prod_an = bal.prod.an;
         let prod_by_src = to_key_value_list(&bal.prod.by_src);
@@ -93,43 +185,730 @@ impl AsCtePlain for EnergyPerformance {
         // Exportada
         let exp_an = bal.exp.an;
         let exp_grid = bal.exp.grid;
+        let exp_grid_sto = if bal.exp.grid_sto == 0.0 {
+            String::new()
+        } else {
+            format!(
+                "\n  - diferida (desde almacenamiento): {:.2}",
+                bal.exp.grid_sto
+            )
+        };
         let exp_nepus = bal.exp.nepus;
+        let exp_nepus_by_srv = if bal.exp.nepus_by_srv.is_empty() {
+            String::new()
+        } else {
+            let list = to_key_value_list(&bal.exp.nepus_by_srv);
+            format!("\n  {}", list.replace('\n', "\n  "))
+        };
         // Ponderada por m2 (por uso)
         let we_a = bal.we.a;
         let we_b = bal.we.b;
-        let RenNrenCo2 { ren, nren, co2, .. } = we_b;
-        let tot = we_b.tot();
+        // Indicadores reglamentarios, siempre por m2
+        let RenNrenCo2 { ren, nren, co2, .. } = self.balance_m2.we.b;
+        let tot = self.balance_m2.we.b.tot();
         let rer = self.rer;
         let rer_nrb = self.rer_nrb;
+        let electrification = 100.0 * self.electrification.fraction;
         let balance_m2_a = rennren2string(&we_a);
         let a_by_srv = to_key_rennrenco2_value_list(&bal.we.a_by_srv);
         let balance_m2_b = rennren2string(&we_b);
         let b_by_srv = to_key_rennrenco2_value_list(&bal.we.b_by_srv);
+        let b_share_by_cr = {
+            let mut entries = bal
+                .we
+                .b_share_by_cr
+                .iter()
+                .map(|(cr, s)| {
+                    format!(
+                        "- {}: ren {:.1}, nren {:.1}, tot: {:.1}, co2: {:.1}",
+                        cr,
+                        100.0 * s.ren,
+                        100.0 * s.nren,
+                        100.0 * s.tot,
+                        100.0 * s.co2
+                    )
+                })
+                .collect::<Vec<String>>();
+            entries.sort();
+            entries.join("\n")
+        };
+        // Balance del edificio completo (usos EPB y no EPB)
+        let whole_out = match &self.whole_building {
+            Some(wb) => {
+                let wb_bal = &wb.balance_m2;
+                let wb_used = wb_bal.used.epus + wb_bal.used.cgnus;
+                let wb_del = wb_bal.del.an;
+                let wb_exp = wb_bal.exp.an;
+                let RenNrenCo2 {
+                    ren: wb_ren,
+                    nren: wb_nren,
+                    co2: wb_co2,
+                } = wb_bal.we.b;
+                let wb_tot = wb_bal.we.b.tot();
+                let wb_rer = wb.rer;
+                let wb_by_srv = to_key_rennrenco2_value_list(&wb_bal.we.b_by_srv);
+                format!(
+                    "\n\n** Edificio completo (usos EPB y no EPB, informativo)
+Energía consumida: {wb_used:.2}
+Energía suministrada: {wb_del:.2}
+Energía exportada: {wb_exp:.2}
+C_ep [kWh/m2.an]: ren = {wb_ren:.1}, nren = {wb_nren:.1}, tot = {wb_tot:.1}
+E_CO2 [kg_CO2e/m2.an]: {wb_co2:.2}
+RER = {wb_rer:.2}
+
+* por servicio (paso B):
+{wb_by_srv}"
+                )
+            }
+            None => String::new(),
+        };
+        // Potencias pico implícitas
+        let peaks_out = match &self.peaks {
+            Some(peaks) => {
+                let needs_peaks = to_key_peak_list(&peaks.needs);
+                let used_peaks = if peaks.used.is_empty() {
+                    "-".to_string()
+                } else {
+                    peaks
+                        .used
+                        .iter()
+                        .map(|(cr, peak)| {
+                            let by_srv = peaks
+                                .used_by_srv
+                                .get(cr)
+                                .map(|map| {
+                                    format!("\n  {}", to_key_peak_list(map).replace('\n', "\n  "))
+                                })
+                                .unwrap_or_default();
+                            format!("- {}: {}{}", cr, peak_to_string(peak), by_srv)
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                };
+                let del_grid_peaks = to_key_peak_list(&peaks.del_grid);
+                format!(
+                    "\n\n** Potencias pico implícitas [kW] (informativo)
+
+* demanda, por servicio:
+{needs_peaks}
+
+* consumo en usos EPB, por vector y servicio:
+{used_peaks}
+
+* suministro de red, por vector:
+{del_grid_peaks}"
+                )
+            }
+            None => String::new(),
+        };
+        // Rendimiento de la instalación fotovoltaica
+        let pv_out = match &self.pv {
+            Some(pv) => format!(
+                "\n\n** Rendimiento de la instalación fotovoltaica (informativo)
+Potencia pico = {:.2} [kWp]
+Producción anual = {:.1} [kWh], específica = {:.1} [kWh/kWp]
+Autoconsumo (usos EPB y no EPB) = {:.1} [kWh] ({:.1} %)
+Exportación a la red = {:.1} [kWh] ({:.1} %), por potencia pico = {:.1} [kWh/kWp]",
+                pv.peak_power,
+                pv.prod_an,
+                pv.specific_yield,
+                pv.self_an,
+                100.0 * pv.self_fraction,
+                pv.exp_grid_an,
+                100.0 * pv.exp_grid_fraction,
+                pv.exp_grid_per_kwp
+            ),
+            None => String::new(),
+        };
+        // Balance por temporadas de servicio (informativo)
+        let seasons_out = if self.seasons.is_empty() {
+            String::new()
+        } else {
+            let lines = self
+                .seasons
+                .iter()
+                .map(|(service, season)| {
+                    let months = season
+                        .months
+                        .iter()
+                        .map(|m| m.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    format!(
+                        "- {} (meses {}): consumo en temporada = {:.2}, fuera de temporada = {:.2} ({:.1} %), C_ep,tot en temporada = {:.2}, fuera de temporada = {:.2}",
+                        service,
+                        months,
+                        season.used_in / area,
+                        season.used_off / area,
+                        100.0 * season.off_fraction(),
+                        season.we_b_in.tot() / area,
+                        season.we_b_off.tot() / area,
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(
+                "\n\n** Balance por temporadas de servicio (informativo) [{}]\n{}",
+                energy_units, lines
+            )
+        };
+        // Necesidades no cubiertas por los sistemas (informativo)
+        let unmet_out = if self.unmet_needs.is_empty() {
+            String::new()
+        } else {
+            let lines = self
+                .unmet_needs
+                .iter()
+                .map(|(service, unmet)| {
+                    let hours = unmet
+                        .unmet_hours
+                        .map(|h| format!(" ({:.0} h)", h))
+                        .unwrap_or_default();
+                    format!(
+                        "- {}: demanda = {:.2}, energía entregada = {:.2}, no cubierta = {:.2} ({:.1} %), pasos no cubiertos = {}{}",
+                        service,
+                        unmet.needs_an / area,
+                        unmet.out_an / area,
+                        unmet.unmet_an / area,
+                        100.0 * unmet.fraction(),
+                        unmet.unmet_steps,
+                        hours,
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(
+                "\n\n** Necesidades no cubiertas por los sistemas (informativo) [{}]\n{}",
+                energy_units, lines
+            )
+        };
+        // Balance por ámbitos, privativo y común (informativo)
+        let scope_out = if self.balance_by_scope.is_empty() {
+            String::new()
+        } else {
+            let lines = self
+                .balance_by_scope
+                .iter()
+                .map(|(scope, bal)| {
+                    let excluded = if bal.excluded_by_srv.is_empty() {
+                        String::new()
+                    } else {
+                        let by_srv = bal
+                            .excluded_by_srv
+                            .iter()
+                            .map(|(service, value)| {
+                                format!("{}: {:.2}", service, value / area)
+                            })
+                            .collect::<Vec<_>>()
+                            .join(", ");
+                        format!(" (excluido del balance EPB: {})", by_srv)
+                    };
+                    format!(
+                        "- {}: consumo EPB = {:.2}, consumo no EPB = {:.2}{}, C_ep,nren = {:.2}, C_ep,tot = {:.2}",
+                        scope,
+                        bal.used / area,
+                        bal.nepus / area,
+                        excluded,
+                        bal.we_b.nren / area,
+                        bal.we_b.tot() / area,
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(
+                "\n\n** Balance por ámbitos (informativo) [{}]\n{}",
+                energy_units, lines
+            )
+        };
+        // Reparto de la energía común entre unidades (informativo)
+        let units_out = if self.unit_shares.is_empty() {
+            String::new()
+        } else {
+            let lines = self
+                .unit_shares_in(units)
+                .iter()
+                .map(|(unit, share)| {
+                    format!(
+                        "- {}: coeficiente = {:.3}, consumo EPB = {:.2} (privativo = {:.2}, común = {:.2}), producción eléctrica in situ = {:.2}, E_ep,nren = {:.2}, E_ep,tot = {:.2}, emisiones = {:.2} {}",
+                        unit,
+                        share.coef,
+                        share.used_own + share.used_common,
+                        share.used_own,
+                        share.used_common,
+                        share.prod_el,
+                        share.we_b.nren,
+                        share.we_b.tot(),
+                        share.we_b.co2,
+                        co2_units,
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(
+                "\n\n** Reparto entre unidades (informativo) [{}]\n{}",
+                energy_units, lines
+            )
+        };
+        // Coste anual de la energía (informativo)
+        let cost_out = match &self.energy_cost {
+            None => String::new(),
+            Some(cost) => {
+                let carriers: std::collections::BTreeSet<_> =
+                    cost.del_by_cr.keys().chain(cost.exp_by_cr.keys()).collect();
+                let lines = carriers
+                    .iter()
+                    .map(|cr| {
+                        let del = cost.del_by_cr.get(cr).copied().unwrap_or_default();
+                        let exp = cost.exp_by_cr.get(cr).copied().unwrap_or_default();
+                        format!(
+                            "- {}: suministro = {:.2}, exportación = {:.2}, neto = {:.2}",
+                            cr,
+                            del,
+                            exp,
+                            del - exp
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let unpriced = if cost.unpriced.is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        "\nVectores sin precio declarado (coste parcial): {}",
+                        cost.unpriced
+                            .iter()
+                            .map(ToString::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                };
+                let by_srv = if cost.by_srv.is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        "\nCoste neto por servicio: {}",
+                        cost.by_srv
+                            .iter()
+                            .map(|(srv, value)| format!("{} = {:.2}", srv, value))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                };
+                format!(
+                    "\n\n** Coste de la energía (informativo) [€/an]\n{}\nCoste neto: {:.2} €/an ({:.2} €/m2.an){}{}",
+                    lines, cost.total, cost.total_m2, by_srv, unpriced
+                )
+            }
+        };
+        // Electrificación mensual de la energía final EPB
+        let electrification_out = if self.electrification.fraction_monthly.is_empty() {
+            String::new()
+        } else {
+            let values = self
+                .electrification
+                .fraction_monthly
+                .iter()
+                .map(|v| format!("{:.1}", 100.0 * v))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "\n\n** Electrificación mensual (energía final EPB) [%]\n{}",
+                values
+            )
+        };
+        // Energía primaria renovable in situ utilizada (HE0)
+        let onsite_out = match self.onsite_renewable_in(units) {
+            Some(onst) => format!(
+                "\n\n** Energía primaria renovable in situ utilizada en servicios EPB [{}]
+E_ren,onst = {:.2} (energía final producida in situ y utilizada = {:.2})
+Por origen:
+{}
+Por servicio:
+{}",
+                energy_units,
+                onst.ren_an,
+                onst.used_an,
+                to_key_value_list(&onst.ren_by_src),
+                to_key_value_list(&onst.ren_by_srv)
+            ),
+            None => String::new(),
+        };
+        // Balance mensual por vector, para resoluciones inferiores a la mensual
+        let monthly_out = match self.components.resolution() {
+            Ok(Some(resolution)) if resolution != Resolution::MENSUAL => {
+                let fmt_monthly = |values: &[f32]| {
+                    monthly_values(values)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|v| format!("{:.1}", v))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                let lines = self
+                    .balance_cr
+                    .iter()
+                    .map(|(cr, bal)| {
+                        format!(
+                            "{cr}\n  Consumo EPB [kWh]: {}\n  Producción [kWh]: {}\n  Suministro de red [kWh]: {}\n  Exportación [kWh]: {}",
+                            fmt_monthly(&bal.used.epus_t),
+                            fmt_monthly(&bal.prod.t),
+                            fmt_monthly(&bal.del.grid_t),
+                            fmt_monthly(&bal.exp.t)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!(
+                    "\n\n** Balance mensual por vector energético (agregado desde resolución {resolution})\n{lines}"
+                )
+            }
+            _ => String::new(),
+        };
+        // Energía ambiente capturada por tipo de fuente
+        let ambient_out = if self
+            .balance_cr
+            .keys()
+            .any(|c| c.is_ambient() && *c != Carrier::EAMBIENTE)
+        {
+            let lines = self
+                .balance_cr
+                .iter()
+                .filter(|(c, _)| c.is_ambient())
+                .map(|(c, bal)| {
+                    let source = match c {
+                        Carrier::EAMBIENTE_AIRE => "aerotermia",
+                        Carrier::EAMBIENTE_GEO => "geotermia",
+                        Carrier::EAMBIENTE_AGUA => "hidrotermia",
+                        _ => "sin desglosar",
+                    };
+                    format!(
+                        "{c} ({source}): capturada = {:.1} [kWh], usada en servicios EPB = {:.1} [kWh]",
+                        bal.prod.an, bal.used.epus_an
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!("\n\n** Energía ambiente capturada por tipo de fuente\n{lines}")
+        } else {
+            String::new()
+        };
+        // Cupos de imputación de la producción eléctrica por servicio
+        let quotas = self.components.production_quotas().unwrap_or_default();
+        let quotas_out = match self.balance_cr.get(&Carrier::ELECTRICIDAD) {
+            Some(bal) if !quotas.is_empty() => {
+                let lines = quotas
+                    .iter()
+                    .map(|(service, quota)| {
+                        let used = bal
+                            .used
+                            .epus_by_srv_an
+                            .get(service)
+                            .copied()
+                            .unwrap_or_default();
+                        let imputed: f32 = bal
+                            .prod
+                            .epus_by_srv_by_src_an
+                            .values()
+                            .filter_map(|by_srv| by_srv.get(service))
+                            .sum();
+                        let coverage = if used > 0.0 { 100.0 * imputed / used } else { 0.0 };
+                        format!(
+                            "{service}: cupo = {:.1} [%], consumo = {used:.1} [kWh], producción imputada = {imputed:.1} [kWh] ({coverage:.1} %)",
+                            100.0 * quota
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!(
+                    "\n\n** Cupos de imputación de la producción eléctrica por servicio\n{lines}"
+                )
+            }
+            _ => String::new(),
+        };
+        // Reparto de la producción eléctrica por sistema consumidor (producciones compartidas con prioridad)
+        let sys_priorities_out = match self.balance_cr.get(&Carrier::ELECTRICIDAD) {
+            Some(bal) if !bal.prod.epus_by_sys_an.is_empty() => {
+                let priorities = self
+                    .components
+                    .production_priorities()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|(id, ids)| {
+                        let ids = ids.iter().map(|i| i.to_string()).collect::<Vec<_>>();
+                        format!(
+                            "Prioridad de la producción del sistema {id}: {}",
+                            ids.join(" > ")
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let lines = bal
+                    .prod
+                    .epus_by_sys_an
+                    .iter()
+                    .map(|(id, used)| format!("Sistema {id}: producción usada = {used:.1} [kWh]"))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!(
+                    "\n\n** Reparto de la producción eléctrica por sistema consumidor\n{priorities}\n{lines}"
+                )
+            }
+            _ => String::new(),
+        };
         // Parámetros de demanda HE4
         let misc_out = if let Some(map) = &self.misc {
             let pct_ren = map.get_str_pct1d("fraccion_renovable_demanda_acs_nrb");
-            format!("\n\n** Indicadores adicionales\nPorcentaje renovable de la demanda de ACS (perímetro próximo): {pct_ren} [%]")
+            let pct_onst_ven = map.get_str_pct1d("cobertura_renovable_onst_VEN");
+            let pct_onst_ilu = map.get_str_pct1d("cobertura_renovable_onst_ILU");
+            format!("\n\n** Indicadores adicionales\nPorcentaje renovable de la demanda de ACS (perímetro próximo): {pct_ren} [%]\nCobertura renovable in situ del consumo de VEN: {pct_onst_ven} [%]\nCobertura renovable in situ del consumo de ILU: {pct_onst_ilu} [%]")
         } else {
             String::new()
         };
+        // Compensación simplificada de excedentes
+        let comp_out = match &self.misc {
+            Some(map) if map.contains_key("compensacion_simplificada_factor") => {
+                let k_comp = map.get_str_1d("compensacion_simplificada_factor");
+                let get = |key: &str| {
+                    map.get(key)
+                        .and_then(|v| v.parse::<f32>().ok())
+                        .unwrap_or_default()
+                };
+                let comp_ren = get("compensacion_simplificada_ren");
+                let comp_nren = get("compensacion_simplificada_nren");
+                let comp_tot = get("compensacion_simplificada_tot");
+                let comp_co2 = get("compensacion_simplificada_co2");
+                let comp_rer = get("compensacion_simplificada_rer");
+                let dif_nren = comp_nren - nren;
+                let dif_tot = comp_tot - tot;
+                let dif_co2 = comp_co2 - co2;
+                format!(
+                    "\n\n** Compensación simplificada de excedentes (k_comp = {k_comp})
+C_ep [kWh/m2.an]: ren = {comp_ren:.1}, nren = {comp_nren:.1}, tot = {comp_tot:.1}
+E_CO2 [kg_CO2e/m2.an]: {comp_co2:.2}
+RER = {comp_rer:.2}
+Diferencia frente al método estándar: nren = {dif_nren:+.1}, tot = {dif_tot:+.1} [kWh/m2.an], co2 = {dif_co2:+.2} [kg_CO2e/m2.an]"
+                )
+            }
+            _ => String::new(),
+        };
+        // Energía embebida (informativa)
+        let embodied_out = match self.embodied_m2 {
+            Some(emb) => {
+                let RenNrenCo2 {
+                    ren: emb_ren,
+                    nren: emb_nren,
+                    co2: emb_co2,
+                } = emb;
+                let emb_tot = emb.tot();
+                format!(
+                    "\n\n** Energía embebida (informativo, no incluida en el balance EPB)
+C_ep,emb [kWh/m2.an]: ren = {emb_ren:.1}, nren = {emb_nren:.1}, tot = {emb_tot:.1}
+E_CO2,emb [kg_CO2e/m2.an]: {emb_co2:.2}"
+                )
+            }
+            None => String::new(),
+        };
+        // Balance exergético
+        let exergy_out = match &self.exergy {
+            Some(xbal) => {
+                let k_area = 1.0 / arearef;
+                let x_del = k_area * xbal.del;
+                let x_exp = k_area * xbal.exp;
+                let x_b = xbal.b_m2;
+                let q_del = xbal.q_del;
+                let mut x_by_cr = xbal.b_by_cr.clone();
+                x_by_cr.values_mut().for_each(|v| *v *= k_area);
+                let x_by_cr = to_key_value_list(&x_by_cr);
+                format!(
+                    "\n\n** Balance exergético [kWh_ex/m2.an]
+Exergía suministrada: {x_del:.2}
+Exergía exportada: {x_exp:.2}
+Exergía (incluyendo el efecto de la energía exportada): {x_b:.2}
+Calidad exergética media de la energía suministrada [-]: {q_del:.3}
+
+* por vector:
+{x_by_cr}"
+                )
+            }
+            None => String::new(),
+        };
+
+        // Huella de carbono marginal (informativa)
+        let marginal_out = match &self.marginal_co2 {
+            Some(mbal) => {
+                let k_area = 1.0 / arearef;
+                let m_del = k_area * mbal.del_grid;
+                let m_avoided = k_area * mbal.avoided;
+                let m_net = mbal.net_m2;
+                let by_cr = mbal
+                    .by_cr
+                    .iter()
+                    .map(|(cr, m)| {
+                        format!(
+                            "{cr}: suministro de red = {:.2}, evitadas por producción usada = {:.2}, evitadas por exportación = {:.2}",
+                            k_area * m.del_grid_an,
+                            k_area * m.avoided_used_an,
+                            k_area * m.avoided_exp_an
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                format!(
+                    "\n\n** Huella de carbono marginal (informativa, no reglamentaria) [kg_CO2e/m2.an]
+Emisiones marginales del suministro de red: {m_del:.2}
+Emisiones marginales evitadas (producción usada y exportación): {m_avoided:.2}
+Emisiones marginales netas: {m_net:.2}
+
+* por vector:
+{by_cr}"
+                )
+            }
+            None => String::new(),
+        };
+
+        // Emisiones con el juego alternativo de factores de emisión
+        let inventory_out = match &self.co2_inventory {
+            Some(inv) => {
+                let k_area = 1.0 / arearef;
+                let official_label = &inv.official_label;
+                let label = &inv.label;
+                let official_m2 = inv.official_m2;
+                let b_m2 = inv.b_m2;
+                let a_m2 = k_area * inv.a;
+                let mut by_cr = inv.b_by_cr.clone();
+                by_cr.values_mut().for_each(|v| *v *= k_area);
+                let by_cr = to_key_value_list(&by_cr);
+                let mut by_srv = inv.b_by_srv.clone();
+                by_srv.values_mut().for_each(|v| *v *= k_area);
+                let by_srv = to_key_value_list(&by_srv);
+                format!(
+                    "\n\n** Emisiones con dos juegos de factores de emisión [kg_CO2e/m2.an]
+E_CO2 ({official_label}): {official_m2:.2}
+E_CO2 ({label}): {b_m2:.2}
+E_CO2 ({label}, paso A): {a_m2:.2}
+
+* {label}, por vector:
+{by_cr}
+
+* {label}, por servicio:
+{by_srv}"
+                )
+            }
+            None => String::new(),
+        };
+
+        // Conciliación con facturas de suministro
+        let bills_out = match &self.bills {
+            Some(rec) => {
+                let pct_or_dash = |v: Option<f32>| match v {
+                    Some(v) => format!("{:+.1} %", v),
+                    None => "-".to_string(),
+                };
+                let by_cr = rec
+                    .by_cr
+                    .iter()
+                    .map(|(cr, cr_rec)| {
+                        let months = cr_rec
+                            .computed
+                            .iter()
+                            .zip(cr_rec.billed.iter())
+                            .zip(cr_rec.dev_pct.iter())
+                            .enumerate()
+                            .map(|(i, ((c, b), d))| {
+                                format!(
+                                    "  - mes {}: {:.1} / {:.1} ({})",
+                                    i + 1,
+                                    c,
+                                    b,
+                                    pct_or_dash(*d)
+                                )
+                            })
+                            .collect::<Vec<String>>()
+                            .join("\n");
+                        format!(
+                            "* {}: calculada = {:.1}, facturada = {:.1}, desviación = {}\n{}",
+                            cr,
+                            cr_rec.computed_an,
+                            cr_rec.billed_an,
+                            pct_or_dash(cr_rec.dev_an_pct),
+                            months
+                        )
+                    })
+                    .collect::<Vec<String>>()
+                    .join("\n\n");
+                let unbilled = if rec.unbilled.is_empty() {
+                    String::new()
+                } else {
+                    format!(
+                        "\n\nVectores suministrados por la red sin energía facturada: {}",
+                        rec.unbilled
+                            .iter()
+                            .map(|cr| cr.to_string())
+                            .collect::<Vec<String>>()
+                            .join(", ")
+                    )
+                };
+                format!(
+                    "\n\n** Conciliación con facturas de suministro [kWh]
+Energía suministrada por la red a todo el edificio, calculada / facturada (desviación)
+
+{by_cr}{unbilled}"
+                )
+            }
+            None => String::new(),
+        };
+
+        // Indicadores provisionales en cálculos con año incompleto
+        // Factores de paso con periodos de vigencia
+        let periods_out = if self.wfactors_periods.is_empty() {
+            String::new()
+        } else {
+            let lines = self
+                .wfactors_periods
+                .iter()
+                .map(|f| {
+                    let period = match f.validity() {
+                        Ok(Some((first, last))) if last == usize::MAX => {
+                            format!("pasos {} a {}", first, self.components.num_steps())
+                        }
+                        Ok(Some((first, last))) => format!("pasos {} a {}", first, last),
+                        _ => String::from("-"),
+                    };
+                    format!(
+                        "- {}, {}, {}, {} ({}): ren = {:.3}, nren = {:.3}, co2 = {:.3}",
+                        f.carrier, f.source, f.dest, f.step, period, f.ren, f.nren, f.co2
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            format!(
+                "\n\n** Factores de paso con periodos de vigencia (aplicados en cada paso de cálculo)\n{}",
+                lines
+            )
+        };
+        let partial_out = match &self.partial_year {
+            Some(partial) => format!("\nIndicadores PROVISIONALES: {}", partial),
+            None => String::new(),
+        };
 
         format!(
             "** Eficiencia energética
 
 Area_ref = {arearef:.2} [m2]
-k_exp = {k_exp:.2}
+k_exp = {k_exp:.2}{kexp_cr_out}{uso_out}{partial_out}
 C_ep [kWh/m2.an]: ren = {ren:.1}, nren = {nren:.1}, tot = {tot:.1}
 E_CO2 [kg_CO2e/m2.an]: {co2:.2}
 RER = {rer:.2}
-RER_nrb = {rer_nrb:.2}
+RER_nrb = {rer_nrb:.2}{nrb_opt_out}{rer_ue_out}{ren_bdc_cal_out}
+Electrificación (energía final EPB) = {electrification:.1} %
 
-** Demanda [kWh/m2.an]:
+** Demanda [{energy_units}]:
 
 - ACS: {dhw_needs}
 - CAL: {heating_needs}
-- REF: {cooling_needs}
+- REF: {cooling_needs}{zones_needs_dev}
 
-** Energía final (todos los vectores) [kWh/m2.an]:
+** Energía final (todos los vectores) [{energy_units}]:
 
 Energía consumida: {used:.2}
 
@@ -141,7 +920,7 @@ Energía consumida: {used:.2}
 * por vector:
 {used_epus_by_cr}
 
-+ Consumida en usos no EPB: {nepus:.2}
++ Consumida en usos no EPB: {nepus:.2}{used_nepus_by_srv}
 
 + Consumida en cogeneración: {cgnus:.2}
 
@@ -163,10 +942,10 @@ Suministrada {del_an:.2}:
 
 Exportada: {exp_an:.2}
 
-- a la red: {exp_grid:.2}
-- a usos no EPB: {exp_nepus:.2}
+- a la red: {exp_grid:.2}{exp_grid_sto}
+- a usos no EPB: {exp_nepus:.2}{exp_nepus_by_srv}
 
-** Energía primaria (ren, nren) [kWh/m2.an] y emisiones [kg_CO2e/m2.an]:
+** Energía primaria (ren, nren) [{energy_units}] y emisiones [{co2_units}]:
 
 Recursos utilizados (paso A): {balance_m2_a}
 
@@ -176,13 +955,16 @@ Recursos utilizados (paso A): {balance_m2_a}
 Incluyendo el efecto de la energía exportada (paso B): {balance_m2_b}
 
 * por servicio:
-{b_by_srv}{misc_out}
+{b_by_srv}
+
+* contribución de cada vector al total (paso B) [%]:
+{b_share_by_cr}{whole_out}{peaks_out}{monthly_out}{electrification_out}{onsite_out}{seasons_out}{unmet_out}{scope_out}{units_out}{cost_out}{pv_out}{ambient_out}{quotas_out}{sys_priorities_out}{misc_out}{comp_out}{embodied_out}{exergy_out}{marginal_out}{inventory_out}{bills_out}{periods_out}
 "
         )
     }
 }
 
-fn to_key_value_list<T: std::fmt::Display>(map: &std::collections::HashMap<T, f32>) -> String {
+fn to_key_value_list<T: std::fmt::Display>(map: &std::collections::BTreeMap<T, f32>) -> String {
     let mut entries = map
         .iter()
         .map(|(k, v)| format!("- {}: {:.2}", k, v))
@@ -191,8 +973,25 @@ fn to_key_value_list<T: std::fmt::Display>(map: &std::collections::HashMap<T, f3
     entries.join("\n")
 }
 
+/// Convierte potencia pico a String con 2 decimales y posición del pico en el año
+fn peak_to_string(peak: &PeakPower) -> String {
+    format!("{:.2} ({})", peak.power.0, peak.position)
+}
+
+fn to_key_peak_list<T: std::fmt::Display>(
+    map: &std::collections::BTreeMap<T, PeakPower>,
+) -> String {
+    if map.is_empty() {
+        return "-".to_string();
+    }
+    map.iter()
+        .map(|(k, v)| format!("- {}: {}", k, peak_to_string(v)))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
 fn to_key_rennrenco2_value_list<T: std::fmt::Display>(
-    map: &std::collections::HashMap<T, RenNrenCo2>,
+    map: &std::collections::BTreeMap<T, RenNrenCo2>,
 ) -> String {
     let mut entries = map
         .iter()
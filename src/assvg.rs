@@ -0,0 +1,319 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Diagrama Sankey en formato SVG
+==============================
+
+Generación de un diagrama Sankey con los flujos de energía del edificio a partir de los resultados
+del cálculo de la eficiencia energética.
+
+El diagrama tiene dos paneles:
+
+- energía final: energía usada en cada servicio EPB por vector energético y origen (red, in situ o
+  cogeneración) y energía exportada por cada vector y origen.
+- energía ponderada (paso A, ren + nren): energía ponderada de cada servicio EPB por vector
+  energético y origen y energía ponderada exportada por cada vector.
+
+Los flujos se obtienen del desglose anual de flujos de energía del balance.
+*/
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::{
+    escape::escape_xml,
+    types::{EnergyPerformance, Source},
+};
+
+// ==================== Conversión a diagrama Sankey SVG
+
+/// Muestra como diagrama Sankey en formato SVG
+pub trait AsSankeySvg {
+    /// Get as Sankey diagram in SVG format
+    fn to_sankey_svg(&self) -> String;
+}
+
+// ================= Implementaciones ====================
+
+/// Anchura del diagrama, px
+const WIDTH: f32 = 900.0;
+/// Altura de cada panel, px
+const PANEL_HEIGHT: f32 = 400.0;
+/// Margen superior de cada panel (título), px
+const PANEL_TOP: f32 = 40.0;
+/// Anchura de los nodos, px
+const NODE_WIDTH: f32 = 14.0;
+/// Separación vertical entre nodos, px
+const NODE_GAP: f32 = 10.0;
+/// Posición horizontal de la columna de nodos de origen, px
+const LEFT_X: f32 = 200.0;
+/// Posición horizontal de la columna de nodos de destino, px
+const RIGHT_X: f32 = 686.0;
+/// Nombre del nodo de destino de la energía exportada
+const EXPORT_NODE: &str = "Exportación";
+/// Colores de los nodos de origen
+const PALETTE: [&str; 10] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+    "#bcbd22", "#17becf",
+];
+
+/// Enlace de un diagrama Sankey (origen, destino, valor)
+type Link = (String, String, f32);
+
+impl AsSankeySvg for EnergyPerformance {
+    fn to_sankey_svg(&self) -> String {
+        let label =
+            |carrier: &dyn std::fmt::Display, source: Source| format!("{} ({})", carrier, source);
+
+        let mut final_links: Vec<Link> = self
+            .flows
+            .iter()
+            .map(|f| (label(&f.carrier, f.source), f.service.to_string(), f.kwh))
+            .collect();
+        let mut weighted_links: Vec<Link> = self
+            .flows
+            .iter()
+            .map(|f| {
+                (
+                    label(&f.carrier, f.source),
+                    f.service.to_string(),
+                    f.we_a.tot(),
+                )
+            })
+            .collect();
+        for (carrier, bal) in &self.balance_cr {
+            // La energía ponderada exportada se reparte entre orígenes según la energía exportada
+            let exp_an: f32 = bal.exp.by_src_an.values().sum();
+            for (src, value) in &bal.exp.by_src_an {
+                let node = label(carrier, Source::from(*src));
+                final_links.push((node.clone(), EXPORT_NODE.to_string(), *value));
+                if exp_an > f32::EPSILON {
+                    weighted_links.push((
+                        node,
+                        EXPORT_NODE.to_string(),
+                        bal.we.exp_a.tot() * value / exp_an,
+                    ));
+                }
+            }
+        }
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w:.0}" height="{h:.0}" viewBox="0 0 {w:.0} {h:.0}" font-family="sans-serif" font-size="12">"#,
+            w = WIDTH,
+            h = 2.0 * PANEL_HEIGHT
+        );
+        svg.push_str(&sankey_panel(
+            &format!(
+                "Energía final (kWh/año) - consumo: {:.2}, exportación: {:.2}",
+                self.balance.used.epus, self.balance.exp.an
+            ),
+            &final_links,
+            0.0,
+        ));
+        svg.push_str(&sankey_panel(
+            &format!(
+                "Energía ponderada, ren + nren (kWh/año) - paso A: {:.2}, paso B: {:.2}",
+                self.balance.we.a.tot(),
+                self.balance.we.b.tot()
+            ),
+            &weighted_links,
+            PANEL_HEIGHT,
+        ));
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// Nodo de un diagrama Sankey, con su valor total y su posición y altura en el panel
+struct Node {
+    value: f32,
+    y: f32,
+    height: f32,
+}
+
+/// Agrupa los valores de los enlaces por nodo, conservando el orden de aparición
+fn column_nodes<'a>(names: impl Iterator<Item = (&'a str, f32)>) -> Vec<(&'a str, Node)> {
+    let mut nodes: Vec<(&str, Node)> = Vec::new();
+    for (name, value) in names {
+        match nodes.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, node)) => node.value += value,
+            None => nodes.push((
+                name,
+                Node {
+                    value,
+                    y: 0.0,
+                    height: 0.0,
+                },
+            )),
+        }
+    }
+    nodes
+}
+
+/// Sitúa los nodos de una columna en el panel con la escala indicada (px/kWh)
+fn place_nodes(nodes: &mut [(&str, Node)], top: f32, scale: f32) {
+    let mut y = top;
+    for (_, node) in nodes.iter_mut() {
+        node.y = y;
+        node.height = node.value * scale;
+        y += node.height + NODE_GAP;
+    }
+}
+
+/// Genera un panel del diagrama Sankey con los enlaces indicados, situado en la posición vertical y0
+///
+/// Se descartan los enlaces con valores nulos o negativos
+fn sankey_panel(title: &str, links: &[Link], y0: f32) -> String {
+    let links: Vec<&Link> = links.iter().filter(|(_, _, v)| *v > 1e-3).collect();
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<g class="panel" transform="translate(0,{:.0})">"#,
+        y0
+    );
+    let _ = writeln!(
+        out,
+        r#"<text x="{:.0}" y="20" text-anchor="middle" font-size="14" font-weight="bold">{}</text>"#,
+        WIDTH / 2.0,
+        escape_xml(title)
+    );
+    if links.is_empty() {
+        let _ = writeln!(
+            out,
+            r#"<text x="{:.0}" y="{:.0}" text-anchor="middle">Sin flujos de energía</text>"#,
+            WIDTH / 2.0,
+            PANEL_HEIGHT / 2.0
+        );
+        out.push_str("</g>\n");
+        return out;
+    }
+
+    let mut sources = column_nodes(links.iter().map(|(s, _, v)| (s.as_str(), *v)));
+    let mut targets = column_nodes(links.iter().map(|(_, t, v)| (t.as_str(), *v)));
+
+    // Escala común a ambas columnas, limitada por la columna con más nodos
+    let total: f32 = links.iter().map(|(_, _, v)| v).sum();
+    let max_nodes = sources.len().max(targets.len()) as f32;
+    let available = PANEL_HEIGHT - PANEL_TOP - 20.0 - NODE_GAP * (max_nodes - 1.0);
+    let scale = available.max(1.0) / total;
+    place_nodes(&mut sources, PANEL_TOP, scale);
+    place_nodes(&mut targets, PANEL_TOP, scale);
+
+    let colors: BTreeMap<&str, &str> = sources
+        .iter()
+        .enumerate()
+        .map(|(i, (name, _))| (*name, PALETTE[i % PALETTE.len()]))
+        .collect();
+
+    // Enlaces, apilados en el orden de aparición en cada nodo
+    let mut src_offset: BTreeMap<&str, f32> = BTreeMap::new();
+    let mut tgt_offset: BTreeMap<&str, f32> = BTreeMap::new();
+    let x0 = LEFT_X + NODE_WIDTH;
+    let x1 = RIGHT_X;
+    let xm = (x0 + x1) / 2.0;
+    for (source, target, value) in &links {
+        let (_, src) = sources.iter().find(|(n, _)| n == source).unwrap();
+        let (_, tgt) = targets.iter().find(|(n, _)| n == target).unwrap();
+        let h = value * scale;
+        let so = src_offset.entry(source.as_str()).or_default();
+        let ys = src.y + *so;
+        *so += h;
+        let to = tgt_offset.entry(target.as_str()).or_default();
+        let yt = tgt.y + *to;
+        *to += h;
+        let _ = writeln!(
+            out,
+            r#"<path d="M{x0:.1},{ys:.1} C{xm:.1},{ys:.1} {xm:.1},{yt:.1} {x1:.1},{yt:.1} L{x1:.1},{yt2:.1} C{xm:.1},{yt2:.1} {xm:.1},{ys2:.1} {x0:.1},{ys2:.1} Z" fill="{color}" fill-opacity="0.4"><title>{s} → {t}: {value:.2}</title></path>"#,
+            ys2 = ys + h,
+            yt2 = yt + h,
+            color = colors[source.as_str()],
+            s = escape_xml(source),
+            t = escape_xml(target),
+        );
+    }
+
+    // Nodos y etiquetas
+    for (name, node) in &sources {
+        let _ = writeln!(
+            out,
+            r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"/><text x="{:.1}" y="{:.1}" text-anchor="end" dominant-baseline="middle">{} ({:.2})</text>"#,
+            LEFT_X,
+            node.y,
+            NODE_WIDTH,
+            node.height,
+            colors[name],
+            LEFT_X - 6.0,
+            node.y + node.height / 2.0,
+            escape_xml(name),
+            node.value
+        );
+    }
+    for (name, node) in &targets {
+        let _ = writeln!(
+            out,
+            r##"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="#555555"/><text x="{:.1}" y="{:.1}" dominant-baseline="middle">{} ({:.2})</text>"##,
+            RIGHT_X,
+            node.y,
+            NODE_WIDTH,
+            node.height,
+            RIGHT_X + NODE_WIDTH + 6.0,
+            node.y + node.height / 2.0,
+            escape_xml(name),
+            node.value
+        );
+    }
+    out.push_str("</g>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sankey_panel_nodes_and_links() {
+        let links = vec![
+            ("ELECTRICIDAD (RED)".to_string(), "CAL".to_string(), 10.0),
+            ("ELECTRICIDAD (INSITU)".to_string(), "CAL".to_string(), 5.0),
+            ("ELECTRICIDAD (INSITU)".to_string(), "ACS".to_string(), 5.0),
+            ("GASNATURAL (RED)".to_string(), "ACS".to_string(), 0.0),
+        ];
+        let svg = sankey_panel("Prueba <1>", &links, 0.0);
+        // Un trazado por enlace con valor positivo
+        assert_eq!(svg.matches("<path").count(), 3);
+        // Nodos de origen y destino, con su valor total
+        assert!(svg.contains("ELECTRICIDAD (INSITU) (10.00)"));
+        assert!(svg.contains("CAL (15.00)"));
+        assert!(!svg.contains("GASNATURAL"));
+        assert!(svg.contains("Prueba &lt;1&gt;"));
+
+        let empty = sankey_panel("Vacío", &[], 0.0);
+        assert!(empty.contains("Sin flujos de energía"));
+    }
+}
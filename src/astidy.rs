@@ -0,0 +1,314 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Resultados por vector en formato de tabla larga
+===============================================
+
+Conversión de los resultados del balance por vector energético (`BalanceCarrier`) a una lista de
+registros `{carrier, metric, period, value}` (formato *tidy* o de tabla larga), adecuada para su
+consumo desde herramientas de análisis de datos, en formato JSON o CSV.
+
+- `carrier`: vector energético (p.e. `ELECTRICIDAD`)
+- `metric`: nombre de la magnitud (ver más abajo)
+- `period`: `an` para el valor anual o número del paso de cálculo (`1`, `2`, ..., `n`)
+- `value`: valor de la magnitud (kWh, salvo `f_match`, adimensional)
+
+Los nombres de las magnitudes son estables y siguen la ruta del campo correspondiente en
+`BalanceCarrier`, eliminando los sufijos `_an` (valor anual) y `_t` (valores por paso de cálculo),
+que se trasladan a `period`. Los desgloses por servicio (`Service`), origen (`ProdSource`) o sistema
+añaden la clave como un segmento más y las magnitudes ponderadas (`RenNrenCo2`) terminan en `.ren`,
+`.nren` o `.co2`:
+
+| Magnitud | Periodos | Descripción |
+|----------|----------|-------------|
+| `f_match` | pasos | Factor de coincidencia de cargas |
+| `used.epus`, `used.epus_by_srv.<SERVICIO>` | an, pasos | Energía usada en servicios EPB |
+| `used.nepus`, `used.nepus_by_srv.<SERVICIO>` | an, pasos | Energía usada en servicios no EPB |
+| `used.cgnus` | an, pasos | Energía usada para cogeneración de electricidad |
+| `prod`, `prod.by_src.<ORIGEN>` | an, pasos | Energía producida |
+| `prod.sto` | an, pasos | Energía liberada por sistemas de almacenamiento |
+| `prod.epus`, `prod.epus_by_src.<ORIGEN>` | an, pasos | Energía producida usada en servicios EPB |
+| `prod.epus_by_srv_by_src.<ORIGEN>.<SERVICIO>` | an, pasos | Idem, por servicio |
+| `prod.epus_by_sys.<ID>` | an | Idem, por sistema consumidor |
+| `exp`, `exp.by_src.<ORIGEN>` | an, pasos | Energía exportada (red y servicios no EPB) |
+| `exp.grid`, `exp.grid_sto`, `exp.nepus` | an, pasos | Energía exportada a la red, a la red desde almacenamiento y a servicios no EPB |
+| `exp.nepus_by_srv.<SERVICIO>` | an | Energía exportada a servicios no EPB, por servicio |
+| `del` | an | Energía suministrada |
+| `del.grid`, `del.onst`, `del.cgn` | an, pasos | Energía suministrada por la red, in situ y por cogeneración |
+| `we.a.*`, `we.a_by_srv.<SERVICIO>.*` | an | Energía ponderada, paso A |
+| `we.b.*`, `we.b_by_srv.<SERVICIO>.*` | an | Energía ponderada, paso B |
+| `we.del.*`, `we.del_grid.*`, `we.del_onst.*`, `we.del_cgn.*` | an | Energía suministrada ponderada |
+| `we.exp.*`, `we.exp_a.*`, `we.exp_ab.*` | an | Energía exportada ponderada (A+B, A y AB) |
+| `we.exp_grid_a.*`, `we.exp_grid_ab.*`, `we.exp_nepus_a.*`, `we.exp_nepus_ab.*` | an | Idem, a la red y a servicios no EPB |
+
+Los registros se ordenan por vector energético, magnitud (en el orden de la tabla) y periodo (valor
+anual antes que los valores por paso de cálculo).
+*/
+
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{BalanceCarrier, Carrier, EnergyPerformance, RenNrenCo2},
+};
+
+/// Periodo de los valores anuales
+pub const TIDY_ANNUAL_PERIOD: &str = "an";
+
+/// Registro de resultados en formato de tabla larga
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TidyRecord {
+    /// Vector energético
+    pub carrier: Carrier,
+    /// Nombre estable de la magnitud
+    pub metric: String,
+    /// Periodo: `an` (anual) o número de paso de cálculo (desde 1)
+    pub period: String,
+    /// Valor
+    pub value: f32,
+}
+
+// ==================== Conversión a tabla larga
+
+/// Muestra los resultados por vector en formato de tabla larga (JSON o CSV)
+pub trait AsTidy {
+    /// Get results as a list of tidy records
+    fn to_tidy_records(&self) -> Vec<TidyRecord>;
+
+    /// Get tidy records as JSON
+    fn to_tidy_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&self.to_tidy_records()).map_err(|e| {
+            EpbdError::WrongInput(format!("Error al generar el JSON de resultados: {}", e))
+        })
+    }
+
+    /// Get tidy records as CSV, with header `carrier,metric,period,value`
+    fn to_tidy_csv(&self) -> String {
+        let mut out = String::from("carrier,metric,period,value\n");
+        for r in self.to_tidy_records() {
+            let _ = writeln!(out, "{},{},{},{}", r.carrier, r.metric, r.period, r.value);
+        }
+        out
+    }
+}
+
+// ================= Implementaciones ====================
+
+impl AsTidy for EnergyPerformance {
+    fn to_tidy_records(&self) -> Vec<TidyRecord> {
+        self.balance_cr
+            .values()
+            .flat_map(|bal| bal.to_tidy_records())
+            .collect()
+    }
+}
+
+impl AsTidy for BalanceCarrier {
+    fn to_tidy_records(&self) -> Vec<TidyRecord> {
+        let mut r = Records {
+            carrier: self.carrier,
+            data: Vec::new(),
+        };
+
+        r.steps("f_match", &self.f_match);
+
+        let used = &self.used;
+        r.series("used.epus", used.epus_an, &used.epus_t);
+        for (srv, values) in &used.epus_by_srv_t {
+            let an = used.epus_by_srv_an.get(srv).copied().unwrap_or_default();
+            r.series(&format!("used.epus_by_srv.{}", srv), an, values);
+        }
+        r.series("used.nepus", used.nepus_an, &used.nepus_t);
+        for (srv, values) in &used.nepus_by_srv_t {
+            let an = used.nepus_by_srv_an.get(srv).copied().unwrap_or_default();
+            r.series(&format!("used.nepus_by_srv.{}", srv), an, values);
+        }
+        r.series("used.cgnus", used.cgnus_an, &used.cgnus_t);
+
+        let prod = &self.prod;
+        r.series("prod", prod.an, &prod.t);
+        for (src, values) in &prod.by_src_t {
+            let an = prod.by_src_an.get(src).copied().unwrap_or_default();
+            r.series(&format!("prod.by_src.{}", src), an, values);
+        }
+        r.series("prod.sto", prod.sto_an, &prod.sto_t);
+        r.series("prod.epus", prod.epus_an, &prod.epus_t);
+        for (src, values) in &prod.epus_by_src_t {
+            let an = prod.epus_by_src_an.get(src).copied().unwrap_or_default();
+            r.series(&format!("prod.epus_by_src.{}", src), an, values);
+        }
+        for (src, by_srv) in &prod.epus_by_srv_by_src_t {
+            for (srv, values) in by_srv {
+                let an = prod
+                    .epus_by_srv_by_src_an
+                    .get(src)
+                    .and_then(|v| v.get(srv))
+                    .copied()
+                    .unwrap_or_default();
+                r.series(
+                    &format!("prod.epus_by_srv_by_src.{}.{}", src, srv),
+                    an,
+                    values,
+                );
+            }
+        }
+        for (id, value) in &prod.epus_by_sys_an {
+            r.annual(&format!("prod.epus_by_sys.{}", id), *value);
+        }
+
+        let exp = &self.exp;
+        r.series("exp", exp.an, &exp.t);
+        for (src, values) in &exp.by_src_t {
+            let an = exp.by_src_an.get(src).copied().unwrap_or_default();
+            r.series(&format!("exp.by_src.{}", src), an, values);
+        }
+        r.series("exp.grid", exp.grid_an, &exp.grid_t);
+        r.series("exp.grid_sto", exp.grid_sto_an, &exp.grid_sto_t);
+        r.series("exp.nepus", exp.nepus_an, &exp.nepus_t);
+        for (srv, value) in &exp.nepus_by_srv_an {
+            r.annual(&format!("exp.nepus_by_srv.{}", srv), *value);
+        }
+
+        let del = &self.del;
+        r.annual("del", del.an);
+        r.series("del.grid", del.grid_an, &del.grid_t);
+        r.series("del.onst", del.onst_an, &del.onst_t);
+        r.series("del.cgn", del.cgn_an, &del.cgn_t);
+
+        let we = &self.we;
+        r.weighted("we.a", we.a);
+        for (srv, value) in &we.a_by_srv {
+            r.weighted(&format!("we.a_by_srv.{}", srv), *value);
+        }
+        r.weighted("we.b", we.b);
+        for (srv, value) in &we.b_by_srv {
+            r.weighted(&format!("we.b_by_srv.{}", srv), *value);
+        }
+        r.weighted("we.del", we.del);
+        r.weighted("we.del_grid", we.del_grid);
+        r.weighted("we.del_onst", we.del_onst);
+        r.weighted("we.del_cgn", we.del_cgn);
+        r.weighted("we.exp", we.exp);
+        r.weighted("we.exp_a", we.exp_a);
+        r.weighted("we.exp_ab", we.exp_ab);
+        r.weighted("we.exp_grid_a", we.exp_grid_a);
+        r.weighted("we.exp_grid_ab", we.exp_grid_ab);
+        r.weighted("we.exp_nepus_a", we.exp_nepus_a);
+        r.weighted("we.exp_nepus_ab", we.exp_nepus_ab);
+
+        r.data
+    }
+}
+
+/// Acumulador de registros de un vector energético
+struct Records {
+    carrier: Carrier,
+    data: Vec<TidyRecord>,
+}
+
+impl Records {
+    fn push(&mut self, metric: &str, period: String, value: f32) {
+        self.data.push(TidyRecord {
+            carrier: self.carrier,
+            metric: metric.to_string(),
+            period,
+            value,
+        });
+    }
+
+    /// Valor anual
+    fn annual(&mut self, metric: &str, value: f32) {
+        self.push(metric, TIDY_ANNUAL_PERIOD.to_string(), value);
+    }
+
+    /// Valores por paso de cálculo (numerados desde 1)
+    fn steps(&mut self, metric: &str, values: &[f32]) {
+        for (i, value) in values.iter().enumerate() {
+            self.push(metric, (i + 1).to_string(), *value);
+        }
+    }
+
+    /// Valor anual y valores por paso de cálculo
+    fn series(&mut self, metric: &str, an: f32, values: &[f32]) {
+        self.annual(metric, an);
+        self.steps(metric, values);
+    }
+
+    /// Valor anual ponderado, desglosado en ren, nren y co2
+    fn weighted(&mut self, metric: &str, value: RenNrenCo2) {
+        self.annual(&format!("{}.ren", metric), value.ren);
+        self.annual(&format!("{}.nren", metric), value.nren);
+        self.annual(&format!("{}.co2", metric), value.co2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{energy_performance, Components, Factors};
+
+    #[test]
+    fn tidy_records_by_carrier() {
+        let comps = "#META CTE_AREAREF: 1.0
+0, CONSUMO, ACS, ELECTRICIDAD, 10.0, 20.0
+0, PRODUCCION, EL_INSITU, 15.0, 5.0"
+            .parse::<Components>()
+            .unwrap();
+        let fp = "#META CTE_FUENTE: CTE2013
+ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+ELECTRICIDAD, INSITU, SUMINISTRO, A, 1.000, 0.000, 0.000
+ELECTRICIDAD, INSITU, A_RED, A, 1.000, 0.000, 0.000
+ELECTRICIDAD, INSITU, A_RED, B, 0.414, 1.954, 0.331"
+            .parse::<Factors>()
+            .unwrap();
+        let ep = energy_performance(&comps, &fp, 1.0, 1.0, false).unwrap();
+        let records = ep.to_tidy_records();
+
+        let find = |metric: &str, period: &str| {
+            records
+                .iter()
+                .find(|r| r.metric == metric && r.period == period)
+                .map(|r| r.value)
+        };
+        assert_eq!(find("used.epus", "an"), Some(30.0));
+        assert_eq!(find("used.epus_by_srv.ACS", "2"), Some(20.0));
+        assert_eq!(find("prod.by_src.EL_INSITU", "1"), Some(15.0));
+        assert_eq!(find("exp.grid", "an"), Some(5.0));
+        assert_eq!(find("del.grid", "2"), Some(15.0));
+        assert_eq!(find("f_match", "an"), None);
+        assert!(find("we.b.nren", "an").is_some());
+        assert!(records.iter().all(|r| r.carrier == Carrier::ELECTRICIDAD));
+
+        let csv = ep.to_tidy_csv();
+        assert!(csv.starts_with("carrier,metric,period,value\n"));
+        assert!(csv.contains("ELECTRICIDAD,used.epus,an,30\n"));
+        let json = ep.to_tidy_json().unwrap();
+        let back: Vec<TidyRecord> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, records);
+    }
+}
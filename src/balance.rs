@@ -31,19 +31,41 @@ Evaluación de la eficiencia energética según la EN ISO 52000-1.
 
 */
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use crate::{
     error::{EpbdError, Result},
     types::{
-        Balance, BalanceCarrier, Carrier, DeliveredEnergy, Dest, Energy, EnergyPerformance,
-        ExportedEnergy, HasValues, ProdSource, ProducedEnergy, RenNrenCo2, Service, Source, Step,
-        UsedEnergy, WeightedEnergy,
+        monthly_values, Balance, BalanceCarrier, Bills, BillsReconciliation, CalcHooks,
+        CalcOptions, Carrier, CarrierReconciliation, Co2Inventory, DeliveredEnergy, Dest,
+        DuplicateComponent, DuplicatePolicy, Electrification, Energy, EnergyCost,
+        EnergyPerformance, ExergyBalance, ExergyFactors, ExportWeighting, ExportedEnergy, Flow,
+        HasValues, InventoryCo2Factors, LoadMatchingMode, MarginalCarbon, MarginalCarbonCarrier,
+        MarginalCo2Factors, NegativeUsePolicy, OnsiteRenewable, PartialYear, PeakPowers,
+        ProdSource, ProducedEnergy, PvPerformance, RenNrenCo2, Resolution, Scope, ScopeBalance,
+        Service, ServiceSeason, Source, Step, Storage, SystemBalance, Tolerances, UnitShare,
+        UnmetNeeds, UsedEnergy, Warning, WarningCode, WeightedEnergy, WholeBuildingBalance,
+        CO2_OFFICIAL_LABEL,
     },
-    vecops::{vecsum, vecvecdif, vecvecmin, vecvecmul, vecvecsum},
+    vecops::{veclistsum, vecsum, vecvecdif, vecvecmin, vecvecmul, vecvecsum, vecvecsum_assign},
     Components, Factors,
 };
 
+/// Referencias normativas de los resultados del balance
+///
+/// Fórmula de la UNE-EN ISO 52000-1:2017 o apartado del DB-HE del que deriva cada magnitud,
+/// usadas para anotar el origen de los resultados en los informes (p.e. en el informe XML).
+pub mod refs {
+    /// Factor de exportación, k_exp
+    pub const K_EXP: &str = "UNE-EN ISO 52000-1:2017, fórmula 20";
+    /// Área de referencia (superficie útil considerada)
+    pub const AREAREF: &str = "DB-HE 2019, Anejo A, superficie útil";
+    /// Consumo de energía primaria total por m2, C_ep,tot
+    pub const C_EP_TOT: &str = "DB-HE 2019, HE0, apartado 3.2";
+    /// Consumo de energía primaria no renovable por m2, C_ep,nren
+    pub const C_EP_NREN: &str = "DB-HE 2019, HE0, apartado 3.1";
+}
+
 /// Calcula enficiencia energética agregando resultados por vector energético
 ///
 /// Compute overall energy performance by aggregating results from all energy carriers.
@@ -52,30 +74,188 @@ use crate::{
 /// * `wfactors` - weighting factors
 /// * `k_exp` - exported energy factor [0, 1]
 /// * `arearef` - reference area used for computing energy performance ratios
-/// * `load_matching` - whether statistical load matching is used or not
+/// * `load_matching` - load matching factor computation mode (see [`LoadMatchingMode`]).
+///   A boolean selects the monthly statistical expression (`true`) or a constant factor (`false`)
+///
+/// Uses the default numerical tolerances (see [`Tolerances`]).
 ///
 /// # Errors
 ///
 /// * Use of an `arearef` less than 1e-3 raises an error
 /// * Missing weighting factors needed for balance computation
 ///
-#[allow(non_snake_case)]
 pub fn energy_performance(
     components: &Components,
     wfactors: &Factors,
     k_exp: f32,
     arearef: f32,
-    load_matching: bool,
+    load_matching: impl Into<LoadMatchingMode>,
+) -> Result<EnergyPerformance> {
+    energy_performance_with_tolerances(
+        components,
+        wfactors,
+        k_exp,
+        arearef,
+        load_matching,
+        &Tolerances::default(),
+    )
+}
+
+/// Calcula enficiencia energética usando las tolerancias numéricas indicadas
+///
+/// Compute overall energy performance using the given numerical tolerances.
+///
+/// * `tolerances` - thresholds used to decide when a value is considered null
+///
+/// See [`energy_performance`] for the rest of the parameters.
+///
+/// # Errors
+///
+/// * Use of an `arearef` less than `tolerances.arearef_min` raises an error
+/// * Missing weighting factors needed for balance computation
+///
+pub fn energy_performance_with_tolerances(
+    components: &Components,
+    wfactors: &Factors,
+    k_exp: f32,
+    arearef: f32,
+    load_matching: impl Into<LoadMatchingMode>,
+    tolerances: &Tolerances,
+) -> Result<EnergyPerformance> {
+    energy_performance_with_hooks(
+        components,
+        wfactors,
+        k_exp,
+        arearef,
+        load_matching,
+        tolerances,
+        &CalcHooks::default(),
+    )
+}
+
+/// Calcula enficiencia energética con seguimiento del progreso y cancelación cooperativa
+///
+/// * `hooks` - función de progreso, a la que se llama tras el balance de cada vector energético,
+///   y testigo de cancelación, que se comprueba antes de cada balance por vector
+///
+/// See [`energy_performance_with_tolerances`] for the rest of the parameters.
+///
+/// # Errors
+///
+/// * Cancellation through the `hooks.cancel` token returns `EpbdError::Cancelled`
+/// * See [`energy_performance_with_tolerances`]
+///
+pub fn energy_performance_with_hooks(
+    components: &Components,
+    wfactors: &Factors,
+    k_exp: f32,
+    arearef: f32,
+    load_matching: impl Into<LoadMatchingMode>,
+    tolerances: &Tolerances,
+    hooks: &CalcHooks,
+) -> Result<EnergyPerformance> {
+    energy_performance_with(
+        components,
+        wfactors,
+        &CalcOptions {
+            k_exp,
+            arearef,
+            load_matching: load_matching.into(),
+            tolerances: *tolerances,
+            negative_use: NegativeUsePolicy::default(),
+            hooks: *hooks,
+        },
+    )
+}
+
+/// Calcula enficiencia energética con las opciones de cálculo indicadas
+///
+/// Compute overall energy performance using the given calculation options
+/// (exported energy factor, reference area, load matching mode, numerical tolerances and hooks).
+///
+/// * `components` - energy components
+/// * `wfactors` - weighting factors
+/// * `options` - calculation options (see [`CalcOptions`])
+///
+/// The functions [`energy_performance`], [`energy_performance_with_tolerances`] and
+/// [`energy_performance_with_hooks`] are shortcuts for this one.
+///
+/// The results keep a copy of the components and weighting factors. Use [`energy_performance_from`]
+/// to avoid that copy when the input data is no longer needed (e.g. with large hourly data).
+///
+/// # Errors
+///
+/// * Use of an `arearef` less than `options.tolerances.arearef_min` raises an error
+/// * Missing weighting factors needed for balance computation
+/// * Cancellation through the `options.hooks.cancel` token returns `EpbdError::Cancelled`
+///
+pub fn energy_performance_with(
+    components: &Components,
+    wfactors: &Factors,
+    options: &CalcOptions,
+) -> Result<EnergyPerformance> {
+    energy_performance_from(components.clone(), wfactors.clone(), options)
+}
+
+/// Calcula enficiencia energética tomando posesión de los componentes y factores de paso
+///
+/// Compute overall energy performance using the given calculation options, taking ownership
+/// of the energy components and weighting factors.
+///
+/// The components and factors are completed and stored in the results without being copied, so
+/// that large (e.g. hourly) input data is not duplicated in memory.
+///
+/// See [`energy_performance_with`] for the parameters and errors.
+///
+#[allow(non_snake_case)]
+pub fn energy_performance_from(
+    mut components: Components,
+    mut wfactors: Factors,
+    options: &CalcOptions,
 ) -> Result<EnergyPerformance> {
-    if arearef < 1e-3 {
+    let k_exp = options.k_exp;
+    let arearef = options.arearef;
+    let load_matching = options.load_matching;
+    let tolerances = &options.tolerances;
+    let hooks = &options.hooks;
+    if arearef < tolerances.arearef_min {
         return Err(EpbdError::WrongInput(format!(
             "El área de referencia no puede ser nula o casi nula y se encontró {}",
             arearef
         )));
     };
-    let components = components.clone();
-    let mut wfactors = wfactors.clone();
+    // Validate declared time resolution, export weighting criterion, heat export network and export factors by carrier
+    components.resolution()?;
+    let export_weighting = components.export_weighting()?;
+    components.heat_export_network()?;
+    components.optional_epb_services()?;
+    let kexp_by_cr = components.kexp_by_carrier()?;
+    if load_matching == LoadMatchingMode::Hourly
+        && Resolution::from_num_steps(components.num_steps()) != Some(Resolution::HORARIA)
+    {
+        return Err(EpbdError::WrongInput(format!(
+            "El factor de coincidencia de cargas horario requiere datos horarios (8760 pasos) y los componentes tienen {} pasos",
+            components.num_steps()
+        )));
+    }
+    // Complete an incomplete year of measured data with estimated values
+    let partial_year = match PartialYear::extrapolate(&components)? {
+        Some((completed, partial)) => {
+            components = completed;
+            Some(partial)
+        }
+        None => None,
+    };
+    let negative_uses = check_negative_uses(&mut components, options.negative_use)?;
+    let duplicates = check_duplicates(&components, tolerances.duplicates)?;
+    let scope_excluded = apply_scope_rules(&mut components)?;
+    apply_optional_epb_rules(&mut components)?;
+    let wfactors_periods = wfactors.apply_validity_periods(components.num_steps())?;
+    wfactors.add_subtype_factors(&components);
     wfactors.add_cgn_factors(&components)?;
+    wfactors.add_heat_export_factors(&components)?;
+    wfactors.check_required_derived(&components)?;
+    wfactors.check_timestep_series(components.num_steps())?;
 
     let mut balance = Balance::default();
 
@@ -84,16 +264,52 @@ pub fn energy_performance(
     balance.needs.CAL = components.needs.CAL.as_ref().map(|nd| nd.iter().sum());
     balance.needs.REF = components.needs.REF.as_ref().map(|nd| nd.iter().sum());
 
+    // Add zone energy needs and deviation of the sum of zone needs from building needs
+    for (zone_id, zone_needs) in &components.needs.zones {
+        let zone_bal_needs = balance.needs.zones.entry(*zone_id).or_default();
+        for service in [Service::ACS, Service::CAL, Service::REF] {
+            if let Some(nd) = zone_needs.get(service) {
+                zone_bal_needs.insert(service, nd.iter().sum());
+            }
+        }
+    }
+    for service in [Service::ACS, Service::CAL, Service::REF] {
+        if let (Some(building), Some(zones)) = (
+            balance.needs.get(service),
+            components.needs.zones_sum(service),
+        ) {
+            balance
+                .needs
+                .zones_dev
+                .insert(service, zones.iter().sum::<f32>() - building);
+        }
+    }
+
+    // Index components by carrier, once for all carrier balances
+    let cr_inputs = CarrierInput::by_carrier(&components, k_exp)?;
+
     // Compute balance for each carrier and accumulate partial balance values for total balance
-    let mut balance_cr: HashMap<Carrier, BalanceCarrier> = HashMap::new();
-    for cr in &components.available_carriers() {
-        // Compute balance for this carrier ---
-        let bal_cr = balance_for_carrier(*cr, &components, &wfactors, k_exp, load_matching)?;
+    let carriers: Vec<Carrier> = components.available_carriers().into_iter().collect();
+    let has_nepb = components.data.iter().any(|c| c.is_nepb_use());
+    let num_balances = if has_nepb { 2 } else { 1 } * carriers.len();
+    let balances = carrier_balances(&carriers, hooks, 0, num_balances, |cr| {
+        balance_for_carrier(
+            &cr_inputs[&cr],
+            &wfactors,
+            export_weighting,
+            load_matching,
+            false,
+            tolerances,
+        )
+    })?;
+    let mut balance_cr: BTreeMap<Carrier, BalanceCarrier> = BTreeMap::new();
+    for (cr, bal_cr) in carriers.iter().zip(balances) {
         // Add up to the global balance
         balance += &bal_cr;
         // Append to the map of balances by carrier
         balance_cr.insert(*cr, bal_cr);
     }
+    balance.we.update_b_share_by_cr(tolerances.zero);
 
     // Compute area weighted total balance
     let balance_m2 = balance.normalize_by_area(arearef);
@@ -105,17 +321,93 @@ pub fn energy_performance(
     let (rer_onst, rer_nrb) = {
         let tot = balance.we.b.tot();
         if tot > 0.0 {
-            let (onst, nrb) = ren_onst_nrb(&balance_cr, k_exp);
+            let k_exp_el = kexp_by_cr
+                .get(&Carrier::ELECTRICIDAD)
+                .copied()
+                .unwrap_or(k_exp);
+            let (onst, nrb) =
+                ren_onst_nrb(&balance_cr, k_exp_el, &components.nearby_opt_carriers());
             (onst / tot, nrb / tot)
         } else {
             (0.0, 0.0)
         }
     };
 
+    // Whole building balance, including non EPB uses (informative)
+    let whole_building = if has_nepb {
+        let mut balance_all = Balance {
+            needs: balance.needs.clone(),
+            ..Default::default()
+        };
+        let balances = carrier_balances(&carriers, hooks, carriers.len(), num_balances, |cr| {
+            balance_for_carrier(
+                &cr_inputs[&cr],
+                &wfactors,
+                export_weighting,
+                load_matching,
+                true,
+                tolerances,
+            )
+        })?;
+        for bal_cr in &balances {
+            balance_all += bal_cr;
+        }
+        balance_all.we.update_b_share_by_cr(tolerances.zero);
+        let balance_all_m2 = balance_all.normalize_by_area(arearef);
+        Some(WholeBuildingBalance {
+            rer: balance_all.we.b.rer(),
+            balance: balance_all,
+            balance_m2: balance_all_m2,
+        })
+    } else {
+        None
+    };
+
+    // Implicit peak power (informative)
+    let peaks = PeakPowers::compute(&components, &balance_cr);
+
+    // Photovoltaic system performance (informative)
+    let pv = PvPerformance::compute(&components, &balance_cr);
+
+    // Service balances restricted to their seasons (informative)
+    let seasons = ServiceSeason::compute(&components, &balance_cr)?;
+
+    // Energy needs not covered by the systems (informative)
+    let unmet_needs = UnmetNeeds::compute(&components, tolerances);
+
+    // Electrification of the final energy used in EPB services
+    let electrification = Electrification::compute(&balance_cr);
+
+    // Renewable primary energy produced onsite and used in EPB services
+    let onsite_renewable = OnsiteRenewable::compute(&balance_cr, &wfactors)?;
+
+    // Balance of each system (informative)
+    let balance_by_system = SystemBalance::compute(&components, &balance_cr);
+
+    // Balance of private and common uses (informative)
+    let balance_by_scope = ScopeBalance::compute(&components, &balance_cr, &scope_excluded);
+
+    // Sharing of common uses and production between units (informative)
+    let unit_shares = UnitShare::compute(&components, &balance_cr, &wfactors)?;
+
+    // Annual energy cost, when energy prices are declared (informative)
+    let energy_cost = EnergyCost::compute(&balance_cr, &wfactors, arearef);
+
+    // Energy flows by carrier, source and service (informative)
+    let flows = Flow::compute(&balance_cr);
+
+    // Embodied energy (informative)
+    let embodied_m2 = if components.embodied.is_empty() {
+        None
+    } else {
+        Some((1.0 / arearef) * components.embodied_total())
+    };
+
     // Energy performance data and results
-    Ok(EnergyPerformance {
+    let mut ep = EnergyPerformance {
         components,
         wfactors,
+        wfactors_periods,
         k_exp,
         arearef,
         balance_cr,
@@ -124,19 +416,522 @@ pub fn energy_performance(
         rer,
         rer_nrb,
         rer_onst,
+        tolerances: *tolerances,
+        duplicates,
+        whole_building,
+        peaks,
+        pv,
+        seasons,
+        unmet_needs,
+        electrification,
+        onsite_renewable,
+        balance_by_system,
+        balance_by_scope,
+        unit_shares,
+        energy_cost,
+        flows,
+        partial_year,
+        embodied_m2,
+        exergy: None,
+        bills: None,
+        marginal_co2: None,
+        co2_inventory: None,
+        warnings: Vec::new(),
         misc: None,
-    })
+    };
+    ep.warnings = balance_warnings(&ep, negative_uses);
+    Ok(ep)
+}
+
+/// Avisos del cálculo de la eficiencia energética
+///
+/// Incluye los avisos generados al normalizar los componentes y depurar los factores de paso y
+/// los detectados en los datos y resultados del balance, según las tolerancias del cálculo,
+/// además de los avisos de consumos EPB negativos (`negative_uses`).
+fn balance_warnings(ep: &EnergyPerformance, negative_uses: Vec<Warning>) -> Vec<Warning> {
+    let tolerances = &ep.tolerances;
+    let mut warnings = ep.components.warnings.clone();
+    warnings.extend(ep.wfactors.warnings.iter().cloned());
+    // Consumos EPB negativos
+    warnings.extend(negative_uses);
+    // Indicadores provisionales (año incompleto)
+    if let Some(partial) = &ep.partial_year {
+        warnings.push(Warning::warn(
+            WarningCode::ANNO_INCOMPLETO,
+            format!("indicadores provisionales, {}", partial),
+        ));
+    }
+    // Componentes duplicados
+    for duplicate in &ep.duplicates {
+        warnings.push(
+            Warning::warn(WarningCode::COMPONENTE_DUPLICADO, duplicate.to_string())
+                .with_component(duplicate.component.clone()),
+        );
+    }
+    // Coherencia entre las demandas del edificio y de sus zonas
+    for (service, dev) in ep
+        .balance
+        .needs
+        .zones_mismatch(tolerances.needs_zones_dev_max)
+    {
+        warnings.push(Warning::warn(
+            WarningCode::DEMANDA_ZONAS,
+            format!(
+                "la suma de las demandas de las zonas para el servicio {} difiere de la demanda del edificio ({:+.1} %)",
+                service,
+                100.0 * dev
+            ),
+        ));
+    }
+    // Rendimientos implícitos de los sistemas no plausibles (posibles errores en SALIDA)
+    for implausible in ep.components.implausible_efficiencies(tolerances) {
+        warnings.push(
+            Warning::warn(
+                WarningCode::RENDIMIENTO_NO_PLAUSIBLE,
+                implausible.to_string(),
+            )
+            .with_component(format!(
+                "{}, SALIDA, {}",
+                implausible.id, implausible.service
+            )),
+        );
+    }
+    // Consumos fuera de la temporada declarada de cada servicio
+    for (service, season) in &ep.seasons {
+        if season.used_off > tolerances.residual_use_min {
+            warnings.push(Warning::warn(
+                WarningCode::CONSUMO_FUERA_TEMPORADA,
+                format!(
+                    "consumo del servicio {} fuera de su temporada: {:.2} kWh ({:.1} %)",
+                    service,
+                    season.used_off,
+                    100.0 * season.off_fraction()
+                ),
+            ));
+        }
+    }
+    // Necesidades no cubiertas por los sistemas por encima de los umbrales de dimensionado
+    for (service, unmet) in &ep.unmet_needs {
+        if unmet.exceeds(tolerances) {
+            warnings.push(Warning::warn(
+                WarningCode::DEMANDA_NO_CUBIERTA,
+                format!(
+                    "demanda del servicio {} no cubierta por los sistemas: {:.2} kWh ({:.1} %) en {} pasos de cálculo",
+                    service,
+                    unmet.unmet_an,
+                    100.0 * unmet.fraction(),
+                    unmet.unmet_steps
+                ),
+            ));
+        }
+    }
+    warnings
+}
+
+/// Calcula el balance exergético a partir de los resultados del balance energético
+///
+/// Compute the exergy balance applying exergy quality factors to the delivered and exported
+/// energy of each carrier (annual values, as quality factors are constant).
+///
+/// * `ep` - energy performance results
+/// * `xfactors` - exergy quality factors, by carrier
+///
+/// # Errors
+///
+/// * Missing exergy factor for a carrier with delivered or exported energy
+///
+pub fn exergy_balance(ep: &EnergyPerformance, xfactors: &ExergyFactors) -> Result<ExergyBalance> {
+    let mut xbal = ExergyBalance {
+        factors: xfactors.clone(),
+        ..Default::default()
+    };
+    let mut del_an = 0.0;
+    for (&carrier, bal_cr) in &ep.balance_cr {
+        let (del, exp) = (&bal_cr.del, &bal_cr.exp);
+        if del.an.abs() < ep.tolerances.zero && exp.an.abs() < ep.tolerances.zero {
+            continue;
+        }
+        let q = xfactors.get(carrier).ok_or_else(|| {
+            EpbdError::MissingFactor(format!("calidad exergética de {}", carrier))
+        })?;
+        let b_cr = q * (del.an - ep.k_exp_for(carrier) * exp.an);
+        xbal.del += q * del.an;
+        xbal.del_grid += q * del.grid_an;
+        xbal.del_onst += q * del.onst_an;
+        xbal.exp += q * exp.an;
+        xbal.b += b_cr;
+        xbal.b_by_cr.insert(carrier, b_cr);
+        del_an += del.an;
+    }
+    xbal.b_m2 = xbal.b / ep.arearef;
+    xbal.q_del = if del_an > ep.tolerances.zero {
+        xbal.del / del_an
+    } else {
+        0.0
+    };
+    Ok(xbal)
+}
+
+/// Concilia la energía suministrada por la red calculada con la energía facturada
+///
+/// Compare, month by month, the energy delivered from the grid for each billed carrier with
+/// the billed energy. Computed values for daily or hourly timesteps are aggregated by month.
+///
+/// As meters measure the whole building, energy delivered from the grid includes, besides
+/// the EPB delivered energy, the energy used in cogeneration and the non EPB uses not covered
+/// by exported energy.
+///
+/// * `ep` - energy performance results
+/// * `bills` - monthly billed energy, by carrier
+///
+/// # Errors
+///
+/// * Timesteps that can't be aggregated by month (12, 365 or 8760 timesteps are needed)
+pub fn bills_reconciliation(ep: &EnergyPerformance, bills: &Bills) -> Result<BillsReconciliation> {
+    let num_steps = ep.components.num_steps();
+    if monthly_values(&vec![0.0; num_steps]).is_none() {
+        return Err(EpbdError::WrongInput(format!(
+            "No se pueden agregar por meses los {} pasos de cálculo de los componentes para conciliar con las facturas",
+            num_steps
+        )));
+    }
+    let mut rec = BillsReconciliation {
+        bills: bills.clone(),
+        ..Default::default()
+    };
+    for billed in &bills.data {
+        let computed = ep
+            .balance_cr
+            .get(&billed.carrier)
+            .and_then(|bal_cr| monthly_values(&metered_grid_t(bal_cr)))
+            .unwrap_or_else(|| vec![0.0; 12]);
+        rec.by_cr.insert(
+            billed.carrier,
+            CarrierReconciliation::new(computed, billed.values.clone()),
+        );
+    }
+    rec.unbilled = ep
+        .balance_cr
+        .iter()
+        .filter(|(cr, bal_cr)| {
+            !cr.is_onsite()
+                && bal_cr.del.grid_an.abs() > ep.tolerances.zero
+                && bills.get(**cr).is_none()
+        })
+        .map(|(cr, _)| *cr)
+        .collect();
+    Ok(rec)
+}
+
+/// Calcula la huella de carbono marginal a partir de los resultados del balance energético
+///
+/// Compute, at each timestep, the marginal emissions of the energy delivered from the grid and the
+/// avoided marginal emissions by the produced energy (onsite or cogenerated) used in EPB services and by
+/// the energy exported to the grid, for each carrier with marginal emission factors.
+/// These are informative results (e.g. for research) and do not modify the EPB balance.
+///
+/// * `ep` - energy performance results
+/// * `mfactors` - marginal emission factors at each timestep, by carrier
+///
+/// # Errors
+///
+/// * Number of marginal emission factors different from the number of timesteps
+///
+pub fn marginal_carbon(
+    ep: &EnergyPerformance,
+    mfactors: &MarginalCo2Factors,
+) -> Result<MarginalCarbon> {
+    let num_steps = ep.components.num_steps();
+    let mut mbal = MarginalCarbon {
+        factors: mfactors.clone(),
+        avoided_t: vec![0.0; num_steps],
+        ..Default::default()
+    };
+    for series in &mfactors.data {
+        if series.values.len() != num_steps {
+            return Err(EpbdError::WrongInput(format!(
+                "Se esperaban {} factores de emisión marginales de {} y se encontraron {}",
+                num_steps,
+                series.carrier,
+                series.values.len()
+            )));
+        }
+        let Some(bal_cr) = ep.balance_cr.get(&series.carrier) else {
+            continue;
+        };
+        let del_grid_t = vecvecmul(&bal_cr.del.grid_t, &series.values);
+        let avoided_used_t = vecvecmul(&bal_cr.prod.epus_t, &series.values);
+        let avoided_exp_t = vecvecmul(&bal_cr.exp.grid_t, &series.values);
+        let mbal_cr = MarginalCarbonCarrier {
+            del_grid_an: vecsum(&del_grid_t),
+            avoided_used_an: vecsum(&avoided_used_t),
+            avoided_exp_an: vecsum(&avoided_exp_t),
+            del_grid_t,
+            avoided_used_t,
+            avoided_exp_t,
+        };
+        mbal.del_grid += mbal_cr.del_grid_an;
+        mbal.avoided += mbal_cr.avoided_used_an + mbal_cr.avoided_exp_an;
+        mbal.avoided_t = vecvecsum(
+            &mbal.avoided_t,
+            &vecvecsum(&mbal_cr.avoided_used_t, &mbal_cr.avoided_exp_t),
+        );
+        mbal.by_cr.insert(series.carrier, mbal_cr);
+    }
+    mbal.net_m2 = (mbal.del_grid - mbal.avoided) / ep.arearef;
+    Ok(mbal)
+}
+
+/// Calcula las emisiones con un juego alternativo de factores de emisión
+///
+/// Weigh the delivered and exported energy of the energy balance with the official weighting
+/// factors, replacing their CO2 emission factors with the alternative ones when available
+/// (e.g. GHG Protocol location-based or market-based inventory factors). Primary energy
+/// results are not modified and both emission results are labelled.
+///
+/// * `ep` - energy performance results
+/// * `cfactors` - alternative emission factors
+///
+/// # Errors
+///
+/// * Missing weighting factors needed for the balance computation
+///
+pub fn co2_inventory(
+    ep: &EnergyPerformance,
+    cfactors: &InventoryCo2Factors,
+) -> Result<Co2Inventory> {
+    let export_weighting = ep.components.export_weighting()?;
+    let mut wfactors = ep.wfactors.clone();
+    for fp in wfactors.wdata.iter_mut() {
+        if let Some(co2) = cfactors.get(fp.carrier, fp.source, fp.dest, fp.step) {
+            fp.co2 = co2;
+            fp.values_t.iter_mut().for_each(|v| v.co2 = co2);
+        }
+    }
+    let mut inventory = Co2Inventory {
+        factors: cfactors.clone(),
+        official_label: CO2_OFFICIAL_LABEL.to_string(),
+        label: cfactors.label(),
+        official: ep.balance.we.b.co2,
+        official_m2: ep.balance_m2.we.b.co2,
+        ..Default::default()
+    };
+    for (&carrier, bal_cr) in &ep.balance_cr {
+        let we = compute_weighted_energy(
+            carrier,
+            ep.k_exp_for(carrier),
+            &wfactors,
+            export_weighting,
+            &bal_cr.used,
+            &bal_cr.exp,
+            &bal_cr.del,
+        )?;
+        inventory.a += we.a.co2;
+        inventory.b += we.b.co2;
+        inventory.b_by_cr.insert(carrier, we.b.co2);
+        for (service, we_srv) in &we.b_by_srv {
+            *inventory.b_by_srv.entry(*service).or_default() += we_srv.co2;
+        }
+    }
+    inventory.b_m2 = inventory.b / ep.arearef;
+    Ok(inventory)
+}
+
+/// Energía suministrada por la red a todo el edificio (usos EPB, no EPB y cogeneración)
+///
+/// Energy delivered from the grid as measured by meters, at each timestep
+fn metered_grid_t(bal_cr: &BalanceCarrier) -> Vec<f32> {
+    let nepus_grid_t = vecvecdif(&bal_cr.used.nepus_t, &bal_cr.exp.nepus_t);
+    vecvecsum(
+        &vecvecsum(&bal_cr.del.grid_t, &bal_cr.del.cgn_t),
+        &nepus_grid_t,
+    )
+}
+
+/// Detecta consumos EPB negativos en los componentes y aplica el tratamiento indicado
+///
+/// Check EPB energy use components with negative values and apply the negative use policy.
+///
+/// * `Allow` - negative values are kept
+/// * `Truncate` - negative values are replaced by zero
+/// * `Error` - an error is raised
+///
+/// All negative uses are returned as warnings (`CONSUMO_NEGATIVO`), so they can be reported.
+///
+/// # Errors
+///
+/// * Negative EPB energy use with the `Error` policy
+fn check_negative_uses(
+    components: &mut Components,
+    policy: NegativeUsePolicy,
+) -> Result<Vec<Warning>> {
+    let mut negative_uses = Vec::new();
+    for component in components.data.iter_mut().filter(|c| c.is_epb_use()) {
+        let values = match component {
+            Energy::Used(e) => &mut e.values,
+            Energy::Aux(e) => &mut e.values,
+            _ => continue,
+        };
+        let steps: Vec<usize> = values
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v < 0.0)
+            .map(|(i, _)| i)
+            .collect();
+        if steps.is_empty() {
+            continue;
+        }
+        let value: f32 = steps.iter().map(|i| values[*i]).sum();
+        if policy == NegativeUsePolicy::Truncate {
+            values.iter_mut().for_each(|v| *v = v.max(0.0));
+        }
+        let (id, carrier, service) = (component.id(), component.carrier(), component.service());
+        negative_uses.push(
+            Warning::warn(
+                WarningCode::CONSUMO_NEGATIVO,
+                format!(
+                    "consumo negativo de {} para {} en el sistema con id:{} ({:.2} kWh en {} pasos de cálculo)",
+                    carrier,
+                    service,
+                    id,
+                    value,
+                    steps.len()
+                ),
+            )
+            .with_component(format!("{}, {}, CONSUMO, {}", id, carrier, service)),
+        );
+    }
+
+    if policy == NegativeUsePolicy::Error && !negative_uses.is_empty() {
+        return Err(EpbdError::WrongInput(format!(
+            "Consumos EPB negativos: {}",
+            negative_uses
+                .iter()
+                .map(|w| w.message.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    for warning in &mut negative_uses {
+        warning.message = format!("{} (tratamiento: {})", warning.message, policy);
+    }
+
+    Ok(negative_uses)
+}
+
+/// Aplica las reglas de inclusión en el balance EPB de los consumos de cada ámbito
+///
+/// Los consumos EPB de un ámbito con reglas (`CTE_AMBITO_EPB_<AMBITO>`) para servicios no incluidos
+/// en ellas pasan a ser consumos no EPB (NEPB). Devuelve la energía excluida por ámbito y servicio declarado.
+///
+/// # Errors
+///
+/// * Ámbito desconocido en la etiqueta `CTEEPBD_AMBITO` de algún consumo
+/// * Reglas de ámbito incorrectas
+fn apply_scope_rules(
+    components: &mut Components,
+) -> Result<BTreeMap<Scope, BTreeMap<Service, f32>>> {
+    let rules = components.scope_rules()?;
+    let mut excluded: BTreeMap<Scope, BTreeMap<Service, f32>> = BTreeMap::new();
+    for c in components.data.iter_mut() {
+        let scope = c.scope()?;
+        let Energy::Used(e) = c else {
+            continue;
+        };
+        let Some(included) = rules.get(&scope) else {
+            continue;
+        };
+        if e.service.is_epb() && !included.contains(&e.service) {
+            *excluded
+                .entry(scope)
+                .or_default()
+                .entry(e.service)
+                .or_default() += vecsum(&e.values);
+            e.service = Service::NEPB;
+        }
+    }
+    Ok(excluded)
+}
+
+/// Aplica el tratamiento de los consumos de servicios no EPB por defecto (VE, APP) indicado en los metadatos
+///
+/// Con el metadato `CTE_<SERVICIO>_EPB: true` todos los consumos del servicio se etiquetan como consumos
+/// EPB (`CTEEPBD_<SERVICIO>_EPB`).
+///
+/// # Errors
+///
+/// * Valor incorrecto de los metadatos `CTE_<SERVICIO>_EPB`
+fn apply_optional_epb_rules(components: &mut Components) -> Result<()> {
+    let services = components.optional_epb_services()?;
+    if services.is_empty() {
+        return Ok(());
+    }
+    for c in components.data.iter_mut() {
+        let Some(service) = c.optional_epb_service() else {
+            continue;
+        };
+        if !services.contains(&service) || c.is_labelled_epb_use() {
+            continue;
+        }
+        let tag = format!("CTEEPBD_{}_EPB", service);
+        let comment = match c {
+            Energy::Used(e) => &mut e.comment,
+            Energy::Aux(e) => &mut e.comment,
+            _ => continue,
+        };
+        if comment.is_empty() {
+            *comment = tag;
+        } else {
+            comment.push(' ');
+            comment.push_str(&tag);
+        }
+    }
+    Ok(())
+}
+
+/// Comprueba los componentes de energía duplicados según el tratamiento indicado
+///
+/// Los componentes duplicados se suman en el balance en todos los casos. Con el tratamiento
+/// `Warn` se devuelven para su registro en los resultados.
+///
+/// # Errors
+///
+/// * Duplicated energy components with the `Error` policy
+fn check_duplicates(
+    components: &Components,
+    policy: DuplicatePolicy,
+) -> Result<Vec<DuplicateComponent>> {
+    if policy == DuplicatePolicy::Sum {
+        return Ok(Vec::new());
+    }
+    let duplicates = components.duplicates();
+    if policy == DuplicatePolicy::Error && !duplicates.is_empty() {
+        return Err(EpbdError::WrongInput(format!(
+            "Componentes duplicados: {}",
+            duplicates
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    Ok(duplicates)
 }
 
 /// Renewable energy used (EPB services) from onsite and nearby sources
 /// This excludes the impact on the grid of the exported energy
 /// Cogen generation is considered onsite (and its renewable contribution depends on the step A factor)
-fn ren_onst_nrb(balance_cr: &HashMap<Carrier, BalanceCarrier>, k_exp: f32) -> (f32, f32) {
+/// Optional nearby carriers (e.g. BIOCARBURANTE) are included when given in `nearby_opt`
+fn ren_onst_nrb(
+    balance_cr: &BTreeMap<Carrier, BalanceCarrier>,
+    k_exp: f32,
+    nearby_opt: &[Carrier],
+) -> (f32, f32) {
     // 1. Renewable energy from all nearby carriers (excluding electricity)
     let ren_nrb_cr = balance_cr
         .iter()
         .map(|(carrier, bal)| {
-            if carrier.is_nearby() {
+            if carrier.is_nearby_with(nearby_opt) {
                 bal.we.b.ren
             } else {
                 0.0
@@ -184,6 +979,167 @@ fn ren_onst_nrb(balance_cr: &HashMap<Carrier, BalanceCarrier>, k_exp: f32) -> (f
 
 // ///////////// By Carrier timestep and annual computations ////////////
 
+/// Componentes de un vector energético y datos necesarios para su balance
+///
+/// Guarda referencias a los componentes del vector, sin copiar sus valores, y los metadatos
+/// que usa su balance ya interpretados, de modo que los balances del vector (p.e. el de los usos
+/// EPB y el del edificio completo) no vuelven a recorrer ni a interpretar todos los componentes.
+struct CarrierInput<'a> {
+    /// Vector energético
+    carrier: Carrier,
+    /// Componentes del vector
+    cr_list: Vec<&'a Energy>,
+    /// Factor de exportación del vector (CTE_KEXP_<VECTOR>), o el general
+    k_exp: f32,
+    /// Producción imputada a servicios
+    dedicated: BTreeMap<Service, Vec<f32>>,
+    /// Cupos de producción por servicio (solo electricidad)
+    quotas: BTreeMap<Service, f32>,
+    /// Prioridades de sistemas consumidores (solo electricidad)
+    sys_priorities: BTreeMap<i32, Vec<i32>>,
+    /// Sistemas de almacenamiento (solo electricidad)
+    storages: &'a [Storage],
+}
+
+impl<'a> CarrierInput<'a> {
+    /// Agrupa los componentes por vector energético, recorriéndolos una sola vez
+    ///
+    /// * `k_exp` - factor de exportación general, para los vectores sin uno propio
+    fn by_carrier(components: &'a Components, k_exp: f32) -> Result<BTreeMap<Carrier, Self>> {
+        let kexp_by_cr = components.kexp_by_carrier()?;
+        let mut lists: BTreeMap<Carrier, Vec<&Energy>> = BTreeMap::new();
+        for e in components.data.iter().filter(|e| !e.is_out()) {
+            lists.entry(e.carrier()).or_default().push(e);
+        }
+        lists
+            .into_iter()
+            .map(|(carrier, cr_list)| {
+                let k_exp = kexp_by_cr.get(&carrier).copied().unwrap_or(k_exp);
+                Self::new(carrier, cr_list, components, k_exp)
+                    .map(|input| (carrier, input))
+                    .map_err(|e| e.for_carrier(carrier))
+            })
+            .collect()
+    }
+
+    /// Datos para el balance de un vector a partir de la lista de sus componentes
+    fn new(
+        carrier: Carrier,
+        cr_list: Vec<&'a Energy>,
+        components: &'a Components,
+        k_exp: f32,
+    ) -> Result<Self> {
+        // Production dedicated to services
+        let dedicated = components.dedicated_productions(carrier)?;
+
+        // Production quotas by service, priorities of consumer systems and electric storage systems (only for electricity)
+        let (quotas, sys_priorities, storages) = if carrier == Carrier::ELECTRICIDAD {
+            (
+                components.production_quotas()?,
+                components.production_priorities()?,
+                components.storages.as_slice(),
+            )
+        } else {
+            (BTreeMap::new(), BTreeMap::new(), &[][..])
+        };
+
+        Ok(Self {
+            carrier,
+            cr_list,
+            k_exp,
+            dedicated,
+            quotas,
+            sys_priorities,
+            storages,
+        })
+    }
+
+    /// Energía usada y producida del vector y factor de coincidencia de cargas de cada paso
+    fn used_produced(
+        &self,
+        load_matching: LoadMatchingMode,
+        include_nepb: bool,
+        tolerances: &Tolerances,
+    ) -> (UsedEnergy, ProducedEnergy, Vec<f32>) {
+        compute_used_produced(
+            &self.cr_list,
+            load_matching,
+            include_nepb,
+            &self.quotas,
+            &self.sys_priorities,
+            &self.dedicated,
+            self.storages,
+            tolerances.prod_min,
+        )
+    }
+}
+
+/// Calcula los balances de los vectores energéticos indicados, en su mismo orden
+///
+/// Antes de cada balance se comprueba la cancelación del cálculo y tras él se notifica el progreso,
+/// contando los `done` balances ya calculados de un total de `total`. Los errores se asocian al vector.
+#[cfg(not(feature = "parallel"))]
+fn carrier_balances<F>(
+    carriers: &[Carrier],
+    hooks: &CalcHooks,
+    done: usize,
+    total: usize,
+    balance: F,
+) -> Result<Vec<BalanceCarrier>>
+where
+    F: Fn(Carrier) -> Result<BalanceCarrier> + Sync,
+{
+    let mut balances = Vec::with_capacity(carriers.len());
+    for (i, cr) in carriers.iter().enumerate() {
+        if hooks.is_cancelled() {
+            return Err(EpbdError::Cancelled);
+        }
+        balances.push(balance(*cr).map_err(|e| e.for_carrier(*cr))?);
+        hooks.progress(*cr, done + i + 1, total);
+    }
+    Ok(balances)
+}
+
+/// Calcula los balances de los vectores energéticos indicados, en paralelo y en su mismo orden
+///
+/// Los balances de cada vector son independientes y se reparten entre los hilos de rayon.
+/// La cancelación se comprueba antes de cada balance y al terminar todos ellos, y el progreso
+/// se notifica, en el orden de los vectores, cuando han terminado todos los balances.
+/// En caso de error se devuelve el del primer vector que falla, como en el cálculo secuencial.
+#[cfg(feature = "parallel")]
+fn carrier_balances<F>(
+    carriers: &[Carrier],
+    hooks: &CalcHooks,
+    done: usize,
+    total: usize,
+    balance: F,
+) -> Result<Vec<BalanceCarrier>>
+where
+    F: Fn(Carrier) -> Result<BalanceCarrier> + Sync,
+{
+    use rayon::prelude::*;
+
+    let cancel = hooks.cancel;
+    let results: Vec<Result<BalanceCarrier>> = carriers
+        .par_iter()
+        .map(|cr| {
+            if cancel.is_some_and(|token| token.is_cancelled()) {
+                return Err(EpbdError::Cancelled);
+            }
+            balance(*cr).map_err(|e| e.for_carrier(*cr))
+        })
+        .collect();
+    let mut balances = Vec::with_capacity(carriers.len());
+    for (i, (cr, res)) in carriers.iter().zip(results).enumerate() {
+        balances.push(res?);
+        hooks.progress(*cr, done + i + 1, total);
+    }
+    if hooks.is_cancelled() {
+        return Err(EpbdError::Cancelled);
+    }
+    Ok(balances)
+}
+
 /// Calcula el balance energético para un vector energético
 ///
 /// Calculate energy balance for a single energy carrier.
@@ -191,35 +1147,40 @@ fn ren_onst_nrb(balance_cr: &HashMap<Carrier, BalanceCarrier>, k_exp: f32) -> (f
 /// This follows the ISO EN 52000-1 procedure for calculation of delivered,
 /// exported and weighted energy balance.
 ///
-/// * `cr_list` - list of components for carrier
-/// * `k_exp` - exported energy factor [0, 1]
-/// * `fp_cr` - weighting factors for carrier
+/// * `cr_input` - components of the carrier and data needed for its balance
+/// * `wfactors` - weighting factors
+/// * `export_weighting` - weighting criterion for exported energy
+/// * `include_nepb` - compute non EPB uses as EPB uses (whole building balance)
 ///
 /// # Errors
 ///
 /// * Missing weighting factors for a carrier, source type, destination or calculation step
 #[allow(non_snake_case)]
 fn balance_for_carrier(
-    carrier: Carrier,
-    components: &Components,
+    cr_input: &CarrierInput,
     wfactors: &Factors,
-    k_exp: f32,
-    load_matching: bool,
+    export_weighting: ExportWeighting,
+    load_matching: LoadMatchingMode,
+    include_nepb: bool,
+    tolerances: &Tolerances,
 ) -> Result<BalanceCarrier> {
-    let cr_list: Vec<Energy> = components
-        .data
-        .iter()
-        .filter(|e| e.has_carrier(carrier))
-        .cloned()
-        .collect();
+    let carrier = cr_input.carrier;
 
     // Compute used and produced energy from components
-    let (used, prod, f_match) = compute_used_produced(cr_list, load_matching);
+    let (used, prod, f_match) = cr_input.used_produced(load_matching, include_nepb, tolerances);
 
     // Compute exported and delivered energy from used and produced energy data
     let (exp, del) = compute_exported_delivered(&used, &prod);
 
-    let we = compute_weighted_energy(carrier, k_exp, wfactors, &used, &exp, &del)?;
+    let we = compute_weighted_energy(
+        carrier,
+        cr_input.k_exp,
+        wfactors,
+        export_weighting,
+        &used,
+        &exp,
+        &del,
+    )?;
 
     Ok(BalanceCarrier {
         carrier,
@@ -232,89 +1193,183 @@ fn balance_for_carrier(
     })
 }
 
+/// Energía usada y producida de un vector, sin calcular la energía exportada, suministrada ni ponderada
+///
+/// Aplica las producciones imputadas a servicios, los cupos de producción por servicio y las listas
+/// de prioridad de sistemas consumidores (estos dos últimos solo para la electricidad) y permite obtener el reparto por servicios de la energía producida
+/// sin el balance completo (p.e. para la fracción renovable de la demanda de ACS).
+///
+/// Devuelve la energía usada, la producida y el factor de coincidencia de cargas de cada paso.
+#[cfg(feature = "cte")]
+pub(crate) fn used_produced_for_carrier(
+    carrier: Carrier,
+    components: &Components,
+    load_matching: LoadMatchingMode,
+    include_nepb: bool,
+    tolerances: &Tolerances,
+) -> Result<(UsedEnergy, ProducedEnergy, Vec<f32>)> {
+    let cr_list: Vec<&Energy> = components
+        .data
+        .iter()
+        .filter(|e| e.has_carrier(carrier))
+        .collect();
+    let cr_input = CarrierInput::new(carrier, cr_list, components, 0.0)?;
+    Ok(cr_input.used_produced(load_matching, include_nepb, tolerances))
+}
+
 /// Compute used and produced energy data from energy components
 ///
-/// TODO: Battery storage support (sto)
-#[allow(non_snake_case)]
+/// When `include_nepb` is true, non EPB uses are accounted as EPB uses.
+///
+/// When `quotas` are given, produced energy used by each service is limited to that fraction of its use
+/// (see [`apply_production_quotas`]).
+///
+/// When `sys_priorities` are given (and there are no `quotas`), produced energy from shared onsite
+/// productions is distributed among consumer systems following their priority lists
+/// (see [`apply_system_priorities`]).
+///
+/// When `dedicated` productions by service are given (and there are no `quotas` nor `sys_priorities`),
+/// produced energy used is first imputed to those services (see [`apply_dedicated_productions`]).
+///
+/// When `storages` are given, onsite production surplus is shifted to later timesteps before
+/// computing produced energy used, exported and delivered energy (see [`apply_storages`]).
+#[allow(non_snake_case, clippy::too_many_arguments)]
 fn compute_used_produced(
-    cr_list: Vec<Energy>,
-    load_matching: bool,
+    cr_list: &[&Energy],
+    load_matching: LoadMatchingMode,
+    include_nepb: bool,
+    quotas: &BTreeMap<Service, f32>,
+    sys_priorities: &BTreeMap<i32, Vec<i32>>,
+    dedicated: &BTreeMap<Service, Vec<f32>>,
+    storages: &[Storage],
+    prod_min: f32,
 ) -> (UsedEnergy, ProducedEnergy, Vec<f32>) {
     // We know all carriers have the same time steps (see FromStr for Components)
     let num_steps = cr_list[0].num_steps();
     let carrier = cr_list[0].carrier();
 
     let mut E_EPus_cr_t = vec![0.0; num_steps];
-    let mut E_EPus_cr_t_by_srv: HashMap<Service, Vec<f32>> = HashMap::new();
+    let mut E_EPus_cr_t_by_srv: BTreeMap<Service, Vec<f32>> = BTreeMap::new();
     let mut E_nEPus_cr_t = vec![0.0; num_steps];
+    let mut E_nEPus_cr_t_by_srv: BTreeMap<Service, Vec<f32>> = BTreeMap::new();
     let mut E_cgn_in_cr_t = vec![0.0; num_steps];
-    let mut E_pr_cr_j_t = HashMap::<ProdSource, Vec<f32>>::new();
-    for c in &cr_list {
+    let mut E_pr_cr_j_t = BTreeMap::<ProdSource, Vec<f32>>::new();
+    let mut E_pr_cr_sto_t = vec![0.0; num_steps];
+    // Used energy by system and service, and production of shared systems (with priority lists)
+    let mut E_EPus_cr_t_by_sys_srv: BTreeMap<(i32, Service), Vec<f32>> = BTreeMap::new();
+    let mut E_pr_cr_t_by_shared_id: BTreeMap<i32, Vec<f32>> = BTreeMap::new();
+    for c in cr_list {
         let vals = c.values();
         if c.is_generated() {
+            // Production of shared systems
+            if c.is_onsite_pr() && sys_priorities.contains_key(&c.id()) {
+                E_pr_cr_t_by_shared_id
+                    .entry(c.id())
+                    .and_modify(|e| vecvecsum_assign(e, vals))
+                    .or_insert_with(|| vals.to_owned());
+            }
+            // Energy released from storage systems
+            if c.is_from_storage() {
+                vecvecsum_assign(&mut E_pr_cr_sto_t, vals);
+            }
             // Onsite production + electr. cogeneration
             E_pr_cr_j_t
                 .entry(c.prod_source())
-                .and_modify(|e| *e = vecvecsum(e, vals))
+                .and_modify(|e| vecvecsum_assign(e, vals))
                 .or_insert_with(|| vals.to_owned());
-        } else if c.is_epb_use() {
+        } else if c.is_epb_use() || (include_nepb && c.is_nepb_use()) {
             // EPB services
             E_EPus_cr_t_by_srv
                 .entry(c.service())
-                .and_modify(|e| *e = vecvecsum(e, vals))
+                .and_modify(|e| vecvecsum_assign(e, vals))
                 .or_insert_with(|| vals.to_owned());
-            E_EPus_cr_t = vecvecsum(&E_EPus_cr_t, vals)
+            if !sys_priorities.is_empty() {
+                E_EPus_cr_t_by_sys_srv
+                    .entry((c.id(), c.service()))
+                    .and_modify(|e| vecvecsum_assign(e, vals))
+                    .or_insert_with(|| vals.to_owned());
+            }
+            vecvecsum_assign(&mut E_EPus_cr_t, vals);
         } else if c.is_cogen_use() {
             // Cogeneration input
-            E_cgn_in_cr_t = vecvecsum(&E_cgn_in_cr_t, vals)
+            vecvecsum_assign(&mut E_cgn_in_cr_t, vals);
         } else {
             // Non EPB services
-            E_nEPus_cr_t = vecvecsum(&E_nEPus_cr_t, vals)
+            E_nEPus_cr_t_by_srv
+                .entry(c.service())
+                .and_modify(|e| vecvecsum_assign(e, vals))
+                .or_insert_with(|| vals.to_owned());
+            vecvecsum_assign(&mut E_nEPus_cr_t, vals);
         }
     }
+
+    // Electric storage systems shift production surplus to cover later uses
+    if !storages.is_empty() && !E_pr_cr_j_t.is_empty() {
+        apply_storages(
+            storages,
+            &vecvecsum(&E_EPus_cr_t, &E_nEPus_cr_t),
+            &mut E_pr_cr_j_t,
+            &mut E_pr_cr_sto_t,
+        );
+    }
+
     let E_EPus_cr_an = vecsum(&E_EPus_cr_t);
     let E_nEPus_cr_an = vecsum(&E_nEPus_cr_t);
     let E_cgn_in_cr_an = vecsum(&E_cgn_in_cr_t);
 
     // Used energy for this carrier for each service for all timesteps
-    let mut E_EPus_cr_an_by_srv = HashMap::<Service, f32>::new();
+    let mut E_EPus_cr_an_by_srv = BTreeMap::<Service, f32>::new();
     for (service, epus_srv) in &E_EPus_cr_t_by_srv {
         E_EPus_cr_an_by_srv.insert(*service, vecsum(epus_srv));
     }
 
+    // Used energy for this carrier for each non EPB service for all timesteps
+    let E_nEPus_cr_an_by_srv: BTreeMap<Service, f32> = E_nEPus_cr_t_by_srv
+        .iter()
+        .map(|(service, nepus_srv)| (*service, vecsum(nepus_srv)))
+        .collect();
+
     // Generation for this carrier from all sources j at each timestep
     let mut E_pr_cr_t = vec![0.0; num_steps];
     // Generation for this carrier from each source for all time steps
-    let mut E_pr_cr_j_an = HashMap::<ProdSource, f32>::new();
+    let mut E_pr_cr_j_an = BTreeMap::<ProdSource, f32>::new();
     for (source, prod_cr_j) in &E_pr_cr_j_t {
-        E_pr_cr_t = vecvecsum(&E_pr_cr_t, prod_cr_j);
+        vecvecsum_assign(&mut E_pr_cr_t, prod_cr_j);
         E_pr_cr_j_an.insert(*source, vecsum(prod_cr_j));
     }
     let E_pr_cr_an = vecsum(&E_pr_cr_t);
+    let E_pr_cr_sto_an = vecsum(&E_pr_cr_sto_t);
 
     // Load matching factor (32) (11.6.2.4)
     let f_match_t = compute_f_match(&E_pr_cr_t, &E_EPus_cr_t, load_matching);
 
     // Generated energy from source j used in EP
     // If there is more than one source... it could have priorities
-    // Compute using priorities priorities (9.6.62.4). EL_INSITU > EL_COGEN
+    // Compute using priorities priorities (9.6.62.4). EL_INSITU > EL_EOLICA > EL_COGEN
+    // Only sources with production are considered
     let (has_priorities, priorities) = ProdSource::get_priorities(carrier);
+    let priorities: Vec<ProdSource> = priorities
+        .into_iter()
+        .filter(|s| E_pr_cr_j_an.contains_key(s))
+        .collect();
+
+    let use_priorities = has_priorities && priorities.len() > 1;
 
     let mut E_pr_cr_used_EPus_t = vec![0.0; num_steps];
-    let mut E_pr_cr_j_used_EPus_t = HashMap::<ProdSource, Vec<f32>>::new();
-    if has_priorities && priorities.iter().all(|s| E_pr_cr_j_an.contains_key(s)) {
+    let mut E_pr_cr_j_used_EPus_t = BTreeMap::<ProdSource, Vec<f32>>::new();
+    if use_priorities {
         // Energy used for that carrier (9)
         let mut E_EPus_cr_left_t = E_EPus_cr_t.clone();
         // Priorities: sources with a higher priority are used first
-        for source in priorities {
+        for source in &priorities {
             // Max usable production (wrt EP uses) (10)
-            let E_pr_cr_j_usmax_t = vecvecmin(&E_pr_cr_j_t[&source], &E_EPus_cr_left_t);
+            let E_pr_cr_j_usmax_t = vecvecmin(&E_pr_cr_j_t[source], &E_EPus_cr_left_t);
             // Energy left for source with next priority (11)
             E_EPus_cr_left_t = vecvecdif(&E_EPus_cr_left_t, &E_pr_cr_j_usmax_t);
             // Energy used for this priority (12) & add to total used in EPB services
             let used = vecvecmul(&E_pr_cr_j_usmax_t, &f_match_t);
-            E_pr_cr_used_EPus_t = vecvecsum(&E_pr_cr_used_EPus_t, &used);
-            E_pr_cr_j_used_EPus_t.insert(source, used);
+            vecvecsum_assign(&mut E_pr_cr_used_EPus_t, &used);
+            E_pr_cr_j_used_EPus_t.insert(*source, used);
             // Add to total produced and used in EPB services
         }
     } else {
@@ -326,30 +1381,101 @@ fn compute_used_produced(
             let f_pr_cr_j: Vec<_> = prod_cr_j_t
                 .iter()
                 .zip(E_pr_cr_t.iter())
-                .map(|(pr_j, pr_all)| if *pr_all > 1e-3 { pr_j / pr_all } else { 0.0 })
+                .map(|(pr_j, pr_all)| {
+                    if *pr_all > prod_min {
+                        pr_j / pr_all
+                    } else {
+                        0.0
+                    }
+                })
                 .collect();
             E_pr_cr_j_used_EPus_t.insert(*source, vecvecmul(&E_pr_cr_used_EPus_t, &f_pr_cr_j));
         }
     }
 
+    // Share of produced energy used by each service at each timestep
+    // By default, it's proportional to the energy used by each service
+    let mut f_us_cr_by_srv_t = compute_f_us_cr_by_srv_t(&E_EPus_cr_t, &E_EPus_cr_t_by_srv);
+
+    // Priorities of consumer systems for shared productions
+    let mut E_pr_cr_used_EPus_an_by_sys = BTreeMap::<i32, f32>::new();
+    if !sys_priorities.is_empty() && quotas.is_empty() {
+        let used_by_sys_srv_t = apply_system_priorities(
+            &E_pr_cr_used_EPus_t,
+            &E_pr_cr_t,
+            &E_pr_cr_t_by_shared_id,
+            sys_priorities,
+            &E_EPus_cr_t_by_sys_srv,
+        );
+        let mut used_by_srv_t = BTreeMap::<Service, Vec<f32>>::new();
+        for ((id, service), values) in &used_by_sys_srv_t {
+            *E_pr_cr_used_EPus_an_by_sys.entry(*id).or_default() += vecsum(values);
+            used_by_srv_t
+                .entry(*service)
+                .and_modify(|e| vecvecsum_assign(e, values))
+                .or_insert_with(|| values.to_owned());
+        }
+        f_us_cr_by_srv_t = compute_f_us_cr_by_srv_t(&E_pr_cr_used_EPus_t, &used_by_srv_t);
+    }
+
+    // Production dedicated to services
+    if !dedicated.is_empty() && sys_priorities.is_empty() && quotas.is_empty() {
+        let used_by_srv_t = apply_dedicated_productions(
+            &E_pr_cr_used_EPus_t,
+            &E_pr_cr_t,
+            dedicated,
+            &E_EPus_cr_t_by_srv,
+        );
+        f_us_cr_by_srv_t = compute_f_us_cr_by_srv_t(&E_pr_cr_used_EPus_t, &used_by_srv_t);
+    }
+
+    // Production quotas by service
+    if !quotas.is_empty() {
+        let (E_pr_cr_used_EPus_by_srv_t, used_t) =
+            apply_production_quotas(&E_pr_cr_used_EPus_t, &E_EPus_cr_t_by_srv, quotas);
+        // Reduce produced energy used from each source (starting from the lowest priority sources)
+        let mut reduction_t = vecvecdif(&E_pr_cr_used_EPus_t, &used_t);
+        if use_priorities {
+            for source in priorities.iter().rev() {
+                if let Some(used_j_t) = E_pr_cr_j_used_EPus_t.get_mut(source) {
+                    let red_j_t = vecvecmin(used_j_t, &reduction_t);
+                    *used_j_t = vecvecdif(used_j_t, &red_j_t);
+                    reduction_t = vecvecdif(&reduction_t, &red_j_t);
+                }
+            }
+        } else {
+            for used_j_t in E_pr_cr_j_used_EPus_t.values_mut() {
+                for ((u_j, u_new), u_old) in
+                    used_j_t.iter_mut().zip(&used_t).zip(&E_pr_cr_used_EPus_t)
+                {
+                    *u_j = if *u_old > 0.0 {
+                        *u_j * u_new / u_old
+                    } else {
+                        0.0
+                    };
+                }
+            }
+        }
+        f_us_cr_by_srv_t = compute_f_us_cr_by_srv_t(&used_t, &E_pr_cr_used_EPus_by_srv_t);
+        E_pr_cr_used_EPus_t = used_t;
+    }
+
     let E_pr_cr_used_EPus_an = vecsum(&E_pr_cr_used_EPus_t);
 
-    let E_pr_cr_j_used_EPus_an: HashMap<ProdSource, f32> = E_pr_cr_j_used_EPus_t
+    let E_pr_cr_j_used_EPus_an: BTreeMap<ProdSource, f32> = E_pr_cr_j_used_EPus_t
         .iter()
         .map(|(source, values)| (*source, vecsum(values)))
         .collect();
 
     // Compute produced energy used for EPB services by source -----
-    // This computes the proportion for each service use for each timestep
-    let f_us_cr_by_srv_t = compute_f_us_cr_by_srv_t(&E_EPus_cr_t, &E_EPus_cr_t_by_srv);
     // Along with the produced energy from each source fore each timestep we can distribute produced energy by sources
-    let mut E_pr_cr_j_used_EPus_by_srv_by_src_t: HashMap<ProdSource, HashMap<Service, Vec<f32>>> =
-        HashMap::new();
-    let mut E_pr_cr_j_used_EPus_by_srv_by_src_an: HashMap<ProdSource, HashMap<Service, f32>> =
-        HashMap::new();
+    let mut E_pr_cr_j_used_EPus_by_srv_by_src_t: BTreeMap<ProdSource, BTreeMap<Service, Vec<f32>>> =
+        BTreeMap::new();
+    let mut E_pr_cr_j_used_EPus_by_srv_by_src_an: BTreeMap<ProdSource, BTreeMap<Service, f32>> =
+        BTreeMap::new();
     for (source, prod) in &E_pr_cr_j_used_EPus_t {
-        let mut source_prod_by_srv_t = HashMap::new();
-        let mut source_prod_by_srv_an = HashMap::new();
+        let mut source_prod_by_srv_t = BTreeMap::new();
+        let mut source_prod_by_srv_an = BTreeMap::new();
         for (service, factors) in &f_us_cr_by_srv_t {
             let values: Vec<_> = prod
                 .iter()
@@ -372,6 +1498,8 @@ fn compute_used_produced(
             epus_by_srv_an: E_EPus_cr_an_by_srv,
             nepus_t: E_nEPus_cr_t,
             nepus_an: E_nEPus_cr_an,
+            nepus_by_srv_t: E_nEPus_cr_t_by_srv,
+            nepus_by_srv_an: E_nEPus_cr_an_by_srv,
             cgnus_t: E_cgn_in_cr_t,
             cgnus_an: E_cgn_in_cr_an,
         },
@@ -380,48 +1508,88 @@ fn compute_used_produced(
             an: E_pr_cr_an,
             by_src_t: E_pr_cr_j_t,
             by_src_an: E_pr_cr_j_an,
+            sto_t: E_pr_cr_sto_t,
+            sto_an: E_pr_cr_sto_an,
             epus_t: E_pr_cr_used_EPus_t,
             epus_an: E_pr_cr_used_EPus_an,
             epus_by_src_t: E_pr_cr_j_used_EPus_t,
             epus_by_src_an: E_pr_cr_j_used_EPus_an,
             epus_by_srv_by_src_t: E_pr_cr_j_used_EPus_by_srv_by_src_t,
             epus_by_srv_by_src_an: E_pr_cr_j_used_EPus_by_srv_by_src_an,
+            epus_by_sys_an: E_pr_cr_used_EPus_an_by_sys,
         },
         f_match_t,
     )
 }
 
-/// Compute load matching factor (32) (11.6.2.4)
+/// Desplazamiento temporal de la producción in situ con sistemas de almacenamiento eléctrico
 ///
-/// When load_matching is true it computes the statistical load matching factor using the
-/// proposed expression for monthly time steps from table B.32, with k=1 and n=1.
+/// Cada batería, en el orden de declaración, se carga con el excedente de producción sobre los usos
+/// (`used_t`) y se descarga para cubrir el déficit en pasos posteriores (ver [`Storage::simulate`]).
 ///
-/// In other cases, it uses a constant factor = 1.0 for all time steps, as the proposed
-/// function for hourly timesteps in table B.32.
-#[allow(non_snake_case)]
-fn compute_f_match(E_pr_cr_t: &[f32], E_EPus_cr_t: &[f32], load_matching: bool) -> Vec<f32> {
-    let num_steps = E_pr_cr_t.len();
-    if load_matching {
-        // x = E_pr_cr_t / E_EPus_cr_t (at each time step)
-        // f_match_t = if x <= 0.0 { 1.0 } else { (x + 1.0/x - 1.0) / (x + 1.0 / x) };
-        E_pr_cr_t
-            .iter()
-            .zip(E_EPus_cr_t.iter())
-            .map(|(produced, used)| if *used > 0.0 { produced / used } else { 0.0 })
-            .map(|x| {
-                if x <= 0.0 {
-                    1.0
-                } else {
-                    (x + 1.0 / x - 1.0) / (x + 1.0 / x)
+/// La energía cargada se descuenta de la producción de cada fuente en proporción a su producción en
+/// el paso de cálculo. La energía descargada se suma a la producción de cada fuente en proporción a la
+/// energía cargada acumulada desde esa fuente y se contabiliza como energía liberada desde
+/// almacenamiento (`pr_sto_t`), junto a la producción marcada con la etiqueta `CTEEPBD_ALMACENAMIENTO`.
+/// La parte exportada de esa energía (p.e. con un factor de coincidencia de cargas menor que la
+/// unidad) es exportación diferida, que se pondera con los factores `A_RED_DIFERIDA`.
+/// Las pérdidas de carga, descarga y autodescarga reducen la producción.
+fn apply_storages(
+    storages: &[Storage],
+    used_t: &[f32],
+    pr_by_src_t: &mut BTreeMap<ProdSource, Vec<f32>>,
+    pr_sto_t: &mut [f32],
+) {
+    for storage in storages {
+        let pr_t = veclistsum(&pr_by_src_t.values().map(Vec::as_slice).collect::<Vec<_>>());
+        let (charge_t, discharge_t) = storage.simulate(&pr_t, used_t);
+        let mut charged_by_src = BTreeMap::<ProdSource, f32>::new();
+        for (step, (charge, discharge)) in charge_t.iter().zip(&discharge_t).enumerate() {
+            if *charge > 0.0 && pr_t[step] > 0.0 {
+                for (source, values) in pr_by_src_t.iter_mut() {
+                    let part = charge * values[step] / pr_t[step];
+                    values[step] -= part;
+                    *charged_by_src.entry(*source).or_default() += part;
                 }
-            })
-            .collect()
-    } else {
-        // Load matching factor with constant value == 1 (11.6.2.4)
-        vec![1.0; num_steps]
+            }
+            if *discharge > 0.0 {
+                let charged: f32 = charged_by_src.values().sum();
+                for (source, charged_src) in &charged_by_src {
+                    if let Some(values) = pr_by_src_t.get_mut(source) {
+                        values[step] += discharge * charged_src / charged;
+                    }
+                }
+            }
+        }
+        vecvecsum_assign(pr_sto_t, &discharge_t);
     }
 }
 
+/// Compute load matching factor (32) (11.6.2.4)
+///
+/// With the `Monthly` mode it computes the statistical load matching factor using the expression
+/// for monthly time steps from table B.32, with the given k and n parameters (k=1 and n=1 proposed).
+/// This expression is also used for daily time steps, where the mismatch between production and
+/// use inside each time step (e.g. day and night) is still relevant.
+///
+/// In other cases, and always for hourly time steps, it uses a constant factor = 1.0 for all time steps,
+/// as the proposed function for hourly timesteps in table B.32.
+#[allow(non_snake_case)]
+fn compute_f_match(
+    E_pr_cr_t: &[f32],
+    E_EPus_cr_t: &[f32],
+    load_matching: LoadMatchingMode,
+) -> Vec<f32> {
+    let resolution = Resolution::from_num_steps(E_pr_cr_t.len());
+    // x = E_pr_cr_t / E_EPus_cr_t (at each time step)
+    E_pr_cr_t
+        .iter()
+        .zip(E_EPus_cr_t.iter())
+        .map(|(produced, used)| if *used > 0.0 { produced / used } else { 0.0 })
+        .map(|x| load_matching.f_match(x, resolution))
+        .collect()
+}
+
 /// Compute exported and delivered energy from used and produced energy data
 #[allow(non_snake_case)]
 fn compute_exported_delivered(
@@ -431,8 +1599,27 @@ fn compute_exported_delivered(
     let E_exp_cr_t = vecvecdif(&prod.t, &prod.epus_t);
     let E_exp_cr_used_nEPus_t = vecvecmin(&E_exp_cr_t, &used.nepus_t);
     let E_exp_cr_used_nEPus_an = vecsum(&E_exp_cr_used_nEPus_t);
+    // Exported energy to non EPB services, distributed by the share of each service at each timestep
+    let E_exp_cr_used_nEPus_an_by_srv: BTreeMap<Service, f32> =
+        compute_f_us_cr_by_srv_t(&used.nepus_t, &used.nepus_by_srv_t)
+            .iter()
+            .map(|(service, f_srv_t)| {
+                (
+                    *service,
+                    vecsum(&vecvecmul(&E_exp_cr_used_nEPus_t, f_srv_t)),
+                )
+            })
+            .collect();
     let E_exp_cr_grid_t = vecvecdif(&E_exp_cr_t, &E_exp_cr_used_nEPus_t);
     let E_exp_cr_grid_an = vecsum(&E_exp_cr_grid_t);
+    // Exported energy to the grid released from storage (deferred export),
+    // proportional to the share of energy released from storage in the production at each timestep
+    let E_exp_cr_grid_sto_t: Vec<f32> = E_exp_cr_grid_t
+        .iter()
+        .zip(prod.sto_t.iter().zip(prod.t.iter()))
+        .map(|(exp_grid, (sto, pr))| if *pr > 0.0 { exp_grid * sto / pr } else { 0.0 })
+        .collect();
+    let E_exp_cr_grid_sto_an = vecsum(&E_exp_cr_grid_sto_t);
     let E_del_cr_t = vecvecdif(&used.epus_t, &prod.epus_t);
     let E_del_cr_an = vecsum(&E_del_cr_t);
 
@@ -441,18 +1628,18 @@ fn compute_exported_delivered(
     for (prod_src, prod_values_t) in &prod.by_src_t {
         match (*prod_src).into() {
             Source::INSITU => {
-                E_del_cr_onsite_t = vecvecsum(&E_del_cr_onsite_t, prod_values_t);
+                vecvecsum_assign(&mut E_del_cr_onsite_t, prod_values_t);
             }
             _ => continue,
         }
     }
     let E_del_cr_onsite_an = vecsum(&E_del_cr_onsite_t);
 
-    let mut E_exp_cr_j_t = HashMap::<ProdSource, Vec<f32>>::new();
+    let mut E_exp_cr_j_t = BTreeMap::<ProdSource, Vec<f32>>::new();
     for (source, prod_src) in &prod.by_src_t {
         E_exp_cr_j_t.insert(*source, vecvecdif(prod_src, &prod.epus_by_src_t[source]));
     }
-    let mut E_exp_cr_j_an = HashMap::<ProdSource, f32>::new();
+    let mut E_exp_cr_j_an = BTreeMap::<ProdSource, f32>::new();
     for (source, exp_src) in &E_exp_cr_j_t {
         E_exp_cr_j_an.insert(*source, vecsum(exp_src));
     }
@@ -466,8 +1653,11 @@ fn compute_exported_delivered(
             by_src_an: E_exp_cr_j_an,
             grid_t: E_exp_cr_grid_t,
             grid_an: E_exp_cr_grid_an,
+            grid_sto_t: E_exp_cr_grid_sto_t,
+            grid_sto_an: E_exp_cr_grid_sto_an,
             nepus_t: E_exp_cr_used_nEPus_t,
             nepus_an: E_exp_cr_used_nEPus_an,
+            nepus_by_srv_an: E_exp_cr_used_nEPus_an_by_srv,
         },
         DeliveredEnergy {
             an: E_del_cr_an + E_del_cr_onsite_an + used.cgnus_an,
@@ -482,32 +1672,37 @@ fn compute_exported_delivered(
 }
 
 /// Compute weighted energy from exported and delivered data
+///
+/// * `export_weighting` - criterion for the mean weighting factors of exported energy with several sources
 #[allow(non_snake_case)]
 fn compute_weighted_energy(
     carrier: Carrier,
     k_exp: f32,
     wfactors: &Factors,
+    export_weighting: ExportWeighting,
     used: &UsedEnergy,
     exp: &ExportedEnergy,
     del: &DeliveredEnergy,
 ) -> Result<WeightedEnergy> {
-    let fP_grid_A = wfactors.find(carrier, Source::RED, Dest::SUMINISTRO, Step::A)?;
+    let fP_grid_A = wfactors.find_factor(carrier, Source::RED, Dest::SUMINISTRO, Step::A)?;
 
     // Weighted energy due to delivered energy from the grid
-    let E_we_del_cr_grid_an = del.grid_an * fP_grid_A;
+    let E_we_del_cr_grid_an = fP_grid_A.weigh(del.grid_an, &del.grid_t);
 
     // Weighted energy due to delivered energy to produce cogenerated electricity
     let E_we_del_cr_cgn_an = if del.cgn_an == 0.0 {
         RenNrenCo2::default()
     } else {
-        del.cgn_an * fP_grid_A
+        fP_grid_A.weigh(del.cgn_an, &del.cgn_t)
     };
 
     // Weighted energy due to delivered energy from onsite sources
     let E_we_del_cr_onsite_an = if del.onst_an == 0.0 {
         RenNrenCo2::default()
     } else {
-        del.onst_an * wfactors.find(carrier, Source::INSITU, Dest::SUMINISTRO, Step::A)?
+        wfactors
+            .find_factor(carrier, Source::INSITU, Dest::SUMINISTRO, Step::A)?
+            .weigh(del.onst_an, &del.onst_t)
     };
 
     let E_we_del_cr_an = E_we_del_cr_grid_an + E_we_del_cr_onsite_an + E_we_del_cr_cgn_an;
@@ -528,34 +1723,108 @@ fn compute_weighted_energy(
 
         // Compute mean energy weighting factor for all (non grid) sources
         // uses exported energy from source j relative to all exported energy as weighting criteria
-        let f_we_exp_cr_compute = |dest: Dest, step: Step| -> Result<RenNrenCo2> {
-            let mut result = RenNrenCo2::default();
-            for (source, E_exp_cr_gen_an) in &exp.by_src_an {
-                result += wfactors.find(carrier, (*source).into(), dest, step)?
-                    * (E_exp_cr_gen_an / exp.an);
+        // When some factor has timestep values, mean factors are computed for each timestep
+        let f_we_exp_cr_compute = |dest: Dest, step: Step| -> Result<ExpFactor> {
+            let src_factors = exp
+                .by_src_an
+                .keys()
+                .map(|source| wfactors.find_factor(carrier, (*source).into(), dest, step))
+                .collect::<Result<Vec<_>>>()?;
+            if src_factors.iter().all(|f| f.values_t.is_empty()) {
+                let mut result = RenNrenCo2::default();
+                for (f, E_exp_cr_gen_an) in src_factors.iter().zip(exp.by_src_an.values()) {
+                    result += f.factors() * (E_exp_cr_gen_an / exp.an);
+                }
+                return Ok(ExpFactor::Annual(result));
+            }
+            let mut result = vec![RenNrenCo2::default(); exp.t.len()];
+            for (f, source) in src_factors.iter().zip(exp.by_src_an.keys()) {
+                let E_exp_cr_gen_t = match exp.by_src_t.get(source) {
+                    Some(values) => values,
+                    None => continue,
+                };
+                for (i, (r, (E_exp_cr_gen, E_exp_cr))) in result
+                    .iter_mut()
+                    .zip(E_exp_cr_gen_t.iter().zip(&exp.t))
+                    .enumerate()
+                {
+                    if *E_exp_cr != 0.0 {
+                        *r += f.factors_at(i) * (E_exp_cr_gen / E_exp_cr);
+                    }
+                }
+            }
+            Ok(ExpFactor::Steps(result))
+        };
+
+        // With the priority criterion, exported energy is allocated at each timestep first to non EPB
+        // services and then to the grid, taking it from the sources in order of priority (9.6.6.2.4),
+        // and mean factors for each destination are computed from its own mix of sources
+        let exp_by_dest = match export_weighting {
+            ExportWeighting::PRIORIDAD => exported_by_priority(carrier, exp),
+            ExportWeighting::PROPORCIONAL => None,
+        };
+        let f_we_exp_cr_dest = |dest: Dest, step: Step| -> Result<ExpFactor> {
+            let Some((nepus_by_src_t, grid_by_src_t)) = &exp_by_dest else {
+                return f_we_exp_cr_compute(dest, step);
+            };
+            let (by_src_t, total_t) = if dest == Dest::A_NEPB {
+                (nepus_by_src_t, &exp.nepus_t)
+            } else {
+                (grid_by_src_t, &exp.grid_t)
+            };
+            let mut result = vec![RenNrenCo2::default(); total_t.len()];
+            for (source, E_exp_cr_gen_t) in by_src_t {
+                let f = wfactors.find_factor(carrier, (*source).into(), dest, step)?;
+                for (i, (r, (E_exp_cr_gen, E_exp_cr))) in result
+                    .iter_mut()
+                    .zip(E_exp_cr_gen_t.iter().zip(total_t))
+                    .enumerate()
+                {
+                    if *E_exp_cr != 0.0 {
+                        *r += f.factors_at(i) * (E_exp_cr_gen / E_exp_cr);
+                    }
+                }
             }
-            Ok(result)
+            Ok(ExpFactor::Steps(result))
         };
+        let no_factor = ExpFactor::Annual(RenNrenCo2::default()); // ren: 0.0, nren: 0.0, co2: 0.0
 
         // Weighting factors for energy exported to nEP uses (step A) (~formula 24)
-        let f_we_exp_cr_stepA_nEPus: RenNrenCo2 = if exp.nepus_an == 0.0 {
+        let f_we_exp_cr_stepA_nEPus = if exp.nepus_an == 0.0 {
             // No exported energy to nEP uses
-            RenNrenCo2::default() // ren: 0.0, nren: 0.0, co2: 0.0
+            no_factor.clone()
         } else {
-            f_we_exp_cr_compute(Dest::A_NEPB, Step::A)?
+            f_we_exp_cr_dest(Dest::A_NEPB, Step::A)?
         };
 
         // Weighting factors for energy exported to the grid (step A) (~formula 25)
-        let f_we_exp_cr_stepA_grid: RenNrenCo2 = if exp.grid_an == 0.0 {
+        let f_we_exp_cr_stepA_grid = if exp.grid_an == 0.0 {
             // No energy exported to grid
-            RenNrenCo2::default() // ren: 0.0, nren: 0.0, co2: 0.0
+            no_factor.clone()
         } else {
-            f_we_exp_cr_compute(Dest::A_RED, Step::A)?
+            f_we_exp_cr_dest(Dest::A_RED, Step::A)?
+        };
+
+        // Energy exported to the grid released from storage (deferred export) uses its own factors,
+        // when defined, and the factors for direct export otherwise
+        let has_grid_sto_factors = exp.grid_sto_an != 0.0
+            && wfactors
+                .wdata
+                .iter()
+                .any(|f| f.carrier == carrier && f.dest == Dest::A_RED_DIFERIDA);
+        let E_exp_cr_grid_direct_an = exp.grid_an - exp.grid_sto_an;
+        let E_exp_cr_grid_direct_t = vecvecdif(&exp.grid_t, &exp.grid_sto_t);
+        let f_we_exp_cr_stepA_grid_sto = if has_grid_sto_factors {
+            f_we_exp_cr_dest(Dest::A_RED_DIFERIDA, Step::A)?
+        } else {
+            f_we_exp_cr_stepA_grid.clone()
         };
 
         // Weighted exported energy according to resources used to generate that energy (formula 23)
-        E_we_exp_cr_nEPus_an_A = exp.nepus_an * f_we_exp_cr_stepA_nEPus; // formula 24
-        E_we_exp_cr_grid_an_A = exp.grid_an * f_we_exp_cr_stepA_grid; // formula 25
+        E_we_exp_cr_nEPus_an_A = f_we_exp_cr_stepA_nEPus.weigh(exp.nepus_an, &exp.nepus_t); // formula 24
+        E_we_exp_cr_grid_an_A = f_we_exp_cr_stepA_grid
+            .weigh(E_exp_cr_grid_direct_an, &E_exp_cr_grid_direct_t)
+            + f_we_exp_cr_stepA_grid_sto.weigh(exp.grid_sto_an, &exp.grid_sto_t); // formula 25
         E_we_exp_cr_an_A = E_we_exp_cr_nEPus_an_A + E_we_exp_cr_grid_an_A;
 
         // * Step B: weighting depends on exported energy generation and avoided resources on the grid
@@ -564,26 +1833,35 @@ fn compute_weighted_energy(
         // (resources avoided to the grid gen)
         let f_we_exp_cr_used_nEPus = if exp.nepus_an == 0.0 {
             // No energy exported to nEP uses
-            RenNrenCo2::default() // ren: 0.0, nren: 0.0, co2: 0.0
+            no_factor.clone()
         } else {
-            f_we_exp_cr_compute(Dest::A_NEPB, Step::B)?
+            f_we_exp_cr_dest(Dest::A_NEPB, Step::B)?
         };
 
         // Weighting factors for energy exported to the grid (step B)
         // (resources avoided to the grid gen)
         let f_we_exp_cr_grid = if exp.grid_an == 0.0 {
             // No energy exported to grid
-            RenNrenCo2::default() // ren: 0.0, nren: 0.0, co2: 0.0
+            no_factor
         } else {
-            f_we_exp_cr_compute(Dest::A_RED, Step::B)?
+            f_we_exp_cr_dest(Dest::A_RED, Step::B)?
+        };
+        let f_we_exp_cr_grid_sto = if has_grid_sto_factors {
+            f_we_exp_cr_dest(Dest::A_RED_DIFERIDA, Step::B)?
+        } else {
+            f_we_exp_cr_grid.clone()
         };
 
         // Effect of exported energy on weighted energy performance (step B) (formula 26)
+        // Computed as the difference of the weighted energy with step B and step A factors
 
         E_we_exp_cr_used_nEPus_an_AB =
-            exp.nepus_an * (f_we_exp_cr_used_nEPus - f_we_exp_cr_stepA_nEPus); // formula 27
+            f_we_exp_cr_used_nEPus.weigh(exp.nepus_an, &exp.nepus_t) - E_we_exp_cr_nEPus_an_A; // formula 27
 
-        E_we_exp_cr_grid_an_AB = exp.grid_an * (f_we_exp_cr_grid - f_we_exp_cr_stepA_grid); // formula 28
+        E_we_exp_cr_grid_an_AB = f_we_exp_cr_grid
+            .weigh(E_exp_cr_grid_direct_an, &E_exp_cr_grid_direct_t)
+            + f_we_exp_cr_grid_sto.weigh(exp.grid_sto_an, &exp.grid_sto_t)
+            - E_we_exp_cr_grid_an_A; // formula 28
 
         E_we_exp_cr_an_AB = E_we_exp_cr_used_nEPus_an_AB + E_we_exp_cr_grid_an_AB; // formula 26
 
@@ -598,8 +1876,8 @@ fn compute_weighted_energy(
     // f_us_cr = (used energy for service_i) / (used energy for all services)
     // This uses the reverse calculation method (E.3.6)
     let f_us_cr = compute_f_us_cr_an(used);
-    let mut E_we_cr_an_A_by_srv: HashMap<Service, RenNrenCo2> = HashMap::new();
-    let mut E_we_cr_an_by_srv: HashMap<Service, RenNrenCo2> = HashMap::new();
+    let mut E_we_cr_an_A_by_srv: BTreeMap<Service, RenNrenCo2> = BTreeMap::new();
+    let mut E_we_cr_an_by_srv: BTreeMap<Service, RenNrenCo2> = BTreeMap::new();
     for (service, f_us_k_cr) in f_us_cr {
         E_we_cr_an_A_by_srv.insert(service, E_we_cr_an_A * f_us_k_cr);
         E_we_cr_an_by_srv.insert(service, E_we_cr_an * f_us_k_cr);
@@ -626,6 +1904,69 @@ fn compute_weighted_energy(
     })
 }
 
+/// Valores por paso de cálculo de cada fuente de producción
+type ValuesBySource = BTreeMap<ProdSource, Vec<f32>>;
+
+/// Energía exportada de cada fuente a usos no EPB y a la red en cada paso de cálculo
+///
+/// La energía exportada se asigna primero a los usos no EPB y luego a la red, tomándola de las fuentes
+/// por orden de prioridad (9.6.6.2.4), como se hace con la energía producida usada en servicios EPB.
+///
+/// Devuelve None si el vector no tiene prioridades entre fuentes o si solo hay una fuente.
+fn exported_by_priority(
+    carrier: Carrier,
+    exp: &ExportedEnergy,
+) -> Option<(ValuesBySource, ValuesBySource)> {
+    let (has_priorities, priorities) = ProdSource::get_priorities(carrier);
+    if !has_priorities || exp.by_src_t.len() < 2 {
+        return None;
+    }
+    // Sources with priorities first, then any other source
+    let mut sources: Vec<ProdSource> = priorities
+        .into_iter()
+        .filter(|s| exp.by_src_t.contains_key(s))
+        .collect();
+    let others: Vec<ProdSource> = exp
+        .by_src_t
+        .keys()
+        .filter(|s| !sources.contains(s))
+        .copied()
+        .collect();
+    sources.extend(others);
+
+    let mut nepus_left_t = exp.nepus_t.clone();
+    let mut nepus_by_src_t = BTreeMap::new();
+    let mut grid_by_src_t = BTreeMap::new();
+    for source in sources {
+        let exp_src_t = &exp.by_src_t[&source];
+        let nepus_src_t = vecvecmin(exp_src_t, &nepus_left_t);
+        nepus_left_t = vecvecdif(&nepus_left_t, &nepus_src_t);
+        grid_by_src_t.insert(source, vecvecdif(exp_src_t, &nepus_src_t));
+        nepus_by_src_t.insert(source, nepus_src_t);
+    }
+    Some((nepus_by_src_t, grid_by_src_t))
+}
+
+/// Factores de paso medios de la energía exportada, anuales o por paso de cálculo
+#[derive(Debug, Clone)]
+enum ExpFactor {
+    Annual(RenNrenCo2),
+    Steps(Vec<RenNrenCo2>),
+}
+
+impl ExpFactor {
+    /// Energía ponderada de una energía con valor anual `value_an` y valores por paso de cálculo `values_t`
+    fn weigh(&self, value_an: f32, values_t: &[f32]) -> RenNrenCo2 {
+        match self {
+            ExpFactor::Annual(f) => value_an * *f,
+            ExpFactor::Steps(f_t) => values_t
+                .iter()
+                .zip(f_t)
+                .fold(RenNrenCo2::default(), |acc, (&v, &f)| acc + v * f),
+        }
+    }
+}
+
 /// Calcula fracción de cada uso EPB para un vector energético i
 ///
 /// Compute share of each EPB use for a given carrier i
@@ -634,8 +1975,8 @@ fn compute_weighted_energy(
 /// It uses the reverse calculation method (E.3.6)
 /// * `cr_list` - components list for the selected carrier i
 ///
-fn compute_f_us_cr_an(used: &UsedEnergy) -> HashMap<Service, f32> {
-    let mut factors_us_k: HashMap<Service, f32> = HashMap::new();
+fn compute_f_us_cr_an(used: &UsedEnergy) -> BTreeMap<Service, f32> {
+    let mut factors_us_k: BTreeMap<Service, f32> = BTreeMap::new();
 
     for (service, used_srv) in &used.epus_by_srv_an {
         let f = if used.epus_an > 0.0 {
@@ -648,6 +1989,182 @@ fn compute_f_us_cr_an(used: &UsedEnergy) -> HashMap<Service, f32> {
     factors_us_k
 }
 
+/// Reparto por servicios de la energía producida usada, con cupos máximos por servicio
+///
+/// En cada paso de cálculo, la energía producida usada (`used_pr_t`) se reparte entre los servicios
+/// en proporción a su consumo, sin superar para cada servicio la fracción de su consumo indicada
+/// en `quotas` (la unidad si no se indica). El exceso se reasigna al resto de servicios, hasta cubrir
+/// su cupo o su consumo, y la parte que no se puede asignar deja de considerarse usada.
+///
+/// Devuelve la energía producida imputada a cada servicio y la energía producida usada total en cada paso.
+fn apply_production_quotas(
+    used_pr_t: &[f32],
+    epus_by_srv_t: &BTreeMap<Service, Vec<f32>>,
+    quotas: &BTreeMap<Service, f32>,
+) -> (BTreeMap<Service, Vec<f32>>, Vec<f32>) {
+    let num_steps = used_pr_t.len();
+    let mut used_by_srv_t: BTreeMap<Service, Vec<f32>> = epus_by_srv_t
+        .keys()
+        .map(|service| (*service, vec![0.0; num_steps]))
+        .collect();
+    for (t, used_pr) in used_pr_t.iter().enumerate() {
+        let mut left = *used_pr;
+        // Servicios aún sin cupo agotado (servicio, consumo, cupo)
+        let mut active: Vec<(Service, f32, f32)> = epus_by_srv_t
+            .iter()
+            .filter(|(_, values)| values[t] > 0.0)
+            .map(|(service, values)| {
+                let quota = quotas.get(service).copied().unwrap_or(1.0);
+                (*service, values[t], quota * values[t])
+            })
+            .collect();
+        while left > 0.0 && !active.is_empty() {
+            let used_active: f32 = active.iter().map(|(_, used, _)| used).sum();
+            let (capped, free): (Vec<_>, Vec<_>) = active
+                .into_iter()
+                .partition(|(_, used, cap)| left * used / used_active > *cap);
+            if capped.is_empty() {
+                for (service, used, _) in &free {
+                    used_by_srv_t.get_mut(service).unwrap()[t] = left * used / used_active;
+                }
+                break;
+            }
+            for (service, _, cap) in &capped {
+                used_by_srv_t.get_mut(service).unwrap()[t] = *cap;
+                left -= cap;
+            }
+            active = free;
+        }
+    }
+    let used_t = used_by_srv_t
+        .values()
+        .fold(vec![0.0; num_steps], |mut acc, values| {
+            vecvecsum_assign(&mut acc, values);
+            acc
+        });
+    (used_by_srv_t, used_t)
+}
+
+/// Reparto entre sistemas consumidores de la energía producida usada, con listas de prioridad
+///
+/// En cada paso de cálculo, la parte de la energía producida usada (`used_pr_t`) que corresponde a cada
+/// producción compartida (en proporción a su producción respecto a la total, `prod_t`) se asigna a los
+/// sistemas de su lista de prioridad por orden, hasta cubrir su consumo. El resto de la energía producida
+/// usada se reparte en proporción al consumo no cubierto de todos los sistemas.
+/// En cada sistema, la energía asignada se reparte entre sus servicios en proporción a su consumo.
+///
+/// Devuelve la energía producida usada por cada sistema y servicio en cada paso.
+fn apply_system_priorities(
+    used_pr_t: &[f32],
+    prod_t: &[f32],
+    shared_prod_by_id_t: &BTreeMap<i32, Vec<f32>>,
+    sys_priorities: &BTreeMap<i32, Vec<i32>>,
+    epus_by_sys_srv_t: &BTreeMap<(i32, Service), Vec<f32>>,
+) -> BTreeMap<(i32, Service), Vec<f32>> {
+    let num_steps = used_pr_t.len();
+    let mut used_by_sys_srv_t: BTreeMap<(i32, Service), Vec<f32>> = epus_by_sys_srv_t
+        .keys()
+        .map(|key| (*key, vec![0.0; num_steps]))
+        .collect();
+    // Assign energy to the given uses, proportionally to the use left, and return the assigned energy
+    let assign = |step: usize,
+                  energy: f32,
+                  keys: &[(i32, Service)],
+                  left: &mut BTreeMap<(i32, Service), f32>,
+                  used: &mut BTreeMap<(i32, Service), Vec<f32>>|
+     -> f32 {
+        let left_tot: f32 = keys.iter().map(|k| left[k]).sum();
+        if left_tot <= 0.0 || energy <= 0.0 {
+            return 0.0;
+        }
+        let assigned = energy.min(left_tot);
+        for key in keys {
+            let value = assigned * left[key] / left_tot;
+            *left.get_mut(key).unwrap() -= value;
+            used.get_mut(key).unwrap()[step] += value;
+        }
+        assigned
+    };
+    for step in 0..num_steps {
+        let mut left: BTreeMap<(i32, Service), f32> = epus_by_sys_srv_t
+            .iter()
+            .map(|(key, values)| (*key, values[step]))
+            .collect();
+        let mut used_left = used_pr_t[step];
+        // Shared productions, following the priority lists
+        for (prod_id, ids) in sys_priorities {
+            let share = match shared_prod_by_id_t.get(prod_id) {
+                Some(prod_id_t) if prod_t[step] > 0.0 => {
+                    used_pr_t[step] * (prod_id_t[step] / prod_t[step]).min(1.0)
+                }
+                _ => continue,
+            };
+            let mut share_left = share.min(used_left);
+            for id in ids {
+                let keys: Vec<_> = left.keys().filter(|(i, _)| i == id).copied().collect();
+                let assigned = assign(step, share_left, &keys, &mut left, &mut used_by_sys_srv_t);
+                share_left -= assigned;
+                used_left -= assigned;
+            }
+        }
+        // Rest of the produced energy used, proportionally to the use left of all systems
+        let keys: Vec<_> = left.keys().copied().collect();
+        assign(step, used_left, &keys, &mut left, &mut used_by_sys_srv_t);
+    }
+    used_by_sys_srv_t
+}
+
+/// Reparto por servicios de la energía producida usada, con producciones imputadas a servicios
+///
+/// En cada paso de cálculo, la parte de la energía producida usada (`used_pr_t`) que corresponde a la
+/// producción imputada a cada servicio (en proporción a su producción respecto a la total, `prod_t`) se
+/// asigna a ese servicio, hasta cubrir su consumo. El resto de la energía producida usada se reparte
+/// en proporción al consumo no cubierto de todos los servicios.
+///
+/// Devuelve la energía producida usada por cada servicio en cada paso.
+fn apply_dedicated_productions(
+    used_pr_t: &[f32],
+    prod_t: &[f32],
+    dedicated: &BTreeMap<Service, Vec<f32>>,
+    epus_by_srv_t: &BTreeMap<Service, Vec<f32>>,
+) -> BTreeMap<Service, Vec<f32>> {
+    let num_steps = used_pr_t.len();
+    let mut used_by_srv_t: BTreeMap<Service, Vec<f32>> = epus_by_srv_t
+        .keys()
+        .map(|service| (*service, vec![0.0; num_steps]))
+        .collect();
+    for step in 0..num_steps {
+        let mut left: BTreeMap<Service, f32> = epus_by_srv_t
+            .iter()
+            .map(|(service, values)| (*service, values[step]))
+            .collect();
+        let mut used_left = used_pr_t[step];
+        // Productions dedicated to a service
+        for (service, ded_t) in dedicated {
+            let Some(left_srv) = left.get_mut(service) else {
+                continue;
+            };
+            if prod_t[step] <= 0.0 {
+                continue;
+            }
+            let share = used_pr_t[step] * (ded_t[step] / prod_t[step]).min(1.0);
+            let assigned = share.min(used_left).min(*left_srv).max(0.0);
+            *left_srv -= assigned;
+            used_left -= assigned;
+            used_by_srv_t.get_mut(service).unwrap()[step] += assigned;
+        }
+        // Rest of the produced energy used, proportionally to the use left of all services
+        let left_tot: f32 = left.values().sum();
+        if left_tot > 0.0 && used_left > 0.0 {
+            let assigned = used_left.min(left_tot);
+            for (service, left_srv) in &left {
+                used_by_srv_t.get_mut(service).unwrap()[step] += assigned * left_srv / left_tot;
+            }
+        }
+    }
+    used_by_srv_t
+}
+
 /// Calcula fracción de cada uso EPB para un vector energético i para cada paso de cálculo
 ///
 /// Compute share of each EPB use for a given carrier i
@@ -658,9 +2175,9 @@ fn compute_f_us_cr_an(used: &UsedEnergy) -> HashMap<Service, f32> {
 ///
 fn compute_f_us_cr_by_srv_t(
     epus_t: &[f32],
-    epus_by_srv_t: &HashMap<Service, Vec<f32>>,
-) -> HashMap<Service, Vec<f32>> {
-    let mut factors_us_k: HashMap<Service, Vec<f32>> = HashMap::new();
+    epus_by_srv_t: &BTreeMap<Service, Vec<f32>>,
+) -> BTreeMap<Service, Vec<f32>> {
+    let mut factors_us_k: BTreeMap<Service, Vec<f32>> = BTreeMap::new();
 
     for (service, used_srv) in epus_by_srv_t {
         let f = used_srv
@@ -0,0 +1,268 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Cálculo en lote
+===============
+
+Cálculo de la eficiencia energética de varios casos (edificios o variantes), con ejecución
+opcional en paralelo (feature `parallel`) y resumen de los errores de cada caso, sin interrumpir el cálculo
+en el primer fallo.
+
+Los resultados se devuelven siempre en el orden de los casos de entrada, también en la
+ejecución en paralelo, de modo que el cálculo sigue siendo determinista.
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    energy_performance,
+    error::Result,
+    types::{EnergyPerformance, LoadMatchingMode},
+    Components, Factors,
+};
+
+/// Datos de entrada de un caso de cálculo
+#[derive(Debug, Clone)]
+pub struct CaseInput {
+    /// Identificador del caso (p.e. nombre del archivo de componentes)
+    pub id: String,
+    /// Componentes energéticos
+    pub components: Components,
+    /// Factores de paso
+    pub wfactors: Factors,
+    /// Factor de exportación [0, 1]
+    pub k_exp: f32,
+    /// Área de referencia [m2]
+    pub arearef: f32,
+    /// Modo de cálculo del factor de coincidencia de cargas
+    pub load_matching: LoadMatchingMode,
+}
+
+impl CaseInput {
+    /// Calcula la eficiencia energética del caso
+    pub fn compute(&self) -> Result<EnergyPerformance> {
+        energy_performance(
+            &self.components,
+            &self.wfactors,
+            self.k_exp,
+            self.arearef,
+            self.load_matching,
+        )
+    }
+}
+
+/// Calcula la eficiencia energética de una lista de casos, secuencialmente
+///
+/// Devuelve el resultado de cada caso, en el mismo orden que los datos de entrada.
+/// Los errores de un caso no interrumpen el cálculo del resto.
+pub fn compute_many(inputs: Vec<CaseInput>) -> Vec<Result<EnergyPerformance>> {
+    inputs.iter().map(CaseInput::compute).collect()
+}
+
+/// Calcula la eficiencia energética de una lista de casos, en paralelo (feature `parallel`)
+///
+/// Los casos se reparten entre los hilos de rayon (el número de hilos puede configurarse con
+/// `rayon::ThreadPoolBuilder`). Devuelve el resultado de cada caso, en el mismo orden que los
+/// datos de entrada, y con los mismos valores que el cálculo secuencial ([`compute_many`]).
+#[cfg(feature = "parallel")]
+pub fn compute_many_parallel(inputs: Vec<CaseInput>) -> Vec<Result<EnergyPerformance>> {
+    use rayon::prelude::*;
+
+    inputs.par_iter().map(CaseInput::compute).collect()
+}
+
+/// Calcula una lista de casos con la función de cálculo indicada
+///
+/// La función de cálculo recibe la posición del caso en la lista de entrada y sus datos,
+/// lo que permite observar el cálculo de cada caso (p.e. para registrar métricas).
+/// Con la feature `parallel` los casos se calculan en paralelo (ver [`compute_many_parallel`])
+/// y, en otro caso, secuencialmente. Los resultados mantienen el orden de los datos de entrada.
+#[cfg(feature = "metrics")]
+pub(crate) fn compute_many_with<F>(
+    inputs: &[CaseInput],
+    compute: F,
+) -> Vec<Result<EnergyPerformance>>
+where
+    F: Fn(usize, &CaseInput) -> Result<EnergyPerformance> + Sync,
+{
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+
+        inputs
+            .par_iter()
+            .enumerate()
+            .map(|(idx, input)| compute(idx, input))
+            .collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        inputs
+            .iter()
+            .enumerate()
+            .map(|(idx, input)| compute(idx, input))
+            .collect()
+    }
+}
+
+/// Error en el cálculo de un caso
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaseError {
+    /// Posición del caso en la lista de entrada
+    pub index: usize,
+    /// Identificador del caso
+    pub id: String,
+    /// Mensaje de error
+    pub message: String,
+}
+
+/// Resumen del cálculo en lote
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatchSummary {
+    /// Número de casos calculados
+    pub num_cases: usize,
+    /// Número de casos calculados sin error
+    pub num_ok: usize,
+    /// Errores de cada caso fallido
+    pub errors: Vec<CaseError>,
+}
+
+impl BatchSummary {
+    /// Resumen de los resultados de un cálculo en lote
+    ///
+    /// * `ids` - identificadores de los casos, en el mismo orden que los resultados
+    /// * `results` - resultados del cálculo de cada caso
+    pub fn new<S: AsRef<str>>(ids: &[S], results: &[Result<EnergyPerformance>]) -> Self {
+        let errors: Vec<CaseError> = results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| {
+                result.as_ref().err().map(|e| CaseError {
+                    index,
+                    id: ids
+                        .get(index)
+                        .map_or_else(|| index.to_string(), |id| id.as_ref().to_string()),
+                    message: e.to_string(),
+                })
+            })
+            .collect();
+        BatchSummary {
+            num_cases: results.len(),
+            num_ok: results.len() - errors.len(),
+            errors,
+        }
+    }
+
+    /// Comprueba si todos los casos se han calculado sin error
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl std::fmt::Display for BatchSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Casos calculados: {}, correctos: {}, con errores: {}",
+            self.num_cases,
+            self.num_ok,
+            self.errors.len()
+        )?;
+        for e in &self.errors {
+            write!(f, "\n- {} (caso {}): {}", e.id, e.index + 1, e.message)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "cte"))]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{cte, UserWF};
+
+    /// Caso de cálculo con los factores de paso reglamentarios de la península (también en `metrics`)
+    pub(crate) fn case(id: &str, comps: &str, arearef: f32) -> CaseInput {
+        CaseInput {
+            id: id.to_string(),
+            components: comps.parse().unwrap(),
+            wfactors: cte::wfactors_from_loc(
+                "PENINSULA",
+                &cte::CTE_LOCWF_RITE2014,
+                UserWF {
+                    red1: None,
+                    red2: None,
+                    hidrogeno: None,
+                },
+                cte::CTE_USERWF,
+            )
+            .unwrap(),
+            k_exp: cte::KEXP_DEFAULT,
+            arearef,
+            load_matching: LoadMatchingMode::Constant,
+        }
+    }
+
+    #[test]
+    fn batch_compute_many() {
+        let inputs: Vec<CaseInput> = (1..=8)
+            .map(|i| {
+                let comps = format!(
+                    "1, CONSUMO, CAL, ELECTRICIDAD, {}\n1, CONSUMO, ACS, GASNATURAL, {}",
+                    10 * i,
+                    5 * i
+                );
+                // El caso 5 tiene un área de referencia nula
+                let arearef = if i == 5 { 0.0 } else { 100.0 };
+                case(&format!("caso{}", i), &comps, arearef)
+            })
+            .collect();
+        let ids: Vec<String> = inputs.iter().map(|c| c.id.clone()).collect();
+
+        #[cfg(feature = "parallel")]
+        let results_par = compute_many_parallel(inputs.clone());
+        let results = compute_many(inputs);
+        assert_eq!(results.len(), 8);
+        #[cfg(feature = "parallel")]
+        {
+            assert_eq!(results_par.len(), 8);
+            for (seq, par) in results.iter().zip(&results_par) {
+                match (seq, par) {
+                    (Ok(seq), Ok(par)) => assert_eq!(seq.balance_m2.we.b, par.balance_m2.we.b),
+                    (Err(_), Err(_)) => (),
+                    _ => panic!("Resultados distintos en el cálculo secuencial y en paralelo"),
+                }
+            }
+        }
+
+        let summary = BatchSummary::new(&ids, &results);
+        assert_eq!(summary.num_cases, 8);
+        assert_eq!(summary.num_ok, 7);
+        assert!(!summary.is_ok());
+        assert_eq!(summary.errors[0].index, 4);
+        assert_eq!(summary.errors[0].id, "caso5");
+        assert!(summary.to_string().contains("- caso5 (caso 5): "));
+    }
+}
@@ -46,9 +46,15 @@ use std::process::exit;
 use std::str::FromStr;
 
 use cteepbd::{
-    cte, energy_performance,
-    types::{EnergyPerformance, MetaVec, RenNrenCo2},
-    AsCtePlain, AsCteXml, Components, UserWF,
+    bills_reconciliation, co2_inventory, cte, energy_performance_from, exergy_balance,
+    marginal_carbon, registro,
+    types::{
+        BalanceUnits, Bills, CalcOptions, DuplicatePolicy, EnergyPerformance, ExergyFactors,
+        InventoryCo2Factors, LoadMatchingMode, MarginalCo2Factors, MetaVec, NegativeUsePolicy,
+        RenNrenCo2, Severity, SummaryReport, Tolerances,
+    },
+    decimal_comma_to_point, uses_decimal_comma, AsCteHtml, AsCtePlain, AsCteXml, AsSankeySvg,
+    AsTidy, Components, DataContainer, UserWF,
 };
 
 const APP_TITLE: &str = r#"CteEPBD"#;
@@ -63,6 +69,16 @@ Autores: Rafael Villar Burke <pachi@ietcc.csic.es>,
 Licencia: Publicado bajo licencia MIT.
 
 "#;
+// Alternativas al archivo de factores de paso y a la localización para definir los factores de paso
+#[cfg(not(feature = "net"))]
+const FP_FILE_ALT: &[&str] = &["fps_loc", "archivo_componentes", "archivo_contenedor"];
+#[cfg(feature = "net")]
+const FP_FILE_ALT: &[&str] = &["fps_loc", "archivo_componentes", "archivo_contenedor", "url_factores"];
+#[cfg(not(feature = "net"))]
+const FP_LOC_ALT: &[&str] = &["archivo_factores", "archivo_componentes", "archivo_contenedor"];
+#[cfg(feature = "net")]
+const FP_LOC_ALT: &[&str] = &["archivo_factores", "archivo_componentes", "archivo_contenedor", "url_factores"];
+
 const APP_ABOUT: &str = r#"CteEpbd - Eficiencia energética de los edificios (CTE DB-HE)."#;
 const APP_LICENSE: &str = r#"
 Copyright (c) 2018-2023 Ministerio de Fomento
@@ -153,6 +169,39 @@ fn validate_kexp(kexpstr: &str, orig: &str) -> Option<f32> {
     Some(kexp)
 }
 
+/// Comprueba validez del valor del factor de compensación simplificada de excedentes
+fn validate_kcomp(kcompstr: &str) -> f32 {
+    let kcomp = kcompstr.parse::<f32>().unwrap_or_else(|_| {
+        eprintln!(
+            "ERROR: factor de compensación simplificada k_comp incorrecto \"{}\"",
+            kcompstr
+        );
+        exit(exitcode::DATAERR);
+    });
+    if !(0.0..=1.0).contains(&kcomp) {
+        eprintln!(
+            "ERROR: factor de compensación simplificada k_comp fuera de rango [0.00 - 1.00]: {:.2}",
+            kcomp
+        );
+        exit(exitcode::DATAERR);
+    };
+    kcomp
+}
+
+/// Comprueba validez de un rendimiento máximo plausible
+fn validate_efficiency_max(effstr: &str, name: &str) -> f32 {
+    match effstr.parse::<f32>() {
+        Ok(eff) if eff > 0.0 && eff.is_finite() => eff,
+        _ => {
+            eprintln!(
+                "ERROR: rendimiento máximo plausible ({}) incorrecto \"{}\"",
+                name, effstr
+            );
+            exit(exitcode::DATAERR);
+        }
+    }
+}
+
 /// Comprueba validez del dato de area
 fn validate_arearef(arearefstr: &str, orig: &str) -> Option<f32> {
     let arearef = arearefstr.parse::<f32>().unwrap_or_else(|_| {
@@ -162,10 +211,11 @@ fn validate_arearef(arearefstr: &str, orig: &str) -> Option<f32> {
         );
         exit(exitcode::DATAERR);
     });
-    if arearef <= 1e-3 {
+    let arearef_min = Tolerances::default().arearef_min;
+    if arearef <= arearef_min {
         eprintln!(
-            "ERROR: área de referencia A_ref fuera de rango [0.001-]: {:.2} ({})",
-            arearef, orig
+            "ERROR: área de referencia A_ref fuera de rango [{}-]: {:.2} ({})",
+            arearef_min, arearef, orig
         );
         exit(exitcode::DATAERR);
     }
@@ -206,11 +256,34 @@ fn get_factor(
     factor
 }
 
+/// Lee archivo de datos (componentes o factores de paso) convirtiendo, si es necesario, la coma decimal
+///
+/// La conversión se aplica si se indica `decimal_comma` o si se detecta que el archivo usa
+/// el punto y coma como separador de campos, y se avisa de las líneas convertidas.
+fn read_data_file(path: &str, decimal_comma: bool) -> String {
+    let data = readfile(path);
+    if !(decimal_comma || uses_decimal_comma(&data)) {
+        return data;
+    }
+    let (converted, conversions) = decimal_comma_to_point(&data);
+    if !conversions.is_empty() {
+        println!(
+            "AVISO: convertidos los números con coma decimal de {} líneas del archivo \"{}\"",
+            conversions.len(),
+            path
+        );
+        for conversion in &conversions {
+            println!("AVISO: {}", conversion);
+        }
+    }
+    converted
+}
+
 /// Carga componentes desde archivo o devuelve componentes por defecto
-fn get_components(archivo: Option<&str>) -> Components {
+fn get_components(archivo: Option<&str>, decimal_comma: bool) -> Components {
     if let Some(archivo_componentes) = archivo {
         println!("Componentes energéticos: \"{}\"", archivo_componentes);
-        readfile(archivo_componentes)
+        read_data_file(archivo_componentes, decimal_comma)
             .parse::<Components>()
             .unwrap_or_else(|e| {
                 eprintln!(
@@ -225,9 +298,34 @@ fn get_components(archivo: Option<&str>) -> Components {
 }
 
 /// Crea aplicación y detecta opciones seleccionadas
+/// Descarga (o recupera de la caché local) la tabla de factores de paso de la URL indicada en la CLI
+///
+/// Devuelve la URL y el contenido de la tabla
+#[cfg(feature = "net")]
+fn remote_factors(matches: &clap::ArgMatches) -> Option<(String, String)> {
+    let url = matches.value_of("url_factores")?;
+    let sha256 = matches.value_of("sha256_factores");
+    let (data, warnings) =
+        cteepbd::remote::fetch_cached(url, sha256, &cteepbd::remote::default_cache_dir())
+            .unwrap_or_else(|e| {
+                eprintln!("ERROR: no se han podido obtener los factores de paso remotos: {}", e);
+                exit(exitcode::IOERR);
+            });
+    for warning in &warnings {
+        eprintln!("{}", warning);
+    }
+    Some((url.to_string(), data))
+}
+
+/// Sin la feature `net` no se admiten factores de paso remotos
+#[cfg(not(feature = "net"))]
+fn remote_factors(_matches: &clap::ArgMatches) -> Option<(String, String)> {
+    None
+}
+
 fn start_app_and_get_matches() -> clap::ArgMatches<'static> {
     use clap::Arg;
-    clap::App::new(APP_TITLE)
+    let app = clap::App::new(APP_TITLE)
         .bin_name("cteepbd")
         .version(env!("CARGO_PKG_VERSION"))
         .author(APP_DESCRIPTION)
@@ -259,8 +357,8 @@ fn start_app_and_get_matches() -> clap::ArgMatches<'static> {
             .short("f")
             .long("archivo_factores")
             .value_name("ARCHIVO_FACTORES")
-            .required_unless_one(&["fps_loc", "archivo_componentes"])
-            .conflicts_with_all(&["fps_loc", "red1", "red2"])
+            .required_unless_one(FP_FILE_ALT)
+            .conflicts_with_all(&["fps_loc", "red1", "red2", "hidrogeno"])
             .help("Archivo de definición de los componentes energéticos")
             .takes_value(true)
             //.validator(clap_validators::fs::is_file))
@@ -269,10 +367,24 @@ fn start_app_and_get_matches() -> clap::ArgMatches<'static> {
             .short("l")
             .value_name("LOCALIZACION")
             .possible_values(&["PENINSULA", "CANARIAS", "BALEARES", "CEUTAMELILLA"])
-            .required_unless_one(&["archivo_factores", "archivo_componentes"])
+            .required_unless_one(FP_LOC_ALT)
             .help("Localización que define los factores de paso\n")
             .takes_value(true)
             .display_order(5))
+        .arg(Arg::with_name("archivo_contenedor")
+            .long("contenedor")
+            .value_name("ARCHIVO_CONTENEDOR")
+            .conflicts_with_all(&["archivo_componentes", "archivo_factores", "fps_loc"])
+            .help("Archivo contenedor con los componentes energéticos y los factores de paso (secciones [COMPONENTES] y [FACTORES])")
+            .takes_value(true)
+            .display_order(5))
+        .arg(Arg::with_name("wf_version")
+            .long("wf-version")
+            .value_name("VERSION")
+            .conflicts_with("archivo_factores")
+            .help("Versión de las tablas reglamentarias de factores de paso por localización (por defecto, RITE2014)\n")
+            .takes_value(true)
+            .display_order(6))
         // Archivos de salida
         .arg(Arg::with_name("gen_archivo_componentes")
             .long("oc")
@@ -284,16 +396,66 @@ fn start_app_and_get_matches() -> clap::ArgMatches<'static> {
             .value_name("GEN_ARCHIVO_FACTORES")
             .help("Archivo de salida de los factores de paso corregidos")
             .takes_value(true))
+        .arg(Arg::with_name("gen_archivo_contenedor")
+            .long("ocontenedor")
+            .value_name("GEN_ARCHIVO_CONTENEDOR")
+            .help("Archivo contenedor de salida con los componentes energéticos y los factores de paso corregidos")
+            .takes_value(true))
         .arg(Arg::with_name("archivo_salida_json")
             .long("json")
             .value_name("ARCHIVO_SALIDA_JSON")
             .help("Archivo de salida de resultados detallados en formato JSON")
             .takes_value(true))
+        .arg(Arg::with_name("archivo_salida_json_resumen")
+            .long("json_resumen")
+            .alias("json-resumen")
+            .value_name("ARCHIVO_SALIDA_JSON")
+            .help("Archivo de salida del resumen de indicadores en formato JSON\n(energía primaria, emisiones, RER, RER_nrb y fracción renovable de la demanda de ACS, total y por servicio)")
+            .takes_value(true))
         .arg(Arg::with_name("archivo_salida_xml")
             .long("xml")
             .value_name("ARCHIVO_SALIDA_XML")
             .help("Archivo de salida de resultados detallados en formato XML")
             .takes_value(true))
+        .arg(Arg::with_name("archivo_salida_html")
+            .long("html")
+            .value_name("ARCHIVO_SALIDA_HTML")
+            .help("Archivo de salida del informe de resultados en formato HTML autocontenido\n(indicadores, balances por vector y servicio, diagrama Sankey, metadatos y avisos)")
+            .takes_value(true))
+        .arg(Arg::with_name("archivo_salida_sankey")
+            .long("sankey")
+            .value_name("ARCHIVO_SALIDA_SVG")
+            .help("Archivo de salida del diagrama Sankey de energía final y ponderada en formato SVG")
+            .takes_value(true))
+        .arg(Arg::with_name("archivo_salida_tidy_json")
+            .long("tidy_json")
+            .value_name("ARCHIVO_SALIDA_JSON")
+            .help("Archivo de salida de resultados por vector en formato de tabla larga (JSON)")
+            .takes_value(true))
+        .arg(Arg::with_name("archivo_salida_tidy_csv")
+            .long("tidy_csv")
+            .value_name("ARCHIVO_SALIDA_CSV")
+            .help("Archivo de salida de resultados por vector en formato de tabla larga (CSV)")
+            .takes_value(true))
+        .arg(Arg::with_name("unidades")
+            .long("unidades")
+            .value_name("UNIDADES")
+            .possible_values(&["m2", "abs"])
+            .default_value("m2")
+            .help("Unidades de los balances en los resultados en texto plano: por m2 de área de referencia (m2) o valores absolutos (abs)")
+            .takes_value(true))
+        .arg(Arg::with_name("archivo_salida_registro")
+            .long("registro")
+            .value_name("ARCHIVO_SALIDA_REGISTRO")
+            .requires("plantilla_registro")
+            .help("Archivo de salida de resultados en formato XML para el registro autonómico, según la plantilla indicada con --plantilla_registro")
+            .takes_value(true))
+        .arg(Arg::with_name("plantilla_registro")
+            .long("plantilla_registro")
+            .value_name("PLANTILLA_REGISTRO")
+            .requires("archivo_salida_registro")
+            .help("Plantilla de exportación al formato XML del registro autonómico (líneas ruta, origen[, decimales])")
+            .takes_value(true))
         .arg(Arg::with_name("archivo_salida_txt")
             .long("txt")
             .value_name("ARCHIVO_SALIDA_TXT")
@@ -312,6 +474,12 @@ fn start_app_and_get_matches() -> clap::ArgMatches<'static> {
             .help("Factores de paso (ren, nren, co2) de la producción del vector RED2.\nP.e.: --red2 0 1.3 0.3")
             .takes_value(true)
             .number_of_values(3))
+        .arg(Arg::with_name("CTE_HIDROGENO")
+            .long("hidrogeno")
+            .value_names(&["HIDROGENO_ren", "HIDROGENO_nren", "HIDROGENO_co2"])
+            .help("Factores de paso (ren, nren, co2) del suministro del vector HIDROGENO.\nP.e.: --hidrogeno 0 1.7 0.36")
+            .takes_value(true)
+            .number_of_values(3))
         // Simplificación de factores
         .arg(Arg::with_name("nosimplificafps")
             .short("F")
@@ -329,9 +497,85 @@ fn start_app_and_get_matches() -> clap::ArgMatches<'static> {
             .help("Sets the level of verbosity"))
         .arg(Arg::with_name("load_matching")
             .long("load_matching")
-            .takes_value(false)
-            .help("Calcula factor de coincidencia de cargas"))
-        .get_matches()
+            .value_name("MODO")
+            .min_values(0)
+            .max_values(1)
+            .help("Calcula factor de coincidencia de cargas.\nOpcionalmente, modo de cálculo: constante, mensual (expresión de la tabla B.32 con k=1 y n=1, por defecto), mensual:k:n u horaria.\nP.e.: --load_matching mensual:2:1"))
+        .arg(Arg::with_name("consumos_negativos")
+            .long("consumos_negativos")
+            .value_name("TRATAMIENTO")
+            .possible_values(&["permitir", "truncar", "error"])
+            .default_value("permitir")
+            .help("Tratamiento de los consumos EPB negativos por servicio (se avisa en todos los casos)")
+            .takes_value(true))
+        .arg(Arg::with_name("duplicados")
+            .long("duplicados")
+            .value_name("TRATAMIENTO")
+            .possible_values(&["sumar", "avisar", "error"])
+            .default_value("sumar")
+            .help("Tratamiento de los componentes de energía duplicados (líneas repetidas)")
+            .takes_value(true))
+        .arg(Arg::with_name("estricto")
+            .long("estricto")
+            .help("Trata como errores las incoherencias de los datos de entrada que, en otro caso, solo generan avisos\n(p.e. factores de paso de archivo de una localización distinta a la declarada)"))
+        .arg(Arg::with_name("coma_decimal")
+            .long("coma_decimal")
+            .alias("decimal-comma")
+            .help("Convierte los números con coma decimal de los archivos de componentes y de factores de paso\n(líneas con punto y coma como separador de campos, que se detectan automáticamente si lo usan todas)"))
+        .arg(Arg::with_name("rendimiento_max_bdc")
+            .long("rendimiento_max_bdc")
+            .value_name("RENDIMIENTO")
+            .help("Rendimiento implícito (SALIDA / consumo) máximo plausible de bombas de calor y sistemas eléctricos, por encima del cual se avisa (por defecto 8.0)")
+            .takes_value(true))
+        .arg(Arg::with_name("rendimiento_max_combustion")
+            .long("rendimiento_max_combustion")
+            .value_name("RENDIMIENTO")
+            .help("Rendimiento implícito (SALIDA / consumo) máximo plausible de calderas y otros sistemas no eléctricos, por encima del cual se avisa (por defecto 1.05)")
+            .takes_value(true))
+        .arg(Arg::with_name("compensacion_simplificada")
+            .long("compensacion_simplificada")
+            .value_name("KCOMP")
+            .min_values(0)
+            .max_values(1)
+            .help("Calcula resultados adicionales con compensación simplificada de excedentes de la electricidad producida in situ.\nOpcionalmente, factor de compensación energética k_comp [0.00 - 1.00] (por defecto 0.00).\nP.e.: --compensacion_simplificada 0.5"))
+        .arg(Arg::with_name("exergia")
+            .long("exergia")
+            .value_name("ARCHIVO_FACTORES_EXERGIA")
+            .help("Calcula el balance exergético usando los factores de calidad exergética por vector del archivo indicado (líneas VECTOR, q)")
+            .takes_value(true))
+        .arg(Arg::with_name("facturas")
+            .long("facturas")
+            .value_name("ARCHIVO_FACTURAS")
+            .help("Concilia la energía suministrada por la red calculada con la energía facturada mensual por vector del archivo indicado (líneas VECTOR, ene, ..., dic)")
+            .takes_value(true))
+        .arg(Arg::with_name("co2_marginal")
+            .long("co2_marginal")
+            .value_name("ARCHIVO_CO2_MARGINAL")
+            .help("Calcula la huella de carbono marginal (informativa) con los factores de emisión marginales por paso de cálculo del archivo indicado (líneas VECTOR, f_1, ..., f_n)")
+            .takes_value(true))
+        .arg(Arg::with_name("co2_inventario")
+            .long("co2_inventario")
+            .value_name("ARCHIVO_CO2_INVENTARIO")
+            .help("Calcula también las emisiones con el juego alternativo de factores de emisión del archivo indicado (líneas VECTOR, ORIGEN, DESTINO, PASO, co2), sin modificar la energía primaria")
+            .takes_value(true));
+    // Factores de paso desde URL remota
+    #[cfg(feature = "net")]
+    let app = app
+        .arg(Arg::with_name("url_factores")
+            .long("url_factores")
+            .value_name("URL_FACTORES")
+            .conflicts_with_all(&["archivo_factores", "fps_loc", "wf_version", "red1", "red2", "hidrogeno"])
+            .help("URL de la tabla de factores de paso, que se descarga y guarda en caché local\n(directorio CTEEPBD_CACHE o caché del usuario)")
+            .takes_value(true)
+            .display_order(4))
+        .arg(Arg::with_name("sha256_factores")
+            .long("sha256_factores")
+            .value_name("SHA256")
+            .requires("url_factores")
+            .help("Resumen SHA-256 de la tabla de factores de paso remota, con el que se verifica su integridad")
+            .takes_value(true)
+            .display_order(4));
+    app.get_matches()
 }
 
 // Función principal ------------------------------------------------------------------------------
@@ -356,8 +600,30 @@ fn main() {
 
     println!("** Datos de entrada\n");
 
+    // Archivos con coma decimal (exportados desde hojas de cálculo en configuración regional española)
+    let decimal_comma = matches.is_present("coma_decimal");
+
     // Componentes energéticos ---------------------------------------------------------------------
-    let mut components = get_components(matches.value_of("archivo_componentes"));
+    // Con archivo contenedor, los componentes y los factores de paso se leen del mismo archivo
+    let (mut components, container_wfactors) = match matches.value_of("archivo_contenedor") {
+        Some(path) => {
+            println!("Archivo contenedor: \"{}\"", path);
+            let container = readfile(path)
+                .parse::<DataContainer>()
+                .unwrap_or_else(|e| {
+                    eprintln!(
+                        "ERROR: formato incorrecto del archivo contenedor \"{}\": {}",
+                        path, e
+                    );
+                    exit(exitcode::DATAERR);
+                });
+            (container.components, Some((path, container.wfactors)))
+        }
+        None => (
+            get_components(matches.value_of("archivo_componentes"), decimal_comma),
+            None,
+        ),
+    };
 
     if verbosity > 1 && !components.meta.is_empty() {
         println!("Metadatos de componentes:");
@@ -366,6 +632,20 @@ fn main() {
         }
     }
 
+    // Uso del edificio (CTE_USO_EDIFICIO) y servicios admitidos --------------------------------
+    let uso_edificio = cte::uso_edificio(&components).and_then(|uso| match uso {
+        Some(uso) => cte::valida_servicios_uso_edificio(&components, uso).map(|_| Some(uso)),
+        None => Ok(None),
+    });
+    match uso_edificio {
+        Ok(Some(uso)) => println!("Uso del edificio (metadatos): {}", uso),
+        Ok(None) => (),
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            exit(exitcode::DATAERR);
+        }
+    }
+
     // Comprobación del parámetro de factor de exportación kexp -----------------------------------
     let kexp_cli = matches
         .value_of("kexp")
@@ -377,19 +657,144 @@ fn main() {
         .and_then(|arearefstr| validate_arearef(arearefstr, "usuario"));
 
     // Método de cálculo del factor de coincidencia de cargas
-    let load_matching = matches.is_present("load_matching");
+    let load_matching = if matches.is_present("load_matching") {
+        matches
+            .value_of("load_matching")
+            .map(|mode| {
+                mode.parse::<LoadMatchingMode>().unwrap_or_else(|e| {
+                    eprintln!(
+                        "ERROR: modo de cálculo del factor de coincidencia de cargas no válido: {}",
+                        e
+                    );
+                    exit(exitcode::USAGE);
+                })
+            })
+            .unwrap_or(LoadMatchingMode::MONTHLY_DEFAULT)
+    } else {
+        LoadMatchingMode::Constant
+    };
+
+    // Tratamiento de consumos negativos
+    let negative_use = matches
+        .value_of("consumos_negativos")
+        .and_then(|v| v.parse::<NegativeUsePolicy>().ok())
+        .unwrap_or_default();
+
+    // Tolerancias y tratamiento de componentes duplicados
+    let tolerances = Tolerances {
+        duplicates: matches
+            .value_of("duplicados")
+            .and_then(|v| v.parse::<DuplicatePolicy>().ok())
+            .unwrap_or_default(),
+        efficiency_max_hp: matches
+            .value_of("rendimiento_max_bdc")
+            .map(|v| validate_efficiency_max(v, "bombas de calor"))
+            .unwrap_or(Tolerances::default().efficiency_max_hp),
+        efficiency_max_comb: matches
+            .value_of("rendimiento_max_combustion")
+            .map(|v| validate_efficiency_max(v, "combustión"))
+            .unwrap_or(Tolerances::default().efficiency_max_comb),
+        ..Default::default()
+    };
+
+    // Compensación simplificada de excedentes
+    let kcomp = if matches.is_present("compensacion_simplificada") {
+        Some(
+            matches
+                .value_of("compensacion_simplificada")
+                .map(validate_kcomp)
+                .unwrap_or(cte::KCOMP_DEFAULT),
+        )
+    } else {
+        None
+    };
+
+    // Factores de calidad exergética (balance exergético opcional)
+    let xfactors = matches.value_of("exergia").map(|path| {
+        readfile(path)
+            .parse::<ExergyFactors>()
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "ERROR: formato incorrecto del archivo de factores de calidad exergética \"{}\": {}",
+                    path, e
+                );
+                exit(exitcode::DATAERR);
+            })
+    });
+
+    // Factores de emisión marginales (huella de carbono marginal opcional)
+    let mfactors = matches.value_of("co2_marginal").map(|path| {
+        readfile(path)
+            .parse::<MarginalCo2Factors>()
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "ERROR: formato incorrecto del archivo de factores de emisión marginales \"{}\": {}",
+                    path, e
+                );
+                exit(exitcode::DATAERR);
+            })
+    });
+
+    // Juego alternativo de factores de emisión (emisiones de inventario opcionales)
+    let cfactors = matches.value_of("co2_inventario").map(|path| {
+        readfile(path)
+            .parse::<InventoryCo2Factors>()
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "ERROR: formato incorrecto del archivo de factores de emisión alternativos \"{}\": {}",
+                    path, e
+                );
+                exit(exitcode::DATAERR);
+            })
+    });
+
+    // Energía facturada (conciliación opcional con facturas de suministro)
+    let bills = matches.value_of("facturas").map(|path| {
+        readfile(path).parse::<Bills>().unwrap_or_else(|e| {
+            eprintln!(
+                "ERROR: formato incorrecto del archivo de energía facturada \"{}\": {}",
+                path, e
+            );
+            exit(exitcode::DATAERR);
+        })
+    });
+
+    // Plantilla de exportación para el registro autonómico
+    let plantilla_registro = matches.value_of("plantilla_registro").map(|path| {
+        readfile(path)
+            .parse::<registro::PlantillaRegistro>()
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "ERROR: formato incorrecto de la plantilla de registro \"{}\": {}",
+                    path, e
+                );
+                exit(exitcode::DATAERR);
+            })
+    });
 
     // Factores de paso ---------------------------------------------------------------------------
 
-    // 0. Factores por defecto, según modo
-    let default_locwf = &cte::CTE_LOCWF_RITE2014;
+    // 0. Factores por defecto, según versión de las tablas reglamentarias
+    // CLI > Metadatos de componentes > Valor por defecto (RITE2014)
+    let wf_version_cli = matches.value_of("wf_version").map(str::to_string);
+    let wf_version_meta = components.get_meta("CTE_FP_VERSION");
+    let (orig_wf_version, wf_version_id) = match (wf_version_cli, wf_version_meta) {
+        (Some(v_cli), _) => ("usuario", v_cli),
+        (None, Some(v_meta)) => ("metadatos", v_meta),
+        _ => ("predefinido", cte::CTE_WF_VERSION_DEFAULT.to_string()),
+    };
+    let wf_version = cte::cte_wf_version(&wf_version_id).unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        exit(exitcode::USAGE);
+    });
+    let default_locwf = wf_version.locwf;
     let default_userwf = cte::CTE_USERWF;
 
     // 1. Factores de paso definibles por el usuario (a través de la CLI o de metadatos)
-    let user_wf = UserWF {
-        red1: get_factor(&matches, &mut components, "CTE_RED1"),
-        red2: get_factor(&matches, &mut components, "CTE_RED2"),
-    };
+    let user_wf = UserWF::default()
+        .with_red1(get_factor(&matches, &mut components, "CTE_RED1"))
+        .with_red2(get_factor(&matches, &mut components, "CTE_RED2"))
+        .with_hidrogeno(get_factor(&matches, &mut components, "CTE_HIDROGENO"));
 
     if verbosity > 2 {
         println!("Factores de paso de usuario:\n{:?}", user_wf)
@@ -401,17 +806,33 @@ fn main() {
     let loc_cli = matches.value_of("fps_loc");
     let loc_meta = components.get_meta("CTE_LOCALIZACION");
 
-    // CLI path > CLI loc > Meta loc > error
-    let (orig_fp, param_fp, fp_opt) = match (fp_path_cli, loc_cli, loc_meta) {
-        (Some(fp_cli), _, _) => {
-            let fp = cte::wfactors_from_str(&readfile(fp_cli), user_wf, default_userwf);
+    // CLI url > CLI contenedor > CLI path > CLI loc > Meta loc > error
+    let fp_remote = remote_factors(&matches);
+    let (orig_fp, param_fp, fp_opt) = match (fp_remote, fp_path_cli, loc_cli, loc_meta) {
+        (Some((url, data)), _, _, _) => {
+            let fp = cte::wfactors_from_str(&data, user_wf, default_userwf);
+            ("remoto", url, fp)
+        }
+        (None, _, _, _) if container_wfactors.is_some() => {
+            let (path, wfactors) = container_wfactors.unwrap();
+            let fp = wfactors
+                .set_user_wfactors(user_wf)
+                .normalize(&default_userwf);
+            ("contenedor", path.to_string(), fp)
+        }
+        (None, Some(fp_cli), _, _) => {
+            let fp = cte::wfactors_from_str(
+                &read_data_file(fp_cli, decimal_comma),
+                user_wf,
+                default_userwf,
+            );
             ("archivo", fp_cli.to_string(), fp)
         }
-        (None, Some(l_cli), _) => {
+        (None, None, Some(l_cli), _) => {
             let fp = cte::wfactors_from_loc(l_cli, default_locwf, user_wf, default_userwf);
             ("usuario", l_cli.to_string(), fp)
         }
-        (None, None, Some(l_meta)) => {
+        (None, None, None, Some(l_meta)) => {
             let fp = cte::wfactors_from_loc(&l_meta, default_locwf, user_wf, default_userwf);
             ("metadatos", l_meta, fp)
         }
@@ -429,7 +850,60 @@ fn main() {
         exit(exitcode::DATAERR);
     });
 
+    // Factores de paso de usuario definidos en los metadatos de los componentes (CTE_FP)
+    let meta_wfactors = components.user_wfactors().unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        exit(exitcode::DATAERR);
+    });
+    if !meta_wfactors.is_empty() {
+        fpdata = fpdata
+            .set_user_factors(&meta_wfactors)
+            .normalize(&default_userwf)
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "ERROR: factores de paso de usuario (CTE_FP) incorrectos: {}",
+                    e
+                );
+                exit(exitcode::DATAERR);
+            });
+    }
+
     println!("Factores de paso ({}): {}", orig_fp, param_fp);
+    if orig_fp == "archivo" || orig_fp == "remoto" || orig_fp == "contenedor" {
+        // Coherencia de la localización declarada (CLI > Metadatos) con los factores de paso
+        let loc_declared = loc_cli
+            .map(str::to_string)
+            .or_else(|| components.get_meta("CTE_LOCALIZACION"));
+        if let Some(loc) = loc_declared {
+            if let Err(e) = cte::check_wfactors_location(&loc, &fpdata) {
+                if matches.is_present("estricto") {
+                    eprintln!("ERROR: {}", e);
+                    exit(exitcode::DATAERR);
+                }
+                println!("AVISO: {}", e);
+            }
+        }
+    } else {
+        println!(
+            "Versión de factores de paso ({}): {} ({})",
+            orig_wf_version, wf_version.id, wf_version.desc
+        );
+        // Actualiza metadato CTE_FP_VERSION a la versión seleccionada
+        components.set_meta("CTE_FP_VERSION", wf_version.id);
+    }
+    if !meta_wfactors.is_empty() {
+        println!(
+            "Factores de paso de usuario (metadatos CTE_FP): {}",
+            meta_wfactors.len()
+        );
+    }
+
+    for f in fpdata.user_defined_renewable_factors() {
+        println!(
+            "AVISO: factor de paso de {} definido por el usuario distinto del predefinido (1.0, 0.0, 0.0): {}",
+            f.carrier, f
+        );
+    }
 
     // Simplificación de los factores de paso -----------------------------------------------------
     if !matches.is_present("nosimplificafps") && !components.data.is_empty() {
@@ -444,6 +918,17 @@ fn main() {
         }
     }
 
+    // Comprobación temprana de factores de paso necesarios para el balance -------------------------
+    if !components.data.is_empty() {
+        if let Err(e) = fpdata.check_required(&components) {
+            eprintln!(
+                "ERROR: factores de paso insuficientes para los componentes: {}",
+                e
+            );
+            exit(exitcode::DATAERR);
+        }
+    }
+
     // Área de referencia -------------------------------------------------------------------------
     // CLI > Metadatos de componentes > Valor por defecto (AREA_REF = 1)
     let arearef_meta = components
@@ -498,7 +983,7 @@ fn main() {
         if verbosity > 2 {
             println!("Componentes energéticos:\n{}", components);
         }
-        writefile(&path, components.to_string().as_bytes());
+        writefile(path, components.to_string().as_bytes());
         if verbosity > 0 {
             println!("Guardado archivo de componentes energéticos: {:?}", path);
         }
@@ -510,16 +995,59 @@ fn main() {
         if verbosity > 2 {
             println!("Factores de paso:\n{}", fpdata);
         }
-        writefile(&path, fpdata.to_string().as_bytes());
+        writefile(path, fpdata.to_string().as_bytes());
         if verbosity > 0 {
             println!("Guardado archivo de factores de paso: {:?}", path);
         }
     }
 
+    // Guardado del archivo contenedor de componentes y factores de paso ---------------------------
+    if matches.is_present("gen_archivo_contenedor") {
+        let path = matches.value_of_os("gen_archivo_contenedor").unwrap();
+        let container = DataContainer {
+            components: components.clone(),
+            wfactors: fpdata.clone(),
+        };
+        writefile(path, container.to_string().as_bytes());
+        if verbosity > 0 {
+            println!("Guardado archivo contenedor: {:?}", path);
+        }
+    }
+
     // Cálculo de la eficiencia energética ------------------------------------------------------------------------
     let ep: Option<EnergyPerformance> = if !components.data.is_empty() {
-        let ep = energy_performance(&components, &fpdata, kexp, arearef, load_matching)
+        // Los componentes y factores no se usan después del cálculo y se ceden sin copiarlos
+        let ep = energy_performance_from(
+            components,
+            fpdata,
+            &CalcOptions::new(kexp, arearef)
+                .load_matching(load_matching)
+                .tolerances(tolerances)
+                .negative_use(negative_use),
+        )
             .map(cte::incorpora_demanda_renovable_acs_nrb)
+            .map(cte::incorpora_cobertura_renovable_onst)
+            .map(|ep| cte::incorpora_rer_directiva(ep, cte::ETA_ELECTRICIDAD_UE))
+            .map(|ep| cte::incorpora_renovable_bdc_calefaccion(ep, cte::ETA_ELECTRICIDAD_UE))
+            .map(|ep| match kcomp {
+                Some(kcomp) => cte::incorpora_compensacion_simplificada(ep, kcomp),
+                None => ep,
+            })
+            .and_then(|mut ep| {
+                if let Some(xfactors) = &xfactors {
+                    ep.exergy = Some(exergy_balance(&ep, xfactors)?);
+                }
+                if let Some(bills) = &bills {
+                    ep.bills = Some(bills_reconciliation(&ep, bills)?);
+                }
+                if let Some(mfactors) = &mfactors {
+                    ep.marginal_co2 = Some(marginal_carbon(&ep, mfactors)?);
+                }
+                if let Some(cfactors) = &cfactors {
+                    ep.co2_inventory = Some(co2_inventory(&ep, cfactors)?);
+                }
+                Ok(ep)
+            })
             .unwrap_or_else(|e| {
                 eprintln!(
                     "ERROR: no se ha podido calcular la eficiencia energética: {}",
@@ -541,6 +1069,12 @@ fn main() {
 
     // Salida de resultados -----------------------------------------------------------------------
     if let Some(ep) = ep {
+        // Avisos del cálculo (las notas informativas solo con salida detallada)
+        for warning in &ep.warnings {
+            if warning.severity != Severity::Info || verbosity > 0 {
+                println!("{}", warning);
+            }
+        }
         // Guardar datos y resultados en formato json
         if matches.is_present("archivo_salida_json") {
             let path = matches.value_of_os("archivo_salida_json").unwrap();
@@ -554,7 +1088,23 @@ fn main() {
                 );
                 exit(exitcode::DATAERR);
             });
-            writefile(&path, json.as_bytes());
+            writefile(path, json.as_bytes());
+        }
+        // Guardar resumen de indicadores en formato JSON
+        if matches.is_present("archivo_salida_json_resumen") {
+            let path = matches.value_of_os("archivo_salida_json_resumen").unwrap();
+            if verbosity > 0 {
+                println!("Resumen de indicadores en formato JSON: {:?}", path);
+            }
+            let summary = SummaryReport::from(&ep);
+            let json = serde_json::to_string_pretty(&summary).unwrap_or_else(|e| {
+                eprintln!(
+                    "ERROR: conversión incorrecta del resumen de indicadores a JSON: {}",
+                    e
+                );
+                exit(exitcode::DATAERR);
+            });
+            writefile(path, json.as_bytes());
         }
         // Guardar datos y resultados en formato XML
         if matches.is_present("archivo_salida_xml") {
@@ -563,10 +1113,69 @@ fn main() {
                 println!("Resultados en formato XML: {:?}", path);
             }
             let xml = &ep.to_xml();
-            writefile(&path, xml.as_bytes());
+            writefile(path, xml.as_bytes());
+        }
+        // Guardar informe en formato HTML
+        if matches.is_present("archivo_salida_html") {
+            let path = matches.value_of_os("archivo_salida_html").unwrap();
+            if verbosity > 0 {
+                println!("Informe de resultados en formato HTML: {:?}", path);
+            }
+            let html = ep.to_html();
+            writefile(path, html.as_bytes());
+        }
+        // Guardar diagrama Sankey en formato SVG
+        if matches.is_present("archivo_salida_sankey") {
+            let path = matches.value_of_os("archivo_salida_sankey").unwrap();
+            if verbosity > 0 {
+                println!("Diagrama Sankey en formato SVG: {:?}", path);
+            }
+            let svg = ep.to_sankey_svg();
+            writefile(path, svg.as_bytes());
+        }
+        // Guardar resultados por vector en formato de tabla larga
+        if matches.is_present("archivo_salida_tidy_json") {
+            let path = matches.value_of_os("archivo_salida_tidy_json").unwrap();
+            if verbosity > 0 {
+                println!("Resultados por vector en formato de tabla larga (JSON): {:?}", path);
+            }
+            let json = ep.to_tidy_json().unwrap_or_else(|e| {
+                eprintln!(
+                    "ERROR: no se han podido convertir los resultados a JSON: {}",
+                    e
+                );
+                exit(exitcode::DATAERR);
+            });
+            writefile(path, json.as_bytes());
+        }
+        if matches.is_present("archivo_salida_tidy_csv") {
+            let path = matches.value_of_os("archivo_salida_tidy_csv").unwrap();
+            if verbosity > 0 {
+                println!("Resultados por vector en formato de tabla larga (CSV): {:?}", path);
+            }
+            writefile(path, ep.to_tidy_csv().as_bytes());
+        }
+        // Guardar resultados en formato XML del registro autonómico
+        if let Some(plantilla) = &plantilla_registro {
+            let path = matches.value_of_os("archivo_salida_registro").unwrap();
+            if verbosity > 0 {
+                println!("Resultados en formato XML del registro: {:?}", path);
+            }
+            let xml = plantilla.render(&ep).unwrap_or_else(|e| {
+                eprintln!(
+                    "ERROR: no se han podido exportar los resultados para el registro: {}",
+                    e
+                );
+                exit(exitcode::DATAERR);
+            });
+            writefile(path, xml.as_bytes());
         }
         // Mostrar siempre en formato de texto plano
-        let plain = ep.to_plain();
+        let units = matches
+            .value_of("unidades")
+            .and_then(|v| v.parse::<BalanceUnits>().ok())
+            .unwrap_or_default();
+        let plain = ep.to_plain_in(units);
         println!("\n{}", plain);
 
         // Guardar datos y resultados en formato de texto plano
@@ -575,7 +1184,7 @@ fn main() {
             if verbosity > 0 {
                 println!("Resultados en formato XML: {:?}", path);
             }
-            writefile(&path, plain.as_bytes());
+            writefile(path, plain.as_bytes());
         }
     };
 }
@@ -583,4 +1192,4 @@ fn main() {
 /// Función ficticia para arreglar linkado en win32
 /// Nunca se llama porque tenemos configurado panic=abort
 #[no_mangle]
-pub extern "C" fn _Unwind_Resume() {}
\ No newline at end of file
+pub extern "C" fn _Unwind_Resume() {}
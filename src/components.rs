@@ -38,7 +38,7 @@ Hipótesis:
 */
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
     fmt, str,
 };
 
@@ -46,7 +46,13 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     error::{EpbdError, Result},
-    types::{BuildingNeeds, Carrier, CType, EProd, Energy, HasValues, Meta, MetaVec, ProdSource, Service},
+    types::{
+        timestep_hours, BuildingNeeds, CType, Carrier, Diagnostic, DuplicateComponent, EOut, EProd,
+        Embodied, Energy, ExportWeighting, Factor, HasValues, ImplausibleEfficiency, Kwh, Meta,
+        MetaVec, Needs, ProdSource, RenNrenCo2, Resolution, Scope, Service, Storage, System,
+        SystemEfficiency, SystemEfficiencyCurve, SystemPeakPower, Tolerances, UserCarrier,
+        UserCarrierScope, Warning, WarningCode,
+    },
     vecops::{veclistsum, vecvecdif, vecvecsum},
 };
 
@@ -65,6 +71,33 @@ pub struct Components {
     pub data: Vec<Energy>,
     /// Building energy needs
     pub needs: BuildingNeeds,
+    /// Embodied energy (informative, not included in the EPB balance)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub embodied: Vec<Embodied>,
+    /// Declared systems (equipment type and nominal power)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub systems: Vec<System>,
+    /// Declared seasonal efficiencies of systems, by service
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub efficiencies: Vec<SystemEfficiency>,
+    /// Declared part load efficiency curves of systems, by service
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub efficiency_curves: Vec<SystemEfficiencyCurve>,
+    /// Declared peak power of photovoltaic systems
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub peak_powers: Vec<SystemPeakPower>,
+    /// Electric storage systems (batteries)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub storages: Vec<Storage>,
+    /// Warnings about components generated or transformed on normalization
+    #[serde(skip)]
+    pub warnings: Vec<Warning>,
 }
 
 impl MetaVec for Components {
@@ -88,6 +121,12 @@ impl fmt::Display for Components {
             .data
             .iter()
             .map(|v| format!("{}", v))
+            .chain(self.embodied.iter().map(|v| format!("{}", v)))
+            .chain(self.systems.iter().map(|v| format!("{}", v)))
+            .chain(self.efficiencies.iter().map(|v| format!("{}", v)))
+            .chain(self.efficiency_curves.iter().map(|v| format!("{}", v)))
+            .chain(self.peak_powers.iter().map(|v| format!("{}", v)))
+            .chain(self.storages.iter().map(|v| format!("{}", v)))
             .collect::<Vec<_>>()
             .join("\n");
         write!(f, "{}\n{}", meta_lines, data_lines)
@@ -99,22 +138,34 @@ impl str::FromStr for Components {
 
     fn from_str(s: &str) -> std::result::Result<Components, Self::Err> {
         let s_no_bom = s.strip_prefix('\u{feff}').unwrap_or(s);
-        let lines: Vec<&str> = s_no_bom.lines().map(str::trim).collect();
+        // Líneas numeradas (desde 1) para informar de la posición de los errores
+        let lines: Vec<(usize, &str)> = s_no_bom
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .map(|(i, l)| (i + 1, l))
+            .collect();
         let meta_lines = lines
             .iter()
-            .filter(|l| l.starts_with("#META") || l.starts_with("#CTE_"));
+            .filter(|(_, l)| l.starts_with("#META") || l.starts_with("#CTE_"));
         let data_lines = lines
             .iter()
-            .filter(|l| !(l.starts_with('#') || l.starts_with("vector,") || l.is_empty()));
+            .filter(|(_, l)| !(l.starts_with('#') || l.starts_with("vector,") || l.is_empty()));
         let cmeta = meta_lines
-            .map(|e| e.parse())
+            .map(|(n, e)| e.parse().map_err(|err: EpbdError| err.at_line(*n)))
             .collect::<Result<Vec<Meta>>>()?;
+        let _user_carriers = UserCarrierScope::from_meta(&cmeta)?;
 
         let mut cdata = Vec::new();
         let mut needs = BuildingNeeds::default();
-        // let mut systems = None;
+        let mut embodied = Vec::new();
+        let mut systems: Vec<System> = Vec::new();
+        let mut efficiencies = Vec::new();
+        let mut efficiency_curves = Vec::new();
+        let mut peak_powers = Vec::new();
+        let mut storages = Vec::new();
 
-        for line in data_lines {
+        for &(line_num, line) in data_lines {
             let [tag1, tag2]: [&str; 2] = line
                 .splitn(3, ',')
                 .map(str::trim)
@@ -123,18 +174,42 @@ impl str::FromStr for Components {
                 .try_into()
                 .unwrap_or(["", ""]);
             let ctype: CType = tag1.parse().or_else(|_| tag2.parse()).map_err(|e| {
+                let msg = match e {
+                    EpbdError::ParseError(msg) => msg,
+                    e => e.to_string(),
+                };
                 EpbdError::ParseError(format!(
-                    "ERROR: No se reconoce el tipo de componente de la línea {}: {}",
-                    line, e
+                    "no se reconoce el tipo de componente en `{}`, {}",
+                    line, msg
                 ))
+                .at_line(line_num)
             })?;
 
+            let at_line = |e: EpbdError| e.at_line(line_num);
             match ctype {
-                CType::CONSUMO => cdata.push(Energy::Used(line.parse()?)),
-                CType::PRODUCCION => cdata.push(Energy::Prod(line.parse()?)),
-                CType::AUX => cdata.push(Energy::Aux(line.parse()?)),
-                CType::SALIDA => cdata.push(Energy::Out(line.parse()?)),
-                CType::DEMANDA => needs.add(line.parse()?)?,
+                CType::CONSUMO => cdata.push(Energy::Used(line.parse().map_err(at_line)?)),
+                CType::PRODUCCION => cdata.push(Energy::Prod(line.parse().map_err(at_line)?)),
+                CType::AUX => cdata.push(Energy::Aux(line.parse().map_err(at_line)?)),
+                CType::SALIDA => cdata.push(Energy::Out(line.parse().map_err(at_line)?)),
+                CType::DEMANDA => needs.add(line.parse().map_err(at_line)?).map_err(at_line)?,
+                CType::EMBEBIDA => embodied.push(line.parse().map_err(at_line)?),
+                CType::SISTEMA => match system_kind(line) {
+                    "POTENCIA_PICO" => peak_powers.push(line.parse().map_err(at_line)?),
+                    "CURVA_RENDIMIENTO" => efficiency_curves.push(line.parse().map_err(at_line)?),
+                    "EQUIPO" => {
+                        let system: System = line.parse().map_err(at_line)?;
+                        if systems.iter().any(|s| s.id == system.id) {
+                            return Err(EpbdError::ParseError(format!(
+                                "equipo del sistema {} declarado más de una vez",
+                                system.id
+                            ))
+                            .at_line(line_num));
+                        }
+                        systems.push(system)
+                    }
+                    _ => efficiencies.push(line.parse().map_err(at_line)?),
+                },
+                CType::ALMACENAMIENTO => storages.push(line.parse().map_err(at_line)?),
             }
         }
 
@@ -155,23 +230,45 @@ impl str::FromStr for Components {
             }
         }
 
-        Components {
+        let components = Components {
             meta: cmeta,
             data: cdata,
             needs,
-        }
-        .normalize()
+            embodied,
+            systems,
+            efficiencies,
+            efficiency_curves,
+            peak_powers,
+            storages,
+            warnings: Vec::new(),
+        };
+        components.resolution()?;
+        components.normalize()
     }
 }
 
+/// Clase de componente SISTEMA (EQUIPO, RENDIMIENTO o POTENCIA_PICO), según su tercer campo
+pub(crate) fn system_kind(line: &str) -> &str {
+    line.split('#')
+        .next()
+        .and_then(|fields| fields.split(',').nth(2))
+        .map(str::trim)
+        .unwrap_or("")
+}
+
 impl Components {
+    /// Datos del equipo del sistema indicado, si se ha declarado
+    pub fn system(&self, id: i32) -> Option<&System> {
+        self.systems.iter().find(|s| s.id == id)
+    }
+
     /// Number of steps of the first component
     pub fn num_steps(&self) -> usize {
-        self.data.get(0).map(|v| v.num_steps()).unwrap_or(0)
+        self.data.first().map(|v| v.num_steps()).unwrap_or(0)
     }
 
-    /// Conjunto de vectores energéticos disponibles en componentes de energía consumida o producida
-    pub fn available_carriers(&self) -> HashSet<Carrier> {
+    /// Conjunto ordenado de vectores energéticos disponibles en componentes de energía consumida o producida
+    pub fn available_carriers(&self) -> BTreeSet<Carrier> {
         self.data
             .iter()
             .filter(|c| c.is_used() || c.is_generated())
@@ -179,25 +276,1040 @@ impl Components {
             .collect()
     }
 
+    /// Vectores opcionales (`Carrier::NRBY_OPT`) que se consideran dentro del perímetro próximo
+    ///
+    /// El BIOCARBURANTE se incluye cuando el metadato `CTE_BIOCARBURANTE_PROXIMO` vale `true`.
+    pub fn nearby_opt_carriers(&self) -> Vec<Carrier> {
+        if self
+            .get_meta_bool("CTE_BIOCARBURANTE_PROXIMO")
+            .unwrap_or(false)
+        {
+            vec![Carrier::BIOCARBURANTE]
+        } else {
+            vec![]
+        }
+    }
+
+    /// ¿Pertenece el vector al perímetro próximo (NEARBY), considerando los vectores opcionales configurados?
+    pub fn is_nearby_carrier(&self, carrier: Carrier) -> bool {
+        carrier.is_nearby_with(&self.nearby_opt_carriers())
+    }
+
+    /// Potencia pico total de las instalaciones fotovoltaicas, kWp
+    ///
+    /// Suma de las potencias pico declaradas (SISTEMA, id, POTENCIA_PICO, valor) o, en su defecto,
+    /// valor del metadato `CTE_PV_KWP`
+    pub fn pv_peak_power(&self) -> Option<f32> {
+        if self.peak_powers.is_empty() {
+            self.get_meta_f32("CTE_PV_KWP").filter(|v| *v > 0.0)
+        } else {
+            Some(self.peak_powers.iter().map(|p| p.value).sum())
+        }
+    }
+
+    /// Resolución temporal de los componentes
+    ///
+    /// Se toma del metadato `CTE_RESOLUCION` (MENSUAL, DIARIA u HORARIA) o, en su defecto, se deduce del
+    /// número de pasos de cálculo. Devuelve None si no se declara y el número de pasos no corresponde a
+    /// ninguna resolución admitida (p.e. un único valor anual).
+    ///
+    /// # Errors
+    ///
+    /// * Resolución desconocida o que no coincide con el número de pasos de los componentes o de las demandas
+    pub fn resolution(&self) -> Result<Option<Resolution>> {
+        let Some(declared) = self.get_meta("CTE_RESOLUCION") else {
+            return Ok(Resolution::from_num_steps(self.num_steps()));
+        };
+        let resolution: Resolution = declared.parse()?;
+        let needs_steps = [Service::ACS, Service::CAL, Service::REF]
+            .iter()
+            .filter_map(|service| self.needs.get(*service))
+            .map(Vec::len);
+        let data_steps = self.data.iter().map(|c| c.num_steps());
+        if let Some(steps) = data_steps
+            .chain(needs_steps)
+            .find(|steps| *steps != resolution.num_steps())
+        {
+            return Err(EpbdError::WrongInput(format!(
+                "Resolución temporal declarada {} ({} pasos) distinta de la de los datos ({} pasos)",
+                resolution,
+                resolution.num_steps(),
+                steps
+            )));
+        }
+        Ok(Some(resolution))
+    }
+
+    /// Criterio de ponderación de la energía exportada cuando hay varias fuentes de producción
+    ///
+    /// Se toma del metadato `CTE_PONDERACION_EXPORTACION` (PROPORCIONAL o PRIORIDAD) y, en su defecto,
+    /// es PROPORCIONAL.
+    ///
+    /// # Errors
+    ///
+    /// * Criterio desconocido
+    pub fn export_weighting(&self) -> Result<ExportWeighting> {
+        self.get_meta("CTE_PONDERACION_EXPORTACION")
+            .map(|v| v.parse())
+            .transpose()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Cupos máximos de imputación de la electricidad producida por servicio
+    ///
+    /// Fracción máxima [0, 1] del consumo eléctrico de cada servicio que puede cubrirse con electricidad
+    /// producida (in situ o cogenerada), declarada con metadatos `CTE_CUPO_PRODUCCION_<SERVICIO>`
+    /// (p.e. `CTE_CUPO_PRODUCCION_ACS: 0.7`).
+    ///
+    /// # Errors
+    ///
+    /// * Servicio desconocido o valor no numérico o fuera del intervalo [0, 1]
+    pub fn production_quotas(&self) -> Result<BTreeMap<Service, f32>> {
+        let mut quotas = BTreeMap::new();
+        for meta in &self.meta {
+            let Some(srv) = meta.key.strip_prefix("CTE_CUPO_PRODUCCION_") else {
+                continue;
+            };
+            let service: Service = srv.parse()?;
+            let quota = meta
+                .value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|v| (0.0..=1.0).contains(v))
+                .ok_or_else(|| {
+                    EpbdError::WrongInput(format!(
+                        "Cupo de producción fuera del intervalo [0, 1] en el metadato {}: {}",
+                        meta.key, meta.value
+                    ))
+                })?;
+            quotas.insert(service, quota);
+        }
+        Ok(quotas)
+    }
+
+    /// Coeficientes de reparto de la energía común entre unidades (viviendas o locales)
+    ///
+    /// Se declaran con metadatos `CTE_REPARTO_<UNIDAD>` (p.e. `CTE_REPARTO_1A: 0.25`), donde la unidad
+    /// es la indicada en la etiqueta `CTEEPBD_UNIDAD` de sus consumos privativos.
+    ///
+    /// # Errors
+    ///
+    /// * Valor no numérico o fuera del intervalo [0, 1]
+    pub fn sharing_coefficients(&self) -> Result<BTreeMap<String, f32>> {
+        let mut coefs = BTreeMap::new();
+        for meta in &self.meta {
+            let Some(unit) = meta.key.strip_prefix("CTE_REPARTO_") else {
+                continue;
+            };
+            let coef = meta
+                .value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|v| (0.0..=1.0).contains(v))
+                .ok_or_else(|| {
+                    EpbdError::WrongInput(format!(
+                        "Coeficiente de reparto fuera del intervalo [0, 1] en el metadato {}: {}",
+                        meta.key, meta.value
+                    ))
+                })?;
+            coefs.insert(unit.to_string(), coef);
+        }
+        Ok(coefs)
+    }
+
+    /// Temporadas de los servicios, como meses activos (1 a 12), declaradas con metadatos `CTE_TEMPORADA_<SERVICIO>`
+    ///
+    /// Los meses se indican como lista separada por comas, admitiendo intervalos que pueden
+    /// pasar de un año al siguiente (p.e. `CTE_TEMPORADA_CAL: 11-3` o `CTE_TEMPORADA_REF: 6, 7, 8, 9`).
+    ///
+    /// # Errors
+    ///
+    /// * Servicio desconocido o no EPB
+    /// * Lista de meses vacía o con valores fuera del intervalo [1, 12]
+    pub fn seasons(&self) -> Result<BTreeMap<Service, Vec<u32>>> {
+        let mut seasons = BTreeMap::new();
+        for meta in &self.meta {
+            let Some(srv) = meta.key.strip_prefix("CTE_TEMPORADA_") else {
+                continue;
+            };
+            let service: Service = srv.parse()?;
+            let wrong_input = || {
+                EpbdError::WrongInput(format!(
+                    "Temporada incorrecta en el metadato {}: {}",
+                    meta.key, meta.value
+                ))
+            };
+            if !service.is_epb() {
+                return Err(wrong_input());
+            }
+            let months = parse_months(&meta.value).ok_or_else(wrong_input)?;
+            seasons.insert(service, months);
+        }
+        Ok(seasons)
+    }
+
+    /// Meses con datos medidos, para el cálculo con año incompleto
+    ///
+    /// Se declaran con el metadato `CTE_MESES_MEDIDOS` como una lista de meses (1 a 12) o
+    /// intervalos de meses separados por comas (p.e. `1-8` o `1, 2, 10-12`). Devuelve `None`
+    /// si no se declara el metadato.
+    ///
+    /// # Errors
+    ///
+    /// * Lista de meses vacía o con valores fuera del intervalo [1, 12]
+    pub fn measured_months(&self) -> Result<Option<Vec<u32>>> {
+        let Some(value) = self.get_meta("CTE_MESES_MEDIDOS") else {
+            return Ok(None);
+        };
+        parse_months(&value).map(Some).ok_or_else(|| {
+            EpbdError::WrongInput(format!(
+                "Meses medidos incorrectos en el metadato CTE_MESES_MEDIDOS: {}",
+                value
+            ))
+        })
+    }
+
+    /// Perfil mensual normalizado declarado con el metadato `CTE_PERFIL_<clave>`, o `CTE_PERFIL` si `key` está vacía
+    ///
+    /// El perfil se declara con 12 valores no negativos separados por comas, que se normalizan
+    /// para que sumen 1. Devuelve `None` si no se declara el metadato.
+    ///
+    /// # Errors
+    ///
+    /// * Perfil sin 12 valores numéricos no negativos o con suma nula
+    pub fn monthly_profile(&self, key: &str) -> Result<Option<Vec<f32>>> {
+        let meta_key = if key.is_empty() {
+            "CTE_PERFIL".to_string()
+        } else {
+            format!("CTE_PERFIL_{}", key)
+        };
+        let Some(value) = self.get_meta(&meta_key) else {
+            return Ok(None);
+        };
+        let wrong_input = || {
+            EpbdError::WrongInput(format!(
+                "Perfil mensual incorrecto en el metadato {}: {}",
+                meta_key, value
+            ))
+        };
+        let values = value
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<std::result::Result<Vec<f32>, _>>()
+            .map_err(|_| wrong_input())?;
+        let total: f32 = values.iter().sum();
+        if values.len() != 12 || values.iter().any(|v| *v < 0.0) || total <= 0.0 {
+            return Err(wrong_input());
+        }
+        Ok(Some(values.iter().map(|v| v / total).collect()))
+    }
+
+    /// Factores de exportación específicos de cada vector energético
+    ///
+    /// Se declaran con metadatos `CTE_KEXP_<VECTOR>` (p.e. `CTE_KEXP_ELECTRICIDAD: 0.5`) y sustituyen,
+    /// para ese vector, al factor de exportación general del cálculo.
+    ///
+    /// # Errors
+    ///
+    /// * Vector energético desconocido
+    /// * Factor no numérico o fuera del rango [0, 1]
+    pub fn kexp_by_carrier(&self) -> Result<BTreeMap<Carrier, f32>> {
+        let _user_carriers = UserCarrierScope::from_meta(&self.meta)?;
+        let mut kexp = BTreeMap::new();
+        for meta in &self.meta {
+            let Some(carrier) = meta.key.strip_prefix("CTE_KEXP_") else {
+                continue;
+            };
+            let carrier: Carrier = carrier.parse()?;
+            let value = meta
+                .value
+                .trim()
+                .parse::<f32>()
+                .ok()
+                .filter(|v| (0.0..=1.0).contains(v))
+                .ok_or_else(|| {
+                    EpbdError::WrongInput(format!(
+                        "Factor de exportación incorrecto en el metadato {} (debe estar entre 0.0 y 1.0): {}",
+                        meta.key, meta.value
+                    ))
+                })?;
+            kexp.insert(carrier, value);
+        }
+        Ok(kexp)
+    }
+
+    /// Servicios no EPB por defecto (VE, APP) cuyos consumos se consideran consumos EPB
+    ///
+    /// Se declaran con los metadatos `CTE_<SERVICIO>_EPB` (`true` o `false`), p.e. `CTE_VE_EPB: true` para la
+    /// recarga de vehículos eléctricos o `CTE_APP_EPB: true` para el equipamiento. Sin el metadato, los consumos
+    /// del servicio son consumos no EPB, salvo los que tengan la etiqueta `CTEEPBD_<SERVICIO>_EPB`.
+    ///
+    /// # Errors
+    ///
+    /// * Valor no booleano del metadato
+    pub fn optional_epb_services(&self) -> Result<Vec<Service>> {
+        let mut services = Vec::new();
+        for service in Service::SERVICES_ALL
+            .iter()
+            .filter(|srv| srv.is_optional_epb())
+        {
+            let key = format!("CTE_{}_EPB", service);
+            let Some(value) = self.get_meta(&key) else {
+                continue;
+            };
+            match self.get_meta_bool(&key) {
+                Some(true) => services.push(*service),
+                Some(false) => {}
+                None => {
+                    return Err(EpbdError::WrongInput(format!(
+                        "Valor incorrecto del metadato {} (debe ser true o false): {}",
+                        key, value
+                    )))
+                }
+            }
+        }
+        Ok(services)
+    }
+
+    /// Red de distrito a la que se exporta el calor producido in situ no consumido
+    ///
+    /// Se declara con el metadato `CTE_EXPORTACION_CALOR` y el vector de la red de calor (`RED1` o `RED2`).
+    /// El excedente de calor solar (TERMOSOLAR) o cogenerado (TERMICA_COGEN) se exporta a esa red y los
+    /// recursos ahorrados (paso B) son los de suministro de la red. Sin el metadato, devuelve None.
+    ///
+    /// # Errors
+    ///
+    /// * Vector distinto de RED1 o RED2
+    pub fn heat_export_network(&self) -> Result<Option<Carrier>> {
+        let Some(value) = self.get_meta("CTE_EXPORTACION_CALOR") else {
+            return Ok(None);
+        };
+        let _user_carriers = UserCarrierScope::from_meta(&self.meta)?;
+        match value.trim().parse::<Carrier>() {
+            Ok(network) if network.is_district_network() => Ok(Some(network)),
+            _ => Err(EpbdError::WrongInput(format!(
+                "Red de calor incorrecta en el metadato CTE_EXPORTACION_CALOR (debe ser RED1, RED2 u otra red de usuario): {}",
+                value
+            ))),
+        }
+    }
+
+    /// Servicios EPB cuyos consumos de cada ámbito (PRIVATIVO o COMUN) entran en el balance EPB
+    ///
+    /// Se declaran con metadatos `CTE_AMBITO_EPB_<AMBITO>` y la lista de servicios EPB incluidos
+    /// (p.e. `CTE_AMBITO_EPB_COMUN: CAL, REF, ACS`). Los consumos de ese ámbito para el resto de servicios
+    /// se consideran consumos no EPB. Una lista vacía excluye todos los consumos del ámbito y los ámbitos
+    /// sin metadato entran en el balance EPB en su totalidad.
+    ///
+    /// # Errors
+    ///
+    /// * Ámbito desconocido
+    /// * Servicio desconocido o no EPB
+    pub fn scope_rules(&self) -> Result<BTreeMap<Scope, Vec<Service>>> {
+        let mut rules = BTreeMap::new();
+        for meta in &self.meta {
+            let Some(scope) = meta.key.strip_prefix("CTE_AMBITO_EPB_") else {
+                continue;
+            };
+            let scope: Scope = scope.parse()?;
+            let services = meta
+                .value
+                .split(',')
+                .map(str::trim)
+                .filter(|srv| !srv.is_empty())
+                .map(str::parse::<Service>)
+                .collect::<Result<Vec<_>>>()?;
+            if let Some(service) = services.iter().find(|srv| !srv.is_epb()) {
+                return Err(EpbdError::WrongInput(format!(
+                    "Servicio no EPB ({}) en el metadato {}",
+                    service, meta.key
+                )));
+            }
+            rules.insert(scope, services);
+        }
+        Ok(rules)
+    }
+
+    /// Listas de prioridad de sistemas consumidores para producciones eléctricas in situ compartidas
+    ///
+    /// Se declaran, para el sistema (id) de la producción compartida, con el metadato
+    /// `CTE_PRIORIDAD_PRODUCCION_<id>` y la lista ordenada de ids de los sistemas consumidores
+    /// (p.e. `CTE_PRIORIDAD_PRODUCCION_3: 1, 2`).
+    ///
+    /// # Errors
+    ///
+    /// * Identificadores no numéricos o repetidos en la lista
+    /// * Sistema sin producción eléctrica in situ
+    pub fn production_priorities(&self) -> Result<BTreeMap<i32, Vec<i32>>> {
+        let mut priorities = BTreeMap::new();
+        for meta in &self.meta {
+            let Some(prod_id) = meta.key.strip_prefix("CTE_PRIORIDAD_PRODUCCION_") else {
+                continue;
+            };
+            let wrong_input = || {
+                EpbdError::WrongInput(format!(
+                    "Lista de prioridad de la producción incorrecta en el metadato {}: {}",
+                    meta.key, meta.value
+                ))
+            };
+            let prod_id: i32 = prod_id.parse().map_err(|_| wrong_input())?;
+            let ids = meta
+                .value
+                .split(',')
+                .map(|id| id.trim().parse::<i32>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|_| wrong_input())?;
+            if ids.iter().enumerate().any(|(i, id)| ids[..i].contains(id)) {
+                return Err(wrong_input());
+            }
+            if !self
+                .data
+                .iter()
+                .any(|c| c.id() == prod_id && c.is_electricity() && c.is_onsite_pr())
+            {
+                return Err(EpbdError::WrongInput(format!(
+                    "El sistema {} con lista de prioridad ({}) no tiene producción eléctrica in situ",
+                    prod_id, meta.key
+                )));
+            }
+            priorities.insert(prod_id, ids);
+        }
+        Ok(priorities)
+    }
+
+    /// Producción de un vector imputada a servicios concretos, en cada paso de cálculo
+    ///
+    /// Se declara con la etiqueta `CTEEPBD_SERVICIO=<SERVICIO>` en el comentario del componente
+    /// de producción (p.e. `1, PRODUCCION, EL_INSITU, 10.0 # FV para ACS CTEEPBD_SERVICIO=ACS`).
+    /// Las producciones de un mismo servicio se suman.
+    ///
+    /// # Errors
+    ///
+    /// * Servicio desconocido o no EPB
+    pub fn dedicated_productions(&self, carrier: Carrier) -> Result<BTreeMap<Service, Vec<f32>>> {
+        let mut dedicated: BTreeMap<Service, Vec<f32>> = BTreeMap::new();
+        for c in self
+            .data
+            .iter()
+            .filter(|c| c.is_generated() && c.has_carrier(carrier))
+        {
+            let tags = c.tags();
+            let Some(srv) = tags.get_str("CTEEPBD_SERVICIO") else {
+                continue;
+            };
+            let service: Service = srv.parse()?;
+            if !service.is_epb() {
+                return Err(EpbdError::WrongInput(format!(
+                    "Producción imputada a un servicio no EPB ({}) en el componente: {}",
+                    srv, c
+                )));
+            }
+            dedicated
+                .entry(service)
+                .and_modify(|e| *e = vecvecsum(e, c.values()))
+                .or_insert_with(|| c.values().to_owned());
+        }
+        Ok(dedicated)
+    }
+
+    /// Sistemas y servicios cuyo rendimiento implícito supera el máximo plausible
+    ///
+    /// El rendimiento implícito es el cociente entre la energía entregada o absorbida (SALIDA, en
+    /// valor absoluto) y la consumida por el sistema para el servicio, excluidas la energía ambiente
+    /// y la solar térmica. Se compara con `tolerances.efficiency_max_hp` cuando el sistema consume
+    /// electricidad o energía ambiente para el servicio (bombas de calor) y con
+    /// `tolerances.efficiency_max_comb` en otro caso (calderas y otros sistemas de combustión).
+    ///
+    /// Un valor de SALIDA erróneo altera el reparto de los consumos auxiliares y los indicadores
+    /// que dependen de la energía entregada, por lo que conviene avisar de estos casos.
+    /// No se evalúan los sistemas sin consumo distinto de energía ambiente o solar térmica.
+    pub fn implausible_efficiencies(&self, tolerances: &Tolerances) -> Vec<ImplausibleEfficiency> {
+        let mut outputs: BTreeMap<(i32, Service), f32> = BTreeMap::new();
+        for c in self.data.iter().filter(|c| c.is_out()) {
+            *outputs.entry((c.id(), c.service())).or_default() +=
+                c.values().iter().map(|v| v.abs()).sum::<f32>();
+        }
+
+        let mut implausible = Vec::new();
+        for ((id, service), output) in outputs {
+            let used: Vec<_> = self
+                .data
+                .iter()
+                .filter(|c| c.is_used() && c.has_id(id) && c.has_service(service))
+                .collect();
+            let input: f32 = used
+                .iter()
+                .filter(|c| !c.carrier().is_onsite())
+                .map(|c| c.values_sum())
+                .sum();
+            if input <= tolerances.zero || output <= tolerances.zero {
+                continue;
+            }
+            let is_hp = used
+                .iter()
+                .any(|c| c.is_electricity() || c.carrier().is_ambient());
+            let limit = if is_hp {
+                tolerances.efficiency_max_hp
+            } else {
+                tolerances.efficiency_max_comb
+            };
+            let value = output / input;
+            if value > limit {
+                implausible.push(ImplausibleEfficiency {
+                    id,
+                    service,
+                    value,
+                    limit,
+                });
+            }
+        }
+        implausible
+    }
+
+    /// Factores de paso de usuario definidos con metadatos `CTE_FP` (repetibles)
+    ///
+    /// Cada metadato define un factor de paso con el formato de los archivos de factores de paso
+    /// (p.e. `CTE_FP: ELECTRICIDAD, RED, SUMINISTRO, A, 0.500, 1.500, 0.200`) y permite que el
+    /// archivo de componentes incluya los datos necesarios para reproducir el cálculo.
+    ///
+    /// # Errors
+    ///
+    /// * Formato incorrecto o factor repetido
+    pub fn user_wfactors(&self) -> Result<Vec<Factor>> {
+        let _user_carriers = UserCarrierScope::from_meta(&self.meta)?;
+        let mut factors: Vec<Factor> = Vec::new();
+        for meta in self.meta.iter().filter(|m| m.key == "CTE_FP") {
+            let factor: Factor = meta.value.parse().map_err(|_| {
+                EpbdError::WrongInput(format!(
+                    "Factor de paso incorrecto en el metadato CTE_FP: {}",
+                    meta.value
+                ))
+            })?;
+            if factors.iter().any(|f| {
+                f.carrier == factor.carrier
+                    && f.source == factor.source
+                    && f.dest == factor.dest
+                    && f.step == factor.step
+            }) {
+                return Err(EpbdError::WrongInput(format!(
+                    "Factor de paso repetido en el metadato CTE_FP: {}",
+                    meta.value
+                )));
+            }
+            factors.push(factor);
+        }
+        Ok(factors)
+    }
+
+    /// Energía embebida total (informativa), en valores anuales equivalentes
+    pub fn embodied_total(&self) -> RenNrenCo2 {
+        self.embodied
+            .iter()
+            .fold(RenNrenCo2::default(), |acc, e| acc + e.value)
+    }
+
+    /// Subconjunto de componentes de los servicios indicados
+    ///
+    /// Conserva los metadatos, los consumos, auxiliares y salidas de esos servicios,
+    /// las producciones de los sistemas (id) con algún componente retenido y las demandas
+    /// del edificio y de las zonas para esos servicios.
+    ///
+    /// Las producciones de sistemas sin consumos de esos servicios (p.e. una instalación
+    /// fotovoltaica definida con un id propio) no se conservan. Los datos de sistemas
+    /// (equipos, rendimientos, curvas, potencias pico y almacenamientos) se filtran igual
+    /// que en [`Components::filter_by_id`].
+    pub fn filter_by_service(&self, services: &[Service]) -> Components {
+        let ids: HashSet<i32> = self
+            .data
+            .iter()
+            .filter(|c| !c.is_generated() && services.contains(&c.service()))
+            .map(Energy::id)
+            .collect();
+        let mut needs = self.needs.clone();
+        needs.retain_services(services);
+        self.subset(
+            |c| {
+                if c.is_generated() {
+                    ids.contains(&c.id())
+                } else {
+                    services.contains(&c.service())
+                }
+            },
+            needs,
+        )
+    }
+
+    /// Subconjunto de componentes de los vectores energéticos indicados
+    ///
+    /// Conserva los metadatos, las demandas, los consumos (y auxiliares, de electricidad)
+    /// y producciones de esos vectores y las salidas de los sistemas (id) con algún consumo retenido.
+    /// Los datos de sistemas se filtran igual que en [`Components::filter_by_id`].
+    pub fn filter_by_carrier(&self, carriers: &[Carrier]) -> Components {
+        let ids: HashSet<i32> = self
+            .data
+            .iter()
+            .filter(|c| !c.is_out() && carriers.contains(&c.carrier()))
+            .map(Energy::id)
+            .collect();
+        self.subset(
+            |c| {
+                if c.is_out() {
+                    ids.contains(&c.id())
+                } else {
+                    carriers.contains(&c.carrier())
+                }
+            },
+            self.needs.clone(),
+        )
+    }
+
+    /// Componentes de energía duplicados
+    ///
+    /// Componentes de consumo, producción, auxiliares o de energía saliente cuya línea de
+    /// componentes (tipo, id, servicio, vector u origen, valores y comentario) aparece más de
+    /// una vez, en el orden de su primera aparición.
+    pub fn duplicates(&self) -> Vec<DuplicateComponent> {
+        let mut counts: Vec<DuplicateComponent> = Vec::new();
+        for line in self.data.iter().map(ToString::to_string) {
+            match counts.iter_mut().find(|d| d.component == line) {
+                Some(d) => d.count += 1,
+                None => counts.push(DuplicateComponent {
+                    component: line,
+                    count: 1,
+                }),
+            }
+        }
+        counts.retain(|d| d.count > 1);
+        counts
+    }
+
+    /// Valida los datos de componentes, con diagnósticos localizados por línea
+    ///
+    /// A diferencia de la interpretación de los componentes (`str::parse`), que se detiene en el
+    /// primer error, analiza todas las líneas y comprueba, además, la coherencia del conjunto:
+    /// valores no numéricos (NaN) o infinitos, distinto número de pasos de cálculo, SALIDA sin
+    /// CONSUMO del mismo sistema y servicio, AUX sin SALIDA en sistemas con varios servicios,
+    /// DEMANDA duplicada y producción por cogeneración sin CONSUMO para el servicio COGEN.
+    ///
+    /// Devuelve los diagnósticos en el orden de las líneas analizadas. Si no hay diagnósticos
+    /// de error, los datos pueden interpretarse con `str::parse`.
+    pub fn validate(s: &str) -> Vec<Diagnostic> {
+        let s_no_bom = s.strip_prefix('\u{feff}').unwrap_or(s);
+        let lines: Vec<(usize, &str)> = s_no_bom
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .map(|(i, l)| (i + 1, l))
+            .collect();
+        let mut diagnostics = Vec::new();
+
+        let mut cmeta: Vec<Meta> = Vec::new();
+        for &(n, line) in lines
+            .iter()
+            .filter(|(_, l)| l.starts_with("#META") || l.starts_with("#CTE_"))
+        {
+            match line.parse() {
+                Ok(meta) => cmeta.push(meta),
+                Err(e) => diagnostics.push(Diagnostic::error(Some(n), e.to_string())),
+            }
+        }
+        let declared = UserCarrier::from_meta(&cmeta).unwrap_or_else(|e| {
+            diagnostics.push(Diagnostic::error(None, e.to_string()));
+            Vec::new()
+        });
+        let _user_carriers = UserCarrierScope::new(declared);
+
+        let mut energy: Vec<(usize, Energy)> = Vec::new();
+        let mut needs: Vec<(usize, Needs)> = Vec::new();
+        let mut efficiencies: Vec<SystemEfficiency> = Vec::new();
+        let mut efficiency_curves: Vec<SystemEfficiencyCurve> = Vec::new();
+        for &(n, line) in lines
+            .iter()
+            .filter(|(_, l)| !(l.starts_with('#') || l.starts_with("vector,") || l.is_empty()))
+        {
+            let mut tags = line.splitn(3, ',').map(str::trim);
+            let (tag1, tag2) = (tags.next().unwrap_or(""), tags.next().unwrap_or(""));
+            let ctype = match tag1.parse::<CType>().or_else(|_| tag2.parse()) {
+                Ok(ctype) => ctype,
+                Err(_) => {
+                    diagnostics.push(Diagnostic::error(
+                        Some(n),
+                        format!("no se reconoce el tipo de componente en `{}`", line),
+                    ));
+                    continue;
+                }
+            };
+            let parsed = match ctype {
+                CType::CONSUMO => line.parse().map(|e| energy.push((n, Energy::Used(e)))),
+                CType::PRODUCCION => line.parse().map(|e| energy.push((n, Energy::Prod(e)))),
+                CType::AUX => line.parse().map(|e| energy.push((n, Energy::Aux(e)))),
+                CType::SALIDA => line.parse().map(|e| energy.push((n, Energy::Out(e)))),
+                CType::DEMANDA => line.parse().map(|d| needs.push((n, d))),
+                CType::EMBEBIDA => line.parse::<Embodied>().map(|_| ()),
+                CType::SISTEMA => match system_kind(line) {
+                    "POTENCIA_PICO" => line.parse::<SystemPeakPower>().map(|_| ()),
+                    "CURVA_RENDIMIENTO" => line.parse().map(|c| efficiency_curves.push(c)),
+                    "EQUIPO" => line.parse::<System>().map(|_| ()),
+                    _ => line.parse().map(|e| efficiencies.push(e)),
+                },
+                CType::ALMACENAMIENTO => line.parse::<Storage>().map(|_| ()),
+            };
+            if let Err(e) = parsed {
+                diagnostics.push(Diagnostic::error(Some(n), e.to_string()));
+            }
+        }
+
+        // Valores no numéricos o infinitos
+        let non_finite = energy
+            .iter()
+            .map(|(n, e)| (*n, e.values()))
+            .chain(needs.iter().map(|(n, d)| (*n, d.values.as_slice())))
+            .filter(|(_, values)| values.iter().any(|v| !v.is_finite()))
+            .map(|(n, _)| n);
+        for n in non_finite {
+            diagnostics.push(Diagnostic::error(
+                Some(n),
+                "valores no numéricos (NaN) o infinitos",
+            ));
+        }
+
+        // Número de pasos de cálculo
+        if let Some((_, first)) = energy.first() {
+            let num_steps = first.num_steps();
+            for (n, e) in energy.iter().filter(|(_, e)| e.num_steps() != num_steps) {
+                diagnostics.push(Diagnostic::error(
+                    Some(*n),
+                    format!(
+                        "número de pasos de cálculo ({}) distinto al del primer componente ({})",
+                        e.num_steps(),
+                        num_steps
+                    ),
+                ));
+            }
+        }
+
+        // SALIDA sin CONSUMO del mismo sistema y servicio
+        for (n, e) in energy.iter().filter(|(_, e)| e.is_out()) {
+            let has_use = energy
+                .iter()
+                .any(|(_, c)| c.is_used() && c.id() == e.id() && c.service() == e.service());
+            if !has_use {
+                diagnostics.push(Diagnostic::warning(
+                    Some(*n),
+                    format!(
+                        "SALIDA del sistema {} para el servicio {} sin CONSUMO del sistema para ese servicio",
+                        e.id(),
+                        e.service()
+                    ),
+                ));
+            }
+        }
+
+        // AUX sin servicio explícito y sin SALIDA para su reparto en sistemas sin un único servicio
+        for (n, e) in energy
+            .iter()
+            .filter(|(_, e)| e.is_aux() && e.service() == Service::NEPB)
+        {
+            let services: HashSet<_> = energy
+                .iter()
+                .filter(|(_, c)| c.is_used() && c.id() == e.id())
+                .map(|(_, c)| c.service())
+                .collect();
+            let has_out = energy.iter().any(|(_, c)| c.is_out() && c.id() == e.id())
+                || efficiencies.iter().any(|eff| eff.id == e.id())
+                || efficiency_curves.iter().any(|curve| curve.id == e.id());
+            if services.len() != 1 && !has_out {
+                diagnostics.push(Diagnostic::error(
+                    Some(*n),
+                    format!(
+                        "AUX del sistema {} sin SALIDA para hacer el reparto de los consumos auxiliares entre sus servicios",
+                        e.id()
+                    ),
+                ));
+            }
+        }
+
+        // DEMANDA duplicada
+        for (i, (n, d)) in needs.iter().enumerate() {
+            if let Some((first, _)) = needs[..i]
+                .iter()
+                .find(|(_, prev)| prev.id == d.id && prev.service == d.service)
+            {
+                diagnostics.push(Diagnostic::warning(
+                    Some(*n),
+                    format!(
+                        "DEMANDA duplicada de la zona {} para el servicio {} (ya definida en la línea {}). Se suman ambas demandas",
+                        d.id, d.service, first
+                    ),
+                ));
+            }
+        }
+
+        // Producción por cogeneración sin CONSUMO para el servicio COGEN
+        let has_cogen_use = energy.iter().any(|(_, c)| c.is_cogen_use());
+        for (n, e) in energy.iter().filter(|(_, e)| e.is_cogen_pr()) {
+            if !has_cogen_use {
+                diagnostics.push(Diagnostic::warning(
+                    Some(*n),
+                    format!(
+                        "PRODUCCION de {} sin CONSUMO para el servicio COGEN",
+                        e.prod_source()
+                    ),
+                ));
+            }
+        }
+
+        // Comprobaciones del conjunto (resolución, normalización...) sin errores previos
+        if !diagnostics.iter().any(Diagnostic::is_error) {
+            if let Err(e) = s.parse::<Components>() {
+                diagnostics.push(match e {
+                    EpbdError::Parse(e) => Diagnostic::error(Some(e.line), e.source.to_string()),
+                    e => Diagnostic::error(None, e.to_string()),
+                });
+            }
+        }
+
+        diagnostics.sort_by_key(|d| d.line.unwrap_or(usize::MAX));
+        diagnostics
+    }
+
+    /// Subconjunto de componentes de los sistemas (id) indicados
+    ///
+    /// Conserva los metadatos, las demandas y todos los componentes (consumos, auxiliares,
+    /// salidas y producciones) de esos sistemas.
+    ///
+    /// Los equipos y potencias pico se conservan para los sistemas retenidos, los rendimientos
+    /// y curvas de rendimiento solo para los servicios con salidas o consumos retenidos y los
+    /// almacenamientos solo si se conserva alguna producción eléctrica in situ, de modo que el
+    /// subconjunto pueda normalizarse y volver a leerse.
+    pub fn filter_by_id(&self, ids: &[i32]) -> Components {
+        self.subset(|c| ids.contains(&c.id()), self.needs.clone())
+    }
+
+    /// Subconjunto de componentes que cumplen la condición, con los metadatos y demandas indicadas
+    ///
+    /// Los equipos y potencias pico se conservan para los sistemas (id) con algún componente
+    /// retenido, los rendimientos y curvas de rendimiento para los pares (id, servicio) con
+    /// salidas o consumos (no de EAMBIENTE) retenidos y los almacenamientos solo si se conserva
+    /// alguna producción eléctrica in situ.
+    fn subset<F: Fn(&Energy) -> bool>(&self, keep: F, needs: BuildingNeeds) -> Components {
+        let data: Vec<Energy> = self.data.iter().filter(|c| keep(c)).cloned().collect();
+        let ids: HashSet<i32> = data.iter().map(Energy::id).collect();
+        let has_use = |id: i32, service: Service| {
+            data.iter().any(|c| {
+                c.has_id(id)
+                    && (c.is_out() || (c.is_used() && !c.carrier().is_ambient()))
+                    && c.service() == service
+            })
+        };
+        let has_el_production = data
+            .iter()
+            .any(|c| c.is_generated() && c.carrier() == Carrier::ELECTRICIDAD);
+        Components {
+            meta: self.meta.clone(),
+            needs,
+            embodied: self.embodied.clone(),
+            systems: self
+                .systems
+                .iter()
+                .filter(|s| ids.contains(&s.id))
+                .cloned()
+                .collect(),
+            efficiencies: self
+                .efficiencies
+                .iter()
+                .filter(|e| has_use(e.id, e.service))
+                .cloned()
+                .collect(),
+            efficiency_curves: self
+                .efficiency_curves
+                .iter()
+                .filter(|e| has_use(e.id, e.service))
+                .cloned()
+                .collect(),
+            peak_powers: self
+                .peak_powers
+                .iter()
+                .filter(|p| ids.contains(&p.id))
+                .cloned()
+                .collect(),
+            storages: if has_el_production {
+                self.storages.clone()
+            } else {
+                Vec::new()
+            },
+            warnings: self.warnings.clone(),
+            data,
+        }
+    }
+
     /// Corrige los componentes de consumo y producción
     ///
-    /// - Asegura que la energía EAMBIENTE consumida tiene su producción correspondiente
+    /// - Asegura que la energía EAMBIENTE (y sus subtipos) consumida tiene su producción correspondiente
     /// - Asegura que la energía TERMOSOLAR consumida tiene su producción correspondiente
+    /// - Genera la energía entregada (SALIDA) de los sistemas con rendimiento declarado
     /// - Reparte los consumos auxiliares proporcionalmente a los servicios
     ///
+    /// Los cambios realizados se registran como avisos informativos en `warnings`.
+    ///
     /// Los metadatos, servicios y coherencia de los vectores se aseguran ya en el parsing
     pub fn normalize(mut self) -> Result<Self> {
         // Compensa consumos no respaldados por producción
-        self.complete_produced_for_onsite_generated_use(Carrier::EAMBIENTE);
+        for carrier in Carrier::AMBIENT {
+            self.complete_produced_for_onsite_generated_use(carrier);
+        }
         self.complete_produced_for_onsite_generated_use(Carrier::TERMOSOLAR);
+        self.generate_out_from_efficiencies()?;
         self.assign_aux_nepb_to_epb_services()?;
         self.sort_by_id();
         Ok(self)
     }
 
+    /// Genera la energía entregada (SALIDA) de los sistemas a partir de su rendimiento declarado
+    ///
+    /// Para cada rendimiento declarado (SISTEMA, id, RENDIMIENTO, servicio, valor) se genera un
+    /// componente de energía entregada por el sistema para el servicio, E_out_t = η · E_in_t,
+    /// siendo E_in_t el consumo del sistema para el servicio, excluida la energía ambiente (EAMBIENTE).
+    ///
+    /// Si se ha declarado una curva de rendimiento a carga parcial para el sistema y servicio
+    /// (SISTEMA, id, CURVA_RENDIMIENTO, servicio, carga:rend...), esta prevalece sobre el rendimiento
+    /// medio estacional y el rendimiento de cada paso de cálculo depende de su fracción de carga,
+    /// E_out_t = η(E_out_t / (P · h_t)) · E_in_t, siendo P la potencia nominal del equipo del sistema
+    /// y h_t la duración del paso de cálculo.
+    ///
+    /// Si ya se ha declarado la energía entregada por el sistema para el servicio, esta prevalece.
+    ///
+    /// # Errors
+    ///
+    /// * Rendimiento o curva de rendimiento declarados más de una vez para el mismo sistema y servicio
+    /// * Rendimiento o curva de rendimiento declarados para un sistema y servicio sin consumos
+    /// * Curva de rendimiento de un sistema sin potencia nominal o con pasos de cálculo de duración desconocida
+    fn generate_out_from_efficiencies(&mut self) -> Result<()> {
+        let num_steps = self.num_steps();
+        let mut declared = HashSet::new();
+        for eff in &self.efficiencies {
+            if !declared.insert((eff.id, eff.service)) {
+                return Err(EpbdError::WrongInput(format!(
+                    "Rendimiento duplicado para el sistema {} y el servicio {}",
+                    eff.id, eff.service
+                )));
+            }
+        }
+        let mut with_curve = HashSet::new();
+        for curve in &self.efficiency_curves {
+            if !with_curve.insert((curve.id, curve.service)) {
+                return Err(EpbdError::WrongInput(format!(
+                    "Curva de rendimiento duplicada para el sistema {} y el servicio {}",
+                    curve.id, curve.service
+                )));
+            }
+        }
+        let mut keys = self
+            .efficiencies
+            .iter()
+            .map(|eff| (eff.id, eff.service))
+            .collect::<Vec<_>>();
+        keys.extend(
+            self.efficiency_curves
+                .iter()
+                .map(|curve| (curve.id, curve.service))
+                .filter(|key| !declared.contains(key)),
+        );
+
+        let mut generated = Vec::new();
+        for (id, service) in keys {
+            let has_out = self
+                .data
+                .iter()
+                .any(|c| c.is_out() && c.id() == id && c.service() == service);
+            if has_out {
+                continue;
+            }
+            let used: Vec<&[f32]> = self
+                .data
+                .iter()
+                .filter(|c| {
+                    c.is_used()
+                        && c.id() == id
+                        && c.service() == service
+                        && !c.carrier().is_ambient()
+                })
+                .map(|c| c.values())
+                .collect();
+            if used.is_empty() {
+                return Err(EpbdError::WrongInput(format!(
+                    "Rendimiento declarado para el sistema {} y el servicio {} sin consumos de energía",
+                    id, service
+                )));
+            }
+            let used = veclistsum(&used);
+            let curve = self
+                .efficiency_curves
+                .iter()
+                .find(|c| c.id == id && c.service == service);
+            let (values, description) = if let Some(curve) = curve {
+                let power = self.system(id).and_then(|s| s.power).ok_or_else(|| {
+                    EpbdError::WrongInput(format!(
+                        "Curva de rendimiento declarada para el sistema {} sin potencia nominal del equipo (SISTEMA, {}, EQUIPO, tipo, potencia)",
+                        id, id
+                    ))
+                })?;
+                let values = used
+                    .iter()
+                    .enumerate()
+                    .map(|(step, &e_in)| {
+                        let hours = timestep_hours(num_steps, step).ok_or_else(|| {
+                            EpbdError::WrongInput(format!(
+                                "Curva de rendimiento del sistema {} con pasos de cálculo de duración desconocida ({} pasos)",
+                                id, num_steps
+                            ))
+                        })?;
+                        Ok(curve.output(Kwh(e_in), power.energy(hours)).0)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                (
+                    values,
+                    "la curva de rendimiento a carga parcial".to_string(),
+                )
+            } else {
+                let eff = self
+                    .efficiencies
+                    .iter()
+                    .find(|e| e.id == id && e.service == service)
+                    .expect("rendimiento declarado");
+                let values = used.iter().map(|v| eff.value * v).collect::<Vec<_>>();
+                (
+                    values,
+                    format!("el rendimiento declarado ({:.3})", eff.value),
+                )
+            };
+            debug_assert_eq!(values.len(), num_steps);
+            self.warnings.push(
+                Warning::info(
+                    WarningCode::SALIDA_GENERADA,
+                    format!(
+                        "energía entregada por el sistema {} para el servicio {} calculada con {}",
+                        id, service, description
+                    ),
+                )
+                .with_component(format!("{}, SALIDA, {}", id, service)),
+            );
+            generated.push(Energy::Out(EOut {
+                id,
+                service,
+                values,
+                comment: format!("Energía entregada calculada con {}", description),
+            }));
+        }
+        self.data.extend(generated);
+        Ok(())
+    }
+
     /// Compensa los consumos declarados de energía insitu no equilibrada por producción
     ///
-    /// Afecta a los vectores EAMBIENTE y TERMOSOLAR
+    /// Afecta a los vectores EAMBIENTE (y sus subtipos) y TERMOSOLAR
     ///
     /// cuando el consumo de esos vectores supera la producción.
     /// Evita tener que declarar las producciones de EAMBIENTE y TERMOSOLAR, basta con los consumos.
@@ -210,9 +1322,12 @@ impl Components {
     /// 3) se genera una producción que completa las cantidades no cubiertas por la producción definida
     ///
     /// Las producciones declaradas para un sistema, que no se consuman, no se trasvasan a otros.
-    fn complete_produced_for_onsite_generated_use(&mut self, carrier: Carrier) {
+    pub(crate) fn complete_produced_for_onsite_generated_use(&mut self, carrier: Carrier) {
         let source = match carrier {
             Carrier::EAMBIENTE => ProdSource::EAMBIENTE,
+            Carrier::EAMBIENTE_AIRE => ProdSource::EAMBIENTE_AIRE,
+            Carrier::EAMBIENTE_GEO => ProdSource::EAMBIENTE_GEO,
+            Carrier::EAMBIENTE_AGUA => ProdSource::EAMBIENTE_AGUA,
             Carrier::TERMOSOLAR => ProdSource::TERMOSOLAR,
             _ => {
                 panic!("Intento de compensación de vector distinto de EAMBIENTE o TERMOSOLAR")
@@ -223,8 +1338,8 @@ impl Components {
         let env_comps: Vec<_> = self
             .data
             .iter()
+            .filter(|&c| c.has_carrier(carrier))
             .cloned()
-            .filter(|c| c.has_carrier(carrier))
             .collect();
         if env_comps.is_empty() {
             return;
@@ -266,6 +1381,18 @@ impl Components {
             };
 
             // Si hay desequilibrio agregamos un componente de producción
+            self.warnings.push(
+                Warning::info(
+                    WarningCode::PRODUCCION_GENERADA,
+                    format!(
+                        "producción de {} del sistema {} generada para equilibrar su consumo ({:.2} kWh)",
+                        carrier,
+                        id,
+                        unbalanced_use.iter().sum::<f32>()
+                    ),
+                )
+                .with_component(format!("{}, PRODUCCION, {}", id, source)),
+            );
             self.data.push(Energy::Prod(EProd {
                 id,
                 source,
@@ -277,8 +1404,9 @@ impl Components {
 
     /// Asigna servicios EPB a los componentes de energía auxiliar
     ///
-    /// Los componentes de consumos auxiliares se cargan inicialmente con el servicio NEPB
-    /// pero representan solo servicios EPB y debemos asignarlos.
+    /// Los componentes de consumos auxiliares sin servicio declarado se cargan inicialmente con el
+    /// servicio NEPB pero representan solo servicios EPB y debemos asignarlos. Los que declaran
+    /// explícitamente su servicio (p.e. `1, AUX, ACS, 5`) se mantienen y no intervienen en el reparto.
     ///
     /// Para hacer esta asignación se actúa sistema a sistema:
     /// 1) si solamente hay un servicio EPB se asigna el consumo Aux a ese servicio
@@ -286,12 +1414,18 @@ impl Components {
     ///    disponible y se asigna a cada servicio un consumo proporcional
     ///    a la energía saliente de cada servicio en relación a la total saliente
     ///    para todos los servicios EPB.
+    ///
+    /// La energía saliente se toma en valor absoluto, de modo que la energía absorbida
+    /// (valores negativos de SALIDA, p.e. en refrigeración con bombas de calor reversibles)
+    /// cuenta como energía suministrada por el sistema al servicio.
     fn assign_aux_nepb_to_epb_services(&mut self) -> Result<()> {
-        // ids with aux energy use
+        let is_unassigned_aux =
+            |c: &Energy| matches!(c, Energy::Aux(e) if e.service == Service::NEPB);
+        // ids with unassigned aux energy use
         let ids: HashSet<_> = self
             .data
             .iter()
-            .filter(|c| c.is_aux())
+            .filter(|c| is_unassigned_aux(c))
             .map(Energy::id)
             .collect();
         for id in ids {
@@ -310,7 +1444,7 @@ impl Components {
                 let service = *services_for_uses_with_id.iter().next().unwrap();
                 for c in &mut self.data {
                     if let Energy::Aux(e) = c {
-                        if e.id == id {
+                        if e.id == id && e.service == Service::NEPB {
                             e.service = service
                         }
                     }
@@ -325,28 +1459,32 @@ impl Components {
                     .data
                     .iter()
                     .filter_map(|c| match c {
-                        Energy::Aux(e) if e.id == id => Some(e.values()),
+                        Energy::Aux(e) if e.id == id && e.service == Service::NEPB => {
+                            Some(e.values())
+                        }
                         _ => None,
                     })
                     .collect::<Vec<_>>(),
             );
 
-            let mut q_out_by_srv: HashMap<Service, Vec<f32>> = HashMap::new();
+            let mut q_out_by_srv: BTreeMap<Service, Vec<f32>> = BTreeMap::new();
             for component in &self.data {
                 if let Energy::Out(e) = component {
                     if e.id == id {
                         q_out_by_srv
                             .entry(e.service)
                             .or_insert_with(|| vec![0.0; self.num_steps()]);
-                        q_out_by_srv
-                            .insert(e.service, vecvecsum(&q_out_by_srv[&e.service], &e.values));
+                        q_out_by_srv.insert(
+                            e.service,
+                            vecvecsum(&q_out_by_srv[&e.service], &e.abs_values()),
+                        );
                     }
                 };
             }
 
             let mut q_out_tot = vec![0.0; self.num_steps()];
             for q_out in q_out_by_srv.values() {
-                q_out_tot = vecvecsum(&*q_out_tot, q_out);
+                q_out_tot = vecvecsum(&q_out_tot, q_out);
             }
 
             if aux_tot.iter().sum::<f32>() > 0.0 && q_out_tot.iter().sum::<f32>() == 0.0 {
@@ -365,8 +1503,9 @@ impl Components {
                 q_out_frac_by_srv.insert(*service, values);
             }
 
-            // Elimina componentes de auxiliares existentes
-            self.data.retain(|c| !c.is_aux());
+            // Elimina componentes de auxiliares sin servicio declarado del sistema
+            self.data
+                .retain(|c| !(is_unassigned_aux(c) && c.id() == id));
 
             // Incorpora nuevos auxiliares con reparto calculado por servicios
             for service in &out_services {
@@ -382,6 +1521,21 @@ impl Components {
                     comment: "Reasignación automática de consumos auxiliares".into(),
                 }));
             }
+            self.warnings.push(
+                Warning::info(
+                    WarningCode::AUX_REPARTIDO,
+                    format!(
+                        "consumo auxiliar del sistema {} repartido entre los servicios {} según su energía entregada",
+                        id,
+                        out_services
+                            .iter()
+                            .map(Service::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                )
+                .with_component(format!("{}, AUX", id)),
+            );
         }
         Ok(())
     }
@@ -392,6 +1546,37 @@ impl Components {
     }
 }
 
+/// Lista de meses (1 a 12) a partir de meses o intervalos de meses separados por comas
+///
+/// Los intervalos pueden abarcar el cambio de año (p.e. `11-3` son los meses 11, 12, 1, 2 y 3).
+/// Devuelve `None` si algún mes no es válido.
+fn parse_months(value: &str) -> Option<Vec<u32>> {
+    let month = |m: &str| {
+        m.trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|m| (1..=12).contains(m))
+    };
+    let mut months = BTreeSet::new();
+    for item in value.split(',') {
+        match item.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (month(start)?, month(end)?);
+                let mut m = start;
+                months.insert(m);
+                while m != end {
+                    m = m % 12 + 1;
+                    months.insert(m);
+                }
+            }
+            None => {
+                months.insert(month(item)?);
+            }
+        }
+    }
+    Some(months.into_iter().collect())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -492,4 +1677,448 @@ mod tests {
         .parse::<Components>()
         .unwrap();
     }
+
+    /// Reparto de auxiliares de una bomba de calor reversible (CAL + REF), con energía
+    /// absorbida en refrigeración, y de un segundo sistema multiservicio
+    #[test]
+    fn tcomponents_aux_reversible_heat_pump() {
+        let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 10.0, 0.0
+            1, CONSUMO, REF, ELECTRICIDAD, 0.0, 10.0
+            1, SALIDA, CAL, 30.0, 0.0 # Energía entregada en calefacción
+            1, SALIDA, REF, 0.0, -20.0 # Energía absorbida en refrigeración
+            1, AUX, 2.0, 2.0
+            2, CONSUMO, CAL, GASNATURAL, 10.0, 10.0
+            2, CONSUMO, ACS, GASNATURAL, 10.0, 10.0
+            2, SALIDA, CAL, 9.0, 3.0
+            2, SALIDA, ACS, 3.0, 9.0
+            2, AUX, 1.0, 1.0"
+            .parse::<Components>()
+            .unwrap();
+        let aux = |id: i32, service: Service| -> Vec<f32> {
+            comps
+                .data
+                .iter()
+                .filter(|c| c.is_aux() && c.has_id(id) && c.has_service(service))
+                .flat_map(|c| c.values().to_vec())
+                .collect()
+        };
+        assert_eq!(aux(1, Service::CAL), vec![2.0, 0.0]);
+        assert_eq!(aux(1, Service::REF), vec![0.0, 2.0]);
+        assert_eq!(aux(2, Service::CAL), vec![0.75, 0.25]);
+        assert_eq!(aux(2, Service::ACS), vec![0.25, 0.75]);
+    }
+
+    /// Auxiliares con servicio declarado explícitamente: no se reparten y, sin otros auxiliares,
+    /// no requieren energía saliente en sistemas multiservicio
+    #[test]
+    fn tcomponents_aux_explicit_service() {
+        let comps = "1, CONSUMO, CAL, GASNATURAL, 10.0, 10.0
+            1, CONSUMO, ACS, GASNATURAL, 10.0, 10.0
+            1, SALIDA, CAL, 9.0, 3.0
+            1, SALIDA, ACS, 3.0, 9.0
+            1, AUX, ACS, 0.5, 0.5 # Bomba de recirculación de ACS
+            1, AUX, 1.0, 1.0
+            2, CONSUMO, CAL, GASNATURAL, 10.0, 10.0
+            2, CONSUMO, ACS, GASNATURAL, 10.0, 10.0
+            2, AUX, CAL, 1.0, 1.0"
+            .parse::<Components>()
+            .unwrap();
+        let aux = |id: i32, service: Service| -> Vec<Vec<f32>> {
+            comps
+                .data
+                .iter()
+                .filter(|c| c.is_aux() && c.has_id(id) && c.has_service(service))
+                .map(|c| c.values().to_vec())
+                .collect()
+        };
+        assert_eq!(aux(1, Service::CAL), vec![vec![0.75, 0.25]]);
+        assert_eq!(aux(1, Service::ACS), vec![vec![0.5, 0.5], vec![0.25, 0.75]]);
+        assert_eq!(aux(2, Service::CAL), vec![vec![1.0, 1.0]]);
+        assert!(aux(2, Service::ACS).is_empty());
+        // Roundtrip
+        assert_eq!(
+            comps.to_string().parse::<Components>().unwrap().to_string(),
+            comps.to_string()
+        );
+    }
+
+    #[test]
+    fn tcomponents_validate() {
+        let comps = "#META CTE_AREAREF: 100.0
+            1, CONSUMO, CAL, GASNATURAL, 10.0, 10.0
+            1, CONSUMO, ACS, GASNATURAL, 10.0, 10.0
+            1, AUX, 1.0, 1.0
+            2, CONSUMO, CAL, ELECTRICIDAD, 5.0, NaN
+            2, SALIDA, ACS, 3.0, 3.0
+            DEMANDA, CAL, 20.0, 10.0
+            DEMANDA, CAL, 20.0, 10.0
+            1, CONSUMO, CAL, GASNATURAL, 10.0, 10.0, 10.0
+            PRODUCCION, EL_COGEN, 2.0, 2.0
+            1, CONSUMO, CAL, CARBONO, 1.0, 1.0";
+        let diagnostics = Components::validate(comps);
+        let found: Vec<_> = diagnostics.iter().map(|d| (d.line, d.is_error())).collect();
+        assert_eq!(
+            found,
+            vec![
+                (Some(4), true),
+                (Some(5), true),
+                (Some(6), false),
+                (Some(8), false),
+                (Some(9), true),
+                (Some(10), false),
+                (Some(11), true),
+            ]
+        );
+        assert_eq!(
+            diagnostics[3].to_string(),
+            "AVISO: línea 8: DEMANDA duplicada de la zona 0 para el servicio CAL (ya definida en la línea 7). Se suman ambas demandas"
+        );
+
+        // Componentes correctos, sin diagnósticos
+        let comps = "1, CONSUMO, CAL, GASNATURAL, 10.0, 10.0
+            1, CONSUMO, ACS, GASNATURAL, 10.0, 10.0
+            1, SALIDA, CAL, 9.0, 3.0
+            1, SALIDA, ACS, 3.0, 9.0
+            1, AUX, 1.0, 1.0";
+        assert!(Components::validate(comps).is_empty());
+    }
+
+    #[test]
+    fn tcomponents_out_from_efficiencies() {
+        let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 10.0, 5.0
+            1, CONSUMO, CAL, EAMBIENTE, 20.0, 10.0
+            1, CONSUMO, ACS, ELECTRICIDAD, 5.0, 10.0
+            1, CONSUMO, ACS, EAMBIENTE, 7.5, 15.0
+            1, AUX, 3.0, 3.0
+            SISTEMA, 1, RENDIMIENTO, CAL, 3.0 # Bomba de calor
+            SISTEMA, 1, RENDIMIENTO, ACS, 2.5"
+            .parse::<Components>()
+            .unwrap();
+        let values = |id: i32, service: Service, out: bool| -> Vec<f32> {
+            comps
+                .data
+                .iter()
+                .filter(|c| {
+                    (if out { c.is_out() } else { c.is_aux() })
+                        && c.has_id(id)
+                        && c.has_service(service)
+                })
+                .flat_map(|c| c.values().to_vec())
+                .collect()
+        };
+        // SALIDA = η · consumo, sin EAMBIENTE
+        assert_eq!(values(1, Service::CAL, true), vec![30.0, 15.0]);
+        assert_eq!(values(1, Service::ACS, true), vec![12.5, 25.0]);
+        // Reparto de auxiliares con la energía entregada generada
+        assert_eq!(values(1, Service::CAL, false), vec![2.1176472, 1.125]);
+        assert_eq!(values(1, Service::ACS, false), vec![0.88235295, 1.875]);
+        // Roundtrip
+        assert!(comps
+            .to_string()
+            .contains("SISTEMA, 1, RENDIMIENTO, CAL, 3.000 # Bomba de calor"));
+
+        // La salida declarada prevalece sobre la calculada
+        let comps = "1, CONSUMO, CAL, GASNATURAL, 10.0
+            1, SALIDA, CAL, 8.0
+            SISTEMA, 1, RENDIMIENTO, CAL, 0.9"
+            .parse::<Components>()
+            .unwrap();
+        assert_eq!(comps.data.iter().filter(|c| c.is_out()).count(), 1);
+        assert_eq!(values_out_sum(&comps), 8.0);
+
+        // Rendimiento de un sistema sin consumos para el servicio o duplicado
+        assert!("1, CONSUMO, CAL, GASNATURAL, 10.0
+            SISTEMA, 1, RENDIMIENTO, ACS, 0.9"
+            .parse::<Components>()
+            .is_err());
+        assert!("1, CONSUMO, CAL, GASNATURAL, 10.0
+            SISTEMA, 1, RENDIMIENTO, CAL, 0.9
+            SISTEMA, 1, RENDIMIENTO, CAL, 0.8"
+            .parse::<Components>()
+            .is_err());
+    }
+
+    #[test]
+    fn tcomponents_out_from_efficiency_curves() {
+        // Enero (744 h) con 744 kWh de consumo y una potencia de 8 kW: capacidad de 5952 kWh
+        // η = 2 + 2 · carga -> E_out = 2 · E_in / (1 - 2 · E_in / capacidad) = 1984 kWh
+        let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 744.0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+            SISTEMA, 1, EQUIPO, BDC, 8.0
+            SISTEMA, 1, RENDIMIENTO, CAL, 3.0
+            SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.0:2.0, 1.0:4.0 # Bomba de calor"
+            .parse::<Components>()
+            .unwrap();
+        let out = comps.data.iter().find(|c| c.is_out()).unwrap();
+        assert_eq!(format!("{:.1}", out.values()[0]), "1984.0");
+        assert_eq!(out.values_sum(), out.values()[0]);
+        assert!(comps
+            .warnings
+            .iter()
+            .any(|w| w.code == WarningCode::SALIDA_GENERADA
+                && w.message.contains("curva de rendimiento")));
+        // Roundtrip
+        assert!(comps.to_string().contains(
+            "SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.00:2.000, 1.00:4.000 # Bomba de calor"
+        ));
+
+        // Curva sin potencia nominal del sistema, con pasos de duración desconocida o duplicada
+        assert!(
+            "1, CONSUMO, CAL, ELECTRICIDAD, 744.0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+            SISTEMA, 1, EQUIPO, BDC
+            SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.0:2.0, 1.0:4.0"
+                .parse::<Components>()
+                .is_err()
+        );
+        assert!("1, CONSUMO, CAL, ELECTRICIDAD, 10.0, 5.0
+            SISTEMA, 1, EQUIPO, BDC, 8.0
+            SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.0:2.0, 1.0:4.0"
+            .parse::<Components>()
+            .is_err());
+        assert!(
+            "1, CONSUMO, CAL, ELECTRICIDAD, 744.0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0
+            SISTEMA, 1, EQUIPO, BDC, 8.0
+            SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.0:2.0, 1.0:4.0
+            SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.0:2.0, 1.0:3.0"
+                .parse::<Components>()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn tcomponents_systems() {
+        let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 10.0, 5.0
+            1, CONSUMO, CAL, EAMBIENTE, 20.0, 10.0
+            SISTEMA, 1, EQUIPO, BDC, 8.0 # Bomba de calor aire-agua
+            SISTEMA, 1, RENDIMIENTO, CAL, 3.0
+            SISTEMA, 2, POTENCIA_PICO, 3.0 # EQUIPO fotovoltaico"
+            .parse::<Components>()
+            .unwrap();
+        assert_eq!(comps.systems.len(), 1);
+        assert_eq!(comps.efficiencies.len(), 1);
+        assert_eq!(comps.peak_powers.len(), 1);
+        let system = comps.system(1).unwrap();
+        assert_eq!(system.kind, "BDC");
+        assert_eq!(system.power, Some(crate::types::Kw(8.0)));
+        assert!(comps.system(2).is_none());
+        // Roundtrip
+        assert!(comps
+            .to_string()
+            .contains("SISTEMA, 1, EQUIPO, BDC, 8.000 # Bomba de calor aire-agua"));
+
+        // Equipo duplicado
+        assert!("1, CONSUMO, CAL, GASNATURAL, 10.0
+            SISTEMA, 1, EQUIPO, CALDERA
+            SISTEMA, 1, EQUIPO, CALDERA, 24.0"
+            .parse::<Components>()
+            .is_err());
+    }
+
+    fn values_out_sum(comps: &Components) -> f32 {
+        comps
+            .data
+            .iter()
+            .filter(|c| c.is_out())
+            .map(|c| c.values_sum())
+            .sum()
+    }
+
+    /// Subconjuntos de componentes por servicio, vector y sistema
+    #[test]
+    fn tcomponents_filter() {
+        let comps = "#META CTE_AREAREF: 1.0
+            DEMANDA, CAL, 30.0
+            DEMANDA, ACS, 10.0
+            1, PRODUCCION, EL_INSITU, 5.0 # PV del sistema 1
+            1, CONSUMO, CAL, ELECTRICIDAD, 10.0 # BdC
+            1, CONSUMO, CAL, EAMBIENTE, 20.0 # BdC
+            1, SALIDA, CAL, 30.0
+            2, CONSUMO, ACS, GASNATURAL, 12.0 # Caldera
+            2, AUX, 1.0 # Auxiliares caldera
+            3, PRODUCCION, EL_INSITU, 2.0 # PV independiente"
+            .parse::<Components>()
+            .unwrap();
+
+        // Por servicio: la producción del sistema 1 (incluida la de EAMBIENTE) se conserva
+        let cal = comps.filter_by_service(&[Service::CAL]);
+        assert_eq!(cal.data.len(), 5);
+        assert!(cal.data.iter().all(|c| c.has_id(1)));
+        assert!(cal.needs.CAL.is_some() && cal.needs.ACS.is_none());
+        assert_eq!(cal.get_meta("CTE_AREAREF"), Some("1.0".to_string()));
+
+        // Por vector: auxiliares como electricidad y salidas de sistemas con consumos retenidos
+        let el = comps.filter_by_carrier(&[Carrier::ELECTRICIDAD]);
+        assert_eq!(el.data.len(), 5);
+        assert!(el.needs.ACS.is_some());
+        assert!(el.data.iter().any(|c| c.is_aux() && c.has_id(2)));
+        assert!(el.data.iter().any(|c| c.is_out() && c.has_id(1)));
+
+        // Por sistema
+        let sys = comps.filter_by_id(&[2, 3]);
+        assert_eq!(sys.data.len(), 3);
+    }
+
+    /// Los subconjuntos filtran los datos de sistemas y pueden normalizarse y volver a leerse
+    #[test]
+    fn tcomponents_filter_roundtrip() {
+        let comps = "#META CTE_AREAREF: 1.0
+            DEMANDA, CAL, 30.0
+            DEMANDA, ACS, 10.0
+            1, CONSUMO, CAL, ELECTRICIDAD, 10.0 # BdC
+            1, CONSUMO, CAL, EAMBIENTE, 20.0 # BdC
+            1, CONSUMO, ACS, GASNATURAL, 12.0 # Caldera mixta
+            4, CONSUMO, CAL, GASNATURAL, 5.0 # Caldera de apoyo
+            SISTEMA, 1, EQUIPO, BDC, 8.0
+            SISTEMA, 1, RENDIMIENTO, CAL, 3.0
+            SISTEMA, 1, RENDIMIENTO, ACS, 0.9
+            SISTEMA, 4, RENDIMIENTO, CAL, 0.92
+            SISTEMA, 2, POTENCIA_PICO, 3.0
+            2, PRODUCCION, EL_INSITU, 5.0 # PV
+            ALMACENAMIENTO, 3, 10.00, 0.950, 0.950, 0.0010 # Batería"
+            .parse::<Components>()
+            .unwrap();
+
+        let roundtrip = |c: Components| {
+            c.to_string()
+                .parse::<Components>()
+                .unwrap()
+                .normalize()
+                .unwrap()
+        };
+
+        let acs = comps.filter_by_service(&[Service::ACS]);
+        assert_eq!(acs.efficiencies.len(), 1);
+        assert_eq!(acs.efficiencies[0].service, Service::ACS);
+        assert_eq!(acs.efficiencies[0].id, 1);
+        assert_eq!(acs.systems.len(), 1);
+        assert!(acs.peak_powers.is_empty());
+        assert!(acs.storages.is_empty());
+        roundtrip(acs);
+
+        // Se conservan las salidas (y rendimientos) de los sistemas con consumos eléctricos
+        let el = comps.filter_by_carrier(&[Carrier::ELECTRICIDAD]);
+        assert_eq!(el.efficiencies.len(), 2);
+        assert!(el.efficiencies.iter().all(|e| e.id == 1));
+        assert_eq!(el.peak_powers.len(), 1);
+        assert_eq!(el.storages.len(), 1);
+        roundtrip(el);
+
+        let gas = comps.filter_by_carrier(&[Carrier::GASNATURAL]);
+        assert_eq!(gas.efficiencies.len(), 3);
+        assert!(gas.peak_powers.is_empty() && gas.storages.is_empty());
+        roundtrip(gas);
+
+        let pv = comps.filter_by_id(&[2]);
+        assert!(pv.efficiencies.is_empty() && pv.systems.is_empty());
+        assert_eq!(pv.peak_powers.len(), 1);
+        assert_eq!(pv.storages.len(), 1);
+        roundtrip(pv);
+
+        let sys1 = comps.filter_by_id(&[1]);
+        assert_eq!(sys1.efficiencies.len(), 2);
+        assert_eq!(sys1.systems.len(), 1);
+        assert!(sys1.peak_powers.is_empty() && sys1.storages.is_empty());
+        roundtrip(sys1);
+    }
+
+    #[test]
+    fn tcomponents_implausible_efficiencies() {
+        let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 10.0, 5.0
+            1, CONSUMO, CAL, EAMBIENTE, 20.0, 10.0
+            1, SALIDA, CAL, 30.0, 15.0
+            1, CONSUMO, REF, ELECTRICIDAD, 1.0, 1.0
+            1, SALIDA, REF, -10.0, -10.0 # EER = 10
+            2, CONSUMO, ACS, GASNATURAL, 10.0, 10.0
+            2, SALIDA, ACS, 11.0, 11.0 # Rendimiento = 1.1
+            3, CONSUMO, ACS, TERMOSOLAR, 5.0, 5.0
+            3, SALIDA, ACS, 5.0, 5.0"
+            .parse::<Components>()
+            .unwrap();
+
+        let implausible = comps.implausible_efficiencies(&Tolerances::default());
+        assert_eq!(implausible.len(), 2);
+        assert_eq!(
+            (implausible[0].id, implausible[0].service),
+            (1, Service::REF)
+        );
+        assert_eq!(format!("{:.2}", implausible[0].value), "10.00");
+        assert_eq!(implausible[0].limit, 8.0);
+        assert_eq!(
+            (implausible[1].id, implausible[1].service),
+            (2, Service::ACS)
+        );
+        assert_eq!(implausible[1].limit, 1.05);
+
+        // Umbrales configurables
+        let tolerances = Tolerances {
+            efficiency_max_hp: 12.0,
+            efficiency_max_comb: 1.2,
+            ..Default::default()
+        };
+        assert!(comps.implausible_efficiencies(&tolerances).is_empty());
+    }
+
+    #[test]
+    fn tcomponents_user_wfactors() {
+        let comps = "#META CTE_FP: GASOLEO, RED, SUMINISTRO, A, 0.000, 2.000, 0.500 # Gasóleo
+            #META CTE_FP: RED1, RED, SUMINISTRO, A, 0.100, 0.900, 0.200
+            1, CONSUMO, CAL, GASOLEO, 100"
+            .parse::<Components>()
+            .unwrap();
+        let factors = comps.user_wfactors().unwrap();
+        assert_eq!(factors.len(), 2);
+        assert_eq!(
+            factors[0].to_string(),
+            "GASOLEO, RED, SUMINISTRO, A, 0.000, 2.000, 0.500 # Gasóleo"
+        );
+
+        // Factores repetidos o con formato incorrecto
+        let comps = "#META CTE_FP: GASOLEO, RED, SUMINISTRO, A, 0.000, 2.000, 0.500
+            #META CTE_FP: GASOLEO, RED, SUMINISTRO, A, 0.000, 1.000, 0.500
+            1, CONSUMO, CAL, GASOLEO, 100"
+            .parse::<Components>()
+            .unwrap();
+        assert!(comps.user_wfactors().is_err());
+        let comps = "#META CTE_FP: GASOLEO, RED, SUMINISTRO, A, 2.000
+            1, CONSUMO, CAL, GASOLEO, 100"
+            .parse::<Components>()
+            .unwrap();
+        assert!(comps.user_wfactors().is_err());
+
+        // Vectores de usuario declarados en los propios componentes
+        let comps = "#META CTE_VECTORES_USUARIO: BIOGAS_LOCAL
+            #META CTE_FP: BIOGAS_LOCAL, RED, SUMINISTRO, A, 0.900, 0.100, 0.050
+            #META CTE_KEXP_BIOGAS_LOCAL: 0.5
+            1, CONSUMO, CAL, BIOGAS_LOCAL, 100"
+            .parse::<Components>()
+            .unwrap();
+        let factors = comps.user_wfactors().unwrap();
+        assert_eq!(factors[0].carrier.to_string(), "BIOGAS_LOCAL");
+        assert_eq!(comps.kexp_by_carrier().unwrap().len(), 1);
+        // ... y no declarados
+        let comps = "#META CTE_FP: BIOGAS_LOCAL, RED, SUMINISTRO, A, 0.900, 0.100, 0.050
+            1, CONSUMO, CAL, GASOLEO, 100"
+            .parse::<Components>()
+            .unwrap();
+        assert!(comps.user_wfactors().is_err());
+    }
+
+    #[test]
+    fn tcomponents_seasons() {
+        let comps = "#META CTE_TEMPORADA_CAL: 11-3
+            #META CTE_TEMPORADA_REF: 6, 7, 8-9
+            1, CONSUMO, CAL, ELECTRICIDAD, 10.0"
+            .parse::<Components>()
+            .unwrap();
+        let seasons = comps.seasons().unwrap();
+        assert_eq!(seasons[&Service::CAL], vec![1, 2, 3, 11, 12]);
+        assert_eq!(seasons[&Service::REF], vec![6, 7, 8, 9]);
+
+        // Meses fuera de rango o servicios no EPB
+        for meta in ["CTE_TEMPORADA_CAL: 0-3", "CTE_TEMPORADA_NEPB: 1, 2"] {
+            let comps = format!("#META {}\n1, CONSUMO, CAL, ELECTRICIDAD, 10.0", meta)
+                .parse::<Components>()
+                .unwrap();
+            assert!(comps.seasons().is_err());
+        }
+    }
 }
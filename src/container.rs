@@ -0,0 +1,168 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Contenedor de datos
+===================
+
+Formato de archivo único con los componentes energéticos y los factores de paso usados en el cálculo,
+de modo que ambos viajan siempre juntos (p.e. para intercambiar o archivar un caso).
+
+El archivo contenedor tiene una sección `[COMPONENTES]` y una sección `[FACTORES]`, cada una con el
+contenido de un archivo de componentes energéticos o de factores de paso, respectivamente:
+
+```text
+[COMPONENTES]
+#META CTE_AREAREF: 100.0
+CONSUMO, CAL, ELECTRICIDAD, 10.0
+[FACTORES]
+ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+```
+
+Los encabezados de sección deben ocupar una línea completa y antes de la primera sección solo
+pueden aparecer líneas en blanco o comentarios.
+*/
+
+use std::{fmt, str};
+
+use crate::{
+    error::{EpbdError, Result},
+    Components, Factors,
+};
+
+/// Encabezado de la sección de componentes energéticos del archivo contenedor
+pub const CONTAINER_COMPONENTS_SECTION: &str = "[COMPONENTES]";
+/// Encabezado de la sección de factores de paso del archivo contenedor
+pub const CONTAINER_WFACTORS_SECTION: &str = "[FACTORES]";
+
+/// Contenedor de los componentes energéticos y los factores de paso de un caso de cálculo
+#[derive(Debug, Clone)]
+pub struct DataContainer {
+    /// Componentes energéticos
+    pub components: Components,
+    /// Factores de paso
+    pub wfactors: Factors,
+}
+
+impl str::FromStr for DataContainer {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<DataContainer> {
+        let headers = [CONTAINER_COMPONENTS_SECTION, CONTAINER_WFACTORS_SECTION];
+        // Líneas de cada sección y sección en curso
+        let mut sections: [Option<Vec<&str>>; 2] = [None, None];
+        let mut current = None;
+        for line in s.lines() {
+            let trimmed = line.trim();
+            if let Some(idx) = headers.iter().position(|h| *h == trimmed) {
+                if sections[idx].is_some() {
+                    return Err(EpbdError::ParseError(format!(
+                        "sección {} duplicada en el archivo contenedor",
+                        trimmed
+                    )));
+                }
+                sections[idx] = Some(Vec::new());
+                current = Some(idx);
+                continue;
+            }
+            match current {
+                Some(idx) => sections[idx].get_or_insert_with(Vec::new).push(line),
+                None if trimmed.is_empty() || trimmed.starts_with('#') => (),
+                None => {
+                    return Err(EpbdError::ParseError(format!(
+                        "contenido fuera de las secciones {} y {} del archivo contenedor: \"{}\"",
+                        CONTAINER_COMPONENTS_SECTION, CONTAINER_WFACTORS_SECTION, line
+                    )))
+                }
+            }
+        }
+        let [components, wfactors] = sections;
+        let missing = |header: &str| {
+            EpbdError::ParseError(format!(
+                "falta la sección {} en el archivo contenedor",
+                header
+            ))
+        };
+        let components = components.ok_or_else(|| missing(CONTAINER_COMPONENTS_SECTION))?;
+        let wfactors = wfactors.ok_or_else(|| missing(CONTAINER_WFACTORS_SECTION))?;
+        Ok(DataContainer {
+            components: components.join("\n").parse()?,
+            wfactors: wfactors.join("\n").parse()?,
+        })
+    }
+}
+
+impl fmt::Display for DataContainer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", CONTAINER_COMPONENTS_SECTION)?;
+        writeln!(f, "{}", self.components.to_string().trim())?;
+        writeln!(f, "{}", CONTAINER_WFACTORS_SECTION)?;
+        writeln!(f, "{}", self.wfactors.to_string().trim())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::MetaVec;
+
+    const TCONTAINER: &str = "# Caso de prueba
+[COMPONENTES]
+#META CTE_AREAREF: 100.0
+CONSUMO, CAL, ELECTRICIDAD, 10.00
+[FACTORES]
+#META CTE_FUENTE: RITE2014
+ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+";
+
+    #[test]
+    fn container_roundtrip() {
+        let container = TCONTAINER.parse::<DataContainer>().unwrap();
+        assert_eq!(container.components.data.len(), 1);
+        assert_eq!(
+            container.components.get_meta("CTE_AREAREF"),
+            Some("100.0".to_string())
+        );
+        assert_eq!(container.wfactors.wdata.len(), 1);
+        let written = container.to_string();
+        assert!(written.starts_with("[COMPONENTES]\n#META CTE_AREAREF: 100.0\n"));
+        let reread = written.parse::<DataContainer>().unwrap();
+        assert_eq!(reread.to_string(), written);
+    }
+
+    #[test]
+    fn container_errors() {
+        for s in [
+            // Falta la sección de factores
+            "[COMPONENTES]\nCONSUMO, CAL, ELECTRICIDAD, 10.0",
+            // Sección duplicada
+            "[COMPONENTES]\n[FACTORES]\n[COMPONENTES]\n",
+            // Contenido fuera de las secciones
+            "CONSUMO, CAL, ELECTRICIDAD, 10.0\n[COMPONENTES]\n[FACTORES]\n",
+        ] {
+            assert!(s.parse::<DataContainer>().is_err());
+        }
+    }
+}
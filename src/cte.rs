@@ -30,17 +30,28 @@ Utilidades para el cumplimiento reglamentario (compliance utilities)
 Utilidades para el manejo de balances energéticos para el CTE:
 
 - valores reglamentarios
+- uso del edificio (CTE_USO_EDIFICIO) y servicios admitidos en el balance
 - generación y transformación de factores de paso
+    - tablas reglamentarias por versión normativa (CTE_WF_VERSIONS)
     - wfactors_from_str
     - wfactors_from_loc
+    - wfactors_from_loc_version
+- indicadores adicionales
+    - fracción renovable de la demanda de ACS en el perímetro próximo
+    - cobertura renovable in situ de VEN e ILU
+    - compensación simplificada de excedentes
+    - RER según la Directiva (UE) con bombas de calor
+    - energía renovable de bombas de calor para calefacción (Decisión 2013/114/UE)
 */
 
 use once_cell::sync::Lazy;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-use crate::{error::EpbdError, types::*, Factors, UserWF};
+use serde::{Deserialize, Serialize};
 
-/**
+use crate::{error::EpbdError, types::*, vecops::vecvecsum_assign, Components, Factors, UserWF};
+
+/*
 Constantes y valores generales
 */
 
@@ -48,17 +59,159 @@ Constantes y valores generales
 pub const AREAREF_DEFAULT: f32 = 1.0;
 /// Valor predefinido del factor de exportación. Valor reglamentario.
 pub const KEXP_DEFAULT: f32 = 0.0;
+/// Valor predefinido del factor de compensación simplificada de excedentes (sin compensación energética)
+pub const KCOMP_DEFAULT: f32 = 0.0;
 /// Localizaciones válidas para CTE
 pub const CTE_LOCS: [&str; 4] = ["PENINSULA", "BALEARES", "CANARIAS", "CEUTAMELILLA"];
 
 // Valores bien conocidos de metadatos:
 // CTE_LOCALIZACION -> str
+// CTE_USO_EDIFICIO -> RESIDENCIAL | TERCIARIO
+// CTE_ZONA_CLIMATICA -> str (p.e. D3, alfa3)
+// CTE_CFI -> f32 (carga interna media, W/m2)
+
+/// Uso del edificio a efectos del DB-HE (metadato CTE_USO_EDIFICIO)
+///
+/// Determina los servicios EPB admitidos en el balance: en uso residencial privado
+/// no se considera el servicio de iluminación (ILU).
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UsoEdificio {
+    /// Uso residencial privado
+    RESIDENCIAL,
+    /// Uso distinto del residencial privado
+    TERCIARIO,
+}
+
+impl UsoEdificio {
+    /// Servicios EPB admitidos en el balance para el uso del edificio
+    pub fn servicios_epb(&self) -> &'static [Service] {
+        use Service::*;
+        match self {
+            UsoEdificio::RESIDENCIAL => &[ACS, CAL, REF, VEN],
+            UsoEdificio::TERCIARIO => &[ACS, CAL, REF, VEN, ILU],
+        }
+    }
+
+    /// Valores límite de consumo de energía primaria no renovable y total (C_ep,nren,lim, C_ep,tot,lim)
+    ///
+    /// Valores para edificios de nueva construcción del DB-HE 2019 (HE0), según la zona climática
+    /// de invierno (alfa, A, B, C, D, E) y, para uso terciario, la carga interna media CFI [W/m2].
+    /// Valores en kWh/m2.an.
+    pub fn limites_he0(&self, zona_invierno: &str, cfi: f32) -> Result<(f32, f32), EpbdError> {
+        let idx = match zona_invierno.trim().to_uppercase().as_str() {
+            "ALFA" | "Α" => 0,
+            "A" => 1,
+            "B" => 2,
+            "C" => 3,
+            "D" => 4,
+            "E" => 5,
+            _ => {
+                return Err(EpbdError::WrongInput(format!(
+                    "Zona climática de invierno desconocida: {}",
+                    zona_invierno
+                )))
+            }
+        };
+        match self {
+            UsoEdificio::RESIDENCIAL => {
+                const NREN: [f32; 6] = [20.0, 25.0, 28.0, 32.0, 38.0, 43.0];
+                const TOT: [f32; 6] = [40.0, 50.0, 56.0, 64.0, 76.0, 86.0];
+                Ok((NREN[idx], TOT[idx]))
+            }
+            UsoEdificio::TERCIARIO => {
+                const NREN: [f32; 6] = [70.0, 55.0, 50.0, 35.0, 20.0, 10.0];
+                const TOT: [f32; 6] = [165.0, 155.0, 150.0, 140.0, 130.0, 120.0];
+                Ok((NREN[idx] + 8.0 * cfi, TOT[idx] + 9.0 * cfi))
+            }
+        }
+    }
+
+    /// Descripción del uso del edificio para los informes
+    pub fn desc(&self) -> &'static str {
+        match self {
+            UsoEdificio::RESIDENCIAL => "residencial privado",
+            UsoEdificio::TERCIARIO => "terciario (distinto del residencial privado)",
+        }
+    }
+}
+
+impl std::fmt::Display for UsoEdificio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::str::FromStr for UsoEdificio {
+    type Err = EpbdError;
 
-/// Factores de paso definibles por el usuario usados por defecto
-pub const CTE_USERWF: UserWF<RenNrenCo2> = UserWF {
-    red1: RenNrenCo2::new(0.0, 1.3, 0.3),
-    red2: RenNrenCo2::new(0.0, 1.3, 0.3),
-};
+    fn from_str(s: &str) -> Result<UsoEdificio, Self::Err> {
+        match s.trim().to_uppercase().as_str() {
+            "RESIDENCIAL" => Ok(UsoEdificio::RESIDENCIAL),
+            "TERCIARIO" => Ok(UsoEdificio::TERCIARIO),
+            _ => Err(EpbdError::ParseError(format!(
+                "Uso del edificio desconocido: {} (RESIDENCIAL o TERCIARIO)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Uso del edificio definido en los metadatos de los componentes (CTE_USO_EDIFICIO)
+///
+/// Devuelve None si no se ha definido el metadato
+pub fn uso_edificio(components: &Components) -> Result<Option<UsoEdificio>, EpbdError> {
+    components
+        .get_meta("CTE_USO_EDIFICIO")
+        .map(|uso| uso.parse())
+        .transpose()
+}
+
+/// Zona climática de invierno a partir del metadato CTE_ZONA_CLIMATICA (p.e. D3 -> D, alfa3 -> alfa)
+///
+/// Devuelve None si no se ha definido el metadato
+pub fn zona_climatica_invierno(components: &Components) -> Option<String> {
+    components.get_meta("CTE_ZONA_CLIMATICA").map(|zona| {
+        zona.trim()
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .to_string()
+    })
+}
+
+/// Comprueba que los consumos EPB corresponden a servicios admitidos para el uso del edificio
+///
+/// No se comprueban los consumos de servicios no EPB por defecto (VE, APP) etiquetados como consumos EPB.
+///
+/// # Errors
+///
+/// * Consumos de servicios EPB no admitidos para el uso del edificio (p.e. ILU en uso residencial privado)
+pub fn valida_servicios_uso_edificio(
+    components: &Components,
+    uso: UsoEdificio,
+) -> Result<(), EpbdError> {
+    let admitidos = uso.servicios_epb();
+    let mut no_admitidos = components
+        .data
+        .iter()
+        .filter(|c| {
+            c.is_epb_use() && !c.service().is_optional_epb() && !admitidos.contains(&c.service())
+        })
+        .map(|c| format!("{} (id:{})", c.service(), c.id()))
+        .collect::<Vec<_>>();
+    if no_admitidos.is_empty() {
+        return Ok(());
+    }
+    no_admitidos.sort();
+    no_admitidos.dedup();
+    Err(EpbdError::WrongInput(format!(
+        "Consumos de servicios no admitidos para el uso {} del edificio: {}",
+        uso.desc(),
+        no_admitidos.join(", ")
+    )))
+}
+
+/// Factores de paso definibles por el usuario usados por defecto (ver [`UserWF::cte_defaults`])
+pub const CTE_USERWF: UserWF<RenNrenCo2> = UserWF::cte_defaults();
 
 /// Factores de paso reglamentarios según el documento reconocido del RITE (20/07/2014)
 ///
@@ -89,7 +242,9 @@ pub static CTE_LOCWF_RITE2014: Lazy<HashMap<&'static str, Factors>> = Lazy::new(
             Factor::new(GASOLEO, RED, SUMINISTRO, A, (0.003, 1.179, 0.311).into(), "Recursos usados para suministrar el vector desde la red"),
             Factor::new(GLP, RED, SUMINISTRO, A, (0.003, 1.201, 0.254).into(), "Recursos usados para suministrar el vector desde la red"),
             Factor::new(ELECTRICIDAD, INSITU, SUMINISTRO, A, (1.000, 0.000, 0.000).into(), "Recursos usados para producir electricidad in situ"),
-        ]};
+        ],
+        ..Default::default()
+    };
     let mut wfpen = wf.clone();
     wfpen.set_meta("CTE_LOCALIZACION", "PENINSULA");
     wfpen.wdata.push(Factor::new(
@@ -143,7 +298,54 @@ pub static CTE_LOCWF_RITE2014: Lazy<HashMap<&'static str, Factors>> = Lazy::new(
     m
 });
 
-/**
+/// Versión de las tablas reglamentarias de factores de paso
+///
+/// Permite recalcular expedientes antiguos con los factores de paso vigentes en su fecha
+#[derive(Debug)]
+pub struct CteWfVersion {
+    /// Identificador de la versión (p.e. RITE2014). Coincide con el metadato CTE_FUENTE de los factores
+    pub id: &'static str,
+    /// Descripción del documento de origen de los factores
+    pub desc: &'static str,
+    /// Fecha de inicio de vigencia (AAAA-MM-DD)
+    pub valid_from: &'static str,
+    /// Fecha de fin de vigencia (AAAA-MM-DD), None si sigue vigente
+    pub valid_to: Option<&'static str>,
+    /// Factores de paso por localización
+    pub locwf: &'static Lazy<HashMap<&'static str, Factors>>,
+}
+
+/// Versión de las tablas de factores de paso usada por defecto
+pub const CTE_WF_VERSION_DEFAULT: &str = "RITE2014";
+
+/// Versiones disponibles de las tablas reglamentarias de factores de paso, por orden cronológico
+pub static CTE_WF_VERSIONS: [CteWfVersion; 1] = [CteWfVersion {
+    id: "RITE2014",
+    desc: "Documento reconocido del RITE de 20/07/2014 (DB-HE 2013, DB-HE 2018)",
+    valid_from: "2014-07-20",
+    valid_to: None,
+    locwf: &CTE_LOCWF_RITE2014,
+}];
+
+/// Localiza una versión de las tablas reglamentarias de factores de paso por su identificador
+pub fn cte_wf_version(version: &str) -> Result<&'static CteWfVersion, EpbdError> {
+    CTE_WF_VERSIONS
+        .iter()
+        .find(|v| v.id.eq_ignore_ascii_case(version.trim()))
+        .ok_or_else(|| {
+            EpbdError::ParseError(format!(
+                "Versión de factores de paso desconocida: {} (disponibles: {})",
+                version,
+                CTE_WF_VERSIONS
+                    .iter()
+                    .map(|v| v.id)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+}
+
+/*
 Manejo de factores de paso para el CTE
 --------------------------------------
 
@@ -180,6 +382,92 @@ pub fn wfactors_from_loc(
         .normalize(&userdefaults)
 }
 
+/// Genera factores de paso a partir de localización y versión de las tablas reglamentarias.
+///
+/// Usa la versión indicada (p.e. RITE2014) de los factores de paso por localización
+pub fn wfactors_from_loc_version(
+    loc: &str,
+    version: &str,
+    user: UserWF<Option<RenNrenCo2>>,
+    userdefaults: UserWF<RenNrenCo2>,
+) -> Result<Factors, EpbdError> {
+    let locwf = cte_wf_version(version)?.locwf;
+    wfactors_from_loc(loc, locwf, user, userdefaults)
+}
+
+/// Localizaciones cuyas tablas reglamentarias de factores de paso coinciden con las indicadas
+///
+/// Compara, en todas las versiones de las tablas reglamentarias, el factor de paso anual de la
+/// electricidad suministrada por la red (ELECTRICIDAD, RED, SUMINISTRO, A), que es el que
+/// distingue unas localizaciones de otras, con una tolerancia de 0.001.
+///
+/// Devuelve las localizaciones ordenadas y sin repetir, o una lista vacía si los factores no
+/// coinciden con los de ninguna localización (p.e. factores propios o sin ese factor).
+pub fn wfactors_locations(wfactors: &Factors) -> Vec<&'static str> {
+    let Ok(factor) = wfactors.find_factor(
+        Carrier::ELECTRICIDAD,
+        Source::RED,
+        Dest::SUMINISTRO,
+        Step::A,
+    ) else {
+        return vec![];
+    };
+    let same = |other: &Factor| {
+        (factor.ren - other.ren).abs() < 1e-3
+            && (factor.nren - other.nren).abs() < 1e-3
+            && (factor.co2 - other.co2).abs() < 1e-3
+    };
+    let mut locations = BTreeSet::new();
+    for version in &CTE_WF_VERSIONS {
+        for (loc, locwf) in version.locwf.iter() {
+            if let Ok(other) = locwf.find_factor(
+                Carrier::ELECTRICIDAD,
+                Source::RED,
+                Dest::SUMINISTRO,
+                Step::A,
+            ) {
+                if same(other) {
+                    locations.insert(*loc);
+                }
+            }
+        }
+    }
+    locations.into_iter().collect()
+}
+
+/// Comprueba la coherencia de la localización declarada con los factores de paso
+///
+/// Permite detectar el uso de factores de paso (p.e. cargados desde archivo) de una localización
+/// distinta a la declarada en los datos (metadato CTE_LOCALIZACION), según las tablas
+/// reglamentarias (ver [`wfactors_locations`]) y el metadato CTE_LOCALIZACION de los factores.
+///
+/// Si los factores no coinciden con los de ninguna localización no se puede comprobar la
+/// coherencia y no se produce error.
+///
+/// # Errors
+///
+/// * Los factores de paso corresponden a otra localización
+pub fn check_wfactors_location(loc: &str, wfactors: &Factors) -> Result<(), EpbdError> {
+    let loc = loc.trim();
+    if let Some(wf_loc) = wfactors.get_meta("CTE_LOCALIZACION") {
+        if !wf_loc.trim().eq_ignore_ascii_case(loc) {
+            return Err(EpbdError::WrongInput(format!(
+                "La localización declarada ({}) no coincide con la de los factores de paso (CTE_LOCALIZACION: {})",
+                loc, wf_loc
+            )));
+        }
+    }
+    let locations = wfactors_locations(wfactors);
+    if !locations.is_empty() && !locations.iter().any(|l| l.eq_ignore_ascii_case(loc)) {
+        return Err(EpbdError::WrongInput(format!(
+            "La localización declarada ({}) no coincide con la de los factores de paso, cuyo factor de la electricidad de red corresponde a: {}",
+            loc,
+            locations.join(", ")
+        )));
+    }
+    Ok(())
+}
+
 /*
 Porcentaje renovable de la demanda de ACS en el perímetro próximo
 -----------------------------------------------------------------
@@ -196,34 +484,574 @@ pub fn incorpora_demanda_renovable_acs_nrb(mut ep: EnergyPerformance) -> EnergyP
                 "fraccion_renovable_demanda_acs_nrb".to_string(),
                 format!("{:.3}", fraccion_renovable_acs_nrb),
             );
-            map.remove("error_acs");
+            ep.replace_warning(
+                WarningCode::INDICADOR_NO_CALCULADO,
+                "fraccion_renovable_demanda_acs_nrb",
+                None,
+            );
+        }
+        Err(e) => {
+            let warning = Warning::info(
+                WarningCode::INDICADOR_NO_CALCULADO,
+                format!("no se puede calcular la demanda renovable de ACS \"{}\"", e),
+            )
+            .with_component("fraccion_renovable_demanda_acs_nrb");
+            ep.replace_warning(
+                WarningCode::INDICADOR_NO_CALCULADO,
+                "fraccion_renovable_demanda_acs_nrb",
+                Some(warning),
+            );
+            map.remove("fraccion_renovable_demanda_acs_nrb");
+        }
+    }
+    ep.misc = Some(map);
+    ep
+}
+
+/*
+Cobertura renovable in situ de servicios eléctricos
+---------------------------------------------------
+*/
+
+/// Devuelve eficiencia energética con la cobertura renovable in situ de los servicios VEN e ILU incorporada
+///
+/// Se incorporan a `EnergyPerformance.misc` las claves `cobertura_renovable_onst_VEN` y
+/// `cobertura_renovable_onst_ILU` para los servicios con consumo.
+pub fn incorpora_cobertura_renovable_onst(mut ep: EnergyPerformance) -> EnergyPerformance {
+    let mut map = ep.misc.take().unwrap_or_default();
+
+    for service in [Service::VEN, Service::ILU] {
+        let key = format!("cobertura_renovable_onst_{}", service);
+        match cobertura_renovable_onst(&ep, service) {
+            Ok(cobertura) => map.insert(key, format!("{:.3}", cobertura)),
+            Err(_) => map.remove(&key),
+        };
+    }
+    ep.misc = Some(map);
+    ep
+}
+
+/// Fracción del consumo de un servicio cubierta con energía renovable procedente de electricidad producida in situ
+///
+/// Se obtiene como la parte renovable (según su factor de paso) de la electricidad producida
+/// in situ (EL_INSITU) y usada en el servicio, respecto al consumo total del servicio
+/// (todos los vectores, incluidos los consumos auxiliares asignados al servicio).
+///
+/// Pensado para servicios eléctricos como VEN e ILU.
+///
+/// # Errors
+///
+/// * El servicio no tiene consumo
+/// * No se encuentra el factor de paso de la electricidad producida in situ
+pub fn cobertura_renovable_onst(
+    ep: &EnergyPerformance,
+    service: Service,
+) -> Result<f32, EpbdError> {
+    let used_srv_an = ep
+        .balance
+        .used
+        .epus_by_srv
+        .get(&service)
+        .copied()
+        .unwrap_or_default();
+    if used_srv_an.abs() < ep.tolerances.zero {
+        return Err(EpbdError::WrongInput(format!(
+            "Consumo nulo o casi nulo para el servicio {}",
+            service
+        )));
+    };
+
+    let prod_el_onst_srv_an: f32 = ep
+        .balance
+        .prod
+        .epus_by_srv_by_src
+        .iter()
+        .filter(|(src, _)| src.is_onsite_el())
+        .filter_map(|(_, by_srv)| by_srv.get(&service))
+        .sum();
+    if prod_el_onst_srv_an.abs() < ep.tolerances.zero {
+        return Ok(0.0);
+    };
+
+    let ren_fraction = get_fpA_del_ren_fraction(Carrier::ELECTRICIDAD, &ep.wfactors)?;
+    Ok(prod_el_onst_srv_an * ren_fraction / used_srv_an)
+}
+
+/*
+Compensación simplificada de excedentes
+---------------------------------------
+*/
+
+/// Devuelve eficiencia energética con los resultados con compensación simplificada de excedentes incorporados
+///
+/// Se incorporan a `EnergyPerformance.misc` el factor de compensación usado y la energía
+/// ponderada por m² (paso B) y RER resultantes. En caso de error se incorpora un aviso
+/// `INDICADOR_NO_CALCULADO` para el indicador `compensacion_simplificada`.
+///
+/// * `k_comp` - factor de compensación de la electricidad producida in situ exportada [0, 1]
+pub fn incorpora_compensacion_simplificada(
+    mut ep: EnergyPerformance,
+    k_comp: f32,
+) -> EnergyPerformance {
+    let mut map = ep.misc.take().unwrap_or_default();
+    let keys = [
+        "compensacion_simplificada_factor",
+        "compensacion_simplificada_ren",
+        "compensacion_simplificada_nren",
+        "compensacion_simplificada_tot",
+        "compensacion_simplificada_co2",
+        "compensacion_simplificada_rer",
+    ];
+
+    match compensacion_simplificada(&ep, k_comp) {
+        Ok(we_b) => {
+            let we_b_m2 = we_b * (1.0 / ep.arearef);
+            let values = [
+                k_comp,
+                we_b_m2.ren,
+                we_b_m2.nren,
+                we_b_m2.tot(),
+                we_b_m2.co2,
+                we_b.rer(),
+            ];
+            for (key, value) in keys.iter().zip(values.iter()) {
+                map.insert(key.to_string(), format!("{:.3}", value));
+            }
+            ep.replace_warning(
+                WarningCode::INDICADOR_NO_CALCULADO,
+                "compensacion_simplificada",
+                None,
+            );
         }
         Err(e) => {
+            let warning = Warning::warn(
+                WarningCode::INDICADOR_NO_CALCULADO,
+                format!(
+                    "no se puede calcular la compensación simplificada de excedentes \"{}\"",
+                    e
+                ),
+            )
+            .with_component("compensacion_simplificada");
+            ep.replace_warning(
+                WarningCode::INDICADOR_NO_CALCULADO,
+                "compensacion_simplificada",
+                Some(warning),
+            );
+            for key in keys {
+                map.remove(key);
+            }
+        }
+    }
+    ep.misc = Some(map);
+    ep
+}
+
+/// Energía ponderada (paso B) con compensación simplificada de excedentes
+///
+/// En el mecanismo de compensación simplificada la electricidad producida in situ (EL_INSITU y EL_EOLICA) y
+/// exportada compensa económicamente pero no (o solo parcialmente) en términos energéticos.
+/// Para reflejarlo, el efecto de la exportación de esa electricidad sobre los recursos evitados
+/// en la red (paso B) se pondera con el factor `k_comp` en lugar de con `k_exp`:
+///
+/// E_we_comp = E_we_B + (k_exp - k_comp) · E_we_exp_AB_EL_INSITU
+///
+/// De este modo, con `k_comp == k_exp` se obtiene el resultado del método ISO estándar y con
+/// `k_comp == 0` la electricidad exportada no reduce la energía ponderada.
+///
+/// El resto de vectores y fuentes (p.e. la electricidad cogenerada) mantiene el tratamiento estándar.
+///
+/// * `k_comp` - factor de compensación de la electricidad producida in situ exportada [0, 1]
+#[allow(non_snake_case)]
+pub fn compensacion_simplificada(
+    ep: &EnergyPerformance,
+    k_comp: f32,
+) -> Result<RenNrenCo2, EpbdError> {
+    use Carrier::ELECTRICIDAD;
+
+    if !(0.0..=1.0).contains(&k_comp) {
+        return Err(EpbdError::WrongInput(format!(
+            "Factor de compensación simplificada fuera de rango [0.00 - 1.00]: {:.2}",
+            k_comp
+        ))
+        .for_indicator("compensacion_simplificada"));
+    };
+
+    let E_we_an = ep.balance.we.b;
+
+    let bal_el = match ep.balance_cr.get(&ELECTRICIDAD) {
+        Some(bal_el) => bal_el,
+        None => return Ok(E_we_an),
+    };
+    let exp = &bal_el.exp;
+    let E_exp_el_onst_an: f32 = exp
+        .by_src_an
+        .iter()
+        .filter(|(src, _)| src.is_onsite_el())
+        .map(|(_, v)| v)
+        .sum();
+    if exp.an.abs() < ep.tolerances.zero || E_exp_el_onst_an.abs() < ep.tolerances.zero {
+        return Ok(E_we_an);
+    }
+
+    // Fracción de la energía exportada que procede de la producción in situ
+    let f_exp_onst = E_exp_el_onst_an / exp.an;
+
+    // Efecto de la exportación (paso B - paso A) de la electricidad producida in situ (fórmulas 27 y 28)
+    // La exportación diferida (desde almacenamiento) usa sus factores, si están definidos
+    let dest_grid_sto = if ep.wfactors.wdata.iter().any(|f| {
+        f.carrier == ELECTRICIDAD && f.source == Source::INSITU && f.dest == Dest::A_RED_DIFERIDA
+    }) {
+        Dest::A_RED_DIFERIDA
+    } else {
+        Dest::A_RED
+    };
+    let mut E_we_exp_el_onst_an_AB = RenNrenCo2::default();
+    for (E_exp_dest_an, dest) in [
+        (exp.nepus_an, Dest::A_NEPB),
+        (exp.grid_an - exp.grid_sto_an, Dest::A_RED),
+        (exp.grid_sto_an, dest_grid_sto),
+    ] {
+        if E_exp_dest_an.abs() < ep.tolerances.zero {
+            continue;
+        }
+        let fpA = ep
+            .wfactors
+            .find(ELECTRICIDAD, Source::INSITU, dest, Step::A)
+            .map_err(|e| e.for_indicator("compensacion_simplificada"))?;
+        let fpB = ep
+            .wfactors
+            .find(ELECTRICIDAD, Source::INSITU, dest, Step::B)
+            .map_err(|e| e.for_indicator("compensacion_simplificada"))?;
+        E_we_exp_el_onst_an_AB += E_exp_dest_an * f_exp_onst * (fpB - fpA);
+    }
+
+    Ok(E_we_an + (ep.k_exp_for(ELECTRICIDAD) - k_comp) * E_we_exp_el_onst_an_AB)
+}
+
+/*
+RER según la Directiva (UE) con bombas de calor
+-----------------------------------------------
+*/
+
+/// Rendimiento medio del sistema eléctrico europeo (η) para el SPF mínimo de las bombas de calor
+///
+/// Según la Decisión 2013/114/UE, η = 45,5 %, con lo que el SPF mínimo es 1,15 · 1/η ≈ 2,5
+pub const ETA_ELECTRICIDAD_UE: f32 = 0.455;
+
+/// Devuelve eficiencia energética con la RER según el criterio de la Directiva (UE) incorporada
+///
+/// Se incorporan a `EnergyPerformance.misc` las claves `rer_directiva` y `rer_directiva_spf_min`.
+/// En caso de error se incorpora un aviso `INDICADOR_NO_CALCULADO` para el indicador `rer_directiva`.
+///
+/// * `eta` - rendimiento medio del sistema eléctrico (η), p.e. ETA_ELECTRICIDAD_UE
+pub fn incorpora_rer_directiva(mut ep: EnergyPerformance, eta: f32) -> EnergyPerformance {
+    let mut map = ep.misc.take().unwrap_or_default();
+    match rer_directiva(&ep, eta) {
+        Ok(rer) => {
+            map.insert("rer_directiva".to_string(), format!("{:.3}", rer));
             map.insert(
-                "error_acs".to_string(),
+                "rer_directiva_spf_min".to_string(),
+                format!("{:.3}", 1.15 / eta),
+            );
+            ep.replace_warning(WarningCode::INDICADOR_NO_CALCULADO, "rer_directiva", None);
+        }
+        Err(e) => {
+            let warning = Warning::warn(
+                WarningCode::INDICADOR_NO_CALCULADO,
                 format!(
-                    "ERROR: no se puede calcular la demanda renovable de ACS \"{}\"",
+                    "no se puede calcular la RER según la Directiva (UE) \"{}\"",
                     e
                 ),
+            )
+            .with_component("rer_directiva");
+            ep.replace_warning(
+                WarningCode::INDICADOR_NO_CALCULADO,
+                "rer_directiva",
+                Some(warning),
             );
-            map.remove("fraccion_renovable_demanda_acs_nrb");
+            map.remove("rer_directiva");
+            map.remove("rer_directiva_spf_min");
         }
     }
     ep.misc = Some(map);
     ep
 }
 
+/// RER con el criterio de la Directiva (UE) para la energía ambiente capturada por bombas de calor
+///
+/// La energía ambiente (EAMBIENTE) consumida en servicios EPB por sistemas con bomba de calor
+/// cuyo rendimiento medio estacional (SPF) no supera el valor mínimo SPF_min = 1,15 · 1/η no se
+/// considera renovable y se excluye del balance (paso B) antes de calcular la RER.
+///
+/// El SPF de cada sistema se obtiene de la etiqueta `SCOP=valor` en el comentario de alguno de sus
+/// componentes de consumo o, en su defecto, de sus consumos de EAMBIENTE y ELECTRICIDAD
+/// (ver `spf_bdc_por_sistema`).
+///
+/// * `eta` - rendimiento medio del sistema eléctrico (η), p.e. ETA_ELECTRICIDAD_UE
+///
+/// # Errors
+///
+/// * Rendimiento del sistema eléctrico fuera de rango (0.00 - 1.00]
+#[allow(non_snake_case)]
+pub fn rer_directiva(ep: &EnergyPerformance, eta: f32) -> Result<f32, EpbdError> {
+    if !(eta > 0.0 && eta <= 1.0) {
+        return Err(EpbdError::WrongInput(format!(
+            "Rendimiento del sistema eléctrico fuera de rango (0.00 - 1.00]: {:.3}",
+            eta
+        ))
+        .for_indicator("rer_directiva"));
+    };
+    let spf_min = 1.15 / eta;
+
+    let E_we_an = ep.balance.we.b;
+    let spf_by_id = spf_bdc_por_sistema(&ep.components);
+
+    // Energía ambiente consumida en servicios EPB por sistemas con SPF insuficiente, para cada tipo de fuente
+    let mut E_we_excl_an = RenNrenCo2::default();
+    for carrier in Carrier::AMBIENT {
+        let bal_amb = match ep.balance_cr.get(&carrier) {
+            Some(bal_amb) if bal_amb.used.epus_an.abs() > ep.tolerances.zero => bal_amb,
+            _ => continue,
+        };
+        let E_amb_excl_an: f32 = ep
+            .components
+            .data
+            .iter()
+            .filter(|c| {
+                c.is_used()
+                    && c.is_epb_use()
+                    && c.has_carrier(carrier)
+                    && spf_by_id.get(&c.id()).is_some_and(|spf| *spf < spf_min)
+            })
+            .map(|c| c.values_sum())
+            .sum();
+        E_we_excl_an += (E_amb_excl_an / bal_amb.used.epus_an) * bal_amb.we.b;
+    }
+
+    Ok((E_we_an - E_we_excl_an).rer())
+}
+
+/// Rendimiento medio estacional (SPF) de los sistemas con bomba de calor, por id de sistema
+///
+/// Se consideran los sistemas con consumo de EAMBIENTE (o sus subtipos) en servicios EPB. El SPF se toma de la
+/// etiqueta `SCOP=valor` del comentario de alguno de los componentes de consumo del sistema o,
+/// si no se declara, se estima como (E_EAMBIENTE + E_ELECTRICIDAD) / E_ELECTRICIDAD, con los
+/// consumos del sistema en servicios EPB. Los sistemas sin SCOP declarado ni consumo eléctrico
+/// no se incluyen.
+#[allow(non_snake_case)]
+pub fn spf_bdc_por_sistema(components: &Components) -> BTreeMap<i32, f32> {
+    let mut spf_by_id = BTreeMap::new();
+    let ids: BTreeSet<i32> = components
+        .data
+        .iter()
+        .filter(|c| c.is_used() && c.is_epb_use() && c.carrier().is_ambient())
+        .map(|c| c.id())
+        .collect();
+    for id in ids {
+        let used: Vec<_> = components
+            .data
+            .iter()
+            .filter(|c| c.is_used() && c.id() == id)
+            .collect();
+        let declared = used.iter().find_map(|c| c.tags().get_f32("SCOP"));
+        let spf = declared.or_else(|| {
+            let used_cr = |is_carrier: fn(&Carrier) -> bool| -> f32 {
+                used.iter()
+                    .filter(|c| c.is_epb_use() && is_carrier(&c.carrier()))
+                    .map(|c| c.values_sum())
+                    .sum()
+            };
+            let E_el = used_cr(|c| *c == Carrier::ELECTRICIDAD);
+            let E_amb = used_cr(Carrier::is_ambient);
+            if E_el > 0.0 {
+                Some((E_amb + E_el) / E_el)
+            } else {
+                None
+            }
+        });
+        if let Some(spf) = spf {
+            spf_by_id.insert(id, spf);
+        }
+    }
+    spf_by_id
+}
+
+/// Energía renovable aportada por una bomba de calor para calefacción según la Decisión 2013/114/UE
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RenovableBdc {
+    /// Rendimiento medio estacional (SPF) del sistema
+    pub spf: f32,
+    /// Calor útil suministrado por la bomba de calor para calefacción, Q_usable [kWh/an]
+    pub q_usable: f32,
+    /// Energía renovable computable, E_RES = Q_usable · (1 - 1/SPF), nula si SPF <= SPF_min [kWh/an]
+    pub e_res: f32,
+}
+
+/// Devuelve eficiencia energética con la energía renovable de las bombas de calor para calefacción incorporada
+///
+/// Se incorporan a `EnergyPerformance.misc`, si hay bombas de calor para calefacción, las claves
+/// `renovable_bdc_cal` (E_RES por m2 de área de referencia), `renovable_bdc_cal_spf_min` y, si se
+/// conoce la demanda de calefacción, `cobertura_renovable_bdc_cal` (fracción de la demanda cubierta por E_RES).
+/// En caso de error se incorpora un aviso `INDICADOR_NO_CALCULADO` para el indicador `renovable_bdc_cal`.
+///
+/// * `eta` - rendimiento medio del sistema eléctrico (η), p.e. ETA_ELECTRICIDAD_UE
+pub fn incorpora_renovable_bdc_calefaccion(
+    mut ep: EnergyPerformance,
+    eta: f32,
+) -> EnergyPerformance {
+    let mut map = ep.misc.take().unwrap_or_default();
+    for key in [
+        "renovable_bdc_cal",
+        "renovable_bdc_cal_spf_min",
+        "cobertura_renovable_bdc_cal",
+    ] {
+        map.remove(key);
+    }
+    ep.replace_warning(
+        WarningCode::INDICADOR_NO_CALCULADO,
+        "renovable_bdc_cal",
+        None,
+    );
+    match renovable_bdc_calefaccion(&ep, eta) {
+        Ok(by_id) if by_id.is_empty() => (),
+        Ok(by_id) => {
+            let e_res_an: f32 = by_id.values().map(|bdc| bdc.e_res).sum();
+            map.insert(
+                "renovable_bdc_cal".to_string(),
+                format!("{:.3}", e_res_an / ep.arearef),
+            );
+            map.insert(
+                "renovable_bdc_cal_spf_min".to_string(),
+                format!("{:.3}", 1.15 / eta),
+            );
+            if let Some(needs_cal) = ep.balance.needs.CAL.filter(|n| *n > ep.tolerances.zero) {
+                map.insert(
+                    "cobertura_renovable_bdc_cal".to_string(),
+                    format!("{:.3}", e_res_an / needs_cal),
+                );
+            }
+        }
+        Err(e) => {
+            ep.warnings.push(
+                Warning::warn(
+                    WarningCode::INDICADOR_NO_CALCULADO,
+                    format!(
+                        "no se puede calcular la energía renovable de bombas de calor para calefacción \"{}\"",
+                        e
+                    ),
+                )
+                .with_component("renovable_bdc_cal"),
+            );
+        }
+    }
+    ep.misc = Some(map);
+    ep
+}
+
+/// Energía renovable de las bombas de calor para calefacción según la Decisión 2013/114/UE, por id de sistema
+///
+/// Para cada sistema con consumo de EAMBIENTE (o sus subtipos) en el servicio de calefacción:
+///
+/// - el SPF se toma de la etiqueta `SCOP=valor` del comentario de alguno de sus componentes de consumo
+///   o, si no se declara, se estima como (E_EAMBIENTE + E_ELECTRICIDAD) / E_ELECTRICIDAD, con los
+///   consumos de calefacción del sistema;
+/// - el calor útil es Q_usable = E_ELECTRICIDAD · SPF o, sin consumo eléctrico, el que corresponde a la
+///   energía ambiente capturada, Q_usable = E_EAMBIENTE · SPF / (SPF - 1);
+/// - la energía renovable es E_RES = Q_usable · (1 - 1/SPF) solamente si SPF > SPF_min = 1,15 · 1/η.
+///
+/// No se incluyen los sistemas sin SCOP declarado ni consumo eléctrico de calefacción.
+///
+/// * `eta` - rendimiento medio del sistema eléctrico (η), p.e. ETA_ELECTRICIDAD_UE
+///
+/// # Errors
+///
+/// * Rendimiento del sistema eléctrico fuera de rango (0.00 - 1.00]
+#[allow(non_snake_case)]
+pub fn renovable_bdc_calefaccion(
+    ep: &EnergyPerformance,
+    eta: f32,
+) -> Result<BTreeMap<i32, RenovableBdc>, EpbdError> {
+    if !(eta > 0.0 && eta <= 1.0) {
+        return Err(EpbdError::WrongInput(format!(
+            "Rendimiento del sistema eléctrico fuera de rango (0.00 - 1.00]: {:.3}",
+            eta
+        ))
+        .for_indicator("renovable_bdc_calefaccion"));
+    };
+    let spf_min = 1.15 / eta;
+
+    let used_cal: Vec<_> = ep
+        .components
+        .data
+        .iter()
+        .filter(|c| c.is_used() && c.is_epb_use() && c.has_service(Service::CAL))
+        .collect();
+    let ids: BTreeSet<i32> = used_cal
+        .iter()
+        .filter(|c| c.carrier().is_ambient())
+        .map(|c| c.id())
+        .collect();
+
+    let mut by_id = BTreeMap::new();
+    for id in ids {
+        let used_cr = |is_carrier: fn(&Carrier) -> bool| -> f32 {
+            used_cal
+                .iter()
+                .filter(|c| c.id() == id && is_carrier(&c.carrier()))
+                .map(|c| c.values_sum())
+                .sum()
+        };
+        let E_el = used_cr(|c| *c == Carrier::ELECTRICIDAD);
+        let E_amb = used_cr(Carrier::is_ambient);
+        let declared = ep
+            .components
+            .data
+            .iter()
+            .filter(|c| c.is_used() && c.id() == id)
+            .find_map(|c| c.tags().get_f32("SCOP"));
+        let spf = match declared {
+            Some(spf) => spf,
+            None if E_el > 0.0 => (E_amb + E_el) / E_el,
+            None => continue,
+        };
+        let q_usable = if E_el > 0.0 {
+            E_el * spf
+        } else if spf > 1.0 {
+            E_amb * spf / (spf - 1.0)
+        } else {
+            0.0
+        };
+        let e_res = if spf > spf_min {
+            q_usable * (1.0 - 1.0 / spf)
+        } else {
+            0.0
+        };
+        by_id.insert(
+            id,
+            RenovableBdc {
+                spf,
+                q_usable,
+                e_res,
+            },
+        );
+    }
+    Ok(by_id)
+}
+
 #[allow(non_snake_case)]
 /// Fracción de la demanda de ACS con origen renovable, considerando el perímetro próximo
 ///
 /// Permite calcular el indicador de HE4 con las siguientes restricciones:
 ///
-/// 1. si hay biomasa (o biomasa densificada), esta y otros vectores insitu o de distrito cubren el 100% de la demanda
+/// 1. si hay biomasa (o biomasa densificada, o biocarburante en el perímetro próximo), esta y otros vectores insitu o de distrito cubren el 100% de la demanda
 /// 2. no se permite el consumo de electricidad cogenerada para producir ACS (solo la parte térmica) aunque podría provenir de BIOMASA / BIOMASADENSIFICADA
-///     Si se pudiese usar electricidad y existiese cogeneración tendríamos 2 vectores no insitu (BIOMASA, ELECTRICIDAD)
-///     y, si no se usase la parte térmica, no sabríamos si tiene procedencia renovable o no.
-/// 3. el rendimiento térmico de la contribución renovable de vectores RED1, RED2 y EAMBIENTE es 1.0. (demanda == consumo)
-/// 4. las únicas aportaciones nearby son biomasa (cualquiera), RED1, RED2, ELECTRICIDAD insitu y EAMBIENTE (insitu)
+///    Si se pudiese usar electricidad y existiese cogeneración tendríamos 2 vectores no insitu (BIOMASA, ELECTRICIDAD)
+///    y, si no se usase la parte térmica, no sabríamos si tiene procedencia renovable o no.
+/// 3. el rendimiento térmico de la contribución renovable de vectores RED1, RED2, RED_CALOR_RESIDUAL y EAMBIENTE es 1.0. (demanda == consumo)
+/// 4. las únicas aportaciones nearby son biomasa (cualquiera), RED1, RED2, RED_CALOR_RESIDUAL, ELECTRICIDAD insitu y EAMBIENTE (insitu),
+///    además del BIOCARBURANTE cuando se indica el metadato `CTE_BIOCARBURANTE_PROXIMO: true`
+/// 5. el calor residual recuperado (RED_CALOR_RESIDUAL) sustituye a la contribución renovable, por lo que
+///    toda la demanda que cubre se considera renovable, con independencia de sus factores de paso
 ///
 /// Se pueden excluir consumos eléctricos auxiliares con la etiqueta CTEEPBD_EXCLUYE_AUX_ACS o CTEEPBD_AUX en el comentario del componente de consumo y vector ELECTRICIDAD
 /// Se pueden excluir producciones renovables para equipos con SCOP < 2,5 con la etiqueta CTEEPBD_EXCLUYE_SCOP_ACS en el comentario del componente de vector EAMBIENTE
@@ -247,12 +1075,128 @@ pub fn incorpora_demanda_renovable_acs_nrb(mut ep: EnergyPerformance) -> EnergyP
 ///       que supone sobre la demanda global del edificio (id=0, DEMANDA).
 ///
 pub fn fraccion_renovable_acs_nrb(ep: &EnergyPerformance) -> Result<f32, EpbdError> {
-    use Carrier::{BIOMASA, BIOMASADENSIFICADA, EAMBIENTE, ELECTRICIDAD};
-
     let bal = &ep.balance;
+    let prod_el_dhw = |src| {
+        bal.prod
+            .epus_by_srv_by_src
+            .get(&src)
+            .and_then(|by_srv| by_srv.get(&Service::ACS))
+            .copied()
+            .unwrap_or_default()
+    };
+    fraccion_renovable_acs_nrb_datos(&DatosAcsNrb {
+        demanda_anual_acs: bal.needs.ACS,
+        dhw_used_by_cr: bal
+            .used
+            .epus_by_cr_by_srv
+            .get(&Service::ACS)
+            .cloned()
+            .unwrap_or_default(),
+        prod_el_onst_dhw: prod_el_dhw(ProdSource::EL_INSITU) + prod_el_dhw(ProdSource::EL_EOLICA),
+        dhw_cogen_use: prod_el_dhw(ProdSource::EL_COGEN),
+        components: &ep.components,
+        wfactors: &ep.wfactors,
+        tolerances: &ep.tolerances,
+    })
+    .map_err(|e| e.for_indicator("fraccion_renovable_demanda_acs_nrb"))
+}
+
+/// Fracción de la demanda de ACS con origen renovable, en el perímetro próximo, sin calcular el balance completo
+///
+/// Obtiene el mismo indicador que [`fraccion_renovable_acs_nrb`] a partir de los componentes y los factores de paso,
+/// calculando solamente el reparto por servicios de la electricidad producida y los consumos y demanda de ACS,
+/// para aplicaciones que solo necesitan el indicador de HE4.
+///
+/// # Errors
+///
+/// * Factores de paso necesarios no definidos
+/// * Ver [`fraccion_renovable_acs_nrb`]
+pub fn fraccion_renovable_acs_desde_componentes(
+    components: &Components,
+    wfactors: &Factors,
+) -> Result<f32, EpbdError> {
+    let tolerances = Tolerances::default();
+    let mut wfactors = wfactors.clone();
+    wfactors.apply_validity_periods(components.num_steps())?;
+    wfactors.add_subtype_factors(components);
+    wfactors.add_cgn_factors(components)?;
+
+    // Consumos EPB de ACS por vector, incluidos los auxiliares
+    let mut dhw_used_by_cr: BTreeMap<Carrier, f32> = BTreeMap::new();
+    for c in components
+        .data
+        .iter()
+        .filter(|c| c.is_epb_use() && c.has_service(Service::ACS))
+    {
+        *dhw_used_by_cr.entry(c.carrier()).or_default() += c.values_sum();
+    }
+
+    // Reparto por servicios de la electricidad producida
+    let prod = if components
+        .available_carriers()
+        .contains(&Carrier::ELECTRICIDAD)
+    {
+        Some(
+            crate::balance::used_produced_for_carrier(
+                Carrier::ELECTRICIDAD,
+                components,
+                LoadMatchingMode::Constant,
+                false,
+                &tolerances,
+            )?
+            .1,
+        )
+    } else {
+        None
+    };
+    let prod_el_dhw = |src| {
+        prod.as_ref()
+            .and_then(|prod| prod.epus_by_srv_by_src_an.get(&src))
+            .and_then(|by_srv| by_srv.get(&Service::ACS))
+            .copied()
+            .unwrap_or_default()
+    };
+
+    fraccion_renovable_acs_nrb_datos(&DatosAcsNrb {
+        demanda_anual_acs: components.needs.ACS.as_ref().map(|nd| nd.iter().sum()),
+        dhw_used_by_cr,
+        prod_el_onst_dhw: prod_el_dhw(ProdSource::EL_INSITU) + prod_el_dhw(ProdSource::EL_EOLICA),
+        dhw_cogen_use: prod_el_dhw(ProdSource::EL_COGEN),
+        components,
+        wfactors: &wfactors,
+        tolerances: &tolerances,
+    })
+    .map_err(|e| e.for_indicator("fraccion_renovable_demanda_acs_nrb"))
+}
+
+/// Datos necesarios para el cálculo de la fracción renovable de la demanda de ACS en el perímetro próximo
+struct DatosAcsNrb<'a> {
+    /// Demanda anual de ACS, kWh
+    demanda_anual_acs: Option<f32>,
+    /// Consumo EPB anual de ACS por vector, incluidos auxiliares, kWh
+    dhw_used_by_cr: BTreeMap<Carrier, f32>,
+    /// Electricidad producida in situ usada en ACS, incluidos auxiliares, kWh
+    prod_el_onst_dhw: f32,
+    /// Electricidad cogenerada usada en ACS, incluidos auxiliares, kWh
+    dhw_cogen_use: f32,
+    components: &'a Components,
+    wfactors: &'a Factors,
+    tolerances: &'a Tolerances,
+}
+
+#[allow(non_snake_case)]
+fn fraccion_renovable_acs_nrb_datos(datos: &DatosAcsNrb) -> Result<f32, EpbdError> {
+    use Carrier::ELECTRICIDAD;
+
+    let DatosAcsNrb {
+        components,
+        wfactors,
+        tolerances,
+        ..
+    } = *datos;
 
     // Demanda anual de ACS
-    let demanda_anual_acs = match bal.needs.ACS {
+    let demanda_anual_acs = match datos.demanda_anual_acs {
         // Sin demanda anual de ACS definida
         None => {
             return Err(EpbdError::WrongInput(
@@ -263,18 +1207,12 @@ pub fn fraccion_renovable_acs_nrb(ep: &EnergyPerformance) -> Result<f32, EpbdErr
     };
 
     // Consumo de de ACS por vectores
-    let dhw_used_by_cr = bal
-        .used
-        .epus_by_cr_by_srv
-        .get(&Service::ACS)
-        .cloned()
-        .unwrap_or_default();
+    let dhw_used_by_cr = &datos.dhw_used_by_cr;
 
     // Calcula consumo de ACS por vectores descontando AUX y consumos de EAMBIENTE de bajo SCOP
     // Los consumos de EAMBIENTE excluidos son los marcados con CTEEPBD_EXCLUYE_SCOP_ACS
     let mut dhw_used_by_cr_no_aux_or_low_scop = dhw_used_by_cr.clone();
-    let dhw_aux_use_an = ep
-        .components
+    let dhw_aux_use_an = components
         .data
         .iter()
         .filter(|c| c.is_aux() && c.has_service(Service::ACS))
@@ -285,40 +1223,36 @@ pub fn fraccion_renovable_acs_nrb(ep: &EnergyPerformance) -> Result<f32, EpbdErr
         .and_modify(|e| *e -= dhw_aux_use_an);
     if dhw_used_by_cr_no_aux_or_low_scop
         .get(&Carrier::ELECTRICIDAD)
-        .map(|v| v.abs() < 0.01)
+        .map(|v| v.abs() < tolerances.residual_use_min)
         .unwrap_or(false)
     {
         dhw_used_by_cr_no_aux_or_low_scop.remove(&ELECTRICIDAD);
     };
-    let dhw_used_low_scop_an: f32 = ep
-        .components
-        .data
-        .iter()
-        .filter(|c| {
-            c.is_used()
-                && c.has_carrier(EAMBIENTE)
-                && c.comment().contains("CTEEPBD_EXCLUYE_SCOP_ACS")
-        })
-        .map(HasValues::values_sum)
-        .sum();
-    dhw_used_by_cr_no_aux_or_low_scop
-        .entry(EAMBIENTE)
-        .and_modify(|e| *e -= dhw_used_low_scop_an);
+    for ambient in Carrier::AMBIENT {
+        let dhw_used_low_scop_an: f32 = components
+            .data
+            .iter()
+            .filter(|c| {
+                c.is_used()
+                    && c.has_carrier(ambient)
+                    && c.comment().contains("CTEEPBD_EXCLUYE_SCOP_ACS")
+            })
+            .map(HasValues::values_sum)
+            .sum();
+        dhw_used_by_cr_no_aux_or_low_scop
+            .entry(ambient)
+            .and_modify(|e| *e -= dhw_used_low_scop_an);
+    }
 
     // Casos sin consumo de ACS
     if dhw_used_by_cr_no_aux_or_low_scop.is_empty() {
         return Ok(0.0);
     };
-    if dhw_used_by_cr_no_aux_or_low_scop
-        .get(&Carrier::EAMBIENTE)
-        .map(|v| v.abs() < 0.01)
-        .unwrap_or(false)
-    {
-        dhw_used_by_cr_no_aux_or_low_scop.remove(&EAMBIENTE);
-    };
+    dhw_used_by_cr_no_aux_or_low_scop
+        .retain(|carrier, used| !carrier.is_ambient() || used.abs() >= tolerances.residual_use_min);
 
     // Demanda anual de ACS nula
-    if demanda_anual_acs.abs() < f32::EPSILON {
+    if demanda_anual_acs.abs() < tolerances.zero {
         return Err(EpbdError::WrongInput(
             "Demanda anual de ACS nula o casi nula".to_string(),
         ));
@@ -327,6 +1261,7 @@ pub fn fraccion_renovable_acs_nrb(ep: &EnergyPerformance) -> Result<f32, EpbdErr
     // Comprobaremos las condiciones para poder calcular las aportaciones renovables a la demanda
     //
     // 1. Las aportaciones de redes de distrito RED1, RED2,TERMOSOLAR y EAMBIENTE son aportaciones renovables según sus factores de paso (fp_ren / fp_tot)
+    //    El calor residual recuperado (RED_CALOR_RESIDUAL) se considera renovable en su totalidad
     // 2. La biomasa (o biomasa densificada)
     //  - si solo se consume uno de esos vectores o vectores insitu o de distrito, y se cubre el 100% de la demanda podemos calcular
     //  - si tenemos el porcentaje de demanda cubierto por la biomasa o biomasa in situ, podemos calcular la demanda renovable.
@@ -340,121 +1275,40 @@ pub fn fraccion_renovable_acs_nrb(ep: &EnergyPerformance) -> Result<f32, EpbdErr
     // Podemos obtener la parte renovable, con la fracción que supone su factor de paso ren respecto al total y
     // suponiendo que la conversión de consumo a demanda es con rendimiento 1.0 (de modo que demanda = consumo para estos vectores)
     // En el caso de la biomasa la conversión depende del rendimiento del sistema
-    let (Q_nrb_non_biomass_an_tot, Q_nrb_non_biomass_an_ren) =
-        Q_nrb_non_biomass_an(&dhw_used_by_cr_no_aux_or_low_scop, ep)?;
-
-    // 2. == Biomasa ==
-    // Vectores energéticos consumidos
-    let has_biomass = dhw_used_by_cr_no_aux_or_low_scop.contains_key(&BIOMASA);
-    let has_dens_biomass = dhw_used_by_cr_no_aux_or_low_scop.contains_key(&BIOMASADENSIFICADA);
-    let has_any_biomass = has_biomass || has_dens_biomass;
-    let has_only_one_type_of_biomass =
-        (has_biomass || has_dens_biomass) && !(has_biomass && has_dens_biomass);
+    let (Q_nrb_non_biomass_an_tot, Q_nrb_non_biomass_an_ren) = Q_nrb_non_biomass_an(
+        &dhw_used_by_cr_no_aux_or_low_scop,
+        components,
+        wfactors,
+        tolerances,
+    )?;
+
+    // 2. == Biomasa (y biocarburante próximo) ==
+    // Vectores energéticos consumidos de tipo biomasa (o BIOCARBURANTE si se considera en el perímetro próximo)
+    let biofuels: Vec<Carrier> = biofuels_nrb(components)
+        .into_iter()
+        .filter(|c| dhw_used_by_cr_no_aux_or_low_scop.contains_key(c))
+        .collect();
     let has_only_nearby = dhw_used_by_cr_no_aux_or_low_scop
         .keys()
-        .all(|&c| c.is_nearby());
+        .all(|&c| components.is_nearby_carrier(c));
 
-    let Q_biomass_an_ren = if has_only_one_type_of_biomass && has_only_nearby {
+    let Q_biomass_an_ren = if biofuels.len() == 1 && has_only_nearby {
         // Solo hay un tipo de biomasa y no hay otros vectores que no sean de distrito o energía ambiente
         // entonces podemos calcular el % de la demanda de ACS abastecida por la biomasa
         // ya que es toda la no cubierta por el resto de vectores
         let Q_any_biomass_acs_an = demanda_anual_acs - Q_nrb_non_biomass_an_tot;
         // Parte renovable: Q_any_biomass_acs_an_ren
-        if has_biomass {
-            Q_any_biomass_acs_an * get_fpA_del_ren_fraction(BIOMASA, &ep.wfactors)?
-        } else {
-            Q_any_biomass_acs_an * get_fpA_del_ren_fraction(BIOMASADENSIFICADA, &ep.wfactors)?
-        }
-    } else if has_any_biomass {
-        // Cuando además de biomasa hay otros vectores que no son de distrito o insitu
-        // necesitamos saber qué cantidad de ACS produce la biomasa para poder calcular
-        let Q_biomass_an_ren = if has_biomass {
-            let fp_ren_fraction_biomass = get_fpA_del_ren_fraction(BIOMASA, &ep.wfactors)?;
-            // Id de sistemas con uso de BIOMASA para ACS
-            let idx_with_acs_use = Vec::from_iter(
-                ep.components
-                    .data
-                    .iter()
-                    .filter(|c| {
-                        c.is_used() && c.has_service(Service::ACS) && c.has_carrier(BIOMASA)
-                    })
-                    .map(|c| c.id())
-                    .collect::<HashSet<i32>>(),
-            );
-            // Comprobar que se ha definido la salida de ACS para equipos de BIOMASA
-            for idx in &idx_with_acs_use {
-                if !ep
-                    .components
-                    .data
-                    .iter()
-                    .any(|c| c.has_id(*idx) && c.is_out() && c.has_service(Service::ACS))
-                {
-                    return Err(EpbdError::WrongInput(
-                        format!("Uso de biomasa en el sistema con id:{} sin definición de la energía entregada para el servicio de ACS.", idx),
-                    ));
-                }
-            }
-            // Suma de demandas de ACS salientes de equipos con consumo de BIOMASA
-            let alt_tot_dhw_use: f32 = ep
-                .components
-                .data
-                .iter()
-                .filter(|c| {
-                    idx_with_acs_use.contains(&c.id()) && c.is_out() && c.has_service(Service::ACS)
-                })
-                .map(HasValues::values_sum)
-                .sum();
-            alt_tot_dhw_use * fp_ren_fraction_biomass
-        } else {
-            0.0
-        };
-        let Q_dens_biomass_an_ren = if has_dens_biomass {
-            let fp_ren_fraction_dens_biomass =
-                get_fpA_del_ren_fraction(BIOMASADENSIFICADA, &ep.wfactors)?;
-            // Id de sistemas con uso de BIOMASADENSIFICADA para ACS
-            let idx_with_acs_use = Vec::from_iter(
-                ep.components
-                    .data
-                    .iter()
-                    .filter(|c| {
-                        c.is_used()
-                            && c.has_service(Service::ACS)
-                            && c.has_carrier(BIOMASADENSIFICADA)
-                    })
-                    .map(|c| c.id())
-                    .collect::<HashSet<i32>>(),
-            );
-            // Comprobar que se ha definido la salida de ACS para equipos de BIOMASADENSIFICADA
-            for idx in &idx_with_acs_use {
-                if !ep
-                    .components
-                    .data
-                    .iter()
-                    .any(|c| c.has_id(*idx) && c.is_out() && c.has_service(Service::ACS))
-                {
-                    return Err(EpbdError::WrongInput(
-                        format!("Uso de biomasa en el sistema con id:{} sin definición de la energía entregada para el servicio de ACS.", idx),
-                    ));
-                }
-            }
-            // Suma de demandas de ACS salientes de equipos con consumo de BIOMASADENSIFICADA
-            let alt_tot_dhw_use: f32 = ep
-                .components
-                .data
-                .iter()
-                .filter(|c| {
-                    idx_with_acs_use.contains(&c.id()) && c.is_out() && c.has_service(Service::ACS)
-                })
-                .map(HasValues::values_sum)
-                .sum();
-            alt_tot_dhw_use * fp_ren_fraction_dens_biomass
-        } else {
-            0.0
-        };
-        Q_biomass_an_ren + Q_dens_biomass_an_ren
+        Q_any_biomass_acs_an
+            * get_fpA_del_ren_fraction_dhw(biofuels[0], components, wfactors, tolerances)?
     } else {
-        // No hay ningún tipo de biomasa
-        0.0
+        // Cuando además de biomasa hay otros vectores que no son de distrito o insitu
+        // necesitamos saber qué cantidad de ACS produce cada tipo de biomasa para poder calcular
+        let mut Q_biomass_an_ren = 0.0;
+        for biofuel in biofuels {
+            Q_biomass_an_ren += Q_biofuel_out_an(biofuel, components)?
+                * get_fpA_del_ren_fraction_dhw(biofuel, components, wfactors, tolerances)?;
+        }
+        Q_biomass_an_ren
     };
 
     // 3. === Electricidad producida in situ (EL_INSITU) ===
@@ -464,20 +1318,14 @@ pub fn fraccion_renovable_acs_nrb(ep: &EnergyPerformance) -> Result<f32, EpbdErr
     // a) Fracción del consumo eléctrico para ACS que suponen los auxiliares
     let frac_non_aux_el_use_dhw = {
         let dhw_el_used_an = dhw_used_by_cr.get(&ELECTRICIDAD).unwrap_or(&0.0);
-        if dhw_el_used_an.abs() > f32::EPSILON {
+        if dhw_el_used_an.abs() > tolerances.zero {
             1.0 - (dhw_aux_use_an / dhw_el_used_an)
         } else {
             1.0
         }
     };
     // b) Producción in situ destinada a ACS, incluidos auxiliares de ACS
-    let prod_el_onst_dhw = bal
-        .prod
-        .epus_by_srv_by_src
-        .get(&ProdSource::EL_INSITU)
-        .and_then(|by_src| by_src.get(&Service::ACS))
-        .copied()
-        .unwrap_or_default();
+    let prod_el_onst_dhw = datos.prod_el_onst_dhw;
     // c) Producción insitu EL_INSITU destinada a ACS, excluidos auxiliares
     let Q_onst_el_an_ren = prod_el_onst_dhw * frac_non_aux_el_use_dhw;
 
@@ -486,27 +1334,21 @@ pub fn fraccion_renovable_acs_nrb(ep: &EnergyPerformance) -> Result<f32, EpbdErr
     // XXX: Duda: ¿es la cogeneración una fuente nearby solo cuando el vector que lo alimenta es nearby o siempre?
 
     // 1. Hay producción de electricidad cogenerada que se usa en ACS
-    let dhw_cogen_use = ep
-        .balance
-        .prod
-        .epus_by_srv_by_src
-        .get(&ProdSource::EL_COGEN)
-        .and_then(|s| s.get(&Service::ACS))
-        .cloned()
-        .unwrap_or_default();
+    let dhw_cogen_use = datos.dhw_cogen_use;
     // 2. La electricidad destinada a usos EPB va más allá de los auxiliares
     let dhw_el_use_no_aux_or_low_scop = dhw_used_by_cr_no_aux_or_low_scop
         .get(&ELECTRICIDAD)
         .cloned()
         .unwrap_or_default();
     // 3. La cogeneración se produce con algún vector del perímetro próximo
-    let cogen_sources: Vec<_> = ep
-        .components
+    let cogen_sources: Vec<_> = components
         .data
         .iter()
         .filter(|c| c.is_cogen_use())
         .collect();
-    let cogen_sources_has_nearby = cogen_sources.iter().any(|c| c.carrier().is_nearby());
+    let cogen_sources_has_nearby = cogen_sources
+        .iter()
+        .any(|c| components.is_nearby_carrier(c.carrier()));
     let Q_nrb_cogen_el_an_ren =
         if dhw_el_use_no_aux_or_low_scop > 0.0 && dhw_cogen_use > 0.0 && cogen_sources_has_nearby {
             // A diferencia de la generación in situ, la electricidad cogenerada se convierte en demanda
@@ -516,7 +1358,7 @@ pub fn fraccion_renovable_acs_nrb(ep: &EnergyPerformance) -> Result<f32, EpbdErr
             // f_ren_nrb = suma (f_pA_cr_i.ren * consumo_cogen_cr_i) cuando cr_i es nrb
             // f_tot = suma(f_pA_cr_i.tot * consumo_cogen_cr_i)
             let f_ren_cgn_nrb = {
-                let f_cgn_A = ep.wfactors.find(
+                let f_cgn_A = wfactors.find(
                     Carrier::ELECTRICIDAD,
                     Source::COGEN,
                     Dest::SUMINISTRO,
@@ -524,9 +1366,8 @@ pub fn fraccion_renovable_acs_nrb(ep: &EnergyPerformance) -> Result<f32, EpbdErr
                 )?;
                 let f_tot = f_cgn_A.ren + f_cgn_A.nren;
                 if f_tot > 0.0 {
-                    let f_cgn_ren_A = ep
-                        .wfactors
-                        .compute_cgn_exp_fP_A(&ep.components, true)?
+                    let f_cgn_ren_A = wfactors
+                        .compute_cgn_exp_fP_A(components, true)?
                         .unwrap_or_default()
                         .ren;
                     println!("f_cgn_ren_A: {f_cgn_ren_A:.3}, f_tot: {f_tot:.3}");
@@ -573,24 +1414,135 @@ fn get_fpA_del_ren_fraction(c: Carrier, wfactors: &Factors) -> Result<f32, EpbdE
         .map(|f| f.ren / (f.ren + f.nren))
 }
 
+/// Fracción renovable del factor de paso de suministro del vector, ponderada con sus consumos de ACS
+///
+/// Con factores de paso por paso de cálculo (p.e. con periodos de vigencia) la fracción renovable
+/// de cada paso se pondera con el consumo EPB de ACS del vector en ese paso (excluidos los marcados
+/// con CTEEPBD_EXCLUYE_SCOP_ACS), como en el balance por pasos de cálculo. Sin serie de factores por
+/// pasos o sin consumos se usa la fracción de los factores anuales.
+#[allow(non_snake_case)]
+fn get_fpA_del_ren_fraction_dhw(
+    c: Carrier,
+    components: &Components,
+    wfactors: &Factors,
+    tolerances: &Tolerances,
+) -> Result<f32, EpbdError> {
+    let fraction_an = get_fpA_del_ren_fraction(c, wfactors)?;
+    let Some(factor) = wfactors.wdata.iter().find(|f| {
+        f.carrier == c && f.source == Source::RED && f.dest == Dest::SUMINISTRO && f.step == Step::A
+    }) else {
+        return Ok(fraction_an);
+    };
+    if factor.values_t.is_empty() {
+        return Ok(fraction_an);
+    }
+    let mut used_t = vec![0.0; components.num_steps()];
+    for cmp in components.data.iter().filter(|cmp| {
+        cmp.is_used()
+            && cmp.is_epb_use()
+            && cmp.has_service(Service::ACS)
+            && cmp.has_carrier(c)
+            && !cmp.comment().contains("CTEEPBD_EXCLUYE_SCOP_ACS")
+    }) {
+        vecvecsum_assign(&mut used_t, cmp.values());
+    }
+    let used_an: f32 = used_t.iter().sum();
+    if used_an.abs() < tolerances.zero {
+        return Ok(fraction_an);
+    }
+    let used_ren_an: f32 = used_t
+        .iter()
+        .enumerate()
+        .map(|(i, used)| {
+            let f = factor.factors_at(i);
+            let f_tot = f.ren + f.nren;
+            if f_tot > 0.0 {
+                used * f.ren / f_tot
+            } else {
+                0.0
+            }
+        })
+        .sum();
+    Ok(used_ren_an / used_an)
+}
+
+/// Vectores de tipo biomasa del perímetro próximo, cuya conversión a demanda depende del rendimiento del sistema
+///
+/// Incluye BIOMASA y BIOMASADENSIFICADA y, si se indica con el metadato `CTE_BIOCARBURANTE_PROXIMO`, el BIOCARBURANTE
+fn biofuels_nrb(components: &Components) -> Vec<Carrier> {
+    [
+        Carrier::BIOMASA,
+        Carrier::BIOMASADENSIFICADA,
+        Carrier::BIOCARBURANTE,
+    ]
+    .into_iter()
+    .filter(|c| components.is_nearby_carrier(*c))
+    .collect()
+}
+
+#[allow(non_snake_case)]
+/// Energía de ACS entregada anualmente por los sistemas que consumen el vector indicado
+///
+/// Requiere la definición de la energía entregada (SALIDA) para el servicio de ACS en esos sistemas
+fn Q_biofuel_out_an(carrier: Carrier, components: &Components) -> Result<f32, EpbdError> {
+    // Id de sistemas con uso del vector para ACS
+    let idx_with_acs_use = components
+        .data
+        .iter()
+        .filter(|c| c.is_used() && c.has_service(Service::ACS) && c.has_carrier(carrier))
+        .map(|c| c.id())
+        .collect::<HashSet<i32>>();
+    // Comprobar que se ha definido la salida de ACS para esos equipos
+    for idx in &idx_with_acs_use {
+        if !components
+            .data
+            .iter()
+            .any(|c| c.has_id(*idx) && c.is_out() && c.has_service(Service::ACS))
+        {
+            let desc = match carrier {
+                Carrier::BIOCARBURANTE => "biocarburante",
+                _ => "biomasa",
+            };
+            return Err(EpbdError::WrongInput(format!(
+                "Uso de {} en el sistema con id:{} sin definición de la energía entregada para el servicio de ACS.",
+                desc, idx
+            )));
+        }
+    }
+    // Suma de demandas de ACS salientes de equipos con consumo del vector
+    Ok(components
+        .data
+        .iter()
+        .filter(|c| idx_with_acs_use.contains(&c.id()) && c.is_out() && c.has_service(Service::ACS))
+        .map(HasValues::values_sum)
+        .sum())
+}
+
 #[allow(non_snake_case)]
 /// Demanda total y renovable de los consumos de ACS cubierto por vectores nearby que no sean biomasa
 /// (EAMBIENTE, RED1, RED2 o TERMOSOLAR)
 ///
 fn Q_nrb_non_biomass_an(
-    dhw_used_by_cr_no_aux_or_low_scop: &HashMap<Carrier, f32>,
-    ep: &EnergyPerformance,
+    dhw_used_by_cr_no_aux_or_low_scop: &BTreeMap<Carrier, f32>,
+    components: &Components,
+    wfactors: &Factors,
+    tolerances: &Tolerances,
 ) -> Result<(f32, f32), EpbdError> {
-    use Carrier::{BIOMASA, BIOMASADENSIFICADA};
+    let biofuels = biofuels_nrb(components);
 
     let (mut tot, mut ren) = (0.0, 0.0);
 
     if !dhw_used_by_cr_no_aux_or_low_scop.is_empty() {
         // Energía usada en vectores nearby que no son biomasa
         for (carrier, us) in dhw_used_by_cr_no_aux_or_low_scop {
-            if carrier.is_nearby() && *carrier != BIOMASA && *carrier != BIOMASADENSIFICADA {
+            if components.is_nearby_carrier(*carrier) && !biofuels.contains(carrier) {
                 tot += us;
-                ren += us * get_fpA_del_ren_fraction(*carrier, &ep.wfactors)?;
+                ren += if *carrier == Carrier::RED_CALOR_RESIDUAL {
+                    // El calor residual sustituye a la contribución renovable
+                    *us
+                } else {
+                    us * get_fpA_del_ren_fraction_dhw(*carrier, components, wfactors, tolerances)?
+                };
             }
         }
     }
@@ -0,0 +1,194 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Coma decimal
+============
+
+Lectura tolerante de archivos de componentes y de factores de paso exportados desde hojas de cálculo
+con configuración regional española, que usan la coma como separador decimal y el punto y coma como
+separador de campos (p.e. `1; CONSUMO; CAL; ELECTRICIDAD; 10,5; 20,3`).
+*/
+
+use std::fmt;
+
+/// Conversión de coma decimal aplicada a una línea
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecimalCommaConversion {
+    /// Número de línea (desde 1)
+    pub line: usize,
+    /// Contenido original de la línea
+    pub original: String,
+    /// Contenido convertido de la línea
+    pub converted: String,
+}
+
+impl fmt::Display for DecimalCommaConversion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "línea {}: coma decimal convertida: \"{}\" -> \"{}\"",
+            self.line, self.original, self.converted
+        )
+    }
+}
+
+/// ¿Usan todas las líneas de datos el punto y coma como separador de campos?
+///
+/// Se consideran líneas de datos las no vacías que no son comentarios y los valores de los metadatos
+/// con más de un campo. Devuelve `false` si no hay ninguna línea con punto y coma.
+pub fn uses_decimal_comma(s: &str) -> bool {
+    let mut found = false;
+    for line in s.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let content = match line.strip_prefix('#') {
+            Some(meta) if is_meta(line) => meta.split_once(':').map(|(_, v)| v).unwrap_or(""),
+            Some(_) => continue,
+            None => data_part(line),
+        };
+        if content.contains(';') {
+            found = true;
+        } else if !is_meta(line) {
+            return false;
+        }
+    }
+    found
+}
+
+/// Convierte a punto decimal las líneas que usan punto y coma como separador de campos
+///
+/// En esas líneas, las comas se sustituyen por puntos y los puntos y comas por comas, tanto en los datos
+/// como en los valores de los metadatos. También se convierten los metadatos con un único valor numérico
+/// con coma decimal (p.e. `#META CTE_AREAREF: 100,5`). Los comentarios y el resto de líneas no se modifican.
+/// Devuelve el texto convertido y la lista de conversiones aplicadas.
+pub fn decimal_comma_to_point(s: &str) -> (String, Vec<DecimalCommaConversion>) {
+    let mut conversions = Vec::new();
+    let lines: Vec<String> = s
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let (head, content, tail) = split_line(line);
+            let is_meta_number = !head.is_empty() && is_decimal_comma_number(content);
+            if !(content.contains(';') || is_meta_number) {
+                return line.to_string();
+            }
+            let converted = format!(
+                "{}{}{}",
+                head,
+                content.replace(',', ".").replace(';', ","),
+                tail
+            );
+            conversions.push(DecimalCommaConversion {
+                line: i + 1,
+                original: line.trim().to_string(),
+                converted: converted.trim().to_string(),
+            });
+            converted
+        })
+        .collect();
+    let mut converted = lines.join("\n");
+    if s.ends_with('\n') {
+        converted.push('\n');
+    }
+    (converted, conversions)
+}
+
+/// ¿Es un único número con coma decimal (p.e. `-100,5`)?
+fn is_decimal_comma_number(value: &str) -> bool {
+    value.trim().split_once(',').is_some_and(|(int, dec)| {
+        let int = int.strip_prefix('-').unwrap_or(int);
+        !int.is_empty()
+            && !dec.is_empty()
+            && int.chars().all(|c| c.is_ascii_digit())
+            && dec.chars().all(|c| c.is_ascii_digit())
+    })
+}
+
+/// ¿Es una línea de metadatos?
+fn is_meta(line: &str) -> bool {
+    line.starts_with("#META") || line.starts_with("#CTE_")
+}
+
+/// Parte de datos de una línea, sin el comentario
+fn data_part(line: &str) -> &str {
+    line.split('#').next().unwrap_or("")
+}
+
+/// Divide una línea en prefijo, contenido convertible y resto no convertible
+///
+/// El contenido convertible es el valor de los metadatos o la parte de datos del resto de líneas.
+/// Las líneas de comentario no tienen contenido convertible.
+fn split_line(line: &str) -> (&str, &str, &str) {
+    let trimmed = line.trim_start();
+    if trimmed.starts_with('#') {
+        if !is_meta(trimmed) {
+            return (line, "", "");
+        }
+        return match line.find(':') {
+            Some(pos) => (&line[..=pos], &line[pos + 1..], ""),
+            None => (line, "", ""),
+        };
+    }
+    let data = data_part(line);
+    ("", data, &line[data.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn decimal_comma() {
+        let input = "#META CTE_AREAREF: 100,5
+#META CTE_RED1: 0,0; 1,3; 0,3
+#META CTE_COMENTARIO: Texto, con comas
+# Comentario; con punto y coma
+1; CONSUMO; CAL; ELECTRICIDAD; 10,5; 20 # Comentario, con coma
+";
+        assert!(uses_decimal_comma(input));
+        let (converted, conversions) = decimal_comma_to_point(input);
+        assert_eq!(
+            converted,
+            "#META CTE_AREAREF: 100.5
+#META CTE_RED1: 0.0, 1.3, 0.3
+#META CTE_COMENTARIO: Texto, con comas
+# Comentario; con punto y coma
+1, CONSUMO, CAL, ELECTRICIDAD, 10.5, 20 # Comentario, con coma
+"
+        );
+        assert_eq!(
+            conversions.iter().map(|c| c.line).collect::<Vec<_>>(),
+            vec![1, 2, 5]
+        );
+        // Sin punto y coma no se detecta ni se convierte
+        let input = "1, CONSUMO, CAL, ELECTRICIDAD, 10.5, 20";
+        assert!(!uses_decimal_comma(input));
+        assert_eq!(decimal_comma_to_point(input), (input.to_string(), vec![]));
+        // Archivos con separadores mezclados no se detectan
+        assert!(!uses_decimal_comma(
+            "1; CONSUMO; CAL; ELECTRICIDAD; 10,5\n1, CONSUMO, ACS, ELECTRICIDAD, 1.5"
+        ));
+    }
+}
@@ -28,39 +28,279 @@ Gestión de errores (error handling)
 ===================================
 
 Tipos y funciones para la gestión de errores
+
+El error raíz de la librería es [`EpbdError`]. Los errores de cada subsistema añaden el contexto
+en el que se produjo el error original, que se mantiene como causa (`source`) del error:
+
+- [`ParseError`]: interpretación de datos de entrada, con la línea en la que se produjo
+- [`BalanceError`]: cálculo del balance, con el vector energético implicado
+- [`CteError`]: cálculo de indicadores CTE, con el indicador implicado
+
+El tipo de error original puede consultarse con [`EpbdError::root`].
 */
 
-use std::fmt;
+use thiserror::Error;
+
+use crate::types::Carrier;
 
 /// Resultado que usa el tipo de error personalizado
 pub type Result<T> = std::result::Result<T, EpbdError>;
 
 /// Errores definidos para la librería y aplicación cteepbd
-#[derive(Debug)]
+///
+/// Los errores con contexto se envuelven en las variantes `Parse`, `Balance` y `Cte`, por lo
+/// que para comprobar el tipo de error original debe usarse [`EpbdError::root`] (p.e.
+/// `matches!(err.root(), EpbdError::ParseError(_))`).
+#[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum EpbdError {
     /// Error al interpretar un valor
+    #[error("Error de formato: {0}")]
     ParseError(String),
     /// Error para un valor de entrada incorrecto (formato o rango incorrecto)
+    #[error("Valor de entrada incorrecto: {0}")]
     WrongInput(String),
     /// Error cuando falta un factor de conversión
+    #[error("Factor de paso no encontrado: {0}")]
     MissingFactor(String),
+    /// Cálculo cancelado a petición del usuario (ver `CancelToken`)
+    #[error("Cálculo cancelado")]
+    Cancelled,
+    /// Error en una línea de los datos de entrada
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+    /// Error en el balance de un vector energético
+    #[error(transparent)]
+    Balance(#[from] BalanceError),
+    /// Error en el cálculo de un indicador CTE
+    #[error(transparent)]
+    Cte(#[from] CteError),
+}
+
+/// Error de interpretación de los datos de entrada en una línea (comenzando en 1)
+#[derive(Debug, Error)]
+#[error("{}: línea {line}: {}", .source.kind(), .source.detail())]
+pub struct ParseError {
+    /// Línea de los datos de entrada (comenzando en 1)
+    pub line: usize,
+    /// Error original
+    #[source]
+    pub source: Box<EpbdError>,
 }
 
-impl fmt::Display for EpbdError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        use EpbdError::*;
+/// Error en el cálculo del balance de un vector energético
+#[derive(Debug, Error)]
+#[error("{}: vector {carrier}: {}", .source.kind(), .source.detail())]
+pub struct BalanceError {
+    /// Vector energético cuyo balance produjo el error
+    pub carrier: Carrier,
+    /// Error original
+    #[source]
+    pub source: Box<EpbdError>,
+}
+
+/// Error en el cálculo de un indicador CTE
+#[derive(Debug, Error)]
+#[error("{}: indicador {indicator}: {}", .source.kind(), .source.detail())]
+pub struct CteError {
+    /// Indicador cuyo cálculo produjo el error (p.e. fraccion_renovable_demanda_acs_nrb)
+    pub indicator: String,
+    /// Error original
+    #[source]
+    pub source: Box<EpbdError>,
+}
+
+impl EpbdError {
+    /// Añade al error el número de línea (comenzando en 1) en el que se produjo
+    pub fn at_line(self, line: usize) -> Self {
+        match self {
+            EpbdError::Cancelled => self,
+            _ => ParseError {
+                line,
+                source: Box::new(self),
+            }
+            .into(),
+        }
+    }
+
+    /// Añade al error el vector energético cuyo balance lo produjo
+    pub fn for_carrier(self, carrier: Carrier) -> Self {
+        match self {
+            EpbdError::Cancelled => self,
+            _ => BalanceError {
+                carrier,
+                source: Box::new(self),
+            }
+            .into(),
+        }
+    }
+
+    /// Añade al error el indicador CTE cuyo cálculo lo produjo
+    pub fn for_indicator(self, indicator: &str) -> Self {
         match self {
-            ParseError(v) => write!(f, "Error de formato: {}", v),
-            WrongInput(v) => write!(f, "Valor de entrada incorrecto: {}", v),
-            MissingFactor(v) => write!(f, "Factor de paso no encontrado: {}", v),
+            EpbdError::Cancelled => self,
+            _ => CteError {
+                indicator: indicator.to_string(),
+                source: Box::new(self),
+            }
+            .into(),
         }
     }
+
+    /// Error original, sin el contexto añadido por los subsistemas
+    pub fn root(&self) -> &EpbdError {
+        match self {
+            EpbdError::Parse(e) => e.source.root(),
+            EpbdError::Balance(e) => e.source.root(),
+            EpbdError::Cte(e) => e.source.root(),
+            _ => self,
+        }
+    }
+
+    /// Código del tipo de error original, para registros y métricas
+    ///
+    /// Es uno de `parse`, `wrong_input`, `missing_factor` o `cancelled`.
+    pub fn code(&self) -> &'static str {
+        match self.root() {
+            EpbdError::ParseError(_) => "parse",
+            EpbdError::WrongInput(_) => "wrong_input",
+            EpbdError::MissingFactor(_) => "missing_factor",
+            _ => "cancelled",
+        }
+    }
+
+    /// Descripción del tipo de error original
+    fn kind(&self) -> &'static str {
+        match self.root() {
+            EpbdError::ParseError(_) => "Error de formato",
+            EpbdError::WrongInput(_) => "Valor de entrada incorrecto",
+            EpbdError::MissingFactor(_) => "Factor de paso no encontrado",
+            _ => "Cálculo cancelado",
+        }
+    }
+
+    /// Mensaje del error, con su contexto y sin la descripción del tipo de error original
+    fn detail(&self) -> String {
+        match self {
+            EpbdError::ParseError(msg)
+            | EpbdError::WrongInput(msg)
+            | EpbdError::MissingFactor(msg) => msg.clone(),
+            EpbdError::Cancelled => String::new(),
+            EpbdError::Parse(e) => format!("línea {}: {}", e.line, e.source.detail()),
+            EpbdError::Balance(e) => format!("vector {}: {}", e.carrier, e.source.detail()),
+            EpbdError::Cte(e) => format!("indicador {}: {}", e.indicator, e.source.detail()),
+        }
+    }
+}
+
+/// Error de interpretación de un valor que debe pertenecer a una lista de opciones
+///
+/// Incluye una sugerencia de corrección cuando hay una opción suficientemente parecida
+/// o, en otro caso, la lista de valores admitidos.
+pub(crate) fn unknown_value_error(kind: &str, value: &str, options: &[&str]) -> EpbdError {
+    let msg = match suggestion(value, options) {
+        Some(option) => format!(
+            "{} desconocido \"{}\" (¿quiso decir {}?)",
+            kind, value, option
+        ),
+        None => format!(
+            "{} desconocido \"{}\" (valores admitidos: {})",
+            kind,
+            value,
+            options.join(", ")
+        ),
+    };
+    EpbdError::ParseError(msg)
 }
 
-impl std::error::Error for EpbdError {}
+/// Opción más parecida a un valor, según la distancia de edición (sin distinguir mayúsculas)
+///
+/// Solo se sugieren opciones a una distancia no mayor de un tercio de su longitud (o 1, en las más cortas)
+/// y menor que su longitud
+pub fn suggestion<'a>(value: &str, options: &[&'a str]) -> Option<&'a str> {
+    let value = value.trim().to_uppercase();
+    if value.is_empty() {
+        return None;
+    }
+    options
+        .iter()
+        .map(|option| (edit_distance(&value, &option.to_uppercase()), *option))
+        .filter(|(dist, option)| {
+            let len = option.chars().count();
+            *dist <= (len / 3).max(1) && *dist < len
+        })
+        .min_by_key(|(dist, _)| *dist)
+        .map(|(_, option)| option)
+}
+
+/// Distancia de edición (Levenshtein) entre dos cadenas
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
 
 impl From<std::num::ParseFloatError> for EpbdError {
     fn from(err: std::num::ParseFloatError) -> Self {
         EpbdError::ParseError(err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn suggestions() {
+        let options = ["SUMINISTRO", "A_RED", "A_NEPB"];
+        assert_eq!(Some("SUMINISTRO"), suggestion("SUMINSTRO", &options));
+        assert_eq!(Some("A_RED"), suggestion("A_REDD", &options));
+        assert_eq!(Some("A_NEPB"), suggestion("a_nepb", &options));
+        assert_eq!(None, suggestion("OTRO", &options));
+        assert_eq!(None, suggestion("", &options));
+        let err = unknown_value_error("destino", "SUMINSTRO", &options).at_line(3);
+        assert!(matches!(err.root(), EpbdError::ParseError(_)));
+        assert_eq!(
+            "Error de formato: línea 3: destino desconocido \"SUMINSTRO\" (¿quiso decir SUMINISTRO?)",
+            unknown_value_error("destino", "SUMINSTRO", &options)
+                .at_line(3)
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn chained_context() {
+        use std::error::Error;
+
+        let err = EpbdError::MissingFactor("GASOLEO, RED, SUMINISTRO, A".into())
+            .for_carrier(Carrier::GASOLEO)
+            .for_indicator("rer_directiva");
+        assert_eq!(
+            "Factor de paso no encontrado: indicador rer_directiva: vector GASOLEO: GASOLEO, RED, SUMINISTRO, A",
+            err.to_string()
+        );
+        assert!(matches!(err.root(), EpbdError::MissingFactor(_)));
+        // Cadena de causas: indicador -> vector -> error original
+        let balance_err = err.source().unwrap();
+        assert!(balance_err.to_string().contains("vector GASOLEO"));
+        let root_err = balance_err.source().unwrap();
+        assert_eq!(
+            "Factor de paso no encontrado: GASOLEO, RED, SUMINISTRO, A",
+            root_err.to_string()
+        );
+        assert!(root_err.source().is_none());
+        assert!(matches!(
+            EpbdError::Cancelled.for_carrier(Carrier::GASOLEO),
+            EpbdError::Cancelled
+        ));
+    }
+}
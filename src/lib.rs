@@ -40,13 +40,12 @@ It also holds the following assumptions:
 - constant weighting factors through all timesteps
 - no priority is defined for energy production (average step A weighting factor f_we_el_stepA)
 - all on-site produced energy from non cogeneration sources is considered as delivered
-- on-site produced energy is not compensated on a service by service basis, but on a carrier basis
+- on-site produced energy is compensated on a carrier basis, unless it is imputed to a specific service
 - unit and constant load matching factor
 
 Some restrictions may be lifted in the future. Specifically:
 
 - implement a load matching factor (f_match_t) following formula B.32 in appendix B
-- allow the imputation to a specific service for produced energy
 - allow setting priorities for energy production
 
 Este *crate* proporciona una biblioteca y un programa que **implementa una parte sustancial del
@@ -62,21 +61,60 @@ También realiza los siguientes supuestos:
 - factores de paso constantes en todo el periodo de cálculo
 - no se definen prioridades para la producción de energía
 - se considera como suministrada toda la energía producida procedente de fuentes distintas a la cogeneración
-- la energía producida in situ se compensa por vector energético y no por servicios
+- la energía producida in situ se compensa por vector energético, salvo que se impute a un servicio concreto
 - factor de coincidencia de cargas igual a la unidad
 
 Algunas restricciones pueden revisarse en el futuro, tales como:
 
 - implementación del factor de coincidencia de cargas según fórmula B.32 del apéndice B
-- imputación de energía generada a servicios específicos
 - fijación de prioridades para la producción de energía
 
+# Reproducibilidad
+
+El cálculo es determinista: con el mismo binario y los mismos datos de entrada se obtienen
+los mismos resultados bit a bit. No se usa paralelismo ni fuentes aleatorias, las operaciones
+se acumulan en el orden de los componentes y los mapas de resultados (`BTreeMap`) se recorren
+y serializan siempre en el mismo orden. Cualquier cálculo en paralelo que se incorpore debe
+mantener esta garantía (p.e., reuniendo los resultados en el orden de entrada).
+
+The computation is deterministic: the same binary and input data give bit-for-bit identical results.
+
+# Preludio
+
+El módulo [`prelude`] reúne la superficie pública mínima de la biblioteca (cálculo del balance,
+componentes, factores de paso, resultados, errores y formatos de salida), que puede importarse con
+`use cteepbd::prelude::*;`. Junto con los módulos públicos, es la parte documentada de la API.
+Las reexportaciones de la raíz del *crate* se mantienen por compatibilidad, ocultas en la
+documentación.
+
+The [`prelude`] module holds the documented public surface; crate root re-exports are hidden.
+
+# Features
+
+- `cte` (por defecto): factores de paso reglamentarios, indicadores y utilidades del CTE (módulo [`cte`])
+- `formats-plain` (por defecto): salida en formato de texto plano (incluye `cte`)
+- `formats-xml` (por defecto): salida y lectura en formato XML
+- `formats-svg` (por defecto): diagrama Sankey de flujos de energía en formato SVG
+- `formats-tidy` (por defecto): resultados por vector en formato de tabla larga (JSON o CSV)
+- `formats-html` (por defecto): informe autocontenido en formato HTML (incluye `formats-svg`)
+- `cli` (por defecto): programa de línea de comandos (incluye todas las anteriores)
+- `net`: descarga de tablas de factores de paso desde una URL remota con caché local
+- `pvgis`: importación de series de producción fotovoltaica de PVGIS (con `net`, también su descarga)
+- `monitoring`: importación de series de monitorización (CSV de contadores con mapeo en TOML)
+- `metrics`: métricas del cálculo en lote en formato Prometheus y registro estructurado de cada caso en JSON
+- `parallel`: cálculo en paralelo (con rayon) de los balances de cada vector energético, útil en casos con muchos vectores y datos horarios, y de los casos del cálculo en lote (`compute_many_parallel`)
+
+Sin las *features* por defecto (`default-features = false`) se compila solo el núcleo de
+cálculo del balance según EN ISO 52000-1, con menos dependencias, p.e. para usos embebidos o wasm.
+
 # Ejemplo
 
 ```rust
 use std::fs::{read_to_string, File};
-use cteepbd::*;
+use cteepbd::{cte, prelude::*};
 
+# #[cfg(all(feature = "cte", feature = "formats-plain"))]
+# {
 // lectura de un archivo de componentes energéticos
 let components = read_to_string("test_data/cte_test_carriers.csv")
     .unwrap()
@@ -84,10 +122,7 @@ let components = read_to_string("test_data/cte_test_carriers.csv")
     .unwrap();
 
 // Definición de los factores de usuario y sus valores por defecto
-let user_wf = UserWF {
-    red1: Some((1.0, 0.0, 0.0).into()),
-    red2: None,
-}; // Factores definidos por el usuario
+let user_wf = UserWF::default().with_red1(Some((1.0, 0.0, 0.0).into())); // Factores definidos por el usuario
 let default_user_wf = cte::CTE_USERWF; // Valores por defecto de factores de paso del usuario
 
 // Factores de usuario reglamentarios según localización y factores de usuario
@@ -102,11 +137,12 @@ let kexp = cte::KEXP_DEFAULT; // factor de exportación [-]
 let arearef = 200.0; // superficie de referencia [m2]
 
 // Cálculo del balance global según EN ISO 52000-1:2017
-let load_matching = false;
+let load_matching = LoadMatchingMode::Constant; // factor de coincidencia de cargas
 let ep = energy_performance(&components, &fp, kexp, arearef, load_matching).unwrap();
 
 // Visualización compacta
 println!("{}", &ep.to_plain());
+# }
 ```
 
 */
@@ -117,21 +153,76 @@ println!("{}", &ep.to_plain());
 #[macro_use]
 extern crate pretty_assertions;
 
+#[cfg(feature = "formats-xml")]
 mod asctexml;
+#[cfg(feature = "formats-html")]
+mod ashtml;
+#[cfg(feature = "formats-plain")]
 mod asplain;
+#[cfg(feature = "formats-svg")]
+mod assvg;
+#[cfg(feature = "formats-tidy")]
+mod astidy;
 mod balance;
+mod batch;
 mod components;
+mod container;
+mod decimal;
+#[cfg(any(feature = "formats-xml", feature = "formats-svg"))]
+mod escape;
+mod measures;
+mod model;
 mod vecops;
+mod visor;
 mod wfactors;
 
+#[cfg(feature = "cte")]
 pub mod cte;
 pub mod error;
+pub mod prelude;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "monitoring")]
+pub mod monitoring;
+#[cfg(feature = "pvgis")]
+pub mod pvgis;
+#[cfg(feature = "net")]
+pub mod remote;
 pub mod types;
 
+// Reexportaciones de compatibilidad, fuera de la superficie pública documentada (ver `prelude`)
+#[cfg(feature = "formats-xml")]
+#[doc(hidden)]
 pub use asctexml::*;
+#[cfg(feature = "formats-html")]
+#[doc(hidden)]
+pub use ashtml::*;
+#[cfg(feature = "formats-plain")]
+#[doc(hidden)]
 pub use asplain::*;
+#[cfg(feature = "formats-svg")]
+#[doc(hidden)]
+pub use assvg::*;
+#[cfg(feature = "formats-tidy")]
+#[doc(hidden)]
+pub use astidy::*;
+#[doc(hidden)]
 pub use balance::*;
+#[doc(hidden)]
+pub use batch::*;
+#[doc(hidden)]
 pub use components::*;
+#[doc(hidden)]
+pub use container::*;
+#[doc(hidden)]
+pub use decimal::*;
+#[doc(hidden)]
+pub use measures::*;
+#[doc(hidden)]
+pub use model::*;
+#[doc(hidden)]
+pub use visor::*;
+#[doc(hidden)]
 pub use wfactors::*;
 
 /// Número de versión de la librería
@@ -0,0 +1,479 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Medidas de mejora
+=================
+
+Análisis incremental del impacto de medidas de mejora (p.e. para informes de rehabilitación).
+
+A partir de los componentes del caso base y de una lista de medidas definidas de forma
+declarativa (p.e. añadir 3 kWp de fotovoltaica o sustituir una caldera por una bomba de calor)
+se generan los componentes modificados de cada variante, se calcula su eficiencia energética y
+se obtienen los ahorros de cada medida, aplicada individualmente sobre el caso base, y del
+conjunto de todas ellas.
+*/
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    balance::{energy_performance_from, energy_performance_with},
+    error::{EpbdError, Result},
+    types::{
+        CalcOptions, Carrier, EProd, EUsed, Energy, EnergyPerformance, HasValues, ProdSource,
+        RenNrenCo2, Service, SystemPeakPower,
+    },
+    vecops::vecvecsum_assign,
+    Components, Factors,
+};
+
+/// Medida de mejora, definida de forma declarativa
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Measure {
+    /// Añade un sistema de producción fotovoltaica in situ
+    ///
+    /// La producción de cada paso de cálculo es la potencia pico por la producción específica
+    /// del paso (p.e. obtenida de PVGIS). El nuevo sistema usa el primer id libre y se declara
+    /// su potencia pico.
+    AddPv {
+        /// Potencia pico, kWp
+        kwp: f32,
+        /// Producción específica de cada paso de cálculo, kWh/kWp
+        yield_t: Vec<f32>,
+    },
+    /// Sustituye un sistema por otro con el vector energético y el rendimiento indicados
+    ///
+    /// El nuevo sistema entrega, para cada servicio, la misma energía que el sistema sustituido
+    /// (SALIDA, declarada o calculada con su rendimiento) y consume E_out / rendimiento.
+    /// Se sustituyen todos los consumos del sistema para esos servicios, salvo los auxiliares.
+    ReplaceSystem {
+        /// Id del sistema sustituido
+        id: i32,
+        /// Vector energético del nuevo sistema
+        carrier: Carrier,
+        /// Rendimiento estacional del nuevo sistema (E_out / E_in)
+        efficiency: f32,
+    },
+    /// Sustituye un sistema por una bomba de calor eléctrica
+    ///
+    /// Como [`Measure::ReplaceSystem`], con un consumo eléctrico E_out / SCOP y, en los
+    /// servicios de calefacción y ACS, un consumo de energía ambiente (EAMBIENTE) igual a
+    /// E_out - E_out / SCOP.
+    HeatPump {
+        /// Id del sistema sustituido
+        id: i32,
+        /// Rendimiento estacional de la bomba de calor (SCOP)
+        scop: f32,
+    },
+    /// Reduce los consumos de un servicio en la fracción indicada (p.e. mejora de la envolvente)
+    ///
+    /// Se reducen en la misma proporción los consumos, la energía entregada (SALIDA) y la
+    /// demanda (del edificio y de sus zonas) del servicio. Los consumos auxiliares se mantienen.
+    ReduceUse {
+        /// Servicio
+        service: Service,
+        /// Fracción de reducción de los consumos [0, 1]
+        fraction: f32,
+    },
+}
+
+impl fmt::Display for Measure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Measure::AddPv { kwp, .. } => write!(f, "Fotovoltaica de {:.1} kWp", kwp),
+            Measure::ReplaceSystem {
+                id,
+                carrier,
+                efficiency,
+            } => write!(
+                f,
+                "Sustitución del sistema {} por otro de {} (rendimiento {:.2})",
+                id, carrier, efficiency
+            ),
+            Measure::HeatPump { id, scop } => write!(
+                f,
+                "Sustitución del sistema {} por bomba de calor (SCOP {:.2})",
+                id, scop
+            ),
+            Measure::ReduceUse { service, fraction } => write!(
+                f,
+                "Reducción del {:.0} % de los consumos de {}",
+                100.0 * fraction,
+                service
+            ),
+        }
+    }
+}
+
+impl Measure {
+    /// Componentes modificados con la medida
+    ///
+    /// La producción in situ de energía ambiente y solar térmica de cada sistema se ajusta a
+    /// sus consumos modificados.
+    ///
+    /// # Errors
+    ///
+    /// * Parámetros de la medida fuera de rango o incoherentes con los componentes
+    /// * Sustitución de un sistema sin energía entregada (SALIDA) ni rendimiento declarado
+    pub fn apply(&self, components: &Components) -> Result<Components> {
+        let mut components = components.clone();
+        let onsite_before = onsite_uses(&components);
+        match self {
+            Measure::AddPv { kwp, yield_t } => add_pv(&mut components, *kwp, yield_t),
+            Measure::ReplaceSystem {
+                id,
+                carrier,
+                efficiency,
+            } => replace_system(&mut components, *id, *carrier, *efficiency, false),
+            Measure::HeatPump { id, scop } => {
+                replace_system(&mut components, *id, Carrier::ELECTRICIDAD, *scop, true)
+            }
+            Measure::ReduceUse { service, fraction } => {
+                reduce_use(&mut components, *service, *fraction)
+            }
+        }
+        .map_err(|e| EpbdError::WrongInput(format!("medida \"{}\": {}", self, e)))?;
+        balance_onsite_production(&mut components, &onsite_before);
+        Ok(components)
+    }
+}
+
+/// Añade la producción de un sistema fotovoltaico y declara su potencia pico
+fn add_pv(components: &mut Components, kwp: f32, yield_t: &[f32]) -> Result<()> {
+    if kwp <= 0.0 {
+        return Err(EpbdError::WrongInput(format!(
+            "potencia pico no positiva ({})",
+            kwp
+        )));
+    }
+    if yield_t.len() != components.num_steps() {
+        return Err(EpbdError::WrongInput(format!(
+            "la producción específica tiene {} valores y los componentes {} pasos de cálculo",
+            yield_t.len(),
+            components.num_steps()
+        )));
+    }
+    let id = components
+        .data
+        .iter()
+        .map(Energy::id)
+        .chain(components.peak_powers.iter().map(|p| p.id))
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let comment = format!("Medida de mejora: fotovoltaica de {:.1} kWp", kwp);
+    components.data.push(Energy::Prod(EProd {
+        id,
+        source: ProdSource::EL_INSITU,
+        values: yield_t.iter().map(|v| v * kwp).collect(),
+        comment: comment.clone(),
+    }));
+    components.peak_powers.push(SystemPeakPower {
+        id,
+        value: kwp,
+        comment,
+    });
+    Ok(())
+}
+
+/// Sustituye los consumos de un sistema por los de otro que entrega la misma energía
+///
+/// Con `heat_pump`, la diferencia entre la energía entregada y el consumo para calefacción y
+/// ACS se cubre con energía ambiente.
+fn replace_system(
+    components: &mut Components,
+    id: i32,
+    carrier: Carrier,
+    efficiency: f32,
+    heat_pump: bool,
+) -> Result<()> {
+    if efficiency <= 0.0 {
+        return Err(EpbdError::WrongInput(format!(
+            "rendimiento no positivo ({})",
+            efficiency
+        )));
+    }
+    // Energía entregada (o absorbida, en valor absoluto) por el sistema para cada servicio
+    let mut out_by_srv: BTreeMap<Service, Vec<f32>> = BTreeMap::new();
+    for c in components
+        .data
+        .iter()
+        .filter(|c| c.is_out() && c.id() == id)
+    {
+        let values: Vec<f32> = c.values().iter().map(|v| v.abs()).collect();
+        out_by_srv
+            .entry(c.service())
+            .and_modify(|e| vecvecsum_assign(e, &values))
+            .or_insert(values);
+    }
+    if out_by_srv.is_empty() {
+        return Err(EpbdError::WrongInput(format!(
+            "el sistema {} no tiene energía entregada (SALIDA) ni rendimiento declarado",
+            id
+        )));
+    }
+
+    components
+        .data
+        .retain(|c| !(c.is_used() && c.id() == id && out_by_srv.contains_key(&c.service())));
+    let comment = "Medida de mejora: sustitución del sistema".to_string();
+    for (service, out) in out_by_srv {
+        let used: Vec<f32> = out.iter().map(|v| v / efficiency).collect();
+        if heat_pump && matches!(service, Service::CAL | Service::ACS) {
+            components.data.push(Energy::Used(EUsed {
+                id,
+                carrier: Carrier::EAMBIENTE,
+                service,
+                values: out
+                    .iter()
+                    .zip(&used)
+                    .map(|(o, u)| (o - u).max(0.0))
+                    .collect(),
+                comment: comment.clone(),
+            }));
+        }
+        components.data.push(Energy::Used(EUsed {
+            id,
+            carrier,
+            service,
+            values: used,
+            comment: comment.clone(),
+        }));
+        for eff in components
+            .efficiencies
+            .iter_mut()
+            .filter(|e| e.id == id && e.service == service)
+        {
+            eff.value = efficiency;
+        }
+    }
+    Ok(())
+}
+
+/// Reduce los consumos, la energía entregada y la demanda de un servicio
+fn reduce_use(components: &mut Components, service: Service, fraction: f32) -> Result<()> {
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(EpbdError::WrongInput(format!(
+            "fracción de reducción fuera del rango [0, 1] ({})",
+            fraction
+        )));
+    }
+    let factor = 1.0 - fraction;
+    for c in components
+        .data
+        .iter_mut()
+        .filter(|c| (c.is_used() || c.is_out()) && c.service() == service)
+    {
+        c.values_mut().iter_mut().for_each(|v| *v *= factor);
+    }
+    components.needs.scale_service(service, factor);
+    Ok(())
+}
+
+/// ¿Es un vector de producción in situ equilibrada con su consumo (energía ambiente o solar térmica)?
+fn is_onsite_balanced(carrier: Carrier) -> bool {
+    carrier.is_ambient() || carrier == Carrier::TERMOSOLAR
+}
+
+/// Consumo de energía ambiente y solar térmica de cada sistema
+fn onsite_uses(components: &Components) -> BTreeMap<(i32, Carrier), Vec<f32>> {
+    let mut uses: BTreeMap<(i32, Carrier), Vec<f32>> = BTreeMap::new();
+    for c in components
+        .data
+        .iter()
+        .filter(|c| c.is_used() && is_onsite_balanced(c.carrier()))
+    {
+        uses.entry((c.id(), c.carrier()))
+            .and_modify(|e| vecvecsum_assign(e, c.values()))
+            .or_insert_with(|| c.values().to_owned());
+    }
+    uses
+}
+
+/// Ajusta la producción in situ de energía ambiente y solar térmica a los consumos modificados
+///
+/// La producción de cada sistema se reduce, en cada paso, en lo que se ha reducido su consumo,
+/// repartiendo la reducción proporcionalmente entre sus componentes de producción, y se completa
+/// la producción de los consumos que no están cubiertos.
+fn balance_onsite_production(
+    components: &mut Components,
+    before: &BTreeMap<(i32, Carrier), Vec<f32>>,
+) {
+    let after = onsite_uses(components);
+    let num_steps = components.num_steps();
+    for (&(id, carrier), old) in before {
+        let new = after.get(&(id, carrier));
+        let reduction: Vec<f32> = (0..num_steps)
+            .map(|t| (old[t] - new.map_or(0.0, |n| n[t])).max(0.0))
+            .collect();
+        if reduction.iter().all(|v| *v == 0.0) {
+            continue;
+        }
+        let is_prod = |c: &Energy| c.is_generated() && c.id() == id && c.has_carrier(carrier);
+        let mut prod_t = vec![0.0; num_steps];
+        for c in components.data.iter().filter(|c| is_prod(c)) {
+            vecvecsum_assign(&mut prod_t, c.values());
+        }
+        for c in components.data.iter_mut().filter(|c| is_prod(c)) {
+            for (t, v) in c.values_mut().iter_mut().enumerate() {
+                if prod_t[t] > 0.0 {
+                    *v = (*v - reduction[t] * *v / prod_t[t]).max(0.0);
+                }
+            }
+        }
+    }
+    for carrier in Carrier::AMBIENT {
+        components.complete_produced_for_onsite_generated_use(carrier);
+    }
+    components.complete_produced_for_onsite_generated_use(Carrier::TERMOSOLAR);
+}
+
+/// Resultados de una variante del análisis de medidas de mejora
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeasureResult {
+    /// Descripción de la variante
+    pub name: String,
+    /// Energía ponderada por m2 de la variante (paso B), C_ep [kWh/m2.an] y emisiones [kg_CO2e/m2.an]
+    pub we_m2: RenNrenCo2,
+    /// Fracción renovable, RER
+    pub rer: f32,
+    /// Ahorro por m2 respecto al caso base (caso base - variante)
+    pub savings_m2: RenNrenCo2,
+    /// Ahorro relativo de energía primaria no renovable respecto al caso base [%]
+    ///
+    /// Es nulo cuando la energía primaria no renovable del caso base es inferior a la tolerancia
+    /// `Tolerances::zero` del cálculo
+    pub savings_nren_pct: f32,
+}
+
+impl MeasureResult {
+    /// Resultados de la variante a partir de su eficiencia energética y la del caso base
+    fn new(name: String, ep: &EnergyPerformance, base_we_m2: RenNrenCo2, zero: f32) -> Self {
+        let we_m2 = ep.balance_m2.we.b;
+        let savings_m2 = base_we_m2 - we_m2;
+        let savings_nren_pct = if base_we_m2.nren.abs() > zero {
+            100.0 * savings_m2.nren / base_we_m2.nren
+        } else {
+            0.0
+        };
+        Self {
+            name,
+            we_m2,
+            rer: ep.rer,
+            savings_m2,
+            savings_nren_pct,
+        }
+    }
+}
+
+/// Análisis incremental de medidas de mejora
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeasuresAnalysis {
+    /// Caso base
+    pub base: MeasureResult,
+    /// Variantes con cada medida aplicada individualmente sobre el caso base
+    pub measures: Vec<MeasureResult>,
+    /// Variante con todas las medidas aplicadas, en el orden indicado
+    pub combined: MeasureResult,
+}
+
+impl fmt::Display for MeasuresAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Variante; C_ep_nren [kWh/m2.an]; C_ep_tot [kWh/m2.an]; CO2 [kg_CO2e/m2.an]; RER; Ahorro C_ep_nren [kWh/m2.an]; Ahorro C_ep_nren [%]; Ahorro C_ep_tot [kWh/m2.an]; Ahorro CO2 [kg_CO2e/m2.an]"
+        )?;
+        for res in std::iter::once(&self.base)
+            .chain(&self.measures)
+            .chain(std::iter::once(&self.combined))
+        {
+            writeln!(
+                f,
+                "{}; {:.1}; {:.1}; {:.2}; {:.2}; {:.1}; {:.1}; {:.1}; {:.2}",
+                res.name,
+                res.we_m2.nren,
+                res.we_m2.tot(),
+                res.we_m2.co2,
+                res.rer,
+                res.savings_m2.nren,
+                res.savings_nren_pct,
+                res.savings_m2.tot(),
+                res.savings_m2.co2
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Calcula el impacto de cada medida de mejora y del conjunto de todas ellas
+///
+/// Cada medida se aplica individualmente sobre los componentes del caso base y el conjunto de
+/// medidas se aplica sucesivamente, en el orden indicado, por lo que el ahorro combinado no es,
+/// en general, la suma de los ahorros individuales.
+///
+/// * `components` - componentes energéticos del caso base
+/// * `wfactors` - factores de paso
+/// * `measures` - medidas de mejora
+/// * `options` - opciones de cálculo (ver [`CalcOptions`])
+///
+/// # Errors
+///
+/// * Medida incoherente con los componentes (ver [`Measure::apply`])
+/// * Errores del cálculo de la eficiencia energética del caso base o de alguna variante
+pub fn measures_analysis(
+    components: &Components,
+    wfactors: &Factors,
+    measures: &[Measure],
+    options: &CalcOptions,
+) -> Result<MeasuresAnalysis> {
+    let ep_base = energy_performance_with(components, wfactors, options)?;
+    let base_we_m2 = ep_base.balance_m2.we.b;
+    let zero = options.tolerances.zero;
+    let base = MeasureResult::new("Caso base".to_string(), &ep_base, base_we_m2, zero);
+
+    let mut results = Vec::with_capacity(measures.len());
+    let mut combined_components = components.clone();
+    for measure in measures {
+        let variant = measure.apply(components)?;
+        let ep = energy_performance_from(variant, wfactors.clone(), options)?;
+        results.push(MeasureResult::new(
+            measure.to_string(),
+            &ep,
+            base_we_m2,
+            zero,
+        ));
+        combined_components = measure.apply(&combined_components)?;
+    }
+    let ep = energy_performance_from(combined_components, wfactors.clone(), options)?;
+    let combined = MeasureResult::new("Conjunto de medidas".to_string(), &ep, base_we_m2, zero);
+
+    Ok(MeasuresAnalysis {
+        base,
+        measures: results,
+        combined,
+    })
+}
@@ -0,0 +1,310 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Métricas y registro del cálculo en lote
+=======================================
+
+Métricas del cálculo en lote en el formato de exposición de texto de Prometheus y registro
+estructurado de cada caso en formato JSON (una línea por caso), para operar el cálculo como
+servicio (feature `metrics`).
+
+Las métricas ([`BatchMetrics`]) acumulan, para todos los lotes calculados:
+
+- `cteepbd_cases_total`: casos calculados
+- `cteepbd_cases_ok_total`: casos calculados sin error
+- `cteepbd_errors_total{kind="..."}`: errores por tipo de error original (ver [`EpbdError::code`])
+- `cteepbd_case_duration_seconds`: histograma de los tiempos de cálculo de cada caso
+
+El registro de cada caso ([`CaseLog`]) incluye su posición e identificador, el tiempo de cálculo
+y, en caso de error, su tipo y mensaje.
+
+Ejemplo:
+
+```rust,ignore
+let metrics = BatchMetrics::new();
+let results = compute_many_monitored(inputs, &metrics, |log| eprintln!("{}", log.to_json()));
+println!("{}", metrics.to_prometheus());
+```
+*/
+
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    batch::compute_many_with,
+    error::{EpbdError, Result},
+    types::EnergyPerformance,
+    CaseInput,
+};
+
+/// Límites superiores de los intervalos del histograma de tiempos de cálculo, s
+pub const DURATION_BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0];
+
+/// Valores acumulados de las métricas
+#[derive(Debug, Clone, Default, PartialEq)]
+struct MetricsData {
+    cases: u64,
+    cases_ok: u64,
+    errors_by_kind: BTreeMap<&'static str, u64>,
+    duration_buckets: [u64; DURATION_BUCKETS.len()],
+    duration_sum: f64,
+}
+
+/// Métricas del cálculo en lote
+///
+/// Se pueden compartir entre hilos y acumulan los valores de todos los casos registrados.
+#[derive(Debug, Default)]
+pub struct BatchMetrics {
+    data: Mutex<MetricsData>,
+}
+
+impl BatchMetrics {
+    /// Métricas sin casos registrados
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Acceso a los datos de las métricas
+    ///
+    /// Un pánico en otro hilo mientras se actualizaban los datos no debe impedir seguir
+    /// registrando y exponiendo las métricas, por lo que se recuperan los datos de un `Mutex`
+    /// envenenado.
+    fn data(&self) -> MutexGuard<'_, MetricsData> {
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registra el tiempo de cálculo y el resultado de un caso
+    pub fn record(&self, duration: Duration, result: &Result<EnergyPerformance>) {
+        let secs = duration.as_secs_f64();
+        let mut data = self.data();
+        data.cases += 1;
+        match result {
+            Ok(_) => data.cases_ok += 1,
+            Err(e) => *data.errors_by_kind.entry(e.code()).or_default() += 1,
+        }
+        for (count, limit) in data.duration_buckets.iter_mut().zip(DURATION_BUCKETS) {
+            if secs <= limit {
+                *count += 1;
+            }
+        }
+        data.duration_sum += secs;
+    }
+
+    /// Número de casos registrados
+    pub fn cases(&self) -> u64 {
+        self.data().cases
+    }
+
+    /// Número de errores registrados del tipo indicado (ver [`EpbdError::code`])
+    pub fn errors(&self, kind: &str) -> u64 {
+        self.data()
+            .errors_by_kind
+            .get(kind)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Métricas en el formato de exposición de texto de Prometheus
+    pub fn to_prometheus(&self) -> String {
+        let data = self.data().clone();
+        let mut out = String::new();
+        out.push_str("# HELP cteepbd_cases_total Casos calculados.\n");
+        out.push_str("# TYPE cteepbd_cases_total counter\n");
+        out.push_str(&format!("cteepbd_cases_total {}\n", data.cases));
+        out.push_str("# HELP cteepbd_cases_ok_total Casos calculados sin error.\n");
+        out.push_str("# TYPE cteepbd_cases_ok_total counter\n");
+        out.push_str(&format!("cteepbd_cases_ok_total {}\n", data.cases_ok));
+        out.push_str("# HELP cteepbd_errors_total Errores de cálculo por tipo de error.\n");
+        out.push_str("# TYPE cteepbd_errors_total counter\n");
+        for (kind, count) in &data.errors_by_kind {
+            out.push_str(&format!(
+                "cteepbd_errors_total{{kind=\"{}\"}} {}\n",
+                kind, count
+            ));
+        }
+        out.push_str(
+            "# HELP cteepbd_case_duration_seconds Tiempo de cálculo de cada caso, en segundos.\n",
+        );
+        out.push_str("# TYPE cteepbd_case_duration_seconds histogram\n");
+        for (count, limit) in data.duration_buckets.iter().zip(DURATION_BUCKETS) {
+            out.push_str(&format!(
+                "cteepbd_case_duration_seconds_bucket{{le=\"{}\"}} {}\n",
+                limit, count
+            ));
+        }
+        out.push_str(&format!(
+            "cteepbd_case_duration_seconds_bucket{{le=\"+Inf\"}} {}\n",
+            data.cases
+        ));
+        out.push_str(&format!(
+            "cteepbd_case_duration_seconds_sum {}\n",
+            data.duration_sum
+        ));
+        out.push_str(&format!(
+            "cteepbd_case_duration_seconds_count {}\n",
+            data.cases
+        ));
+        out
+    }
+}
+
+/// Registro estructurado del cálculo de un caso
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CaseLog {
+    /// Instante del registro, en milisegundos desde 1970-01-01T00:00:00Z
+    pub timestamp_ms: u128,
+    /// Nivel del registro (`info` o `error`)
+    pub level: String,
+    /// Posición del caso en la lista de entrada
+    pub index: usize,
+    /// Identificador del caso
+    pub id: String,
+    /// Tiempo de cálculo, ms
+    pub duration_ms: f64,
+    /// Tipo de error, si se produjo (ver [`EpbdError::code`])
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<String>,
+    /// Mensaje de error, si se produjo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl CaseLog {
+    /// Registro del cálculo de un caso
+    pub fn new(
+        index: usize,
+        id: &str,
+        duration: Duration,
+        result: &Result<EnergyPerformance>,
+    ) -> Self {
+        let error = result.as_ref().err();
+        Self {
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis()),
+            level: if error.is_some() { "error" } else { "info" }.to_string(),
+            index,
+            id: id.to_string(),
+            duration_ms: 1000.0 * duration.as_secs_f64(),
+            error_kind: error.map(|e| e.code().to_string()),
+            message: error.map(EpbdError::to_string),
+        }
+    }
+
+    /// Registro en formato JSON, en una sola línea
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Calcula una lista de casos registrando sus métricas y el registro de cada caso
+///
+/// Con la feature `parallel` los casos se calculan en paralelo.
+///
+/// * `metrics` - métricas en las que se acumulan los tiempos de cálculo y resultados
+/// * `log` - función a la que se pasa el registro de cada caso calculado (p.e. para escribirlo en JSON)
+///
+/// Devuelve los mismos resultados, y en el mismo orden, que [`crate::compute_many`].
+pub fn compute_many_monitored<L>(
+    inputs: Vec<CaseInput>,
+    metrics: &BatchMetrics,
+    log: L,
+) -> Vec<Result<EnergyPerformance>>
+where
+    L: Fn(&CaseLog) + Sync,
+{
+    compute_many_with(&inputs, |index, input| {
+        let start = Instant::now();
+        let result = input.compute();
+        let duration = start.elapsed();
+        metrics.record(duration, &result);
+        log(&CaseLog::new(index, &input.id, duration, &result));
+        result
+    })
+}
+
+#[cfg(all(test, feature = "cte"))]
+mod tests {
+    use super::*;
+    use crate::batch::tests::case;
+
+    #[test]
+    fn metrics_compute_many_monitored() {
+        let inputs = vec![
+            case("caso1", "1, CONSUMO, CAL, ELECTRICIDAD, 10", 100.0),
+            case("caso2", "1, CONSUMO, CAL, ELECTRICIDAD, 10", 0.0),
+            case("caso3", "1, CONSUMO, ACS, GASNATURAL, 20", 100.0),
+        ];
+        let metrics = BatchMetrics::new();
+        let logs = Mutex::new(Vec::new());
+        let results = compute_many_monitored(inputs, &metrics, |log| {
+            logs.lock().unwrap().push(log.clone())
+        });
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_err());
+        assert_eq!(metrics.cases(), 3);
+        assert_eq!(metrics.errors("wrong_input"), 1);
+
+        let prometheus = metrics.to_prometheus();
+        assert!(prometheus.contains("cteepbd_cases_total 3\n"));
+        assert!(prometheus.contains("cteepbd_cases_ok_total 2\n"));
+        assert!(prometheus.contains("cteepbd_errors_total{kind=\"wrong_input\"} 1\n"));
+        assert!(prometheus.contains("cteepbd_case_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(prometheus.contains("cteepbd_case_duration_seconds_count 3\n"));
+
+        let mut logs = logs.into_inner().unwrap();
+        logs.sort_by_key(|log| log.index);
+        assert_eq!(logs.len(), 3);
+        assert_eq!(logs[1].id, "caso2");
+        assert_eq!(logs[1].level, "error");
+        assert_eq!(logs[1].error_kind.as_deref(), Some("wrong_input"));
+        let json: serde_json::Value = serde_json::from_str(&logs[0].to_json()).unwrap();
+        assert_eq!(json["id"], "caso1");
+        assert_eq!(json["level"], "info");
+        assert!(json.get("message").is_none());
+    }
+
+    #[test]
+    fn metrics_poisoned_lock() {
+        let metrics = BatchMetrics::new();
+        let _ = std::thread::scope(|s| {
+            s.spawn(|| {
+                let _data = metrics.data.lock().unwrap();
+                panic!("pánico con las métricas bloqueadas");
+            })
+            .join()
+        });
+        assert!(metrics.data.is_poisoned());
+        metrics.record(Duration::from_millis(10), &Err(EpbdError::Cancelled));
+        assert_eq!(metrics.cases(), 1);
+        assert!(metrics.to_prometheus().contains("cteepbd_cases_total 1\n"));
+    }
+}
@@ -0,0 +1,449 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Modelo simplificado del edificio
+================================
+
+Define el tipo BuildingModel, una descripción declarativa y simplificada del edificio
+(demandas, sistemas con su cobertura y rendimiento e instalación fotovoltaica) a partir
+de la que se generan componentes energéticos coherentes.
+
+Está pensado para estudios rápidos en fase de anteproyecto, sin simulación detallada.
+
+Hipótesis:
+
+- Cada sistema cubre una fracción constante de la demanda del servicio en todos los pasos de cálculo
+- El consumo de cada sistema se obtiene con su rendimiento medio estacional (E_in = Q_out / η)
+- Las bombas de calor eléctricas (rendimiento > 1) de calefacción o ACS consumen energía ambiente (EAMBIENTE) por la diferencia
+- La producción fotovoltaica se obtiene a partir de la potencia pico y la producción específica mensual
+*/
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{
+        BuildingNeeds, Carrier, EProd, EUsed, Energy, Meta, Needs, ProdSource, Service,
+        SystemEfficiency, SystemPeakPower,
+    },
+    Components,
+};
+
+/// Producción específica mensual orientativa de una instalación fotovoltaica, kWh/kWp
+///
+/// Valores medios para módulos orientados al sur con inclinación próxima a la óptima en la península (1630 kWh/kWp.año)
+pub const PV_KWH_KWP_DEFAULT: [f32; 12] = [
+    95.0, 110.0, 145.0, 150.0, 165.0, 170.0, 180.0, 170.0, 145.0, 120.0, 95.0, 85.0,
+];
+
+/// Modelo simplificado y declarativo del edificio
+///
+/// Ejemplo (JSON):
+///
+/// ```json
+/// {
+///   "meta": [{ "key": "CTE_AREAREF", "value": "100.0" }],
+///   "demandas": { "CAL": [1000.0], "ACS": [500.0] },
+///   "sistemas": [
+///     { "id": 1, "servicio": "CAL", "vector": "ELECTRICIDAD", "rendimiento": 3.0 },
+///     { "id": 2, "servicio": "ACS", "vector": "GASNATURAL", "rendimiento": 0.9 }
+///   ],
+///   "pv_kwp": 5.0
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BuildingModel {
+    /// Metadatos que se trasladan a los componentes generados
+    #[serde(default)]
+    pub meta: Vec<Meta>,
+    /// Demanda del edificio por servicio (CAL, REF, ACS), kWh
+    ///
+    /// Todas las demandas deben tener el mismo número de pasos de cálculo (1, valor anual, o 12, valores mensuales)
+    pub demandas: BTreeMap<Service, Vec<f32>>,
+    /// Sistemas que atienden las demandas
+    #[serde(default)]
+    pub sistemas: Vec<ModelSystem>,
+    /// Potencia pico de la instalación fotovoltaica, kWp
+    #[serde(default)]
+    pub pv_kwp: f32,
+    /// Producción específica mensual de la instalación fotovoltaica, kWh/kWp
+    ///
+    /// Por defecto, PV_KWH_KWP_DEFAULT
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pv_kwh_kwp: Option<[f32; 12]>,
+}
+
+/// Sistema del modelo simplificado que atiende (una parte de) la demanda de un servicio
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelSystem {
+    /// Id del sistema (>0)
+    pub id: i32,
+    /// Servicio atendido (CAL, REF o ACS)
+    pub servicio: Service,
+    /// Vector energético consumido
+    pub vector: Carrier,
+    /// Rendimiento medio estacional (SCOP o SEER en bombas de calor). E_out / E_in
+    pub rendimiento: f32,
+    /// Fracción de la demanda del servicio cubierta por el sistema (0, 1]
+    #[serde(default = "default_cobertura")]
+    pub cobertura: f32,
+    /// Comentario descriptivo
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub comentario: String,
+}
+
+fn default_cobertura() -> f32 {
+    1.0
+}
+
+impl BuildingModel {
+    /// Número de pasos de cálculo de las demandas del modelo
+    fn num_steps(&self) -> Result<usize> {
+        let mut lengths = self.demandas.values().map(Vec::len);
+        let num_steps = lengths.next().unwrap_or(12);
+        if lengths.any(|len| len != num_steps) {
+            return Err(EpbdError::WrongInput(
+                "Demandas del modelo con distinto número de pasos de cálculo".into(),
+            ));
+        }
+        if num_steps != 1 && num_steps != 12 {
+            return Err(EpbdError::WrongInput(format!(
+                "Demandas del modelo con {} pasos de cálculo (se admiten 1 o 12)",
+                num_steps
+            )));
+        }
+        Ok(num_steps)
+    }
+
+    /// Producción fotovoltaica en cada paso de cálculo, kWh
+    fn pv_values(&self, num_steps: usize) -> Vec<f32> {
+        let monthly = self
+            .pv_kwh_kwp
+            .unwrap_or(PV_KWH_KWP_DEFAULT)
+            .map(|v| v * self.pv_kwp);
+        match num_steps {
+            1 => vec![monthly.iter().sum()],
+            _ => monthly.to_vec(),
+        }
+    }
+
+    /// Genera los componentes energéticos del modelo
+    ///
+    /// - demandas del edificio (DEMANDA)
+    /// - consumos de cada sistema (CONSUMO), obtenidos con su cobertura y rendimiento
+    /// - consumos de energía ambiente de las bombas de calor eléctricas de calefacción y ACS
+    /// - rendimientos de los sistemas (SISTEMA, RENDIMIENTO), de los que se obtienen las salidas (SALIDA)
+    /// - producción fotovoltaica (PRODUCCION, EL_INSITU) y su potencia pico (SISTEMA, POTENCIA_PICO), si se indica esta
+    ///
+    /// Los componentes se devuelven normalizados (ver `Components::normalize`)
+    ///
+    /// # Errors
+    ///
+    /// * Demandas con distinto número de pasos o en servicios distintos de CAL, REF o ACS
+    /// * Sistemas con id no positivo, rendimiento no positivo o cobertura fuera de (0, 1]
+    /// * Sistemas de servicios sin demanda
+    /// * Coberturas de un servicio que suman más de la unidad
+    pub fn to_components(&self) -> Result<Components> {
+        let num_steps = self.num_steps()?;
+
+        let mut needs = BuildingNeeds::default();
+        for (service, values) in &self.demandas {
+            needs.add(Needs {
+                id: 0,
+                service: *service,
+                values: values.clone(),
+            })?;
+        }
+
+        let mut data = Vec::new();
+        let mut efficiencies = Vec::new();
+        let mut coverage = HashMap::<Service, f32>::new();
+        for sys in &self.sistemas {
+            if sys.id <= 0 {
+                return Err(EpbdError::WrongInput(format!(
+                    "Sistema del modelo con id no positivo ({})",
+                    sys.id
+                )));
+            }
+            if sys.rendimiento <= 0.0 {
+                return Err(EpbdError::WrongInput(format!(
+                    "Rendimiento no positivo para el sistema {} ({})",
+                    sys.id, sys.rendimiento
+                )));
+            }
+            if sys.cobertura <= 0.0 || sys.cobertura > 1.0 {
+                return Err(EpbdError::WrongInput(format!(
+                    "Cobertura fuera de rango (0, 1] para el sistema {} ({})",
+                    sys.id, sys.cobertura
+                )));
+            }
+            let demand = self.demandas.get(&sys.servicio).ok_or_else(|| {
+                EpbdError::WrongInput(format!(
+                    "Sistema {} para el servicio {} sin demanda definida",
+                    sys.id, sys.servicio
+                ))
+            })?;
+            *coverage.entry(sys.servicio).or_default() += sys.cobertura;
+
+            let comment = if sys.comentario.is_empty() {
+                "Generado a partir del modelo del edificio".to_string()
+            } else {
+                sys.comentario.clone()
+            };
+            let out: Vec<f32> = demand.iter().map(|q| q * sys.cobertura).collect();
+            data.push(Energy::Used(EUsed {
+                id: sys.id,
+                carrier: sys.vector,
+                service: sys.servicio,
+                values: out.iter().map(|q| q / sys.rendimiento).collect(),
+                comment: comment.clone(),
+            }));
+            if sys.vector == Carrier::ELECTRICIDAD
+                && sys.rendimiento > 1.0
+                && matches!(sys.servicio, Service::CAL | Service::ACS)
+            {
+                data.push(Energy::Used(EUsed {
+                    id: sys.id,
+                    carrier: Carrier::EAMBIENTE,
+                    service: sys.servicio,
+                    values: out
+                        .iter()
+                        .map(|q| q * (1.0 - 1.0 / sys.rendimiento))
+                        .collect(),
+                    comment: comment.clone(),
+                }));
+            }
+            efficiencies.push(SystemEfficiency {
+                id: sys.id,
+                service: sys.servicio,
+                value: sys.rendimiento,
+                comment,
+            });
+        }
+        if let Some((service, cov)) = coverage.iter().find(|(_, &cov)| cov > 1.0 + 1e-3) {
+            return Err(EpbdError::WrongInput(format!(
+                "La cobertura de los sistemas del servicio {} supera la unidad ({:.2})",
+                service, cov
+            )));
+        }
+
+        let mut peak_powers = Vec::new();
+        if self.pv_kwp > 0.0 {
+            peak_powers.push(SystemPeakPower {
+                id: 0,
+                value: self.pv_kwp,
+                comment: String::new(),
+            });
+            data.push(Energy::Prod(EProd {
+                id: 0,
+                source: ProdSource::EL_INSITU,
+                values: self.pv_values(num_steps),
+                comment: format!(
+                    "Instalación fotovoltaica de {:.2} kWp generada a partir del modelo del edificio",
+                    self.pv_kwp
+                ),
+            }));
+        }
+
+        Components {
+            meta: self.meta.clone(),
+            data,
+            needs,
+            embodied: Vec::new(),
+            systems: Vec::new(),
+            efficiencies,
+            efficiency_curves: Vec::new(),
+            peak_powers,
+            storages: Vec::new(),
+            warnings: Vec::new(),
+        }
+        .normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{HasValues, MetaVec};
+    use pretty_assertions::assert_eq;
+
+    const TMODEL: &str = r#"{
+        "meta": [{ "key": "CTE_AREAREF", "value": "100.0" }],
+        "demandas": { "CAL": [1200.0], "ACS": [600.0] },
+        "sistemas": [
+            { "id": 1, "servicio": "CAL", "vector": "ELECTRICIDAD", "rendimiento": 3.0 },
+            { "id": 2, "servicio": "ACS", "vector": "GASNATURAL", "rendimiento": 0.9, "cobertura": 0.5 },
+            { "id": 3, "servicio": "ACS", "vector": "TERMOSOLAR", "rendimiento": 1.0, "cobertura": 0.5 }
+        ],
+        "pv_kwp": 2.0
+    }"#;
+
+    fn sum_of<F: Fn(&Energy) -> bool>(comps: &Components, f: F) -> f32 {
+        comps
+            .data
+            .iter()
+            .filter(|c| f(c))
+            .map(HasValues::values_sum)
+            .sum()
+    }
+
+    #[test]
+    fn model_to_components() {
+        let model: BuildingModel = serde_json::from_str(TMODEL).unwrap();
+        let comps = model.to_components().unwrap();
+        assert_eq!(comps.get_meta_f32("CTE_AREAREF"), Some(100.0));
+        assert_eq!(comps.num_steps(), 1);
+        assert_eq!(comps.needs.CAL, Some(vec![1200.0]));
+        // Bomba de calor: consumo eléctrico, energía ambiente y salida
+        let el = sum_of(&comps, |c| {
+            c.is_used() && c.has_carrier(Carrier::ELECTRICIDAD)
+        });
+        let ma = sum_of(&comps, |c| c.is_used() && c.has_carrier(Carrier::EAMBIENTE));
+        assert!((el - 400.0).abs() < 1e-3);
+        assert!((ma - 800.0).abs() < 1e-3);
+        let out_cal = sum_of(&comps, |c| c.is_out() && c.has_service(Service::CAL));
+        assert!((out_cal - 1200.0).abs() < 1e-3);
+        // Caldera de gas al 50% y solar térmica al 50% del ACS
+        let gn = sum_of(&comps, |c| {
+            c.is_used() && c.has_carrier(Carrier::GASNATURAL)
+        });
+        assert!((gn - 333.333).abs() < 1e-2);
+        let out_acs = sum_of(&comps, |c| c.is_out() && c.has_service(Service::ACS));
+        assert!((out_acs - 600.0).abs() < 1e-3);
+        // Producciones de energía ambiente, termosolar y fotovoltaica
+        let pr_el = sum_of(&comps, |c| {
+            c.is_generated() && c.has_carrier(Carrier::ELECTRICIDAD)
+        });
+        assert!((pr_el - 2.0 * 1630.0).abs() < 1e-2);
+        assert_eq!(comps.pv_peak_power(), Some(2.0));
+        assert!(comps
+            .data
+            .iter()
+            .any(|c| c.is_generated() && c.has_carrier(Carrier::TERMOSOLAR)));
+        assert!(comps
+            .data
+            .iter()
+            .any(|c| c.is_generated() && c.has_carrier(Carrier::EAMBIENTE)));
+    }
+
+    #[test]
+    fn model_to_components_monthly() {
+        let model = BuildingModel {
+            demandas: BTreeMap::from([(Service::REF, vec![100.0; 12])]),
+            sistemas: vec![ModelSystem {
+                id: 1,
+                servicio: Service::REF,
+                vector: Carrier::ELECTRICIDAD,
+                rendimiento: 4.0,
+                cobertura: 1.0,
+                comentario: "Enfriadora".into(),
+            }],
+            pv_kwp: 1.0,
+            ..Default::default()
+        };
+        let comps = model.to_components().unwrap();
+        assert_eq!(comps.num_steps(), 12);
+        // Sin energía ambiente en refrigeración
+        assert!(!comps.data.iter().any(|c| c.has_carrier(Carrier::EAMBIENTE)));
+        let el = sum_of(&comps, |c| {
+            c.is_used() && c.has_carrier(Carrier::ELECTRICIDAD)
+        });
+        assert!((el - 300.0).abs() < 1e-3);
+        let pv = comps
+            .data
+            .iter()
+            .find(|c| c.is_generated())
+            .unwrap()
+            .values()
+            .to_vec();
+        assert_eq!(pv, PV_KWH_KWP_DEFAULT.to_vec());
+    }
+
+    #[test]
+    fn model_to_components_errors() {
+        let sys = |servicio, cobertura, rendimiento| ModelSystem {
+            id: 1,
+            servicio,
+            vector: Carrier::GASNATURAL,
+            rendimiento,
+            cobertura,
+            comentario: String::new(),
+        };
+        let model = |demandas: Vec<(Service, Vec<f32>)>, sistemas| BuildingModel {
+            demandas: demandas.into_iter().collect(),
+            sistemas,
+            ..Default::default()
+        };
+        // Distinto número de pasos
+        assert!(model(
+            vec![(Service::CAL, vec![1.0]), (Service::ACS, vec![1.0; 12])],
+            vec![]
+        )
+        .to_components()
+        .is_err());
+        // Número de pasos no admitido
+        assert!(model(vec![(Service::CAL, vec![1.0; 24])], vec![])
+            .to_components()
+            .is_err());
+        // Servicio sin demanda
+        assert!(model(
+            vec![(Service::CAL, vec![1.0])],
+            vec![sys(Service::ACS, 1.0, 0.9)]
+        )
+        .to_components()
+        .is_err());
+        // Cobertura fuera de rango y rendimiento no positivo
+        assert!(model(
+            vec![(Service::CAL, vec![1.0])],
+            vec![sys(Service::CAL, 1.5, 0.9)]
+        )
+        .to_components()
+        .is_err());
+        assert!(model(
+            vec![(Service::CAL, vec![1.0])],
+            vec![sys(Service::CAL, 1.0, 0.0)]
+        )
+        .to_components()
+        .is_err());
+        // Coberturas que superan la unidad
+        let mut sys2 = sys(Service::CAL, 0.6, 0.9);
+        sys2.id = 2;
+        assert!(model(
+            vec![(Service::CAL, vec![1.0])],
+            vec![sys(Service::CAL, 0.6, 0.9), sys2]
+        )
+        .to_components()
+        .is_err());
+        // Demanda de servicio no admitido
+        assert!(model(vec![(Service::ILU, vec![1.0])], vec![])
+            .to_components()
+            .is_err());
+    }
+}
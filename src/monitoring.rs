@@ -0,0 +1,919 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Importación de series de monitorización
+=======================================
+
+Importación de series temporales de plataformas de monitorización o sensores como componentes de
+energía (CONSUMO, PRODUCCION, AUX o SALIDA). Los datos son archivos CSV con una columna de fecha y
+hora y una columna por contador, y su interpretación se define con un archivo de mapeo en formato
+TOML (ver `MonitoringMapping`).
+
+Disponible con la *feature* `monitoring`, que incorpora las dependencias `toml`, para la lectura
+del archivo de mapeo, y `chrono` y `chrono-tz`, para las fechas y zonas horarias (base de datos IANA).
+
+Los valores de cada contador se agregan en los pasos de cálculo de la resolución indicada
+(`MENSUAL`, `DIARIA` u `HORARIA`) de un año natural, omitiendo el 29 de febrero. Los pasos diarios
+y mensuales corresponden a los días naturales en hora oficial de la zona horaria y los pasos
+horarios, a su hora estándar (sin horario de verano), para obtener 8760 horas consecutivas.
+Las fechas con desfase horario explícito (`Z`, `+01:00`...) o en segundos desde 1970 (UTC) se
+convierten a la zona horaria indicada y las fechas sin desfase se interpretan como hora oficial de
+esa zona, incluido el horario de verano.
+
+Ejemplo de archivo de mapeo:
+
+```toml
+separador = ";"              # separador de campos, "," por defecto
+columna_fecha = "fecha"      # primera columna por defecto
+zona_horaria = "Europe/Madrid" # UTC, +01:00 o zona IANA: Europe/Madrid (por defecto), Atlantic/Canary...
+resolucion = "HORARIA"       # MENSUAL, DIARIA u HORARIA (por defecto)
+ejercicio = 2023             # año natural, por defecto el del primer registro
+marca_tiempo = "inicio"      # la fecha marca el inicio (por defecto) o el fin del intervalo
+permitir_huecos = false      # pasos sin datos con valor nulo, en lugar de error
+
+[[columna]]
+nombre = "General (Wh)"      # nombre de la columna en la cabecera del CSV
+componente = "CONSUMO"       # CONSUMO, PRODUCCION, AUX o SALIDA
+id = 1                       # id del sistema, 0 por defecto
+servicio = "CAL"             # CONSUMO, SALIDA y, opcionalmente, AUX
+vector = "ELECTRICIDAD"      # CONSUMO
+tipo = "contador"            # energía por intervalo ("energia", por defecto) o lectura acumulada
+factor = 0.001               # conversión a kWh, 1.0 por defecto
+
+[[columna]]
+nombre = "FV (kWh)"
+componente = "PRODUCCION"
+origen = "EL_INSITU"         # PRODUCCION
+comentario = "Producción fotovoltaica monitorizada"
+```
+
+Las lecturas de contador indican la energía acumulada en el instante de la fecha, y la diferencia
+entre lecturas consecutivas se asigna al paso de cálculo en el que termina el intervalo.
+*/
+
+use std::ops::Range;
+use std::str;
+
+use chrono::{
+    DateTime, Datelike, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeDelta,
+    TimeZone as _, Timelike, Utc,
+};
+use chrono_tz::{OffsetComponents, Tz};
+use serde::Deserialize;
+use toml::Spanned;
+
+use crate::{
+    error::{unknown_value_error, EpbdError, Result},
+    types::{CType, Carrier, EAux, EOut, EProd, EUsed, Energy, ProdSource, Resolution, Service},
+    Components,
+};
+
+/// Zona horaria de las fechas de los datos de monitorización
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimeZone {
+    /// Desfase fijo respecto a UTC
+    Fixed(FixedOffset),
+    /// Zona horaria de la base de datos IANA (p.e. Europe/Madrid), con su horario de verano
+    Iana(Tz),
+}
+
+impl TimeZone {
+    /// Desfase de la hora estándar (sin horario de verano) respecto a UTC en el instante indicado
+    pub fn std_offset(self, utc: DateTime<Utc>) -> FixedOffset {
+        match self {
+            TimeZone::Fixed(offset) => offset,
+            TimeZone::Iana(tz) => {
+                let offset = tz.offset_from_utc_datetime(&utc.naive_utc());
+                i32::try_from(offset.base_utc_offset().num_seconds())
+                    .ok()
+                    .and_then(FixedOffset::east_opt)
+                    .unwrap_or_else(|| offset.fix())
+            }
+        }
+    }
+
+    /// Desfase de la hora oficial respecto a UTC en el instante indicado
+    pub fn offset(self, utc: DateTime<Utc>) -> FixedOffset {
+        match self {
+            TimeZone::Fixed(offset) => offset,
+            TimeZone::Iana(tz) => tz.offset_from_utc_datetime(&utc.naive_utc()).fix(),
+        }
+    }
+
+    /// Instante UTC de una hora oficial local
+    ///
+    /// Las horas repetidas al terminar el horario de verano se interpretan como la primera aparición
+    /// y las horas inexistentes al comenzar, como hora estándar.
+    pub fn to_utc(self, local: NaiveDateTime) -> DateTime<Utc> {
+        match self {
+            TimeZone::Fixed(offset) => (local - offset).and_utc(),
+            TimeZone::Iana(tz) => tz
+                .from_local_datetime(&local)
+                .earliest()
+                .map(|dt| dt.with_timezone(&Utc))
+                .unwrap_or_else(|| (local - self.std_offset(local.and_utc())).and_utc()),
+        }
+    }
+}
+
+impl Default for TimeZone {
+    fn default() -> Self {
+        TimeZone::Iana(chrono_tz::Europe::Madrid)
+    }
+}
+
+impl str::FromStr for TimeZone {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<TimeZone> {
+        match s.trim() {
+            "UTC" | "Z" => Ok(TimeZone::Fixed(Utc.fix())),
+            name => parse_offset(name)
+                .map(TimeZone::Fixed)
+                .or_else(|| name.parse::<Tz>().ok().map(TimeZone::Iana))
+                .ok_or_else(|| {
+                    EpbdError::ParseError(format!(
+                        "Zona horaria desconocida `{}` (UTC, desfase +HH:MM o zona horaria IANA, como Europe/Madrid o Atlantic/Canary)",
+                        s
+                    ))
+                }),
+        }
+    }
+}
+
+/// Tipo de valores de una columna de datos de monitorización
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MeterKind {
+    /// Energía de cada intervalo
+    Energy,
+    /// Lectura acumulada de un contador
+    Counter,
+}
+
+/// Posición de la fecha de los registros en el intervalo de medida
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimeStampMark {
+    /// La fecha indica el inicio del intervalo
+    Start,
+    /// La fecha indica el fin del intervalo
+    End,
+}
+
+/// Mapeo de una columna del CSV de monitorización a un componente de energía
+#[derive(Debug, Clone)]
+pub struct ColumnMapping {
+    /// Nombre de la columna en la cabecera del CSV
+    pub name: String,
+    /// Componente generado, con su tipo, id, servicio, vector u origen y comentario (sin valores)
+    pub component: Energy,
+    /// Tipo de valores de la columna
+    pub kind: MeterKind,
+    /// Factor de conversión de los valores a kWh
+    pub factor: f32,
+}
+
+/// Configuración de la importación de datos de monitorización
+#[derive(Debug, Clone)]
+pub struct MonitoringMapping {
+    /// Separador de campos del CSV
+    pub separator: char,
+    /// Nombre de la columna de fecha y hora (por defecto, la primera)
+    pub time_column: Option<String>,
+    /// Zona horaria de los datos y de los pasos de cálculo
+    pub timezone: TimeZone,
+    /// Resolución de las series generadas
+    pub resolution: Resolution,
+    /// Año natural de las series generadas (por defecto, el del primer registro)
+    pub year: Option<i32>,
+    /// Posición de la fecha en el intervalo de medida de los valores de energía
+    pub mark: TimeStampMark,
+    /// Admite pasos de cálculo sin datos, con valor nulo
+    pub allow_gaps: bool,
+    /// Mapeo de columnas a componentes
+    pub columns: Vec<ColumnMapping>,
+}
+
+/// Archivo de mapeo: claves de primer nivel y tablas `[[columna]]`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MappingFile {
+    separador: Option<Spanned<String>>,
+    columna_fecha: Option<String>,
+    zona_horaria: Option<Spanned<String>>,
+    resolucion: Option<Spanned<String>>,
+    ejercicio: Option<i32>,
+    marca_tiempo: Option<Spanned<String>>,
+    permitir_huecos: Option<bool>,
+    #[serde(default)]
+    columna: Vec<Spanned<ColumnFile>>,
+}
+
+/// Tabla `[[columna]]` del archivo de mapeo
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ColumnFile {
+    nombre: Option<String>,
+    componente: Option<Spanned<String>>,
+    #[serde(default)]
+    id: i32,
+    servicio: Option<Spanned<String>>,
+    vector: Option<Spanned<String>>,
+    origen: Option<Spanned<String>>,
+    tipo: Option<Spanned<String>>,
+    factor: Option<f32>,
+    comentario: Option<String>,
+}
+
+impl str::FromStr for MonitoringMapping {
+    type Err = EpbdError;
+
+    /// Interpreta un archivo de mapeo en formato TOML (ver documentación del módulo)
+    fn from_str(s: &str) -> Result<MonitoringMapping> {
+        let file: MappingFile = toml::from_str(s).map_err(|e| mapping_error(s, &e))?;
+
+        let separator = match &file.separador {
+            None => ',',
+            Some(sep) => {
+                let mut chars = sep.get_ref().chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => {
+                        return Err(EpbdError::ParseError(format!(
+                            "Separador de campos incorrecto `{}` (debe ser un único carácter)",
+                            sep.get_ref()
+                        ))
+                        .at_line(line_at(s, sep.span())))
+                    }
+                }
+            }
+        };
+        let timezone = match &file.zona_horaria {
+            None => TimeZone::default(),
+            Some(tz) => parse_key(s, tz)?,
+        };
+        let resolution = match &file.resolucion {
+            None => Resolution::HORARIA,
+            Some(res) => parse_key(s, res)?,
+        };
+        let mark = match file
+            .marca_tiempo
+            .as_ref()
+            .map(|m| (m.get_ref().as_str(), m))
+        {
+            None | Some(("inicio", _)) => TimeStampMark::Start,
+            Some(("fin", _)) => TimeStampMark::End,
+            Some((other, m)) => {
+                return Err(
+                    unknown_value_error("marca de tiempo", other, &["inicio", "fin"])
+                        .at_line(line_at(s, m.span())),
+                )
+            }
+        };
+
+        let columns = file
+            .columna
+            .iter()
+            .map(|column| {
+                column_mapping(s, column.get_ref()).map_err(|e| match e {
+                    EpbdError::Parse(_) => e,
+                    e => e.at_line(line_at(s, column.span())),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if columns.is_empty() {
+            return Err(EpbdError::ParseError(
+                "El archivo de mapeo no define ninguna columna de datos ([[columna]])".into(),
+            ));
+        }
+
+        Ok(MonitoringMapping {
+            separator,
+            time_column: file.columna_fecha,
+            timezone,
+            resolution,
+            year: file.ejercicio,
+            mark,
+            allow_gaps: file.permitir_huecos.unwrap_or(false),
+            columns,
+        })
+    }
+}
+
+/// Mapeo de una columna a partir de su tabla `[[columna]]` del archivo de mapeo `s`
+fn column_mapping(s: &str, table: &ColumnFile) -> Result<ColumnMapping> {
+    let name = table.nombre.clone().ok_or_else(|| {
+        EpbdError::ParseError("Columna de datos sin nombre (clave `nombre`)".into())
+    })?;
+    let required = |value: &'_ Option<Spanned<String>>, key: &str, kind: &str| {
+        value.clone().ok_or_else(|| {
+            EpbdError::ParseError(format!(
+                "La columna `{}` de tipo {} necesita la clave `{}`",
+                name, kind, key
+            ))
+        })
+    };
+    let id = table.id;
+    let comment = table
+        .comentario
+        .clone()
+        .unwrap_or_else(|| format!("Monitorización: {}", name));
+    let values = Vec::new();
+
+    let ctype_key = required(&table.componente, "componente", "de componente")?;
+    let component = match parse_key(s, &ctype_key)? {
+        CType::CONSUMO => Energy::Used(EUsed {
+            id,
+            carrier: parse_key::<Carrier>(s, &required(&table.vector, "vector", "CONSUMO")?)?,
+            service: parse_key::<Service>(s, &required(&table.servicio, "servicio", "CONSUMO")?)?,
+            values,
+            comment,
+        }),
+        CType::PRODUCCION => Energy::Prod(EProd {
+            id,
+            source: parse_key::<ProdSource>(
+                s,
+                &required(&table.origen, "origen", "PRODUCCION")?,
+            )?,
+            values,
+            comment,
+        }),
+        CType::AUX => Energy::Aux(EAux {
+            id,
+            service: match &table.servicio {
+                Some(service) => parse_key(s, service)?,
+                None => Service::NEPB,
+            },
+            values,
+            comment,
+        }),
+        CType::SALIDA => Energy::Out(EOut {
+            id,
+            service: parse_key::<Service>(s, &required(&table.servicio, "servicio", "SALIDA")?)?,
+            values,
+            comment,
+        }),
+        other => {
+            return Err(EpbdError::ParseError(format!(
+                "Tipo de componente {} no admitido en los datos de monitorización (CONSUMO, PRODUCCION, AUX o SALIDA)",
+                other
+            ))
+            .at_line(line_at(s, ctype_key.span())))
+        }
+    };
+    let kind = match table.tipo.as_ref().map(|t| (t.get_ref().as_str(), t)) {
+        None | Some(("energia", _)) => MeterKind::Energy,
+        Some(("contador", _)) => MeterKind::Counter,
+        Some((other, t)) => {
+            return Err(
+                unknown_value_error("tipo de columna", other, &["energia", "contador"])
+                    .at_line(line_at(s, t.span())),
+            )
+        }
+    };
+    let factor = table.factor.unwrap_or(1.0);
+    Ok(ColumnMapping {
+        name,
+        component,
+        kind,
+        factor,
+    })
+}
+
+/// Línea del archivo de mapeo `s` en la que comienza el fragmento indicado
+fn line_at(s: &str, span: Range<usize>) -> usize {
+    s.get(..span.start).unwrap_or(s).matches('\n').count() + 1
+}
+
+/// Valor de una clave de cadena del archivo de mapeo `s`, con la línea de la clave en los errores
+fn parse_key<T: str::FromStr<Err = EpbdError>>(s: &str, value: &Spanned<String>) -> Result<T> {
+    value
+        .get_ref()
+        .parse()
+        .map_err(|e: EpbdError| e.at_line(line_at(s, value.span())))
+}
+
+/// Error de interpretación del archivo de mapeo `s`, con su línea
+///
+/// Las claves desconocidas se indican con la sugerencia de la clave admitida más parecida.
+fn mapping_error(s: &str, e: &toml::de::Error) -> EpbdError {
+    // Mensaje de serde: unknown field `clave`, expected one of `clave1`, `clave2`...
+    let mut names = e.message().split('`').skip(1).step_by(2);
+    let err = match names.next() {
+        Some(key) if e.message().starts_with("unknown field") => {
+            unknown_value_error("Clave", key, &names.collect::<Vec<_>>())
+        }
+        _ => EpbdError::ParseError(format!("Archivo de mapeo incorrecto: {}", e.message())),
+    };
+    match e.span() {
+        Some(span) => err.at_line(line_at(s, span)),
+        None => err,
+    }
+}
+
+impl MonitoringMapping {
+    /// Componentes de energía a partir de un CSV de monitorización, con una serie por columna mapeada
+    ///
+    /// # Errors
+    ///
+    /// * CSV sin cabecera o sin alguna de las columnas mapeadas
+    /// * Fechas o valores incorrectos, lecturas de contador decrecientes
+    /// * Sin registros en el año indicado o, salvo que se admitan, pasos de cálculo sin datos
+    pub fn import(&self, csv: &str) -> Result<Vec<Energy>> {
+        let split = |line: &str| -> Vec<String> {
+            line.split(self.separator)
+                .map(|f| f.trim().trim_matches('"').trim().to_string())
+                .collect()
+        };
+        let mut lines = csv
+            .strip_prefix('\u{feff}')
+            .unwrap_or(csv)
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim()))
+            .filter(|(_, l)| !l.is_empty());
+        let header = lines
+            .next()
+            .map(|(_, l)| split(l))
+            .ok_or_else(|| EpbdError::ParseError("Datos de monitorización sin cabecera".into()))?;
+        let position = |name: &str| {
+            header.iter().position(|h| h == name).ok_or_else(|| {
+                EpbdError::ParseError(format!(
+                    "Columna `{}` no encontrada en la cabecera de los datos de monitorización",
+                    name
+                ))
+                .at_line(1)
+            })
+        };
+        let time_col = match &self.time_column {
+            Some(name) => position(name)?,
+            None => 0,
+        };
+        let cols = self
+            .columns
+            .iter()
+            .map(|c| position(&c.name))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Registros con su instante UTC y valores (None si faltan), en orden cronológico
+        let mut records: Vec<(DateTime<Utc>, Vec<Option<f64>>)> = Vec::new();
+        for (n, line) in lines {
+            let fields = split(line);
+            let time = fields.get(time_col).map(String::as_str).unwrap_or_default();
+            let utc = match parse_timestamp(time) {
+                Some((datetime, Some(offset))) => (datetime - offset).and_utc(),
+                Some((datetime, None)) => self.timezone.to_utc(datetime),
+                None => {
+                    return Err(EpbdError::ParseError(format!(
+                        "Fecha y hora incorrecta `{}` en los datos de monitorización",
+                        time
+                    ))
+                    .at_line(n))
+                }
+            };
+            let mut values = Vec::with_capacity(cols.len());
+            for (&col, mapping) in cols.iter().zip(&self.columns) {
+                let raw = fields.get(col).map(String::as_str).unwrap_or_default();
+                values.push(parse_value(raw, self.separator).map_err(|_| {
+                    EpbdError::ParseError(format!(
+                        "Valor incorrecto `{}` de la columna `{}` en los datos de monitorización",
+                        raw, mapping.name
+                    ))
+                    .at_line(n)
+                })?);
+            }
+            records.push((utc, values));
+        }
+        records.sort_by_key(|(utc, _)| *utc);
+
+        // Hora local de los pasos de cálculo: estándar en pasos horarios y oficial en el resto
+        let local = |utc: DateTime<Utc>| match self.resolution {
+            Resolution::HORARIA => utc.naive_utc() + self.timezone.std_offset(utc),
+            _ => utc.naive_utc() + self.timezone.offset(utc),
+        };
+        let energy_shift = match self.mark {
+            TimeStampMark::Start => TimeDelta::zero(),
+            TimeStampMark::End => -TimeDelta::seconds(1),
+        };
+        let year = match self.year {
+            Some(year) => year,
+            None => {
+                let (utc, _) = records.first().ok_or_else(|| {
+                    EpbdError::WrongInput("Datos de monitorización sin registros".into())
+                })?;
+                local(*utc + energy_shift).year()
+            }
+        };
+        let step_of = |utc: DateTime<Utc>| timestep(local(utc), year, self.resolution);
+
+        let num_steps = self.resolution.num_steps();
+        let mut components = Vec::with_capacity(self.columns.len());
+        for (i, mapping) in self.columns.iter().enumerate() {
+            let mut values = vec![0.0_f64; num_steps];
+            let mut covered = vec![false; num_steps];
+            let mut last_reading: Option<(DateTime<Utc>, f64)> = None;
+            for (utc, record) in &records {
+                let Some(value) = record[i] else { continue };
+                let (step, value) = match mapping.kind {
+                    MeterKind::Energy => (step_of(*utc + energy_shift), value),
+                    MeterKind::Counter => {
+                        let previous = last_reading.replace((*utc, value));
+                        let Some((_, prev_value)) = previous else {
+                            continue;
+                        };
+                        if value < prev_value {
+                            return Err(EpbdError::WrongInput(format!(
+                                "Lectura decreciente del contador `{}` ({} tras {})",
+                                mapping.name, value, prev_value
+                            )));
+                        }
+                        (step_of(*utc - TimeDelta::seconds(1)), value - prev_value)
+                    }
+                };
+                if let Some(step) = step {
+                    values[step] += value;
+                    covered[step] = true;
+                }
+            }
+            if !covered.iter().any(|c| *c) {
+                return Err(EpbdError::WrongInput(format!(
+                    "Sin datos de la columna `{}` en el año {}",
+                    mapping.name, year
+                )));
+            }
+            let gaps = covered.iter().filter(|c| !**c).count();
+            if gaps > 0 && !self.allow_gaps {
+                let first = covered.iter().position(|c| !*c).unwrap_or_default();
+                return Err(EpbdError::WrongInput(format!(
+                    "Datos de la columna `{}` incompletos: {} pasos de cálculo sin datos en el año {} (el primero, el paso {})",
+                    mapping.name,
+                    gaps,
+                    year,
+                    first + 1
+                )));
+            }
+            let mut component = mapping.component.clone();
+            let factor = f64::from(mapping.factor);
+            for (value, total) in component_values(&mut component, num_steps)
+                .iter_mut()
+                .zip(values)
+            {
+                *value = (total * factor) as f32;
+            }
+            components.push(component);
+        }
+        Ok(components)
+    }
+}
+
+/// Valores de un componente de energía, con la longitud indicada
+fn component_values(component: &mut Energy, num_steps: usize) -> &mut Vec<f32> {
+    let values = match component {
+        Energy::Used(e) => &mut e.values,
+        Energy::Prod(e) => &mut e.values,
+        Energy::Aux(e) => &mut e.values,
+        Energy::Out(e) => &mut e.values,
+    };
+    values.resize(num_steps, 0.0);
+    values
+}
+
+impl Components {
+    /// Incorpora a los componentes las series de un CSV de monitorización según el mapeo indicado
+    ///
+    /// # Errors
+    ///
+    /// * Datos de monitorización incorrectos (ver `MonitoringMapping::import`)
+    /// * Número de pasos de cálculo distinto del de los componentes existentes
+    pub fn add_monitoring_data(&mut self, csv: &str, mapping: &MonitoringMapping) -> Result<()> {
+        let components = mapping.import(csv)?;
+        if !self.data.is_empty() && mapping.resolution.num_steps() != self.num_steps() {
+            return Err(EpbdError::WrongInput(format!(
+                "Las series de monitorización tienen {} pasos de cálculo y los componentes, {}",
+                mapping.resolution.num_steps(),
+                self.num_steps()
+            )));
+        }
+        self.data.extend(components);
+        Ok(())
+    }
+}
+
+// ==================== Fechas
+
+/// Paso de cálculo del año indicado para una hora local
+///
+/// Devuelve None para fechas de otros años o del 29 de febrero.
+fn timestep(local: NaiveDateTime, year: i32, resolution: Resolution) -> Option<usize> {
+    if local.year() != year {
+        return None;
+    }
+    // Día del año sin 29 de febrero (referido a un año no bisiesto)
+    let doy = NaiveDate::from_ymd_opt(2001, local.month(), local.day())?.ordinal0() as usize;
+    Some(match resolution {
+        Resolution::MENSUAL => local.month0() as usize,
+        Resolution::DIARIA => doy,
+        Resolution::HORARIA => doy * 24 + local.hour() as usize,
+    })
+}
+
+/// Desfase horario `+HH:MM`, `-HHMM` o `+HH`
+fn parse_offset(s: &str) -> Option<FixedOffset> {
+    match s.len() {
+        3 => format!("{}00", s).parse().ok(),
+        _ => s.parse().ok(),
+    }
+}
+
+/// Fecha y hora de un registro y desfase horario (si se indica)
+///
+/// Admite fechas ISO 8601 (`AAAA-MM-DD[T| ]HH:MM[:SS[.fff]][Z|±HH:MM]`), fechas con día, mes y año
+/// (`DD/MM/AAAA[ HH:MM[:SS]]`) y segundos desde 1970 (UTC).
+fn parse_timestamp(s: &str) -> Option<(NaiveDateTime, Option<FixedOffset>)> {
+    let s = s.trim();
+    if s.len() >= 9 && s.chars().all(|c| c.is_ascii_digit()) {
+        let utc = DateTime::from_timestamp(s.parse().ok()?, 0)?;
+        return Some((utc.naive_utc(), Some(Utc.fix())));
+    }
+    let (date, time) = match s.find(['T', ' ']) {
+        Some(pos) => (&s[..pos], s[pos + 1..].trim()),
+        None => (s, ""),
+    };
+    let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(date, "%d/%m/%Y"))
+        .ok()?;
+
+    let (time, offset) = if let Some(time) = time.strip_suffix('Z') {
+        (time, Some(Utc.fix()))
+    } else if let Some(pos) = time.rfind(['+', '-']) {
+        (&time[..pos], Some(parse_offset(&time[pos..])?))
+    } else {
+        (time, None)
+    };
+    let time = match time {
+        "" => NaiveTime::MIN,
+        time => NaiveTime::parse_from_str(time, "%H:%M:%S%.f")
+            .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M"))
+            .ok()?,
+    };
+    Some((date.and_time(time), offset))
+}
+
+/// Valor numérico de un campo (None si está vacío), admitiendo coma decimal si no es el separador
+fn parse_value(s: &str, separator: char) -> std::result::Result<Option<f64>, ()> {
+    if s.is_empty() || s.eq_ignore_ascii_case("null") || s.eq_ignore_ascii_case("nan") {
+        return Ok(None);
+    }
+    let s = if separator == ',' {
+        s.to_string()
+    } else {
+        s.replace(',', ".")
+    };
+    s.parse::<f64>()
+        .ok()
+        .filter(|v| v.is_finite())
+        .map(Some)
+        .ok_or(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::HasValues;
+
+    const TMAPPING: &str = r#"# Contadores del edificio
+separador = ";"
+columna_fecha = "Fecha"
+resolucion = "MENSUAL"
+ejercicio = 2023
+
+[[columna]]
+nombre = "General (Wh)"
+componente = "CONSUMO"
+id = 1
+servicio = "CAL"
+vector = "ELECTRICIDAD"
+tipo = "contador"
+factor = 0.001
+
+[[columna]]
+nombre = "FV"
+componente = "PRODUCCION"
+origen = "EL_INSITU"
+comentario = "Producción # monitorizada" # Comentario final
+"#;
+
+    /// Lecturas diarias en hora oficial de Madrid de 2023, con la lectura del contador
+    /// (+10 kWh/día) y la producción diaria (1 kWh/día, con coma decimal)
+    fn daily_csv() -> String {
+        let mut csv = String::from("Fecha;General (Wh);FV\n");
+        let start = NaiveDate::from_ymd_opt(2023, 1, 1).unwrap();
+        for (i, date) in start.iter_days().take(366).enumerate() {
+            csv.push_str(&format!(
+                "{} 00:00;{};1,0\n",
+                date.format("%d/%m/%Y"),
+                10_000 * i
+            ));
+        }
+        csv
+    }
+
+    /// Desfase horario de las horas indicadas
+    fn hours(h: i32) -> FixedOffset {
+        FixedOffset::east_opt(h * 3600).unwrap()
+    }
+
+    #[test]
+    fn monitoring_dates() {
+        let madrid: TimeZone = "Europe/Madrid".parse().unwrap();
+        let utc = |s: &str| parse_timestamp(s).unwrap().0.and_utc();
+        // Horario de verano de 2023: de 01:00 UTC del 26 de marzo a 01:00 UTC del 29 de octubre
+        assert_eq!(madrid.offset(utc("2023-03-26 00:59")), hours(1));
+        assert_eq!(madrid.offset(utc("2023-03-26 01:00")), hours(2));
+        assert_eq!(madrid.offset(utc("2023-10-29 00:59")), hours(2));
+        assert_eq!(madrid.offset(utc("2023-10-29 01:00")), hours(1));
+        assert_eq!(madrid.std_offset(utc("2023-07-15 12:00")), hours(1));
+
+        let (winter, _) = parse_timestamp("2023-01-15 12:00").unwrap();
+        let (summer, _) = parse_timestamp("2023-07-15T12:00:00").unwrap();
+        assert_eq!(madrid.to_utc(winter), (winter - hours(1)).and_utc());
+        assert_eq!(madrid.to_utc(summer), (summer - hours(2)).and_utc());
+        // Horas repetidas (primera aparición) e inexistentes (hora estándar)
+        let (repeated, _) = parse_timestamp("2023-10-29 02:30").unwrap();
+        assert_eq!(madrid.to_utc(repeated), (repeated - hours(2)).and_utc());
+        let (missing, _) = parse_timestamp("2023-03-26 02:30").unwrap();
+        assert_eq!(madrid.to_utc(missing), (missing - hours(1)).and_utc());
+
+        assert_eq!(
+            "+05:30".parse::<TimeZone>().unwrap(),
+            TimeZone::Fixed(FixedOffset::east_opt(19800).unwrap())
+        );
+        assert_eq!(
+            "-03".parse::<TimeZone>().unwrap(),
+            TimeZone::Fixed(hours(-3))
+        );
+        assert_eq!(
+            "Atlantic/Canary".parse::<TimeZone>().unwrap(),
+            TimeZone::Iana(chrono_tz::Atlantic::Canary)
+        );
+        assert!("Mars/Olympus".parse::<TimeZone>().is_err());
+
+        let noon = NaiveDate::from_ymd_opt(2023, 7, 15)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        assert_eq!(
+            parse_timestamp("2023-07-15T12:00:00+02:00"),
+            Some((noon, Some(hours(2))))
+        );
+        assert_eq!(
+            parse_timestamp("2023-07-15T12:00Z"),
+            Some((noon, Some(hours(0))))
+        );
+        assert_eq!(parse_timestamp("15/07/2023 12:00:00"), Some((noon, None)));
+        let epoch = (noon - hours(2)).and_utc().timestamp();
+        assert_eq!(
+            parse_timestamp(&epoch.to_string()),
+            Some((noon - hours(2), Some(hours(0))))
+        );
+        assert_eq!(parse_timestamp("2023-02-29 12:00"), None);
+        assert_eq!(parse_timestamp("2023-07-15 25:00"), None);
+    }
+
+    #[test]
+    fn monitoring_import_monthly() {
+        let mapping: MonitoringMapping = TMAPPING.parse().unwrap();
+        assert_eq!(mapping.separator, ';');
+        assert_eq!(mapping.timezone, TimeZone::Iana(chrono_tz::Europe::Madrid));
+        assert_eq!(mapping.columns[0].kind, MeterKind::Counter);
+
+        let components = mapping.import(&daily_csv()).unwrap();
+        let days = [
+            31.0, 28.0, 31.0, 30.0, 31.0, 30.0, 31.0, 31.0, 30.0, 31.0, 30.0, 31.0,
+        ];
+        let used: Vec<f32> = days.iter().map(|d| 10.0 * d).collect();
+        assert_eq!(
+            components[0].to_string(),
+            format!(
+                "1, CONSUMO, CAL, ELECTRICIDAD, {} # Monitorización: General (Wh)",
+                used.iter()
+                    .map(|v| format!("{:.2}", v))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        );
+        // La producción del 1 de enero de 2024 queda fuera del ejercicio
+        assert_eq!(components[1].values(), days.as_slice());
+        assert_eq!(components[1].comment(), "Producción # monitorizada");
+
+        let mut comps =
+            "1, CONSUMO, ACS, GASNATURAL, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10"
+                .parse::<Components>()
+                .unwrap();
+        comps.add_monitoring_data(&daily_csv(), &mapping).unwrap();
+        assert_eq!(comps.data.len(), 3);
+        let mut comps = "1, CONSUMO, ACS, GASNATURAL, 10"
+            .parse::<Components>()
+            .unwrap();
+        assert!(comps.add_monitoring_data(&daily_csv(), &mapping).is_err());
+
+        // Lecturas de contador decrecientes
+        let csv = "Fecha;General (Wh);FV\n01/01/2023;1000;1\n02/01/2023;500;1";
+        assert!(mapping.import(csv).is_err());
+        // Columna inexistente
+        assert!(mapping
+            .import("Fecha;General (Wh)\n01/01/2023;1000")
+            .is_err());
+    }
+
+    #[test]
+    fn monitoring_import_hourly() {
+        let mapping = "zona_horaria = \"UTC\"
+            [[columna]]
+            nombre = \"E\"
+            componente = \"AUX\"
+            id = 2
+            marca_tiempo = \"fin\"";
+        // Clave de primer nivel dentro de una tabla de columna
+        let err = mapping.parse::<MonitoringMapping>().unwrap_err();
+        assert!(err.to_string().contains("línea 6"));
+
+        let mapping: MonitoringMapping = "zona_horaria = \"UTC\"
+            marca_tiempo = \"fin\"
+            [[columna]]
+            nombre = \"E\"
+            componente = \"AUX\"
+            id = 2"
+            .parse()
+            .unwrap();
+        let csv: String = std::iter::once("time,E".to_string())
+            .chain((1..=24).map(|h| format!("{},0.5", 1_672_531_200 + h * 3600)))
+            .collect::<Vec<_>>()
+            .join("\n");
+        // Pasos sin datos
+        assert!(mapping.import(&csv).is_err());
+        let mapping = MonitoringMapping {
+            allow_gaps: true,
+            ..mapping
+        };
+        let components = mapping.import(&csv).unwrap();
+        let values = components[0].values();
+        assert_eq!(values.len(), 8760);
+        assert_eq!(values[0], 0.5);
+        assert_eq!(values[23], 0.5);
+        assert_eq!(values[24], 0.0);
+        assert_eq!(components[0].service(), Service::NEPB);
+    }
+
+    #[test]
+    fn monitoring_mapping_errors() {
+        // Clave desconocida, con sugerencia
+        let err = "[[columna]]\nnombre = \"E\"\ncomponente = \"SALIDA\"\nservcio = \"CAL\""
+            .parse::<MonitoringMapping>()
+            .unwrap_err();
+        assert!(err.to_string().contains("¿quiso decir servicio?"));
+        assert!(err.to_string().contains("línea 4"));
+        // Clave necesaria para el tipo de componente
+        assert!(
+            "[[columna]]\nnombre = \"E\"\ncomponente = \"CONSUMO\"\nservicio = \"CAL\""
+                .parse::<MonitoringMapping>()
+                .is_err()
+        );
+        // Tipo de componente no admitido
+        assert!("[[columna]]\nnombre = \"E\"\ncomponente = \"DEMANDA\""
+            .parse::<MonitoringMapping>()
+            .is_err());
+        // Tablas no admitidas, sin columnas o tipos de valor incorrectos
+        assert!("[formato]\nseparador = \",\""
+            .parse::<MonitoringMapping>()
+            .is_err());
+        assert!("separador = \",\"".parse::<MonitoringMapping>().is_err());
+        assert!(
+            "ejercicio = \"2023\"\n[[columna]]\nnombre = \"E\"\ncomponente = \"AUX\""
+                .parse::<MonitoringMapping>()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Preludio
+========
+
+Superficie pública mínima de la biblioteca para integradores: cálculo del balance, componentes,
+factores de paso, resultados, errores y formatos de salida.
+
+Junto con los módulos públicos (`types`, `error`, `cte` y los de las *features* opcionales), es la
+parte documentada de la API. Los elementos reexportados en la raíz del *crate* (p.e.
+`cteepbd::Components`) se mantienen por compatibilidad, pero están ocultos en la documentación y
+pueden cambiar entre versiones menores. La prueba `public_api` compara la superficie documentada
+con la lista de `tests/public-api.txt`.
+
+Este módulo no supone una garantía de estabilidad adicional a la del versionado de la biblioteca
+(versiones 0.x). Los tipos que pueden ampliarse con nuevas variantes o campos (`Carrier`,
+`Service`, `ProdSource`, `Dest`, `UserWF`) son `#[non_exhaustive]`.
+
+Minimal documented public surface for integrators. Crate root re-exports are hidden and may change.
+
+```rust
+use cteepbd::prelude::*;
+
+fn main() -> Result<()> {
+    let components: Components = "CONSUMO, CAL, ELECTRICIDAD, 100.0".parse()?;
+    let wfactors: Factors = "ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+        ELECTRICIDAD, INSITU, SUMINISTRO, A, 1.000, 0.000, 0.000"
+        .parse()?;
+    let defaults = UserWF::cte_defaults().with_red1(RenNrenCo2::new(0.0, 1.3, 0.3));
+    let wfactors = wfactors.normalize(&defaults)?;
+    let ep: EnergyPerformance =
+        energy_performance(&components, &wfactors, 0.0, 1.0, LoadMatchingMode::Constant)?;
+    let we_b: RenNrenCo2 = ep.balance_m2.we.b;
+    assert!((we_b.tot() - 236.8).abs() < 0.1);
+    Ok(())
+}
+```
+*/
+
+pub use crate::{
+    balance::{
+        energy_performance, energy_performance_from, energy_performance_with,
+        energy_performance_with_tolerances,
+    },
+    components::Components,
+    error::{EpbdError, Result},
+    types::{
+        CalcOptions, Carrier, Dest, EnergyPerformance, LoadMatchingMode, Meta, MetaVec, ProdSource,
+        RenNrenCo2, Service, Source, Step, Tolerances,
+    },
+    wfactors::{Factors, UserWF},
+};
+
+#[cfg(feature = "formats-xml")]
+pub use crate::asctexml::AsCteXml;
+#[cfg(feature = "formats-html")]
+pub use crate::ashtml::AsCteHtml;
+#[cfg(feature = "formats-plain")]
+pub use crate::asplain::AsCtePlain;
+#[cfg(feature = "formats-svg")]
+pub use crate::assvg::AsSankeySvg;
+#[cfg(feature = "formats-tidy")]
+pub use crate::astidy::AsTidy;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Firmas de los elementos del preludio usados habitualmente por los integradores
+    #[test]
+    fn prelude_api() {
+        let _: fn(&Components, &Factors, f32, f32, LoadMatchingMode) -> Result<EnergyPerformance> =
+            |c, f, k, a, lm| energy_performance(c, f, k, a, lm);
+        let _: fn(
+            &Components,
+            &Factors,
+            f32,
+            f32,
+            LoadMatchingMode,
+            &Tolerances,
+        ) -> Result<EnergyPerformance> =
+            |c, f, k, a, lm, t| energy_performance_with_tolerances(c, f, k, a, lm, t);
+        let _: fn(&Components, &Factors, &CalcOptions) -> Result<EnergyPerformance> =
+            |c, f, o| energy_performance_with(c, f, o);
+        let _: fn(Components, Factors, &CalcOptions) -> Result<EnergyPerformance> =
+            |c, f, o| energy_performance_from(c, f, o);
+        let _: CalcOptions = CalcOptions {
+            k_exp: 0.0,
+            arearef: 1.0,
+            load_matching: LoadMatchingMode::Constant,
+            tolerances: Tolerances::default(),
+            ..Default::default()
+        }
+        .k_exp(0.0)
+        .arearef(1.0)
+        .load_matching(LoadMatchingMode::Constant)
+        .tolerances(Tolerances::default());
+        let _: fn(&Factors, &UserWF<RenNrenCo2>) -> Result<Factors> = |f, u| f.clone().normalize(u);
+
+        let components: Components = "CONSUMO, CAL, ELECTRICIDAD, 100.0".parse().unwrap();
+        assert_eq!(components.get_meta("CTE_AREAREF"), None);
+        let ep = energy_performance(
+            &components,
+            &crate::Factors::default(),
+            0.0,
+            1.0,
+            LoadMatchingMode::Constant,
+        );
+        assert!(matches!(ep, Err(EpbdError::MissingFactor(_))));
+        let _ = (
+            Carrier::ELECTRICIDAD,
+            Service::CAL,
+            Source::RED,
+            Dest::SUMINISTRO,
+            Step::A,
+            ProdSource::EL_INSITU,
+            Meta::new("CTE_AREAREF", "1.0"),
+        );
+    }
+}
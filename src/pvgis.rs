@@ -0,0 +1,391 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Producción fotovoltaica desde PVGIS
+===================================
+
+Importación de series de producción fotovoltaica obtenidas con PVGIS (Photovoltaic Geographical
+Information System, JRC - Comisión Europea) como componentes de producción `EL_INSITU`.
+
+Disponible con la *feature* `pvgis`. Se admiten los archivos CSV de PVGIS:
+
+- de producción mensual (herramienta *PVcalc*, columna `E_m`, en kWh/mes), con 12 valores;
+- de series horarias (herramienta *seriescalc* con `pvcalculation=1`, columna `P`, en W), de las
+  que se toma el primer año completo, sin el 29 de febrero, con 8760 valores.
+
+Con la *feature* `net` se pueden descargar los datos directamente del servicio de PVGIS
+para unas coordenadas y parámetros de instalación dados (ver `fetch_pvgis_csv`).
+*/
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{EProd, Energy, HasValues, ProdSource, SystemPeakPower},
+    Components,
+};
+
+/// URL base del servicio de PVGIS
+pub const PVGIS_API_URL: &str = "https://re.jrc.ec.europa.eu/api/v5_2";
+
+/// Parámetros de la instalación fotovoltaica para la consulta a PVGIS
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PvgisParams {
+    /// Latitud (grados decimales, positiva al norte)
+    pub lat: f32,
+    /// Longitud (grados decimales, positiva al este)
+    pub lon: f32,
+    /// Potencia pico de la instalación (kWp)
+    pub peakpower: f32,
+    /// Pérdidas del sistema (%)
+    pub loss: f32,
+    /// Inclinación de los módulos respecto a la horizontal (grados)
+    pub angle: f32,
+    /// Orientación (azimut) de los módulos (grados, 0 = sur, 90 = oeste, -90 = este)
+    pub aspect: f32,
+}
+
+impl Default for PvgisParams {
+    fn default() -> Self {
+        Self {
+            lat: 40.4,
+            lon: -3.7,
+            peakpower: 1.0,
+            loss: 14.0,
+            angle: 30.0,
+            aspect: 0.0,
+        }
+    }
+}
+
+impl PvgisParams {
+    /// URL de consulta a PVGIS de la producción mensual (`hourly == false`) o de la serie horaria
+    pub fn url(&self, hourly: bool) -> String {
+        let common = format!(
+            "lat={}&lon={}&peakpower={}&loss={}&angle={}&aspect={}&outputformat=csv",
+            self.lat, self.lon, self.peakpower, self.loss, self.angle, self.aspect
+        );
+        if hourly {
+            format!(
+                "{}/seriescalc?pvcalculation=1&startyear=2020&endyear=2020&{}",
+                PVGIS_API_URL, common
+            )
+        } else {
+            format!("{}/PVcalc?{}", PVGIS_API_URL, common)
+        }
+    }
+}
+
+/// Serie de producción fotovoltaica importada de PVGIS
+#[derive(Debug, Clone, PartialEq)]
+pub struct PvgisSeries {
+    /// Producción en cada paso de cálculo (12 valores mensuales u 8760 horarios), kWh
+    pub values: Vec<f32>,
+    /// Potencia pico de la instalación (kWp), si figura en la cabecera del archivo
+    pub peakpower: Option<f32>,
+    /// Latitud y longitud, si figuran en la cabecera del archivo
+    pub location: Option<(f32, f32)>,
+}
+
+impl PvgisSeries {
+    /// Interpreta un archivo CSV de PVGIS de producción mensual o de serie horaria
+    ///
+    /// # Errors
+    ///
+    /// * Archivo sin tabla de producción mensual (`Month ... E_m`) ni serie horaria (`time,P,...`)
+    /// * Valores no numéricos, meses incompletos o serie horaria de menos de un año
+    pub fn from_csv(csv: &str) -> Result<Self> {
+        let lines: Vec<&str> = csv.lines().map(str::trim).collect();
+        let header_value = |prefix: &str| -> Option<f32> {
+            lines
+                .iter()
+                .find(|l| l.starts_with(prefix))
+                .and_then(|l| l.split(':').nth(1))
+                .and_then(|v| v.trim().parse().ok())
+        };
+        let peakpower = header_value("Nominal power of the PV system");
+        let location = header_value("Latitude").zip(header_value("Longitude"));
+
+        let values = if let Some(pos) = lines.iter().position(|l| l.starts_with("time,")) {
+            hourly_values(&lines[pos..])?
+        } else if let Some(pos) = lines.iter().position(|l| l.starts_with("Month")) {
+            monthly_values(&lines[pos..])?
+        } else {
+            return Err(EpbdError::ParseError(
+                "No se reconoce el formato del archivo de PVGIS (producción mensual o serie horaria)"
+                    .into(),
+            ));
+        };
+
+        Ok(PvgisSeries {
+            values,
+            peakpower,
+            location,
+        })
+    }
+
+    /// Componente de producción eléctrica in situ del sistema `id` con la serie de PVGIS
+    pub fn to_component(&self, id: i32) -> Energy {
+        let location = self
+            .location
+            .map(|(lat, lon)| format!(" lat={} lon={}", lat, lon))
+            .unwrap_or_default();
+        Energy::Prod(EProd {
+            id,
+            source: ProdSource::EL_INSITU,
+            values: self.values.clone(),
+            comment: format!("Producción fotovoltaica PVGIS{}", location),
+        })
+    }
+}
+
+/// Producción mensual (columna E_m) de la tabla mensual de PVCalc
+fn monthly_values(lines: &[&str]) -> Result<Vec<f32>> {
+    let header: Vec<&str> = lines[0].split_whitespace().collect();
+    let col = header
+        .iter()
+        .position(|c| *c == "E_m")
+        .ok_or_else(|| EpbdError::ParseError("Tabla mensual de PVGIS sin columna E_m".into()))?;
+    let mut values = Vec::with_capacity(12);
+    for (month, line) in lines[1..].iter().take(12).enumerate() {
+        let items: Vec<&str> = line.split_whitespace().collect();
+        let value = match items.as_slice() {
+            [m, ..] if m.parse::<usize>().ok() == Some(month + 1) => {
+                items.get(col).and_then(|v| v.parse::<f32>().ok())
+            }
+            _ => None,
+        };
+        values.push(value.ok_or_else(|| {
+            EpbdError::ParseError(format!(
+                "Valor de producción del mes {} incorrecto en los datos de PVGIS: {}",
+                month + 1,
+                line
+            ))
+        })?);
+    }
+    if values.len() != 12 {
+        return Err(EpbdError::ParseError(
+            "Tabla mensual de PVGIS incompleta".into(),
+        ));
+    }
+    Ok(values)
+}
+
+/// Producción horaria (columna P, en W) del primer año de la serie de seriescalc, sin el 29 de febrero
+fn hourly_values(lines: &[&str]) -> Result<Vec<f32>> {
+    let header: Vec<&str> = lines[0].split(',').map(str::trim).collect();
+    let col = header
+        .iter()
+        .position(|c| *c == "P")
+        .ok_or_else(|| EpbdError::ParseError("Serie horaria de PVGIS sin columna P".into()))?;
+    let mut values = Vec::with_capacity(8760);
+    for line in &lines[1..] {
+        let items: Vec<&str> = line.split(',').map(str::trim).collect();
+        // Las líneas de datos comienzan con la fecha y hora (AAAAMMDD:HHMM)
+        let Some(time) = items
+            .first()
+            .filter(|t| t.len() == 13 && t.as_bytes()[8] == b':')
+        else {
+            if values.is_empty() {
+                continue;
+            }
+            break;
+        };
+        if &time[4..8] == "0229" {
+            continue;
+        }
+        let power = items
+            .get(col)
+            .and_then(|v| v.parse::<f32>().ok())
+            .ok_or_else(|| {
+                EpbdError::ParseError(format!(
+                    "Valor de potencia incorrecto en los datos de PVGIS: {}",
+                    line
+                ))
+            })?;
+        values.push(power / 1000.0);
+        if values.len() == 8760 {
+            break;
+        }
+    }
+    if values.len() != 8760 {
+        return Err(EpbdError::ParseError(format!(
+            "Serie horaria de PVGIS incompleta ({} valores, se necesita un año completo)",
+            values.len()
+        )));
+    }
+    Ok(values)
+}
+
+impl Components {
+    /// Incorpora a los componentes la producción fotovoltaica del sistema `id` a partir de un CSV de PVGIS
+    ///
+    /// Si el archivo indica la potencia pico de la instalación y no se ha declarado para ese sistema,
+    /// se incorpora también como `SISTEMA, id, POTENCIA_PICO`.
+    ///
+    /// # Errors
+    ///
+    /// * Formato incorrecto de los datos de PVGIS
+    /// * Número de pasos de cálculo distinto del de los componentes existentes
+    pub fn add_pvgis_production(&mut self, csv: &str, id: i32) -> Result<()> {
+        let series = PvgisSeries::from_csv(csv)?;
+        let component = series.to_component(id);
+        if !self.data.is_empty() && component.num_steps() != self.num_steps() {
+            return Err(EpbdError::WrongInput(format!(
+                "La serie de PVGIS tiene {} pasos de cálculo y los componentes, {}",
+                component.num_steps(),
+                self.num_steps()
+            )));
+        }
+        self.data.push(component);
+        if let Some(peakpower) = series.peakpower {
+            if !self.peak_powers.iter().any(|p| p.id == id) {
+                self.peak_powers.push(SystemPeakPower {
+                    id,
+                    value: peakpower,
+                    comment: "Potencia pico según PVGIS".into(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Descarga de PVGIS los datos de producción (mensual u horaria) de una instalación fotovoltaica
+///
+/// Los datos se guardan en la caché local de datos remotos (ver `remote::fetch_cached`), que
+/// devuelve también los avisos generados al usarla.
+#[cfg(feature = "net")]
+pub fn fetch_pvgis_csv(
+    params: &PvgisParams,
+    hourly: bool,
+) -> Result<(String, Vec<crate::types::Warning>)> {
+    crate::remote::fetch_cached(
+        &params.url(hourly),
+        None,
+        &crate::remote::default_cache_dir(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TMONTHLY: &str = "Latitude (decimal degrees):\t40.400
+Longitude (decimal degrees):\t-3.700
+Nominal power of the PV system (c-Si) (kWp):\t2.0
+System losses (%):\t14.0
+
+Fixed slope of modules (deg.) (optimum at given location):\t30
+Orientation (azimuth) of modules (deg.) (optimum at given location):\t0
+
+Month\t\tE_d\t\tE_m\t\tH(i)_d\t\tH(i)_m\t\tSD_m
+1\t\t6.0\t\t186.0\t\t4.4\t\t135.9\t\t20.4
+2\t\t7.0\t\t196.0\t\t5.2\t\t146.0\t\t25.1
+3\t\t8.5\t\t263.5\t\t6.3\t\t196.4\t\t30.2
+4\t\t8.7\t\t261.0\t\t6.6\t\t197.3\t\t22.6
+5\t\t9.2\t\t285.2\t\t7.0\t\t218.7\t\t15.1
+6\t\t9.9\t\t297.0\t\t7.6\t\t228.9\t\t10.2
+7\t\t10.4\t\t322.4\t\t8.1\t\t251.2\t\t8.4
+8\t\t10.0\t\t310.0\t\t7.8\t\t241.6\t\t9.8
+9\t\t8.8\t\t264.0\t\t6.7\t\t200.2\t\t14.3
+10\t\t7.4\t\t229.4\t\t5.6\t\t172.9\t\t18.5
+11\t\t6.0\t\t180.0\t\t4.4\t\t133.0\t\t19.7
+12\t\t5.5\t\t170.5\t\t4.0\t\t124.0\t\t21.1
+
+Year\t\t8.1\t\t2965.0\t\t6.1\t\t2246.1\t\t95.3
+";
+
+    fn hourly_csv(year: u32) -> String {
+        let mut csv = String::from(
+            "Latitude (decimal degrees):\t40.400\nLongitude (decimal degrees):\t-3.700\n\ntime,P,G(i),H_sun,T2m,WS10m,Int\n",
+        );
+        let days = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+        for (month, num_days) in days.iter().enumerate() {
+            for day in 1..=*num_days {
+                for hour in 0..24 {
+                    let power = if (8..18).contains(&hour) { 500.0 } else { 0.0 };
+                    csv.push_str(&format!(
+                        "{}{:02}{:02}:{:02}10,{},0.0,0.0,10.0,1.0,0.0\n",
+                        year,
+                        month + 1,
+                        day,
+                        hour,
+                        power
+                    ));
+                }
+            }
+        }
+        csv.push_str("\nP: PV system power (W)\n");
+        csv
+    }
+
+    #[test]
+    fn pvgis_monthly() {
+        let series = PvgisSeries::from_csv(TMONTHLY).unwrap();
+        assert_eq!(series.values.len(), 12);
+        assert_eq!(series.values[0], 186.0);
+        assert_eq!(series.values.iter().sum::<f32>(), 2965.0);
+        assert_eq!(series.peakpower, Some(2.0));
+        assert_eq!(series.location, Some((40.4, -3.7)));
+
+        let mut comps = "1, CONSUMO, CAL, ELECTRICIDAD, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100"
+            .parse::<Components>()
+            .unwrap();
+        comps.add_pvgis_production(TMONTHLY, 0).unwrap();
+        assert!(comps.data[1].is_onsite_pr());
+        assert_eq!(comps.pv_peak_power(), Some(2.0));
+        assert!(comps
+            .to_string()
+            .contains("# Producción fotovoltaica PVGIS lat=40.4 lon=-3.7"));
+
+        // Número de pasos incompatible
+        let mut comps = "1, CONSUMO, CAL, ELECTRICIDAD, 100"
+            .parse::<Components>()
+            .unwrap();
+        assert!(comps.add_pvgis_production(TMONTHLY, 0).is_err());
+    }
+
+    #[test]
+    fn pvgis_hourly() {
+        // Año bisiesto: se omite el 29 de febrero
+        let series = PvgisSeries::from_csv(&hourly_csv(2020)).unwrap();
+        assert_eq!(series.values.len(), 8760);
+        assert_eq!(series.values[8], 0.5);
+        assert!((series.values.iter().sum::<f32>() - 365.0 * 5.0).abs() < 0.01);
+        assert_eq!(series.peakpower, None);
+
+        assert!(PvgisSeries::from_csv("Month\tE_m\n1\t10.0").is_err());
+        assert!(PvgisSeries::from_csv("sin datos").is_err());
+    }
+
+    #[test]
+    fn pvgis_url() {
+        let url = PvgisParams::default().url(false);
+        assert!(url.starts_with("https://re.jrc.ec.europa.eu/api/v5_2/PVcalc?lat=40.4&lon=-3.7"));
+        assert!(PvgisParams::default()
+            .url(true)
+            .contains("/seriescalc?pvcalculation=1"));
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Datos remotos
+=============
+
+Descarga de tablas de factores de paso desde una URL remota, con caché local y verificación
+de integridad mediante su resumen SHA-256.
+
+Disponible con la *feature* `net`.
+
+La copia en caché se identifica por la URL y se reutiliza mientras coincida con el resumen
+indicado. Si no se indica el resumen, se intenta descargar siempre la tabla y se recurre a la
+copia en caché solamente cuando falla la descarga.
+
+Las incidencias no fatales (uso de la copia en caché, fallo al guardarla) no se escriben en la
+salida de error sino que se devuelven como avisos, para que las gestione la aplicación.
+*/
+
+use std::{
+    env, fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{Warning, WarningCode},
+};
+
+/// Tamaño máximo admitido para los datos descargados, en bytes
+const MAX_DOWNLOAD_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Resumen SHA-256 de los datos, como cadena hexadecimal en minúsculas
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Directorio por defecto de la caché local de datos remotos
+///
+/// Se usa, por orden de preferencia, la variable de entorno `CTEEPBD_CACHE`,
+/// `$XDG_CACHE_HOME/cteepbd`, `$HOME/.cache/cteepbd` o el directorio temporal del sistema.
+pub fn default_cache_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("CTEEPBD_CACHE") {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = env::var_os("XDG_CACHE_HOME") {
+        return PathBuf::from(dir).join("cteepbd");
+    }
+    if let Some(dir) = env::var_os("HOME") {
+        return PathBuf::from(dir).join(".cache").join("cteepbd");
+    }
+    env::temp_dir().join("cteepbd")
+}
+
+/// Ruta del archivo de caché correspondiente a una URL
+fn cache_path(url: &str, cache_dir: &Path) -> PathBuf {
+    cache_dir.join(format!("{}.txt", sha256_hex(url.as_bytes())))
+}
+
+/// Comprueba que los datos tienen el resumen SHA-256 indicado, si se ha indicado
+fn check_sha256(data: &str, sha256: Option<&str>) -> Result<()> {
+    match sha256 {
+        Some(expected) if !sha256_hex(data.as_bytes()).eq_ignore_ascii_case(expected.trim()) => {
+            Err(EpbdError::WrongInput(format!(
+                "el resumen SHA-256 de los datos ({}) no coincide con el indicado ({})",
+                sha256_hex(data.as_bytes()),
+                expected.trim()
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Descarga el contenido de la URL como texto
+fn download(url: &str) -> Result<String> {
+    let response = ureq::get(url).call().map_err(|e| {
+        EpbdError::WrongInput(format!("no se ha podido descargar `{}`: {}", url, e))
+    })?;
+    let mut data = String::new();
+    response
+        .into_reader()
+        .take(MAX_DOWNLOAD_SIZE)
+        .read_to_string(&mut data)
+        .map_err(|e| {
+            EpbdError::WrongInput(format!(
+                "no se ha podido leer el contenido de `{}`: {}",
+                url, e
+            ))
+        })?;
+    Ok(data)
+}
+
+/// Obtiene el contenido de una URL, usando la caché local en `cache_dir`
+///
+/// - si se indica el resumen SHA-256 y la copia en caché coincide con él, se usa sin descargar
+/// - en otro caso, se descarga, se verifica su resumen (si se ha indicado) y se guarda en caché
+/// - si falla la descarga y no se ha indicado el resumen, se usa la copia en caché, si existe
+///
+/// Devuelve el contenido y los avisos generados (uso de la copia en caché al fallar la descarga o
+/// fallo al guardar en caché los datos descargados).
+///
+/// # Errors
+///
+/// * Fallo en la descarga sin copia válida en caché
+/// * Datos descargados que no coinciden con el resumen SHA-256 indicado
+pub fn fetch_cached(
+    url: &str,
+    sha256: Option<&str>,
+    cache_dir: &Path,
+) -> Result<(String, Vec<Warning>)> {
+    let path = cache_path(url, cache_dir);
+    let cached = fs::read_to_string(&path).ok();
+    let mut warnings = Vec::new();
+
+    if let (Some(data), Some(_)) = (&cached, sha256) {
+        if check_sha256(data, sha256).is_ok() {
+            return Ok((data.clone(), warnings));
+        }
+    }
+
+    let data = match download(url) {
+        Ok(data) => data,
+        Err(e) => {
+            return match cached {
+                Some(data) if sha256.is_none() => {
+                    warnings.push(Warning::warn(
+                        WarningCode::CACHE_USADA,
+                        format!("{}. Se usa la copia local en caché", e),
+                    ));
+                    Ok((data, warnings))
+                }
+                _ => Err(e),
+            }
+        }
+    };
+    check_sha256(&data, sha256)?;
+
+    // La caché es opcional: si no se puede escribir se continúa con los datos descargados
+    if let Err(e) = fs::create_dir_all(cache_dir).and_then(|_| fs::write(&path, &data)) {
+        warnings.push(Warning::warn(
+            WarningCode::CACHE_NO_GUARDADA,
+            format!(
+                "no se ha podido guardar en caché `{}`: {}",
+                path.display(),
+                e
+            ),
+        ));
+    }
+    Ok((data, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    const TFACTORS: &str = "#META CTE_FUENTE: REMOTA
+ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+";
+
+    /// Servidor HTTP local que responde una vez con el cuerpo indicado
+    fn serve_once(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+        format!("http://{}/factores.csv", addr)
+    }
+
+    fn temp_cache_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cteepbd_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn remote_sha256() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn remote_fetch_and_cache() {
+        let cache_dir = temp_cache_dir("fetch");
+        let hash = sha256_hex(TFACTORS.as_bytes());
+
+        // Descarga y guarda en caché
+        let url = serve_once(TFACTORS);
+        let (data, warnings) = fetch_cached(&url, Some(&hash), &cache_dir).unwrap();
+        assert_eq!(data, TFACTORS);
+        assert!(warnings.is_empty());
+        assert!(cache_path(&url, &cache_dir).exists());
+
+        // Con el resumen correcto se usa la caché sin descargar (el servidor ya no responde)
+        let (data, warnings) = fetch_cached(&url, Some(&hash.to_uppercase()), &cache_dir).unwrap();
+        assert_eq!(data, TFACTORS);
+        assert!(warnings.is_empty());
+
+        // Sin resumen, si falla la descarga se recurre a la caché, con un aviso
+        let (data, warnings) = fetch_cached(&url, None, &cache_dir).unwrap();
+        assert_eq!(data, TFACTORS);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, WarningCode::CACHE_USADA);
+
+        // Con un resumen distinto se descarta la caché y falla la descarga
+        assert!(fetch_cached(&url, Some("00"), &cache_dir).is_err());
+
+        let _ = fs::remove_dir_all(&cache_dir);
+    }
+
+    #[test]
+    fn remote_fetch_wrong_hash() {
+        let cache_dir = temp_cache_dir("hash");
+        let url = serve_once(TFACTORS);
+        assert!(fetch_cached(&url, Some("00"), &cache_dir).is_err());
+        // No se guarda en caché un contenido no verificado
+        assert!(!cache_path(&url, &cache_dir).exists());
+        let _ = fs::remove_dir_all(&cache_dir);
+    }
+}
@@ -30,7 +30,7 @@ Balance para todos los vectores
 Balance global, con agregación de todos los vectores, en valor absoluto o por m2.
 */
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
@@ -62,6 +62,14 @@ impl Balance {
     pub fn normalize_by_area(&self, area: f32) -> Balance {
         let k_area = if area == 0.0 { 0.0 } else { 1.0 / area };
 
+        let mut needs_zones = self.needs.zones.clone();
+        needs_zones
+            .values_mut()
+            .for_each(|v| v.values_mut().for_each(|v| *v *= k_area));
+
+        let mut needs_zones_dev = self.needs.zones_dev.clone();
+        needs_zones_dev.values_mut().for_each(|v| *v *= k_area);
+
         let mut used_epus_by_srv = self.used.epus_by_srv.clone();
         used_epus_by_srv.values_mut().for_each(|v| *v *= k_area);
 
@@ -73,6 +81,12 @@ impl Balance {
             .values_mut()
             .for_each(|v| v.values_mut().for_each(|v| *v *= k_area));
 
+        let mut used_nepus_by_srv = self.used.nepus_by_srv.clone();
+        used_nepus_by_srv.values_mut().for_each(|v| *v *= k_area);
+
+        let mut exp_nepus_by_srv = self.exp.nepus_by_srv.clone();
+        exp_nepus_by_srv.values_mut().for_each(|v| *v *= k_area);
+
         let mut prod_by_src = self.prod.by_src.clone();
         prod_by_src.values_mut().for_each(|v| *v *= k_area);
 
@@ -96,11 +110,16 @@ impl Balance {
         let mut B_by_srv = self.we.b_by_srv.clone();
         B_by_srv.values_mut().for_each(|v| *v *= k_area);
 
+        let mut B_by_cr = self.we.b_by_cr.clone();
+        B_by_cr.values_mut().for_each(|v| *v *= k_area);
+
         Balance {
             needs: BalNeeds {
                 ACS: self.needs.ACS.map(|v| v * k_area),
                 CAL: self.needs.CAL.map(|v| v * k_area),
                 REF: self.needs.REF.map(|v| v * k_area),
+                zones: needs_zones,
+                zones_dev: needs_zones_dev,
             },
             used: BalUsed {
                 epus: k_area * self.used.epus,
@@ -109,6 +128,7 @@ impl Balance {
                 epus_by_srv: used_epus_by_srv,
                 epus_by_cr: used_epus_by_cr,
                 epus_by_cr_by_srv: used_epus_by_srv_by_cr,
+                nepus_by_srv: used_nepus_by_srv,
             },
             prod: BalProd {
                 an: k_area * self.prod.an,
@@ -126,7 +146,9 @@ impl Balance {
             exp: BalExp {
                 an: k_area * self.exp.an,
                 grid: k_area * self.exp.grid,
+                grid_sto: k_area * self.exp.grid_sto,
                 nepus: k_area * self.exp.nepus,
+                nepus_by_srv: exp_nepus_by_srv,
             },
             we: BalWeighted {
                 a: k_area * self.we.a,
@@ -136,6 +158,8 @@ impl Balance {
                 del: k_area * self.we.del,
                 exp_a: k_area * self.we.exp_a,
                 exp: k_area * self.we.exp,
+                b_by_cr: B_by_cr,
+                b_share_by_cr: self.we.b_share_by_cr.clone(),
             },
         }
     }
@@ -157,6 +181,7 @@ impl std::ops::AddAssign<&BalanceCarrier> for Balance {
         self.exp.an += rhs.exp.an;
         self.exp.nepus += rhs.exp.nepus_an;
         self.exp.grid += rhs.exp.grid_an;
+        self.exp.grid_sto += rhs.exp.grid_sto_an;
 
         // Modify global balance using this carrier balance ---
         // E_we_an =  E_we_del_an - E_we_exp_an; // formula 2 step A
@@ -168,6 +193,8 @@ impl std::ops::AddAssign<&BalanceCarrier> for Balance {
         self.we.del += rhs.we.del;
         self.we.exp_a += rhs.we.exp_a;
         self.we.exp += rhs.we.exp;
+        // Weighted energy by carrier (contribution to the total, once all carriers are added)
+        *self.we.b_by_cr.entry(rhs.carrier).or_default() += rhs.we.b;
 
         // Aggregation by EPB service
         for (&service, &used_epb_for_service) in &rhs.used.epus_by_srv_an {
@@ -191,6 +218,14 @@ impl std::ops::AddAssign<&BalanceCarrier> for Balance {
                 .or_default() += used_epb_for_service;
         }
 
+        // Aggregation by non EPB service
+        for (&service, &used_nepb_for_service) in &rhs.used.nepus_by_srv_an {
+            *self.used.nepus_by_srv.entry(service).or_default() += used_nepb_for_service;
+        }
+        for (&service, &exp_nepb_for_service) in &rhs.exp.nepus_by_srv_an {
+            *self.exp.nepus_by_srv.entry(service).or_default() += exp_nepb_for_service;
+        }
+
         // Aggregation by energy source
         for (source, produced) in &rhs.prod.by_src_an {
             *self.prod.by_src.entry(*source).or_default() += produced;
@@ -235,6 +270,55 @@ pub struct BalNeeds {
     #[serde(default)]
     #[serde(skip_serializing_if="Option::is_none")]
     pub REF: Option<f32>,
+    /// Building zones energy needs, by zone id and service. kWh
+    #[serde(default)]
+    #[serde(skip_serializing_if="BTreeMap::is_empty")]
+    pub zones: BTreeMap<i32, BTreeMap<Service, f32>>,
+    /// Deviation of the sum of zone energy needs from the building energy needs, by service. kWh
+    ///
+    /// Only available for services with building and zone needs (sum of zones - building)
+    #[serde(default)]
+    #[serde(skip_serializing_if="BTreeMap::is_empty")]
+    pub zones_dev: BTreeMap<Service, f32>,
+}
+
+impl BalNeeds {
+    /// Demanda del edificio para el servicio indicado
+    pub fn get(&self, service: Service) -> Option<f32> {
+        match service {
+            Service::ACS => self.ACS,
+            Service::CAL => self.CAL,
+            Service::REF => self.REF,
+            _ => None,
+        }
+    }
+
+    /// Servicios en los que la suma de las demandas de las zonas no coincide con la del edificio
+    ///
+    /// Devuelve el servicio y el desvío relativo respecto a la demanda del edificio, para los casos en
+    /// los que ese desvío supera la tolerancia relativa indicada. Con demanda del edificio nula se
+    /// devuelve el desvío absoluto.
+    pub fn zones_mismatch(&self, tolerance: f32) -> Vec<(Service, f32)> {
+        let mut mismatch: Vec<_> = self
+            .zones_dev
+            .iter()
+            .filter_map(|(service, dev)| {
+                let building = self.get(*service).unwrap_or_default();
+                let rel_dev = if building.abs() > 0.0 {
+                    dev / building
+                } else {
+                    *dev
+                };
+                if rel_dev.abs() > tolerance {
+                    Some((*service, rel_dev))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        mismatch.sort_by_key(|(service, _)| service.to_string());
+        mismatch
+    }
 }
 
 /// Datos de energía consumida para el balance global
@@ -248,11 +332,14 @@ pub struct BalUsed {
     /// Energy use for Cogen
     pub cgnus: f32,
     /// Energy use for EPB services, by service
-    pub epus_by_srv: HashMap<Service, f32>,
+    pub epus_by_srv: BTreeMap<Service, f32>,
     /// Energy use for EPB uses, by carrier
-    pub epus_by_cr: HashMap<Carrier, f32>,
+    pub epus_by_cr: BTreeMap<Carrier, f32>,
     /// Energy use for EPB services, by service, by carrier
-    pub epus_by_cr_by_srv: HashMap<Service, HashMap<Carrier, f32>>,
+    pub epus_by_cr_by_srv: BTreeMap<Service, BTreeMap<Carrier, f32>>,
+    /// Energy use for non EPB services, by service
+    #[serde(default)]
+    pub nepus_by_srv: BTreeMap<Service, f32>,
 }
 
 /// Datos de energía producida in situ o cogenerada para el balance global
@@ -262,13 +349,13 @@ pub struct BalProd {
     /// Produced energy from all sources
     pub an: f32,
     /// Produced energy by carrier
-    pub by_cr: HashMap<Carrier, f32>,
+    pub by_cr: BTreeMap<Carrier, f32>,
     /// Produced energy by source
-    pub by_src: HashMap<ProdSource, f32>,
+    pub by_src: BTreeMap<ProdSource, f32>,
     /// Produced energy delivered to EPB services, by source
-    pub epus_by_src: HashMap<ProdSource, f32>,
+    pub epus_by_src: BTreeMap<ProdSource, f32>,
     /// Produced energy delivered for each EPB service, by source
-    pub epus_by_srv_by_src: HashMap<ProdSource, HashMap<Service, f32>>,
+    pub epus_by_srv_by_src: BTreeMap<ProdSource, BTreeMap<Service, f32>>,
 }
 
 /// Datos de energía suministrada por la red o producción insitu para el balance global
@@ -282,7 +369,7 @@ pub struct BalDel {
     /// Delivered by the grid
     pub grid: f32,
     /// Delivered by the grid, by carrier
-    pub grid_by_cr: HashMap<Carrier, f32>,
+    pub grid_by_cr: BTreeMap<Carrier, f32>,
 }
 
 /// Datos de energía exportada a la red o a usos no EPB para el balance global
@@ -293,8 +380,14 @@ pub struct BalExp {
     pub an: f32,
     /// Exported energy to the grid
     pub grid: f32,
+    /// Exported energy to the grid released from storage systems (deferred export)
+    #[serde(default)]
+    pub grid_sto: f32,
     /// Exported energy to nEPB services
     pub nepus: f32,
+    /// Exported energy to nEPB services, by service
+    #[serde(default)]
+    pub nepus_by_srv: BTreeMap<Service, f32>,
 }
 
 /// Datos de energía ponderada, paso A y B para el balance global
@@ -304,15 +397,69 @@ pub struct BalWeighted {
     /// Balance result for calculation step A
     pub a: RenNrenCo2,
     /// Weighted energy for calculation step A, by EPB service
-    pub a_by_srv: HashMap<Service, RenNrenCo2>,
+    pub a_by_srv: BTreeMap<Service, RenNrenCo2>,
     /// Balance result for calculation step B
     pub b: RenNrenCo2,
     /// Weighted energy, by EPB service
-    pub b_by_srv: HashMap<Service, RenNrenCo2>,
+    pub b_by_srv: BTreeMap<Service, RenNrenCo2>,
     /// Weighted delivered energy for calculation step B
     pub del: RenNrenCo2,
     /// Weighted exported energy for calculation step A
     pub exp_a: RenNrenCo2,
     /// Weighted exported energy for calculation step B
     pub exp: RenNrenCo2,
+    /// Weighted energy for calculation step B, by carrier
+    #[serde(default)]
+    pub b_by_cr: BTreeMap<Carrier, RenNrenCo2>,
+    /// Contribution of each carrier to the weighted energy for calculation step B (fraction of total)
+    #[serde(default)]
+    pub b_share_by_cr: BTreeMap<Carrier, WeightedShare>,
+}
+
+impl BalWeighted {
+    /// Actualiza la contribución de cada vector a los indicadores del paso B
+    ///
+    /// Los indicadores con valor absoluto inferior a `zero` se consideran nulos y la contribución
+    /// de cada vector a ellos es cero.
+    pub(crate) fn update_b_share_by_cr(&mut self, zero: f32) {
+        let share = |value: f32, total: f32| {
+            if total.abs() > zero {
+                value / total
+            } else {
+                0.0
+            }
+        };
+        let b = self.b;
+        self.b_share_by_cr = self
+            .b_by_cr
+            .iter()
+            .map(|(&carrier, we_cr)| {
+                (
+                    carrier,
+                    WeightedShare {
+                        ren: share(we_cr.ren, b.ren),
+                        nren: share(we_cr.nren, b.nren),
+                        tot: share(we_cr.tot(), b.tot()),
+                        co2: share(we_cr.co2, b.co2),
+                    },
+                )
+            })
+            .collect();
+    }
+}
+
+/// Contribución de un vector energético a los indicadores de energía ponderada (fracción del total)
+///
+/// Los vectores con exportación pueden tener contribuciones negativas y, por ello,
+/// otros vectores pueden superar la unidad.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct WeightedShare {
+    /// Fraction of the total renewable primary energy
+    pub ren: f32,
+    /// Fraction of the total non renewable primary energy
+    pub nren: f32,
+    /// Fraction of the total primary energy
+    pub tot: f32,
+    /// Fraction of the total CO2 emissions
+    pub co2: f32,
 }
@@ -0,0 +1,135 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Balance por ámbitos
+===================
+
+Energía usada y ponderada de los consumos privativos y comunes (`CTEEPBD_AMBITO`) de edificios de
+uso residencial colectivo.
+
+La energía ponderada de cada vector se reparte entre los ámbitos en proporción a su consumo EPB de ese
+vector, del mismo modo que se reparte entre los servicios o los sistemas.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    types::{Carrier, HasValues, RenNrenCo2, Scope, Service},
+    vecops::vecsum,
+    Components,
+};
+
+use super::BalanceCarrier;
+
+/// Balance de un ámbito (PRIVATIVO o COMUN)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScopeBalance {
+    /// Energía usada en servicios EPB (consumos y auxiliares), por vector, kWh
+    pub used_by_cr: BTreeMap<Carrier, f32>,
+    /// Energía usada en servicios EPB (consumos y auxiliares), por servicio, kWh
+    pub used_by_srv: BTreeMap<Service, f32>,
+    /// Energía usada en servicios EPB (consumos y auxiliares), kWh
+    pub used: f32,
+    /// Energía usada en servicios no EPB (incluye los consumos excluidos del balance EPB), kWh
+    pub nepus: f32,
+    /// Energía usada excluida del balance EPB por las reglas del ámbito (`CTE_AMBITO_EPB_<AMBITO>`),
+    /// por servicio declarado, kWh
+    pub excluded_by_srv: BTreeMap<Service, f32>,
+    /// Energía ponderada (paso A), kWh
+    pub we_a: RenNrenCo2,
+    /// Energía ponderada (paso B), kWh
+    pub we_b: RenNrenCo2,
+}
+
+impl ScopeBalance {
+    /// Calcula el balance de cada ámbito de los consumos
+    ///
+    /// * `excluded` - energía excluida del balance EPB, por ámbito y servicio declarado
+    ///
+    /// Devuelve un mapa vacío cuando ningún consumo declara su ámbito y no hay reglas de ámbito.
+    pub fn compute(
+        components: &Components,
+        balance_cr: &BTreeMap<Carrier, BalanceCarrier>,
+        excluded: &BTreeMap<Scope, BTreeMap<Service, f32>>,
+    ) -> BTreeMap<Scope, Self> {
+        let has_scopes = components
+            .data
+            .iter()
+            .any(|c| c.tags().has("CTEEPBD_AMBITO"));
+        if !has_scopes && excluded.is_empty() {
+            return BTreeMap::new();
+        }
+        let mut by_scope: BTreeMap<Scope, Self> = BTreeMap::new();
+        for c in components
+            .data
+            .iter()
+            .filter(|c| c.is_epb_use() || c.is_nepb_use())
+        {
+            let value = vecsum(c.values());
+            let scope = by_scope.entry(c.scope().unwrap_or_default()).or_default();
+            if c.is_epb_use() {
+                *scope.used_by_cr.entry(c.carrier()).or_default() += value;
+                *scope.used_by_srv.entry(c.service()).or_default() += value;
+                scope.used += value;
+            } else {
+                scope.nepus += value;
+            }
+        }
+        for (scope, excluded_by_srv) in excluded {
+            by_scope.entry(*scope).or_default().excluded_by_srv = excluded_by_srv.clone();
+        }
+        // Energía ponderada de cada vector, en proporción al consumo EPB de cada ámbito
+        for scope in by_scope.values_mut() {
+            for (carrier, used) in &scope.used_by_cr {
+                let Some(bal_cr) = balance_cr.get(carrier) else {
+                    continue;
+                };
+                if bal_cr.used.epus_an.abs() > f32::EPSILON {
+                    let f_scope = used / bal_cr.used.epus_an;
+                    scope.we_a += f_scope * bal_cr.we.a;
+                    scope.we_b += f_scope * bal_cr.we.b;
+                }
+            }
+        }
+        by_scope
+    }
+
+    /// Normalize values using area
+    pub fn normalize_by_area(&self, area: f32) -> Self {
+        let k_area = if area == 0.0 { 0.0 } else { 1.0 / area };
+        let mut bal = self.clone();
+        bal.used_by_cr.values_mut().for_each(|v| *v *= k_area);
+        bal.used_by_srv.values_mut().for_each(|v| *v *= k_area);
+        bal.used *= k_area;
+        bal.nepus *= k_area;
+        bal.excluded_by_srv.values_mut().for_each(|v| *v *= k_area);
+        bal.we_a *= k_area;
+        bal.we_b *= k_area;
+        bal
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Balance por sistemas
+====================
+
+Energía usada, producida y ponderada de cada sistema (id) de los componentes.
+
+Permite analizar cada equipo por separado. La energía ponderada de cada vector se reparte entre los
+sistemas en proporción a su consumo EPB de ese vector, del mismo modo que se reparte entre los servicios.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    types::{Carrier, HasValues, ProdSource, RenNrenCo2, Service},
+    vecops::vecsum,
+    Components,
+};
+
+use super::BalanceCarrier;
+
+/// Balance de un sistema (id)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SystemBalance {
+    /// Energía usada en servicios EPB (consumos y auxiliares), por vector, kWh
+    pub used_by_cr: BTreeMap<Carrier, f32>,
+    /// Energía usada en servicios EPB (consumos y auxiliares), por servicio, kWh
+    pub used_by_srv: BTreeMap<Service, f32>,
+    /// Energía usada en servicios EPB (consumos y auxiliares), kWh
+    pub used: f32,
+    /// Energía producida, por origen, kWh
+    pub prod_by_src: BTreeMap<ProdSource, f32>,
+    /// Energía producida, kWh
+    pub prod: f32,
+    /// Energía entregada o absorbida (SALIDA), por servicio, kWh
+    pub out_by_srv: BTreeMap<Service, f32>,
+    /// Energía ponderada (paso A), kWh
+    pub we_a: RenNrenCo2,
+    /// Energía ponderada (paso B), kWh
+    pub we_b: RenNrenCo2,
+}
+
+impl SystemBalance {
+    /// Calcula el balance de cada uno de los sistemas (id) de los componentes
+    pub fn compute(
+        components: &Components,
+        balance_cr: &BTreeMap<Carrier, BalanceCarrier>,
+    ) -> BTreeMap<i32, Self> {
+        let mut by_sys: BTreeMap<i32, Self> = BTreeMap::new();
+        for c in &components.data {
+            let value = vecsum(c.values());
+            let sys = by_sys.entry(c.id()).or_default();
+            if c.is_generated() {
+                *sys.prod_by_src.entry(c.prod_source()).or_default() += value;
+                sys.prod += value;
+            } else if c.is_out() {
+                *sys.out_by_srv.entry(c.service()).or_default() += value;
+            } else if c.is_epb_use() {
+                *sys.used_by_cr.entry(c.carrier()).or_default() += value;
+                *sys.used_by_srv.entry(c.service()).or_default() += value;
+                sys.used += value;
+            }
+        }
+        // Energía ponderada de cada vector, en proporción al consumo EPB de cada sistema
+        for sys in by_sys.values_mut() {
+            for (carrier, used) in &sys.used_by_cr {
+                let Some(bal_cr) = balance_cr.get(carrier) else {
+                    continue;
+                };
+                if bal_cr.used.epus_an.abs() > f32::EPSILON {
+                    let f_sys = used / bal_cr.used.epus_an;
+                    sys.we_a += f_sys * bal_cr.we.a;
+                    sys.we_b += f_sys * bal_cr.we.b;
+                }
+            }
+        }
+        by_sys
+    }
+
+    /// Normalize values using area
+    pub fn normalize_by_area(&self, area: f32) -> Self {
+        let k_area = if area == 0.0 { 0.0 } else { 1.0 / area };
+        let mut bal = self.clone();
+        bal.used_by_cr.values_mut().for_each(|v| *v *= k_area);
+        bal.used_by_srv.values_mut().for_each(|v| *v *= k_area);
+        bal.used *= k_area;
+        bal.prod_by_src.values_mut().for_each(|v| *v *= k_area);
+        bal.prod *= k_area;
+        bal.out_by_srv.values_mut().for_each(|v| *v *= k_area);
+        bal.we_a *= k_area;
+        bal.we_b *= k_area;
+        bal
+    }
+}
@@ -0,0 +1,153 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Coste de la energía
+===================
+
+Indicador económico del coste anual de la energía (EN 15459-1), obtenido a partir del balance por
+pasos de cálculo de cada vector y de los precios de la energía (€/kWh) declarados en los factores
+de paso con la etiqueta `CTEEPBD_COSTE` en su comentario
+(p.e. `ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331 # CTEEPBD_COSTE=0.15`).
+
+- el precio de los factores de suministro (`SUMINISTRO`, paso A) se aplica a la energía suministrada
+  desde la red (fuente `RED`) o in situ (fuente `INSITU`)
+- el precio de los factores de exportación a la red (`A_RED`, paso A) se aplica, como ingreso, a la energía
+  exportada a la red por cada fuente de producción
+- los precios pueden variar por paso de cálculo usando factores de paso con periodos de vigencia
+  (`CTEEPBD_VIGENCIA`) con distintos precios (ver [`Factor::cost_t`](crate::types::Factor::cost_t))
+- el coste neto de cada vector se reparte entre los servicios EPB en proporción a su consumo, igual
+  que la energía ponderada por servicio
+
+El coste solo se calcula si algún factor de paso tiene precio y es nulo para los vectores sin precio,
+que se indican para avisar de que el coste total es parcial.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    types::{Carrier, Dest, Service, Source, Step},
+    Factors,
+};
+
+use super::BalanceCarrier;
+
+/// Coste anual de la energía (EN 15459-1)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EnergyCost {
+    /// Coste de la energía suministrada, por vector, €/an
+    pub del_by_cr: BTreeMap<Carrier, f32>,
+    /// Ingresos por la energía exportada a la red, por vector, €/an
+    pub exp_by_cr: BTreeMap<Carrier, f32>,
+    /// Coste neto anual de la energía de los servicios EPB, por servicio, €/an
+    #[serde(default)]
+    pub by_srv: BTreeMap<Service, f32>,
+    /// Coste neto anual de la energía (suministrada - exportada), €/an
+    pub total: f32,
+    /// Coste neto anual de la energía por superficie de referencia, €/m²·an
+    pub total_m2: f32,
+    /// Vectores con energía suministrada sin precio declarado
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub unpriced: Vec<Carrier>,
+}
+
+impl EnergyCost {
+    /// Calcula el coste anual de la energía a partir del balance por vectores y los precios de los factores de paso
+    ///
+    /// Devuelve None si ningún factor de paso tiene precio declarado
+    pub fn compute(
+        balance_cr: &BTreeMap<Carrier, BalanceCarrier>,
+        wfactors: &Factors,
+        arearef: f32,
+    ) -> Option<Self> {
+        if wfactors
+            .wdata
+            .iter()
+            .all(|f| f.cost().is_none() && f.cost_t.is_empty())
+        {
+            return None;
+        }
+        let cost = |carrier, source, dest, values_t: &[f32]| {
+            wfactors
+                .find_factor(carrier, source, dest, Step::A)
+                .ok()
+                .and_then(|f| f.cost_of(values_t))
+        };
+        let mut result = Self::default();
+        for (&carrier, bal) in balance_cr {
+            let del_grid = cost(carrier, Source::RED, Dest::SUMINISTRO, &bal.del.grid_t);
+            if del_grid.is_none() && bal.del.grid_an > 0.0 && !carrier.is_onsite() {
+                result.unpriced.push(carrier);
+            }
+            let del = del_grid.unwrap_or_default()
+                + cost(carrier, Source::INSITU, Dest::SUMINISTRO, &bal.del.onst_t)
+                    .unwrap_or_default();
+            // Energía exportada a la red por cada fuente, en proporción a su exportación en cada paso
+            let exp: f32 = bal
+                .exp
+                .by_src_t
+                .iter()
+                .map(|(src, exp_src_t)| {
+                    let exp_grid_src_t: Vec<f32> = exp_src_t
+                        .iter()
+                        .zip(&bal.exp.t)
+                        .zip(&bal.exp.grid_t)
+                        .map(|((exp_src, exp), exp_grid)| {
+                            if *exp > 0.0 {
+                                exp_grid * exp_src / exp
+                            } else {
+                                0.0
+                            }
+                        })
+                        .collect();
+                    cost(carrier, Source::from(*src), Dest::A_RED, &exp_grid_src_t)
+                        .unwrap_or_default()
+                })
+                .sum();
+            if del != 0.0 {
+                result.del_by_cr.insert(carrier, del);
+            }
+            if exp != 0.0 {
+                result.exp_by_cr.insert(carrier, exp);
+            }
+            // Reparto del coste neto entre servicios EPB según su consumo
+            if bal.used.epus_an > 0.0 && (del - exp) != 0.0 {
+                for (service, used_srv) in &bal.used.epus_by_srv_an {
+                    *result.by_srv.entry(*service).or_default() +=
+                        (del - exp) * used_srv / bal.used.epus_an;
+                }
+            }
+            result.total += del - exp;
+        }
+        result.total_m2 = if arearef > 0.0 {
+            result.total / arearef
+        } else {
+            0.0
+        };
+        Some(result)
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Electrificación del edificio
+============================
+
+Fracción de la energía final usada en servicios EPB que corresponde a la electricidad (de red,
+producida in situ o cogenerada), anual y mensual.
+
+La energía final incluye todos los vectores energéticos salvo la energía ambiente y la solar térmica
+capturadas in situ (EAMBIENTE y sus subtipos y TERMOSOLAR), que no son energía suministrada.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{monthly_values, Carrier};
+
+use super::BalanceCarrier;
+
+/// Indicador de electrificación del edificio
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Electrification {
+    /// Energía final usada en servicios EPB (excluye la energía ambiente y solar térmica), kWh
+    pub final_an: f32,
+    /// Electricidad usada en servicios EPB, kWh
+    pub el_an: f32,
+    /// Fracción de la energía final EPB que es electricidad, -
+    pub fraction: f32,
+    /// Fracción mensual de la energía final EPB que es electricidad, -
+    ///
+    /// Solo para resoluciones mensual, diaria u horaria. Es 0 en los meses sin consumo.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fraction_monthly: Vec<f32>,
+}
+
+impl Electrification {
+    /// Calcula el indicador de electrificación a partir del balance por vectores
+    pub fn compute(balance_cr: &BTreeMap<Carrier, BalanceCarrier>) -> Self {
+        let mut result = Self::default();
+        let mut final_monthly = Some(vec![0.0; 12]);
+        let mut el_monthly = vec![0.0; 12];
+        for (carrier, bal) in balance_cr.iter().filter(|(c, _)| !c.is_onsite()) {
+            result.final_an += bal.used.epus_an;
+            let monthly = monthly_values(&bal.used.epus_t);
+            if let (Some(final_m), Some(monthly)) = (final_monthly.as_mut(), &monthly) {
+                final_m.iter_mut().zip(monthly).for_each(|(f, v)| *f += v);
+            } else {
+                final_monthly = None;
+            }
+            if *carrier == Carrier::ELECTRICIDAD {
+                result.el_an = bal.used.epus_an;
+                if let Some(monthly) = monthly {
+                    el_monthly = monthly;
+                }
+            }
+        }
+        let fraction = |el: f32, fin: f32| if fin > 0.0 { el / fin } else { 0.0 };
+        result.fraction = fraction(result.el_an, result.final_an);
+        if let Some(final_monthly) = final_monthly.filter(|_| !balance_cr.is_empty()) {
+            result.fraction_monthly = el_monthly
+                .iter()
+                .zip(&final_monthly)
+                .map(|(el, fin)| fraction(*el, *fin))
+                .collect();
+        }
+        result
+    }
+}
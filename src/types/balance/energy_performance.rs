@@ -32,13 +32,23 @@ según la EN ISO 52000-1.
 
 */
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
-use crate::{types::Carrier, Components, Factors};
+use crate::{
+    error::{unknown_value_error, EpbdError, Result},
+    types::{
+        BillsReconciliation, Carrier, Co2Inventory, ExergyBalance, Factor, MarginalCarbon,
+        RenNrenCo2, Scope, Service, Tolerances, Warning, WarningCode,
+    },
+    Components, Factors,
+};
 
-use super::{BalanceCarrier, Balance};
+use super::{
+    Balance, BalanceCarrier, Electrification, EnergyCost, Flow, OnsiteRenewable, PartialYear,
+    PeakPowers, PvPerformance, ScopeBalance, ServiceSeason, SystemBalance, UnitShare, UnmetNeeds,
+};
 
 // Overall energy performance
 // --------------------------
@@ -51,12 +61,18 @@ pub struct EnergyPerformance {
     pub components: Components,
     /// Weighting factors (weighting factors + metadata)
     pub wfactors: Factors,
+    /// Weighting factors with a validity period (`CTEEPBD_VIGENCIA` tag), as declared
+    ///
+    /// They are combined in `wfactors` as factors with timestep values
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub wfactors_periods: Vec<Factor>,
     /// Exported energy factor [0, 1]
     pub k_exp: f32,
     /// Reference area used for energy performance ratios (>1e-3)
     pub arearef: f32,
     /// Energy balance results by carrier
-    pub balance_cr: HashMap<Carrier, BalanceCarrier>,
+    pub balance_cr: BTreeMap<Carrier, BalanceCarrier>,
     /// Global energy balance results
     pub balance: Balance,
     /// Global energy balance results expressed as area ratios
@@ -70,13 +86,349 @@ pub struct EnergyPerformance {
     /// Renewable Energy Ratio considering onsite perimeter
     /// RER_onst = we_ren_onst / we_tot
     pub rer_onst: f32,
+    /// Numerical tolerances used in the computation
+    #[serde(default)]
+    pub tolerances: Tolerances,
+    /// Duplicated energy components found in components (only with the `Warn` duplicates policy)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub duplicates: Vec<DuplicateComponent>,
+    /// Whole building energy balance, including non EPB uses (informative)
+    ///
+    /// Only available when there are non EPB uses
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub whole_building: Option<WholeBuildingBalance>,
+    /// Implicit peak power of energy needs, EPB energy use and grid delivered energy
+    ///
+    /// Only available for monthly, daily or hourly timesteps
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peaks: Option<PeakPowers>,
+    /// Photovoltaic system performance indicators (informative)
+    ///
+    /// Only available when the peak power is declared and there is onsite electricity production (EL_INSITU)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pv: Option<PvPerformance>,
+    /// Energy use and weighted energy of services restricted to their declared seasons (active months)
+    ///
+    /// Only available when seasons are declared with `CTE_TEMPORADA_<SERVICIO>` metadata
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub seasons: BTreeMap<Service, ServiceSeason>,
+    /// Energy needs not covered by the energy delivered by the systems (SALIDA), by service
+    ///
+    /// Only available for services with declared energy needs and delivered energy
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub unmet_needs: BTreeMap<Service, UnmetNeeds>,
+    /// Share of the final energy used in EPB services supplied by electricity (annual and monthly)
+    #[serde(default)]
+    pub electrification: Electrification,
+    /// Renewable primary energy produced onsite and used in EPB services, by source and by service
+    ///
+    /// Only available when onsite produced energy is used in EPB services
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub onsite_renewable: Option<OnsiteRenewable>,
+    /// Energy use, production and weighted energy of each system (id)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub balance_by_system: BTreeMap<i32, SystemBalance>,
+    /// Energy use and weighted energy of private and common uses (`CTEEPBD_AMBITO` tag)
+    ///
+    /// Only available when some energy use declares its scope or there are scope rules (`CTE_AMBITO_EPB_<AMBITO>`)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub balance_by_scope: BTreeMap<Scope, ScopeBalance>,
+    /// Energy use and weighted energy of each unit (dwelling or premises), including its share of
+    /// common uses and onsite electricity production
+    ///
+    /// Only available when private energy uses declare their unit (`CTEEPBD_UNIDAD` tag)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub unit_shares: BTreeMap<String, UnitShare>,
+    /// Annual energy cost (EN 15459-1), from the energy prices declared in the weighting factors
+    ///
+    /// Only available when some weighting factor declares its price (`CTEEPBD_COSTE` tag)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub energy_cost: Option<EnergyCost>,
+    /// Annual energy flows by carrier, source and EPB service
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub flows: Vec<Flow>,
+    /// Measured and estimated data of a calculation with an incomplete year (provisional indicators)
+    ///
+    /// Only available when measured months are declared with `CTE_MESES_MEDIDOS` metadata
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partial_year: Option<PartialYear>,
+    /// Embodied energy and emissions, by reference area (informative, not included in the EPB balance)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embodied_m2: Option<RenNrenCo2>,
+    /// Optional exergy balance results
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exergy: Option<ExergyBalance>,
+    /// Optional reconciliation of delivered energy from the grid with billed energy
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bills: Option<BillsReconciliation>,
+    /// Optional marginal carbon footprint results (informative, for research)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marginal_co2: Option<MarginalCarbon>,
+    /// Optional emissions computed with an alternative set of emission factors (e.g. GHG inventory)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub co2_inventory: Option<Co2Inventory>,
+    /// Structured warnings found on data preparation and energy balance computation
+    ///
+    /// Includes the warnings generated on components normalization and weighting factors stripping,
+    /// those found on the energy balance and those of the additional indicators (see `cte` module)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<Warning>,
     /// Generic miscelaneous user provided data
     pub misc: Option<MiscMap>,
 }
 
+impl EnergyPerformance {
+    /// Avisos con el código indicado
+    pub fn warnings_for(&self, code: WarningCode) -> impl Iterator<Item = &Warning> {
+        self.warnings.iter().filter(move |w| w.code == code)
+    }
+
+    /// Sustituye los avisos con el código indicado por el aviso dado, si existe
+    ///
+    /// Permite actualizar los avisos de un cálculo que puede repetirse (p.e. indicadores adicionales)
+    pub fn replace_warning(
+        &mut self,
+        code: WarningCode,
+        component: &str,
+        warning: Option<Warning>,
+    ) {
+        self.warnings
+            .retain(|w| !(w.code == code && w.component.as_deref() == Some(component)));
+        self.warnings.extend(warning);
+    }
+
+    /// Factor de exportación aplicado a un vector energético
+    ///
+    /// Es el declarado para el vector con el metadato `CTE_KEXP_<VECTOR>` o, en su defecto, el
+    /// factor de exportación general (`k_exp`).
+    pub fn k_exp_for(&self, carrier: Carrier) -> f32 {
+        self.components
+            .kexp_by_carrier()
+            .ok()
+            .and_then(|kexp| kexp.get(&carrier).copied())
+            .unwrap_or(self.k_exp)
+    }
+
+    /// Devuelve los resultados expresados para una nueva área de referencia, sin repetir el balance
+    ///
+    /// Se recalculan los resultados por m² (`balance_m2`, balance del edificio completo,
+    /// energía embebida, balance exergético, coste de la energía y valores por m² de la
+    /// compensación simplificada incorporados a `misc`). Los indicadores adimensionales (RER, fracciones) no cambian.
+    ///
+    /// # Errors
+    ///
+    /// * Área de referencia nula, casi nula (por debajo de la tolerancia) o no finita
+    pub fn with_arearef(mut self, arearef: f32) -> Result<Self> {
+        if !arearef.is_finite() || arearef < self.tolerances.arearef_min {
+            return Err(EpbdError::WrongInput(format!(
+                "El área de referencia no puede ser nula o casi nula y se encontró {}",
+                arearef
+            )));
+        };
+        let k_area = self.arearef / arearef;
+        self.arearef = arearef;
+        self.balance_m2 = self.balance.normalize_by_area(arearef);
+        if let Some(wb) = self.whole_building.as_mut() {
+            wb.balance_m2 = wb.balance.normalize_by_area(arearef);
+        }
+        if !self.components.embodied.is_empty() {
+            self.embodied_m2 = Some((1.0 / arearef) * self.components.embodied_total());
+        }
+        if let Some(xbal) = self.exergy.as_mut() {
+            xbal.b_m2 = xbal.b / arearef;
+        }
+        if let Some(cost) = self.energy_cost.as_mut() {
+            cost.total_m2 = cost.total / arearef;
+        }
+        if let Some(mbal) = self.marginal_co2.as_mut() {
+            mbal.net_m2 = (mbal.del_grid - mbal.avoided) / arearef;
+        }
+        if let Some(inventory) = self.co2_inventory.as_mut() {
+            inventory.official_m2 = self.balance_m2.we.b.co2;
+            inventory.b_m2 = inventory.b / arearef;
+        }
+        if let Some(map) = self.misc.as_mut() {
+            for key in [
+                "compensacion_simplificada_ren",
+                "compensacion_simplificada_nren",
+                "compensacion_simplificada_tot",
+                "compensacion_simplificada_co2",
+            ] {
+                if let Some(value) = map.get(key).and_then(|v| v.parse::<f32>().ok()) {
+                    map.insert(key.to_string(), format!("{:.3}", value * k_area));
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    /// Superficie por la que se dividen los resultados absolutos para expresarlos en las unidades indicadas, m2
+    ///
+    /// Es el área de referencia para los resultados por m2 y la unidad para los resultados absolutos.
+    pub fn units_area(&self, units: BalanceUnits) -> f32 {
+        match units {
+            BalanceUnits::PerArea => self.arearef,
+            BalanceUnits::Absolute => 1.0,
+        }
+    }
+
+    /// Balance global en las unidades indicadas
+    pub fn balance_in(&self, units: BalanceUnits) -> &Balance {
+        match units {
+            BalanceUnits::PerArea => &self.balance_m2,
+            BalanceUnits::Absolute => &self.balance,
+        }
+    }
+
+    /// Balance por vector energético en las unidades indicadas
+    pub fn balance_cr_in(&self, units: BalanceUnits) -> BTreeMap<Carrier, BalanceCarrier> {
+        let area = self.units_area(units);
+        self.balance_cr
+            .iter()
+            .map(|(cr, bal)| (*cr, bal.normalize_by_area(area)))
+            .collect()
+    }
+
+    /// Balance por sistema (id) en las unidades indicadas
+    pub fn balance_by_system_in(&self, units: BalanceUnits) -> BTreeMap<i32, SystemBalance> {
+        let area = self.units_area(units);
+        self.balance_by_system
+            .iter()
+            .map(|(id, bal)| (*id, bal.normalize_by_area(area)))
+            .collect()
+    }
+
+    /// Balance por ámbito en las unidades indicadas
+    pub fn balance_by_scope_in(&self, units: BalanceUnits) -> BTreeMap<Scope, ScopeBalance> {
+        let area = self.units_area(units);
+        self.balance_by_scope
+            .iter()
+            .map(|(scope, bal)| (*scope, bal.normalize_by_area(area)))
+            .collect()
+    }
+
+    /// Energía primaria renovable in situ utilizada en las unidades indicadas
+    pub fn onsite_renewable_in(&self, units: BalanceUnits) -> Option<OnsiteRenewable> {
+        let area = self.units_area(units);
+        self.onsite_renewable
+            .as_ref()
+            .map(|onst| onst.normalize_by_area(area))
+    }
+
+    /// Reparto de la energía entre unidades en las unidades indicadas
+    ///
+    /// Los valores por m2 se refieren al área de referencia del edificio, no a la de cada unidad.
+    pub fn unit_shares_in(&self, units: BalanceUnits) -> BTreeMap<String, UnitShare> {
+        let area = self.units_area(units);
+        self.unit_shares
+            .iter()
+            .map(|(unit, share)| (unit.clone(), share.normalize_by_area(area)))
+            .collect()
+    }
+}
+
+/// Unidades de presentación de los resultados del balance
+///
+/// Los resultados se calculan en valor absoluto y se pueden expresar por m2 de área de referencia
+/// (p.e. `EnergyPerformance::balance_cr_in`). Los indicadores reglamentarios (C_ep, E_CO2) se
+/// expresan siempre por m2.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BalanceUnits {
+    /// Valores por m2 de área de referencia (kWh/m2.an)
+    #[default]
+    PerArea,
+    /// Valores absolutos (kWh/an)
+    Absolute,
+}
+
+impl BalanceUnits {
+    /// Unidades de la energía
+    pub fn energy(&self) -> &'static str {
+        match self {
+            BalanceUnits::PerArea => "kWh/m2.an",
+            BalanceUnits::Absolute => "kWh/an",
+        }
+    }
+
+    /// Unidades de las emisiones
+    pub fn co2(&self) -> &'static str {
+        match self {
+            BalanceUnits::PerArea => "kg_CO2e/m2.an",
+            BalanceUnits::Absolute => "kg_CO2e/an",
+        }
+    }
+}
+
+impl std::str::FromStr for BalanceUnits {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<BalanceUnits> {
+        match s {
+            "m2" => Ok(BalanceUnits::PerArea),
+            "abs" => Ok(BalanceUnits::Absolute),
+            _ => Err(unknown_value_error("unidades", s, &["m2", "abs"])),
+        }
+    }
+}
+
+/// Balance del edificio completo (usos EPB y no EPB)
+///
+/// Los consumos de usos no EPB se ponderan con sus factores de suministro, como los de usos EPB,
+/// y la energía producida in situ puede cubrir cualquier consumo del edificio.
+/// Es un balance informativo, útil para auditorías energéticas o la comparación con la facturación.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WholeBuildingBalance {
+    /// Whole building energy balance results
+    pub balance: Balance,
+    /// Whole building energy balance results expressed as area ratios
+    pub balance_m2: Balance,
+    /// Renewable Energy Ratio considering the distant perimeter
+    pub rer: f32,
+}
+
+/// Componente de energía duplicado
+///
+/// Componente cuya línea de componentes aparece repetida (ver `Components::duplicates`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DuplicateComponent {
+    /// Component line
+    pub component: String,
+    /// Number of occurrences (>1)
+    pub count: usize,
+}
+
+impl std::fmt::Display for DuplicateComponent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "componente duplicado ({} apariciones): {}",
+            self.count, self.component
+        )
+    }
+}
+
 /// Diccionario de valores adicionales
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct MiscMap(pub HashMap<String, String>);
+pub struct MiscMap(pub BTreeMap<String, String>);
 
 impl MiscMap {
     /// Get value as a string with 1 digit precision or a dash if value is missing or is not a number
@@ -86,6 +438,13 @@ impl MiscMap {
             .unwrap_or_else(|| "-".to_string())
     }
 
+    /// Get value as a string with 2 digits precision or a dash if value is missing or is not a number
+    pub fn get_str_2d(&self, key: &str) -> String {
+        self.get(key)
+            .and_then(|v| v.parse::<f32>().map(|r| format!("{:.2}", r)).ok())
+            .unwrap_or_else(|| "-".to_string())
+    }
+
     /// Get value as a string for a value, as a percent with 1 digit precision or a dash if value is missing or is not a number
     pub fn get_str_pct1d(&self, key: &str) -> String {
         self.get(key)
@@ -95,7 +454,7 @@ impl MiscMap {
 }
 
 impl std::ops::Deref for MiscMap {
-    type Target = HashMap<String, String>;
+    type Target = BTreeMap<String, String>;
 
     fn deref(&self) -> &Self::Target {
         &self.0
@@ -0,0 +1,124 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Flujos de energía
+=================
+
+Desglose anual del balance por combinación de vector energético, origen (red, in situ o cogeneración)
+y servicio EPB, útil p.e. para construir diagramas Sankey.
+
+La energía de cada flujo es la energía del vector usada en el servicio que procede de ese origen:
+la producción usada en el servicio para los orígenes in situ y de cogeneración y el resto del consumo
+para la red. La energía ponderada de cada servicio (pasos A y B) se reparte entre sus orígenes en
+proporción a la energía ponderada suministrada por cada origen, de modo que la suma de los flujos de
+un servicio coincide con su energía ponderada.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Carrier, RenNrenCo2, Service, Source};
+
+use super::BalanceCarrier;
+
+/// Flujo anual de energía de un vector, desde un origen, a un servicio EPB
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Flow {
+    /// Vector energético
+    pub carrier: Carrier,
+    /// Origen de la energía (red, in situ o cogeneración)
+    pub source: Source,
+    /// Servicio EPB
+    pub service: Service,
+    /// Energía usada en el servicio procedente del origen, kWh
+    pub kwh: f32,
+    /// Energía ponderada (paso A), kWh
+    pub we_a: RenNrenCo2,
+    /// Energía ponderada (paso B), kWh
+    pub we_b: RenNrenCo2,
+}
+
+impl Flow {
+    /// Calcula los flujos de energía de todos los vectores, orígenes y servicios EPB con consumo
+    pub fn compute(balance_cr: &BTreeMap<Carrier, BalanceCarrier>) -> Vec<Self> {
+        let mut flows = Vec::new();
+        for (carrier, bal) in balance_cr {
+            // Energía ponderada suministrada por unidad de energía suministrada, para cada origen
+            let unit = |we: RenNrenCo2, del_an: f32| {
+                if del_an > f32::EPSILON {
+                    we.tot() / del_an
+                } else {
+                    0.0
+                }
+            };
+            let unit_red = unit(bal.we.del_grid, bal.del.grid_an);
+            let unit_onst = unit(bal.we.del_onst, bal.del.onst_an);
+            let unit_cgn = unit(bal.we.del_cgn, bal.del.cgn_an);
+
+            for (service, used_srv) in &bal.used.epus_by_srv_an {
+                // Producción usada en el servicio, por origen
+                let (mut onst, mut cgn) = (0.0, 0.0);
+                for (src, by_srv) in &bal.prod.epus_by_srv_by_src_an {
+                    let value = by_srv.get(service).copied().unwrap_or_default();
+                    match Source::from(*src) {
+                        Source::COGEN => cgn += value,
+                        _ => onst += value,
+                    }
+                }
+                let grid = (used_srv - onst - cgn).max(0.0);
+                let by_src = [
+                    (Source::RED, grid, grid * unit_red),
+                    (Source::INSITU, onst, onst * unit_onst),
+                    (Source::COGEN, cgn, cgn * unit_cgn),
+                ];
+                let kwh_tot: f32 = by_src.iter().map(|(_, kwh, _)| kwh).sum();
+                let weight_tot: f32 = by_src.iter().map(|(_, _, weight)| weight).sum();
+                let we_a = bal.we.a_by_srv.get(service).copied().unwrap_or_default();
+                let we_b = bal.we.b_by_srv.get(service).copied().unwrap_or_default();
+                for (source, kwh, weight) in by_src {
+                    if kwh <= f32::EPSILON {
+                        continue;
+                    }
+                    let share = if weight_tot > f32::EPSILON {
+                        weight / weight_tot
+                    } else {
+                        kwh / kwh_tot
+                    };
+                    flows.push(Flow {
+                        carrier: *carrier,
+                        source,
+                        service: *service,
+                        kwh,
+                        we_a: share * we_a,
+                        we_b: share * we_b,
+                    });
+                }
+            }
+        }
+        flows
+    }
+}
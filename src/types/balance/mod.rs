@@ -33,11 +33,39 @@ según la EN ISO 52000-1.
 */
 
 mod all_carriers;
+mod by_scope;
+mod by_system;
+mod cost;
+mod electrification;
 mod energy_performance;
+mod flows;
+mod onsite;
+mod partial_year;
+mod peaks;
+mod pv;
+mod season;
 mod single_carrier;
+mod summary;
+mod unmet;
+mod units;
 
-pub use all_carriers::{BalDel, BalExp, BalProd, BalUsed, Balance};
-pub use energy_performance::EnergyPerformance;
+pub use all_carriers::{BalDel, BalExp, BalProd, BalUsed, Balance, WeightedShare};
+pub use by_scope::ScopeBalance;
+pub use by_system::SystemBalance;
+pub use cost::EnergyCost;
+pub use electrification::Electrification;
+pub use energy_performance::{
+    BalanceUnits, DuplicateComponent, EnergyPerformance, WholeBuildingBalance,
+};
+pub use flows::Flow;
+pub use onsite::OnsiteRenewable;
+pub use partial_year::PartialYear;
+pub use peaks::{PeakPower, PeakPowers};
+pub use pv::PvPerformance;
+pub use season::ServiceSeason;
 pub use single_carrier::{
     BalanceCarrier, DeliveredEnergy, ExportedEnergy, ProducedEnergy, UsedEnergy, WeightedEnergy,
 };
+pub use summary::SummaryReport;
+pub use unmet::UnmetNeeds;
+pub use units::UnitShare;
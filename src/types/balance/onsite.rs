@@ -0,0 +1,107 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Energía renovable in situ utilizada
+===================================
+
+Energía primaria renovable producida in situ y utilizada en servicios EPB, en valor absoluto,
+con su desglose por origen de la producción y por servicio, para la justificación del DB-HE (HE0).
+
+Se obtiene de la energía producida in situ (salvo cogeneración) usada en servicios EPB, ponderada
+con la parte renovable del factor de paso de suministro in situ (paso A) de cada vector.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Result,
+    types::{Carrier, Dest, ProdSource, Service, Source, Step},
+    Factors,
+};
+
+use super::BalanceCarrier;
+
+/// Energía primaria renovable producida in situ y utilizada en servicios EPB
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OnsiteRenewable {
+    /// Energía producida in situ y usada en servicios EPB (energía final), kWh
+    pub used_an: f32,
+    /// Energía primaria renovable producida in situ y usada en servicios EPB, kWh
+    pub ren_an: f32,
+    /// Energía primaria renovable producida in situ y usada en servicios EPB, por origen, kWh
+    pub ren_by_src: BTreeMap<ProdSource, f32>,
+    /// Energía primaria renovable producida in situ y usada en servicios EPB, por servicio, kWh
+    pub ren_by_srv: BTreeMap<Service, f32>,
+}
+
+impl OnsiteRenewable {
+    /// Calcula la energía primaria renovable in situ utilizada a partir del balance por vectores
+    ///
+    /// Devuelve `None` si no se usa en servicios EPB energía producida in situ.
+    pub fn compute(
+        balance_cr: &BTreeMap<Carrier, BalanceCarrier>,
+        wfactors: &Factors,
+    ) -> Result<Option<Self>> {
+        let mut result = Self::default();
+        for (carrier, bal) in balance_cr {
+            for (src, by_srv_t) in &bal.prod.epus_by_srv_by_src_t {
+                if Source::from(*src) != Source::INSITU {
+                    continue;
+                }
+                let factor =
+                    wfactors.find_factor(*carrier, Source::INSITU, Dest::SUMINISTRO, Step::A)?;
+                for (service, values_t) in by_srv_t {
+                    let used_an: f32 = values_t.iter().sum();
+                    if used_an == 0.0 {
+                        continue;
+                    }
+                    let ren = factor.weigh(used_an, values_t).ren;
+                    result.used_an += used_an;
+                    result.ren_an += ren;
+                    *result.ren_by_src.entry(*src).or_default() += ren;
+                    *result.ren_by_srv.entry(*service).or_default() += ren;
+                }
+            }
+        }
+        if result.used_an == 0.0 {
+            return Ok(None);
+        }
+        Ok(Some(result))
+    }
+
+    /// Normalize values using area
+    pub fn normalize_by_area(&self, area: f32) -> Self {
+        let k_area = if area == 0.0 { 0.0 } else { 1.0 / area };
+        let mut onst = self.clone();
+        onst.used_an *= k_area;
+        onst.ren_an *= k_area;
+        onst.ren_by_src.values_mut().for_each(|v| *v *= k_area);
+        onst.ren_by_srv.values_mut().for_each(|v| *v *= k_area);
+        onst
+    }
+}
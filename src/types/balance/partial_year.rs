@@ -0,0 +1,157 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+/*!
+Año incompleto (datos medidos parciales)
+========================================
+
+Estimación del desempeño energético en operación a partir de datos medidos de parte del año
+(p.e. 8 meses), declarados con el metadato `CTE_MESES_MEDIDOS` de los componentes.
+
+Los valores de los meses no medidos de cada componente se extrapolan a partir de los medidos con
+perfiles mensuales normalizados declarados con los metadatos `CTE_PERFIL_<SERVICIO>` (consumos,
+auxiliares y energía saliente), `CTE_PERFIL_<ORIGEN>` (producción) o `CTE_PERFIL` (todos los
+componentes), o un perfil uniforme en su defecto. Los indicadores obtenidos son provisionales.
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{Energy, HasValues},
+    Components,
+};
+
+/// Datos de un cálculo con año incompleto
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PartialYear {
+    /// Meses con datos medidos (1 a 12)
+    pub months: Vec<u32>,
+    /// Consumo (CONSUMO y AUX) en los meses medidos, kWh
+    pub used_measured: f32,
+    /// Consumo (CONSUMO y AUX) estimado en los meses no medidos, kWh
+    pub used_estimated: f32,
+}
+
+impl PartialYear {
+    /// Fracción del consumo anual que procede de datos medidos [0, 1]
+    ///
+    /// Sin consumos, es la fracción de meses medidos
+    pub fn measured_fraction(&self) -> f32 {
+        let total = self.used_measured + self.used_estimated;
+        if total > 0.0 {
+            self.used_measured / total
+        } else {
+            self.months.len() as f32 / 12.0
+        }
+    }
+
+    /// Completa los componentes con los valores estimados de los meses no medidos
+    ///
+    /// Devuelve `None` si no se declaran meses medidos con el metadato `CTE_MESES_MEDIDOS`.
+    ///
+    /// Para cada componente, el valor anual se estima como el cociente entre la suma de sus valores
+    /// medidos y la fracción del perfil correspondiente a los meses medidos, y el valor de cada mes
+    /// no medido es el producto del valor anual estimado por la fracción del perfil de ese mes.
+    ///
+    /// # Errors
+    ///
+    /// * Meses medidos o perfiles mal definidos (ver `Components::measured_months` y `Components::monthly_profile`)
+    /// * Componentes con un número de pasos de cálculo distinto de 12 (mensuales)
+    /// * Componente con valores medidos y perfil nulo en los meses medidos
+    pub fn extrapolate(components: &Components) -> Result<Option<(Components, Self)>> {
+        let Some(months) = components.measured_months()? else {
+            return Ok(None);
+        };
+        if components.num_steps() != 12 {
+            return Err(EpbdError::WrongInput(format!(
+                "El cálculo con año incompleto (CTE_MESES_MEDIDOS) necesita datos mensuales (12 pasos de cálculo) y se encontraron {} pasos",
+                components.num_steps()
+            )));
+        }
+        let is_measured = |i: usize| months.contains(&(i as u32 + 1));
+
+        let mut completed = components.clone();
+        let mut partial = PartialYear {
+            months: months.clone(),
+            ..Default::default()
+        };
+        for component in &mut completed.data {
+            let key = match component {
+                Energy::Prod(e) => e.source.to_string(),
+                Energy::Used(e) => e.service.to_string(),
+                Energy::Aux(e) => e.service.to_string(),
+                Energy::Out(e) => e.service.to_string(),
+            };
+            let profile = match components.monthly_profile(&key)? {
+                Some(profile) => profile,
+                None => components
+                    .monthly_profile("")?
+                    .unwrap_or_else(|| vec![1.0 / 12.0; 12]),
+            };
+            let measured: f32 = (0..12)
+                .filter(|&i| is_measured(i))
+                .map(|i| component.values()[i])
+                .sum();
+            let profile_measured: f32 = (0..12)
+                .filter(|&i| is_measured(i))
+                .map(|i| profile[i])
+                .sum();
+            let annual = if profile_measured > 0.0 {
+                measured / profile_measured
+            } else if measured == 0.0 {
+                0.0
+            } else {
+                return Err(EpbdError::WrongInput(format!(
+                    "No se pueden estimar los meses no medidos del componente `{}` con un perfil nulo en los meses medidos",
+                    component
+                )));
+            };
+            let values = component.values_mut();
+            for (i, value) in values.iter_mut().enumerate() {
+                if !is_measured(i) {
+                    *value = annual * profile[i];
+                }
+            }
+            if component.is_used() || component.is_aux() {
+                partial.used_measured += measured;
+                partial.used_estimated += component.values_sum() - measured;
+            }
+        }
+        Ok(Some((completed, partial)))
+    }
+}
+
+impl std::fmt::Display for PartialYear {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pct_measured = 100.0 * self.measured_fraction();
+        write!(
+            f,
+            "año incompleto con {} meses medidos: consumo medido {:.1} % y estimado {:.1} %",
+            self.months.len(),
+            pct_measured,
+            100.0 - pct_measured
+        )
+    }
+}
@@ -0,0 +1,168 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Potencias pico implícitas
+=========================
+
+Potencias pico implícitas en las series de demanda y de consumo de energía.
+
+La potencia de cada paso de cálculo es la potencia media necesaria para obtener su energía
+en la duración del paso (p.e. 744 h en enero, en series mensuales, o 1 h en series horarias),
+por lo que estos valores son orientativos y crecen al reducir la duración del paso de cálculo.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    types::{timestep_hours, timestep_label, Carrier, Kw, Kwh, Service},
+    Components,
+};
+
+use super::BalanceCarrier;
+
+/// Potencia pico implícita de una serie de energía
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeakPower {
+    /// Potencia media en el paso de cálculo del pico, kW
+    pub power: Kw,
+    /// Energía del paso de cálculo del pico, kWh
+    pub energy: Kwh,
+    /// Paso de cálculo del pico (desde 0)
+    pub step: usize,
+    /// Posición del pico en el año (p.e. `mes 7`, `15/07` o `15/07 14h`)
+    pub position: String,
+}
+
+impl PeakPower {
+    /// Potencia pico implícita de una serie anual de energía
+    ///
+    /// Se toma el paso de cálculo con mayor potencia media (el primero, en caso de empate).
+    /// Devuelve None si la serie no tiene una longitud admitida (12, 365 u 8760 pasos)
+    /// o si no tiene valores positivos.
+    pub fn from_values(values: &[f32]) -> Option<Self> {
+        let num_steps = values.len();
+        let mut peak: Option<(usize, f32)> = None;
+        for (step, &energy) in values.iter().enumerate() {
+            let power = energy / timestep_hours(num_steps, step)?;
+            if power > 0.0 && peak.is_none_or(|(_, max)| power > max) {
+                peak = Some((step, power));
+            }
+        }
+        let (step, power) = peak?;
+        Some(Self {
+            power: Kw(power),
+            energy: Kwh(values[step]),
+            step,
+            position: timestep_label(num_steps, step)?,
+        })
+    }
+}
+
+/// Potencias pico implícitas de demanda y consumo
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PeakPowers {
+    /// Demanda del edificio, por servicio
+    pub needs: BTreeMap<Service, PeakPower>,
+    /// Consumo en usos EPB, por vector
+    pub used: BTreeMap<Carrier, PeakPower>,
+    /// Consumo en usos EPB, por vector y servicio
+    pub used_by_srv: BTreeMap<Carrier, BTreeMap<Service, PeakPower>>,
+    /// Energía suministrada por la red, por vector
+    pub del_grid: BTreeMap<Carrier, PeakPower>,
+}
+
+impl PeakPowers {
+    /// Calcula las potencias pico a partir de los componentes y del balance por vectores
+    ///
+    /// Devuelve None si el número de pasos de cálculo no permite conocer su duración.
+    pub fn compute(
+        components: &Components,
+        balance_cr: &BTreeMap<Carrier, BalanceCarrier>,
+    ) -> Option<Self> {
+        timestep_hours(components.num_steps(), 0)?;
+
+        let mut peaks = Self::default();
+        for service in [Service::ACS, Service::CAL, Service::REF] {
+            if let Some(peak) = components
+                .needs
+                .get(service)
+                .and_then(|v| PeakPower::from_values(v))
+            {
+                peaks.needs.insert(service, peak);
+            }
+        }
+        for (carrier, bal_cr) in balance_cr {
+            if let Some(peak) = PeakPower::from_values(&bal_cr.used.epus_t) {
+                peaks.used.insert(*carrier, peak);
+            }
+            let by_srv: BTreeMap<Service, PeakPower> = bal_cr
+                .used
+                .epus_by_srv_t
+                .iter()
+                .filter_map(|(service, values)| {
+                    PeakPower::from_values(values).map(|peak| (*service, peak))
+                })
+                .collect();
+            if !by_srv.is_empty() {
+                peaks.used_by_srv.insert(*carrier, by_srv);
+            }
+            if let Some(peak) = PeakPower::from_values(&bal_cr.del.grid_t) {
+                peaks.del_grid.insert(*carrier, peak);
+            }
+        }
+        Some(peaks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn peak_power_from_values() {
+        // Enero (744 h) tiene más energía pero febrero (672 h) más potencia media
+        let mut values = vec![0.0; 12];
+        values[0] = 744.0;
+        values[1] = 700.0;
+        let peak = PeakPower::from_values(&values).unwrap();
+        assert_eq!(1, peak.step);
+        assert_eq!("mes 2", peak.position);
+        assert_eq!(Kwh(700.0), peak.energy);
+        assert!((peak.power.0 - 700.0 / 672.0).abs() < 1e-6);
+
+        let mut hourly = vec![1.0; 8760];
+        hourly[24 + 8] = 5.0;
+        let peak = PeakPower::from_values(&hourly).unwrap();
+        assert_eq!(Kw(5.0), peak.power);
+        assert_eq!("02/01 08h", peak.position);
+
+        assert_eq!(None, PeakPower::from_values(&[0.0; 12]));
+        assert_eq!(None, PeakPower::from_values(&[1.0, 2.0]));
+    }
+}
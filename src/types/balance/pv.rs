@@ -0,0 +1,106 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+/*!
+Rendimiento de la instalación fotovoltaica
+==========================================
+
+Indicadores de rendimiento de las instalaciones fotovoltaicas (producción EL_INSITU), a partir de su
+potencia pico declarada (SISTEMA, id, POTENCIA_PICO, valor o metadato `CTE_PV_KWP`).
+
+La energía exportada de cada origen se reparte entre la red y los usos no EPB en proporción a la
+exportación total del vector.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    types::{Carrier, ProdSource},
+    Components,
+};
+
+use super::BalanceCarrier;
+
+/// Indicadores de rendimiento de la instalación fotovoltaica
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PvPerformance {
+    /// Potencia pico declarada, kWp
+    pub peak_power: f32,
+    /// Producción anual, kWh
+    pub prod_an: f32,
+    /// Producción específica anual, kWh/kWp
+    pub specific_yield: f32,
+    /// Energía producida consumida en el edificio (usos EPB y no EPB), kWh
+    pub self_an: f32,
+    /// Fracción de la producción consumida en el edificio (usos EPB y no EPB), -
+    pub self_fraction: f32,
+    /// Energía producida exportada a la red, kWh
+    pub exp_grid_an: f32,
+    /// Fracción de la producción exportada a la red, -
+    pub exp_grid_fraction: f32,
+    /// Energía producida exportada a la red por unidad de potencia pico, kWh/kWp
+    pub exp_grid_per_kwp: f32,
+}
+
+impl PvPerformance {
+    /// Calcula los indicadores de la instalación fotovoltaica
+    ///
+    /// Devuelve None si no se ha declarado la potencia pico o no hay producción EL_INSITU
+    pub fn compute(
+        components: &Components,
+        balance_cr: &BTreeMap<Carrier, BalanceCarrier>,
+    ) -> Option<Self> {
+        let peak_power = components.pv_peak_power()?;
+        let bal_el = balance_cr.get(&Carrier::ELECTRICIDAD)?;
+        let prod_an = *bal_el.prod.by_src_an.get(&ProdSource::EL_INSITU)?;
+        if prod_an <= 0.0 {
+            return None;
+        }
+        let exp_an = bal_el
+            .exp
+            .by_src_an
+            .get(&ProdSource::EL_INSITU)
+            .copied()
+            .unwrap_or_default();
+        let grid_share = if bal_el.exp.an > 0.0 {
+            bal_el.exp.grid_an / bal_el.exp.an
+        } else {
+            0.0
+        };
+        let exp_grid_an = exp_an * grid_share;
+        let self_an = prod_an - exp_grid_an;
+        Some(Self {
+            peak_power,
+            prod_an,
+            specific_yield: prod_an / peak_power,
+            self_an,
+            self_fraction: self_an / prod_an,
+            exp_grid_an,
+            exp_grid_fraction: exp_grid_an / prod_an,
+            exp_grid_per_kwp: exp_grid_an / peak_power,
+        })
+    }
+}
@@ -0,0 +1,143 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+/*!
+Balance por temporadas de servicio
+==================================
+
+Consumo y energía ponderada de cada servicio restringidos a su temporada (meses activos), declarada
+con los metadatos `CTE_TEMPORADA_<SERVICIO>` de los componentes.
+
+Permite detectar consumos fuera de temporada (p.e. de calefacción en verano), que suelen indicar
+errores en los datos de entrada. La energía ponderada de cada vector se reparte entre la temporada
+y el resto del año en proporción a su consumo en cada periodo.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{monthly_values, Carrier, RenNrenCo2, Service},
+    Components,
+};
+
+use super::BalanceCarrier;
+
+/// Balance de un servicio restringido a su temporada
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServiceSeason {
+    /// Meses de la temporada (1 a 12)
+    pub months: Vec<u32>,
+    /// Consumo EPB mensual del servicio (todos los vectores), kWh
+    pub used_by_month: Vec<f32>,
+    /// Consumo EPB del servicio en temporada, kWh
+    pub used_in: f32,
+    /// Consumo EPB del servicio fuera de temporada, kWh
+    pub used_off: f32,
+    /// Energía ponderada del servicio en temporada (paso B), kWh
+    pub we_b_in: RenNrenCo2,
+    /// Energía ponderada del servicio fuera de temporada (paso B), kWh
+    pub we_b_off: RenNrenCo2,
+}
+
+impl ServiceSeason {
+    /// Calcula el balance por temporadas de los servicios con temporada declarada
+    ///
+    /// # Errors
+    ///
+    /// * Temporadas mal definidas (ver `Components::seasons`)
+    /// * Pasos de cálculo que no se pueden agregar por meses (se necesitan 12, 365 u 8760 pasos)
+    pub fn compute(
+        components: &Components,
+        balance_cr: &BTreeMap<Carrier, BalanceCarrier>,
+    ) -> Result<BTreeMap<Service, Self>> {
+        let seasons = components.seasons()?;
+        if seasons.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+        let num_steps = components.num_steps();
+        if monthly_values(&vec![0.0; num_steps]).is_none() {
+            return Err(EpbdError::WrongInput(format!(
+                "No se pueden agregar por meses los {} pasos de cálculo de los componentes para obtener el balance por temporadas",
+                num_steps
+            )));
+        }
+
+        let mut by_srv = BTreeMap::new();
+        for (service, months) in seasons {
+            let in_season = |month: usize| months.contains(&(month as u32 + 1));
+            let mut season = ServiceSeason {
+                used_by_month: vec![0.0; 12],
+                ..Default::default()
+            };
+            for bal_cr in balance_cr.values() {
+                let Some(used_monthly) = bal_cr
+                    .used
+                    .epus_by_srv_t
+                    .get(&service)
+                    .and_then(|used_t| monthly_values(used_t))
+                else {
+                    continue;
+                };
+                let (mut used_in, mut used_off) = (0.0, 0.0);
+                for (month, used) in used_monthly.iter().enumerate() {
+                    season.used_by_month[month] += used;
+                    if in_season(month) {
+                        used_in += used;
+                    } else {
+                        used_off += used;
+                    }
+                }
+                season.used_in += used_in;
+                season.used_off += used_off;
+                let used_an = used_in + used_off;
+                if used_an.abs() > f32::EPSILON {
+                    let we_b = bal_cr
+                        .we
+                        .b_by_srv
+                        .get(&service)
+                        .copied()
+                        .unwrap_or_default();
+                    season.we_b_in += (used_in / used_an) * we_b;
+                    season.we_b_off += (used_off / used_an) * we_b;
+                }
+            }
+            season.months = months;
+            by_srv.insert(service, season);
+        }
+        Ok(by_srv)
+    }
+
+    /// Fracción del consumo EPB anual del servicio que se produce fuera de temporada, -
+    pub fn off_fraction(&self) -> f32 {
+        let used_an = self.used_in + self.used_off;
+        if used_an.abs() > f32::EPSILON {
+            self.used_off / used_an
+        } else {
+            0.0
+        }
+    }
+}
@@ -29,7 +29,7 @@ Balance para un vector energético
 
 */
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use serde::{Deserialize, Serialize};
 
@@ -66,15 +66,21 @@ pub struct UsedEnergy {
     /// Energy used for EPB services at each timestep
     pub epus_t: Vec<f32>,
     /// Energy used for EPB services at each timestep, by service
-    pub epus_by_srv_t: HashMap<Service, Vec<f32>>,
+    pub epus_by_srv_t: BTreeMap<Service, Vec<f32>>,
     /// Energy used for EPB services at each timestep
     pub epus_an: f32,
     /// Energy used for EPB services, by service
-    pub epus_by_srv_an: HashMap<Service, f32>,
+    pub epus_by_srv_an: BTreeMap<Service, f32>,
     /// Used energy for non EPB services at each timestep
     pub nepus_t: Vec<f32>,
     /// Energy used for non EPB services
     pub nepus_an: f32,
+    /// Used energy for non EPB services at each timestep, by service
+    #[serde(default)]
+    pub nepus_by_srv_t: BTreeMap<Service, Vec<f32>>,
+    /// Energy used for non EPB services, by service
+    #[serde(default)]
+    pub nepus_by_srv_an: BTreeMap<Service, f32>,
     /// Energy input allocated to electricity cogeneration at each timestep
     pub cgnus_t: Vec<f32>,
     /// Energy input allocated to electricity cogeneration
@@ -89,21 +95,31 @@ pub struct ProducedEnergy {
     /// Produced energy (from all sources)
     pub an: f32,
     /// Produced energy at each timestep by source
-    pub by_src_t: HashMap<ProdSource, Vec<f32>>,
+    pub by_src_t: BTreeMap<ProdSource, Vec<f32>>,
     /// Produced energy by source
-    pub by_src_an: HashMap<ProdSource, f32>,
+    pub by_src_an: BTreeMap<ProdSource, f32>,
+    /// Produced energy released from storage systems at each timestep (included in t)
+    #[serde(default)]
+    pub sto_t: Vec<f32>,
+    /// Produced energy released from storage systems (included in an)
+    #[serde(default)]
+    pub sto_an: f32,
     /// Produced energy from all sources and used for EPB services at each timestep
     pub epus_t: Vec<f32>,
     /// Produced energy from all sources and used for EPB services
     pub epus_an: f32,
     /// Produced energy used for EPB services at each timestep by source
-    pub epus_by_src_t: HashMap<ProdSource, Vec<f32>>,
+    pub epus_by_src_t: BTreeMap<ProdSource, Vec<f32>>,
     /// Produced energy used for EPB services by source
-    pub epus_by_src_an: HashMap<ProdSource, f32>,
+    pub epus_by_src_an: BTreeMap<ProdSource, f32>,
     /// Produced energy used for EPB services at each timestep by service, by source
-    pub epus_by_srv_by_src_t: HashMap<ProdSource, HashMap<Service, Vec<f32>>>,
+    pub epus_by_srv_by_src_t: BTreeMap<ProdSource, BTreeMap<Service, Vec<f32>>>,
     /// Produced energy used for EPB services by service, by source
-    pub epus_by_srv_by_src_an: HashMap<ProdSource, HashMap<Service, f32>>,
+    pub epus_by_srv_by_src_an: BTreeMap<ProdSource, BTreeMap<Service, f32>>,
+    /// Produced energy used for EPB services by consumer system (id), when there are priority lists
+    /// for shared productions (`CTE_PRIORIDAD_PRODUCCION_<id>`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub epus_by_sys_an: BTreeMap<i32, f32>,
 }
 
 /// Exported Energy Data and Results
@@ -117,14 +133,23 @@ pub struct ExportedEnergy {
     pub grid_t: Vec<f32>,
     /// Exported energy to the grid
     pub grid_an: f32,
+    /// Exported energy to the grid released from storage systems (deferred export) at each timestep
+    #[serde(default)]
+    pub grid_sto_t: Vec<f32>,
+    /// Exported energy to the grid released from storage systems (deferred export)
+    #[serde(default)]
+    pub grid_sto_an: f32,
     /// Exported energy to non EPB services at each timestep
     pub nepus_t: Vec<f32>,
     /// Exported energy to non EPB services
     pub nepus_an: f32,
+    /// Exported energy to non EPB services, by service
+    #[serde(default)]
+    pub nepus_by_srv_an: BTreeMap<Service, f32>,
     /// Exported energy to the grid and non EPB services at each timestep, by source
-    pub by_src_t: HashMap<ProdSource, Vec<f32>>,
+    pub by_src_t: BTreeMap<ProdSource, Vec<f32>>,
     /// Exported energy to the grid and non EPB services, by source
-    pub by_src_an: HashMap<ProdSource, f32>,
+    pub by_src_an: BTreeMap<ProdSource, f32>,
 }
 
 /// Delivered Energy Data and Results
@@ -152,11 +177,11 @@ pub struct WeightedEnergy {
     /// Weighted energy for calculation step B
     pub b: RenNrenCo2,
     /// Weighted energy for calculation step B, by service (for EPB services)
-    pub b_by_srv: HashMap<Service, RenNrenCo2>,
+    pub b_by_srv: BTreeMap<Service, RenNrenCo2>,
     /// Weighted energy for calculation step A
     pub a: RenNrenCo2,
     /// Weighted energy for calculation step A, by service (for EPB services)
-    pub a_by_srv: HashMap<Service, RenNrenCo2>,
+    pub a_by_srv: BTreeMap<Service, RenNrenCo2>,
     /// Weighted delivered energy by the grid and any energy production sources
     pub del: RenNrenCo2,
     /// Weighted delivered energy by the grid
@@ -180,3 +205,100 @@ pub struct WeightedEnergy {
     /// Weighted exported energy and calculation step AB
     pub exp_ab: RenNrenCo2,
 }
+
+impl BalanceCarrier {
+    /// Normalize values using area
+    ///
+    /// El factor de coincidencia de cargas (`f_match`), adimensional, no se modifica.
+    pub fn normalize_by_area(&self, area: f32) -> BalanceCarrier {
+        let k_area = if area == 0.0 { 0.0 } else { 1.0 / area };
+        let mut bal = self.clone();
+
+        let used = &mut bal.used;
+        scale_values(&mut used.epus_t, k_area);
+        scale_values_map(&mut used.epus_by_srv_t, k_area);
+        used.epus_an *= k_area;
+        scale_map(&mut used.epus_by_srv_an, k_area);
+        scale_values(&mut used.nepus_t, k_area);
+        used.nepus_an *= k_area;
+        scale_values_map(&mut used.nepus_by_srv_t, k_area);
+        scale_map(&mut used.nepus_by_srv_an, k_area);
+        scale_values(&mut used.cgnus_t, k_area);
+        used.cgnus_an *= k_area;
+
+        let prod = &mut bal.prod;
+        scale_values(&mut prod.t, k_area);
+        prod.an *= k_area;
+        scale_values_map(&mut prod.by_src_t, k_area);
+        scale_map(&mut prod.by_src_an, k_area);
+        scale_values(&mut prod.sto_t, k_area);
+        prod.sto_an *= k_area;
+        scale_values(&mut prod.epus_t, k_area);
+        prod.epus_an *= k_area;
+        scale_values_map(&mut prod.epus_by_src_t, k_area);
+        scale_map(&mut prod.epus_by_src_an, k_area);
+        prod.epus_by_srv_by_src_t
+            .values_mut()
+            .for_each(|v| scale_values_map(v, k_area));
+        prod.epus_by_srv_by_src_an
+            .values_mut()
+            .for_each(|v| scale_map(v, k_area));
+        scale_map(&mut prod.epus_by_sys_an, k_area);
+
+        let exp = &mut bal.exp;
+        scale_values(&mut exp.t, k_area);
+        exp.an *= k_area;
+        scale_values(&mut exp.grid_t, k_area);
+        exp.grid_an *= k_area;
+        scale_values(&mut exp.grid_sto_t, k_area);
+        exp.grid_sto_an *= k_area;
+        scale_values(&mut exp.nepus_t, k_area);
+        exp.nepus_an *= k_area;
+        scale_map(&mut exp.nepus_by_srv_an, k_area);
+        scale_values_map(&mut exp.by_src_t, k_area);
+        scale_map(&mut exp.by_src_an, k_area);
+
+        let del = &mut bal.del;
+        del.an *= k_area;
+        scale_values(&mut del.grid_t, k_area);
+        del.grid_an *= k_area;
+        scale_values(&mut del.onst_t, k_area);
+        del.onst_an *= k_area;
+        scale_values(&mut del.cgn_t, k_area);
+        del.cgn_an *= k_area;
+
+        let we = &mut bal.we;
+        we.b *= k_area;
+        we.b_by_srv.values_mut().for_each(|v| *v *= k_area);
+        we.a *= k_area;
+        we.a_by_srv.values_mut().for_each(|v| *v *= k_area);
+        we.del *= k_area;
+        we.del_grid *= k_area;
+        we.del_onst *= k_area;
+        we.del_cgn *= k_area;
+        we.exp *= k_area;
+        we.exp_a *= k_area;
+        we.exp_nepus_a *= k_area;
+        we.exp_grid_a *= k_area;
+        we.exp_nepus_ab *= k_area;
+        we.exp_grid_ab *= k_area;
+        we.exp_ab *= k_area;
+
+        bal
+    }
+}
+
+/// Escala los valores de una serie
+fn scale_values(values: &mut [f32], k: f32) {
+    values.iter_mut().for_each(|v| *v *= k);
+}
+
+/// Escala los valores de un diccionario de valores anuales
+fn scale_map<K>(map: &mut BTreeMap<K, f32>, k: f32) {
+    map.values_mut().for_each(|v| *v *= k);
+}
+
+/// Escala los valores de un diccionario de series
+fn scale_values_map<K>(map: &mut BTreeMap<K, Vec<f32>>, k: f32) {
+    map.values_mut().for_each(|v| scale_values(v, k));
+}
@@ -0,0 +1,94 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Resumen de indicadores
+======================
+
+Indicadores finales de la eficiencia energética, sin los componentes, los factores de paso ni
+las series de valores de los resultados, para integraciones ligeras (p.e. salida JSON reducida).
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{EnergyPerformance, RenNrenCo2, Service};
+
+/// Resumen de los indicadores de eficiencia energética
+///
+/// Los valores se expresan por m2 de área de referencia y se redondean a tres decimales.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SummaryReport {
+    /// Reference area used for energy performance ratios. m2
+    pub arearef: f32,
+    /// Exported energy factor [0, 1]
+    pub k_exp: f32,
+    /// Weighted energy (step B): renewable and non renewable primary energy and CO2 emissions. kWh/m2.an, kg_CO2e/m2.an
+    pub ep: RenNrenCo2,
+    /// Total primary energy (step B). kWh/m2.an
+    pub ep_tot: f32,
+    /// Renewable Energy Ratio considering the distant perimeter
+    pub rer: f32,
+    /// Renewable Energy Ratio considering onsite and nearby perimeter
+    pub rer_nrb: f32,
+    /// Renewable fraction of the domestic hot water needs in the nearby perimeter (CTE)
+    ///
+    /// Only available when computed (see `cte::incorpora_demanda_renovable_acs_nrb`)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dhw_ren_fraction_nrb: Option<f32>,
+    /// Weighted energy (step B), by EPB service. kWh/m2.an, kg_CO2e/m2.an
+    pub ep_by_srv: BTreeMap<Service, RenNrenCo2>,
+}
+
+impl SummaryReport {
+    /// Resumen de los indicadores de la eficiencia energética indicada
+    pub fn new(ep: &EnergyPerformance) -> Self {
+        let round3 = |v: f32| (v * 1000.0).round() / 1000.0;
+        let we = &ep.balance_m2.we;
+        let dhw_ren_fraction_nrb = ep
+            .misc
+            .as_ref()
+            .and_then(|misc| misc.get("fraccion_renovable_demanda_acs_nrb"))
+            .and_then(|v| v.parse::<f32>().ok());
+        Self {
+            arearef: round3(ep.arearef),
+            k_exp: round3(ep.k_exp),
+            ep: we.b,
+            ep_tot: round3(we.b.tot()),
+            rer: round3(ep.rer),
+            rer_nrb: round3(ep.rer_nrb),
+            dhw_ren_fraction_nrb,
+            ep_by_srv: we.b_by_srv.clone(),
+        }
+    }
+}
+
+impl From<&EnergyPerformance> for SummaryReport {
+    fn from(ep: &EnergyPerformance) -> Self {
+        Self::new(ep)
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Reparto entre unidades
+======================
+
+Reparto de la energía común entre las unidades (viviendas o locales) de edificios de uso residencial
+colectivo, para comunidades de propietarios.
+
+Cada unidad recibe sus consumos EPB privativos (etiqueta `CTEEPBD_UNIDAD`) y una parte de los consumos
+EPB comunes (`CTEEPBD_AMBITO=COMUN`) y de la producción eléctrica in situ común, según su coeficiente de
+reparto (metadatos `CTE_REPARTO_<UNIDAD>`) o, si no se declaran coeficientes, en proporción a su consumo
+EPB privativo.
+
+La energía ponderada de la electricidad de cada unidad es la de su consumo imputado suministrado
+por la red menos su parte del ahorro debido a la producción in situ, de modo que esta se reparte según
+los coeficientes. La del resto de vectores se reparte en proporción al consumo imputado de cada vector.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{Carrier, Dest, HasValues, RenNrenCo2, Scope, Source, Step},
+    Components, Factors,
+};
+
+use super::BalanceCarrier;
+
+/// Desviación admitida de la suma de los coeficientes de reparto respecto a la unidad
+const SUM_TOLERANCE: f32 = 0.01;
+
+/// Energía imputada a una unidad (vivienda o local) y sus indicadores
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UnitShare {
+    /// Coeficiente de reparto de la energía común
+    pub coef: f32,
+    /// Energía usada en servicios EPB privativos de la unidad, kWh
+    pub used_own: f32,
+    /// Energía usada en servicios EPB comunes imputada a la unidad, kWh
+    pub used_common: f32,
+    /// Energía usada en servicios EPB imputada a la unidad (privativa y común), por vector, kWh
+    pub used_by_cr: BTreeMap<Carrier, f32>,
+    /// Electricidad producida in situ común imputada a la unidad, kWh
+    pub prod_el: f32,
+    /// Energía ponderada imputada a la unidad (paso B), kWh
+    pub we_b: RenNrenCo2,
+}
+
+impl UnitShare {
+    /// Reparte la energía común entre las unidades declaradas en los consumos EPB privativos
+    ///
+    /// Devuelve un mapa vacío cuando ningún consumo declara su unidad.
+    ///
+    /// # Errors
+    ///
+    /// * Consumos EPB privativos sin unidad en edificios con unidades
+    /// * Coeficientes de reparto incorrectos, de unidades sin consumos, que no cubren todas las
+    ///   unidades o que no suman la unidad
+    /// * Falta el factor de paso de la electricidad suministrada por la red
+    pub fn compute(
+        components: &Components,
+        balance_cr: &BTreeMap<Carrier, BalanceCarrier>,
+        wfactors: &Factors,
+    ) -> Result<BTreeMap<String, Self>> {
+        let has_units = components
+            .data
+            .iter()
+            .any(|c| c.is_epb_use() && c.tags().has("CTEEPBD_UNIDAD"));
+        if !has_units {
+            return Ok(BTreeMap::new());
+        }
+
+        // Consumos EPB comunes y privativos de cada unidad, por vector
+        let mut common_by_cr: BTreeMap<Carrier, f32> = BTreeMap::new();
+        let mut own_by_cr: BTreeMap<String, BTreeMap<Carrier, f32>> = BTreeMap::new();
+        for c in components.data.iter().filter(|c| c.is_epb_use()) {
+            let value = c.values_sum();
+            if c.scope()? == Scope::COMUN {
+                *common_by_cr.entry(c.carrier()).or_default() += value;
+                continue;
+            }
+            let Some(unit) = c.tags().get_str("CTEEPBD_UNIDAD").map(str::to_string) else {
+                return Err(EpbdError::WrongInput(format!(
+                    "Consumo EPB privativo sin unidad (CTEEPBD_UNIDAD) en un edificio con unidades: {}",
+                    c
+                )));
+            };
+            *own_by_cr
+                .entry(unit)
+                .or_default()
+                .entry(c.carrier())
+                .or_default() += value;
+        }
+        let own: BTreeMap<&String, f32> = own_by_cr
+            .iter()
+            .map(|(unit, by_cr)| (unit, by_cr.values().sum()))
+            .collect();
+
+        // Coeficientes de reparto declarados o, en su defecto, proporcionales al consumo privativo
+        let declared = components.sharing_coefficients()?;
+        let coefs: BTreeMap<&String, f32> = if declared.is_empty() {
+            let total: f32 = own.values().sum();
+            own.iter()
+                .map(|(unit, used)| {
+                    let coef = if total > 0.0 {
+                        used / total
+                    } else {
+                        1.0 / own.len() as f32
+                    };
+                    (*unit, coef)
+                })
+                .collect()
+        } else {
+            if let Some(unit) = declared.keys().find(|unit| !own_by_cr.contains_key(*unit)) {
+                return Err(EpbdError::WrongInput(format!(
+                    "Coeficiente de reparto de una unidad sin consumos privativos: {}",
+                    unit
+                )));
+            }
+            let missing = own_by_cr
+                .keys()
+                .filter(|unit| !declared.contains_key(*unit))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                return Err(EpbdError::WrongInput(format!(
+                    "Unidades sin coeficiente de reparto (CTE_REPARTO_<UNIDAD>): {}",
+                    missing.join(", ")
+                )));
+            }
+            let sum: f32 = declared.values().sum();
+            if (sum - 1.0).abs() > SUM_TOLERANCE {
+                return Err(EpbdError::WrongInput(format!(
+                    "Los coeficientes de reparto suman {:.3} y deben sumar 1.0",
+                    sum
+                )));
+            }
+            own_by_cr
+                .keys()
+                .map(|unit| (unit, declared[unit]))
+                .collect()
+        };
+
+        // Ahorro de la electricidad producida in situ respecto al suministro de red del consumo EPB
+        let (fp_el_grid, prod_el, saving_el) = match balance_cr.get(&Carrier::ELECTRICIDAD) {
+            Some(bal_el) => {
+                let fp_el_grid = wfactors.find(
+                    Carrier::ELECTRICIDAD,
+                    Source::RED,
+                    Dest::SUMINISTRO,
+                    Step::A,
+                )?;
+                let saving_el = bal_el.used.epus_an * fp_el_grid - bal_el.we.b;
+                (fp_el_grid, bal_el.prod.an, saving_el)
+            }
+            None => Default::default(),
+        };
+
+        let mut shares = BTreeMap::new();
+        for (unit, by_cr) in &own_by_cr {
+            let coef = coefs[unit];
+            let mut used_by_cr = by_cr.clone();
+            for (carrier, used) in &common_by_cr {
+                *used_by_cr.entry(*carrier).or_default() += coef * used;
+            }
+            let mut we_b = RenNrenCo2::default();
+            for (carrier, used) in &used_by_cr {
+                if *carrier == Carrier::ELECTRICIDAD {
+                    we_b += *used * fp_el_grid - coef * saving_el;
+                } else if let Some(bal_cr) = balance_cr.get(carrier) {
+                    if bal_cr.used.epus_an.abs() > f32::EPSILON {
+                        we_b += (used / bal_cr.used.epus_an) * bal_cr.we.b;
+                    }
+                }
+            }
+            shares.insert(
+                unit.clone(),
+                Self {
+                    coef,
+                    used_own: own[unit],
+                    used_common: coef * common_by_cr.values().sum::<f32>(),
+                    used_by_cr,
+                    prod_el: coef * prod_el,
+                    we_b,
+                },
+            );
+        }
+        Ok(shares)
+    }
+
+    /// Normalize values using area
+    ///
+    /// El coeficiente de reparto, adimensional, no se modifica.
+    pub fn normalize_by_area(&self, area: f32) -> Self {
+        let k_area = if area == 0.0 { 0.0 } else { 1.0 / area };
+        let mut share = self.clone();
+        share.used_own *= k_area;
+        share.used_common *= k_area;
+        share.used_by_cr.values_mut().for_each(|v| *v *= k_area);
+        share.prod_el *= k_area;
+        share.we_b *= k_area;
+        share
+    }
+}
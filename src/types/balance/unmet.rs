@@ -0,0 +1,149 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Necesidades no cubiertas
+========================
+
+Comparación, para cada servicio y paso de cálculo, de la energía entregada por los sistemas (SALIDA)
+con la demanda declarada del edificio (DEMANDA), como indicador de la calidad del dimensionado de los
+sistemas.
+
+La demanda no cubierta en cada paso es la parte de la demanda que supera a la energía entregada por
+todos los sistemas del servicio. Se usan los valores absolutos de demandas y energía entregada, de modo
+que el criterio de signos de la refrigeración no afecta al resultado.
+
+Solo se calcula para los servicios con demanda declarada y energía entregada (SALIDA), declarada o
+calculada a partir del rendimiento de los sistemas.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    types::{timestep_hours, HasValues, Resolution, Service, Tolerances},
+    vecops::vecsum,
+    Components,
+};
+
+/// Necesidades no cubiertas de un servicio
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct UnmetNeeds {
+    /// Demanda anual del servicio, kWh
+    pub needs_an: f32,
+    /// Energía entregada anual por los sistemas del servicio (SALIDA), kWh
+    pub out_an: f32,
+    /// Demanda no cubierta en cada paso de cálculo, kWh
+    pub unmet_t: Vec<f32>,
+    /// Demanda no cubierta anual, kWh
+    pub unmet_an: f32,
+    /// Número de pasos de cálculo con demanda no cubierta
+    pub unmet_steps: usize,
+    /// Horas de los pasos de cálculo con demanda no cubierta, h
+    ///
+    /// Solo para resoluciones mensual, diaria u horaria. En pasos mensuales y diarios se cuentan
+    /// todas las horas del paso, por lo que es una cota superior de las horas no cubiertas.
+    pub unmet_hours: Option<f32>,
+}
+
+impl UnmetNeeds {
+    /// Calcula las necesidades no cubiertas de los servicios con demanda y energía entregada
+    ///
+    /// Los pasos con demanda no cubierta son los que superan la tolerancia `tolerances.residual_use_min`.
+    pub fn compute(components: &Components, tolerances: &Tolerances) -> BTreeMap<Service, Self> {
+        let num_steps = components.num_steps();
+        let mut by_srv = BTreeMap::new();
+        for service in [Service::ACS, Service::CAL, Service::REF] {
+            let Some(needs_t) = components.needs.get(service) else {
+                continue;
+            };
+            let outs: Vec<_> = components
+                .data
+                .iter()
+                .filter(|c| c.is_out() && c.has_service(service))
+                .collect();
+            if outs.is_empty() {
+                continue;
+            }
+            let mut out_t = vec![0.0_f32; num_steps];
+            for c in outs {
+                for (o, v) in out_t.iter_mut().zip(c.values()) {
+                    *o += v;
+                }
+            }
+            let unmet_t: Vec<f32> = needs_t
+                .iter()
+                .zip(&out_t)
+                .map(|(nd, out)| (nd.abs() - out.abs()).max(0.0))
+                .collect();
+            let unmet_steps: Vec<usize> = unmet_t
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| **v > tolerances.residual_use_min)
+                .map(|(step, _)| step)
+                .collect();
+            let unmet_hours = unmet_steps
+                .iter()
+                .map(|step| timestep_hours(num_steps, *step))
+                .sum::<Option<f32>>();
+            by_srv.insert(
+                service,
+                UnmetNeeds {
+                    needs_an: needs_t.iter().map(|v| v.abs()).sum(),
+                    out_an: out_t.iter().map(|v| v.abs()).sum(),
+                    unmet_an: vecsum(&unmet_t),
+                    unmet_t,
+                    unmet_steps: unmet_steps.len(),
+                    unmet_hours,
+                },
+            );
+        }
+        by_srv
+    }
+
+    /// Fracción de la demanda anual del servicio no cubierta por los sistemas, -
+    pub fn fraction(&self) -> f32 {
+        if self.needs_an > f32::EPSILON {
+            self.unmet_an / self.needs_an
+        } else {
+            0.0
+        }
+    }
+
+    /// ¿Superan las necesidades no cubiertas los umbrales admisibles de dimensionado?
+    ///
+    /// Se comparan la fracción de demanda no cubierta con `tolerances.unmet_needs_max` y, solo con
+    /// datos horarios, las horas no cubiertas con `tolerances.unmet_hours_max`.
+    pub fn exceeds(&self, tolerances: &Tolerances) -> bool {
+        let is_hourly = self.unmet_t.len() == Resolution::HORARIA.num_steps();
+        self.fraction() > tolerances.unmet_needs_max
+            || (is_hourly
+                && self
+                    .unmet_hours
+                    .map(|h| h > tolerances.unmet_hours_max)
+                    .unwrap_or(false))
+    }
+}
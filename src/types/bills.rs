@@ -0,0 +1,309 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Facturas de suministro y conciliación
+=====================================
+
+Energía facturada por vector energético y resultados de su conciliación con la energía
+suministrada por la red obtenida en el balance energético.
+
+La conciliación es opcional y compara, mes a mes, la energía suministrada por la red calculada
+para cada vector con las lecturas de facturación, lo que resulta útil en auditorías con datos medidos.
+*/
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{Carrier, Meta, MetaVec, UserCarrierScope},
+};
+
+// ==================== Billed energy
+
+/// Energía facturada de un vector energético
+///
+/// Monthly billed energy for an energy carrier (kWh)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BilledEnergy {
+    /// Energy carrier
+    pub carrier: Carrier,
+    /// Monthly billed energy values (12 values). kWh
+    pub values: Vec<f32>,
+    /// Descriptive comment string for the billed energy
+    pub comment: String,
+}
+
+impl fmt::Display for BilledEnergy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        let values = self
+            .values
+            .iter()
+            .map(|v| format!("{:.2}", v))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "{}, {}{}", self.carrier, values, comment)
+    }
+}
+
+impl str::FromStr for BilledEnergy {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<BilledEnergy> {
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+        let carrier: Carrier = items[0].parse()?;
+        let values = items[1..]
+            .iter()
+            .map(|v| v.parse::<f32>())
+            .collect::<std::result::Result<Vec<f32>, _>>()?;
+        if values.len() != 12 {
+            return Err(EpbdError::WrongInput(format!(
+                "Se esperaban 12 valores mensuales de energía facturada de {} y se encontraron {}",
+                carrier,
+                values.len()
+            )));
+        };
+        if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+            return Err(EpbdError::WrongInput(format!(
+                "Valores de energía facturada no válidos para {}",
+                carrier
+            )));
+        };
+        Ok(BilledEnergy {
+            carrier,
+            values,
+            comment,
+        })
+    }
+}
+
+/// Lista de energía facturada por vector energético
+///
+/// Se leen de una tabla con líneas `VECTOR, ene, feb, ..., dic # Comentario` (kWh)
+/// y metadatos `#META CLAVE: valor`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Bills {
+    /// Billing metadata
+    pub meta: Vec<Meta>,
+    /// Billed energy data
+    pub data: Vec<BilledEnergy>,
+}
+
+impl Bills {
+    /// Energía facturada de un vector energético
+    pub fn get(&self, carrier: Carrier) -> Option<&BilledEnergy> {
+        self.data.iter().find(|b| b.carrier == carrier)
+    }
+}
+
+impl MetaVec for Bills {
+    fn get_metavec(&self) -> &Vec<Meta> {
+        &self.meta
+    }
+    fn get_mut_metavec(&mut self) -> &mut Vec<Meta> {
+        &mut self.meta
+    }
+}
+
+impl fmt::Display for Bills {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metalines = self
+            .meta
+            .iter()
+            .map(|v| format!("{}", v))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let datalines = self
+            .data
+            .iter()
+            .map(|v| format!("{}", v))
+            .collect::<Vec<_>>()
+            .join("\n");
+        write!(f, "{}\n{}", metalines, datalines)
+    }
+}
+
+impl str::FromStr for Bills {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<Bills> {
+        let meta = s
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, l)| l.starts_with("#META"))
+            .map(|(i, l)| l.parse::<Meta>().map_err(|e| e.at_line(i + 1)))
+            .collect::<Result<Vec<_>>>()?;
+        let _user_carriers = UserCarrierScope::from_meta(&meta)?;
+        let mut data: Vec<BilledEnergy> = Vec::new();
+        for (i, line) in s.lines().map(str::trim).enumerate() {
+            if !(line.starts_with('#') || line.starts_with("vector,") || line.is_empty()) {
+                let billed = line.parse::<BilledEnergy>().map_err(|e| e.at_line(i + 1))?;
+                if data.iter().any(|b| b.carrier == billed.carrier) {
+                    return Err(EpbdError::WrongInput(format!(
+                        "Energía facturada duplicada para {}",
+                        billed.carrier
+                    ))
+                    .at_line(i + 1));
+                }
+                data.push(billed);
+            }
+        }
+        Ok(Bills { meta, data })
+    }
+}
+
+// ==================== Bills reconciliation
+
+/// Conciliación de la energía suministrada por la red calculada con la facturada, para un vector
+///
+/// Valores mensuales (12 valores) y anuales, en kWh. Las desviaciones, en %, se calculan como
+/// 100 · (calculada - facturada) / facturada y no se definen si la energía facturada es nula.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CarrierReconciliation {
+    /// Computed delivered energy from the grid, by month
+    pub computed: Vec<f32>,
+    /// Billed energy, by month
+    pub billed: Vec<f32>,
+    /// Deviation of computed from billed energy, by month. %
+    pub dev_pct: Vec<Option<f32>>,
+    /// Computed delivered energy from the grid
+    pub computed_an: f32,
+    /// Billed energy
+    pub billed_an: f32,
+    /// Annual deviation of computed from billed energy. %
+    pub dev_an_pct: Option<f32>,
+}
+
+impl CarrierReconciliation {
+    /// Conciliación de valores mensuales calculados y facturados
+    pub fn new(computed: Vec<f32>, billed: Vec<f32>) -> Self {
+        let dev_pct = computed
+            .iter()
+            .zip(billed.iter())
+            .map(|(c, b)| deviation_pct(*c, *b))
+            .collect();
+        let computed_an = computed.iter().sum();
+        let billed_an = billed.iter().sum();
+        Self {
+            computed,
+            billed,
+            dev_pct,
+            computed_an,
+            billed_an,
+            dev_an_pct: deviation_pct(computed_an, billed_an),
+        }
+    }
+
+    /// Máxima desviación mensual, en valor absoluto (%)
+    pub fn max_dev_pct(&self) -> Option<f32> {
+        self.dev_pct
+            .iter()
+            .flatten()
+            .map(|v| v.abs())
+            .reduce(f32::max)
+    }
+}
+
+/// Desviación porcentual de un valor calculado respecto al facturado
+fn deviation_pct(computed: f32, billed: f32) -> Option<f32> {
+    if billed > 0.0 {
+        Some(100.0 * (computed - billed) / billed)
+    } else {
+        None
+    }
+}
+
+/// Resultados de la conciliación con las facturas de suministro
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct BillsReconciliation {
+    /// Bills used in the reconciliation
+    pub bills: Bills,
+    /// Reconciliation results, by carrier
+    pub by_cr: BTreeMap<Carrier, CarrierReconciliation>,
+    /// Carriers delivered by the grid without billed energy
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unbilled: Vec<Carrier>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn bills_parse() {
+        let bills: Bills = "#META FUENTE: Facturas 2023
+ELECTRICIDAD, 100, 90, 80, 70, 60, 50, 60, 70, 80, 90, 100, 110 # Lecturas de la comercializadora
+GASNATURAL, 200, 180, 120, 60, 0, 0, 0, 0, 0, 60, 120, 180"
+            .parse()
+            .unwrap();
+        assert_eq!(2, bills.data.len());
+        assert_eq!(Some("Facturas 2023".to_string()), bills.get_meta("FUENTE"));
+        assert_eq!(
+            960.0,
+            bills
+                .get(Carrier::ELECTRICIDAD)
+                .unwrap()
+                .values
+                .iter()
+                .sum::<f32>()
+        );
+        assert_eq!(bills.to_string().parse::<Bills>().unwrap().data, bills.data);
+
+        assert!("ELECTRICIDAD, 100, 90".parse::<Bills>().is_err());
+        assert!("ELECTRICIDAD, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1\nELECTRICIDAD, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1"
+            .parse::<Bills>()
+            .is_err());
+    }
+
+    #[test]
+    fn carrier_reconciliation() {
+        let mut computed = vec![100.0; 12];
+        computed[0] = 110.0;
+        let mut billed = vec![100.0; 12];
+        billed[11] = 0.0;
+        let rec = CarrierReconciliation::new(computed, billed);
+        assert_eq!(Some(10.0), rec.dev_pct[0]);
+        assert_eq!(Some(0.0), rec.dev_pct[1]);
+        assert_eq!(None, rec.dev_pct[11]);
+        assert_eq!(Some(10.0), rec.max_dev_pct());
+        assert_eq!(1210.0, rec.computed_an);
+        assert_eq!(1100.0, rec.billed_an);
+        assert!((rec.dev_an_pct.unwrap() - 10.0).abs() < 1e-4);
+    }
+}
@@ -25,21 +25,29 @@
 
 //! Vectores energéticos
 
+use std::cell::RefCell;
 use std::fmt;
 use std::str;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::ProdSource;
 
-use crate::error::EpbdError;
+use crate::error::{unknown_value_error, EpbdError};
 
 /// Vector energético (energy carrier).
 #[allow(non_camel_case_types)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
 pub enum Carrier {
     /// Environment thermal energy (from heat pumps and other)
     EAMBIENTE,
+    /// Environment thermal energy captured from the outdoor air (aerothermal)
+    EAMBIENTE_AIRE,
+    /// Environment thermal energy captured from the ground (geothermal)
+    EAMBIENTE_GEO,
+    /// Environment thermal energy captured from water bodies (hydrothermal)
+    EAMBIENTE_AGUA,
     /// Biofuel
     BIOCARBURANTE,
     /// Biomass
@@ -56,39 +64,257 @@ pub enum Carrier {
     GASOLEO,
     /// LPG - Liquefied petroleum gas
     GLP,
+    /// Hydrogen (boilers and fuel cells)
+    HIDROGENO,
     /// Generic energy carrier 1
     RED1,
     /// Generic energy carrier 2
     RED2,
+    /// Waste heat recovered from processes or data centers, supplied through a nearby network
+    RED_CALOR_RESIDUAL,
+    /// Useful heat from onsite cogeneration
+    TERMICA_COGEN,
     /// Thermal energy from solar collectors
     TERMOSOLAR,
+    /// Thermal energy from nearby third-party solar collectors (not onsite)
+    TERMOSOLAR_PROXIMO,
+    /// User defined energy carrier (district networks RED3, RED4... or other names declared by the user)
+    USUARIO(UserCarrier),
 }
 
+/// Longitud máxima del nombre de un vector energético definido por el usuario
+pub const USER_CARRIER_MAX_LEN: usize = 32;
+
+/// Vector energético definido por el usuario
+///
+/// Son vectores de usuario las redes de distrito adicionales (`RED3`, `RED4`...) y los nombres
+/// declarados en el metadato `CTE_VECTORES_USUARIO` de los datos en los que se usan
+/// (p.e. `CTE_VECTORES_USUARIO: BIOGAS_LOCAL, ASTILLA_COMARCAL`). Cada archivo (componentes,
+/// factores de paso, facturas...) debe declarar los vectores de usuario que emplea, de modo que
+/// la interpretación de unos datos no depende de los interpretados anteriormente.
+///
+/// El nombre se guarda en el propio valor (hasta `USER_CARRIER_MAX_LEN` caracteres), sin registro global.
+///
+/// Las redes de distrito de usuario, como RED1 y RED2, se consideran dentro del perímetro próximo
+/// y el resto de vectores de usuario son distantes. Ninguno tiene factores de paso por defecto.
+/// Los vectores de usuario se ordenan por nombre.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UserCarrier([u8; USER_CARRIER_MAX_LEN]);
+
+impl UserCarrier {
+    /// Vector de usuario con ese nombre
+    ///
+    /// # Errors
+    ///
+    /// * Nombre vacío, con caracteres distintos de mayúsculas, dígitos o guion bajo o que no empieza por una letra
+    /// * Nombre de más de `USER_CARRIER_MAX_LEN` caracteres
+    /// * Nombre de un vector energético predefinido
+    pub fn new(name: &str) -> Result<Self, EpbdError> {
+        let name = name.trim();
+        let valid = name.len() <= USER_CARRIER_MAX_LEN
+            && name.starts_with(|c: char| c.is_ascii_uppercase())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+        if !valid || BUILTIN_NAMES.contains(&name) {
+            return Err(EpbdError::WrongInput(format!(
+                "Nombre incorrecto de vector energético de usuario: \"{}\"",
+                name
+            )));
+        }
+        let mut bytes = [0; USER_CARRIER_MAX_LEN];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(Self(bytes))
+    }
+
+    /// Nombre del vector
+    pub fn name(&self) -> &str {
+        let len = self.0.iter().position(|&b| b == 0).unwrap_or(self.0.len());
+        str::from_utf8(&self.0[..len]).unwrap_or_default()
+    }
+
+    /// Is this a user defined district network (RED3, RED4...)?
+    pub fn is_network(&self) -> bool {
+        is_network_name(self.name())
+    }
+
+    /// Vectores de usuario declarados en el metadato `CTE_VECTORES_USUARIO`
+    ///
+    /// # Errors
+    ///
+    /// * Nombres incorrectos de vectores de usuario
+    pub fn from_meta(meta: &[super::Meta]) -> Result<Vec<Self>, EpbdError> {
+        meta.iter()
+            .filter(|m| m.key == "CTE_VECTORES_USUARIO")
+            .flat_map(|m| m.value.split(','))
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+            .map(Self::new)
+            .collect()
+    }
+}
+
+impl fmt::Debug for UserCarrier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+thread_local! {
+    /// Vectores de usuario declarados en los datos que se están interpretando
+    static DECLARED_USER_CARRIERS: RefCell<Vec<UserCarrier>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Ámbito de interpretación de unos datos con los vectores de usuario declarados en ellos
+///
+/// Mientras existe, `Carrier::from_str` admite los vectores declarados, además de los predefinidos
+/// y de las redes de distrito de usuario. Al eliminarse, se recuperan los vectores del ámbito anterior.
+pub(crate) struct UserCarrierScope(Vec<UserCarrier>);
+
+impl UserCarrierScope {
+    /// Abre un ámbito de interpretación con los vectores de usuario declarados
+    pub(crate) fn new(declared: Vec<UserCarrier>) -> Self {
+        Self(DECLARED_USER_CARRIERS.with(|d| d.replace(declared)))
+    }
+
+    /// Abre un ámbito de interpretación con los vectores declarados en el metadato `CTE_VECTORES_USUARIO`
+    ///
+    /// # Errors
+    ///
+    /// * Nombres incorrectos de vectores de usuario
+    pub(crate) fn from_meta(meta: &[super::Meta]) -> Result<Self, EpbdError> {
+        UserCarrier::from_meta(meta).map(Self::new)
+    }
+
+    /// Vector de usuario declarado en el ámbito actual con ese nombre, si existe
+    fn find(name: &str) -> Option<UserCarrier> {
+        DECLARED_USER_CARRIERS.with(|d| d.borrow().iter().find(|c| c.name() == name).copied())
+    }
+}
+
+impl Drop for UserCarrierScope {
+    fn drop(&mut self) {
+        let previous = std::mem::take(&mut self.0);
+        DECLARED_USER_CARRIERS.with(|d| d.replace(previous));
+    }
+}
+
+/// Is this the name of a user defined district network (RED3, RED4...)?
+fn is_network_name(name: &str) -> bool {
+    name.strip_prefix("RED")
+        .and_then(|n| n.parse::<u32>().ok())
+        .is_some_and(|n| n > 2)
+}
+
+/// Nombres de los vectores energéticos predefinidos
+const BUILTIN_NAMES: [&str; 19] = [
+    "EAMBIENTE",
+    "EAMBIENTE_AIRE",
+    "EAMBIENTE_GEO",
+    "EAMBIENTE_AGUA",
+    "BIOCARBURANTE",
+    "BIOMASA",
+    "BIOMASADENSIFICADA",
+    "CARBON",
+    "ELECTRICIDAD",
+    "GASNATURAL",
+    "GASOLEO",
+    "GLP",
+    "HIDROGENO",
+    "RED1",
+    "RED2",
+    "RED_CALOR_RESIDUAL",
+    "TERMICA_COGEN",
+    "TERMOSOLAR",
+    "TERMOSOLAR_PROXIMO",
+];
+
 /// TODO: La clasificación de los vectores en función del perímetro debería hacerse
 /// TODO: en la propia definición de esos vectores
 impl Carrier {
     /// Vectores considerados dentro del perímetro NEARBY (a excepción de la ELECTRICIDAD in situ).
-    pub const NRBY: [Carrier; 6] = [
+    pub const NRBY: [Carrier; 11] = [
         Carrier::BIOMASA,
         Carrier::BIOMASADENSIFICADA,
         Carrier::RED1,
         Carrier::RED2,
+        Carrier::RED_CALOR_RESIDUAL,
         Carrier::EAMBIENTE,
+        Carrier::EAMBIENTE_AIRE,
+        Carrier::EAMBIENTE_GEO,
+        Carrier::EAMBIENTE_AGUA,
         Carrier::TERMOSOLAR,
+        Carrier::TERMOSOLAR_PROXIMO,
     ]; // Ver B.23. Solo biomasa sólida
 
+    /// Vectores que pueden incluirse opcionalmente en el perímetro NEARBY según su procedencia.
+    ///
+    /// El BIOCARBURANTE se considera, por defecto, distante (B.23 solo incluye la biomasa sólida),
+    /// pero puede incluirse en el perímetro próximo cuando se produce en las proximidades del edificio
+    /// (ver metadato `CTE_BIOCARBURANTE_PROXIMO` de los componentes).
+    pub const NRBY_OPT: [Carrier; 1] = [Carrier::BIOCARBURANTE];
+
     /// Vectores considerados dentro del perímetro ONSITE (a excepción de la ELECTRICIDAD in situ).
-    pub const ONST: [Carrier; 2] = [Carrier::EAMBIENTE, Carrier::TERMOSOLAR];
+    ///
+    /// El calor solar comprado a instalaciones próximas de terceros (TERMOSOLAR_PROXIMO) es próximo pero no in situ.
+    pub const ONST: [Carrier; 5] = [
+        Carrier::EAMBIENTE,
+        Carrier::EAMBIENTE_AIRE,
+        Carrier::EAMBIENTE_GEO,
+        Carrier::EAMBIENTE_AGUA,
+        Carrier::TERMOSOLAR,
+    ];
+
+    /// Vectores de energía ambiente: genérico y desglosados por tipo de fuente (aerotermia, geotermia e hidrotermia).
+    ///
+    /// Los subtipos tienen, por defecto, los mismos factores de paso que EAMBIENTE, pero su balance se calcula por separado.
+    pub const AMBIENT: [Carrier; 4] = [
+        Carrier::EAMBIENTE,
+        Carrier::EAMBIENTE_AIRE,
+        Carrier::EAMBIENTE_GEO,
+        Carrier::EAMBIENTE_AGUA,
+    ];
 
     /// Is this a carrier from the onsite or nearby perimeter?
+    ///
+    /// User defined district networks (RED3, RED4...) are nearby carriers, as RED1 and RED2
     pub fn is_nearby(&self) -> bool {
-        Carrier::NRBY.contains(self)
+        match self {
+            Carrier::USUARIO(user) => user.is_network(),
+            _ => Carrier::NRBY.contains(self),
+        }
+    }
+
+    /// Is this a district network carrier (RED1, RED2 or a user defined network such as RED3)?
+    pub fn is_district_network(&self) -> bool {
+        match self {
+            Carrier::RED1 | Carrier::RED2 => true,
+            Carrier::USUARIO(user) => user.is_network(),
+            _ => false,
+        }
+    }
+
+    /// Is this a user defined carrier?
+    pub fn is_user(&self) -> bool {
+        matches!(self, Carrier::USUARIO(_))
+    }
+
+    /// Is this a carrier from the onsite or nearby perimeter, including the optional nearby carriers in `nearby_opt`?
+    ///
+    /// Only carriers in `Carrier::NRBY_OPT` are taken into account from `nearby_opt`.
+    pub fn is_nearby_with(&self, nearby_opt: &[Carrier]) -> bool {
+        self.is_nearby() || (Carrier::NRBY_OPT.contains(self) && nearby_opt.contains(self))
     }
 
     /// Is this a carrier from the onsite perimeter?
     pub fn is_onsite(&self) -> bool {
         Carrier::ONST.contains(self)
     }
+
+    /// Is this an environment thermal energy carrier (EAMBIENTE or any of its subtypes)?
+    pub fn is_ambient(&self) -> bool {
+        Carrier::AMBIENT.contains(self)
+    }
 }
 
 impl str::FromStr for Carrier {
@@ -97,6 +323,9 @@ impl str::FromStr for Carrier {
     fn from_str(s: &str) -> Result<Carrier, Self::Err> {
         match s {
             "EAMBIENTE" => Ok(Carrier::EAMBIENTE),
+            "EAMBIENTE_AIRE" => Ok(Carrier::EAMBIENTE_AIRE),
+            "EAMBIENTE_GEO" => Ok(Carrier::EAMBIENTE_GEO),
+            "EAMBIENTE_AGUA" => Ok(Carrier::EAMBIENTE_AGUA),
             "BIOCARBURANTE" => Ok(Carrier::BIOCARBURANTE),
             "BIOMASA" => Ok(Carrier::BIOMASA),
             "BIOMASADENSIFICADA" => Ok(Carrier::BIOMASADENSIFICADA),
@@ -105,17 +334,51 @@ impl str::FromStr for Carrier {
             "GASNATURAL" => Ok(Carrier::GASNATURAL),
             "GASOLEO" => Ok(Carrier::GASOLEO),
             "GLP" => Ok(Carrier::GLP),
+            "HIDROGENO" => Ok(Carrier::HIDROGENO),
             "RED1" => Ok(Carrier::RED1),
             "RED2" => Ok(Carrier::RED2),
+            "RED_CALOR_RESIDUAL" => Ok(Carrier::RED_CALOR_RESIDUAL),
+            "TERMICA_COGEN" => Ok(Carrier::TERMICA_COGEN),
             "TERMOSOLAR" => Ok(Carrier::TERMOSOLAR),
-            _ => Err(EpbdError::ParseError(s.into())),
+            "TERMOSOLAR_PROXIMO" => Ok(Carrier::TERMOSOLAR_PROXIMO),
+            _ => {
+                if is_network_name(s) {
+                    UserCarrier::new(s).map(Carrier::USUARIO)
+                } else if let Some(user) = UserCarrierScope::find(s) {
+                    Ok(Carrier::USUARIO(user))
+                } else {
+                    Err(unknown_value_error("vector energético", s, &BUILTIN_NAMES))
+                }
+            }
         }
     }
 }
 
 impl std::fmt::Display for Carrier {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Carrier::USUARIO(user) => write!(f, "{}", user.name()),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl Serialize for Carrier {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Al deserializar se admite cualquier nombre válido de vector de usuario, ya que los datos
+/// serializados proceden de datos ya interpretados (y sus vectores de usuario, declarados).
+impl<'de> Deserialize<'de> for Carrier {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().or_else(|e| {
+            UserCarrier::new(&name)
+                .map(Carrier::USUARIO)
+                .map_err(|_| serde::de::Error::custom(e))
+        })
     }
 }
 
@@ -123,9 +386,14 @@ impl std::convert::From<ProdSource> for Carrier {
     fn from(value: ProdSource) -> Self {
         match value {
             ProdSource::EL_INSITU => Carrier::ELECTRICIDAD,
+            ProdSource::EL_EOLICA => Carrier::ELECTRICIDAD,
             ProdSource::EL_COGEN => Carrier::ELECTRICIDAD,
             ProdSource::TERMOSOLAR => Carrier::TERMOSOLAR,
+            ProdSource::TERMICA_COGEN => Carrier::TERMICA_COGEN,
             ProdSource::EAMBIENTE => Carrier::EAMBIENTE,
+            ProdSource::EAMBIENTE_AIRE => Carrier::EAMBIENTE_AIRE,
+            ProdSource::EAMBIENTE_GEO => Carrier::EAMBIENTE_GEO,
+            ProdSource::EAMBIENTE_AGUA => Carrier::EAMBIENTE_AGUA,
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,290 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Juego alternativo de factores de emisión e inventario de emisiones
+==================================================================
+
+Factores de emisión de CO2 alternativos a los de los factores de paso reglamentarios (p.e.
+factores de inventario según el GHG Protocol, con enfoque basado en la ubicación o en el mercado)
+y resultados de las emisiones calculadas con ellos.
+
+El cálculo es opcional y solo modifica las emisiones: se pondera la misma energía suministrada y
+exportada del balance con los factores de emisión alternativos, sin alterar la energía primaria.
+Los factores de paso sin factor de emisión alternativo conservan el valor oficial.
+*/
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{Carrier, Dest, Meta, MetaVec, Service, Source, Step, UserCarrierScope},
+};
+
+/// Etiqueta por defecto de las emisiones calculadas con los factores de paso reglamentarios
+pub const CO2_OFFICIAL_LABEL: &str = "oficial";
+
+/// Etiqueta por defecto de las emisiones calculadas con el juego alternativo de factores
+pub const CO2_INVENTORY_LABEL: &str = "inventario";
+
+// ==================== Inventory emission factors
+
+/// Factor de emisión alternativo de un vector energético, origen, destino y paso de cálculo
+///
+/// Emission factor for a weighting factor (kg_CO2e/kWh)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Co2Factor {
+    /// Energy carrier
+    pub carrier: Carrier,
+    /// Carrier source (`RED`, `INSITU` or `COGEN`)
+    pub source: Source,
+    /// Destination use of the energy (`SUMINISTRO`, `A_RED`, `A_NEPB`)
+    pub dest: Dest,
+    /// Evaluation step
+    pub step: Step,
+    /// CO2 emissions for each end use unit of this carrier. kg_CO2e/kWh
+    pub co2: f32,
+    /// Descriptive comment string for the emission factor
+    pub comment: String,
+}
+
+impl fmt::Display for Co2Factor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        write!(
+            f,
+            "{}, {}, {}, {}, {:.3}{}",
+            self.carrier, self.source, self.dest, self.step, self.co2, comment
+        )
+    }
+}
+
+impl str::FromStr for Co2Factor {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<Co2Factor> {
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+        if items.len() != 5 {
+            return Err(EpbdError::ParseError(s.into()));
+        };
+        let carrier: Carrier = items[0].parse()?;
+        let source: Source = items[1].parse()?;
+        let dest: Dest = items[2].parse()?;
+        let step: Step = items[3].parse()?;
+        let co2: f32 = items[4].parse()?;
+        if !co2.is_finite() {
+            return Err(EpbdError::WrongInput(format!(
+                "Factor de emisión no válido para {}, {}, {}, {}: {}",
+                carrier, source, dest, step, co2
+            )));
+        };
+        Ok(Co2Factor {
+            carrier,
+            source,
+            dest,
+            step,
+            co2,
+            comment,
+        })
+    }
+}
+
+/// Juego alternativo de factores de emisión
+///
+/// Se leen de una tabla con líneas `VECTOR, ORIGEN, DESTINO, PASO, co2 # Comentario`
+/// (kg_CO2e/kWh) y metadatos `#META CLAVE: valor`. La etiqueta de los resultados se toma del
+/// metadato `CTE_ETIQUETA` (por defecto, "inventario").
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InventoryCo2Factors {
+    /// Emission factors metadata
+    pub meta: Vec<Meta>,
+    /// Emission factors data
+    pub data: Vec<Co2Factor>,
+}
+
+impl InventoryCo2Factors {
+    /// Factor de emisión alternativo de un vector energético, origen, destino y paso de cálculo
+    pub fn get(&self, carrier: Carrier, source: Source, dest: Dest, step: Step) -> Option<f32> {
+        self.data
+            .iter()
+            .find(|f| {
+                f.carrier == carrier && f.source == source && f.dest == dest && f.step == step
+            })
+            .map(|f| f.co2)
+    }
+
+    /// Etiqueta de las emisiones calculadas con este juego de factores
+    pub fn label(&self) -> String {
+        self.get_meta("CTE_ETIQUETA")
+            .unwrap_or_else(|| CO2_INVENTORY_LABEL.to_string())
+    }
+}
+
+impl MetaVec for InventoryCo2Factors {
+    fn get_metavec(&self) -> &Vec<Meta> {
+        &self.meta
+    }
+    fn get_mut_metavec(&mut self) -> &mut Vec<Meta> {
+        &mut self.meta
+    }
+}
+
+impl fmt::Display for InventoryCo2Factors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metalines = self
+            .meta
+            .iter()
+            .map(|v| format!("{}", v))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let datalines = self
+            .data
+            .iter()
+            .map(|v| format!("{}", v))
+            .collect::<Vec<_>>()
+            .join("\n");
+        write!(f, "{}\n{}", metalines, datalines)
+    }
+}
+
+impl str::FromStr for InventoryCo2Factors {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<InventoryCo2Factors> {
+        let meta = s
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, l)| l.starts_with("#META"))
+            .map(|(i, l)| l.parse::<Meta>().map_err(|e| e.at_line(i + 1)))
+            .collect::<Result<Vec<_>>>()?;
+        let _user_carriers = UserCarrierScope::from_meta(&meta)?;
+        let mut data: Vec<Co2Factor> = Vec::new();
+        for (i, line) in s.lines().map(str::trim).enumerate() {
+            if !(line.starts_with('#') || line.starts_with("vector,") || line.is_empty()) {
+                let factor = line.parse::<Co2Factor>().map_err(|e| e.at_line(i + 1))?;
+                if data.iter().any(|f| {
+                    f.carrier == factor.carrier
+                        && f.source == factor.source
+                        && f.dest == factor.dest
+                        && f.step == factor.step
+                }) {
+                    return Err(EpbdError::WrongInput(format!(
+                        "Factor de emisión duplicado para {}, {}, {}, {}",
+                        factor.carrier, factor.source, factor.dest, factor.step
+                    ))
+                    .at_line(i + 1));
+                }
+                data.push(factor);
+            }
+        }
+        Ok(InventoryCo2Factors { meta, data })
+    }
+}
+
+// ==================== Emissions inventory
+
+/// Emisiones calculadas con el juego oficial y con el juego alternativo de factores de emisión
+///
+/// Emisiones en kg_CO2e/an (salvo indicación contraria). La energía primaria no cambia.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Co2Inventory {
+    /// Emission factors used in the computation
+    pub factors: InventoryCo2Factors,
+    /// Label of the emissions computed with the official weighting factors
+    pub official_label: String,
+    /// Label of the emissions computed with the alternative emission factors
+    pub label: String,
+    /// Emissions with the official weighting factors, step B
+    pub official: f32,
+    /// Emissions with the official weighting factors, step B, by reference area. kg_CO2e/m2.an
+    pub official_m2: f32,
+    /// Emissions with the alternative emission factors, step A
+    pub a: f32,
+    /// Emissions with the alternative emission factors, step B
+    pub b: f32,
+    /// Emissions with the alternative emission factors, step B, by reference area. kg_CO2e/m2.an
+    pub b_m2: f32,
+    /// Emissions with the alternative emission factors, step B, by carrier
+    pub b_by_cr: BTreeMap<Carrier, f32>,
+    /// Emissions with the alternative emission factors, step B, by EPB service
+    pub b_by_srv: BTreeMap<Service, f32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn tinventoryco2factors() {
+        let factors1 = "#META CTE_ETIQUETA: GHG market-based
+ELECTRICIDAD, RED, SUMINISTRO, A, 0.000 # Garantía de origen renovable
+GASNATURAL, RED, SUMINISTRO, A, 0.202";
+
+        // roundtrip building from/to string
+        let factors = factors1.parse::<InventoryCo2Factors>().unwrap();
+        assert_eq!(factors.to_string(), factors1);
+        assert_eq!(factors.label(), "GHG market-based");
+        assert_eq!(
+            Some(0.202),
+            factors.get(Carrier::GASNATURAL, Source::RED, Dest::SUMINISTRO, Step::A)
+        );
+        assert_eq!(
+            None,
+            factors.get(Carrier::ELECTRICIDAD, Source::RED, Dest::A_RED, Step::A)
+        );
+        assert_eq!(
+            "GASNATURAL, RED, SUMINISTRO, A, 0.202"
+                .parse::<InventoryCo2Factors>()
+                .unwrap()
+                .label(),
+            CO2_INVENTORY_LABEL
+        );
+
+        // bad lines and duplicates
+        assert!("ELECTRICIDAD, RED, SUMINISTRO, A"
+            .parse::<Co2Factor>()
+            .is_err());
+        assert!("ELECTRICIDAD, RED, SUMINISTRO, A, xx"
+            .parse::<Co2Factor>()
+            .is_err());
+        assert!(
+            "ELECTRICIDAD, RED, SUMINISTRO, A, 0.1\nELECTRICIDAD, RED, SUMINISTRO, A, 0.2"
+                .parse::<InventoryCo2Factors>()
+                .is_err()
+        );
+    }
+}
@@ -28,11 +28,12 @@ use std::str;
 
 use serde::{Deserialize, Serialize};
 
-use crate::error::EpbdError;
+use crate::error::{unknown_value_error, EpbdError};
 
 /// Tipos de componentes (energía final y demanda)
 #[allow(non_camel_case_types)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum CType {
     /// Consumo de energía final
     CONSUMO,
@@ -44,6 +45,12 @@ pub enum CType {
     SALIDA,
     /// Demanda energética
     DEMANDA,
+    /// Energía embebida (informativa)
+    EMBEBIDA,
+    /// Propiedades de un sistema (p.e. rendimiento estacional)
+    SISTEMA,
+    /// Almacenamiento eléctrico (batería)
+    ALMACENAMIENTO,
 }
 
 impl str::FromStr for CType {
@@ -56,7 +63,23 @@ impl str::FromStr for CType {
             "AUX" => Ok(CType::AUX),
             "SALIDA" => Ok(CType::SALIDA),
             "DEMANDA" => Ok(CType::DEMANDA),
-            _ => Err(EpbdError::ParseError(s.into())),
+            "EMBEBIDA" => Ok(CType::EMBEBIDA),
+            "SISTEMA" => Ok(CType::SISTEMA),
+            "ALMACENAMIENTO" => Ok(CType::ALMACENAMIENTO),
+            _ => Err(unknown_value_error(
+                "tipo de componente",
+                s,
+                &[
+                    "CONSUMO",
+                    "PRODUCCION",
+                    "AUX",
+                    "SALIDA",
+                    "DEMANDA",
+                    "EMBEBIDA",
+                    "SISTEMA",
+                    "ALMACENAMIENTO",
+                ],
+            )),
         }
     }
 }
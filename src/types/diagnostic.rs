@@ -0,0 +1,102 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Diagnósticos de validación
+==========================
+
+Avisos y errores detectados al validar los datos de entrada (ver `Components::validate`),
+con la línea de los datos en la que se localizan, cuando es posible.
+*/
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Gravedad de un diagnóstico de validación o de un aviso del cálculo
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Severity {
+    /// Nota informativa. Los datos se han completado o transformado automáticamente
+    Info,
+    /// Aviso. Los datos pueden calcularse, pero probablemente contienen algún error
+    Warning,
+    /// Error. Los datos no pueden calcularse
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Info => write!(f, "NOTA"),
+            Severity::Warning => write!(f, "AVISO"),
+            Severity::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+/// Diagnóstico de validación de los datos de entrada
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    /// Gravedad del diagnóstico
+    pub severity: Severity,
+    /// Línea de los datos de entrada (comenzando en 1), si se puede localizar
+    pub line: Option<usize>,
+    /// Descripción del problema detectado
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Diagnóstico de error en la línea indicada
+    pub fn error<T: Into<String>>(line: Option<usize>, message: T) -> Self {
+        Self {
+            severity: Severity::Error,
+            line,
+            message: message.into(),
+        }
+    }
+
+    /// Diagnóstico de aviso en la línea indicada
+    pub fn warning<T: Into<String>>(line: Option<usize>, message: T) -> Self {
+        Self {
+            severity: Severity::Warning,
+            line,
+            message: message.into(),
+        }
+    }
+
+    /// ¿Es un diagnóstico de error?
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}: línea {}: {}", self.severity, line, self.message),
+            None => write!(f, "{}: {}", self.severity, self.message),
+        }
+    }
+}
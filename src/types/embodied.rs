@@ -0,0 +1,139 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Energía embebida
+================
+
+Componente informativo de energía embebida (materiales, construcción), expresada como
+valores anuales equivalentes de energía primaria y emisiones.
+
+Este componente no interviene en el balance EPB y sus resultados se informan por separado.
+*/
+
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::EpbdError;
+use crate::types::{CType, RenNrenCo2, Tags};
+
+/// Componente de energía embebida (informativo)
+///
+/// Energía primaria renovable y no renovable y emisiones anuales equivalentes de un
+/// elemento (p.e. los materiales de la envolvente), sin efecto sobre el balance EPB.
+///
+/// Se serializa como: `EMBEBIDA, ren, nren, co2 # comentario`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Embodied {
+    /// Annual equivalent embodied primary energy (ren, nren, kWh/an) and emissions (kg_CO2e/an)
+    pub value: RenNrenCo2,
+    /// Descriptive comment string
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+}
+
+impl Embodied {
+    /// Get structured tags (key=value pairs and flags) from the comment of this component
+    pub fn tags(&self) -> Tags {
+        Tags::from_comment(&self.comment)
+    }
+}
+
+impl fmt::Display for Embodied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        write!(
+            f,
+            "EMBEBIDA, {:.2}, {:.2}, {:.2}{}",
+            self.value.ren, self.value.nren, self.value.co2, comment
+        )
+    }
+}
+
+impl str::FromStr for Embodied {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<Embodied, Self::Err> {
+        // Split comment from the rest of fields
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+
+        // Check type and length (EMBEBIDA + ren + nren + co2)
+        if items.len() != 4 || !matches!(items[0].parse(), Ok(CType::EMBEBIDA)) {
+            return Err(EpbdError::ParseError(format!(
+                "No se reconoce el formato como elemento de energía embebida: {}",
+                s
+            )));
+        };
+
+        let values = items[1..]
+            .iter()
+            .map(|v| v.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|_| {
+                EpbdError::ParseError(format!("se esperaban valores numéricos en línea `{}`", s))
+            })?;
+
+        Ok(Embodied {
+            value: RenNrenCo2::new(values[0], values[1], values[2]),
+            comment,
+        })
+    }
+}
+
+// ========================== Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn component_embodied() {
+        let component1 = Embodied {
+            value: RenNrenCo2::new(1.5, 12.0, 3.25),
+            comment: "Aislamiento térmico".into(),
+        };
+        let component1str = "EMBEBIDA, 1.50, 12.00, 3.25 # Aislamiento térmico";
+        assert_eq!(component1.to_string(), component1str);
+
+        // roundtrip building from/to string
+        assert_eq!(
+            component1str.parse::<Embodied>().unwrap().to_string(),
+            component1str
+        );
+
+        // missing values
+        assert!("EMBEBIDA, 1.0, 2.0".parse::<Embodied>().is_err());
+    }
+}
@@ -39,6 +39,9 @@ use crate::types::{HasValues, Service, CType};
 /// Representa el consumo de energía (eléctrica) para usos auxiliares
 /// del servicio X en el subsistema Y, para los distintos pasos de cálculo,
 /// Subsistema: generación + almacenamiento
+///
+/// El servicio puede declararse explícitamente (`1, AUX, ACS, 5`). Si no se declara, el componente
+/// se carga con el servicio NEPB y se asigna a los servicios EPB del sistema al normalizar los componentes.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EAux {
     /// System or part id (generator i)
@@ -47,7 +50,7 @@ pub struct EAux {
     /// Negative numbers should represent fictitious systems (such as the reference ones)
     /// A value greater than 0 identifies a specific system that is using some energy
     pub id: i32,
-    /// End use
+    /// End use (NEPB until assigned, when not explicitly declared)
     pub service: Service,
     /// List of timestep energy use for the current carrier and service. kWh
     pub values: Vec<f32>,
@@ -77,11 +80,15 @@ impl fmt::Display for EAux {
             "".to_owned()
         };
 
-        write!(
-            f,
-            "{}, AUX, {}{}",
-            self.id, value_list, comment
-        )
+        if self.service == Service::NEPB {
+            write!(f, "{}, AUX, {}{}", self.id, value_list, comment)
+        } else {
+            write!(
+                f,
+                "{}, AUX, {}, {}{}",
+                self.id, self.service, value_list, comment
+            )
+        }
     }
 }
 
@@ -115,11 +122,24 @@ impl str::FromStr for EAux {
             }
         };
         
-        // Initial service is NEPB. This is changed when normalizing data
-        let service = Service::NEPB;
+        // Explicit service, if the field after the type is not a number.
+        // Otherwise, initial service is NEPB. This is changed when normalizing data
+        let (values_idx, service) = match items.get(base_idx + 1) {
+            Some(field) if field.parse::<f32>().is_err() => {
+                let service: Service = field.parse()?;
+                if service == Service::NEPB {
+                    return Err(EpbdError::ParseError(format!(
+                        "Componente de energía auxiliar con servicio no EPB: {}",
+                        s
+                    )));
+                }
+                (base_idx + 2, service)
+            }
+            _ => (base_idx + 1, Service::NEPB),
+        };
 
         // Collect energy values from the service field on
-        let values = items[base_idx + 1..]
+        let values = items[values_idx..]
             .iter()
             .map(|v| v.parse::<f32>())
             .collect::<Result<Vec<f32>, _>>()
@@ -162,5 +182,14 @@ mod tests {
             component1str.parse::<EAux>().unwrap().to_string(),
             component1str
         );
+
+        // Explicit service
+        let component2str = "1, AUX, ACS, 5.00 # Bomba de recirculación";
+        let component2 = component2str.parse::<EAux>().unwrap();
+        assert_eq!(component2.service, Service::ACS);
+        assert_eq!(component2.values, vec![5.0]);
+        assert_eq!(component2.to_string(), component2str);
+        assert!("1, AUX, NEPB, 5.0".parse::<EAux>().is_err());
+        assert!("1, AUX, XXX, 5.0".parse::<EAux>().is_err());
     }
 }
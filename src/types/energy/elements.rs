@@ -28,7 +28,8 @@ use std::{fmt, str};
 use serde::{Deserialize, Serialize};
 
 use super::{EAux, EOut, EProd, EUsed};
-use crate::types::{Carrier, HasValues, ProdSource, Service, Source};
+use crate::error::EpbdError;
+use crate::types::{Carrier, HasValues, ProdSource, Scope, Service, Source, Tags};
 
 /// Componentes de energía generada, consumida, auxiliar o saliente (entregada/absorbida)
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -122,6 +123,20 @@ impl Energy {
         }
     }
 
+    /// Get structured tags (key=value pairs and flags) from the comment of this component
+    pub fn tags(&self) -> Tags {
+        Tags::from_comment(self.comment())
+    }
+
+    /// Scope of this energy use (PRIVATIVO or COMUN), from the CTEEPBD_AMBITO tag
+    ///
+    /// Components without the tag are PRIVATIVO
+    pub fn scope(&self) -> Result<Scope, EpbdError> {
+        self.tags()
+            .get_str("CTEEPBD_AMBITO")
+            .map_or(Ok(Scope::PRIVATIVO), str::parse)
+    }
+
     /// Is this of kind UsedEnergy?
     pub fn is_used(&self) -> bool {
         match self {
@@ -142,6 +157,15 @@ impl Energy {
         }
     }
 
+    /// Is this produced energy released from a storage system?
+    ///
+    /// Produced energy components flagged with the CTEEPBD_ALMACENAMIENTO tag, for storage systems
+    /// not modelled with ALMACENAMIENTO components. The discharge of modelled storages is also counted
+    /// as energy released from storage in the balance (`ProducedEnergy::sto_t`)
+    pub fn is_from_storage(&self) -> bool {
+        self.is_generated() && self.tags().has("CTEEPBD_ALMACENAMIENTO")
+    }
+
     /// Is this energy of the auxiliary energy kind?
     pub fn is_aux(&self) -> bool {
         match self {
@@ -163,11 +187,13 @@ impl Energy {
     }
 
     /// Is this of kind UsedEnergy and destination is an EPB service (includes aux and used)?
+    ///
+    /// Optional EPB services (VE, APP) are EPB uses only when labelled with the CTEEPBD_<SERVICE>_EPB tag
     pub fn is_epb_use(&self) -> bool {
         match self {
             Energy::Prod(_) => false,
-            Energy::Used(e) => e.service.is_epb(),
-            Energy::Aux(e) => e.service.is_epb(),
+            Energy::Used(e) => e.service.is_epb() || self.is_labelled_epb_use(),
+            Energy::Aux(e) => e.service.is_epb() || self.is_labelled_epb_use(),
             Energy::Out(_) => false,
         }
     }
@@ -176,12 +202,30 @@ impl Energy {
     pub fn is_nepb_use(&self) -> bool {
         match self {
             Energy::Prod(_) => false,
-            Energy::Used(e) => e.service.is_nepb(),
-            Energy::Aux(e) => e.service.is_nepb(),
+            Energy::Used(e) => e.service.is_nepb() && !self.is_labelled_epb_use(),
+            Energy::Aux(e) => e.service.is_nepb() && !self.is_labelled_epb_use(),
             Energy::Out(_) => false,
         }
     }
 
+    /// Is this an optional EPB service use (VE, APP) labelled as EPB use (CTEEPBD_<SERVICE>_EPB tag)?
+    pub fn is_labelled_epb_use(&self) -> bool {
+        self.optional_epb_service().is_some_and(|service| {
+            self.tags()
+                .get_bool(&format!("CTEEPBD_{}_EPB", service))
+                .unwrap_or(false)
+        })
+    }
+
+    /// Service of this energy use, when it is an optional EPB service (VE, APP)
+    pub fn optional_epb_service(&self) -> Option<Service> {
+        match self {
+            Energy::Used(e) if e.service.is_optional_epb() => Some(e.service),
+            Energy::Aux(e) if e.service.is_optional_epb() => Some(e.service),
+            _ => None,
+        }
+    }
+
     /// Is this of kind UsedEnergy and destination is cogeneration (COGEN)?
     pub fn is_cogen_use(&self) -> bool {
         match self {
@@ -195,17 +239,17 @@ impl Energy {
     /// Is this energy of the onsite produced kind?
     pub fn is_onsite_pr(&self) -> bool {
         match self {
-            Energy::Prod(e) => e.source != ProdSource::EL_COGEN,
+            Energy::Prod(e) => !e.source.is_cogen(),
             Energy::Used(_) => false,
             Energy::Aux(_) => false,
             Energy::Out(_) => false,
         }
     }
 
-    /// Is this energy of the cogeneration produced kind?
+    /// Is this energy of the cogeneration produced kind (electricity or heat)?
     pub fn is_cogen_pr(&self) -> bool {
         match self {
-            Energy::Prod(e) => e.source == ProdSource::EL_COGEN,
+            Energy::Prod(e) => e.source.is_cogen(),
             Energy::Used(_) => false,
             Energy::Aux(_) => false,
             Energy::Out(_) => false,
@@ -216,6 +260,7 @@ impl Energy {
     pub fn is_electricity(&self) -> bool {
         match self {
             Energy::Aux(_) => true,
+            Energy::Out(_) => false,
             _ => self.carrier() == Carrier::ELECTRICIDAD,
         }
     }
@@ -250,6 +295,18 @@ impl std::fmt::Display for Energy {
     }
 }
 
+impl Energy {
+    /// Get mutable list of values
+    pub fn values_mut(&mut self) -> &mut [f32] {
+        match self {
+            Energy::Prod(e) => &mut e.values,
+            Energy::Used(e) => &mut e.values,
+            Energy::Aux(e) => &mut e.values,
+            Energy::Out(e) => &mut e.values,
+        }
+    }
+}
+
 impl HasValues for Energy {
     fn values(&self) -> &[f32] {
         match self {
@@ -39,6 +39,12 @@ use crate::types::{CType, HasValues, Service};
 ///
 /// Energía entregada o absorbida por los sistemas pertenecientes al subsistema de generación del edificio, E_X_gen_i_out
 ///
+/// Los valores positivos indican energía entregada (p.e. calor de una caldera) y los negativos,
+/// energía absorbida (p.e. por una enfriadora), que solo se admite para el servicio de refrigeración (REF).
+/// En los cálculos que usan la energía saliente (p.e. el reparto de los consumos auxiliares
+/// entre servicios) se emplea su valor absoluto, de modo que una bomba de calor reversible
+/// reparte sus auxiliares según la energía entregada en calefacción y la absorbida en refrigeración.
+///
 /// Se serializa como: `id, SALIDA, servicio, vals... # comentario`
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EOut {
@@ -58,6 +64,15 @@ pub struct EOut {
     pub comment: String,
 }
 
+impl EOut {
+    /// Energía entregada o absorbida en valor absoluto, para cada paso de cálculo
+    ///
+    /// Absolute value of delivered or absorbed energy, for each timestep. kWh
+    pub fn abs_values(&self) -> Vec<f32> {
+        self.values.iter().map(|v| v.abs()).collect()
+    }
+}
+
 impl HasValues for EOut {
     fn values(&self) -> &[f32] {
         &self.values
@@ -141,6 +156,14 @@ impl str::FromStr for EOut {
                 EpbdError::ParseError(format!("se esperaban valores numéricos en línea `{}`", s))
             })?;
 
+        // Check that absorbed energy (negative values) is only used for the cooling service
+        if service != Service::REF && values.iter().any(|&v| v < 0.0) {
+            return Err(EpbdError::ParseError(format!(
+                "energía absorbida (valores negativos) definida para un servicio distinto de REF `{}`",
+                s
+            )));
+        }
+
         Ok(EOut {
             id,
             service,
@@ -176,5 +199,12 @@ mod tests {
             component1str.parse::<EOut>().unwrap().to_string(),
             component1str
         );
+
+        // absolute values of absorbed energy
+        assert_eq!(component1.abs_values()[11], 12.0);
+
+        // absorbed energy only for cooling
+        assert!("0, SALIDA, CAL, -1.0, 2.0".parse::<EOut>().is_err());
+        assert!("0, SALIDA, REF, -1.0, 2.0".parse::<EOut>().is_ok());
     }
 }
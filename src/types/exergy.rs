@@ -0,0 +1,227 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Factores y balance exergético
+=============================
+
+Factores de calidad exergética por vector energético y resultados del balance exergético.
+
+El balance exergético es opcional y se obtiene a partir de las mismas series de energía
+suministrada y exportada del balance energético, aplicando a cada vector un factor de calidad
+exergética (kWh_ex/kWh_f) definido por el usuario.
+*/
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{Carrier, Meta, MetaVec, UserCarrierScope},
+};
+
+// ==================== Exergy factors
+
+/// Factor de calidad exergética de un vector energético
+///
+/// Quality factor for an energy carrier (kWh_ex/kWh_f)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExergyFactor {
+    /// Energy carrier
+    pub carrier: Carrier,
+    /// Exergy quality factor. kWh_ex/kWh_f
+    pub q: f32,
+    /// Descriptive comment string for the exergy factor
+    pub comment: String,
+}
+
+impl fmt::Display for ExergyFactor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        write!(f, "{}, {:.3}{}", self.carrier, self.q, comment)
+    }
+}
+
+impl str::FromStr for ExergyFactor {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<ExergyFactor> {
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+        if items.len() < 2 {
+            return Err(EpbdError::ParseError(s.into()));
+        };
+        let carrier: Carrier = items[0]
+            .parse()
+            .map_err(|_| EpbdError::ParseError(items[0].into()))?;
+        let q: f32 = items[1].parse()?;
+        if !q.is_finite() || q < 0.0 {
+            return Err(EpbdError::WrongInput(format!(
+                "Factor de calidad exergética no válido para {}: {}",
+                carrier, q
+            )));
+        };
+        Ok(ExergyFactor {
+            carrier,
+            q,
+            comment,
+        })
+    }
+}
+
+/// Lista de factores de calidad exergética por vector energético
+///
+/// Se leen de una tabla con líneas `VECTOR, q # Comentario` y metadatos `#META CLAVE: valor`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ExergyFactors {
+    /// Exergy factors metadata
+    pub wmeta: Vec<Meta>,
+    /// Exergy factors data
+    pub wdata: Vec<ExergyFactor>,
+}
+
+impl ExergyFactors {
+    /// Factor de calidad exergética de un vector energético
+    pub fn get(&self, carrier: Carrier) -> Option<f32> {
+        self.wdata
+            .iter()
+            .find(|f| f.carrier == carrier)
+            .map(|f| f.q)
+    }
+}
+
+impl MetaVec for ExergyFactors {
+    fn get_metavec(&self) -> &Vec<Meta> {
+        &self.wmeta
+    }
+    fn get_mut_metavec(&mut self) -> &mut Vec<Meta> {
+        &mut self.wmeta
+    }
+}
+
+impl fmt::Display for ExergyFactors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metalines = self
+            .wmeta
+            .iter()
+            .map(|v| format!("{}", v))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let datalines = self
+            .wdata
+            .iter()
+            .map(|v| format!("{}", v))
+            .collect::<Vec<_>>()
+            .join("\n");
+        write!(f, "{}\n{}", metalines, datalines)
+    }
+}
+
+impl str::FromStr for ExergyFactors {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<ExergyFactors> {
+        let lines: Vec<&str> = s.lines().map(str::trim).collect();
+        let wmeta = lines
+            .iter()
+            .filter(|l| l.starts_with("#META") || l.starts_with("#CTE_"))
+            .map(|e| e.parse())
+            .collect::<Result<Vec<Meta>>>()?;
+        let _user_carriers = UserCarrierScope::from_meta(&wmeta)?;
+        let wdata = lines
+            .iter()
+            .filter(|l| !(l.starts_with('#') || l.starts_with("vector,") || l.is_empty()))
+            .map(|e| e.parse())
+            .collect::<Result<Vec<ExergyFactor>>>()?;
+        for (i, factor) in wdata.iter().enumerate() {
+            if wdata[..i].iter().any(|f| f.carrier == factor.carrier) {
+                return Err(EpbdError::WrongInput(format!(
+                    "Factor de calidad exergética duplicado para {}",
+                    factor.carrier
+                )));
+            }
+        }
+        Ok(ExergyFactors { wmeta, wdata })
+    }
+}
+
+// ==================== Exergy balance
+
+/// Resultados del balance exergético
+///
+/// Exergía de la energía suministrada y exportada, en kWh_ex/an (salvo indicación contraria)
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ExergyBalance {
+    /// Exergy factors used in the computation
+    pub factors: ExergyFactors,
+    /// Delivered exergy (grid + onsite)
+    pub del: f32,
+    /// Delivered exergy from the grid
+    pub del_grid: f32,
+    /// Delivered exergy from onsite sources
+    pub del_onst: f32,
+    /// Exported exergy
+    pub exp: f32,
+    /// Exergy balance including the effect of exported energy (del - k_exp · exp)
+    pub b: f32,
+    /// Exergy balance including the effect of exported energy, by carrier
+    pub b_by_cr: BTreeMap<Carrier, f32>,
+    /// Exergy balance including the effect of exported energy, by reference area. kWh_ex/m2.an
+    pub b_m2: f32,
+    /// Mean exergy quality of delivered energy (del exergy / del energy). kWh_ex/kWh_f
+    pub q_del: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn texergyfactors() {
+        let xfactors1 = "#META CTE_FUENTE: Prueba
+ELECTRICIDAD, 1.000 # Electricidad
+GASNATURAL, 0.940";
+
+        // roundtrip building from/to string
+        let xfactors = xfactors1.parse::<ExergyFactors>().unwrap();
+        assert_eq!(xfactors.to_string(), xfactors1);
+        assert_eq!(Some(1.0), xfactors.get(Carrier::ELECTRICIDAD));
+        assert_eq!(None, xfactors.get(Carrier::BIOMASA));
+        assert_eq!(Some("Prueba".to_string()), xfactors.get_meta("CTE_FUENTE"));
+
+        // bad carrier or bad value
+        assert!("ELECTRICIDA, 1.0".parse::<ExergyFactor>().is_err());
+        assert!("ELECTRICIDAD, xx".parse::<ExergyFactor>().is_err());
+    }
+}
@@ -30,7 +30,10 @@ use serde::{Deserialize, Serialize};
 
 use super::{Carrier, ProdSource};
 
-use crate::{error::EpbdError, types::RenNrenCo2};
+use crate::{
+    error::{unknown_value_error, EpbdError},
+    types::{RenNrenCo2, Tags},
+};
 
 // ==================== Weighting factors
 
@@ -40,6 +43,12 @@ use crate::{error::EpbdError, types::RenNrenCo2};
 ///
 /// Representa la fracción renovable, no renovable y emisiones de una unidad de energía final,
 /// evaluados en el paso de cálculo y para un vector y una fuente determinados.
+///
+/// Opcionalmente puede incluir una serie de valores por paso de cálculo (p.e. factores horarios
+/// de la red eléctrica), que se usa en el balance ponderado del vector. Los valores anuales se
+/// usan en el resto de cálculos y cuando no hay serie.
+///
+/// Se serializa como: `VECTOR, FUENTE, USO, PASO, ren, nren, co2[, ren_1, nren_1, co2_1, ..., ren_n, nren_n, co2_n] # comentario`
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Factor {
     /// Energy carrier
@@ -56,6 +65,15 @@ pub struct Factor {
     pub nren: f32,
     /// CO2 emissions for each end use unit of this carrier
     pub co2: f32,
+    /// Weighting factors for each timestep (optional). When empty, annual values are used at every timestep
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub values_t: Vec<RenNrenCo2>,
+    /// Energy prices for each timestep, €/kWh (optional, e.g. from validity periods with different
+    /// `CTEEPBD_COSTE` prices). When empty, the annual price of the comment is used at every timestep
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cost_t: Vec<f32>,
     /// Descriptive comment string for the weighting factor
     #[serde(default)]
     #[serde(skip_serializing_if = "String::is_empty")]
@@ -80,6 +98,8 @@ impl Factor {
             ren,
             nren,
             co2,
+            values_t: Vec::new(),
+            cost_t: Vec::new(),
             comment: comment.into(),
         }
     }
@@ -93,11 +113,98 @@ impl Factor {
         }
     }
 
+    /// Precio de la energía (€/kWh), declarado con la etiqueta `CTEEPBD_COSTE` en el comentario
+    ///
+    /// P.e.: `ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331 # Electricidad CTEEPBD_COSTE=0.15`
+    pub fn cost(&self) -> Option<f32> {
+        Tags::from_comment(&self.comment).get_f32("CTEEPBD_COSTE")
+    }
+
+    /// Precio de la energía (€/kWh) en el paso de cálculo `step` (el anual si no hay serie por pasos)
+    pub fn cost_at(&self, step: usize) -> Option<f32> {
+        self.cost_t.get(step).copied().or_else(|| self.cost())
+    }
+
+    /// Coste (€) de una energía con valores por paso de cálculo `values_t`
+    ///
+    /// Se suma el producto de los valores y el precio de cada paso. Devuelve None si el factor
+    /// no tiene precio declarado.
+    pub fn cost_of(&self, values_t: &[f32]) -> Option<f32> {
+        if self.cost_t.is_empty() {
+            self.cost()
+                .map(|price| price * values_t.iter().sum::<f32>())
+        } else {
+            Some(
+                values_t
+                    .iter()
+                    .zip(&self.cost_t)
+                    .map(|(value, price)| value * price)
+                    .sum(),
+            )
+        }
+    }
+
+    /// Periodo de vigencia, declarado con la etiqueta `CTEEPBD_VIGENCIA` en el comentario
+    ///
+    /// Devuelve los pasos de cálculo inicial y final del periodo (contando desde 1, incluidos).
+    /// El periodo puede indicar un solo paso (`7`) o dejar abierto su final (`7-`, hasta el último paso).
+    ///
+    /// P.e.: `ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331 # Factores 2024 CTEEPBD_VIGENCIA=1-6`
+    ///
+    /// # Errors
+    ///
+    /// * Periodo de vigencia mal formado
+    pub fn validity(&self) -> Result<Option<(usize, usize)>, EpbdError> {
+        let tags = Tags::from_comment(&self.comment);
+        let Some(value) = tags.get_str("CTEEPBD_VIGENCIA") else {
+            return Ok(None);
+        };
+        let step = |v: &str| v.trim().parse::<usize>().ok().filter(|v| *v > 0);
+        let period = match value.split_once('-') {
+            Some((first, "")) => step(first).map(|first| (first, usize::MAX)),
+            Some((first, last)) => step(first).zip(step(last)),
+            None => step(value).map(|first| (first, first)),
+        };
+        match period {
+            Some((first, last)) if first <= last => Ok(Some((first, last))),
+            _ => Err(EpbdError::ParseError(format!(
+                "periodo de vigencia incorrecto del factor de paso '{}, {}, {}, {}': \"{}\" (se esperaba inicio-fin, p.e. 1-6)",
+                self.carrier, self.source, self.dest, self.step, value
+            ))),
+        }
+    }
+
+    /// Factores de paso en el paso de cálculo `step` (los anuales si no hay serie por pasos)
+    pub fn factors_at(&self, step: usize) -> RenNrenCo2 {
+        self.values_t
+            .get(step)
+            .copied()
+            .unwrap_or_else(|| self.factors())
+    }
+
+    /// Energía ponderada de una energía con valor anual `value_an` y valores por paso de cálculo `values_t`
+    ///
+    /// Sin serie de factores por pasos se usan los factores anuales sobre el valor anual y, en otro caso,
+    /// se suma el producto de los valores y los factores de cada paso.
+    pub fn weigh(&self, value_an: f32, values_t: &[f32]) -> RenNrenCo2 {
+        if self.values_t.is_empty() {
+            value_an * self.factors()
+        } else {
+            values_t
+                .iter()
+                .zip(&self.values_t)
+                .fold(RenNrenCo2::default(), |acc, (&v, &f)| acc + v * f)
+        }
+    }
+
     /// Copia los factores desde una estructura RenNRenCo2
+    ///
+    /// Elimina la serie de factores por paso de cálculo, si la hubiese
     pub fn set_values(&mut self, &values: &RenNrenCo2) {
         self.ren = values.ren;
         self.nren = values.nren;
         self.co2 = values.co2;
+        self.values_t.clear();
     }
 }
 
@@ -108,10 +215,23 @@ impl fmt::Display for Factor {
         } else {
             "".to_owned()
         };
+        let values_t = self
+            .values_t
+            .iter()
+            .map(|v| format!(", {:.3}, {:.3}, {:.3}", v.ren, v.nren, v.co2))
+            .collect::<String>();
         write!(
             f,
-            "{}, {}, {}, {}, {:.3}, {:.3}, {:.3}{}",
-            self.carrier, self.source, self.dest, self.step, self.ren, self.nren, self.co2, comment
+            "{}, {}, {}, {}, {:.3}, {:.3}, {:.3}{}{}",
+            self.carrier,
+            self.source,
+            self.dest,
+            self.step,
+            self.ren,
+            self.nren,
+            self.co2,
+            values_t,
+            comment
         )
     }
 }
@@ -126,21 +246,26 @@ impl str::FromStr for Factor {
         if items.len() < 7 {
             return Err(EpbdError::ParseError(s.into()));
         };
-        let carrier: Carrier = items[0]
-            .parse()
-            .map_err(|_| EpbdError::ParseError(items[0].into()))?;
-        let source: Source = items[1]
-            .parse()
-            .map_err(|_| EpbdError::ParseError(items[1].into()))?;
-        let dest: Dest = items[2]
-            .parse()
-            .map_err(|_| EpbdError::ParseError(items[2].into()))?;
-        let step: Step = items[3]
-            .parse()
-            .map_err(|_| EpbdError::ParseError(items[3].into()))?;
+        let carrier: Carrier = items[0].parse()?;
+        let source: Source = items[1].parse()?;
+        let dest: Dest = items[2].parse()?;
+        let step: Step = items[3].parse()?;
         let ren: f32 = items[4].parse()?;
         let nren: f32 = items[5].parse()?;
         let co2: f32 = items[6].parse()?;
+        // Serie opcional de factores por paso de cálculo (ren, nren, co2)
+        if !(items.len() - 7).is_multiple_of(3) {
+            return Err(EpbdError::ParseError(format!(
+                "la serie de factores por paso de cálculo debe tener tres valores (ren, nren, co2) por paso en `{}`",
+                s
+            )));
+        }
+        let values_t = items[7..]
+            .chunks(3)
+            .map(|v| -> Result<RenNrenCo2, EpbdError> {
+                Ok(RenNrenCo2::new(v[0].parse()?, v[1].parse()?, v[2].parse()?))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
         Ok(Factor {
             carrier,
             source,
@@ -149,6 +274,8 @@ impl str::FromStr for Factor {
             ren,
             nren,
             co2,
+            values_t,
+            cost_t: Vec::new(),
             comment,
         })
     }
@@ -176,7 +303,11 @@ impl str::FromStr for Source {
             "RED" => Ok(Source::RED),
             "INSITU" => Ok(Source::INSITU),
             "COGEN" => Ok(Source::COGEN),
-            _ => Err(EpbdError::ParseError(s.into())),
+            _ => Err(unknown_value_error(
+                "fuente",
+                s,
+                &["RED", "INSITU", "COGEN"],
+            )),
         }
     }
 }
@@ -190,10 +321,13 @@ impl std::fmt::Display for Source {
 impl std::convert::From<ProdSource> for Source {
     fn from(value: ProdSource) -> Self {
         match value {
-            ProdSource::EL_INSITU => Source::INSITU,
-            ProdSource::EL_COGEN => Source::COGEN,
+            ProdSource::EL_INSITU | ProdSource::EL_EOLICA => Source::INSITU,
+            ProdSource::EL_COGEN | ProdSource::TERMICA_COGEN => Source::COGEN,
             ProdSource::TERMOSOLAR => Source::INSITU,
-            ProdSource::EAMBIENTE => Source::INSITU,
+            ProdSource::EAMBIENTE
+            | ProdSource::EAMBIENTE_AIRE
+            | ProdSource::EAMBIENTE_GEO
+            | ProdSource::EAMBIENTE_AGUA => Source::INSITU,
         }
     }
 }
@@ -203,11 +337,14 @@ impl std::convert::From<ProdSource> for Source {
 /// Destino de la energía
 #[allow(non_camel_case_types)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum Dest {
     /// Building delivery destination
     SUMINISTRO,
     /// Grid destination
     A_RED,
+    /// Grid destination, for energy exported after going through a storage system
+    A_RED_DIFERIDA,
     /// Non EPB uses destination
     A_NEPB,
 }
@@ -219,8 +356,13 @@ impl str::FromStr for Dest {
         match s {
             "SUMINISTRO" => Ok(Dest::SUMINISTRO),
             "A_RED" => Ok(Dest::A_RED),
+            "A_RED_DIFERIDA" => Ok(Dest::A_RED_DIFERIDA),
             "A_NEPB" => Ok(Dest::A_NEPB),
-            _ => Err(EpbdError::ParseError(s.into())),
+            _ => Err(unknown_value_error(
+                "destino",
+                s,
+                &["SUMINISTRO", "A_RED", "A_RED_DIFERIDA", "A_NEPB"],
+            )),
         }
     }
 }
@@ -250,7 +392,7 @@ impl str::FromStr for Step {
         match s {
             "A" => Ok(Step::A),
             "B" => Ok(Step::B),
-            _ => Err(EpbdError::ParseError(s.into())),
+            _ => Err(unknown_value_error("paso de cálculo", s, &["A", "B"])),
         }
     }
 }
@@ -278,6 +420,8 @@ mod tests {
             ren: 0.414,
             nren: 1.954,
             co2: 0.331,
+            values_t: Vec::new(),
+            cost_t: Vec::new(),
             comment: "Electricidad de red paso A".into(),
         };
         let factor1str =
@@ -292,4 +436,24 @@ mod tests {
             factor1str
         );
     }
+
+    #[test]
+    fn tfactor_values_t() {
+        let factorstr = "ELECTRICIDAD, RED, SUMINISTRO, A, 0.500, 1.500, 0.300, 0.500, 2.000, 0.420, 0.500, 1.000, 0.180 # Factores mensuales";
+        let factor = factorstr.parse::<Factor>().unwrap();
+        assert_eq!(factor.values_t.len(), 2);
+        assert_eq!(factor.factors_at(1), RenNrenCo2::new(0.5, 1.0, 0.18));
+        assert_eq!(
+            factor.weigh(150.0, &[100.0, 50.0]),
+            RenNrenCo2::new(75.0, 250.0, 51.0)
+        );
+
+        // roundtrip building from/to string
+        assert_eq!(factor.to_string(), factorstr);
+
+        // Serie incompleta
+        assert!("ELECTRICIDAD, RED, SUMINISTRO, A, 0.5, 1.5, 0.3, 0.5, 2.0"
+            .parse::<Factor>()
+            .is_err());
+    }
 }
@@ -0,0 +1,108 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+//! Seguimiento del progreso y cancelación del cálculo
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use super::Carrier;
+
+/// Testigo de cancelación cooperativa del cálculo
+///
+/// Puede clonarse y compartirse entre hilos: al llamar a `cancel` desde cualquiera de sus copias,
+/// el cálculo se interrumpe en el siguiente punto de comprobación (tras procesar un vector energético).
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Nuevo testigo de cancelación (no cancelado)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Solicita la cancelación del cálculo
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Comprueba si se ha solicitado la cancelación del cálculo
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Estado del progreso del cálculo
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Progress {
+    /// Último vector energético procesado
+    pub carrier: Carrier,
+    /// Número de balances por vector calculados
+    pub done: usize,
+    /// Número total de balances por vector a calcular
+    ///
+    /// Incluye, si hay consumos no EPB, los balances del edificio completo (informativos).
+    pub total: usize,
+}
+
+/// Funciones de seguimiento del progreso y cancelación del cálculo
+///
+/// Por defecto no se hace seguimiento ni se puede cancelar el cálculo, sin coste adicional.
+#[derive(Clone, Copy, Default)]
+pub struct CalcHooks<'a> {
+    /// Función a la que se llama tras calcular el balance de cada vector energético
+    pub on_progress: Option<&'a dyn Fn(&Progress)>,
+    /// Testigo de cancelación cooperativa
+    pub cancel: Option<&'a CancelToken>,
+}
+
+impl CalcHooks<'_> {
+    /// Comprueba si se ha solicitado la cancelación del cálculo
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_some_and(CancelToken::is_cancelled)
+    }
+
+    /// Notifica el progreso del cálculo
+    pub fn progress(&self, carrier: Carrier, done: usize, total: usize) {
+        if let Some(on_progress) = self.on_progress {
+            on_progress(&Progress {
+                carrier,
+                done,
+                total,
+            });
+        }
+    }
+}
+
+impl std::fmt::Debug for CalcHooks<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CalcHooks")
+            .field("on_progress", &self.on_progress.is_some())
+            .field("cancel", &self.cancel)
+            .finish()
+    }
+}
@@ -0,0 +1,407 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Magnitudes físicas
+==================
+
+Tipos para expresar valores de energía (kWh) y potencia (kW) sin ambigüedad de unidades.
+
+La conversión entre ambas magnitudes requiere indicar explícitamente la duración (h)
+del intervalo considerado.
+*/
+
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul, Sub};
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{EpbdError, Result};
+
+/// Días de cada mes de un año no bisiesto
+const DAYS_IN_MONTH: [f32; 12] = [
+    31.0, 28.0, 31.0, 30.0, 31.0, 30.0, 31.0, 31.0, 30.0, 31.0, 30.0, 31.0,
+];
+
+/// Duración (h) del paso de cálculo `step` en una serie anual de `num_steps` pasos
+///
+/// Se admiten series mensuales (12), diarias (365) y horarias (8760).
+/// Devuelve None para otras longitudes o índices fuera de rango.
+pub fn timestep_hours(num_steps: usize, step: usize) -> Option<f32> {
+    if step >= num_steps {
+        return None;
+    }
+    match num_steps {
+        12 => Some(DAYS_IN_MONTH[step] * 24.0),
+        365 => Some(24.0),
+        8760 => Some(1.0),
+        _ => None,
+    }
+}
+
+/// Mes (desde 0) del paso de cálculo `step` en una serie anual de `num_steps` pasos
+///
+/// Devuelve None para longitudes no admitidas o índices fuera de rango.
+pub fn timestep_month(num_steps: usize, step: usize) -> Option<usize> {
+    timestep_hours(num_steps, step)?;
+    if num_steps == 12 {
+        return Some(step);
+    }
+    let mut day = if num_steps == 365 { step } else { step / 24 };
+    let mut month = 0;
+    while day >= DAYS_IN_MONTH[month] as usize {
+        day -= DAYS_IN_MONTH[month] as usize;
+        month += 1;
+    }
+    Some(month)
+}
+
+/// Posición en el año del paso de cálculo `step` en una serie anual de `num_steps` pasos
+///
+/// Se indica el mes (`mes 7`) en series mensuales, el día (`15/07`) en series diarias
+/// y el día y la hora de inicio (`15/07 14h`) en series horarias.
+/// Devuelve None para otras longitudes o índices fuera de rango.
+pub fn timestep_label(num_steps: usize, step: usize) -> Option<String> {
+    let month = timestep_month(num_steps, step)?;
+    if num_steps == 12 {
+        return Some(format!("mes {}", step + 1));
+    }
+    let day_of_year = if num_steps == 365 { step } else { step / 24 };
+    let day = day_of_year
+        - DAYS_IN_MONTH[..month]
+            .iter()
+            .map(|&d| d as usize)
+            .sum::<usize>();
+    let date = format!("{:02}/{:02}", day + 1, month + 1);
+    Some(if num_steps == 365 {
+        date
+    } else {
+        format!("{} {:02}h", date, step % 24)
+    })
+}
+
+/// Agrega una serie anual de valores por pasos de cálculo en valores mensuales
+///
+/// Devuelve None para longitudes de serie no admitidas (12, 365 u 8760 pasos).
+pub fn monthly_values(values: &[f32]) -> Option<Vec<f32>> {
+    timestep_hours(values.len(), 0)?;
+    let mut monthly = vec![0.0; 12];
+    for (step, value) in values.iter().enumerate() {
+        monthly[timestep_month(values.len(), step)?] += value;
+    }
+    Some(monthly)
+}
+
+/// Resolución temporal de las series de valores (paso de cálculo)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Resolution {
+    /// Paso mensual (12 valores)
+    MENSUAL,
+    /// Paso diario (365 valores)
+    DIARIA,
+    /// Paso horario (8760 valores)
+    HORARIA,
+}
+
+impl Resolution {
+    /// Resolución correspondiente a una serie anual de `num_steps` pasos
+    ///
+    /// Devuelve None para longitudes no admitidas (distintas de 12, 365 u 8760 pasos).
+    pub fn from_num_steps(num_steps: usize) -> Option<Self> {
+        match num_steps {
+            12 => Some(Resolution::MENSUAL),
+            365 => Some(Resolution::DIARIA),
+            8760 => Some(Resolution::HORARIA),
+            _ => None,
+        }
+    }
+
+    /// Número de pasos de cálculo de una serie anual con esta resolución
+    pub fn num_steps(self) -> usize {
+        match self {
+            Resolution::MENSUAL => 12,
+            Resolution::DIARIA => 365,
+            Resolution::HORARIA => 8760,
+        }
+    }
+
+    /// Indica si tiene sentido el factor de coincidencia de cargas estadístico (tabla B.32)
+    ///
+    /// En pasos horarios el desfase entre producción y consumo dentro de cada paso es despreciable
+    /// y el factor de coincidencia es la unidad. En pasos diarios y mensuales se usa la expresión estadística.
+    pub fn has_statistical_load_matching(self) -> bool {
+        self != Resolution::HORARIA
+    }
+}
+
+impl str::FromStr for Resolution {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<Resolution> {
+        match s.trim().to_uppercase().as_str() {
+            "MENSUAL" => Ok(Resolution::MENSUAL),
+            "DIARIA" => Ok(Resolution::DIARIA),
+            "HORARIA" => Ok(Resolution::HORARIA),
+            _ => Err(EpbdError::ParseError(format!(
+                "Resolución temporal desconocida `{}` (MENSUAL, DIARIA u HORARIA)",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Criterio de ponderación de la energía exportada cuando hay varias fuentes de producción (9.6.6.2.4)
+///
+/// Se declara con el metadato `CTE_PONDERACION_EXPORTACION`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportWeighting {
+    /// Factores medios ponderados por la energía exportada de cada fuente (fórmula 8)
+    #[default]
+    PROPORCIONAL,
+    /// La energía exportada se asigna en cada paso de cálculo primero a los usos no EPB y luego a la red,
+    /// tomándola de las fuentes por orden de prioridad (EL_INSITU > EL_EOLICA > EL_COGEN), igual que para los usos EPB
+    PRIORIDAD,
+}
+
+impl str::FromStr for ExportWeighting {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<ExportWeighting> {
+        match s.trim().to_uppercase().as_str() {
+            "PROPORCIONAL" => Ok(ExportWeighting::PROPORCIONAL),
+            "PRIORIDAD" => Ok(ExportWeighting::PRIORIDAD),
+            _ => Err(EpbdError::ParseError(format!(
+                "Criterio de ponderación de la energía exportada desconocido `{}` (PROPORCIONAL o PRIORIDAD)",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ExportWeighting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Energía, en kWh
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Kwh(pub f32);
+
+/// Potencia, en kW
+#[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Kw(pub f32);
+
+impl Kwh {
+    /// Potencia media necesaria para obtener esta energía en `hours` horas
+    ///
+    /// # Errors
+    ///
+    /// * Duración nula, negativa o no finita
+    pub fn mean_power(self, hours: f32) -> Result<Kw> {
+        if !hours.is_finite() || hours <= 0.0 {
+            return Err(EpbdError::WrongInput(format!(
+                "Duración incorrecta para el cálculo de la potencia media: {} h",
+                hours
+            )));
+        }
+        Ok(Kw(self.0 / hours))
+    }
+
+    /// Energía validada: valor finito
+    ///
+    /// # Errors
+    ///
+    /// * Valor no finito (NaN o infinito)
+    pub fn checked(value: f32) -> Result<Self> {
+        if !value.is_finite() {
+            return Err(EpbdError::WrongInput(format!(
+                "Valor de energía no válido: {}",
+                value
+            )));
+        }
+        Ok(Kwh(value))
+    }
+}
+
+impl Kw {
+    /// Energía obtenida con esta potencia durante `hours` horas
+    pub fn energy(self, hours: f32) -> Kwh {
+        Kwh(self.0 * hours)
+    }
+
+    /// Potencia validada: valor finito y no negativo
+    ///
+    /// # Errors
+    ///
+    /// * Valor negativo o no finito (NaN o infinito)
+    pub fn checked(value: f32) -> Result<Self> {
+        if !value.is_finite() || value < 0.0 {
+            return Err(EpbdError::WrongInput(format!(
+                "Valor de potencia no válido: {}",
+                value
+            )));
+        }
+        Ok(Kw(value))
+    }
+}
+
+/// Implementa operaciones básicas, formato y lectura para una magnitud
+macro_rules! impl_magnitude {
+    ($t:ident, $unit:expr) => {
+        impl Add for $t {
+            type Output = $t;
+
+            fn add(self, other: $t) -> $t {
+                $t(self.0 + other.0)
+            }
+        }
+
+        impl AddAssign for $t {
+            fn add_assign(&mut self, other: $t) {
+                self.0 += other.0;
+            }
+        }
+
+        impl Sub for $t {
+            type Output = $t;
+
+            fn sub(self, other: $t) -> $t {
+                $t(self.0 - other.0)
+            }
+        }
+
+        impl Mul<f32> for $t {
+            type Output = $t;
+
+            fn mul(self, rhs: f32) -> $t {
+                $t(self.0 * rhs)
+            }
+        }
+
+        impl Sum for $t {
+            fn sum<I: Iterator<Item = $t>>(iter: I) -> $t {
+                iter.fold($t::default(), |acc, v| acc + v)
+            }
+        }
+
+        impl fmt::Display for $t {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let decimals = f.precision().unwrap_or(2);
+                write!(f, "{:.*} {}", decimals, self.0, $unit)
+            }
+        }
+
+        impl str::FromStr for $t {
+            type Err = EpbdError;
+
+            /// Lee un valor numérico, opcionalmente seguido de la unidad
+            fn from_str(s: &str) -> Result<$t> {
+                let s = s.trim();
+                let (value, unit) = match s.find(|c: char| c.is_alphabetic()) {
+                    Some(pos) => (s[..pos].trim(), s[pos..].trim()),
+                    None => (s, $unit),
+                };
+                if unit != $unit {
+                    return Err(EpbdError::ParseError(format!(
+                        "Unidad incorrecta en \"{}\" (se esperaba {})",
+                        s, $unit
+                    )));
+                }
+                Ok($t(value.parse::<f32>()?))
+            }
+        }
+    };
+}
+
+impl_magnitude!(Kwh, "kWh");
+impl_magnitude!(Kw, "kW");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn magnitudes_conversion() {
+        assert_eq!(Kwh(10.0), Kw(2.5).energy(4.0));
+        assert_eq!(Kw(2.5), Kwh(10.0).mean_power(4.0).unwrap());
+        assert!(Kwh(10.0).mean_power(0.0).is_err());
+        assert_eq!(Some(744.0), timestep_hours(12, 0));
+        assert_eq!(Some(672.0), timestep_hours(12, 1));
+        assert_eq!(Some(1.0), timestep_hours(8760, 100));
+        assert_eq!(None, timestep_hours(12, 12));
+        assert_eq!(None, timestep_hours(10, 0));
+        assert_eq!(Some("mes 7".to_string()), timestep_label(12, 6));
+        assert_eq!(Some("01/02".to_string()), timestep_label(365, 31));
+        assert_eq!(Some("31/12".to_string()), timestep_label(365, 364));
+        assert_eq!(
+            Some("15/07 14h".to_string()),
+            timestep_label(8760, 195 * 24 + 14)
+        );
+        assert_eq!(None, timestep_label(10, 0));
+        assert_eq!(Some(11), timestep_month(8760, 8759));
+        let monthly = monthly_values(&[1.0; 365]).unwrap();
+        assert_eq!(31.0, monthly[0]);
+        assert_eq!(28.0, monthly[1]);
+        assert_eq!(None, monthly_values(&[1.0, 2.0]));
+    }
+
+    #[test]
+    fn magnitudes_resolution() {
+        assert_eq!(Some(Resolution::DIARIA), Resolution::from_num_steps(365));
+        assert_eq!(None, Resolution::from_num_steps(1));
+        assert_eq!(365, Resolution::DIARIA.num_steps());
+        assert_eq!(Resolution::DIARIA, "diaria".parse::<Resolution>().unwrap());
+        assert!("SEMANAL".parse::<Resolution>().is_err());
+        assert_eq!("HORARIA", Resolution::HORARIA.to_string());
+        assert!(Resolution::DIARIA.has_statistical_load_matching());
+        assert!(!Resolution::HORARIA.has_statistical_load_matching());
+    }
+
+    #[test]
+    fn magnitudes_parse_and_display() {
+        assert_eq!(Kwh(1.5), "1.5".parse::<Kwh>().unwrap());
+        assert_eq!(Kwh(1.5), "1.5 kWh".parse::<Kwh>().unwrap());
+        assert_eq!(Kw(3.0), "3kW".parse::<Kw>().unwrap());
+        assert!("3 kW".parse::<Kwh>().is_err());
+        assert!("3 kWh".parse::<Kw>().is_err());
+        assert_eq!("1.50 kWh", Kwh(1.5).to_string());
+        assert_eq!("3.0 kW", format!("{:.1}", Kw(3.0)));
+        assert!(Kw::checked(-1.0).is_err());
+        assert!(Kwh::checked(f32::NAN).is_err());
+        assert_eq!(Kwh(3.0), [Kwh(1.0), Kwh(2.0)].into_iter().sum());
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Factores de emisión marginales y huella de carbono marginal
+===========================================================
+
+Series de factores de emisión marginales por vector energético (p.e. factores horarios de CO2
+marginal de la red eléctrica) y resultados del cálculo de la huella de carbono marginal.
+
+El cálculo es opcional e informativo (para estudios de investigación) y no modifica el balance
+reglamentario. Estima, para cada paso de cálculo, las emisiones marginales del suministro de red y
+las emisiones marginales evitadas por la energía producida in situ usada en el edificio y por la
+energía exportada a la red.
+*/
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{Carrier, Meta, MetaVec, UserCarrierScope},
+};
+
+// ==================== Marginal emission factors
+
+/// Serie de factores de emisión marginales de un vector energético
+///
+/// Marginal emission factors for an energy carrier at each timestep (kg_CO2e/kWh)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarginalCo2Series {
+    /// Energy carrier
+    pub carrier: Carrier,
+    /// Marginal emission factors at each timestep. kg_CO2e/kWh
+    pub values: Vec<f32>,
+    /// Descriptive comment string for the marginal emission factors
+    pub comment: String,
+}
+
+impl fmt::Display for MarginalCo2Series {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        let values = self
+            .values
+            .iter()
+            .map(|v| format!("{:.3}", v))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "{}, {}{}", self.carrier, values, comment)
+    }
+}
+
+impl str::FromStr for MarginalCo2Series {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<MarginalCo2Series> {
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+        let carrier: Carrier = items[0].parse()?;
+        let values = items[1..]
+            .iter()
+            .map(|v| v.parse::<f32>())
+            .collect::<std::result::Result<Vec<f32>, _>>()?;
+        if values.is_empty() || values.iter().any(|v| !v.is_finite()) {
+            return Err(EpbdError::WrongInput(format!(
+                "Valores de factores de emisión marginales no válidos para {}",
+                carrier
+            )));
+        };
+        Ok(MarginalCo2Series {
+            carrier,
+            values,
+            comment,
+        })
+    }
+}
+
+/// Lista de series de factores de emisión marginales por vector energético
+///
+/// Se leen de una tabla con líneas `VECTOR, f_1, f_2, ..., f_n # Comentario` (kg_CO2e/kWh),
+/// con un valor para cada paso de cálculo, y metadatos `#META CLAVE: valor`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MarginalCo2Factors {
+    /// Marginal emission factors metadata
+    pub meta: Vec<Meta>,
+    /// Marginal emission factors data
+    pub data: Vec<MarginalCo2Series>,
+}
+
+impl MarginalCo2Factors {
+    /// Serie de factores de emisión marginales de un vector energético
+    pub fn get(&self, carrier: Carrier) -> Option<&MarginalCo2Series> {
+        self.data.iter().find(|s| s.carrier == carrier)
+    }
+}
+
+impl MetaVec for MarginalCo2Factors {
+    fn get_metavec(&self) -> &Vec<Meta> {
+        &self.meta
+    }
+    fn get_mut_metavec(&mut self) -> &mut Vec<Meta> {
+        &mut self.meta
+    }
+}
+
+impl fmt::Display for MarginalCo2Factors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metalines = self
+            .meta
+            .iter()
+            .map(|v| format!("{}", v))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let datalines = self
+            .data
+            .iter()
+            .map(|v| format!("{}", v))
+            .collect::<Vec<_>>()
+            .join("\n");
+        write!(f, "{}\n{}", metalines, datalines)
+    }
+}
+
+impl str::FromStr for MarginalCo2Factors {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<MarginalCo2Factors> {
+        let meta = s
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, l)| l.starts_with("#META"))
+            .map(|(i, l)| l.parse::<Meta>().map_err(|e| e.at_line(i + 1)))
+            .collect::<Result<Vec<_>>>()?;
+        let _user_carriers = UserCarrierScope::from_meta(&meta)?;
+        let mut data: Vec<MarginalCo2Series> = Vec::new();
+        for (i, line) in s.lines().map(str::trim).enumerate() {
+            if !(line.starts_with('#') || line.starts_with("vector,") || line.is_empty()) {
+                let series = line
+                    .parse::<MarginalCo2Series>()
+                    .map_err(|e| e.at_line(i + 1))?;
+                if data.iter().any(|s| s.carrier == series.carrier) {
+                    return Err(EpbdError::WrongInput(format!(
+                        "Factores de emisión marginales duplicados para {}",
+                        series.carrier
+                    ))
+                    .at_line(i + 1));
+                }
+                data.push(series);
+            }
+        }
+        Ok(MarginalCo2Factors { meta, data })
+    }
+}
+
+// ==================== Marginal carbon footprint
+
+/// Huella de carbono marginal de un vector energético
+///
+/// Emisiones marginales en cada paso de cálculo y anuales, en kg_CO2e.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MarginalCarbonCarrier {
+    /// Marginal emissions of delivered energy from the grid at each timestep
+    pub del_grid_t: Vec<f32>,
+    /// Avoided marginal emissions by onsite produced energy used in EPB services at each timestep
+    pub avoided_used_t: Vec<f32>,
+    /// Avoided marginal emissions by energy exported to the grid at each timestep
+    pub avoided_exp_t: Vec<f32>,
+    /// Marginal emissions of delivered energy from the grid
+    pub del_grid_an: f32,
+    /// Avoided marginal emissions by onsite produced energy used in EPB services
+    pub avoided_used_an: f32,
+    /// Avoided marginal emissions by energy exported to the grid
+    pub avoided_exp_an: f32,
+}
+
+/// Resultados de la huella de carbono marginal (informativos, no reglamentarios)
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct MarginalCarbon {
+    /// Marginal emission factors used in the computation
+    pub factors: MarginalCo2Factors,
+    /// Marginal carbon footprint, by carrier
+    pub by_cr: BTreeMap<Carrier, MarginalCarbonCarrier>,
+    /// Marginal emissions of delivered energy from the grid. kg_CO2e/an
+    pub del_grid: f32,
+    /// Avoided marginal emissions by onsite produced energy used and exported energy. kg_CO2e/an
+    pub avoided: f32,
+    /// Avoided marginal emissions at each timestep, for all carriers. kg_CO2e
+    pub avoided_t: Vec<f32>,
+    /// Net marginal emissions (del_grid - avoided), by reference area. kg_CO2e/m2.an
+    pub net_m2: f32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn tmarginalco2factors() {
+        let mfactors1 = "#META CTE_FUENTE: Prueba
+ELECTRICIDAD, 0.300, 0.250, 0.400 # Factores marginales horarios";
+
+        // roundtrip building from/to string
+        let mfactors = mfactors1.parse::<MarginalCo2Factors>().unwrap();
+        assert_eq!(mfactors.to_string(), mfactors1);
+        assert_eq!(
+            Some(&vec![0.3, 0.25, 0.4]),
+            mfactors.get(Carrier::ELECTRICIDAD).map(|s| &s.values)
+        );
+        assert!(mfactors.get(Carrier::GASNATURAL).is_none());
+
+        // bad carrier, bad values or duplicated carrier
+        assert!("ELECTRICIDA, 0.3".parse::<MarginalCo2Factors>().is_err());
+        assert!("ELECTRICIDAD, xx".parse::<MarginalCo2Factors>().is_err());
+        assert!("ELECTRICIDAD".parse::<MarginalCo2Factors>().is_err());
+        assert!("ELECTRICIDAD, 0.3\nELECTRICIDAD, 0.2"
+            .parse::<MarginalCo2Factors>()
+            .is_err());
+    }
+}
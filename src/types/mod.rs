@@ -32,25 +32,57 @@ Definición de tipos básicos para el cálculo de la eficiencia energética.
 */
 
 mod balance;
+mod bills;
 mod carrier;
+mod co2;
 mod ctypes;
+mod diagnostic;
+mod embodied;
 mod energy;
+mod exergy;
 mod factor;
 mod hasvalues;
+mod hooks;
+mod magnitudes;
+mod marginal;
 mod needs;
+mod network_mix;
+mod options;
 mod prodsource;
 mod rennrenco2;
+mod scope;
 mod service;
+mod storage;
+mod system;
+mod tags;
 mod tmeta;
+mod tolerances;
+mod warning;
 
 pub use balance::*;
+pub use bills::*;
 pub use carrier::*;
+pub use co2::*;
 pub use ctypes::CType;
+pub use diagnostic::*;
+pub use embodied::*;
 pub use energy::*;
+pub use exergy::*;
 pub use factor::*;
 pub use hasvalues::*;
+pub use hooks::*;
+pub use magnitudes::*;
+pub use marginal::*;
 pub use needs::*;
+pub use network_mix::*;
+pub use options::*;
 pub use prodsource::*;
 pub use rennrenco2::*;
+pub use scope::*;
 pub use service::*;
+pub use storage::*;
+pub use system::*;
+pub use tags::*;
 pub use tmeta::*;
+pub use tolerances::*;
+pub use warning::*;
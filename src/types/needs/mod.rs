@@ -23,6 +23,7 @@
 //            Daniel Jiménez González <dani@ietcc.csic.es>,
 //            Marta Sorribes Gil <msorribes@ietcc.csic.es>
 
+use std::collections::BTreeMap;
 use std::fmt;
 use std::str;
 
@@ -36,6 +37,7 @@ use crate::vecops::vecvecsum;
 // Define basic Building Energy Needs Component type and a container of all Building needs
 // The component is used to express energy needs of the whole building provide service X (X=CAL/REF/ACS) (Q_X_nd_t)
 // The component stores building needs for heating (CAL), cooling (REF) and domestic heat water (ACS)
+// Components with an id other than 0 express energy needs of a building zone
 
 /// Demandas del edificio
 #[allow(non_snake_case)]
@@ -53,40 +55,175 @@ pub struct BuildingNeeds {
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub REF: Option<Vec<f32>>,
+    /// Timestep energy needs of each building zone, by zone id
+    #[serde(default)]
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub zones: BTreeMap<i32, ZoneNeeds>,
 }
 
 impl BuildingNeeds {
     /// Añade elemento de demanda del edificio, sumando los valores si ya se han definido para ese servicio
+    ///
+    /// Los elementos con id distinto de 0 se incorporan como demanda de la zona correspondiente
     pub fn add(&mut self, need: Needs) -> Result<(), EpbdError> {
-        let update = |cur_values: &Option<Vec<f32>>, new_values| {
-            if let Some(nd) = cur_values {
-                Some(vecvecsum(nd, new_values))
-            } else {
-                Some(new_values.to_owned())
+        if need.id != 0 {
+            return self.zones.entry(need.id).or_default().add(need);
+        }
+        match need.service {
+            Service::ACS => add_values(&mut self.ACS, &need.values),
+            Service::CAL => add_values(&mut self.CAL, &need.values),
+            Service::REF => add_values(&mut self.REF, &need.values),
+            _ => {
+                return Err(EpbdError::WrongInput(format!(
+                    "Demanda de edificio con servicio no contemplado por el programa: {}",
+                    need.service
+                )))
             }
         };
+        Ok(())
+    }
+
+    /// Demanda del edificio para el servicio indicado
+    pub fn get(&self, service: Service) -> Option<&Vec<f32>> {
+        match service {
+            Service::ACS => self.ACS.as_ref(),
+            Service::CAL => self.CAL.as_ref(),
+            Service::REF => self.REF.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Suma de las demandas de las zonas para el servicio indicado
+    ///
+    /// Devuelve None si ninguna zona define demanda para ese servicio
+    pub fn zones_sum(&self, service: Service) -> Option<Vec<f32>> {
+        self.zones
+            .values()
+            .filter_map(|zone| zone.get(service))
+            .fold(None, |acc, values| match acc {
+                None => Some(values.to_owned()),
+                Some(acc) => Some(vecvecsum(&acc, values)),
+            })
+    }
+
+    /// Conserva únicamente las demandas (del edificio y de las zonas) de los servicios indicados
+    pub fn retain_services(&mut self, services: &[Service]) {
+        retain_values(&mut self.ACS, Service::ACS, services);
+        retain_values(&mut self.CAL, Service::CAL, services);
+        retain_values(&mut self.REF, Service::REF, services);
+        for zone in self.zones.values_mut() {
+            zone.retain_services(services);
+        }
+        self.zones
+            .retain(|_, zone| zone.ACS.is_some() || zone.CAL.is_some() || zone.REF.is_some());
+    }
+
+    /// Multiplica por `factor` las demandas (del edificio y de las zonas) del servicio indicado
+    pub fn scale_service(&mut self, service: Service, factor: f32) {
+        scale_values(&mut self.ACS, Service::ACS, service, factor);
+        scale_values(&mut self.CAL, Service::CAL, service, factor);
+        scale_values(&mut self.REF, Service::REF, service, factor);
+        for zone in self.zones.values_mut() {
+            zone.scale_service(service, factor);
+        }
+    }
+}
+
+/// Demandas de una zona del edificio
+#[allow(non_snake_case)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ZoneNeeds {
+    /// Timestep zone energy needs to provide the domestic heat water service, Q_DHW_nd_t. kWh
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ACS: Option<Vec<f32>>,
+    /// Timestep zone energy needs to provide the heating service, Q_H_nd_t. kWh
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub CAL: Option<Vec<f32>>,
+    /// Timestep zone energy needs to provide the cooling service, Q_C_nd_t. kWh
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub REF: Option<Vec<f32>>,
+}
+
+impl ZoneNeeds {
+    /// Añade elemento de demanda de la zona, sumando los valores si ya se han definido para ese servicio
+    pub fn add(&mut self, need: Needs) -> Result<(), EpbdError> {
         match need.service {
-            Service::ACS => self.ACS = update(&self.ACS, &need.values),
-            Service::CAL => self.CAL = update(&self.CAL, &need.values),
-            Service::REF => self.REF = update(&self.REF, &need.values),
+            Service::ACS => add_values(&mut self.ACS, &need.values),
+            Service::CAL => add_values(&mut self.CAL, &need.values),
+            Service::REF => add_values(&mut self.REF, &need.values),
             _ => {
                 return Err(EpbdError::WrongInput(format!(
-                    "Demanda de edificio con servicio no contemplado por el programa: {}",
+                    "Demanda de zona con servicio no contemplado por el programa: {}",
                     need.service
                 )))
             }
         };
         Ok(())
     }
+
+    /// Demanda de la zona para el servicio indicado
+    pub fn get(&self, service: Service) -> Option<&Vec<f32>> {
+        match service {
+            Service::ACS => self.ACS.as_ref(),
+            Service::CAL => self.CAL.as_ref(),
+            Service::REF => self.REF.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Conserva únicamente las demandas de la zona de los servicios indicados
+    pub fn retain_services(&mut self, services: &[Service]) {
+        retain_values(&mut self.ACS, Service::ACS, services);
+        retain_values(&mut self.CAL, Service::CAL, services);
+        retain_values(&mut self.REF, Service::REF, services);
+    }
+
+    /// Multiplica por `factor` las demandas de la zona del servicio indicado
+    pub fn scale_service(&mut self, service: Service, factor: f32) {
+        scale_values(&mut self.ACS, Service::ACS, service, factor);
+        scale_values(&mut self.CAL, Service::CAL, service, factor);
+        scale_values(&mut self.REF, Service::REF, service, factor);
+    }
 }
 
-/// Componente de demanda de edificio.
+/// Elimina los valores si su servicio no está entre los indicados
+fn retain_values(cur_values: &mut Option<Vec<f32>>, service: Service, services: &[Service]) {
+    if !services.contains(&service) {
+        *cur_values = None;
+    }
+}
+
+/// Multiplica los valores por `factor` si su servicio es el indicado
+fn scale_values(cur_values: &mut Option<Vec<f32>>, service: Service, target: Service, factor: f32) {
+    if service == target {
+        if let Some(values) = cur_values {
+            values.iter_mut().for_each(|v| *v *= factor);
+        }
+    }
+}
+
+/// Suma valores a los existentes o los asigna si no hay valores previos
+fn add_values(cur_values: &mut Option<Vec<f32>>, new_values: &[f32]) {
+    *cur_values = match cur_values {
+        Some(nd) => Some(vecvecsum(nd, new_values)),
+        None => Some(new_values.to_owned()),
+    };
+}
+
+/// Componente de demanda de edificio o de zona.
 ///
-/// Se serializa como: `DEMANDA, servicio, vals... # comentario`
+/// Se serializa como: `DEMANDA, servicio, vals... # comentario` (edificio) o
+/// `id, DEMANDA, servicio, vals... # comentario` (zona id, con id distinto de 0)
 ///
 /// - servicio == CAL / REF / ACS
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Needs {
+    /// Zone id. The whole building has id == 0
+    #[serde(default)]
+    pub id: i32,
     /// End use (CAL, REF, ACS)
     pub service: Service,
     /// List of timestep energy needs for the building to provide service X, Q_X_nd_t. kWh
@@ -107,7 +244,11 @@ impl fmt::Display for Needs {
             .map(|v| format!("{:.2}", v))
             .collect::<Vec<_>>()
             .join(", ");
-        write!(f, "DEMANDA, {}, {}", self.service, value_list)
+        if self.id == 0 {
+            write!(f, "DEMANDA, {}, {}", self.service, value_list)
+        } else {
+            write!(f, "{}, DEMANDA, {}, {}", self.id, self.service, value_list)
+        }
     }
 }
 
@@ -119,6 +260,12 @@ impl str::FromStr for Needs {
         let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
         let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
 
+        // Zone needs start with the zone id
+        let (id, items) = match items[0].parse::<i32>() {
+            Ok(id) => (id, &items[1..]),
+            Err(_) => (0, &items[..]),
+        };
+
         // Minimal possible length (DEMANDA + Service + 1 value)
         if items.len() < 3 {
             return Err(EpbdError::ParseError(s.into()));
@@ -150,7 +297,11 @@ impl str::FromStr for Needs {
             .map(|v| v.parse::<f32>())
             .collect::<Result<Vec<f32>, _>>()?;
 
-        Ok(Needs { service, values })
+        Ok(Needs {
+            id,
+            service,
+            values,
+        })
     }
 }
 
@@ -165,6 +316,7 @@ mod tests {
     fn component_building_needs() {
         // zone energy needs component
         let component1 = Needs {
+            id: 0,
             service: "REF".parse().unwrap(),
             values: vec![
                 1.0, 2.0, 3.0, 4.0, 5.0, -6.0, -7.0, -8.0, -9.0, 10.0, 11.0, 12.0,
@@ -178,5 +330,25 @@ mod tests {
             component1str.parse::<Needs>().unwrap().to_string(),
             component1str
         );
+
+        // zone needs component
+        let component2str = "2, DEMANDA, CAL, 1.00, 2.00, 3.00";
+        let component2 = component2str.parse::<Needs>().unwrap();
+        assert_eq!(component2.id, 2);
+        assert_eq!(component2.to_string(), component2str);
+
+        // building and zone needs
+        let mut needs = BuildingNeeds::default();
+        needs
+            .add("DEMANDA, CAL, 2.0, 4.0, 6.0".parse().unwrap())
+            .unwrap();
+        needs.add(component2).unwrap();
+        needs
+            .add("3, DEMANDA, CAL, 1.0, 2.0, 3.0".parse().unwrap())
+            .unwrap();
+        assert_eq!(needs.CAL, Some(vec![2.0, 4.0, 6.0]));
+        assert_eq!(needs.zones.len(), 2);
+        assert_eq!(needs.zones_sum(Service::CAL), Some(vec![2.0, 4.0, 6.0]));
+        assert_eq!(needs.zones_sum(Service::ACS), None);
     }
 }
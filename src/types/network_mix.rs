@@ -0,0 +1,203 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Mix de generación de redes de distrito
+======================================
+
+Mix de generación declarado de una red de distrito (RED1, RED2 u otras redes de usuario),
+a partir del que se obtienen los factores de paso agregados de suministro de la red.
+
+El mix se registra en los metadatos de los factores de paso (`CTE_MIX_<RED>`), con una lista de
+tecnologías separadas por punto y coma, cada una con su nombre, su porcentaje de la energía suministrada
+por la red y sus factores de paso (ren, nren, co2) por unidad de energía suministrada:
+
+`#META CTE_MIX_RED1: BIOMASA, 60, 1.003, 0.034, 0.018; GASNATURAL, 40, 0.005, 1.190, 0.252`
+*/
+
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{EpbdError, Result},
+    types::{Carrier, RenNrenCo2},
+};
+
+/// Tolerancia admitida en la suma de los porcentajes del mix, %
+const SHARES_TOLERANCE: f32 = 0.1;
+
+/// Tecnología de generación del mix de una red de distrito
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkMixItem {
+    /// Nombre de la tecnología (p.e. BIOMASA, GASNATURAL, CALOR_RESIDUAL)
+    pub tech: String,
+    /// Porcentaje de la energía suministrada por la red, %
+    pub share: f32,
+    /// Factores de paso de la tecnología por unidad de energía suministrada
+    pub factors: RenNrenCo2,
+}
+
+impl fmt::Display for NetworkMixItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, {:.1}, {:.3}, {:.3}, {:.3}",
+            self.tech, self.share, self.factors.ren, self.factors.nren, self.factors.co2
+        )
+    }
+}
+
+impl str::FromStr for NetworkMixItem {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<NetworkMixItem> {
+        let items: Vec<&str> = s.split(',').map(str::trim).collect();
+        if items.len() != 5 || items[0].is_empty() {
+            return Err(EpbdError::ParseError(format!(
+                "Tecnología del mix de red con formato incorrecto (tecnología, %, ren, nren, co2): {}",
+                s.trim()
+            )));
+        }
+        let values = items[1..]
+            .iter()
+            .map(|v| v.parse::<f32>())
+            .collect::<std::result::Result<Vec<f32>, _>>()?;
+        if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+            return Err(EpbdError::WrongInput(format!(
+                "Valores negativos o no válidos en la tecnología del mix de red: {}",
+                s.trim()
+            )));
+        }
+        Ok(NetworkMixItem {
+            tech: items[0].to_string(),
+            share: values[0],
+            factors: RenNrenCo2::new(values[1], values[2], values[3]),
+        })
+    }
+}
+
+/// Mix de generación de una red de distrito
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NetworkMix {
+    /// Vector de la red de distrito
+    pub carrier: Carrier,
+    /// Tecnologías de generación
+    pub items: Vec<NetworkMixItem>,
+}
+
+impl NetworkMix {
+    /// Construye el mix de una red de distrito a partir del valor del metadato `CTE_MIX_<RED>`
+    ///
+    /// # Errors
+    ///
+    /// * Vector que no es una red de distrito
+    /// * Tecnologías con formato incorrecto o sin tecnologías
+    /// * Porcentajes que no suman 100%
+    pub fn new(carrier: Carrier, value: &str) -> Result<Self> {
+        if !carrier.is_district_network() {
+            return Err(EpbdError::WrongInput(format!(
+                "El vector {} no es una red de distrito y no admite mix de generación",
+                carrier
+            )));
+        }
+        let items = value
+            .split(';')
+            .filter(|item| !item.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<NetworkMixItem>>>()?;
+        let total: f32 = items.iter().map(|item| item.share).sum();
+        if items.is_empty() || (total - 100.0).abs() > SHARES_TOLERANCE {
+            return Err(EpbdError::WrongInput(format!(
+                "Los porcentajes del mix de generación de la red {} deben sumar 100% ({:.1}%)",
+                carrier, total
+            )));
+        }
+        Ok(NetworkMix { carrier, items })
+    }
+
+    /// Clave del metadato de factores de paso en el que se registra el mix (`CTE_MIX_<RED>`)
+    pub fn meta_key(&self) -> String {
+        format!("CTE_MIX_{}", self.carrier)
+    }
+
+    /// Factores de paso agregados de suministro de la red (RED, SUMINISTRO, A)
+    ///
+    /// Media de los factores de las tecnologías ponderada por su porcentaje de la energía suministrada
+    pub fn factors(&self) -> RenNrenCo2 {
+        self.items.iter().fold(RenNrenCo2::default(), |acc, item| {
+            acc + item.factors * (item.share / 100.0)
+        })
+    }
+}
+
+/// Valor del metadato `CTE_MIX_<RED>` con el mix de generación
+impl fmt::Display for NetworkMix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let items = self
+            .items
+            .iter()
+            .map(NetworkMixItem::to_string)
+            .collect::<Vec<_>>()
+            .join("; ");
+        write!(f, "{}", items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn network_mix() {
+        let mix = NetworkMix::new(
+            Carrier::RED1,
+            "BIOMASA, 60, 1.0, 0.05, 0.02; GASNATURAL, 40, 0.0, 1.2, 0.25",
+        )
+        .unwrap();
+        assert_eq!(mix.meta_key(), "CTE_MIX_RED1");
+        let factors = mix.factors();
+        assert!((factors.ren - 0.6).abs() < 1e-5);
+        assert!((factors.nren - 0.51).abs() < 1e-5);
+        assert!((factors.co2 - 0.112).abs() < 1e-5);
+        assert_eq!(
+            mix.to_string(),
+            "BIOMASA, 60.0, 1.000, 0.050, 0.020; GASNATURAL, 40.0, 0.000, 1.200, 0.250"
+        );
+        // Roundtrip
+        assert_eq!(
+            NetworkMix::new(Carrier::RED1, &mix.to_string()).unwrap(),
+            mix
+        );
+        // Porcentajes que no suman 100%
+        assert!(NetworkMix::new(Carrier::RED1, "BIOMASA, 60, 1.0, 0.05, 0.02").is_err());
+        // Formato incorrecto
+        assert!(NetworkMix::new(Carrier::RED2, "BIOMASA, 100, 1.0, 0.05").is_err());
+        // Vector que no es red de distrito
+        assert!(NetworkMix::new(Carrier::GASNATURAL, "BIOMASA, 100, 1.0, 0.05, 0.02").is_err());
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+//! Opciones del cálculo de la eficiencia energética
+
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use super::{CalcHooks, Resolution, Tolerances};
+use crate::error::EpbdError;
+
+/// Opciones del cálculo de la eficiencia energética
+///
+/// Reúne los parámetros del cálculo distintos de los componentes y los factores de paso
+/// (ver [`crate::energy_performance_with`]). Puede construirse indicando sus campos o con los
+/// métodos de construcción a partir de los valores por defecto:
+///
+/// ```
+/// use cteepbd::types::{CalcOptions, LoadMatchingMode};
+///
+/// let options = CalcOptions::default()
+///     .k_exp(0.0)
+///     .arearef(150.0)
+///     .load_matching(true);
+/// assert_eq!(options.arearef, 150.0);
+/// assert_ne!(options.load_matching, LoadMatchingMode::Constant);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct CalcOptions<'a> {
+    /// Factor de exportación [0, 1]
+    pub k_exp: f32,
+    /// Área de referencia usada para los indicadores por m2, m2
+    pub arearef: f32,
+    /// Modo de cálculo del factor de coincidencia de cargas
+    pub load_matching: LoadMatchingMode,
+    /// Tolerancias numéricas del cálculo
+    pub tolerances: Tolerances,
+    /// Tratamiento de los consumos EPB negativos por servicio
+    pub negative_use: NegativeUsePolicy,
+    /// Funciones de seguimiento del progreso y cancelación del cálculo
+    pub hooks: CalcHooks<'a>,
+}
+
+impl Default for CalcOptions<'_> {
+    /// Opciones por defecto: k_exp = 0 (valor reglamentario del CTE), área de referencia de 1 m2,
+    /// factor de coincidencia de cargas constante, tolerancias por defecto y consumos negativos
+    /// permitidos (con aviso)
+    fn default() -> Self {
+        Self {
+            k_exp: 0.0,
+            arearef: 1.0,
+            load_matching: LoadMatchingMode::default(),
+            tolerances: Tolerances::default(),
+            negative_use: NegativeUsePolicy::default(),
+            hooks: CalcHooks::default(),
+        }
+    }
+}
+
+impl<'a> CalcOptions<'a> {
+    /// Opciones con el factor de exportación y el área de referencia indicados
+    pub fn new(k_exp: f32, arearef: f32) -> Self {
+        Self {
+            k_exp,
+            arearef,
+            ..Default::default()
+        }
+    }
+
+    /// Fija el factor de exportación [0, 1]
+    pub fn k_exp(mut self, k_exp: f32) -> Self {
+        self.k_exp = k_exp;
+        self
+    }
+
+    /// Fija el área de referencia, m2
+    pub fn arearef(mut self, arearef: f32) -> Self {
+        self.arearef = arearef;
+        self
+    }
+
+    /// Fija el modo de cálculo del factor de coincidencia de cargas
+    ///
+    /// Un valor lógico selecciona la expresión estadística mensual (`true`) o un factor constante (`false`)
+    pub fn load_matching(mut self, load_matching: impl Into<LoadMatchingMode>) -> Self {
+        self.load_matching = load_matching.into();
+        self
+    }
+
+    /// Fija las tolerancias numéricas del cálculo
+    pub fn tolerances(mut self, tolerances: Tolerances) -> Self {
+        self.tolerances = tolerances;
+        self
+    }
+
+    /// Fija el tratamiento de los consumos EPB negativos por servicio
+    pub fn negative_use(mut self, negative_use: NegativeUsePolicy) -> Self {
+        self.negative_use = negative_use;
+        self
+    }
+
+    /// Fija las funciones de seguimiento del progreso y cancelación del cálculo
+    pub fn hooks(mut self, hooks: CalcHooks<'a>) -> Self {
+        self.hooks = hooks;
+        self
+    }
+}
+
+/// Modo de cálculo del factor de coincidencia de cargas (f_match), según la tabla B.32
+///
+/// Load matching factor computation mode
+#[derive(Debug, Default, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LoadMatchingMode {
+    /// Factor constante igual a la unidad en todos los pasos de cálculo
+    #[default]
+    Constant,
+    /// Expresión estadística para pasos mensuales (también diarios) de la tabla B.32, con parámetros k y n
+    ///
+    /// f_match = (x^n + x^-k - 1) / (x^n + x^-k), siendo x = E_pr / E_EPus en cada paso de cálculo.
+    /// Con k = 1 y n = 1 es la expresión propuesta en la norma. En pasos horarios el factor es la unidad.
+    Monthly {
+        /// Parámetro k
+        k: f32,
+        /// Parámetro n
+        n: f32,
+    },
+    /// Expresión para pasos horarios de la tabla B.32 (factor igual a la unidad), solo con datos horarios
+    Hourly,
+}
+
+impl LoadMatchingMode {
+    /// Expresión estadística mensual con los parámetros propuestos en la tabla B.32 (k = 1, n = 1)
+    pub const MONTHLY_DEFAULT: LoadMatchingMode = LoadMatchingMode::Monthly { k: 1.0, n: 1.0 };
+
+    /// Factor de coincidencia de cargas para la fracción x = E_pr / E_EPus de un paso de cálculo
+    /// de la resolución indicada
+    pub fn f_match(self, x: f32, resolution: Option<Resolution>) -> f32 {
+        match self {
+            LoadMatchingMode::Monthly { k, n }
+                if resolution.is_none_or(Resolution::has_statistical_load_matching) =>
+            {
+                if x <= 0.0 {
+                    1.0
+                } else {
+                    let sum = x.powf(n) + x.powf(-k);
+                    (sum - 1.0) / sum
+                }
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+impl From<bool> for LoadMatchingMode {
+    /// Modo estadístico mensual por defecto (true) o factor constante (false)
+    fn from(load_matching: bool) -> Self {
+        if load_matching {
+            LoadMatchingMode::MONTHLY_DEFAULT
+        } else {
+            LoadMatchingMode::Constant
+        }
+    }
+}
+
+impl str::FromStr for LoadMatchingMode {
+    type Err = EpbdError;
+
+    /// Interpreta `constante`, `horaria`, `mensual` o `mensual:k:n`
+    fn from_str(s: &str) -> Result<LoadMatchingMode, Self::Err> {
+        let s = s.trim().to_lowercase();
+        let mut items = s.split(':').map(str::trim);
+        match (items.next(), items.next(), items.next(), items.next()) {
+            (Some("constante"), None, None, None) => Ok(LoadMatchingMode::Constant),
+            (Some("horaria"), None, None, None) => Ok(LoadMatchingMode::Hourly),
+            (Some("mensual"), None, None, None) => Ok(LoadMatchingMode::MONTHLY_DEFAULT),
+            (Some("mensual"), Some(k), Some(n), None) => {
+                let (k, n): (f32, f32) = (k.parse()?, n.parse()?);
+                if !(k.is_finite() && n.is_finite() && k > 0.0 && n > 0.0) {
+                    return Err(EpbdError::WrongInput(format!(
+                        "Parámetros k y n del factor de coincidencia de cargas no válidos: {}, {}",
+                        k, n
+                    )));
+                }
+                Ok(LoadMatchingMode::Monthly { k, n })
+            }
+            _ => Err(EpbdError::ParseError(format!(
+                "Modo de factor de coincidencia de cargas desconocido `{}` (constante, mensual, mensual:k:n u horaria)",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for LoadMatchingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadMatchingMode::Constant => write!(f, "constante"),
+            LoadMatchingMode::Monthly { k, n } => write!(f, "mensual:{}:{}", k, n),
+            LoadMatchingMode::Hourly => write!(f, "horaria"),
+        }
+    }
+}
+
+/// Tratamiento de los consumos EPB negativos por servicio
+///
+/// Tras el reparto de consumos (p.e. de los consumos auxiliares entre servicios) pueden
+/// aparecer consumos EPB negativos en algunos pasos de cálculo. En todos los casos se
+/// registran como avisos en los resultados (`EnergyPerformance.warnings`).
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NegativeUsePolicy {
+    /// Se mantienen los valores negativos en el cálculo
+    #[default]
+    Allow,
+    /// Se sustituyen los valores negativos por cero
+    Truncate,
+    /// Se interrumpe el cálculo con un error
+    Error,
+}
+
+impl fmt::Display for NegativeUsePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            NegativeUsePolicy::Allow => "permitir",
+            NegativeUsePolicy::Truncate => "truncar",
+            NegativeUsePolicy::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl str::FromStr for NegativeUsePolicy {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<NegativeUsePolicy, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "permitir" | "allow" => Ok(NegativeUsePolicy::Allow),
+            "truncar" | "truncate" => Ok(NegativeUsePolicy::Truncate),
+            "error" => Ok(NegativeUsePolicy::Error),
+            _ => Err(EpbdError::ParseError(format!(
+                "Tratamiento de consumos negativos desconocido: {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn load_matching_mode() {
+        let monthly = LoadMatchingMode::MONTHLY_DEFAULT;
+        assert_eq!(monthly, true.into());
+        assert_eq!(LoadMatchingMode::Constant, false.into());
+        // x = 0.5 -> f_match = 0.6 (k = 1, n = 1)
+        let mensual = Some(Resolution::MENSUAL);
+        assert!((monthly.f_match(0.5, mensual) - 0.6).abs() < 1e-6);
+        assert_eq!(monthly.f_match(0.0, mensual), 1.0);
+        assert_eq!(monthly.f_match(0.5, Some(Resolution::HORARIA)), 1.0);
+        assert_eq!(LoadMatchingMode::Constant.f_match(0.5, mensual), 1.0);
+        // x = 0.5, k = 2, n = 1 -> f_match = 3.5 / 4.5
+        let custom = "mensual:2:1".parse::<LoadMatchingMode>().unwrap();
+        assert_eq!(custom, LoadMatchingMode::Monthly { k: 2.0, n: 1.0 });
+        assert!((custom.f_match(0.5, mensual) - 3.5 / 4.5).abs() < 1e-6);
+        assert_eq!("mensual:2:1", custom.to_string());
+        assert_eq!(
+            LoadMatchingMode::Hourly,
+            "HORARIA".parse::<LoadMatchingMode>().unwrap()
+        );
+        assert!("mensual:0:1".parse::<LoadMatchingMode>().is_err());
+        assert!("mensual:2".parse::<LoadMatchingMode>().is_err());
+        assert!("semanal".parse::<LoadMatchingMode>().is_err());
+    }
+}
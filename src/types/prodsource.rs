@@ -32,30 +32,51 @@ use serde::{Deserialize, Serialize};
 
 use super::Carrier;
 
-use crate::error::EpbdError;
+use crate::error::{unknown_value_error, EpbdError};
 
 /// Fuente de origen de la energía producida
 #[allow(non_camel_case_types)]
-#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum ProdSource {
-    /// On site generated electricity
+    /// On site generated electricity (photovoltaic and other onsite electricity sources)
     EL_INSITU,
+    /// On site generated electricity from (small) wind turbines
+    EL_EOLICA,
     /// On site co-generated electricity
     EL_COGEN,
     /// On site solar thermal
     TERMOSOLAR,
+    /// On site co-generated heat (useful heat from cogeneration)
+    TERMICA_COGEN,
     /// On site ambient heat
     EAMBIENTE,
+    /// On site ambient heat captured from the outdoor air (aerothermal)
+    EAMBIENTE_AIRE,
+    /// On site ambient heat captured from the ground (geothermal)
+    EAMBIENTE_GEO,
+    /// On site ambient heat captured from water bodies (hydrothermal)
+    EAMBIENTE_AGUA,
 }
 
 impl ProdSource {
     /// Priorities for electrical production sources
     pub fn get_priorities(carrier: Carrier) -> (bool, Vec<Self>) {
         match carrier {
-            Carrier::ELECTRICIDAD => (true, vec![Self::EL_INSITU, Self::EL_COGEN]),
+            Carrier::ELECTRICIDAD => (true, vec![Self::EL_INSITU, Self::EL_EOLICA, Self::EL_COGEN]),
             _ => (false, vec![]),
         }
     }
+
+    /// Is this a cogeneration source (EL_COGEN, TERMICA_COGEN)?
+    pub fn is_cogen(&self) -> bool {
+        matches!(self, Self::EL_COGEN | Self::TERMICA_COGEN)
+    }
+
+    /// Is this an onsite electricity source, excluding cogeneration (EL_INSITU, EL_EOLICA)?
+    pub fn is_onsite_el(&self) -> bool {
+        matches!(self, Self::EL_INSITU | Self::EL_EOLICA)
+    }
 }
 
 impl str::FromStr for ProdSource {
@@ -64,10 +85,29 @@ impl str::FromStr for ProdSource {
     fn from_str(s: &str) -> Result<ProdSource, Self::Err> {
         match s {
             "EL_INSITU" => Ok(ProdSource::EL_INSITU),
+            "EL_EOLICA" => Ok(ProdSource::EL_EOLICA),
             "EL_COGEN" => Ok(ProdSource::EL_COGEN),
             "TERMOSOLAR" => Ok(ProdSource::TERMOSOLAR),
+            "TERMICA_COGEN" => Ok(ProdSource::TERMICA_COGEN),
             "EAMBIENTE" => Ok(ProdSource::EAMBIENTE),
-            _ => Err(EpbdError::ParseError(s.into())),
+            "EAMBIENTE_AIRE" => Ok(ProdSource::EAMBIENTE_AIRE),
+            "EAMBIENTE_GEO" => Ok(ProdSource::EAMBIENTE_GEO),
+            "EAMBIENTE_AGUA" => Ok(ProdSource::EAMBIENTE_AGUA),
+            _ => Err(unknown_value_error(
+                "origen de la producción",
+                s,
+                &[
+                    "EL_INSITU",
+                    "EL_EOLICA",
+                    "EL_COGEN",
+                    "TERMOSOLAR",
+                    "TERMICA_COGEN",
+                    "EAMBIENTE",
+                    "EAMBIENTE_AIRE",
+                    "EAMBIENTE_GEO",
+                    "EAMBIENTE_AGUA",
+                ],
+            )),
         }
     }
 }
@@ -33,12 +33,12 @@ Energy performance type as a tuple to represent energy or emission values.
 use std::fmt;
 use std::ops::{Add, AddAssign, Mul, MulAssign, Sub, SubAssign};
 
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Serialize};
 
 use crate::error::EpbdError;
 
 /// Tupla que representa los factores de energía primaria renovable, no renovable y de emisión
-/// 
+///
 /// Energy pairs representing renewable and non renewable energy quantities or factors.
 #[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
 pub struct RenNrenCo2 {
@@ -164,7 +164,7 @@ impl Add for RenNrenCo2 {
     }
 }
 
-impl<'a> Add for &'a RenNrenCo2 {
+impl Add for &RenNrenCo2 {
     type Output = RenNrenCo2;
 
     fn add(self, other: &RenNrenCo2) -> RenNrenCo2 {
@@ -200,7 +200,7 @@ impl Sub for RenNrenCo2 {
     }
 }
 
-impl<'a> Sub for &'a RenNrenCo2 {
+impl Sub for &RenNrenCo2 {
     type Output = RenNrenCo2;
 
     fn sub(self, other: &RenNrenCo2) -> RenNrenCo2 {
@@ -238,7 +238,7 @@ impl Mul<f32> for RenNrenCo2 {
 }
 
 // rennren * &f32
-impl<'a> Mul<&'a f32> for RenNrenCo2 {
+impl Mul<&f32> for RenNrenCo2 {
     type Output = RenNrenCo2;
 
     fn mul(self, rhs: &f32) -> RenNrenCo2 {
@@ -251,7 +251,7 @@ impl<'a> Mul<&'a f32> for RenNrenCo2 {
 }
 
 // &rennren * f32
-impl<'a> Mul<f32> for &'a RenNrenCo2 {
+impl Mul<f32> for &RenNrenCo2 {
     type Output = RenNrenCo2;
 
     fn mul(self, rhs: f32) -> RenNrenCo2 {
@@ -277,7 +277,7 @@ impl Mul<RenNrenCo2> for f32 {
 }
 
 // &f32 * rennren
-impl<'a> Mul<RenNrenCo2> for &'a f32 {
+impl Mul<RenNrenCo2> for &f32 {
     type Output = RenNrenCo2;
 
     fn mul(self, rhs: RenNrenCo2) -> RenNrenCo2 {
@@ -290,7 +290,7 @@ impl<'a> Mul<RenNrenCo2> for &'a f32 {
 }
 
 // f32 * &rennren
-impl<'a> Mul<&'a RenNrenCo2> for f32 {
+impl Mul<&RenNrenCo2> for f32 {
     type Output = RenNrenCo2;
 
     fn mul(self, rhs: &RenNrenCo2) -> RenNrenCo2 {
@@ -316,7 +316,7 @@ impl MulAssign<f32> for RenNrenCo2 {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use pretty_assertions::{assert_eq};
+    use pretty_assertions::assert_eq;
 
     #[test]
     fn add() {
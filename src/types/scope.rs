@@ -0,0 +1,68 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+//! Ámbito de los consumos
+
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{unknown_value_error, EpbdError};
+
+/// Ámbito de un consumo en edificios de uso residencial colectivo
+///
+/// Se declara con la etiqueta `CTEEPBD_AMBITO=<AMBITO>` en el comentario del componente de consumo
+/// (p.e. `0, CONSUMO, ILU, ELECTRICIDAD, 10.0 # Portales CTEEPBD_AMBITO=COMUN`). Los consumos sin
+/// etiqueta son privativos.
+#[allow(non_camel_case_types)]
+#[derive(
+    Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Scope {
+    /// Consumo de las viviendas o locales (espacios privativos)
+    #[default]
+    PRIVATIVO,
+    /// Consumo de los servicios comunes (p.e. ascensores o iluminación de zonas comunes)
+    COMUN,
+}
+
+impl str::FromStr for Scope {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<Scope, Self::Err> {
+        match s {
+            "PRIVATIVO" => Ok(Scope::PRIVATIVO),
+            "COMUN" => Ok(Scope::COMUN),
+            _ => Err(unknown_value_error("ámbito", s, &["PRIVATIVO", "COMUN"])),
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
@@ -30,7 +30,7 @@ use std::str;
 
 use serde::{Deserialize, Serialize};
 
-use crate::error::EpbdError;
+use crate::error::{unknown_value_error, EpbdError};
 
 /// Uso al que está destinada la energía
 ///
@@ -41,7 +41,8 @@ use crate::error::EpbdError;
 /// no EPB en algunos casos (p.e. residencial privado) y en ese caso no deben indicarse los consumos
 /// como ILU sino como NEPB
 #[allow(non_camel_case_types)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[non_exhaustive]
 pub enum Service {
     /// DHW
     ACS,
@@ -55,6 +56,17 @@ pub enum Service {
     ILU,
     /// Generic non EPB use
     NEPB,
+    /// Swimming pool water heating (non EPB use)
+    /// It can receive surplus TERMOSOLAR production as energy exported to non EPB uses
+    PISCINA,
+    /// Electric vehicle charging
+    /// It is a non EPB use unless the component is labelled as EPB use with the CTEEPBD_VE_EPB tag
+    /// (or all of them with the CTE_VE_EPB metadata)
+    VE,
+    /// Appliances and plug loads (equipment)
+    /// It is a non EPB use unless the component is labelled as EPB use with the CTEEPBD_APP_EPB tag
+    /// (or all of them with the CTE_APP_EPB metadata)
+    APP,
     /// Energy feeding an electricity cogeneration system
     /// It accounts for energy used for electricity generation and excludes all
     /// energy that can attributed to thermal use
@@ -63,13 +75,16 @@ pub enum Service {
 
 impl Service {
     /// List of all available services
-    pub const SERVICES_ALL: [Service; 7] = [
+    pub const SERVICES_ALL: [Service; 10] = [
         Service::ACS,
         Service::CAL,
         Service::REF,
         Service::VEN,
         Service::ILU,
         Service::NEPB,
+        Service::PISCINA,
+        Service::VE,
+        Service::APP,
         Service::COGEN,
     ];
 
@@ -83,15 +98,22 @@ impl Service {
     ];
 
     /// Check if service is an EPB service
-    /// This doesn't include the NEPB and GEN services
+    /// This doesn't include the non EPB (NEPB, PISCINA, VE, APP) and COGEN services
     pub fn is_epb(&self) -> bool {
-        *self != Self::NEPB && *self != Self::COGEN
+        !self.is_nepb() && *self != Self::COGEN
     }
 
-    /// Check if service is a non EPB service
-    /// This doesn't include the GEN service
+    /// Check if service is a non EPB service (NEPB, PISCINA, VE, APP)
+    /// This doesn't include the COGEN service
     pub fn is_nepb(&self) -> bool {
-        *self == Self::NEPB
+        matches!(self, Self::NEPB | Self::PISCINA | Self::VE | Self::APP)
+    }
+
+    /// Check if service is a non EPB service that can be labelled as EPB use (VE, APP)
+    ///
+    /// Components are labelled with the CTEEPBD_<SERVICE>_EPB tag (e.g. CTEEPBD_VE_EPB)
+    pub fn is_optional_epb(&self) -> bool {
+        matches!(self, Self::VE | Self::APP)
     }
 
     /// Check if service is for electricity cogeneration
@@ -111,8 +133,17 @@ impl str::FromStr for Service {
             "VEN" => Ok(Service::VEN),
             "ILU" => Ok(Service::ILU),
             "NEPB" => Ok(Service::NEPB),
+            "PISCINA" => Ok(Service::PISCINA),
+            "VE" | "EVCHARGING" => Ok(Service::VE),
+            "APP" => Ok(Service::APP),
             "COGEN" => Ok(Service::COGEN),
-            _ => Err(EpbdError::ParseError(s.into())),
+            _ => Err(unknown_value_error(
+                "servicio",
+                s,
+                &[
+                    "ACS", "CAL", "REF", "VEN", "ILU", "NEPB", "PISCINA", "VE", "APP", "COGEN",
+                ],
+            )),
         }
     }
 }
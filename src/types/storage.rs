@@ -0,0 +1,235 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Almacenamiento eléctrico
+========================
+
+Componente de almacenamiento de energía eléctrica (baterías), que desplaza temporalmente
+la producción in situ de electricidad no consumida en el momento en que se produce.
+*/
+
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::EpbdError;
+use crate::types::{CType, Kwh, Tags};
+
+/// Componente de almacenamiento eléctrico (batería)
+///
+/// La batería se carga con el excedente de producción eléctrica in situ de cada paso de cálculo
+/// (producción no consumida por los usos del edificio) y se descarga para cubrir el déficit de los
+/// pasos posteriores, antes de calcular la energía exportada y suministrada. La energía descargada
+/// se contabiliza como liberada desde almacenamiento, igual que la producción marcada con la
+/// etiqueta `CTEEPBD_ALMACENAMIENTO`, y su parte exportada como exportación diferida (`A_RED_DIFERIDA`).
+///
+/// Se serializa como: `ALMACENAMIENTO, id, capacidad, rend_carga, rend_descarga, perdidas # comentario`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Storage {
+    /// System id
+    pub id: i32,
+    /// Usable storage capacity (>= 0)
+    pub capacity: Kwh,
+    /// Charge efficiency (0, 1]. Stored energy / energy drawn from production
+    pub eff_charge: f32,
+    /// Discharge efficiency (0, 1]. Delivered energy / energy drawn from storage
+    pub eff_discharge: f32,
+    /// Self discharge losses at each timestep [0, 1). Fraction of stored energy
+    pub losses: f32,
+    /// Descriptive comment string
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+}
+
+impl Storage {
+    /// Get structured tags (key=value pairs and flags) from the comment of this component
+    pub fn tags(&self) -> Tags {
+        Tags::from_comment(&self.comment)
+    }
+
+    /// Simula la carga y descarga de la batería a lo largo del periodo de cálculo
+    ///
+    /// La batería, inicialmente descargada, se carga en cada paso de cálculo con el excedente de
+    /// producción (`prod_t` - `use_t`) y se descarga para cubrir el déficit (`use_t` - `prod_t`),
+    /// dentro de los límites de su capacidad. En cada paso se pierde la fracción `losses` de la
+    /// energía almacenada.
+    ///
+    /// Devuelve la energía tomada de la producción para la carga y la entregada en la descarga,
+    /// para cada paso de cálculo. kWh
+    pub fn simulate(&self, prod_t: &[f32], use_t: &[f32]) -> (Vec<f32>, Vec<f32>) {
+        let mut soc = 0.0_f32;
+        let mut charge_t = Vec::with_capacity(prod_t.len());
+        let mut discharge_t = Vec::with_capacity(prod_t.len());
+        for (prod, used) in prod_t.iter().zip(use_t) {
+            soc *= 1.0 - self.losses;
+            let surplus = (prod - used).max(0.0);
+            let deficit = (used - prod).max(0.0);
+            let charge = surplus.min((self.capacity.0 - soc).max(0.0) / self.eff_charge);
+            soc += charge * self.eff_charge;
+            let discharge = deficit.min(soc * self.eff_discharge);
+            soc = (soc - discharge / self.eff_discharge).max(0.0);
+            charge_t.push(charge);
+            discharge_t.push(discharge);
+        }
+        (charge_t, discharge_t)
+    }
+}
+
+impl fmt::Display for Storage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        write!(
+            f,
+            "ALMACENAMIENTO, {}, {:.2}, {:.3}, {:.3}, {:.4}{}",
+            self.id, self.capacity.0, self.eff_charge, self.eff_discharge, self.losses, comment
+        )
+    }
+}
+
+impl str::FromStr for Storage {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<Storage, Self::Err> {
+        // Split comment from the rest of fields
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+
+        // Check type and length (ALMACENAMIENTO + id + capacidad + rend_carga + rend_descarga + perdidas)
+        if items.len() != 6 || !matches!(items[0].parse(), Ok(CType::ALMACENAMIENTO)) {
+            return Err(EpbdError::ParseError(format!(
+                "No se reconoce el formato como almacenamiento eléctrico: {}",
+                s
+            )));
+        };
+
+        let id = items[1].parse::<i32>().map_err(|_| {
+            EpbdError::ParseError(format!("Id erróneo en almacenamiento eléctrico: {}", s))
+        })?;
+
+        let values = items[2..]
+            .iter()
+            .map(|v| v.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|_| {
+                EpbdError::ParseError(format!("se esperaban valores numéricos en línea `{}`", s))
+            })?;
+        let (capacity, eff_charge, eff_discharge, losses) =
+            (values[0], values[1], values[2], values[3]);
+
+        if !(capacity >= 0.0 && capacity.is_finite()) {
+            return Err(EpbdError::ParseError(format!(
+                "la capacidad del almacenamiento no puede ser negativa `{}`",
+                s
+            )));
+        }
+        if !(eff_charge > 0.0 && eff_charge <= 1.0 && eff_discharge > 0.0 && eff_discharge <= 1.0) {
+            return Err(EpbdError::ParseError(format!(
+                "los rendimientos de carga y descarga deben estar en el intervalo (0, 1] `{}`",
+                s
+            )));
+        }
+        if !(0.0..1.0).contains(&losses) {
+            return Err(EpbdError::ParseError(format!(
+                "las pérdidas del almacenamiento deben estar en el intervalo [0, 1) `{}`",
+                s
+            )));
+        }
+
+        Ok(Storage {
+            id,
+            capacity: Kwh(capacity),
+            eff_charge,
+            eff_discharge,
+            losses,
+            comment,
+        })
+    }
+}
+
+// ========================== Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn component_storage() {
+        let component1 = Storage {
+            id: 1,
+            capacity: Kwh(10.0),
+            eff_charge: 0.95,
+            eff_discharge: 0.95,
+            losses: 0.001,
+            comment: "Batería de litio".into(),
+        };
+        let component1str = "ALMACENAMIENTO, 1, 10.00, 0.950, 0.950, 0.0010 # Batería de litio";
+        assert_eq!(component1.to_string(), component1str);
+
+        // roundtrip building from/to string
+        assert_eq!(
+            component1str.parse::<Storage>().unwrap().to_string(),
+            component1str
+        );
+
+        // wrong values
+        assert!("ALMACENAMIENTO, 1, 10.0, 0.95, 0.95"
+            .parse::<Storage>()
+            .is_err());
+        assert!("ALMACENAMIENTO, 1, -10.0, 0.95, 0.95, 0.0"
+            .parse::<Storage>()
+            .is_err());
+        assert!("ALMACENAMIENTO, 1, 10.0, 1.2, 0.95, 0.0"
+            .parse::<Storage>()
+            .is_err());
+        assert!("ALMACENAMIENTO, 1, 10.0, 0.95, 0.95, 1.0"
+            .parse::<Storage>()
+            .is_err());
+    }
+
+    #[test]
+    fn storage_simulation() {
+        let storage = Storage {
+            id: 0,
+            capacity: Kwh(8.0),
+            eff_charge: 0.8,
+            eff_discharge: 0.5,
+            losses: 0.0,
+            comment: String::new(),
+        };
+        // Excedente de 20 kWh (se cargan 10 kWh, 8 kWh almacenados) y déficit de 5 kWh (se descargan 4 kWh)
+        let (charge_t, discharge_t) = storage.simulate(&[20.0, 0.0, 0.0], &[0.0, 5.0, 5.0]);
+        assert_eq!(charge_t, vec![10.0, 0.0, 0.0]);
+        assert_eq!(discharge_t, vec![0.0, 4.0, 0.0]);
+    }
+}
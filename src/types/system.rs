@@ -0,0 +1,647 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Sistemas
+========
+
+Propiedades de los sistemas del edificio declaradas con componentes SISTEMA.
+
+Los sistemas se identifican por el id usado en los componentes de consumo, producción,
+auxiliares y salida.
+*/
+
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::EpbdError;
+use crate::types::{CType, Kw, Kwh, Service, Tags};
+
+/// Sistema (equipo) del edificio
+///
+/// Describe el tipo de equipo y su potencia nominal. Los rendimientos y la potencia pico del sistema se
+/// declaran con sus propios componentes SISTEMA (ver `SystemEfficiency` y `SystemPeakPower`), usando el mismo id.
+///
+/// Se serializa como: `SISTEMA, id, EQUIPO, tipo, potencia # comentario`, donde la potencia es opcional.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct System {
+    /// System id
+    pub id: i32,
+    /// Equipment type (p.e. CALDERA, BDC, FV)
+    pub kind: String,
+    /// Nominal power (>0)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power: Option<Kw>,
+    /// Descriptive comment string
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+}
+
+impl System {
+    /// Get structured tags (key=value pairs and flags) from the comment of this component
+    pub fn tags(&self) -> Tags {
+        Tags::from_comment(&self.comment)
+    }
+}
+
+impl fmt::Display for System {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let power = match self.power {
+            Some(power) => format!(", {:.3}", power.0),
+            None => "".to_owned(),
+        };
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        write!(
+            f,
+            "SISTEMA, {}, EQUIPO, {}{}{}",
+            self.id, self.kind, power, comment
+        )
+    }
+}
+
+impl str::FromStr for System {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<System, Self::Err> {
+        // Split comment from the rest of fields
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+
+        // Check type and length (SISTEMA + id + EQUIPO + tipo [+ potencia])
+        if !(items.len() == 4 || items.len() == 5)
+            || !matches!(items[0].parse(), Ok(CType::SISTEMA))
+            || items[2] != "EQUIPO"
+        {
+            return Err(EpbdError::ParseError(format!(
+                "No se reconoce el formato como equipo de un sistema: {}",
+                s
+            )));
+        };
+
+        let id = items[1].parse::<i32>().map_err(|_| {
+            EpbdError::ParseError(format!("Id erróneo en equipo de un sistema: {}", s))
+        })?;
+
+        let kind = items[3].to_string();
+        if kind.is_empty() {
+            return Err(EpbdError::ParseError(format!(
+                "falta el tipo de equipo del sistema `{}`",
+                s
+            )));
+        }
+
+        let power = match items.get(4) {
+            Some(value) => {
+                let value = value.parse::<f32>().map_err(|_| {
+                    EpbdError::ParseError(format!("se esperaba un valor numérico en línea `{}`", s))
+                })?;
+                if !(value > 0.0 && value.is_finite()) {
+                    return Err(EpbdError::ParseError(format!(
+                        "la potencia de un sistema debe ser positiva `{}`",
+                        s
+                    )));
+                }
+                Some(Kw(value))
+            }
+            None => None,
+        };
+
+        Ok(System {
+            id,
+            kind,
+            power,
+            comment,
+        })
+    }
+}
+
+/// Rendimiento medio estacional de un sistema para un servicio
+///
+/// Permite generar automáticamente la energía entregada (SALIDA) por el sistema a partir de
+/// sus consumos (ver `Components::normalize`).
+///
+/// El rendimiento se refiere a la energía consumida por el sistema para el servicio, excluida
+/// la energía ambiente (EAMBIENTE), de modo que para una bomba de calor es su SCOP.
+///
+/// Se serializa como: `SISTEMA, id, RENDIMIENTO, servicio, valor # comentario`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemEfficiency {
+    /// System id
+    pub id: i32,
+    /// EPB service
+    pub service: Service,
+    /// Seasonal efficiency (>0). E_out / E_in
+    pub value: f32,
+    /// Descriptive comment string
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+}
+
+impl SystemEfficiency {
+    /// Get structured tags (key=value pairs and flags) from the comment of this component
+    pub fn tags(&self) -> Tags {
+        Tags::from_comment(&self.comment)
+    }
+}
+
+impl fmt::Display for SystemEfficiency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        write!(
+            f,
+            "SISTEMA, {}, RENDIMIENTO, {}, {:.3}{}",
+            self.id, self.service, self.value, comment
+        )
+    }
+}
+
+impl str::FromStr for SystemEfficiency {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<SystemEfficiency, Self::Err> {
+        // Split comment from the rest of fields
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+
+        // Check type and length (SISTEMA + id + RENDIMIENTO + servicio + valor)
+        if items.len() != 5
+            || !matches!(items[0].parse(), Ok(CType::SISTEMA))
+            || items[2] != "RENDIMIENTO"
+        {
+            return Err(EpbdError::ParseError(format!(
+                "No se reconoce el formato como rendimiento de un sistema: {}",
+                s
+            )));
+        };
+
+        let id = items[1].parse::<i32>().map_err(|_| {
+            EpbdError::ParseError(format!("Id erróneo en rendimiento de un sistema: {}", s))
+        })?;
+
+        let service: Service = items[3].parse()?;
+        if !service.is_epb() {
+            return Err(EpbdError::ParseError(format!(
+                "rendimiento definido para un uso no EPB `{}`",
+                s
+            )));
+        }
+
+        let value = items[4].parse::<f32>().map_err(|_| {
+            EpbdError::ParseError(format!("se esperaba un valor numérico en línea `{}`", s))
+        })?;
+        if !(value > 0.0 && value.is_finite()) {
+            return Err(EpbdError::ParseError(format!(
+                "el rendimiento de un sistema debe ser positivo `{}`",
+                s
+            )));
+        }
+
+        Ok(SystemEfficiency {
+            id,
+            service,
+            value,
+            comment,
+        })
+    }
+}
+
+/// Curva de rendimiento a carga parcial de un sistema para un servicio
+///
+/// Define el rendimiento del sistema en función de su fracción de carga (cociente entre la
+/// energía entregada en el paso de cálculo y la que entregaría a potencia nominal durante todo
+/// el paso), mediante pares carga:rendimiento, con cargas crecientes. Entre los puntos declarados
+/// se interpola linealmente y fuera de ellos se usa el rendimiento del punto extremo más cercano.
+///
+/// Al generar la energía entregada (SALIDA) del sistema (ver `Components::normalize`) prevalece
+/// sobre el rendimiento medio estacional y necesita la potencia nominal del equipo del sistema.
+///
+/// Se serializa como: `SISTEMA, id, CURVA_RENDIMIENTO, servicio, carga1:rend1, carga2:rend2... # comentario`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SystemEfficiencyCurve {
+    /// System id
+    pub id: i32,
+    /// EPB service
+    pub service: Service,
+    /// Part load ratio (>=0, increasing) and efficiency (>0) pairs. E_out / E_in
+    pub points: Vec<(f32, f32)>,
+    /// Descriptive comment string
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+}
+
+impl SystemEfficiencyCurve {
+    /// Rendimiento para la fracción de carga indicada
+    pub fn efficiency_at(&self, load: f32) -> f32 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if load <= first.0 {
+            return first.1;
+        }
+        if load >= last.0 {
+            return last.1;
+        }
+        self.points
+            .windows(2)
+            .find(|w| load <= w[1].0)
+            .map(|w| {
+                let ((l0, e0), (l1, e1)) = (w[0], w[1]);
+                e0 + (e1 - e0) * (load - l0) / (l1 - l0)
+            })
+            .unwrap_or(last.1)
+    }
+
+    /// Energía entregada por el sistema a partir de la energía consumida en un paso de cálculo
+    ///
+    /// `capacity` es la energía que entregaría el sistema a potencia nominal durante el paso
+    /// (ver [`Kw::energy`]). La fracción de carga depende de la propia energía entregada,
+    /// E_out = η(E_out / capacity) · E_in, por lo que se resuelve por bisección.
+    pub fn output(&self, used: Kwh, capacity: Kwh) -> Kwh {
+        let (Kwh(used), Kwh(capacity)) = (used, capacity);
+        if used <= 0.0 || capacity <= 0.0 {
+            return Kwh(0.0);
+        }
+        let max_eff = self.points.iter().map(|p| p.1).fold(0.0, f32::max);
+        // residuo(carga) = carga · capacidad - η(carga) · E_in, negativo en 0 y positivo en hi
+        let residual = |load: f32| load * capacity - self.efficiency_at(load) * used;
+        let (mut lo, mut hi) = (0.0_f32, max_eff * used / capacity);
+        for _ in 0..60 {
+            let mid = 0.5 * (lo + hi);
+            if residual(mid) > 0.0 {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        let load = 0.5 * (lo + hi);
+        Kwh(self.efficiency_at(load) * used)
+    }
+
+    /// Get structured tags (key=value pairs and flags) from the comment of this component
+    pub fn tags(&self) -> Tags {
+        Tags::from_comment(&self.comment)
+    }
+}
+
+impl fmt::Display for SystemEfficiencyCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points = self
+            .points
+            .iter()
+            .map(|(load, eff)| format!("{:.2}:{:.3}", load, eff))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        write!(
+            f,
+            "SISTEMA, {}, CURVA_RENDIMIENTO, {}, {}{}",
+            self.id, self.service, points, comment
+        )
+    }
+}
+
+impl str::FromStr for SystemEfficiencyCurve {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<SystemEfficiencyCurve, Self::Err> {
+        // Split comment from the rest of fields
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+
+        // Check type and length (SISTEMA + id + CURVA_RENDIMIENTO + servicio + 2 o más puntos)
+        if items.len() < 6
+            || !matches!(items[0].parse(), Ok(CType::SISTEMA))
+            || items[2] != "CURVA_RENDIMIENTO"
+        {
+            return Err(EpbdError::ParseError(format!(
+                "No se reconoce el formato como curva de rendimiento de un sistema: {}",
+                s
+            )));
+        };
+
+        let id = items[1].parse::<i32>().map_err(|_| {
+            EpbdError::ParseError(format!(
+                "Id erróneo en curva de rendimiento de un sistema: {}",
+                s
+            ))
+        })?;
+
+        let service: Service = items[3].parse()?;
+        if !service.is_epb() {
+            return Err(EpbdError::ParseError(format!(
+                "curva de rendimiento definida para un uso no EPB `{}`",
+                s
+            )));
+        }
+
+        let points = items[4..]
+            .iter()
+            .map(|item| {
+                let pair = item.split_once(':').and_then(|(load, eff)| {
+                    let load = load.trim().parse::<f32>().ok()?;
+                    let eff = eff.trim().parse::<f32>().ok()?;
+                    Some((load, eff))
+                });
+                let (load, eff) = pair.ok_or_else(|| {
+                        EpbdError::ParseError(format!(
+                            "se esperaba un par carga:rendimiento en `{}` de la línea `{}`",
+                            item, s
+                        ))
+                    })?;
+                if !(load >= 0.0 && load.is_finite() && eff > 0.0 && eff.is_finite()) {
+                    return Err(EpbdError::ParseError(format!(
+                        "la carga de una curva de rendimiento no puede ser negativa y el rendimiento debe ser positivo `{}`",
+                        s
+                    )));
+                }
+                Ok((load, eff))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if points.windows(2).any(|w| w[1].0 <= w[0].0) {
+            return Err(EpbdError::ParseError(format!(
+                "las cargas de una curva de rendimiento deben ser crecientes `{}`",
+                s
+            )));
+        }
+
+        Ok(SystemEfficiencyCurve {
+            id,
+            service,
+            points,
+            comment,
+        })
+    }
+}
+
+/// Potencia pico de una instalación fotovoltaica
+///
+/// Permite obtener los indicadores de rendimiento de la instalación (producción específica, fracción exportada, etc).
+///
+/// Se serializa como: `SISTEMA, id, POTENCIA_PICO, valor # comentario`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemPeakPower {
+    /// System id
+    pub id: i32,
+    /// Peak power (>0). kWp
+    pub value: f32,
+    /// Descriptive comment string
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+}
+
+impl fmt::Display for SystemPeakPower {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let comment = if !self.comment.is_empty() {
+            format!(" # {}", self.comment)
+        } else {
+            "".to_owned()
+        };
+        write!(
+            f,
+            "SISTEMA, {}, POTENCIA_PICO, {:.3}{}",
+            self.id, self.value, comment
+        )
+    }
+}
+
+impl str::FromStr for SystemPeakPower {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<SystemPeakPower, Self::Err> {
+        // Split comment from the rest of fields
+        let items: Vec<&str> = s.trim().splitn(2, '#').map(str::trim).collect();
+        let comment = items.get(1).unwrap_or(&"").to_string();
+        let items: Vec<&str> = items[0].split(',').map(str::trim).collect();
+
+        // Check type and length (SISTEMA + id + POTENCIA_PICO + valor)
+        if items.len() != 4
+            || !matches!(items[0].parse(), Ok(CType::SISTEMA))
+            || items[2] != "POTENCIA_PICO"
+        {
+            return Err(EpbdError::ParseError(format!(
+                "No se reconoce el formato como potencia pico de un sistema: {}",
+                s
+            )));
+        };
+
+        let id = items[1].parse::<i32>().map_err(|_| {
+            EpbdError::ParseError(format!("Id erróneo en potencia pico de un sistema: {}", s))
+        })?;
+
+        let value = items[3].parse::<f32>().map_err(|_| {
+            EpbdError::ParseError(format!("se esperaba un valor numérico en línea `{}`", s))
+        })?;
+        if !(value > 0.0 && value.is_finite()) {
+            return Err(EpbdError::ParseError(format!(
+                "la potencia pico de un sistema debe ser positiva `{}`",
+                s
+            )));
+        }
+
+        Ok(SystemPeakPower { id, value, comment })
+    }
+}
+
+/// Rendimiento implícito de un sistema para un servicio superior al máximo plausible
+///
+/// El rendimiento implícito es el cociente entre la energía entregada o absorbida (SALIDA, en
+/// valor absoluto) y la energía consumida por el sistema para el servicio, excluida la energía
+/// ambiente y solar térmica (ver `Components::implausible_efficiencies`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImplausibleEfficiency {
+    /// System id
+    pub id: i32,
+    /// EPB service
+    pub service: Service,
+    /// Implied seasonal efficiency. E_out / E_in
+    pub value: f32,
+    /// Maximum plausible efficiency for this kind of system
+    pub limit: f32,
+}
+
+impl fmt::Display for ImplausibleEfficiency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rendimiento implícito del sistema {} para el servicio {} ({:.2}) superior al máximo plausible ({:.2})",
+            self.id, self.service, self.value, self.limit
+        )
+    }
+}
+
+// ========================== Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn component_system() {
+        let component1 = System {
+            id: 1,
+            kind: "BDC".into(),
+            power: Some(Kw(8.5)),
+            comment: "Bomba de calor aire-agua".into(),
+        };
+        let component1str = "SISTEMA, 1, EQUIPO, BDC, 8.500 # Bomba de calor aire-agua";
+        assert_eq!(component1.to_string(), component1str);
+        assert_eq!(component1str.parse::<System>().unwrap(), component1);
+
+        // optional power
+        let component2str = "SISTEMA, 2, EQUIPO, CALDERA";
+        assert_eq!(component2str.parse::<System>().unwrap().power, None);
+        assert_eq!(
+            component2str.parse::<System>().unwrap().to_string(),
+            component2str
+        );
+
+        // missing type, non positive power or wrong tag
+        assert!("SISTEMA, 1, EQUIPO, , 8.5".parse::<System>().is_err());
+        assert!("SISTEMA, 1, EQUIPO, BDC, 0.0".parse::<System>().is_err());
+        assert!("SISTEMA, 1, RENDIMIENTO, CAL, 3.0"
+            .parse::<System>()
+            .is_err());
+    }
+
+    #[test]
+    fn component_system_efficiency() {
+        let component1 = SystemEfficiency {
+            id: 2,
+            service: Service::CAL,
+            value: 3.2,
+            comment: "Bomba de calor".into(),
+        };
+        let component1str = "SISTEMA, 2, RENDIMIENTO, CAL, 3.200 # Bomba de calor";
+        assert_eq!(component1.to_string(), component1str);
+
+        // roundtrip building from/to string
+        assert_eq!(
+            component1str
+                .parse::<SystemEfficiency>()
+                .unwrap()
+                .to_string(),
+            component1str
+        );
+
+        // non EPB service, non positive value or missing fields
+        assert!("SISTEMA, 2, RENDIMIENTO, NEPB, 0.9"
+            .parse::<SystemEfficiency>()
+            .is_err());
+        assert!("SISTEMA, 2, RENDIMIENTO, CAL, 0.0"
+            .parse::<SystemEfficiency>()
+            .is_err());
+        assert!("SISTEMA, 2, RENDIMIENTO, CAL"
+            .parse::<SystemEfficiency>()
+            .is_err());
+    }
+
+    #[test]
+    fn component_system_efficiency_curve() {
+        let component1str =
+            "SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.10:2.500, 0.50:3.500, 1.00:3.000 # Bomba de calor";
+        let component1 = component1str.parse::<SystemEfficiencyCurve>().unwrap();
+        assert_eq!(component1.points, vec![(0.1, 2.5), (0.5, 3.5), (1.0, 3.0)]);
+        assert_eq!(component1.to_string(), component1str);
+
+        // interpolación lineal entre puntos y valores extremos fuera de ellos
+        assert_eq!(component1.efficiency_at(0.0), 2.5);
+        assert_eq!(component1.efficiency_at(0.3), 3.0);
+        assert_eq!(component1.efficiency_at(0.75), 3.25);
+        assert_eq!(component1.efficiency_at(1.5), 3.0);
+
+        // E_out = η(E_out / capacidad) · E_in
+        let Kwh(out) = component1.output(Kwh(10.0), Kw(12.5).energy(8.0));
+        assert!((out - component1.efficiency_at(out / 100.0) * 10.0).abs() < 1e-3);
+        assert_eq!(format!("{:.2}", out), "30.00");
+        assert_eq!(component1.output(Kwh(0.0), Kwh(100.0)), Kwh(0.0));
+
+        // non EPB service, single point, non increasing loads or wrong pairs
+        assert!("SISTEMA, 1, CURVA_RENDIMIENTO, NEPB, 0.1:2.5, 1.0:3.0"
+            .parse::<SystemEfficiencyCurve>()
+            .is_err());
+        assert!("SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 1.0:3.0"
+            .parse::<SystemEfficiencyCurve>()
+            .is_err());
+        assert!("SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.5:2.5, 0.5:3.0"
+            .parse::<SystemEfficiencyCurve>()
+            .is_err());
+        assert!("SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.5:0.0, 1.0:3.0"
+            .parse::<SystemEfficiencyCurve>()
+            .is_err());
+        assert!("SISTEMA, 1, CURVA_RENDIMIENTO, CAL, 0.5, 1.0:3.0"
+            .parse::<SystemEfficiencyCurve>()
+            .is_err());
+    }
+
+    #[test]
+    fn component_system_peak_power() {
+        let component1 = SystemPeakPower {
+            id: 3,
+            value: 4.5,
+            comment: "Instalación FV cubierta".into(),
+        };
+        let component1str = "SISTEMA, 3, POTENCIA_PICO, 4.500 # Instalación FV cubierta";
+        assert_eq!(component1.to_string(), component1str);
+        assert_eq!(
+            component1str
+                .parse::<SystemPeakPower>()
+                .unwrap()
+                .to_string(),
+            component1str
+        );
+
+        // non positive value, wrong tag or extra fields
+        assert!("SISTEMA, 3, POTENCIA_PICO, 0.0"
+            .parse::<SystemPeakPower>()
+            .is_err());
+        assert!("SISTEMA, 3, RENDIMIENTO, 4.5"
+            .parse::<SystemPeakPower>()
+            .is_err());
+        assert!("SISTEMA, 3, POTENCIA_PICO, CAL, 4.5"
+            .parse::<SystemPeakPower>()
+            .is_err());
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Etiquetas estructuradas en comentarios
+======================================
+
+Pares `clave=valor` (y etiquetas sin valor) incluidos en el comentario de un componente.
+
+Permiten adjuntar metadatos a cada línea de componentes sin modificar su formato. Por ejemplo:
+
+`1, CONSUMO, CAL, GASNATURAL, 100.0 # Caldera equipo=CAL-01 rend=0.92 nombre="Caldera de condensación" CTEEPBD_AUX`
+
+da lugar a las etiquetas `equipo` = `CAL-01`, `rend` = `0.92`, `nombre` = `Caldera de condensación`
+y a la etiqueta sin valor `CTEEPBD_AUX`.
+*/
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Etiquetas estructuradas de un comentario (pares clave=valor y etiquetas sin valor)
+///
+/// Las claves admiten letras, dígitos y los caracteres `_`, `-` y `.`. Los valores terminan
+/// en el primer espacio salvo que se escriban entre comillas dobles. Las etiquetas sin valor
+/// solo se reconocen si están en mayúsculas e incluyen algún guion bajo (p.e. CTEEPBD_AUX), para no
+/// confundirlas con el texto libre.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tags(pub BTreeMap<String, String>);
+
+impl Tags {
+    /// Obtiene las etiquetas de un comentario
+    pub fn from_comment(comment: &str) -> Self {
+        let mut tags = BTreeMap::new();
+        let mut rest = comment.trim_start();
+        while !rest.is_empty() {
+            let token_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let token = &rest[..token_end];
+            match token.split_once('=') {
+                Some((key, value)) if is_valid_key(key) => {
+                    if let Some(quoted) = rest[key.len() + 1..].strip_prefix('"') {
+                        // Valor entre comillas: puede incluir espacios
+                        let value_end = quoted.find('"').unwrap_or(quoted.len());
+                        tags.insert(key.to_string(), quoted[..value_end].to_string());
+                        rest = quoted.get(value_end + 1..).unwrap_or("");
+                    } else {
+                        tags.insert(key.to_string(), value.to_string());
+                        rest = &rest[token_end..];
+                    }
+                }
+                None if is_valid_key(token)
+                    && token == token.to_uppercase()
+                    && token.contains('_') =>
+                {
+                    tags.insert(token.to_string(), String::new());
+                    rest = &rest[token_end..];
+                }
+                _ => rest = &rest[token_end..],
+            }
+            rest = rest.trim_start();
+        }
+        Tags(tags)
+    }
+
+    /// Valor de la etiqueta como cadena
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Valor numérico de la etiqueta (None si no existe o no es un número)
+    pub fn get_f32(&self, key: &str) -> Option<f32> {
+        self.get_str(key).and_then(|v| v.parse().ok())
+    }
+
+    /// Valor lógico de la etiqueta (true/false, si/no, 1/0)
+    ///
+    /// Las etiquetas sin valor se consideran verdaderas
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        match self.get_str(key)?.to_lowercase().as_str() {
+            "" | "true" | "si" | "sí" | "1" => Some(true),
+            "false" | "no" | "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Comprueba si existe la etiqueta (con o sin valor)
+    pub fn has(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    /// Comprueba si no hay etiquetas
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Comprueba que la clave solo contiene caracteres válidos
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn tags_from_comment() {
+        let tags = Tags::from_comment(
+            "Caldera equipo=CAL-01 rend=0.92 nombre=\"Caldera de condensación\" CTEEPBD_AUX activo=no",
+        );
+        assert_eq!(tags.0.len(), 5);
+        assert_eq!(tags.get_str("equipo"), Some("CAL-01"));
+        assert_eq!(tags.get_f32("rend"), Some(0.92));
+        assert_eq!(tags.get_str("nombre"), Some("Caldera de condensación"));
+        assert_eq!(tags.get_bool("CTEEPBD_AUX"), Some(true));
+        assert_eq!(tags.get_bool("activo"), Some(false));
+        assert!(!tags.has("Caldera"));
+        assert_eq!(tags.get_f32("equipo"), None);
+
+        // Comentarios sin etiquetas
+        assert!(Tags::from_comment("Energía entregada = 3 kWh").is_empty());
+        assert!(Tags::from_comment("").is_empty());
+    }
+}
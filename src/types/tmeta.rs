@@ -34,14 +34,14 @@ use std::fmt;
 use std::str;
 use std::str::FromStr;
 
-use serde::{Serialize, Deserialize};
+use serde::{Deserialize, Serialize};
 
 use crate::{error::EpbdError, types::RenNrenCo2};
 
 // ==================== Metadata types
 
 /// Metadatos de los componentes o de los factores de paso
-/// 
+///
 /// Metadata of components or weighting factors
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Meta {
@@ -130,6 +130,18 @@ pub trait MetaVec {
             .and_then(|v| f32::from_str(v.value.trim()).ok())
     }
 
+    /// Get (optional) metadata value by key as bool
+    ///
+    /// Admite los valores `true`, `1`, `si`, `sí` y `false`, `0`, `no` (sin distinguir mayúsculas)
+    fn get_meta_bool(&self, key: &str) -> Option<bool> {
+        self.get_meta(key)
+            .and_then(|v| match v.trim().to_lowercase().as_str() {
+                "true" | "1" | "si" | "sí" => Some(true),
+                "false" | "0" | "no" => Some(false),
+                _ => None,
+            })
+    }
+
     /// Get (optional) metadata value (f32, f32) by key as RenNrenCo2 struct
     fn get_meta_rennren(&self, key: &str) -> Option<RenNrenCo2> {
         self.get_metavec()
@@ -138,9 +150,8 @@ pub trait MetaVec {
             .and_then(|v| {
                 v.value
                     .parse::<RenNrenCo2>()
-                    .map_err(|e| {
+                    .inspect_err(|_e| {
                         eprintln!("No se puede transformar el metadato a RenNrenCo2: {:?}", v);
-                        e
                     })
                     .ok()
             })
@@ -163,7 +174,7 @@ pub trait MetaVec {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use pretty_assertions::{assert_eq};
+    use pretty_assertions::assert_eq;
 
     #[test]
     fn tmeta() {
@@ -177,4 +188,25 @@ mod tests {
         assert_eq!(format!("{}", meta2), metastr);
         assert_eq!(format!("{}", metastr.parse::<Meta>().unwrap()), metastr);
     }
+
+    #[test]
+    fn tmeta_bool() {
+        struct Metas(Vec<Meta>);
+        impl MetaVec for Metas {
+            fn get_metavec(&self) -> &Vec<Meta> {
+                &self.0
+            }
+            fn get_mut_metavec(&mut self) -> &mut Vec<Meta> {
+                &mut self.0
+            }
+        }
+        let mut metas = Metas(vec![]);
+        metas.set_meta("CTE_A", "true");
+        metas.set_meta("CTE_B", " No");
+        metas.set_meta("CTE_C", "quizá");
+        assert_eq!(metas.get_meta_bool("CTE_A"), Some(true));
+        assert_eq!(metas.get_meta_bool("CTE_B"), Some(false));
+        assert_eq!(metas.get_meta_bool("CTE_C"), None);
+        assert_eq!(metas.get_meta_bool("CTE_D"), None);
+    }
 }
@@ -0,0 +1,128 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+//! Tolerancias del cálculo
+
+use std::fmt;
+use std::str;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::EpbdError;
+
+/// Tolerancias numéricas usadas en el cálculo
+///
+/// Agrupa los umbrales con los que se decide si un valor se considera nulo.
+/// Los valores por defecto reproducen el comportamiento histórico, pero pueden
+/// reducirse, p.e., al trabajar con series horarias de valores pequeños, en las que
+/// los umbrales fijos pueden anular valores significativos.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tolerances {
+    /// Área de referencia mínima admisible (m²). Por defecto, 1e-3
+    pub arearef_min: f32,
+    /// Producción mínima en un paso de cálculo para repartirla proporcionalmente entre fuentes (kWh). Por defecto, 1e-3
+    ///
+    /// Por debajo de este valor la fracción de cada fuente en ese paso se toma como nula.
+    pub prod_min: f32,
+    /// Consumo anual residual de un vector, una vez descontados auxiliares y consumos excluidos, por debajo del cual se considera nulo (kWh). Por defecto, 0.01
+    pub residual_use_min: f32,
+    /// Desvío relativo máximo admisible entre la suma de las demandas de las zonas y la demanda del edificio. Por defecto, 0.01 (1%)
+    pub needs_zones_dev_max: f32,
+    /// Fracción máxima admisible de la demanda anual de un servicio no cubierta por sus sistemas (SALIDA). Por defecto, 0.05 (5%)
+    pub unmet_needs_max: f32,
+    /// Horas máximas admisibles con demanda no cubierta de un servicio, con datos horarios (h). Por defecto, 300
+    pub unmet_hours_max: f32,
+    /// Rendimiento implícito (SALIDA / consumo) máximo plausible de bombas de calor y sistemas eléctricos. Por defecto, 8.0
+    pub efficiency_max_hp: f32,
+    /// Rendimiento implícito (SALIDA / consumo) máximo plausible de sistemas de combustión y otros sistemas no eléctricos. Por defecto, 1.05
+    pub efficiency_max_comb: f32,
+    /// Valor absoluto por debajo del cual una magnitud se considera cero. Por defecto, `f32::EPSILON`
+    pub zero: f32,
+    /// Tratamiento de los componentes de energía duplicados. Por defecto, se suman (sin aviso)
+    pub duplicates: DuplicatePolicy,
+}
+
+impl Default for Tolerances {
+    fn default() -> Self {
+        Self {
+            arearef_min: 1e-3,
+            prod_min: 1e-3,
+            residual_use_min: 0.01,
+            needs_zones_dev_max: 0.01,
+            unmet_needs_max: 0.05,
+            unmet_hours_max: 300.0,
+            efficiency_max_hp: 8.0,
+            efficiency_max_comb: 1.05,
+            zero: f32::EPSILON,
+            duplicates: DuplicatePolicy::default(),
+        }
+    }
+}
+
+/// Tratamiento de los componentes de energía duplicados
+///
+/// Son duplicados los componentes de consumo, producción, auxiliares o de energía saliente
+/// con la misma representación como línea de componentes (mismo tipo, id, servicio, vector u
+/// origen, valores y comentario), que suelen provenir de errores de exportación de otras
+/// herramientas. Los duplicados detectados se registran en los resultados
+/// (`EnergyPerformance.duplicates`) salvo con el tratamiento por defecto.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DuplicatePolicy {
+    /// Se suman todos los componentes, sin aviso
+    #[default]
+    Sum,
+    /// Se suman todos los componentes y se avisa de los duplicados
+    Warn,
+    /// Se interrumpe el cálculo con un error
+    Error,
+}
+
+impl fmt::Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DuplicatePolicy::Sum => "sumar",
+            DuplicatePolicy::Warn => "avisar",
+            DuplicatePolicy::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl str::FromStr for DuplicatePolicy {
+    type Err = EpbdError;
+
+    fn from_str(s: &str) -> Result<DuplicatePolicy, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "sumar" | "sum" => Ok(DuplicatePolicy::Sum),
+            "avisar" | "warn" => Ok(DuplicatePolicy::Warn),
+            "error" => Ok(DuplicatePolicy::Error),
+            _ => Err(EpbdError::ParseError(format!(
+                "Tratamiento de componentes duplicados desconocido: {}",
+                s
+            ))),
+        }
+    }
+}
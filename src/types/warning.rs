@@ -0,0 +1,127 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Avisos del cálculo
+==================
+
+Avisos estructurados generados al preparar los datos (ver `Components::normalize` y `Factors::strip`)
+y al calcular el balance energético (ver `EnergyPerformance::warnings`).
+*/
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::Severity;
+
+/// Código de aviso, que identifica la causa del aviso
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum WarningCode {
+    /// Consumo EPB negativo
+    CONSUMO_NEGATIVO,
+    /// Componente duplicado
+    COMPONENTE_DUPLICADO,
+    /// Año incompleto de datos medidos, completado con valores estimados
+    ANNO_INCOMPLETO,
+    /// Suma de las demandas de las zonas distinta de la demanda del edificio
+    DEMANDA_ZONAS,
+    /// Rendimiento implícito de un sistema no plausible
+    RENDIMIENTO_NO_PLAUSIBLE,
+    /// Consumo de un servicio fuera de su temporada
+    CONSUMO_FUERA_TEMPORADA,
+    /// Demanda no cubierta por los sistemas
+    DEMANDA_NO_CUBIERTA,
+    /// Producción generada para equilibrar el consumo de energía in situ sin producción declarada
+    PRODUCCION_GENERADA,
+    /// Energía entregada (SALIDA) generada a partir del rendimiento declarado
+    SALIDA_GENERADA,
+    /// Consumo auxiliar repartido entre los servicios del sistema
+    AUX_REPARTIDO,
+    /// Factores de paso eliminados por no usarse en los componentes
+    FACTORES_ELIMINADOS,
+    /// Indicador adicional que no se ha podido calcular
+    INDICADOR_NO_CALCULADO,
+    /// Datos remotos tomados de la copia en caché al fallar la descarga
+    CACHE_USADA,
+    /// Datos remotos descargados que no se han podido guardar en la caché
+    CACHE_NO_GUARDADA,
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Aviso estructurado del cálculo
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Warning {
+    /// Código del aviso
+    pub code: WarningCode,
+    /// Gravedad del aviso
+    pub severity: Severity,
+    /// Descripción del aviso
+    pub message: String,
+    /// Componente afectado (p.e. "1, ELECTRICIDAD, CONSUMO, CAL"), si lo hay
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub component: Option<String>,
+}
+
+impl Warning {
+    /// Aviso con el código, gravedad y descripción indicados
+    pub fn new<T: Into<String>>(code: WarningCode, severity: Severity, message: T) -> Self {
+        Self {
+            code,
+            severity,
+            message: message.into(),
+            component: None,
+        }
+    }
+
+    /// Aviso de gravedad `Warning`
+    pub fn warn<T: Into<String>>(code: WarningCode, message: T) -> Self {
+        Self::new(code, Severity::Warning, message)
+    }
+
+    /// Aviso informativo, de gravedad `Info`
+    pub fn info<T: Into<String>>(code: WarningCode, message: T) -> Self {
+        Self::new(code, Severity::Info, message)
+    }
+
+    /// Indica el componente afectado
+    pub fn with_component<T: Into<String>>(mut self, component: T) -> Self {
+        self.component = Some(component.into());
+        self
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)
+    }
+}
@@ -61,6 +61,14 @@ pub fn vecvecsum<T: Float>(vec1: &[T], vec2: &[T]) -> Vec<T> {
     vec1.iter().zip(vec2.iter()).map(|(a, b)| *a + *b).collect()
 }
 
+/// Elementwise sum in place, accumulating vec2 into vec1: vec1[i] += vec2[i]
+///
+/// Avoids allocating a new vector when accumulating series (e.g. hourly series of 8760 values)
+pub fn vecvecsum_assign<T: Float>(vec1: &mut [T], vec2: &[T]) {
+    assert_eq!(vec1.len(), vec2.len());
+    vec1.iter_mut().zip(vec2.iter()).for_each(|(a, b)| *a = *a + *b);
+}
+
 /// Elementwise difference res[i] = vec1[i] - vec2[i]
 pub fn vecvecdif<T: Float>(vec1: &[T], vec2: &[T]) -> Vec<T> {
     assert_eq!(vec1.len(), vec2.len());
@@ -129,6 +137,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn vecops_vecvecsum_assign() {
+        let mut acc = vec![2.0, 1.0, 3.0];
+        vecvecsum_assign(&mut acc, &[2.0, 3.0, 1.0]);
+        assert_eq!(vec![4.0, 4.0, 4.0], acc);
+    }
+
     #[test]
     fn vecops_vecvecdif() {
         assert_eq!(
@@ -147,7 +162,7 @@ mod tests {
 
     #[test]
     fn vecops_veckmul() {
-        assert_eq!(vec![2.0, 4.0, 6.0], veckmul(&[1.0, 2.0, 3.0], 2.0));
+        assert_eq!(vec![2.0, 4.0, 6.0], veckmul([1.0, 2.0, 3.0], 2.0));
         assert_eq!(vec![2.0, 4.0, 6.0], veckmul(vec![1.0, 2.0, 3.0], 2.0));
     }
 
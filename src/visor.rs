@@ -0,0 +1,407 @@
+// Copyright (c) 2018-2023  Ministerio de Fomento
+//                          Instituto de Ciencias de la Construcción Eduardo Torroja (IETcc-CSIC)
+
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+
+// Author(s): Rafael Villar Burke <pachi@ietcc.csic.es>,
+//            Daniel Jiménez González <dani@ietcc.csic.es>,
+//            Marta Sorribes Gil <msorribes@ietcc.csic.es>
+
+/*!
+Conversión al formato del VisorEPBD
+===================================
+
+Conversión entre los componentes energéticos y la estructura JSON usada por la herramienta
+complementaria VisorEPBD, de modo que los archivos puedan intercambiarse entre ambas herramientas
+sin que diverjan sus modelos de datos.
+
+El formato del visor es un objeto JSON con los metadatos (`meta`), la lista de componentes
+energéticos y de demanda (`components`) y, en `other`, las líneas de componentes que no tienen
+representación propia en el visor (energía embebida y sistemas), en el formato de texto de los componentes:
+
+```json
+{
+  "meta": [{ "key": "CTE_AREAREF", "value": "100.0" }],
+  "components": [
+    { "id": 1, "type": "CONSUMO", "service": "CAL", "carrier": "ELECTRICIDAD", "values": [10.0, 5.0], "comment": "BdC" },
+    { "id": 0, "type": "PRODUCCION", "source": "EL_INSITU", "values": [2.0, 3.0] },
+    { "id": 1, "type": "AUX", "values": [1.0, 0.5] },
+    { "id": 1, "type": "SALIDA", "service": "CAL", "values": [30.0, 15.0] },
+    { "id": 0, "type": "DEMANDA", "service": "CAL", "values": [28.0, 14.0] }
+  ],
+  "other": ["SISTEMA, 1, RENDIMIENTO, CAL, 3.000"]
+}
+```
+*/
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    components::system_kind,
+    error::{EpbdError, Result},
+    types::{
+        CType, EAux, EOut, EProd, EUsed, Energy, HasValues, Meta, Needs, ProdSource, Service,
+        UserCarrierScope,
+    },
+    Components,
+};
+
+/// Datos de componentes en el formato del VisorEPBD
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VisorData {
+    /// Metadatos
+    #[serde(default)]
+    pub meta: Vec<VisorMeta>,
+    /// Componentes de energía y de demanda
+    #[serde(default)]
+    pub components: Vec<VisorComponent>,
+    /// Líneas de componentes sin representación propia en el visor (energía embebida y sistemas)
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub other: Vec<String>,
+}
+
+/// Metadato en el formato del VisorEPBD
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VisorMeta {
+    /// Clave
+    pub key: String,
+    /// Valor
+    pub value: String,
+}
+
+/// Componente en el formato del VisorEPBD
+///
+/// Según el tipo de componente (`type`) se usan los campos `carrier` (CONSUMO), `source` (PRODUCCION)
+/// y `service` (CONSUMO, SALIDA y DEMANDA).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VisorComponent {
+    /// Id del sistema (o de la zona, en las demandas)
+    #[serde(default)]
+    pub id: i32,
+    /// Tipo de componente (CONSUMO, PRODUCCION, AUX, SALIDA o DEMANDA)
+    #[serde(rename = "type")]
+    pub ctype: CType,
+    /// Servicio
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Vector energético consumido
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub carrier: Option<String>,
+    /// Origen de la energía producida
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Valores para cada paso de cálculo, kWh
+    pub values: Vec<f32>,
+    /// Comentario
+    #[serde(default)]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub comment: String,
+}
+
+impl VisorComponent {
+    fn new(id: i32, ctype: CType, values: &[f32], comment: &str) -> Self {
+        Self {
+            id,
+            ctype,
+            service: None,
+            carrier: None,
+            source: None,
+            values: values.to_vec(),
+            comment: comment.to_string(),
+        }
+    }
+
+    /// Valor obligatorio de un campo
+    fn required<'a>(&self, field: &'a Option<String>, name: &str) -> Result<&'a str> {
+        field.as_deref().ok_or_else(|| {
+            EpbdError::ParseError(format!(
+                "componente del visor de tipo {} sin campo `{}`",
+                self.ctype, name
+            ))
+        })
+    }
+}
+
+impl From<&Components> for VisorData {
+    fn from(components: &Components) -> Self {
+        let meta = components
+            .meta
+            .iter()
+            .map(|m| VisorMeta {
+                key: m.key.clone(),
+                value: m.value.clone(),
+            })
+            .collect();
+
+        let mut vcomponents: Vec<VisorComponent> = components
+            .data
+            .iter()
+            .map(|c| match c {
+                Energy::Used(e) => VisorComponent {
+                    service: Some(e.service.to_string()),
+                    carrier: Some(e.carrier.to_string()),
+                    ..VisorComponent::new(e.id, CType::CONSUMO, &e.values, &e.comment)
+                },
+                Energy::Prod(e) => VisorComponent {
+                    source: Some(e.source.to_string()),
+                    ..VisorComponent::new(e.id, CType::PRODUCCION, &e.values, &e.comment)
+                },
+                Energy::Aux(e) => VisorComponent {
+                    service: (e.service != Service::NEPB).then(|| e.service.to_string()),
+                    ..VisorComponent::new(e.id, CType::AUX, &e.values, &e.comment)
+                },
+                Energy::Out(e) => VisorComponent {
+                    service: Some(e.service.to_string()),
+                    ..VisorComponent::new(e.id, CType::SALIDA, &e.values, &e.comment)
+                },
+            })
+            .collect();
+
+        // Demandas del edificio (id = 0) y de las zonas
+        let needs = &components.needs;
+        let zone_needs = needs
+            .zones
+            .iter()
+            .map(|(id, zone)| (*id, [&zone.ACS, &zone.CAL, &zone.REF]));
+        for (id, [acs, cal, ref_]) in
+            std::iter::once((0, [&needs.ACS, &needs.CAL, &needs.REF])).chain(zone_needs)
+        {
+            for (service, values) in [
+                (Service::ACS, acs),
+                (Service::CAL, cal),
+                (Service::REF, ref_),
+            ] {
+                if let Some(values) = values {
+                    vcomponents.push(VisorComponent {
+                        service: Some(service.to_string()),
+                        ..VisorComponent::new(id, CType::DEMANDA, values, "")
+                    });
+                }
+            }
+        }
+
+        let other = components
+            .embodied
+            .iter()
+            .map(ToString::to_string)
+            .chain(components.systems.iter().map(ToString::to_string))
+            .chain(components.efficiencies.iter().map(ToString::to_string))
+            .chain(components.efficiency_curves.iter().map(ToString::to_string))
+            .chain(components.peak_powers.iter().map(ToString::to_string))
+            .chain(components.storages.iter().map(ToString::to_string))
+            .collect();
+
+        VisorData {
+            meta,
+            components: vcomponents,
+            other,
+        }
+    }
+}
+
+impl TryFrom<VisorData> for Components {
+    type Error = EpbdError;
+
+    fn try_from(visor: VisorData) -> Result<Self> {
+        let mut components = Components {
+            meta: visor
+                .meta
+                .into_iter()
+                .map(|m| Meta::new(m.key, m.value))
+                .collect(),
+            ..Default::default()
+        };
+        let _user_carriers = UserCarrierScope::from_meta(&components.meta)?;
+
+        for vc in &visor.components {
+            let (id, values, comment) = (vc.id, vc.values.clone(), vc.comment.clone());
+            match vc.ctype {
+                CType::CONSUMO => components.data.push(Energy::Used(EUsed {
+                    id,
+                    carrier: vc.required(&vc.carrier, "carrier")?.parse()?,
+                    service: vc.required(&vc.service, "service")?.parse()?,
+                    values,
+                    comment,
+                })),
+                CType::PRODUCCION => components.data.push(Energy::Prod(EProd {
+                    id,
+                    source: vc.required(&vc.source, "source")?.parse::<ProdSource>()?,
+                    values,
+                    comment,
+                })),
+                // Los consumos auxiliares sin servicio declarado se asignan a los servicios EPB al normalizar
+                CType::AUX => components.data.push(Energy::Aux(EAux {
+                    id,
+                    service: vc
+                        .service
+                        .as_deref()
+                        .map(str::parse)
+                        .transpose()?
+                        .unwrap_or(Service::NEPB),
+                    values,
+                    comment,
+                })),
+                CType::SALIDA => components.data.push(Energy::Out(EOut {
+                    id,
+                    service: vc.required(&vc.service, "service")?.parse()?,
+                    values,
+                    comment,
+                })),
+                CType::DEMANDA => components.needs.add(Needs {
+                    id,
+                    service: vc.required(&vc.service, "service")?.parse()?,
+                    values,
+                })?,
+                CType::EMBEBIDA | CType::SISTEMA | CType::ALMACENAMIENTO => {
+                    return Err(EpbdError::ParseError(format!(
+                        "componente del visor de tipo {} no admitido en `components`, debe incluirse en `other`",
+                        vc.ctype
+                    )))
+                }
+            }
+        }
+
+        // Líneas sin representación propia en el visor
+        for line in &visor.other {
+            let is_ctype = |ctype: CType| {
+                line.splitn(3, ',')
+                    .take(2)
+                    .any(|tag| tag.trim().parse::<CType>().ok() == Some(ctype))
+            };
+            if is_ctype(CType::EMBEBIDA) {
+                components.embodied.push(line.parse()?);
+            } else if is_ctype(CType::SISTEMA) && system_kind(line) == "POTENCIA_PICO" {
+                components.peak_powers.push(line.parse()?);
+            } else if is_ctype(CType::SISTEMA) && system_kind(line) == "CURVA_RENDIMIENTO" {
+                components.efficiency_curves.push(line.parse()?);
+            } else if is_ctype(CType::SISTEMA) && system_kind(line) == "EQUIPO" {
+                components.systems.push(line.parse()?);
+            } else if is_ctype(CType::SISTEMA) {
+                components.efficiencies.push(line.parse()?);
+            } else if is_ctype(CType::ALMACENAMIENTO) {
+                components.storages.push(line.parse()?);
+            } else {
+                return Err(EpbdError::ParseError(format!(
+                    "línea no admitida en `other` de los datos del visor: {}",
+                    line
+                )));
+            }
+        }
+
+        let num_steps = components.num_steps();
+        if components.data.iter().any(|c| c.num_steps() != num_steps) {
+            return Err(EpbdError::ParseError(
+                "Componentes con distinto número de pasos de cálculo".into(),
+            ));
+        }
+
+        components.normalize()
+    }
+}
+
+impl Components {
+    /// Convierte los componentes al formato JSON del VisorEPBD
+    pub fn to_visor_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(&VisorData::from(self)).map_err(|e| {
+            EpbdError::WrongInput(format!("Error al generar el JSON del visor: {}", e))
+        })
+    }
+
+    /// Obtiene los componentes a partir de datos en el formato JSON del VisorEPBD
+    ///
+    /// # Errors
+    ///
+    /// * JSON mal formado o con valores de vectores, servicios u orígenes desconocidos
+    /// * Componentes con distinto número de pasos de cálculo
+    pub fn from_visor_json(json: &str) -> Result<Self> {
+        let visor: VisorData = serde_json::from_str(json)
+            .map_err(|e| EpbdError::ParseError(format!("JSON del visor mal formado: {}", e)))?;
+        Components::try_from(visor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCOMPS: &str = "#META CTE_AREAREF: 100.0
+1, CONSUMO, CAL, ELECTRICIDAD, 10.00, 5.00 # BdC
+1, CONSUMO, CAL, EAMBIENTE, 20.00, 10.00 # BdC
+1, CONSUMO, ACS, GASNATURAL, 8.00, 8.00 # Caldera
+1, AUX, 1.00, 0.50
+1, SALIDA, CAL, 30.00, 15.00
+1, SALIDA, ACS, 7.00, 7.00
+0, PRODUCCION, EL_INSITU, 2.00, 3.00 # FV
+DEMANDA, CAL, 28.00, 14.00
+2, DEMANDA, CAL, 10.00, 5.00
+SISTEMA, 1, RENDIMIENTO, CAL, 3.000 # SCOP";
+
+    #[test]
+    fn visor_roundtrip() {
+        let comps = TCOMPS.parse::<Components>().unwrap();
+        let json = comps.to_visor_json().unwrap();
+        let visor: VisorData = serde_json::from_str(&json).unwrap();
+        assert_eq!(visor.meta[0].key, "CTE_AREAREF");
+        assert_eq!(
+            visor.other,
+            vec!["SISTEMA, 1, RENDIMIENTO, CAL, 3.000 # SCOP"]
+        );
+        assert!(visor
+            .components
+            .iter()
+            .any(|c| c.ctype == CType::PRODUCCION && c.source.as_deref() == Some("EL_INSITU")));
+        assert_eq!(
+            visor
+                .components
+                .iter()
+                .filter(|c| c.ctype == CType::DEMANDA)
+                .count(),
+            2
+        );
+
+        let comps2 = Components::from_visor_json(&json).unwrap();
+        assert_eq!(comps.to_string(), comps2.to_string());
+        assert_eq!(comps.needs.CAL, comps2.needs.CAL);
+        assert_eq!(comps.needs.zones[&2].CAL, comps2.needs.zones[&2].CAL);
+        assert_eq!(VisorData::from(&comps2), visor);
+    }
+
+    #[test]
+    fn visor_errors() {
+        // Consumo sin vector energético
+        let json =
+            r#"{"components": [{"id": 1, "type": "CONSUMO", "service": "CAL", "values": [1.0]}]}"#;
+        assert!(Components::from_visor_json(json).is_err());
+        // Vector desconocido
+        let json = r#"{"components": [{"id": 1, "type": "CONSUMO", "service": "CAL", "carrier": "URANIO", "values": [1.0]}]}"#;
+        assert!(Components::from_visor_json(json).is_err());
+        // Distinto número de pasos
+        let json = r#"{"components": [
+            {"id": 1, "type": "CONSUMO", "service": "CAL", "carrier": "GASNATURAL", "values": [1.0]},
+            {"id": 1, "type": "CONSUMO", "service": "ACS", "carrier": "GASNATURAL", "values": [1.0, 2.0]}
+        ]}"#;
+        assert!(Components::from_visor_json(json).is_err());
+        // JSON mal formado
+        assert!(Components::from_visor_json("{").is_err());
+    }
+}
@@ -39,7 +39,10 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     error::{EpbdError, Result},
-    types::{Carrier, Dest, Factor, Meta, MetaVec, RenNrenCo2, Source, Step},
+    types::{
+        Carrier, Dest, Factor, Meta, MetaVec, NetworkMix, RenNrenCo2, Source, Step,
+        UserCarrierScope, Warning, WarningCode,
+    },
     Components,
 };
 
@@ -54,6 +57,9 @@ pub struct Factors {
     pub wmeta: Vec<Meta>,
     /// Metadata
     pub wdata: Vec<Factor>,
+    /// Warnings about weighting factors removed on stripping
+    #[serde(skip)]
+    pub warnings: Vec<Warning>,
 }
 
 impl Factors {
@@ -64,17 +70,188 @@ impl Factors {
     /// * `dest` - match this energy destination (use)
     /// * `step` - match this calculation step
     pub fn find(&self, cr: Carrier, source: Source, dest: Dest, step: Step) -> Result<RenNrenCo2> {
+        self.find_factor(cr, source, dest, step)
+            .map(Factor::factors)
+    }
+
+    /// Find weighting factor, including its timestep values
+    ///
+    /// See [`Factors::find`]
+    pub fn find_factor(
+        &self,
+        cr: Carrier,
+        source: Source,
+        dest: Dest,
+        step: Step,
+    ) -> Result<&Factor> {
         self.wdata
             .iter()
             .find(|fp| {
                 fp.carrier == cr && fp.source == source && fp.dest == dest && fp.step == step
             })
-            .map(|fp| fp.factors())
             .ok_or_else(|| {
                 EpbdError::MissingFactor(format!("'{}, {}, {}, {}'", cr, source, dest, step))
             })
     }
 
+    /// Comprueba que las series de factores por paso de cálculo tienen `num_steps` valores
+    ///
+    /// # Errors
+    ///
+    /// * Factor de paso con serie de longitud distinta al número de pasos de cálculo
+    pub fn check_timestep_series(&self, num_steps: usize) -> Result<()> {
+        match self
+            .wdata
+            .iter()
+            .find(|f| !f.values_t.is_empty() && f.values_t.len() != num_steps)
+        {
+            Some(f) => Err(EpbdError::WrongInput(format!(
+                "El factor de paso '{}, {}, {}, {}' tiene {} valores por paso de cálculo y los componentes {} pasos",
+                f.carrier,
+                f.source,
+                f.dest,
+                f.step,
+                f.values_t.len(),
+                num_steps
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Combina los factores de paso con periodo de vigencia en factores con serie por paso de cálculo
+    ///
+    /// Los factores con periodo de vigencia (ver [`Factor::validity`]) de un mismo vector, fuente,
+    /// uso y paso se sustituyen por un único factor cuya serie por pasos toma, en cada paso de
+    /// cálculo, los valores del factor vigente. En los pasos no cubiertos por ningún periodo se usa el
+    /// factor sin periodo de vigencia del mismo vector, fuente, uso y paso, que debe existir.
+    ///
+    /// Los valores anuales del factor combinado son la media simple de los valores de todos los
+    /// pasos y no están ponderados con la energía de cada paso, por lo que solo son una referencia
+    /// informativa: el balance usa la serie por pasos y la fracción renovable de la demanda de ACS
+    /// pondera la serie con los consumos de ACS de cada paso. Otros cálculos que usan los valores
+    /// anuales (p.e. los factores de exportación de la cogeneración) son una aproximación.
+    ///
+    /// Si algún factor vigente declara su precio (`CTEEPBD_COSTE`), el factor combinado incluye
+    /// también la serie de precios por paso de cálculo (ver [`Factor::cost_t`]).
+    ///
+    /// Devuelve los factores con periodo de vigencia originales, para documentar el cálculo.
+    ///
+    /// # Errors
+    ///
+    /// * Periodo de vigencia mal formado o que supera el número de pasos de cálculo
+    /// * Periodos de vigencia solapados
+    /// * Pasos de cálculo sin factor vigente
+    pub fn apply_validity_periods(&mut self, num_steps: usize) -> Result<Vec<Factor>> {
+        let mut periods: Vec<(Factor, usize, usize)> = Vec::new();
+        let mut wdata = Vec::with_capacity(self.wdata.len());
+        for factor in self.wdata.drain(..) {
+            match factor.validity()? {
+                Some((first, last)) => {
+                    let last = last.min(num_steps);
+                    if first > last {
+                        return Err(EpbdError::WrongInput(format!(
+                            "El periodo de vigencia del factor de paso '{}, {}, {}, {}' comienza en el paso {} y los componentes tienen {} pasos",
+                            factor.carrier, factor.source, factor.dest, factor.step, first, num_steps
+                        )));
+                    }
+                    periods.push((factor, first, last));
+                }
+                None => wdata.push(factor),
+            }
+        }
+        self.wdata = wdata;
+        if periods.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let key = |f: &Factor| (f.carrier, f.source, f.dest, f.step);
+        let mut keys = Vec::new();
+        for (factor, _, _) in &periods {
+            if !keys.contains(&key(factor)) {
+                keys.push(key(factor));
+            }
+        }
+        for (carrier, source, dest, step) in keys {
+            let desc = format!("'{}, {}, {}, {}'", carrier, source, dest, step);
+            let base = self
+                .wdata
+                .iter()
+                .position(|f| key(f) == (carrier, source, dest, step));
+            let mut values_t: Vec<Option<(RenNrenCo2, Option<f32>)>> = vec![None; num_steps];
+            for (factor, first, last) in periods
+                .iter()
+                .filter(|(f, _, _)| key(f) == (carrier, source, dest, step))
+            {
+                for (i, value) in values_t.iter_mut().enumerate().take(*last).skip(first - 1) {
+                    if value.is_some() {
+                        return Err(EpbdError::WrongInput(format!(
+                            "Periodos de vigencia solapados en el paso {} para el factor de paso {}",
+                            i + 1,
+                            desc
+                        )));
+                    }
+                    *value = Some((factor.factors_at(i), factor.cost_at(i)));
+                }
+            }
+            let values_t = values_t
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    value
+                        .or_else(|| {
+                            base.map(|idx| {
+                                let factor = &self.wdata[idx];
+                                (factor.factors_at(i), factor.cost_at(i))
+                            })
+                        })
+                        .ok_or_else(|| {
+                            EpbdError::WrongInput(format!(
+                                "Sin factor de paso vigente en el paso {} para el factor de paso {}",
+                                i + 1,
+                                desc
+                            ))
+                        })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let (values_t, costs_t): (Vec<RenNrenCo2>, Vec<Option<f32>>) =
+                values_t.into_iter().unzip();
+            // Serie de precios solo si algún factor vigente tiene precio (los pasos sin precio tienen coste nulo)
+            let cost_t = if costs_t.iter().any(Option::is_some) {
+                costs_t.into_iter().map(Option::unwrap_or_default).collect()
+            } else {
+                Vec::new()
+            };
+            let annual = values_t
+                .iter()
+                .fold(RenNrenCo2::default(), |acc, v| acc + *v)
+                * (1.0 / num_steps as f32);
+            match base {
+                Some(idx) => {
+                    let factor = &mut self.wdata[idx];
+                    factor.ren = annual.ren;
+                    factor.nren = annual.nren;
+                    factor.co2 = annual.co2;
+                    factor.values_t = values_t;
+                    factor.cost_t = cost_t;
+                }
+                None => {
+                    let mut factor = Factor::new(
+                        carrier,
+                        source,
+                        dest,
+                        step,
+                        annual,
+                        "Factor de paso con periodos de vigencia (valores anuales medios)",
+                    );
+                    factor.values_t = values_t;
+                    factor.cost_t = cost_t;
+                    self.wdata.push(factor);
+                }
+            }
+        }
+        Ok(periods.into_iter().map(|(factor, _, _)| factor).collect())
+    }
+
     /// Actualiza o establece valores de un factor de paso
     pub fn update_wfactor(
         &mut self,
@@ -115,9 +292,31 @@ impl Factors {
         };
     }
 
-    /// Actualiza los factores definibles por el usuario (cogen_to_grid, cogen_to_nepb, red1 y red2)
+    /// Asegura que existe un factor de paso, copiando los valores (y serie por pasos) de otro factor.
+    /// Si ya existe no se modifica
+    fn ensure_wfactor_from(
+        &mut self,
+        carrier: Carrier,
+        source: Source,
+        dest: Dest,
+        step: Step,
+        from: &Factor,
+        comment: &str,
+    ) {
+        if !self
+            .wdata
+            .iter()
+            .any(|f| f.carrier == carrier && f.source == source && f.step == step && f.dest == dest)
+        {
+            let mut factor = Factor::new(carrier, source, dest, step, from.factors(), comment);
+            factor.values_t = from.values_t.clone();
+            self.wdata.push(factor);
+        };
+    }
+
+    /// Actualiza los factores definibles por el usuario (red1, red2 e hidrogeno)
     pub fn set_user_wfactors(mut self, user: UserWF<Option<RenNrenCo2>>) -> Self {
-        use Carrier::{RED1, RED2};
+        use Carrier::{HIDROGENO, RED1, RED2};
         use Dest::SUMINISTRO;
         use Source::RED;
         use Step::A;
@@ -125,6 +324,14 @@ impl Factors {
         [
             (RED1, RED, SUMINISTRO, A, user.red1, "Factor de usuario"),
             (RED2, RED, SUMINISTRO, A, user.red2, "Factor de usuario"),
+            (
+                HIDROGENO,
+                RED,
+                SUMINISTRO,
+                A,
+                user.hidrogeno,
+                "Factor de usuario",
+            ),
         ]
         .iter()
         .for_each(|(carrier, source, dest, step, uservalue, comment)| {
@@ -136,14 +343,102 @@ impl Factors {
         self
     }
 
+    /// Mixes de generación de redes de distrito registrados en los metadatos `CTE_MIX_<RED>`
+    ///
+    /// # Errors
+    ///
+    /// * Vector desconocido o que no es una red de distrito
+    /// * Mix con formato incorrecto o cuyos porcentajes no suman 100%
+    pub fn network_mixes(&self) -> Result<Vec<NetworkMix>> {
+        let _user_carriers = UserCarrierScope::from_meta(&self.wmeta)?;
+        self.wmeta
+            .iter()
+            .filter_map(|m| {
+                m.key
+                    .strip_prefix("CTE_MIX_")
+                    .map(|carrier| (carrier, m.value.as_str()))
+            })
+            .map(|(carrier, value)| NetworkMix::new(carrier.parse()?, value))
+            .collect()
+    }
+
+    /// Registra el mix de generación de una red de distrito en los metadatos y actualiza sus factores de suministro
+    ///
+    /// Los factores de suministro de la red (RED, SUMINISTRO, A) se sustituyen por los agregados del mix.
+    pub fn set_network_mix(&mut self, mix: &NetworkMix) {
+        self.set_meta(&mix.meta_key(), &mix.to_string());
+        self.apply_network_mix(mix);
+    }
+
+    /// Sustituye los factores de suministro de una red de distrito por los agregados de su mix de generación
+    fn apply_network_mix(&mut self, mix: &NetworkMix) {
+        let comment = format!(
+            "Recursos usados para suministrar energía de la red de distrito, según su mix de generación ({})",
+            mix.meta_key()
+        );
+        self.wdata.retain(|f| {
+            !(f.carrier == mix.carrier
+                && f.source == Source::RED
+                && f.dest == Dest::SUMINISTRO
+                && f.step == Step::A)
+        });
+        self.wdata.push(Factor::new(
+            mix.carrier,
+            Source::RED,
+            Dest::SUMINISTRO,
+            Step::A,
+            mix.factors(),
+            &comment,
+        ));
+    }
+
+    /// Sustituye o añade factores de paso definidos por el usuario (p.e. con metadatos `CTE_FP` de los componentes)
+    ///
+    /// Se eliminan los factores de exportación (A_RED, A_NEPB) que se deducen de los factores de suministro
+    /// modificados, de modo que, al normalizar de nuevo los factores de paso, se obtengan a partir de los
+    /// nuevos valores. Los factores de exportación definidos explícitamente en `factors` se mantienen.
+    pub fn set_user_factors(mut self, factors: &[Factor]) -> Self {
+        for uf in factors
+            .iter()
+            .filter(|f| f.dest == Dest::SUMINISTRO && f.step == Step::A)
+        {
+            self.wdata.retain(|f| {
+                !(f.carrier == uf.carrier
+                    && (f.dest == Dest::A_RED || f.dest == Dest::A_NEPB)
+                    && ((f.step == Step::A && f.source == uf.source)
+                        || (f.step == Step::B && uf.source == Source::RED)))
+            });
+        }
+        for f in factors {
+            let comment = if f.comment.is_empty() {
+                "Factor de usuario"
+            } else {
+                &f.comment
+            };
+            self.update_wfactor(f.carrier, f.source, f.dest, f.step, f.factors(), comment);
+            if let Some(factor) = self.wdata.iter_mut().find(|uf| {
+                uf.carrier == f.carrier
+                    && uf.source == f.source
+                    && uf.dest == f.dest
+                    && uf.step == f.step
+            }) {
+                factor.values_t = f.values_t.clone();
+            }
+        }
+        self
+    }
+
     /// Asegura consistencia de factores de paso definidos y deduce algunos de los que falten.
     ///
     /// Realiza los siguientes pasos:
-    /// - asegura definición de factores de producción in situ
+    /// - asegura definición de factores de producción in situ (los de EAMBIENTE y TERMOSOLAR,
+    ///   por defecto 1.0, 0.0, 0.0, se respetan si los define el usuario)
     /// - asegura definición de factores desde la red para todos los vectores
     /// - asegura que factor paso A para suministro de cogeneración es 0.0 (se considera en vector sourceal)
     /// - asegura definición de factores a la red para vectores con exportación
-    /// - asegura que existe RED1 | RED2 en suministro
+    /// - asegura que existe RED1 | RED2 | HIDROGENO | RED_CALOR_RESIDUAL en suministro
+    /// - obtiene los factores de suministro de las redes de distrito con mix de generación declarado
+    ///   (metadatos `CTE_MIX_<RED>`), que prevalecen sobre los definidos directamente
     ///
     /// TODO: refactorizar moviendo algunos métodos a trait CteFactorsExt
     pub fn normalize(mut self, defaults: &UserWF<RenNrenCo2>) -> Result<Self> {
@@ -156,7 +451,7 @@ impl Factors {
         let wf_carriers: HashSet<_> = self.wdata.iter().map(|f| f.carrier).collect();
 
         // Asegura que existe EAMBIENTE, INSITU, SUMINISTRO, A, 1.0, 0.0
-        self.update_wfactor(
+        self.ensure_wfactor(
             EAMBIENTE,
             INSITU,
             SUMINISTRO,
@@ -166,7 +461,7 @@ impl Factors {
         );
 
         // Asegura que existe EAMBIENTE, RED, SUMINISTRO, A, 1.0, 0.0
-        self.update_wfactor(
+        self.ensure_wfactor(
             EAMBIENTE,
             RED,
             SUMINISTRO,
@@ -176,7 +471,7 @@ impl Factors {
         );
 
         // Asegura que existe TERMOSOLAR, INSITU, SUMINISTRO, A, 1.0, 0.0
-        self.update_wfactor(
+        self.ensure_wfactor(
             TERMOSOLAR,
             INSITU,
             SUMINISTRO,
@@ -186,7 +481,7 @@ impl Factors {
         );
 
         // Asegura que existe TERMOSOLAR, RED, SUMINISTRO, A, 1.0, 0.0
-        self.update_wfactor(
+        self.ensure_wfactor(
             TERMOSOLAR,
             RED,
             SUMINISTRO,
@@ -250,25 +545,25 @@ impl Factors {
                         && f.step == Step::A
                         && f.dest == Dest::SUMINISTRO
                 })
-                .map(|f| f.factors());
+                .cloned();
 
-            if let Some(factors) = fp_a_input {
+            if let Some(factor) = fp_a_input {
                 // VECTOR, SRC, A_RED, A, ren, nren === VECTOR, SRC, SUMINISTRO, A, ren, nren
-                self.ensure_wfactor(
+                self.ensure_wfactor_from(
                     *c,
                     *s,
                     A_RED,
                     A,
-                    factors,
+                    &factor,
                     "Recursos usados para producir la energía exportada a la red",
                 );
                 // VECTOR, SRC, A_NEPB, A, ren, nren == VECTOR, SRC, SUMINISTRO, A, ren, nren
-                self.ensure_wfactor(
+                self.ensure_wfactor_from(
                     *c,
                     *s,
                     A_NEPB,
                     A,
-                    factors,
+                    &factor,
                     "Recursos usados para producir la energía exportada a usos no EPB",
                 );
             }
@@ -283,25 +578,25 @@ impl Factors {
                         && f.dest == Dest::SUMINISTRO
                         && f.step == Step::A
                 })
-                .map(|f| f.factors());
+                .cloned();
 
-            if let Some(factors) = fp_a_red_input {
+            if let Some(factor) = fp_a_red_input {
                 // VECTOR, SRC, A_RED, B, ren, nren == VECTOR, RED, SUMINISTRO, A, ren, nren
-                self.ensure_wfactor(
+                self.ensure_wfactor_from(
                     *c,
                     *s,
                     A_RED,
                     B,
-                    factors,
+                    &factor,
                     "Recursos ahorrados a la red por la energía producida in situ y exportada a la red",
                 );
                 // VECTOR, SRC, A_NEPB, B, ren, nren == VECTOR, RED, SUMINISTRO, A, ren, nren
-                self.ensure_wfactor(
+                self.ensure_wfactor_from(
                     *c,
                     *s,
                     A_NEPB,
                     B,
-                    factors,
+                    &factor,
                     "Recursos ahorrados a la red por la energía producida in situ y exportada a usos no EPB",
                 );
             } else {
@@ -309,6 +604,11 @@ impl Factors {
             }
         }
 
+        // Factores de suministro de las redes de distrito con mix de generación declarado
+        for mix in self.network_mixes()? {
+            self.apply_network_mix(&mix);
+        }
+
         // Asegura que existe RED1 | RED2, RED, SUMINISTRO, A, ren, nren
         self.ensure_wfactor(
             RED1,
@@ -328,6 +628,27 @@ impl Factors {
             "Recursos usados para suministrar energía de la red de distrito 2 (definible por el usuario)",
         );
 
+        // Asegura que existe HIDROGENO, RED, SUMINISTRO, A, ren, nren
+        self.ensure_wfactor(
+            HIDROGENO,
+            RED,
+            SUMINISTRO,
+            A,
+            defaults.hidrogeno,
+            "Recursos usados para suministrar hidrógeno (definible por el usuario)",
+        );
+
+        // Asegura que existe RED_CALOR_RESIDUAL, RED, SUMINISTRO, A, 0.0, 0.0, 0.0
+        // Los recursos usados para generar el calor residual se imputan al proceso que lo produce
+        self.ensure_wfactor(
+            RED_CALOR_RESIDUAL,
+            RED,
+            SUMINISTRO,
+            A,
+            RenNrenCo2::new(0.0, 0.0, 0.0),
+            "Recursos usados para suministrar calor residual recuperado (definible por el usuario)",
+        );
+
         Ok(self)
     }
 
@@ -338,10 +659,25 @@ impl Factors {
     ///  - de cogeneración si no hay cogeneración
     ///  - para exportación a usos no EPB si no se aparecen en los datos
     ///  - de electricidad in situ si no aparece una producción de ese tipo
+    ///
+    /// Los factores eliminados se registran como avisos informativos en `warnings`.
     pub fn strip(mut self, components: &Components) -> Self {
+        let key = |f: &Factor| (f.carrier, f.source, f.dest, f.step);
+        let keys_orig: Vec<_> = self.wdata.iter().map(key).collect();
         let wf_carriers = components.available_carriers();
         // Mantenemos factores para todos los vectores usados
-        self.wdata.retain(|f| wf_carriers.contains(&f.carrier));
+        // y los de EAMBIENTE si se usa alguno de sus subtipos, ya que estos toman sus factores por defecto
+        // y los de RED1 si hay calor cogenerado, ya que este toma por defecto sus factores de suministro de red
+        let has_ambient = wf_carriers.iter().any(Carrier::is_ambient);
+        // y los de la red de calor a la que se exporta el calor producido in situ, si se declara
+        let has_cogen_heat = wf_carriers.contains(&Carrier::TERMICA_COGEN);
+        let heat_network = components.heat_export_network().ok().flatten();
+        self.wdata.retain(|f| {
+            wf_carriers.contains(&f.carrier)
+                || (f.carrier == Carrier::EAMBIENTE && has_ambient)
+                || (f.carrier == Carrier::RED1 && has_cogen_heat)
+                || Some(f.carrier) == heat_network
+        });
         // Mantenemos factores para cogeneración sólo si hay cogeneración
         let has_cogen = components.data.iter().any(|c| c.is_cogen_pr());
         self.wdata
@@ -357,6 +693,18 @@ impl Factors {
         self.wdata.retain(|f| {
             f.carrier != Carrier::ELECTRICIDAD || f.source != Source::INSITU || has_elec_onsite
         });
+        let keys_kept: Vec<_> = self.wdata.iter().map(key).collect();
+        for (carrier, source, dest, step) in
+            keys_orig.into_iter().filter(|k| !keys_kept.contains(k))
+        {
+            self.warnings.push(
+                Warning::info(
+                    WarningCode::FACTORES_ELIMINADOS,
+                    "factor de paso eliminado por no usarse en los componentes",
+                )
+                .with_component(format!("{}, {}, {}, {}", carrier, source, dest, step)),
+            );
+        }
         self
     }
 
@@ -387,20 +735,147 @@ impl Factors {
                 ))
             }
         }
-        let mut factors = Factors { wmeta, wdata };
+        let mut factors = Factors {
+            wmeta,
+            wdata,
+            ..Default::default()
+        };
         factors.set_meta("CTE_PERIMETRO", "NEARBY");
         factors
     }
 
+    /// Incorpora los factores de paso de los subtipos de vectores usados en los componentes
+    ///
+    /// Los vectores EAMBIENTE_AIRE, EAMBIENTE_GEO y EAMBIENTE_AGUA toman, por defecto, los factores de paso
+    /// definidos para EAMBIENTE, el vector TERMOSOLAR_PROXIMO los de suministro desde la red de TERMOSOLAR
+    /// y el vector TERMICA_COGEN los de suministro de la red de distrito RED1 (calor no cubierto por la cogeneración).
+    /// Los factores definidos expresamente para un subtipo no se modifican.
+    pub(crate) fn add_subtype_factors(&mut self, components: &Components) {
+        for carrier in components.available_carriers() {
+            let parent = match carrier {
+                Carrier::EAMBIENTE_AIRE | Carrier::EAMBIENTE_GEO | Carrier::EAMBIENTE_AGUA => {
+                    Carrier::EAMBIENTE
+                }
+                Carrier::TERMOSOLAR_PROXIMO => Carrier::TERMOSOLAR,
+                Carrier::TERMICA_COGEN => Carrier::RED1,
+                _ => continue,
+            };
+            let parent_factors: Vec<Factor> = self
+                .wdata
+                .iter()
+                .filter(|f| {
+                    f.carrier == parent
+                        && (parent != Carrier::TERMOSOLAR || f.source == Source::RED)
+                })
+                .cloned()
+                .collect();
+            for f in &parent_factors {
+                self.ensure_wfactor(carrier, f.source, f.dest, f.step, f.factors(), &f.comment);
+            }
+        }
+    }
+
+    /// Factores de suministro de EAMBIENTE y TERMOSOLAR distintos de los predefinidos (ren = 1.0, nren = 0.0, co2 = 0.0)
+    ///
+    /// Permite advertir de valores definidos por el usuario, p.e. para análisis de sensibilidad.
+    pub fn user_defined_renewable_factors(&self) -> Vec<&Factor> {
+        let default = RenNrenCo2::new(1.0, 0.0, 0.0);
+        self.wdata
+            .iter()
+            .filter(|f| {
+                (f.carrier == Carrier::EAMBIENTE || f.carrier == Carrier::TERMOSOLAR)
+                    && f.dest == Dest::SUMINISTRO
+                    && f.step == Step::A
+                    && f.factors() != default
+            })
+            .collect()
+    }
+
+    /// Comprueba que están definidos todos los factores de paso necesarios para el balance de los componentes
+    ///
+    /// Se tienen en cuenta los factores que se obtienen durante el cálculo (subtipos de vectores
+    /// y electricidad cogenerada), de modo que la comprobación puede hacerse antes de calcular,
+    /// p.e. tras la simplificación de los factores de paso.
+    ///
+    /// # Errors
+    ///
+    /// * Falta algún factor de paso necesario, con la lista de todos los factores no encontrados
+    pub fn check_required(&self, components: &Components) -> Result<()> {
+        let mut wfactors = self.clone();
+        wfactors.add_subtype_factors(components);
+        wfactors.add_cgn_factors(components)?;
+        wfactors.add_heat_export_factors(components)?;
+        wfactors.check_required_derived(components)
+    }
+
+    /// Comprueba los factores necesarios para el balance una vez incorporados los factores derivados
+    pub(crate) fn check_required_derived(&self, components: &Components) -> Result<()> {
+        let missing = self.missing_factors(components);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let list: Vec<String> = missing
+            .iter()
+            .map(|(cr, source, dest, step)| format!("'{}, {}, {}, {}'", cr, source, dest, step))
+            .collect();
+        Err(EpbdError::MissingFactor(format!(
+            "factores necesarios para el balance no definidos ({}): {}",
+            list.len(),
+            list.join(", ")
+        )))
+    }
+
+    /// Lista de factores de paso (vector, fuente, destino, paso) necesarios para el balance de los componentes y no definidos
+    ///
+    /// Son necesarios:
+    /// - los factores de suministro de red de todos los vectores consumidos o producidos
+    /// - los factores de suministro in situ de los vectores con producción in situ
+    /// - los factores de exportación a la red (y a usos no EPB, si existen) de la electricidad producida, por fuente
+    pub fn missing_factors(&self, components: &Components) -> Vec<(Carrier, Source, Dest, Step)> {
+        let has_nepb = components.data.iter().any(|c| c.is_nepb_use());
+        let mut required = Vec::new();
+        for carrier in components.available_carriers() {
+            required.push((carrier, Source::RED, Dest::SUMINISTRO, Step::A));
+            let mut sources: Vec<Source> = Vec::new();
+            for c in &components.data {
+                if c.is_generated() && c.has_carrier(carrier) && !sources.contains(&c.source()) {
+                    sources.push(c.source());
+                }
+            }
+            if sources.contains(&Source::INSITU) {
+                required.push((carrier, Source::INSITU, Dest::SUMINISTRO, Step::A));
+            }
+            if carrier != Carrier::ELECTRICIDAD {
+                continue;
+            }
+            for source in sources {
+                for step in [Step::A, Step::B] {
+                    required.push((carrier, source, Dest::A_RED, step));
+                    if has_nepb {
+                        required.push((carrier, source, Dest::A_NEPB, step));
+                    }
+                }
+            }
+        }
+        required
+            .into_iter()
+            .filter(|(cr, source, dest, step)| self.find(*cr, *source, *dest, *step).is_err())
+            .collect()
+    }
+
     /// Incorpora factores de exportación de la electricidad cogenerada
     ///
     /// Devuelve a definición de los factores de exportación a NEPB y RED (paso A y paso B),
     /// para la electricidad cogenerada, que pueden ser agregados directamente a Factors.wdata
     ///
+    /// Incorpora también los factores del calor cogenerado (ver `add_cgn_heat_factors`)
+    ///
     /// También devuelve las estructuras de datos de los factores de exportación paso A
     /// para el perímetro distante y próximo, para facilitar el cálculo de RER_nrb
     #[allow(non_snake_case)]
     pub(crate) fn add_cgn_factors(&mut self, components: &Components) -> Result<()> {
+        self.add_cgn_heat_factors(components)?;
+
         let fP_exp_el_cgn_A = match self.compute_cgn_exp_fP_A(components, false)? {
             Some(fP) => fP,
             _ => return Ok(()),
@@ -468,6 +943,97 @@ impl Factors {
         Ok(())
     }
 
+    /// Incorpora los factores del calor cogenerado (TERMICA_COGEN), si no se han definido expresamente
+    ///
+    /// El impacto de la cogeneración se tiene en cuenta en su consumo, de modo que los recursos usados
+    /// para suministrar y exportar el calor cogenerado (paso A) son nulos. Los recursos ahorrados a la red
+    /// por el calor exportado (paso B) son los de suministro de red del vector TERMICA_COGEN.
+    fn add_cgn_heat_factors(&mut self, components: &Components) -> Result<()> {
+        if !components
+            .available_carriers()
+            .contains(&Carrier::TERMICA_COGEN)
+        {
+            return Ok(());
+        }
+        let carrier = Carrier::TERMICA_COGEN;
+        self.ensure_wfactor(
+            carrier,
+            Source::COGEN,
+            Dest::SUMINISTRO,
+            Step::A,
+            RenNrenCo2::default(),
+            "Recursos usados para suministrar calor cogenerado (el impacto de la cogeneración se tiene en cuenta en su consumo)",
+        );
+        for dest in [Dest::A_RED, Dest::A_NEPB] {
+            self.ensure_wfactor(
+                carrier,
+                Source::COGEN,
+                dest,
+                Step::A,
+                RenNrenCo2::default(),
+                "Recursos usados para producir el calor cogenerado exportado (el impacto de la cogeneración se tiene en cuenta en su consumo)",
+            );
+        }
+        let fp_grid_a = self
+            .find_factor(carrier, Source::RED, Dest::SUMINISTRO, Step::A)?
+            .clone();
+        for dest in [Dest::A_RED, Dest::A_NEPB] {
+            self.ensure_wfactor_from(
+                carrier,
+                Source::COGEN,
+                dest,
+                Step::B,
+                &fp_grid_a,
+                "Recursos ahorrados a la red por el calor cogenerado exportado",
+            );
+        }
+        Ok(())
+    }
+
+    /// Incorpora los factores de exportación del calor producido in situ a una red de calor
+    ///
+    /// Cuando se declara la red de calor de exportación (metadato `CTE_EXPORTACION_CALOR`), los recursos
+    /// ahorrados a la red por el calor solar (TERMOSOLAR) y cogenerado (TERMICA_COGEN) exportado
+    /// (`A_RED`, paso B) son los de suministro de la red de calor (`RED1` o `RED2`, `RED`, `SUMINISTRO`, paso A).
+    /// Los recursos usados para producir el calor exportado (paso A) no cambian.
+    pub(crate) fn add_heat_export_factors(&mut self, components: &Components) -> Result<()> {
+        let Some(network) = components.heat_export_network()? else {
+            return Ok(());
+        };
+        let fp_network = self
+            .find_factor(network, Source::RED, Dest::SUMINISTRO, Step::A)?
+            .clone();
+        let produced = components.available_carriers();
+        for (carrier, source) in [
+            (Carrier::TERMOSOLAR, Source::INSITU),
+            (Carrier::TERMICA_COGEN, Source::COGEN),
+        ] {
+            if !produced.contains(&carrier) {
+                continue;
+            }
+            self.wdata.retain(|f| {
+                !(f.carrier == carrier
+                    && f.source == source
+                    && f.dest == Dest::A_RED
+                    && f.step == Step::B)
+            });
+            let mut factor = Factor::new(
+                carrier,
+                source,
+                Dest::A_RED,
+                Step::B,
+                fp_network.factors(),
+                format!(
+                    "Recursos ahorrados a la red de calor {} por el calor exportado",
+                    network
+                ),
+            );
+            factor.values_t = fp_network.values_t.clone();
+            self.wdata.push(factor);
+        }
+        Ok(())
+    }
+
     #[allow(non_snake_case)]
     pub(crate) fn compute_cgn_exp_fP_A(
         &self,
@@ -478,9 +1044,9 @@ impl Factors {
         // Calcula f_exp_pr_el_A_chp_t = suma (E_in_t * f_in_t) / pr_el_chp_t
         use crate::types::Energy;
         use crate::vecops::vecvecsum;
-        use std::collections::HashMap;
+        use std::collections::BTreeMap;
         let mut prod = Vec::<f32>::new();
-        let mut used = HashMap::<Carrier, Vec<f32>>::new();
+        let mut used = BTreeMap::<Carrier, Vec<f32>>::new();
         for c in &components.data {
             match c {
                 Energy::Used(e) if c.is_cogen_use() => {
@@ -488,7 +1054,7 @@ impl Factors {
                         .and_modify(|item| *item = vecvecsum(item, &e.values))
                         .or_insert_with(|| e.values.clone());
                 }
-                Energy::Prod(e) if c.is_cogen_pr() => {
+                Energy::Prod(e) if c.is_cogen_pr() && c.is_electricity() => {
                     prod = if prod.is_empty() {
                         e.values.clone()
                     } else {
@@ -508,7 +1074,7 @@ impl Factors {
         };
         let mut fP_exp_el_cgn_A = RenNrenCo2::default();
         for (carrier, used_t) in used {
-            if only_nearby && !carrier.is_nearby() {
+            if only_nearby && !components.is_nearby_carrier(carrier) {
                 continue;
             }
             let fP_A_cr = self.find(carrier, Source::RED, Dest::SUMINISTRO, Step::A)?;
@@ -523,6 +1089,188 @@ impl Factors {
     }
 }
 
+// --------------------------- Transactions
+
+/// Cambio pendiente en una transacción de edición de factores de paso
+#[derive(Debug, Clone)]
+enum FactorChange {
+    /// Actualiza o establece un factor de paso
+    Update(Factor),
+    /// Añade un factor de paso si no existe
+    Ensure(Factor),
+    /// Elimina un factor de paso existente
+    Remove(Carrier, Source, Dest, Step),
+}
+
+/// Transacción de edición de factores de paso
+///
+/// Agrupa cambios en una tabla de factores de paso (p.e. en una interfaz que edita los factores
+/// de forma interactiva) y solo los aplica al confirmarla con [`FactorsTransaction::commit`], tras
+/// validar los valores y normalizar la tabla completa. Si la validación falla, o la transacción
+/// se descarta (con [`FactorsTransaction::rollback`] o al salir de ámbito), la tabla no cambia.
+///
+/// ```
+/// use cteepbd::{types::{Carrier, Dest, RenNrenCo2, Source, Step}, Factors, UserWF};
+///
+/// let mut factors: Factors = "ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331"
+///     .parse()
+///     .unwrap();
+/// let defaults = UserWF::cte_defaults();
+/// let mut tx = factors.transaction();
+/// tx.update(
+///     Carrier::GASNATURAL,
+///     Source::RED,
+///     Dest::SUMINISTRO,
+///     Step::A,
+///     RenNrenCo2::new(0.005, 1.190, 0.252),
+///     "Gas natural",
+/// );
+/// tx.commit(&defaults).unwrap();
+/// assert!(factors
+///     .find(Carrier::GASNATURAL, Source::RED, Dest::SUMINISTRO, Step::A)
+///     .is_ok());
+/// ```
+#[must_use = "los cambios no se aplican hasta confirmar la transacción con `commit`"]
+#[derive(Debug)]
+pub struct FactorsTransaction<'a> {
+    factors: &'a mut Factors,
+    changes: Vec<FactorChange>,
+}
+
+impl Factors {
+    /// Inicia una transacción de edición de la tabla de factores de paso
+    pub fn transaction(&mut self) -> FactorsTransaction<'_> {
+        FactorsTransaction {
+            factors: self,
+            changes: Vec::new(),
+        }
+    }
+}
+
+impl FactorsTransaction<'_> {
+    /// Actualiza o establece valores de un factor de paso
+    pub fn update(
+        &mut self,
+        carrier: Carrier,
+        source: Source,
+        dest: Dest,
+        step: Step,
+        values: RenNrenCo2,
+        comment: &str,
+    ) -> &mut Self {
+        self.changes.push(FactorChange::Update(Factor::new(
+            carrier, source, dest, step, values, comment,
+        )));
+        self
+    }
+
+    /// Asegura que existe un factor de paso. Si ya existe no se modifica
+    pub fn ensure(
+        &mut self,
+        carrier: Carrier,
+        source: Source,
+        dest: Dest,
+        step: Step,
+        values: RenNrenCo2,
+        comment: &str,
+    ) -> &mut Self {
+        self.changes.push(FactorChange::Ensure(Factor::new(
+            carrier, source, dest, step, values, comment,
+        )));
+        self
+    }
+
+    /// Elimina un factor de paso, que debe existir al confirmar la transacción
+    pub fn remove(
+        &mut self,
+        carrier: Carrier,
+        source: Source,
+        dest: Dest,
+        step: Step,
+    ) -> &mut Self {
+        self.changes
+            .push(FactorChange::Remove(carrier, source, dest, step));
+        self
+    }
+
+    /// Número de cambios pendientes
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+
+    /// Indica si no hay cambios pendientes
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Descarta los cambios pendientes sin modificar la tabla de factores de paso
+    pub fn rollback(self) {}
+
+    /// Aplica los cambios pendientes, valida y normaliza la tabla de factores de paso
+    ///
+    /// Los cambios se aplican, en orden, sobre una copia de la tabla, que solo sustituye a la
+    /// original si todos los cambios son válidos y la normalización (ver [`Factors::normalize`])
+    /// tiene éxito.
+    ///
+    /// * `defaults` - valores por defecto de los factores de paso de las redes de distrito
+    ///
+    /// # Errors
+    ///
+    /// * Valores de factores de paso no finitos o con energía primaria negativa
+    /// * Eliminación de un factor de paso inexistente
+    /// * Series por paso de cálculo de distinta longitud
+    /// * Errores de normalización (p.e. factores de red no definidos para algún vector)
+    pub fn commit(self, defaults: &UserWF<RenNrenCo2>) -> Result<()> {
+        let mut factors = self.factors.clone();
+        for change in &self.changes {
+            match change {
+                FactorChange::Update(f) | FactorChange::Ensure(f) => {
+                    let v = f.factors();
+                    if !(v.ren.is_finite() && v.nren.is_finite() && v.co2.is_finite())
+                        || v.ren < 0.0
+                        || v.nren < 0.0
+                    {
+                        return Err(EpbdError::WrongInput(format!(
+                            "Valores no válidos del factor de paso '{}, {}, {}, {}': {}, {}, {}",
+                            f.carrier, f.source, f.dest, f.step, v.ren, v.nren, v.co2
+                        )));
+                    }
+                    if matches!(change, FactorChange::Update(_)) {
+                        factors.update_wfactor(f.carrier, f.source, f.dest, f.step, v, &f.comment);
+                    } else {
+                        factors.ensure_wfactor(f.carrier, f.source, f.dest, f.step, v, &f.comment);
+                    }
+                }
+                FactorChange::Remove(carrier, source, dest, step) => {
+                    let len = factors.wdata.len();
+                    factors.wdata.retain(|f| {
+                        !(f.carrier == *carrier
+                            && f.source == *source
+                            && f.dest == *dest
+                            && f.step == *step)
+                    });
+                    if factors.wdata.len() == len {
+                        return Err(EpbdError::MissingFactor(format!(
+                            "'{}, {}, {}, {}'",
+                            carrier, source, dest, step
+                        )));
+                    }
+                }
+            }
+        }
+        if let Some(num_steps) = factors
+            .wdata
+            .iter()
+            .map(|f| f.values_t.len())
+            .find(|len| *len > 0)
+        {
+            factors.check_timestep_series(num_steps)?;
+        }
+        *self.factors = factors.normalize(defaults)?;
+        Ok(())
+    }
+}
+
 impl MetaVec for Factors {
     fn get_metavec(&self) -> &Vec<Meta> {
         &self.wmeta
@@ -554,25 +1302,44 @@ impl str::FromStr for Factors {
     type Err = EpbdError;
 
     fn from_str(s: &str) -> std::result::Result<Factors, Self::Err> {
-        let lines: Vec<&str> = s.lines().map(str::trim).collect();
+        // Líneas numeradas (desde 1) para informar de la posición de los errores
+        let lines: Vec<(usize, &str)> = s
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .map(|(i, l)| (i + 1, l))
+            .collect();
         let metalines = lines
             .iter()
-            .filter(|l| l.starts_with("#META") || l.starts_with("#CTE_"));
+            .filter(|(_, l)| l.starts_with("#META") || l.starts_with("#CTE_"));
         let datalines = lines
             .iter()
-            .filter(|l| !(l.starts_with('#') || l.starts_with("vector,") || l.is_empty()));
+            .filter(|(_, l)| !(l.starts_with('#') || l.starts_with("vector,") || l.is_empty()));
         let wmeta = metalines
-            .map(|e| e.parse())
+            .map(|(n, e)| e.parse().map_err(|err: EpbdError| err.at_line(*n)))
             .collect::<Result<Vec<Meta>>>()?;
+        let _user_carriers = UserCarrierScope::from_meta(&wmeta)?;
         let wdata = datalines
-            .map(|e| e.parse())
+            .map(|(n, e)| e.parse().map_err(|err: EpbdError| err.at_line(*n)))
             .collect::<Result<Vec<Factor>>>()?;
-        Ok(Factors { wmeta, wdata })
+        Ok(Factors {
+            wmeta,
+            wdata,
+            ..Default::default()
+        })
     }
 }
 
 /// Estructura para definir valores por defecto y valores de usuario
+///
+/// Fuera de este crate se construye a partir de un valor inicial y los métodos `with_*`, de modo
+/// que la incorporación de nuevos factores de usuario no rompe el código existente:
+///
+/// - valores de usuario (`UserWF<Option<RenNrenCo2>>`): `UserWF::default()`, sin factores definidos
+/// - valores por defecto (`UserWF<RenNrenCo2>`): [`UserWF::cte_defaults`], con los valores
+///   reglamentarios, ya que unos factores nulos no son un valor por defecto razonable
 #[derive(Debug, Copy, Clone)]
+#[non_exhaustive]
 pub struct UserWF<T = RenNrenCo2> {
     /// Factores de paso de redes de distrito 1.
     /// RED1, RED, SUMINISTRO, A, ren, nren
@@ -580,6 +1347,54 @@ pub struct UserWF<T = RenNrenCo2> {
     /// Factores de paso de redes de distrito 2.
     /// RED2, RED, SUMINISTRO, A, ren, nren
     pub red2: T,
+    /// Factores de paso del hidrógeno.
+    /// HIDROGENO, RED, SUMINISTRO, A, ren, nren
+    pub hidrogeno: T,
+}
+
+impl<T> Default for UserWF<Option<T>> {
+    /// Sin factores de paso de usuario definidos
+    fn default() -> Self {
+        Self {
+            red1: None,
+            red2: None,
+            hidrogeno: None,
+        }
+    }
+}
+
+impl UserWF<RenNrenCo2> {
+    /// Factores de paso definibles por el usuario usados por defecto en el CTE
+    ///
+    /// Para el hidrógeno se toman, a falta de valores reglamentarios, los del hidrógeno obtenido
+    /// por reformado de gas natural (con un rendimiento de en torno al 70%).
+    pub const fn cte_defaults() -> Self {
+        Self {
+            red1: RenNrenCo2::new(0.0, 1.3, 0.3),
+            red2: RenNrenCo2::new(0.0, 1.3, 0.3),
+            hidrogeno: RenNrenCo2::new(0.0, 1.7, 0.36),
+        }
+    }
+}
+
+impl<T> UserWF<T> {
+    /// Con los factores de paso de redes de distrito 1
+    pub fn with_red1(mut self, red1: T) -> Self {
+        self.red1 = red1;
+        self
+    }
+
+    /// Con los factores de paso de redes de distrito 2
+    pub fn with_red2(mut self, red2: T) -> Self {
+        self.red2 = red2;
+        self
+    }
+
+    /// Con los factores de paso del hidrógeno
+    pub fn with_hidrogeno(mut self, hidrogeno: T) -> Self {
+        self.hidrogeno = hidrogeno;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -598,6 +1413,165 @@ ELECTRICIDAD, INSITU, SUMINISTRO, A, 1.000, 0.000, 0.000 # Recursos usados para
         assert_eq!(tfactors1.parse::<Factors>().unwrap().to_string(), tfactors1);
     }
 
+    /// Los valores por defecto parten de los reglamentarios y los de usuario, de ninguno
+    #[test]
+    fn userwf_defaults() {
+        let defaults = UserWF::cte_defaults().with_red2(RenNrenCo2::new(0.5, 0.5, 0.1));
+        assert_eq!(defaults.red1.nren, 1.3);
+        assert_eq!(defaults.red2.ren, 0.5);
+        assert_eq!(defaults.hidrogeno.nren, 1.7);
+        let user: UserWF<Option<RenNrenCo2>> = UserWF::default();
+        assert!(user.red1.is_none() && user.red2.is_none() && user.hidrogeno.is_none());
+    }
+
+    #[test]
+    fn factors_transaction() {
+        use Carrier::*;
+        let defaults = UserWF {
+            red1: RenNrenCo2::new(0.0, 1.3, 0.3),
+            red2: RenNrenCo2::new(0.0, 1.3, 0.3),
+            hidrogeno: RenNrenCo2::new(0.0, 1.7, 0.36),
+        };
+        let mut factors = "ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331"
+            .parse::<Factors>()
+            .unwrap()
+            .normalize(&defaults)
+            .unwrap();
+        let original = factors.to_string();
+
+        // Cambios válidos: se aplican y la tabla queda normalizada
+        let mut tx = factors.transaction();
+        tx.update(
+            ELECTRICIDAD,
+            Source::RED,
+            Dest::SUMINISTRO,
+            Step::A,
+            RenNrenCo2::new(0.5, 1.5, 0.2),
+            "",
+        )
+        .ensure(
+            GASNATURAL,
+            Source::RED,
+            Dest::SUMINISTRO,
+            Step::A,
+            RenNrenCo2::new(0.005, 1.190, 0.252),
+            "Gas natural",
+        );
+        assert_eq!(tx.len(), 2);
+        tx.commit(&defaults).unwrap();
+        assert_eq!(
+            factors
+                .find(ELECTRICIDAD, Source::RED, Dest::SUMINISTRO, Step::A)
+                .unwrap(),
+            RenNrenCo2::new(0.5, 1.5, 0.2)
+        );
+        assert!(factors
+            .find(GASNATURAL, Source::RED, Dest::SUMINISTRO, Step::A)
+            .is_ok());
+        let committed = factors.to_string();
+        assert_ne!(committed, original);
+
+        // Cambios no válidos: la tabla no cambia
+        let mut tx = factors.transaction();
+        tx.update(
+            GASNATURAL,
+            Source::RED,
+            Dest::SUMINISTRO,
+            Step::A,
+            RenNrenCo2::new(0.0, 1.0, 0.2),
+            "",
+        )
+        .update(
+            BIOMASA,
+            Source::RED,
+            Dest::SUMINISTRO,
+            Step::A,
+            RenNrenCo2::new(-1.0, 0.0, 0.0),
+            "",
+        );
+        assert!(tx.commit(&defaults).is_err());
+        assert_eq!(factors.to_string(), committed);
+
+        // Eliminación de un factor inexistente o que deja la tabla sin factores de red
+        let mut tx = factors.transaction();
+        tx.remove(BIOMASA, Source::RED, Dest::SUMINISTRO, Step::A);
+        assert!(tx.commit(&defaults).is_err());
+        let mut tx = factors.transaction();
+        tx.remove(GASNATURAL, Source::RED, Dest::SUMINISTRO, Step::A)
+            .ensure(
+                GASNATURAL,
+                Source::INSITU,
+                Dest::SUMINISTRO,
+                Step::A,
+                RenNrenCo2::new(0.0, 1.0, 0.2),
+                "",
+            );
+        assert!(tx.commit(&defaults).is_err());
+        assert_eq!(factors.to_string(), committed);
+
+        // Transacción descartada
+        let mut tx = factors.transaction();
+        tx.remove(GASNATURAL, Source::RED, Dest::SUMINISTRO, Step::A);
+        tx.rollback();
+        assert_eq!(factors.to_string(), committed);
+    }
+
+    #[test]
+    fn normalize_keeps_user_renewable_factors() {
+        let tfactors = "ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+EAMBIENTE, INSITU, SUMINISTRO, A, 0.800, 0.200, 0.010 # Sensibilidad energía ambiente"
+            .parse::<Factors>()
+            .unwrap()
+            .normalize(&UserWF {
+                red1: RenNrenCo2::new(0.0, 1.3, 0.3),
+                red2: RenNrenCo2::new(0.0, 1.3, 0.3),
+                hidrogeno: RenNrenCo2::new(0.0, 1.7, 0.36),
+            })
+            .unwrap();
+        let user = RenNrenCo2::new(0.8, 0.2, 0.01);
+        let find = |c, s, d, st| tfactors.find(c, s, d, st).unwrap();
+        use Carrier::*;
+        assert_eq!(
+            find(EAMBIENTE, Source::INSITU, Dest::SUMINISTRO, Step::A),
+            user
+        );
+        assert_eq!(find(EAMBIENTE, Source::INSITU, Dest::A_RED, Step::A), user);
+        // Los factores no definidos por el usuario toman los valores predefinidos
+        assert_eq!(
+            find(TERMOSOLAR, Source::INSITU, Dest::SUMINISTRO, Step::A),
+            RenNrenCo2::new(1.0, 0.0, 0.0)
+        );
+        let user_defined = tfactors.user_defined_renewable_factors();
+        assert_eq!(user_defined.len(), 1);
+        assert_eq!(user_defined[0].comment, "Sensibilidad energía ambiente");
+    }
+
+    #[test]
+    fn check_required_factors() {
+        let tfactors = "ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+ELECTRICIDAD, INSITU, SUMINISTRO, A, 1.000, 0.000, 0.000
+ELECTRICIDAD, INSITU, A_RED, A, 1.000, 0.000, 0.000"
+            .parse::<Factors>()
+            .unwrap();
+        let tcomps = "CONSUMO, CAL, GASOLEO, 190
+CONSUMO, ACS, ELECTRICIDAD, 50
+PRODUCCION, EL_INSITU, 40"
+            .parse::<Components>()
+            .unwrap();
+        assert_eq!(
+            tfactors.missing_factors(&tcomps),
+            vec![
+                (Carrier::ELECTRICIDAD, Source::INSITU, Dest::A_RED, Step::B),
+                (Carrier::GASOLEO, Source::RED, Dest::SUMINISTRO, Step::A),
+            ]
+        );
+        assert_eq!(
+            tfactors.check_required(&tcomps).unwrap_err().to_string(),
+            "Factor de paso no encontrado: factores necesarios para el balance no definidos (2): \
+'ELECTRICIDAD, INSITU, A_RED, B', 'GASOLEO, RED, SUMINISTRO, A'"
+        );
+    }
+
     #[test]
     fn set_user_factors() {
         let tfactors1 = "#META CTE_FUENTE: RITE2014
@@ -616,12 +1590,57 @@ RED2, RED, SUMINISTRO, A, 0.125, 0.100, 0.500 # Factor de usuario";
                 .set_user_wfactors(UserWF {
                     red1: Some(RenNrenCo2::new(0.1, 0.125, 0.5)),
                     red2: Some(RenNrenCo2::new(0.125, 0.1, 0.5)),
+                    hidrogeno: None,
                 })
                 .to_string(),
             tfactorsres
         );
     }
 
+    #[test]
+    fn set_user_factors_and_renormalize() {
+        let defaults = UserWF {
+            red1: RenNrenCo2::new(0.0, 1.3, 0.3),
+            red2: RenNrenCo2::new(0.0, 1.3, 0.3),
+            hidrogeno: RenNrenCo2::new(0.0, 1.7, 0.36),
+        };
+        let tfactors = "ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+ELECTRICIDAD, INSITU, SUMINISTRO, A, 1.000, 0.000, 0.000"
+            .parse::<Factors>()
+            .unwrap()
+            .normalize(&defaults)
+            .unwrap();
+        let user = [
+            "ELECTRICIDAD, RED, SUMINISTRO, A, 0.500, 1.500, 0.200"
+                .parse::<Factor>()
+                .unwrap(),
+            "GASOLEO, RED, SUMINISTRO, A, 0.000, 2.000, 0.500 # Gasóleo"
+                .parse::<Factor>()
+                .unwrap(),
+        ];
+        let tfactors = tfactors
+            .set_user_factors(&user)
+            .normalize(&defaults)
+            .unwrap();
+
+        let find =
+            |carrier, source, dest, step| tfactors.find(carrier, source, dest, step).unwrap();
+        use Carrier::*;
+        assert_eq!(
+            find(ELECTRICIDAD, Source::RED, Dest::SUMINISTRO, Step::A),
+            RenNrenCo2::new(0.5, 1.5, 0.2)
+        );
+        // Los factores de exportación deducidos se obtienen de los nuevos factores de suministro
+        assert_eq!(
+            find(ELECTRICIDAD, Source::INSITU, Dest::A_RED, Step::B),
+            RenNrenCo2::new(0.5, 1.5, 0.2)
+        );
+        assert_eq!(
+            find(GASOLEO, Source::RED, Dest::SUMINISTRO, Step::A),
+            RenNrenCo2::new(0.0, 2.0, 0.5)
+        );
+    }
+
     #[test]
     fn normalize_and_strip() {
         let tfactors = "#META CTE_FUENTE: RITE2014
@@ -650,7 +1669,9 @@ TERMOSOLAR, INSITU, A_NEPB, A, 1.000, 0.000, 0.000 # Recursos usados para produc
 TERMOSOLAR, INSITU, A_RED, B, 1.000, 0.000, 0.000 # Recursos ahorrados a la red por la energía producida in situ y exportada a la red
 TERMOSOLAR, INSITU, A_NEPB, B, 1.000, 0.000, 0.000 # Recursos ahorrados a la red por la energía producida in situ y exportada a usos no EPB
 RED1, RED, SUMINISTRO, A, 0.000, 1.300, 0.300 # Recursos usados para suministrar energía de la red de distrito 1 (definible por el usuario)
-RED2, RED, SUMINISTRO, A, 0.000, 1.300, 0.300 # Recursos usados para suministrar energía de la red de distrito 2 (definible por el usuario)";
+RED2, RED, SUMINISTRO, A, 0.000, 1.300, 0.300 # Recursos usados para suministrar energía de la red de distrito 2 (definible por el usuario)
+HIDROGENO, RED, SUMINISTRO, A, 0.000, 1.700, 0.360 # Recursos usados para suministrar hidrógeno (definible por el usuario)
+RED_CALOR_RESIDUAL, RED, SUMINISTRO, A, 0.000, 0.000, 0.000 # Recursos usados para suministrar calor residual recuperado (definible por el usuario)";
         let tcomps = "CONSUMO, ILU, ELECTRICIDAD, 1 # Solo consume electricidad de red"
             .parse::<Components>()
             .unwrap();
@@ -670,6 +1691,11 @@ ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331 # Recursos usados para sum
                     nren: 1.3,
                     co2: 0.3,
                 },
+                hidrogeno: RenNrenCo2 {
+                    ren: 0.0,
+                    nren: 1.7,
+                    co2: 0.36,
+                },
             })
             .unwrap();
         let tfactors_normalized_stripped = tfactors_normalized.clone().strip(&tcomps);
@@ -679,5 +1705,121 @@ ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331 # Recursos usados para sum
             tfactors_normalized_stripped.to_string(),
             tfactors_normalized_stripped_str
         );
+        // Un aviso por cada factor eliminado
+        assert_eq!(
+            tfactors_normalized.wdata.len() - tfactors_normalized_stripped.wdata.len(),
+            tfactors_normalized_stripped.warnings.len()
+        );
+        assert!(tfactors_normalized_stripped
+            .warnings
+            .iter()
+            .all(|w| w.code == WarningCode::FACTORES_ELIMINADOS));
+    }
+
+    #[test]
+    fn validity_periods() {
+        let mut wfactors = "ELECTRICIDAD, RED, SUMINISTRO, A, 0.4, 2.0, 0.3
+            ELECTRICIDAD, RED, SUMINISTRO, A, 0.6, 1.5, 0.2 # Factores nuevos CTEEPBD_VIGENCIA=3-
+            GASNATURAL, RED, SUMINISTRO, A, 0.0, 1.0, 0.2 # CTEEPBD_VIGENCIA=1-2
+            GASNATURAL, RED, SUMINISTRO, A, 0.0, 1.2, 0.3 # CTEEPBD_VIGENCIA=3-4"
+            .parse::<Factors>()
+            .unwrap();
+        let periods = wfactors.apply_validity_periods(4).unwrap();
+        assert_eq!(periods.len(), 3);
+        assert_eq!(wfactors.wdata.len(), 2);
+        // Con factor sin periodo de vigencia los valores anuales son también la media de los pasos
+        let el = wfactors
+            .find_factor(
+                Carrier::ELECTRICIDAD,
+                Source::RED,
+                Dest::SUMINISTRO,
+                Step::A,
+            )
+            .unwrap();
+        assert_eq!(
+            format!("{:.3}, {:.3}, {:.3}", el.ren, el.nren, el.co2),
+            "0.500, 1.750, 0.250"
+        );
+        assert_eq!(el.factors_at(1), RenNrenCo2::new(0.4, 2.0, 0.3));
+        assert_eq!(el.factors_at(3), RenNrenCo2::new(0.6, 1.5, 0.2));
+        // Sin factor sin periodo de vigencia se usa la media de los pasos
+        let gn = wfactors
+            .find_factor(Carrier::GASNATURAL, Source::RED, Dest::SUMINISTRO, Step::A)
+            .unwrap();
+        assert_eq!(gn.values_t.len(), 4);
+        assert!((gn.nren - 1.1).abs() < 1e-6);
+
+        // Periodos solapados
+        let mut wfactors = "GASNATURAL, RED, SUMINISTRO, A, 0.0, 1.0, 0.2 # CTEEPBD_VIGENCIA=1-3
+            GASNATURAL, RED, SUMINISTRO, A, 0.0, 1.2, 0.3 # CTEEPBD_VIGENCIA=3-4"
+            .parse::<Factors>()
+            .unwrap();
+        assert!(wfactors.apply_validity_periods(4).is_err());
+        // Pasos sin factor vigente
+        let mut wfactors = "GASNATURAL, RED, SUMINISTRO, A, 0.0, 1.0, 0.2 # CTEEPBD_VIGENCIA=1-2"
+            .parse::<Factors>()
+            .unwrap();
+        assert!(wfactors.apply_validity_periods(4).is_err());
+        // Periodo mal formado
+        let mut wfactors = "GASNATURAL, RED, SUMINISTRO, A, 0.0, 1.0, 0.2 # CTEEPBD_VIGENCIA=4-2"
+            .parse::<Factors>()
+            .unwrap();
+        assert!(wfactors.apply_validity_periods(4).is_err());
+    }
+
+    #[test]
+    fn network_mix_factors() {
+        let defaults = UserWF {
+            red1: RenNrenCo2::new(0.0, 1.3, 0.3),
+            red2: RenNrenCo2::new(0.0, 1.3, 0.3),
+            hidrogeno: RenNrenCo2::new(0.0, 1.7, 0.36),
+        };
+        // Mix declarado en los metadatos, que prevalece sobre el factor definido directamente
+        let tfactors =
+            "#META CTE_MIX_RED1: BIOMASA, 60, 1.0, 0.05, 0.02; GASNATURAL, 40, 0.0, 1.2, 0.25
+ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+RED1, RED, SUMINISTRO, A, 0.000, 1.300, 0.300"
+                .parse::<Factors>()
+                .unwrap()
+                .normalize(&defaults)
+                .unwrap();
+        let red1 = tfactors
+            .find(Carrier::RED1, Source::RED, Dest::SUMINISTRO, Step::A)
+            .unwrap();
+        assert_eq!(format!("{:.3}", red1.ren), "0.600");
+        assert_eq!(format!("{:.3}", red1.nren), "0.510");
+        assert_eq!(format!("{:.3}", red1.co2), "0.112");
+        // Sin mix se usan los valores por defecto
+        assert_eq!(
+            tfactors
+                .find(Carrier::RED2, Source::RED, Dest::SUMINISTRO, Step::A)
+                .unwrap(),
+            defaults.red2
+        );
+
+        // Registro del mix en los metadatos
+        let mut tfactors = tfactors;
+        let mix = NetworkMix::new(Carrier::RED2, "CALOR_RESIDUAL, 100, 0.0, 0.0, 0.0").unwrap();
+        tfactors.set_network_mix(&mix);
+        assert_eq!(
+            tfactors.get_meta("CTE_MIX_RED2").unwrap(),
+            "CALOR_RESIDUAL, 100.0, 0.000, 0.000, 0.000"
+        );
+        let tfactors = tfactors.to_string().parse::<Factors>().unwrap();
+        assert_eq!(tfactors.network_mixes().unwrap().len(), 2);
+        assert_eq!(
+            tfactors
+                .find(Carrier::RED2, Source::RED, Dest::SUMINISTRO, Step::A)
+                .unwrap(),
+            RenNrenCo2::new(0.0, 0.0, 0.0)
+        );
+
+        // Mix incorrecto
+        assert!("#META CTE_MIX_GASNATURAL: BIOMASA, 100, 1.0, 0.05, 0.02
+ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331"
+            .parse::<Factors>()
+            .unwrap()
+            .normalize(&defaults)
+            .is_err());
     }
 }
@@ -0,0 +1,208 @@
+//! Superficie pública documentada de la biblioteca
+//!
+//! Compara los elementos públicos documentados (módulos, tipos, funciones, variantes, campos y
+//! métodos) con la lista de `tests/public-api.txt`, obtenida de la salida JSON de rustdoc con
+//! todas las *features*. Los elementos ocultos con `#[doc(hidden)]` (p.e. las reexportaciones de
+//! la raíz del *crate*) no forman parte de la superficie.
+//!
+//! Necesita la versión nightly de Rust, por lo que se ejecuta a petición:
+//!
+//! `cargo test --test public_api -- --ignored`
+//!
+//! Con la variable de entorno `UPDATE_PUBLIC_API=1` se actualiza la lista tras un cambio
+//! intencionado de la API.
+
+use std::collections::{BTreeSet, HashSet};
+use std::path::Path;
+use std::process::Command;
+
+use serde_json::Value;
+
+const PUBLIC_API_PATH: &str = "tests/public-api.txt";
+
+/// Genera la documentación en JSON con rustdoc (nightly) y devuelve su contenido
+fn rustdoc_json() -> Value {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = manifest_dir.join("target").join("public-api");
+    let status = Command::new("cargo")
+        .args([
+            "+nightly",
+            "rustdoc",
+            "--lib",
+            "--all-features",
+            "--target-dir",
+        ])
+        .arg(&target_dir)
+        .args(["--", "-Zunstable-options", "--output-format", "json"])
+        .current_dir(manifest_dir)
+        .status()
+        .expect("No se ha podido ejecutar cargo +nightly rustdoc");
+    assert!(
+        status.success(),
+        "Error al generar la documentación en JSON"
+    );
+    let json = std::fs::read_to_string(target_dir.join("doc").join("cteepbd.json")).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+/// Recorre los elementos públicos alcanzables desde el elemento `id`, con la ruta `path`
+fn walk(
+    index: &Value,
+    id: &Value,
+    path: &str,
+    visited: &mut HashSet<String>,
+    api: &mut BTreeSet<String>,
+) {
+    let Some(item) = index.get(id_key(id)) else {
+        return;
+    };
+    if !visited.insert(format!("{path}#{}", id_key(id))) {
+        return;
+    }
+    let Some((kind, inner)) = item["inner"].as_object().and_then(|o| o.iter().next()) else {
+        return;
+    };
+    match kind.as_str() {
+        "module" => {
+            api.insert(format!("mod {path}"));
+            for child in inner["items"].as_array().into_iter().flatten() {
+                walk_child(index, child, path, visited, api);
+            }
+        }
+        "struct" => {
+            api.insert(format!("struct {path}"));
+            let fields = inner["kind"]
+                .get("plain")
+                .and_then(|p| p.get("fields"))
+                .or_else(|| inner["kind"].get("tuple"));
+            for field in fields.and_then(Value::as_array).into_iter().flatten() {
+                if let Some(name) = index.get(id_key(field)).and_then(|f| f["name"].as_str()) {
+                    api.insert(format!("field {path}::{name}"));
+                }
+            }
+            walk_impls(index, inner, path, api);
+        }
+        "enum" => {
+            api.insert(format!("enum {path}"));
+            for variant in inner["variants"].as_array().into_iter().flatten() {
+                if let Some(name) = index.get(id_key(variant)).and_then(|v| v["name"].as_str()) {
+                    api.insert(format!("variant {path}::{name}"));
+                }
+            }
+            walk_impls(index, inner, path, api);
+        }
+        "trait" => {
+            api.insert(format!("trait {path}"));
+            for child in inner["items"].as_array().into_iter().flatten() {
+                if let Some(name) = index.get(id_key(child)).and_then(|c| c["name"].as_str()) {
+                    api.insert(format!("trait_item {path}::{name}"));
+                }
+            }
+        }
+        other => {
+            api.insert(format!("{other} {path}"));
+        }
+    }
+}
+
+/// Recorre un elemento de un módulo, resolviendo las reexportaciones (`use`)
+fn walk_child(
+    index: &Value,
+    child: &Value,
+    path: &str,
+    visited: &mut HashSet<String>,
+    api: &mut BTreeSet<String>,
+) {
+    let Some(item) = index.get(id_key(child)) else {
+        return;
+    };
+    if item["visibility"] != "public" {
+        return;
+    }
+    match item["inner"].get("use") {
+        Some(import) => {
+            let target = &import["id"];
+            let name = import["name"].as_str().unwrap_or_default();
+            if import["is_glob"] == true {
+                if let Some(module) = index.get(id_key(target)) {
+                    for child in module["inner"]["module"]["items"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                    {
+                        walk_child(index, child, path, visited, api);
+                    }
+                }
+            } else if index.get(id_key(target)).is_some() {
+                walk(index, target, &format!("{path}::{name}"), visited, api);
+            } else {
+                api.insert(format!("use {path}::{name}"));
+            }
+        }
+        None => {
+            if let Some(name) = item["name"].as_str() {
+                walk(index, child, &format!("{path}::{name}"), visited, api);
+            }
+        }
+    }
+}
+
+/// Añade los métodos de las implementaciones propias (no de traits) del tipo
+fn walk_impls(index: &Value, inner: &Value, path: &str, api: &mut BTreeSet<String>) {
+    for imp in inner["impls"].as_array().into_iter().flatten() {
+        let Some(imp) = index.get(id_key(imp)).map(|i| &i["inner"]["impl"]) else {
+            continue;
+        };
+        if !imp["trait"].is_null() {
+            continue;
+        }
+        for method in imp["items"].as_array().into_iter().flatten() {
+            let Some(method) = index.get(id_key(method)) else {
+                continue;
+            };
+            if method["visibility"] == "public" {
+                if let Some(name) = method["name"].as_str() {
+                    api.insert(format!("method {path}::{name}"));
+                }
+            }
+        }
+    }
+}
+
+fn id_key(id: &Value) -> String {
+    match id {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[test]
+#[ignore = "necesita la versión nightly de Rust"]
+fn public_api() {
+    let doc = rustdoc_json();
+    let index = &doc["index"];
+    let mut api = BTreeSet::new();
+    walk(
+        index,
+        &doc["root"],
+        "cteepbd",
+        &mut HashSet::new(),
+        &mut api,
+    );
+    let current = api.into_iter().collect::<Vec<_>>().join("\n") + "\n";
+
+    let expected_path = Path::new(env!("CARGO_MANIFEST_DIR")).join(PUBLIC_API_PATH);
+    if std::env::var_os("UPDATE_PUBLIC_API").is_some() {
+        std::fs::write(&expected_path, &current).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&expected_path).unwrap_or_default();
+    let expected: BTreeSet<&str> = expected.lines().collect();
+    let current: BTreeSet<&str> = current.lines().collect();
+    let removed: Vec<_> = expected.difference(&current).collect();
+    let added: Vec<_> = current.difference(&expected).collect();
+    assert!(
+        removed.is_empty() && added.is_empty(),
+        "La superficie pública ha cambiado (actualizar con UPDATE_PUBLIC_API=1)\nEliminados: {removed:#?}\nAñadidos: {added:#?}"
+    );
+}
@@ -243,3 +243,722 @@ fn ejemplo_acs_demanda_ren_con_nepb_con_exclusion_aux() {
         .contains("Porcentaje renovable de la demanda de ACS (perímetro próximo): 96.7 [%]")
         .unwrap();
 }
+
+#[test]
+fn ejemplo_j3_compensacion_simplificada() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ3_basePVexcess.csv",
+            "-f",
+            "test_data/factores_paso_test.csv",
+            "-k",
+            "1",
+            "--compensacion_simplificada",
+        ])
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 120.0, nren = -80.0, tot = 40.0")
+        .stdout()
+        .contains("** Compensación simplificada de excedentes (k_comp = 0.0)\nC_ep [kWh/m2.an]: ren = 100.0, nren = 0.0, tot = 100.0")
+        .stdout()
+        .contains("Diferencia frente al método estándar: nren = +80.0, tot = +60.0 [kWh/m2.an]")
+        .unwrap();
+}
+
+#[test]
+fn demanda_zonas() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-c", "test_data/demanda_zonas.csv", "-l", "PENINSULA"])
+        .stdout()
+        .contains("AVISO: la suma de las demandas de las zonas para el servicio CAL difiere de la demanda del edificio (+5.6 %)")
+        .stdout()
+        .contains("* Desvío de la suma de demandas de zonas respecto a la del edificio:\n- CAL: 0.20")
+        .unwrap();
+}
+
+#[test]
+fn cobertura_renovable_onst_ven_ilu() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-c", "test_data/cte_test_carriers.csv", "-l", "PENINSULA"])
+        .stdout()
+        .contains("Cobertura renovable in situ del consumo de VEN: 34.7 [%]\nCobertura renovable in situ del consumo de ILU: - [%]")
+        .unwrap();
+}
+
+#[test]
+fn consumos_negativos() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-c", "test_data/consumos_negativos.csv", "-l", "PENINSULA"])
+        .stdout()
+        .contains("AVISO: consumo negativo de ELECTRICIDAD para REF en el sistema con id:2 (-10.00 kWh en 2 pasos de cálculo) (tratamiento: permitir)")
+        .unwrap();
+
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/consumos_negativos.csv",
+            "-l",
+            "PENINSULA",
+            "--consumos_negativos",
+            "error",
+        ])
+        .fails()
+        .unwrap();
+}
+
+#[test]
+fn version_factores_paso() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ1_base.csv",
+            "-l",
+            "PENINSULA",
+            "--wf-version",
+            "RITE2014",
+        ])
+        .stdout()
+        .contains("Versión de factores de paso (usuario): RITE2014")
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 41.4, nren = 195.4, tot = 236.8")
+        .unwrap();
+
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ1_base.csv",
+            "-l",
+            "PENINSULA",
+            "--wf-version",
+            "RITE2000",
+        ])
+        .fails()
+        .unwrap();
+}
+
+#[test]
+fn balance_exergetico() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ5_gasPV.csv",
+            "-f",
+            "test_data/factores_paso_test.csv",
+            "--exergia",
+            "test_data/factores_exergia.csv",
+        ])
+        .stdout()
+        .contains("Exergía suministrada: 218.60")
+        .stdout()
+        .contains("- GASNATURAL: 178.60")
+        .unwrap();
+}
+
+#[test]
+fn contribucion_vectores() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ5_gasPV.csv",
+            "-f",
+            "test_data/factores_paso_test.csv",
+            "-k",
+            "1.0",
+        ])
+        .stdout()
+        .contains("- GASNATURAL: ren 0.0, nren 123.7, tot: 105.0, co2: 100.0")
+        .unwrap();
+}
+
+#[test]
+fn uso_edificio() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-c", "test_data/uso_residencial.csv", "-l", "PENINSULA"])
+        .fails()
+        .and()
+        .stderr()
+        .contains("Consumos de servicios no admitidos para el uso residencial privado del edificio: ILU (id:2)")
+        .unwrap();
+}
+
+#[test]
+fn energia_embebida() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-c", "test_data/energia_embebida.csv", "-l", "PENINSULA"])
+        .stdout()
+        .contains("C_ep,emb [kWh/m2.an]: ren = 0.6, nren = 9.5, tot = 10.1")
+        .unwrap();
+}
+
+#[test]
+fn ejemplo_edificio_completo_con_nepb() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/acs_demanda_ren_con_nepb.csv",
+            "-l",
+            "PENINSULA",
+        ])
+        .stdout()
+        .contains("** Edificio completo (usos EPB y no EPB, informativo)\nEnergía consumida: 70.23")
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 46.8, nren = 77.6, tot = 124.4")
+        .unwrap();
+}
+
+#[test]
+fn rer_directiva_bdc() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/bdc_rer_directiva.csv",
+            "-l",
+            "PENINSULA",
+        ])
+        .stdout()
+        .contains("RER = 0.47")
+        .stdout()
+        .contains("RER_UE (BdC con SPF > 2.53) = 0.28")
+        .unwrap();
+}
+
+#[test]
+fn error_formato_con_sugerencia() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ1_base.csv",
+            "-f",
+            "test_data/factores_paso_errata.csv",
+        ])
+        .fails()
+        .and()
+        .stderr()
+        .contains("línea 3: destino desconocido \"SUMINSTRO\" (¿quiso decir SUMINISTRO?)")
+        .unwrap();
+}
+
+#[test]
+fn conciliacion_facturas() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ9_electr.csv",
+            "-l",
+            "PENINSULA",
+            "--facturas",
+            "test_data/facturas_J9.csv",
+        ])
+        .stdout()
+        .contains("* ELECTRICIDAD: calculada = 581.0, facturada = 575.0, desviación = +1.0 %")
+        .stdout()
+        .contains("  - mes 1: 186.0 / 180.0 (+3.3 %)")
+        .unwrap();
+}
+
+#[test]
+fn factores_insuficientes_sin_simplificar() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/caldera_gasoleo.csv",
+            "-f",
+            "test_data/factores_paso_test.csv",
+            "--no_simplifica_fps",
+        ])
+        .fails()
+        .and()
+        .stderr()
+        .contains("ERROR: factores de paso insuficientes para los componentes: Factor de paso no encontrado: factores necesarios para el balance no definidos (1): 'GASOLEO, RED, SUMINISTRO, A'")
+        .unwrap();
+}
+
+#[test]
+fn factores_eambiente_usuario() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/bdc_rer_directiva.csv",
+            "-f",
+            "test_data/factores_paso_eambiente_usuario.csv",
+        ])
+        .stdout()
+        .contains("AVISO: factor de paso de EAMBIENTE definido por el usuario distinto del predefinido (1.0, 0.0, 0.0): EAMBIENTE, INSITU, SUMINISTRO, A, 0.500, 0.500, 0.000 # Sensibilidad energía ambiente")
+        .unwrap();
+}
+
+#[test]
+fn exportacion_registro_autonomico() {
+    let path = std::env::temp_dir().join("cteepbd_test_registro.xml");
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/registro_edificio.csv",
+            "-l",
+            "PENINSULA",
+            "--plantilla_registro",
+            "test_data/plantilla_registro.txt",
+            "--registro",
+            path.to_str().unwrap(),
+        ])
+        .succeeds()
+        .unwrap();
+    let xml = std::fs::read_to_string(&path).unwrap();
+    assert!(xml.contains("<RegistroCEE version=\"1.0\">"));
+    assert!(xml.contains("<ReferenciaCatastral>9872023VH5797S0001WX</ReferenciaCatastral>"));
+    assert!(xml.contains("<ConsumoEPNoRenovable unidad=\"kWh/m2.an\">45.5</ConsumoEPNoRenovable>"));
+}
+
+#[test]
+fn exportacion_registro_sin_metadatos() {
+    let path = std::env::temp_dir().join("cteepbd_test_registro_error.xml");
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ1_base.csv",
+            "-l",
+            "PENINSULA",
+            "--plantilla_registro",
+            "test_data/plantilla_registro.txt",
+            "--registro",
+            path.to_str().unwrap(),
+        ])
+        .fails()
+        .and()
+        .stderr()
+        .contains("Valores no disponibles para la plantilla de registro: meta:CTE_REFERENCIA_CATASTRAL, meta:CTE_MUNICIPIO")
+        .unwrap();
+}
+
+#[test]
+fn huella_carbono_marginal() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ3_basePVexcess.csv",
+            "-l",
+            "PENINSULA",
+            "--co2_marginal",
+            "test_data/factores_co2_marginal.csv",
+        ])
+        .stdout()
+        .contains("** Huella de carbono marginal (informativa, no reglamentaria) [kg_CO2e/m2.an]")
+        .stdout()
+        .contains("ELECTRICIDAD: suministro de red = 0.00, evitadas por producción usada = 25.00, evitadas por exportación = 10.00")
+        .unwrap();
+}
+
+#[test]
+fn rendimiento_implicito_no_plausible() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/salida_rendimiento_excesivo.csv",
+            "-l",
+            "PENINSULA",
+        ])
+        .stdout()
+        .contains("AVISO: rendimiento implícito del sistema 1 para el servicio CAL (1.20) superior al máximo plausible (1.05)")
+        .unwrap();
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/salida_rendimiento_excesivo.csv",
+            "-l",
+            "PENINSULA",
+            "--rendimiento_max_combustion",
+            "1.25",
+        ])
+        .stdout()
+        .doesnt_contain("AVISO: rendimiento implícito")
+        .unwrap();
+}
+
+#[test]
+fn factores_usuario_en_metadatos() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/caldera_gasoleo_factores_usuario.csv",
+            "-l",
+            "PENINSULA",
+        ])
+        .stdout()
+        .contains("Factores de paso de usuario (metadatos CTE_FP): 1")
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 0.0, nren = 200.0, tot = 200.0")
+        .unwrap();
+}
+
+#[test]
+fn consumo_fuera_de_temporada() {
+    assert_cli::Assert::main_binary()
+        .with_args(&["-c", "test_data/temporadas_servicios.csv", "-l", "PENINSULA"])
+        .stdout()
+        .contains("AVISO: consumo del servicio CAL fuera de su temporada: 10.00 kWh (2.2 %)")
+        .stdout()
+        .contains("- REF (meses 6, 7, 8, 9): consumo en temporada = 6.00, fuera de temporada = 0.00 (0.0 %)")
+        .unwrap();
+}
+
+#[test]
+fn componentes_duplicados() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/componentes_duplicados.csv",
+            "-l",
+            "PENINSULA",
+            "--duplicados",
+            "avisar",
+        ])
+        .stdout()
+        .contains("AVISO: componente duplicado (2 apariciones): 1, CONSUMO, CAL, GASNATURAL")
+        .unwrap();
+
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/componentes_duplicados.csv",
+            "-l",
+            "PENINSULA",
+            "--duplicados",
+            "error",
+        ])
+        .fails()
+        .unwrap();
+}
+
+#[test]
+fn diagrama_sankey_svg() {
+    let path = std::env::temp_dir().join("cteepbd_test_sankey.svg");
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ3_basePVexcess.csv",
+            "-l",
+            "PENINSULA",
+            "--sankey",
+            path.to_str().unwrap(),
+        ])
+        .succeeds()
+        .unwrap();
+    let svg = std::fs::read_to_string(&path).unwrap();
+    assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(svg.contains("ELECTRICIDAD (INSITU) → CAL: 100.00"));
+    assert!(svg.contains("ELECTRICIDAD (INSITU) → Exportación: 40.00"));
+    assert!(svg.trim_end().ends_with("</svg>"));
+}
+
+#[test]
+fn emisiones_dos_juegos_factores() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ1_base.csv",
+            "-l",
+            "PENINSULA",
+            "--co2_inventario",
+            "test_data/factores_co2_inventario.csv",
+        ])
+        .stdout()
+        .contains("** Emisiones con dos juegos de factores de emisión [kg_CO2e/m2.an]")
+        .stdout()
+        .contains("E_CO2 (GHG market-based): 0.00\n")
+        .unwrap();
+}
+
+#[test]
+fn factor_coincidencia_cargas_modos() {
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/acs_demanda_ren_con_nepb.csv",
+            "-l",
+            "PENINSULA",
+            "--load_matching",
+            "mensual:1:1",
+        ])
+        .stdout()
+        .contains("* generada y usada en servicios EPB, por origen:\n- EAMBIENTE: 13.00\n- EL_INSITU: 2.92")
+        .unwrap();
+
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/acs_demanda_ren_con_nepb.csv",
+            "-l",
+            "PENINSULA",
+            "--load_matching",
+            "constante",
+        ])
+        .stdout()
+        .contains("* generada y usada en servicios EPB, por origen:\n- EAMBIENTE: 26.00\n- EL_INSITU: 4.45")
+        .unwrap();
+
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/acs_demanda_ren_con_nepb.csv",
+            "-l",
+            "PENINSULA",
+            "--load_matching",
+            "horaria",
+        ])
+        .fails()
+        .and()
+        .stderr()
+        .contains("requiere datos horarios")
+        .unwrap();
+
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/acs_demanda_ren_con_nepb.csv",
+            "-l",
+            "PENINSULA",
+            "--load_matching",
+            "semanal",
+        ])
+        .fails()
+        .unwrap();
+}
+
+#[test]
+fn resultados_tabla_larga() {
+    let path_json = std::env::temp_dir().join("cteepbd_test_tidy.json");
+    let path_csv = std::env::temp_dir().join("cteepbd_test_tidy.csv");
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ3_basePVexcess.csv",
+            "-l",
+            "PENINSULA",
+            "--tidy_json",
+            path_json.to_str().unwrap(),
+            "--tidy_csv",
+            path_csv.to_str().unwrap(),
+        ])
+        .succeeds()
+        .unwrap();
+    let csv = std::fs::read_to_string(&path_csv).unwrap();
+    assert!(csv.starts_with("carrier,metric,period,value\n"));
+    assert!(csv.contains("ELECTRICIDAD,prod.by_src.EL_INSITU,an,140\n"));
+    assert!(csv.contains("ELECTRICIDAD,used.epus_by_srv.CAL,1,100\n"));
+    let json = std::fs::read_to_string(&path_json).unwrap();
+    assert!(json.contains("\"metric\": \"we.b.nren\""));
+    assert!(json.contains("\"period\": \"an\""));
+}
+
+#[test]
+fn archivo_contenedor() {
+    let path = std::env::temp_dir().join("cteepbd_test_contenedor.txt");
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ3_basePVexcess.csv",
+            "-l",
+            "PENINSULA",
+            "--ocontenedor",
+            path.to_str().unwrap(),
+        ])
+        .succeeds()
+        .and()
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 100.0, nren = 0.0, tot = 100.0")
+        .unwrap();
+    let container = std::fs::read_to_string(&path).unwrap();
+    assert!(container.starts_with("[COMPONENTES]\n"));
+    assert!(container.contains("\n[FACTORES]\n"));
+
+    // El cálculo con el archivo contenedor reproduce el resultado original
+    assert_cli::Assert::main_binary()
+        .with_args(&["--contenedor", path.to_str().unwrap()])
+        .succeeds()
+        .and()
+        .stdout()
+        .contains("Factores de paso (contenedor)")
+        .and()
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 100.0, nren = 0.0, tot = 100.0")
+        .unwrap();
+
+    // No se admite junto a un archivo de componentes
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "--contenedor",
+            path.to_str().unwrap(),
+            "-c",
+            "test_data/ejemploJ3_basePVexcess.csv",
+        ])
+        .fails()
+        .unwrap();
+}
+
+#[test]
+fn coma_decimal() {
+    // Se detecta el punto y coma como separador de campos y se avisa de la conversión
+    assert_cli::Assert::main_binary()
+        .with_args(&["-c", "test_data/coma_decimal.csv", "-l", "PENINSULA"])
+        .succeeds()
+        .and()
+        .stdout()
+        .contains("AVISO: convertidos los números con coma decimal de 4 líneas del archivo \"test_data/coma_decimal.csv\"")
+        .and()
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 21.0, nren = 226.1, tot = 247.1")
+        .unwrap();
+    // La opción también admite el nombre --decimal-comma
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/coma_decimal.csv",
+            "-l",
+            "PENINSULA",
+            "--decimal-comma",
+        ])
+        .succeeds()
+        .and()
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 21.0, nren = 226.1, tot = 247.1")
+        .unwrap();
+}
+
+#[test]
+fn unidades_absolutas() {
+    // Los balances se muestran en valor absoluto y los indicadores reglamentarios por m2
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/cte_test_carriers.csv",
+            "-l",
+            "PENINSULA",
+            "--unidades",
+            "abs",
+        ])
+        .succeeds()
+        .and()
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 24.6, nren = 18.9, tot = 43.5")
+        .and()
+        .stdout()
+        .contains("** Energía final (todos los vectores) [kWh/an]:\n\nEnergía consumida: 6050.99")
+        .unwrap();
+}
+
+#[test]
+fn factores_vigencia_temporal() {
+    // Factores de paso de la electricidad distintos a partir del paso 7
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/cte_test_carriers.csv",
+            "-f",
+            "test_data/factores_paso_vigencia.csv",
+            "-a",
+            "100",
+        ])
+        .succeeds()
+        .and()
+        .stdout()
+        .contains("C_ep [kWh/m2.an]: ren = 51.8, nren = 34.0, tot = 85.8")
+        .and()
+        .stdout()
+        .contains("- ELECTRICIDAD, RED, SUMINISTRO, A (pasos 7 a 12): ren = 0.600, nren = 1.500, co2 = 0.300")
+        .unwrap();
+}
+
+#[test]
+fn localizacion_incoherente_factores_archivo() {
+    // Componentes en CANARIAS con factores de paso de archivo de PENINSULA
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/acs_demanda_ren_con_exclusion_auxiliares.csv",
+            "-f",
+            "test_data/factores_paso_PENINSULA_20140203.csv",
+        ])
+        .succeeds()
+        .and()
+        .stdout()
+        .contains("AVISO: Valor de entrada incorrecto: La localización declarada (CANARIAS) no coincide")
+        .unwrap();
+    // En modo estricto la incoherencia es un error
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/acs_demanda_ren_con_exclusion_auxiliares.csv",
+            "-f",
+            "test_data/factores_paso_PENINSULA_20140203.csv",
+            "--estricto",
+        ])
+        .fails()
+        .and()
+        .stderr()
+        .contains("ERROR: Valor de entrada incorrecto: La localización declarada (CANARIAS) no coincide")
+        .unwrap();
+    // Sin incoherencia no hay aviso
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/acs_demanda_ren_con_nepb.csv",
+            "-f",
+            "test_data/factores_paso_PENINSULA_20140203.csv",
+            "--estricto",
+        ])
+        .succeeds()
+        .and()
+        .stdout()
+        .doesnt_contain("La localización declarada")
+        .unwrap();
+}
+
+#[test]
+fn resumen_indicadores_json() {
+    let path = std::env::temp_dir().join("cteepbd_test_resumen.json");
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/cte_test_carriers.csv",
+            "-l",
+            "PENINSULA",
+            "--json-resumen",
+            path.to_str().unwrap(),
+        ])
+        .succeeds()
+        .unwrap();
+    let json = std::fs::read_to_string(&path).unwrap();
+    let summary: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(summary["ep_tot"], 43.495);
+    assert_eq!(summary["rer"], 0.565);
+    assert_eq!(summary["dhw_ren_fraction_nrb"], 0.66);
+    assert_eq!(summary["ep_by_srv"]["CAL"]["nren"], 6.177);
+    // Sin componentes ni series de valores
+    assert!(summary.get("components").is_none());
+    assert!(!json.contains("values"));
+}
+
+#[test]
+fn informe_html() {
+    let path = std::env::temp_dir().join("cteepbd_test_informe.html");
+    assert_cli::Assert::main_binary()
+        .with_args(&[
+            "-c",
+            "test_data/ejemploJ3_basePVexcess.csv",
+            "-l",
+            "PENINSULA",
+            "--html",
+            path.to_str().unwrap(),
+        ])
+        .succeeds()
+        .unwrap();
+    let html = std::fs::read_to_string(&path).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<a href=\"#vectores\">Balance por vector</a>"));
+    assert!(html.contains("<h2 id=\"servicios\">Balance por servicio</h2>"));
+    assert!(html.contains("<tr><th>ELECTRICIDAD</th>"));
+    assert!(html.contains("<tr><th>CTE_LOCALIZACION</th><td>PENINSULA</td></tr>"));
+    assert!(html.contains("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+    assert!(!html.contains("<link") && !html.contains("<script"));
+    assert!(html.trim_end().ends_with("</html>"));
+}
@@ -1,6 +1,6 @@
 #![allow(non_snake_case)]
 
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
@@ -69,10 +69,7 @@ TERMOSOLAR, INSITU, A_NEPB,  B, 1.0, 0.0, 0.0
 const TESTKEXP: f32 = 1.0;
 
 fn get_ctefp_peninsula() -> Factors {
-    let user_wf = UserWF {
-        red1: None,
-        red2: None,
-    };
+    let user_wf = UserWF::default();
     wfactors_from_loc("PENINSULA", &CTE_LOCWF_RITE2014, user_wf, CTE_USERWF).unwrap()
 }
 
@@ -119,6 +116,13 @@ fn get_energydatalist() -> Components {
             }),
         ],
         needs: Default::default(),
+        embodied: Default::default(),
+        systems: Default::default(),
+        efficiencies: Default::default(),
+        efficiency_curves: Default::default(),
+        peak_powers: Default::default(),
+        storages: Default::default(),
+        warnings: Default::default(),
     }
 }
 
@@ -135,10 +139,7 @@ fn wfactors_from_file(path: &str) -> Factors {
     let mut f = File::open(path).unwrap();
     let mut wfactors_string = String::new();
     f.read_to_string(&mut wfactors_string).unwrap();
-    let user_wf = UserWF {
-        red1: None,
-        red2: None,
-    };
+    let user_wf = UserWF::default();
     wfactors_from_str(&wfactors_string, user_wf, CTE_USERWF).unwrap()
 }
 
@@ -679,10 +680,10 @@ fn cte_test_carriers_kexp_0() {
 #[test]
 fn cte_EPBD() {
     let comps = components_from_file("test_data/cteEPBD-N_R09_unif-ET5-V048R070-C1_peninsula.csv");
-    let user_wf = UserWF {
-        red1: Some(CTE_USERWF.red1),
-        red2: Some(CTE_USERWF.red2),
-    };
+    let user_wf = UserWF::default()
+        .with_red1(Some(CTE_USERWF.red1))
+        .with_red2(Some(CTE_USERWF.red2))
+        .with_hidrogeno(Some(CTE_USERWF.hidrogeno));
     let FP = wfactors_from_loc("PENINSULA", &CTE_LOCWF_RITE2014, user_wf, CTE_USERWF).unwrap();
     let bal = energy_performance(&comps, &FP, 0.0, 217.4, false).unwrap();
     assert!(approx_equal(
@@ -717,7 +718,7 @@ fn cte_balance_by_srv() {
     let FP = get_ctefp_peninsula();
     let bal = energy_performance(&ENERGYDATALIST, &FP, TESTKEXP, 1.0, false).unwrap();
 
-    let mut result: HashMap<Service, RenNrenCo2> = HashMap::new();
+    let mut result: BTreeMap<Service, RenNrenCo2> = BTreeMap::new();
     result.insert(
         Service::CAL,
         RenNrenCo2 {
@@ -760,6 +761,28 @@ CONSUMO,ACS,TERMOSOLAR,60"
     assert_eq!(format!("{:.2}", fraccion_ren_acs), "0.60");
 }
 
+/// Gas natural (rend=0.9) y 60% de cobertura con calor residual recuperado (100kWh demanda ACS)
+#[test]
+fn cte_ACS_demanda_ren_gn_60_calor_residual() {
+    let comps = "DEMANDA,ACS,100 # Demanda anual ACS (kWh)
+CONSUMO,ACS,GASNATURAL,44.44
+CONSUMO,ACS,RED_CALOR_RESIDUAL,60 # Calor recuperado de un centro de datos"
+        .parse::<Components>()
+        .unwrap();
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    // El calor residual sustituye a la contribución renovable
+    let fraccion_ren_acs = fraccion_renovable_acs_nrb(&ep).unwrap();
+    assert_eq!(format!("{:.2}", fraccion_ren_acs), "0.60");
+    // Por defecto no tiene recursos asociados y pertenece al perímetro próximo
+    assert!(Carrier::RED_CALOR_RESIDUAL.is_nearby());
+    assert!(approx_equal(
+        ep.balance_cr[&Carrier::RED_CALOR_RESIDUAL].we.b,
+        RenNrenCo2::new(0.0, 0.0, 0.0)
+    ));
+    assert!(ep.to_plain().contains("RED_CALOR_RESIDUAL"));
+}
+
 /// Gas natural (fp_nren = 1.1, con rend=0.9) y 60% de cobertura solar (100kWh demanda ACS)
 /// Con cogeneración para otro servicio, sin afectar al ACS
 #[test]
@@ -822,6 +845,82 @@ fn cte_ACS_demanda_ren_biomasa_y_biomasa_densificada_100() {
     assert_eq!(format!("{:.3}", fraccion_ren_acs), "0.917");
 }
 
+/// Biocarburante rend 75% (75kWh demanda ACS), distante por defecto o próximo con CTE_BIOCARBURANTE_PROXIMO
+#[test]
+fn cte_ACS_demanda_ren_biocarburante_proximo() {
+    let TESTFPEXT = format!(
+        "{}\n{}",
+        TESTFP, "BIOCARBURANTE, RED, SUMINISTRO, A, 1.1, 0.1, 0.07"
+    );
+    let FP: Factors = TESTFPEXT.parse().unwrap();
+    let comps_str = "DEMANDA,ACS,75 # Demanda anual ACS (kWh)
+CONSUMO,ACS,BIOCARBURANTE,100";
+
+    // Por defecto el biocarburante no pertenece al perímetro próximo
+    let comps = comps_str.parse::<Components>().unwrap();
+    assert!(!comps.is_nearby_carrier(Carrier::BIOCARBURANTE));
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    assert_eq!(
+        format!("{:.3}", fraccion_renovable_acs_nrb(&ep).unwrap()),
+        "0.000"
+    );
+    assert_eq!(format!("{:.3}", ep.rer_nrb), "0.000");
+
+    // Biocarburante producido en las proximidades
+    let comps = format!("#META CTE_BIOCARBURANTE_PROXIMO: true\n{}", comps_str)
+        .parse::<Components>()
+        .unwrap();
+    assert!(comps.is_nearby_carrier(Carrier::BIOCARBURANTE));
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    assert_eq!(
+        format!("{:.3}", fraccion_renovable_acs_nrb(&ep).unwrap()),
+        "0.917"
+    );
+    assert_eq!(format!("{:.3}", ep.rer_nrb), "0.917");
+    assert!(ep
+        .to_plain()
+        .contains("(incluye BIOCARBURANTE en el perímetro próximo)"));
+}
+
+/// Calor solar comprado a una instalación próxima de terceros (50% demanda ACS) + Gas rend 100%
+#[test]
+fn cte_ACS_demanda_ren_termosolar_proximo() {
+    let FP: Factors = TESTFP.parse().unwrap();
+    let comps_for = |carrier: &str| {
+        format!(
+            "DEMANDA,ACS,100 # Demanda anual ACS (kWh)
+1,CONSUMO,ACS,GASNATURAL,50
+2,CONSUMO,ACS,{},50",
+            carrier
+        )
+        .parse::<Components>()
+        .unwrap()
+    };
+
+    // Instalación solar térmica in situ
+    let comps = comps_for("TERMOSOLAR");
+    let ep_onst = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+
+    // Instalación solar térmica próxima: no se genera producción in situ
+    let comps = comps_for("TERMOSOLAR_PROXIMO");
+    assert!(comps.is_nearby_carrier(Carrier::TERMOSOLAR_PROXIMO));
+    assert!(!Carrier::TERMOSOLAR_PROXIMO.is_onsite());
+    assert!(!comps.data.iter().any(|c| c.is_generated()));
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    assert_eq!(
+        format!("{:.3}", fraccion_renovable_acs_nrb(&ep).unwrap()),
+        format!("{:.3}", fraccion_renovable_acs_nrb(&ep_onst).unwrap())
+    );
+    assert_eq!(
+        format!("{:.3}", ep.rer_nrb),
+        format!("{:.3}", ep_onst.rer_nrb)
+    );
+    assert!(ep_onst.rer_onst > 0.0);
+    assert_eq!(format!("{:.3}", ep.rer_onst), "0.000");
+    // Toma por defecto los factores de suministro de red de TERMOSOLAR
+    assert!(approx_equal(ep.balance.we.b, ep_onst.balance.we.b));
+}
+
 /// Gas rend 90% (40% demanda -> 50kWh) + Biomasa rend 75% + Biomasa densificada rend 75% cada una participando al 50% (75kWh demanda ACS las dos)
 #[test]
 fn cte_ACS_demanda_ren_gas_biomasa_y_biomasa_densificada_125() {
@@ -860,6 +959,59 @@ CONSUMO,ACS,RED2,50"
     assert_eq!(format!("{:.2}", fraccion_ren_acs), "0.30");
 }
 
+/// Red de distrito con factores de paso con periodo de vigencia (100kWh demanda ACS)
+///
+/// La red pasa de ser 50% renovable a 100% renovable en el segundo paso de cálculo, por lo que la
+/// fracción renovable de la demanda, con consumos uniformes, es del 75%
+#[test]
+fn cte_ACS_demanda_ren_red1_vigencia() {
+    let comps = "DEMANDA,ACS,50,50 # Demanda ACS (kWh)
+CONSUMO,ACS,RED1,50,50"
+        .parse::<Components>()
+        .unwrap();
+    let TESTFPEXT = format!(
+        "{}\n{}\n{}",
+        TESTFP,
+        "RED1,RED,SUMINISTRO,A,0.5,0.5,0.0 # Red de distrito 50% renovable",
+        "RED1,RED,SUMINISTRO,A,1.0,0.0,0.0 # Red de distrito 100% renovable CTEEPBD_VIGENCIA=2-"
+    );
+    let FP: Factors = TESTFPEXT.parse().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    let fraccion_ren_acs = fraccion_renovable_acs_nrb(&ep).unwrap();
+    assert_eq!(format!("{:.2}", fraccion_ren_acs), "0.75");
+    // Coherente con la energía ponderada del balance por pasos
+    let we_red1 = ep.balance_cr[&Carrier::RED1].we.a;
+    assert_eq!(format!("{:.2}", we_red1.ren / we_red1.tot()), "0.75");
+}
+
+/// Red de distrito con factores de paso con periodo de vigencia y consumos no uniformes
+///
+/// Todo el consumo se produce en el primer paso, con la red 50% renovable, por lo que la fracción
+/// renovable de la demanda se pondera con los consumos de cada paso (50%) y no con el factor medio (75%)
+#[test]
+fn cte_ACS_demanda_ren_red1_vigencia_ponderada() {
+    let comps = "DEMANDA,ACS,100,0 # Demanda ACS (kWh)
+CONSUMO,ACS,RED1,100,0"
+        .parse::<Components>()
+        .unwrap();
+    let TESTFPEXT = format!(
+        "{}\n{}\n{}",
+        TESTFP,
+        "RED1,RED,SUMINISTRO,A,0.5,0.5,0.0 # Red de distrito 50% renovable",
+        "RED1,RED,SUMINISTRO,A,1.0,0.0,0.0 # Red de distrito 100% renovable CTEEPBD_VIGENCIA=2-"
+    );
+    let FP: Factors = TESTFPEXT.parse().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    let fraccion_ren_acs = fraccion_renovable_acs_nrb(&ep).unwrap();
+    assert_eq!(format!("{:.2}", fraccion_ren_acs), "0.50");
+    // Coherente con la energía ponderada del balance por pasos
+    let we_red1 = ep.balance_cr[&Carrier::RED1].we.a;
+    assert_eq!(format!("{:.2}", we_red1.ren / we_red1.tot()), "0.50");
+    // Mismo resultado sin calcular el balance completo
+    let fraccion_ren_acs = fraccion_renovable_acs_desde_componentes(&comps, &FP).unwrap();
+    assert_eq!(format!("{:.2}", fraccion_ren_acs), "0.50");
+}
+
 /// Bomba de calor (SCOP=2.5) (100kWh demanda ACS)
 #[test]
 fn cte_ACS_demanda_ren_bdc_60ma() {
@@ -999,6 +1151,54 @@ fn cte_ACS_demanda_ren_excluye_aux() {
     assert_eq!(format!("{:.3}", fraccion_ren_acs), "0.917");
 }
 
+/// Fracción renovable de ACS sin balance completo, igual a la obtenida con el balance completo
+#[test]
+fn cte_ACS_demanda_ren_desde_componentes() {
+    let FP: Factors = TESTFP.parse().unwrap();
+    let cases = [
+        "DEMANDA,ACS,100
+CONSUMO,ACS,ELECTRICIDAD,40.0
+CONSUMO,ACS,EAMBIENTE,60
+PRODUCCION,EL_INSITU,10
+CONSUMO,NEPB,ELECTRICIDAD,40.0",
+        "DEMANDA,ACS,100
+CONSUMO,ACS,ELECTRICIDAD,40.0
+CONSUMO,ACS,EAMBIENTE,60
+PRODUCCION,EL_INSITU,10
+PRODUCCION,EL_COGEN,10
+CONSUMO,COGEN,BIOMASA,25",
+        "DEMANDA,ACS,100
+CONSUMO,ACS,GASNATURAL,44.44
+CONSUMO,ACS,TERMOSOLAR,60",
+    ];
+    for case in cases {
+        let comps = case.parse::<Components>().unwrap();
+        let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+        assert_eq!(
+            format!(
+                "{:.3}",
+                fraccion_renovable_acs_desde_componentes(&comps, &FP).unwrap()
+            ),
+            format!("{:.3}", fraccion_renovable_acs_nrb(&ep).unwrap())
+        );
+    }
+
+    let comps = components_from_file("test_data/acs_demanda_ren_con_exclusion_auxiliares.csv");
+    assert_eq!(
+        format!(
+            "{:.3}",
+            fraccion_renovable_acs_desde_componentes(&comps, &FP).unwrap()
+        ),
+        "0.917"
+    );
+
+    // Sin demanda de ACS
+    let comps = "CONSUMO,ACS,ELECTRICIDAD,40.0"
+        .parse::<Components>()
+        .unwrap();
+    assert!(fraccion_renovable_acs_desde_componentes(&comps, &FP).is_err());
+}
+
 /// Componentes con id de sistema explicitados, usos no EPB y exportación a usos nEPB y a la red
 /// La producción declarada de TERMOSOLAR y EAMBIENTE solo se imputa a su sistema (id) si tiene consumo
 /// El consumo no declarado para un sistema se completa automáticamente
@@ -1121,3 +1321,2477 @@ fn cte_prioridades_prod_epus_pv_cogen() {
         format!("{:.3}", ep.balance.prod.epus_by_src[&ProdSource::EL_COGEN])
     );
 }
+
+/// Ponderación de la energía exportada por prioridad de fuente (CTE_PONDERACION_EXPORTACION: PRIORIDAD)
+///
+/// Se exportan 20 kWh de EL_INSITU y 80 kWh de EL_COGEN, de los que 40 kWh van a usos no EPB.
+/// Con el criterio proporcional las dos exportaciones tienen la misma mezcla de fuentes (20% EL_INSITU).
+/// Por prioridad, los usos no EPB toman primero los 20 kWh de EL_INSITU y a la red solo va EL_COGEN.
+#[test]
+fn cte_ponderacion_exportacion_prioridad() {
+    let comps_base = "CONSUMO,ILU,ELECTRICIDAD,100.0
+    CONSUMO,NEPB,ELECTRICIDAD,40.0
+    PRODUCCION,EL_INSITU,120
+    CONSUMO,COGEN,GASNATURAL,200
+    PRODUCCION,EL_COGEN,80";
+    let FP: Factors = TESTFP.parse().unwrap();
+
+    let comps = comps_base.parse::<Components>().unwrap();
+    let ep_prop = energy_performance(&comps, &FP, 1.0, 100.0, false).unwrap();
+    let comps = format!(
+        "#META CTE_PONDERACION_EXPORTACION: PRIORIDAD\n{}",
+        comps_base
+    )
+    .parse::<Components>()
+    .unwrap();
+    assert_eq!(
+        comps.export_weighting().unwrap(),
+        ExportWeighting::PRIORIDAD
+    );
+    let ep_prio = energy_performance(&comps, &FP, 1.0, 100.0, false).unwrap();
+
+    let el_prop = &ep_prop.balance_cr[&Carrier::ELECTRICIDAD];
+    let el_prio = &ep_prio.balance_cr[&Carrier::ELECTRICIDAD];
+    // La energía exportada no cambia, solo su ponderación
+    assert_eq!(el_prop.exp.nepus_an, el_prio.exp.nepus_an);
+    assert_eq!(el_prop.exp.grid_an, el_prio.exp.grid_an);
+    // Energía renovable exportada (paso A): solo la de EL_INSITU (20 kWh, f_ren = 1.0)
+    assert_eq!(format!("{:.1}", el_prop.we.exp_nepus_a.ren), "8.0");
+    assert_eq!(format!("{:.1}", el_prop.we.exp_grid_a.ren), "12.0");
+    assert_eq!(format!("{:.1}", el_prio.we.exp_nepus_a.ren), "20.0");
+    assert_eq!(format!("{:.1}", el_prio.we.exp_grid_a.ren), "0.0");
+    assert_eq!(format!("{:.1}", el_prio.we.exp_a.ren), "20.0");
+
+    // Criterio desconocido
+    let comps = format!("#META CTE_PONDERACION_EXPORTACION: OTRO\n{}", comps_base)
+        .parse::<Components>()
+        .unwrap();
+    assert!(energy_performance(&comps, &FP, 1.0, 100.0, false).is_err());
+}
+
+/// Consumos privativos y comunes en residencial colectivo (CTEEPBD_AMBITO y CTE_AMBITO_EPB_<AMBITO>)
+///
+/// La iluminación de zonas comunes solo entra en el balance EPB si no hay reglas para el ámbito COMUN
+/// o si las reglas la incluyen.
+#[test]
+fn cte_balance_por_ambitos() {
+    let comps_base = "1, CONSUMO, CAL, GASNATURAL, 100.0
+    1, CONSUMO, ACS, GASNATURAL, 50.0
+    2, CONSUMO, ILU, ELECTRICIDAD, 20.0 # Zonas comunes CTEEPBD_AMBITO=COMUN
+    2, CONSUMO, NEPB, ELECTRICIDAD, 30.0 # Ascensores CTEEPBD_AMBITO=COMUN
+    3, CONSUMO, CAL, ELECTRICIDAD, 10.0 # Apoyo central CTEEPBD_AMBITO=COMUN";
+    let FP = get_ctefp_peninsula();
+
+    // Sin reglas: todos los consumos EPB entran en el balance
+    let comps = comps_base.parse::<Components>().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    let comun = &ep.balance_by_scope[&Scope::COMUN];
+    let privativo = &ep.balance_by_scope[&Scope::PRIVATIVO];
+    assert_eq!(comun.used, 30.0);
+    assert_eq!(comun.nepus, 30.0);
+    assert!(comun.excluded_by_srv.is_empty());
+    assert_eq!(privativo.used, 150.0);
+    assert!(approx_equal(comun.we_b + privativo.we_b, ep.balance.we.b));
+
+    // Con reglas: la iluminación común pasa a ser un consumo no EPB
+    let comps = format!("#META CTE_AMBITO_EPB_COMUN: CAL, ACS\n{}", comps_base)
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    let comun = &ep.balance_by_scope[&Scope::COMUN];
+    assert_eq!(comun.used, 10.0);
+    assert_eq!(comun.nepus, 50.0);
+    assert_eq!(comun.excluded_by_srv[&Service::ILU], 20.0);
+    assert_eq!(ep.balance.used.epus, 160.0);
+    assert!(ep.to_plain().contains(
+        "- COMUN: consumo EPB = 0.10, consumo no EPB = 0.50 (excluido del balance EPB: ILU: 0.20)"
+    ));
+
+    // Sin ámbitos declarados no hay desglose
+    let comps = "1, CONSUMO, CAL, GASNATURAL, 100.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    assert!(ep.balance_by_scope.is_empty());
+
+    // Ámbito desconocido o servicio no EPB en las reglas
+    for comps in [
+        "1, CONSUMO, CAL, GASNATURAL, 100.0 # CTEEPBD_AMBITO=PUBLICO".to_string(),
+        format!("#META CTE_AMBITO_EPB_COMUN: NEPB\n{}", comps_base),
+    ] {
+        let comps = comps.parse::<Components>().unwrap();
+        assert!(energy_performance(&comps, &FP, TESTKEXP, 100.0, false).is_err());
+    }
+}
+
+/// Factor de exportación por vector energético (CTE_KEXP_<VECTOR>)
+///
+/// El factor declarado para la electricidad sustituye al general solo para ese vector
+#[test]
+fn cte_kexp_por_vector() {
+    let comps_base = "CONSUMO, CAL, ELECTRICIDAD, 10.0
+    CONSUMO, ACS, GASNATURAL, 20.0
+    PRODUCCION, EL_INSITU, 20.0";
+    let FP = get_ctefp_peninsula();
+
+    let comps = comps_base.parse::<Components>().unwrap();
+    let ep_0 = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    let ep_1 = energy_performance(&comps, &FP, 1.0, 1.0, false).unwrap();
+
+    let comps = format!("#META CTE_KEXP_ELECTRICIDAD: 1.0\n{}", comps_base)
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    assert_eq!(ep.k_exp, 0.0);
+    assert_eq!(ep.k_exp_for(Carrier::ELECTRICIDAD), 1.0);
+    assert_eq!(ep.k_exp_for(Carrier::GASNATURAL), 0.0);
+    assert!(approx_equal(
+        ep_1.balance_cr[&Carrier::ELECTRICIDAD].we.b,
+        ep.balance_cr[&Carrier::ELECTRICIDAD].we.b
+    ));
+    assert!(approx_equal(ep_1.balance.we.b, ep.balance.we.b));
+    assert!(!approx_equal(ep_0.balance.we.b, ep.balance.we.b));
+    assert!(ep.to_plain().contains("k_exp = 0.00 (ELECTRICIDAD: 1.00)"));
+
+    // Factor fuera de rango o vector desconocido
+    for meta in ["CTE_KEXP_ELECTRICIDAD: 1.5", "CTE_KEXP_ELECTRICIDA: 0.5"] {
+        let comps = format!("#META {}\n{}", meta, comps_base)
+            .parse::<Components>()
+            .unwrap();
+        assert!(energy_performance(&comps, &FP, 0.0, 1.0, false).is_err());
+    }
+}
+
+/// Indicador de electrificación: fracción de la energía final EPB que es electricidad
+///
+/// La energía ambiente capturada no cuenta como energía final
+#[test]
+fn cte_electrificacion() {
+    let comps = "CONSUMO, CAL, GASNATURAL, 10, 10, 10, 10, 10, 10, 0, 0, 0, 0, 0, 0
+    CONSUMO, ACS, ELECTRICIDAD, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10
+    CONSUMO, ACS, EAMBIENTE, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5"
+        .parse::<Components>()
+        .unwrap();
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let el = &ep.electrification;
+    assert_eq!(el.final_an, 180.0);
+    assert_eq!(el.el_an, 120.0);
+    assert_eq!(format!("{:.3}", el.fraction), "0.667");
+    assert_eq!(el.fraction_monthly[0], 0.5);
+    assert_eq!(el.fraction_monthly[11], 1.0);
+    let plain = ep.to_plain();
+    assert!(plain.contains("Electrificación (energía final EPB) = 66.7 %"));
+    assert!(plain.contains(
+        "** Electrificación mensual (energía final EPB) [%]\n50.0, 50.0, 50.0, 50.0, 50.0, 50.0, 100.0"
+    ));
+}
+
+/// Producción eléctrica eólica in situ (EL_EOLICA)
+///
+/// Se pondera como EL_INSITU pero se desglosa por separado, usándose tras EL_INSITU
+#[test]
+fn cte_produccion_eolica() {
+    let FP = get_ctefp_peninsula();
+    let comps = "CONSUMO, ILU, ELECTRICIDAD, 100.0
+    PRODUCCION, EL_INSITU, 50.0
+    PRODUCCION, EL_EOLICA, 80.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let by_src = &ep.balance.prod.epus_by_src;
+    assert_eq!(by_src[&ProdSource::EL_INSITU], 50.0);
+    assert_eq!(by_src[&ProdSource::EL_EOLICA], 50.0);
+    assert_eq!(ep.balance.prod.by_src[&ProdSource::EL_EOLICA], 80.0);
+
+    let comps_insitu = "CONSUMO, ILU, ELECTRICIDAD, 100.0
+    PRODUCCION, EL_INSITU, 130.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep_insitu = energy_performance(&comps_insitu, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!(approx_equal(ep_insitu.balance.we.a, ep.balance.we.a));
+    assert!(approx_equal(ep_insitu.balance.we.b, ep.balance.we.b));
+    assert_eq!(ep_insitu.rer, ep.rer);
+    assert_eq!(ep_insitu.rer_onst, ep.rer_onst);
+}
+
+/// Calor cogenerado (TERMICA_COGEN)
+///
+/// El calor útil de la cogeneración aparece en la producción y su excedente se exporta,
+/// sin impacto en el suministro (se contabiliza en el consumo de la cogeneración)
+#[test]
+fn cte_calor_cogenerado() {
+    let FP = get_ctefp_peninsula();
+    let comps = "CONSUMO, COGEN, GASNATURAL, 200.0
+    PRODUCCION, EL_COGEN, 60.0
+    PRODUCCION, TERMICA_COGEN, 120.0
+    CONSUMO, CAL, TERMICA_COGEN, 100.0
+    CONSUMO, ILU, ELECTRICIDAD, 50.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let bal = &ep.balance_cr[&Carrier::TERMICA_COGEN];
+    assert_eq!(bal.prod.an, 120.0);
+    assert_eq!(bal.prod.epus_an, 100.0);
+    assert_eq!(bal.exp.grid_an, 20.0);
+    assert_eq!(bal.del.grid_an, 0.0);
+    assert_eq!(ep.balance.prod.by_src[&ProdSource::TERMICA_COGEN], 120.0);
+    // Sin impacto en el suministro (paso A) y ahorro de la red de distrito en la exportación (paso B)
+    assert_eq!(bal.we.a, RenNrenCo2::default());
+    let fp_red = ep
+        .wfactors
+        .find(
+            Carrier::TERMICA_COGEN,
+            Source::RED,
+            Dest::SUMINISTRO,
+            Step::A,
+        )
+        .unwrap();
+    let fp_red1 = ep
+        .wfactors
+        .find(Carrier::RED1, Source::RED, Dest::SUMINISTRO, Step::A)
+        .unwrap();
+    assert_eq!(fp_red, fp_red1);
+    assert!(approx_equal(bal.we.b, fp_red * -20.0 * TESTKEXP));
+    // Los factores de la electricidad cogenerada solo tienen en cuenta la producción eléctrica
+    let fp_cgn = ep
+        .wfactors
+        .find(
+            Carrier::ELECTRICIDAD,
+            Source::COGEN,
+            Dest::SUMINISTRO,
+            Step::A,
+        )
+        .unwrap();
+    let fp_gn = ep
+        .wfactors
+        .find(Carrier::GASNATURAL, Source::RED, Dest::SUMINISTRO, Step::A)
+        .unwrap();
+    assert!(approx_equal(fp_cgn, fp_gn * (200.0 / 60.0)));
+}
+
+/// Exportación del excedente de calor solar a una red de distrito
+#[test]
+fn cte_exportacion_calor_red_distrito() {
+    let FP = get_ctefp_peninsula();
+    let comps_str = "PRODUCCION, TERMOSOLAR, 100.0
+    CONSUMO, ACS, TERMOSOLAR, 60.0
+    CONSUMO, ILU, ELECTRICIDAD, 50.0";
+    // Sin red de calor declarada, el excedente solar no tiene efecto en el paso B
+    let comps = comps_str.parse::<Components>().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let bal = &ep.balance_cr[&Carrier::TERMOSOLAR];
+    assert_eq!(bal.exp.grid_an, 40.0);
+    let we_b_no_network = bal.we.b;
+    // Con red de calor, el calor exportado ahorra los recursos de suministro de la red
+    let comps = format!("#META CTE_EXPORTACION_CALOR: RED2\n{}", comps_str)
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let bal = &ep.balance_cr[&Carrier::TERMOSOLAR];
+    let fp_red2 = ep
+        .wfactors
+        .find(Carrier::RED2, Source::RED, Dest::SUMINISTRO, Step::A)
+        .unwrap();
+    let fp_exp_b = ep
+        .wfactors
+        .find(Carrier::TERMOSOLAR, Source::INSITU, Dest::A_RED, Step::B)
+        .unwrap();
+    assert_eq!(fp_exp_b, fp_red2);
+    let fp_exp_a = ep
+        .wfactors
+        .find(Carrier::TERMOSOLAR, Source::INSITU, Dest::A_RED, Step::A)
+        .unwrap();
+    assert!(approx_equal(
+        bal.we.b,
+        bal.we.a + (fp_exp_a - fp_red2) * 40.0 * TESTKEXP
+    ));
+    assert!(bal.we.b.nren < we_b_no_network.nren);
+    // Solo se admiten redes de calor
+    let comps = format!("#META CTE_EXPORTACION_CALOR: GASNATURAL\n{}", comps_str)
+        .parse::<Components>()
+        .unwrap();
+    assert!(energy_performance(&comps, &FP, TESTKEXP, 1.0, false).is_err());
+}
+
+/// Recarga de vehículo eléctrico como consumo no EPB (por defecto) o EPB
+#[test]
+fn cte_recarga_vehiculo_electrico() {
+    let FP = get_ctefp_peninsula();
+    let comps_str = "CONSUMO, CAL, ELECTRICIDAD, 100.0
+    CONSUMO, EVCHARGING, ELECTRICIDAD, 50.0 # Punto de recarga";
+    let comps = comps_str.parse::<Components>().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert_eq!(ep.balance.used.epus, 100.0);
+    assert_eq!(ep.balance.used.nepus, 50.0);
+    assert_eq!(ep.balance.used.nepus_by_srv[&Service::VE], 50.0);
+    let we_nepb = ep.balance.we.b;
+    // Recarga como consumo EPB, para todos los consumos (metadato) o para algunos (etiqueta)
+    for comps_str in [
+        format!("#META CTE_VE_EPB: true\n{}", comps_str),
+        format!("{} CTEEPBD_VE_EPB", comps_str),
+    ] {
+        let comps = comps_str.parse::<Components>().unwrap();
+        let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+        assert_eq!(ep.balance.used.epus, 150.0);
+        assert_eq!(ep.balance.used.nepus, 0.0);
+        assert_eq!(ep.balance.used.epus_by_srv[&Service::VE], 50.0);
+        assert!(approx_equal(ep.balance.we.b, we_nepb * 1.5));
+    }
+    let comps = format!("#META CTE_VE_EPB: quizás\n{}", comps_str)
+        .parse::<Components>()
+        .unwrap();
+    assert!(energy_performance(&comps, &FP, TESTKEXP, 1.0, false).is_err());
+}
+
+/// Equipamiento (APP) como consumo no EPB (por defecto) o EPB, independiente de la recarga de vehículos
+#[test]
+fn cte_equipamiento_terciario() {
+    let FP = get_ctefp_peninsula();
+    let comps_str = "#META CTE_USO_EDIFICIO: TERCIARIO
+    CONSUMO, ILU, ELECTRICIDAD, 100.0
+    CONSUMO, APP, ELECTRICIDAD, 40.0 # Equipos de oficina
+    CONSUMO, VE, ELECTRICIDAD, 20.0";
+    let comps = comps_str.parse::<Components>().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert_eq!(ep.balance.used.epus, 100.0);
+    assert_eq!(ep.balance.used.nepus_by_srv[&Service::APP], 40.0);
+
+    let comps = format!("#META CTE_APP_EPB: true\n{}", comps_str)
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert_eq!(ep.balance.used.epus, 140.0);
+    assert_eq!(ep.balance.used.epus_by_srv[&Service::APP], 40.0);
+    assert_eq!(ep.balance.used.nepus_by_srv[&Service::VE], 20.0);
+    // Equipamiento etiquetado como consumo EPB
+    let comps = comps_str
+        .replace("oficina", "oficina CTEEPBD_APP_EPB")
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert_eq!(ep.balance.used.epus, 140.0);
+    assert!(valida_servicios_uso_edificio(&comps, UsoEdificio::TERCIARIO).is_ok());
+}
+
+/// Vectores energéticos definidos por el usuario (redes de distrito adicionales y otros nombres declarados)
+#[test]
+fn cte_vectores_usuario() {
+    let FP = format!(
+        "#META CTE_VECTORES_USUARIO: BIOGAS_LOCAL
+        RED3, RED, SUMINISTRO, A, 0.5, 0.5, 0.1
+        BIOGAS_LOCAL, RED, SUMINISTRO, A, 0.9, 0.1, 0.05
+        {}",
+        get_ctefp_peninsula()
+    )
+    .parse::<Factors>()
+    .unwrap();
+    let comps = "#META CTE_VECTORES_USUARIO: BIOGAS_LOCAL
+    1, CONSUMO, CAL, RED3, 100.0
+    2, CONSUMO, ACS, BIOGAS_LOCAL, 50.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let red3: Carrier = "RED3".parse().unwrap();
+    let biogas = Carrier::USUARIO(UserCarrier::new("BIOGAS_LOCAL").unwrap());
+    assert!(red3.is_user() && red3.is_nearby());
+    assert!(!biogas.is_nearby());
+    assert!(approx_equal(
+        ep.balance_cr[&red3].we.b,
+        RenNrenCo2::new(50.0, 50.0, 10.0)
+    ));
+    assert!(approx_equal(
+        ep.balance_cr[&biogas].we.b,
+        RenNrenCo2::new(45.0, 5.0, 2.5)
+    ));
+    // Los vectores de usuario se muestran y serializan con su nombre
+    assert_eq!(serde_json::to_string(&biogas).unwrap(), "\"BIOGAS_LOCAL\"");
+    assert_eq!(serde_json::from_str::<Carrier>("\"RED3\"").unwrap(), red3);
+    assert!(ep.to_plain().contains("RED3"));
+    // Los nombres no declarados siguen siendo errores de formato
+    assert!("1, CONSUMO, CAL, BIOGAS_VECINAL, 100.0"
+        .parse::<Components>()
+        .is_err());
+    // No se pueden redefinir los vectores predefinidos
+    assert!(UserCarrier::new("GASOLEO").is_err());
+    assert!(UserCarrier::new("gas local").is_err());
+    assert!(UserCarrier::new(&"A".repeat(USER_CARRIER_MAX_LEN + 1)).is_err());
+}
+
+/// Los vectores de usuario declarados en unos datos no son válidos al interpretar otros datos
+#[test]
+fn cte_vectores_usuario_aislados() {
+    let comps = "#META CTE_VECTORES_USUARIO: BIOGAS_LOCAL
+    1, CONSUMO, ACS, BIOGAS_LOCAL, 50.0"
+        .parse::<Components>()
+        .unwrap();
+    assert_eq!(comps.data[0].carrier().to_string(), "BIOGAS_LOCAL");
+    // Los datos interpretados a continuación deben declarar sus propios vectores de usuario
+    assert!("1, CONSUMO, ACS, BIOGAS_LOCAL, 50.0"
+        .parse::<Components>()
+        .is_err());
+    assert!("BIOGAS_LOCAL, RED, SUMINISTRO, A, 0.9, 0.1, 0.05"
+        .parse::<Factors>()
+        .is_err());
+    assert!("BIOGAS_LOCAL".parse::<Carrier>().is_err());
+    // Las redes de distrito de usuario no necesitan declararse
+    assert!("1, CONSUMO, CAL, RED4, 100.0".parse::<Components>().is_ok());
+}
+
+/// Vector HIDROGENO con factores de paso por defecto y definidos por el usuario
+#[test]
+fn cte_hidrogeno() {
+    let comps = "1, CONSUMO, CAL, HIDROGENO, 100.0 # Caldera de hidrógeno"
+        .parse::<Components>()
+        .unwrap();
+    let no_user_wf = UserWF::default();
+    let FP = wfactors_from_loc("PENINSULA", &CTE_LOCWF_RITE2014, no_user_wf, CTE_USERWF).unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!(approx_equal(
+        ep.balance.we.b,
+        RenNrenCo2::new(0.0, 170.0, 36.0)
+    ));
+    assert!(!Carrier::HIDROGENO.is_nearby());
+
+    // Hidrógeno renovable definido por el usuario
+    let user_wf = no_user_wf.with_hidrogeno(Some(RenNrenCo2::new(1.0, 0.1, 0.02)));
+    let FP = wfactors_from_loc("PENINSULA", &CTE_LOCWF_RITE2014, user_wf, CTE_USERWF).unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!(approx_equal(
+        ep.balance.we.b,
+        RenNrenCo2::new(100.0, 10.0, 2.0)
+    ));
+}
+
+/// Coste anual de la energía a partir de los precios declarados en los factores de paso
+#[test]
+fn cte_coste_energia() {
+    let mut FP = get_ctefp_peninsula();
+    let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 100.0, 0.0
+    1, PRODUCCION, EL_INSITU, 40.0, 60.0
+    2, CONSUMO, ACS, GASNATURAL, 25.0, 25.0"
+        .parse::<Components>()
+        .unwrap();
+    // Sin precios no se calcula el coste
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 10.0, false).unwrap();
+    assert!(ep.energy_cost.is_none());
+
+    for f in FP.wdata.iter_mut() {
+        if f.carrier == Carrier::ELECTRICIDAD && f.step == Step::A {
+            match (f.source, f.dest) {
+                (Source::RED, Dest::SUMINISTRO) => f.comment.push_str(" CTEEPBD_COSTE=0.20"),
+                (Source::INSITU, Dest::A_RED) => f.comment.push_str(" CTEEPBD_COSTE=0.05"),
+                _ => (),
+            }
+        }
+    }
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 10.0, false).unwrap();
+    let cost = ep.energy_cost.as_ref().unwrap();
+    // Suministro: 60 kWh x 0.20 €/kWh, exportación: 60 kWh x 0.05 €/kWh
+    assert!((cost.del_by_cr[&Carrier::ELECTRICIDAD] - 12.0).abs() < 0.01);
+    assert!((cost.exp_by_cr[&Carrier::ELECTRICIDAD] - 3.0).abs() < 0.01);
+    assert!((cost.total - 9.0).abs() < 0.01);
+    assert!((cost.total_m2 - 0.9).abs() < 0.01);
+    // Coste por servicio: la electricidad es de calefacción y el gas no tiene precio
+    assert!((cost.by_srv[&Service::CAL] - 9.0).abs() < 0.01);
+    assert_eq!(cost.by_srv.get(&Service::ACS), None);
+    // El gas natural no tiene precio declarado
+    assert_eq!(cost.unpriced, vec![Carrier::GASNATURAL]);
+    assert!(ep
+        .to_plain()
+        .contains("Coste neto: 9.00 €/an (0.90 €/m2.an)\nCoste neto por servicio: CAL = 9.00"));
+
+    // Cambio del área de referencia
+    let ep = ep.with_arearef(20.0).unwrap();
+    let cost = ep.energy_cost.as_ref().unwrap();
+    assert!((cost.total - 9.0).abs() < 0.01);
+    assert!((cost.total_m2 - 0.45).abs() < 0.01);
+}
+
+/// Coste de la energía con precios por paso de cálculo (periodos de vigencia) y por servicio
+#[test]
+fn cte_coste_energia_por_pasos() {
+    let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 100.0, 0.0
+    2, CONSUMO, ACS, ELECTRICIDAD, 0.0, 50.0"
+        .parse::<Components>()
+        .unwrap();
+    let FP: Factors = format!(
+        "{}\n{}\n{}",
+        "ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331 # Tarifa base CTEEPBD_COSTE=0.20",
+        "ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331 # Tarifa nueva CTEEPBD_COSTE=0.10 CTEEPBD_VIGENCIA=2-",
+        "ELECTRICIDAD, INSITU, SUMINISTRO, A, 1.000, 0.000, 0.000"
+    )
+    .parse()
+    .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 10.0, false).unwrap();
+    let cost = ep.energy_cost.as_ref().unwrap();
+    // Paso 1: 100 kWh x 0.20 €/kWh, paso 2: 50 kWh x 0.10 €/kWh
+    assert!((cost.del_by_cr[&Carrier::ELECTRICIDAD] - 25.0).abs() < 0.01);
+    assert!((cost.total - 25.0).abs() < 0.01);
+    // Reparto por servicios según el consumo anual de cada servicio
+    assert!((cost.by_srv[&Service::CAL] - 25.0 * 100.0 / 150.0).abs() < 0.01);
+    assert!((cost.by_srv[&Service::ACS] - 25.0 * 50.0 / 150.0).abs() < 0.01);
+}
+
+/// Resultados del balance en valores absolutos y por m2 en todos los niveles de desglose
+#[test]
+fn cte_balance_unidades() {
+    let FP = get_ctefp_peninsula();
+    let comps = "#META CTE_REPARTO_1A: 0.6\n#META CTE_REPARTO_1B: 0.4
+    1, CONSUMO, CAL, GASNATURAL, 100.0 # CTEEPBD_UNIDAD=1A
+    2, CONSUMO, CAL, GASNATURAL, 50.0 # CTEEPBD_UNIDAD=1B
+    3, CONSUMO, ILU, ELECTRICIDAD, 40.0 # Zonas comunes CTEEPBD_AMBITO=COMUN
+    3, PRODUCCION, EL_INSITU, 30.0 # FV común"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 200.0, false).unwrap();
+
+    // Balance global
+    assert!(approx_equal(
+        ep.balance_in(BalanceUnits::PerArea).we.b,
+        ep.balance_m2.we.b
+    ));
+    assert!(approx_equal(
+        ep.balance_in(BalanceUnits::Absolute).we.b,
+        ep.balance.we.b
+    ));
+
+    // Balance por vector, que suma el global en ambas unidades
+    let by_cr_m2 = ep.balance_cr_in(BalanceUnits::PerArea);
+    let gas = &by_cr_m2[&Carrier::GASNATURAL];
+    assert!((gas.used.epus_an - 0.75).abs() < 1e-4);
+    assert!((gas.used.epus_t[0] - 0.75).abs() < 1e-4);
+    let total_m2 = by_cr_m2
+        .values()
+        .fold(RenNrenCo2::default(), |acc, bal| acc + bal.we.b);
+    assert!(approx_equal(total_m2, ep.balance_m2.we.b));
+    assert_eq!(
+        ep.balance_cr_in(BalanceUnits::Absolute)[&Carrier::GASNATURAL]
+            .used
+            .epus_an,
+        150.0
+    );
+
+    // Balance por sistema, por ámbito y reparto entre unidades
+    assert!((ep.balance_by_system_in(BalanceUnits::PerArea)[&1].used - 0.5).abs() < 1e-4);
+    assert_eq!(
+        ep.balance_by_system_in(BalanceUnits::Absolute)[&1].used,
+        100.0
+    );
+    assert!((ep.balance_by_scope_in(BalanceUnits::PerArea)[&Scope::COMUN].used - 0.2).abs() < 1e-4);
+    let shares_m2 = ep.unit_shares_in(BalanceUnits::PerArea);
+    assert_eq!(shares_m2["1A"].coef, 0.6);
+    assert!((shares_m2["1A"].used_own - 0.5).abs() < 1e-4);
+
+    // Formato de texto plano en ambas unidades, con los indicadores siempre por m2
+    let plain_abs = ep.to_plain_in(BalanceUnits::Absolute);
+    assert!(plain_abs.contains("** Energía final (todos los vectores) [kWh/an]:"));
+    assert!(plain_abs.contains("** Reparto entre unidades (informativo) [kWh/an]"));
+    let plain_m2 = ep.to_plain();
+    assert!(plain_m2.contains("** Energía final (todos los vectores) [kWh/m2.an]:"));
+    assert!(plain_m2.contains("** Reparto entre unidades (informativo) [kWh/m2.an]"));
+    let cep = |plain: &str| {
+        plain
+            .lines()
+            .find(|l| l.starts_with("C_ep [kWh/m2.an]"))
+            .map(str::to_string)
+    };
+    assert_eq!(cep(&plain_abs), cep(&plain_m2));
+
+    // Unidades por nombre
+    assert_eq!(
+        "abs".parse::<BalanceUnits>().unwrap(),
+        BalanceUnits::Absolute
+    );
+    assert!("kWh".parse::<BalanceUnits>().is_err());
+}
+
+/// Avisos estructurados de la normalización de componentes, el balance y los indicadores adicionales
+#[test]
+fn cte_avisos_estructurados() {
+    let FP = get_ctefp_peninsula();
+    let comps = "1, CONSUMO, CAL, GASNATURAL, 100.0, 50.0
+        1, CONSUMO, ACS, GASNATURAL, 20.0, 30.0
+        1, AUX, 4.0, 2.0
+        SISTEMA, 1, RENDIMIENTO, CAL, 0.9
+        SISTEMA, 1, RENDIMIENTO, ACS, 0.8"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    let codes: Vec<_> = ep.warnings.iter().map(|w| w.code).collect();
+    assert_eq!(
+        vec![
+            WarningCode::SALIDA_GENERADA,
+            WarningCode::SALIDA_GENERADA,
+            WarningCode::AUX_REPARTIDO
+        ],
+        codes
+    );
+    assert!(ep.warnings.iter().all(|w| w.severity == Severity::Info));
+    assert_eq!(Some("1, AUX"), ep.warnings[2].component.as_deref());
+
+    // Sin demanda de ACS no se calcula la fracción renovable de la demanda de ACS
+    let ep = incorpora_demanda_renovable_acs_nrb(ep);
+    let ep = incorpora_demanda_renovable_acs_nrb(ep);
+    let not_computed: Vec<_> = ep
+        .warnings_for(WarningCode::INDICADOR_NO_CALCULADO)
+        .collect();
+    assert_eq!(1, not_computed.len());
+    assert_eq!(
+        Some("fraccion_renovable_demanda_acs_nrb"),
+        not_computed[0].component.as_deref()
+    );
+    assert!(!ep
+        .misc
+        .unwrap()
+        .contains_key("fraccion_renovable_demanda_acs_nrb"));
+
+    // Consumos negativos
+    let comps = components_from_file("test_data/consumos_negativos.csv");
+    let FP: Factors = TESTFP.parse().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    let negative: Vec<_> = ep.warnings_for(WarningCode::CONSUMO_NEGATIVO).collect();
+    assert_eq!(1, negative.len());
+    assert_eq!(Severity::Warning, negative[0].severity);
+    assert_eq!(
+        Some("2, ELECTRICIDAD, CONSUMO, REF"),
+        negative[0].component.as_deref()
+    );
+    assert!(negative[0]
+        .to_string()
+        .starts_with("AVISO: consumo negativo"));
+}
+
+/// Energía primaria renovable in situ utilizada, por origen y por servicio
+#[test]
+fn cte_renovable_insitu_utilizada() {
+    let FP = get_ctefp_peninsula();
+    let comps = "1, PRODUCCION, EL_INSITU, 10.0, 10.0
+        1, CONSUMO, ILU, ELECTRICIDAD, 8.0, 15.0
+        2, CONSUMO, ACS, EAMBIENTE, 5.0, 5.0
+        2, CONSUMO, ACS, ELECTRICIDAD, 2.0, 2.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 10.0, false).unwrap();
+    let onst = ep.onsite_renewable.as_ref().unwrap();
+    assert_eq!("30.00", format!("{:.2}", onst.used_an));
+    assert_eq!("30.00", format!("{:.2}", onst.ren_an));
+    assert_eq!(
+        "20.00",
+        format!("{:.2}", onst.ren_by_src[&ProdSource::EL_INSITU])
+    );
+    assert_eq!(
+        "10.00",
+        format!("{:.2}", onst.ren_by_src[&ProdSource::EAMBIENTE])
+    );
+    assert_eq!(
+        "30.00",
+        format!(
+            "{:.2}",
+            onst.ren_by_srv[&Service::ACS] + onst.ren_by_srv[&Service::ILU]
+        )
+    );
+    assert!(onst.ren_by_srv[&Service::ACS] > 10.0);
+
+    // Por m2 de área de referencia
+    let onst_m2 = ep.onsite_renewable_in(BalanceUnits::PerArea).unwrap();
+    assert_eq!("3.00", format!("{:.2}", onst_m2.ren_an));
+
+    // Sin producción in situ
+    let comps = "1, CONSUMO, ILU, ELECTRICIDAD, 8.0, 15.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 10.0, false).unwrap();
+    assert!(ep.onsite_renewable.is_none());
+}
+
+/// Cálculo con opciones de cálculo, equivalente a las funciones con parámetros
+#[test]
+fn cte_opciones_calculo() {
+    let comps = components_from_file("test_data/ejemploJ7_cogenfuelgasboiler.csv");
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, 0.5, 150.0, true).unwrap();
+    let options = CalcOptions::new(0.5, 150.0).load_matching(true);
+    let ep_with = energy_performance_with(&comps, &FP, &options).unwrap();
+    assert_eq!(ep.balance_m2.we.b, ep_with.balance_m2.we.b);
+    assert_eq!(ep.rer, ep_with.rer);
+
+    // Las tolerancias forman parte de las opciones
+    let options = CalcOptions {
+        arearef: 1e-4,
+        ..options
+    };
+    assert!(energy_performance_with(&comps, &FP, &options).is_err());
+    let tolerances = Tolerances {
+        arearef_min: 1e-5,
+        ..Default::default()
+    };
+    assert!(energy_performance_with(&comps, &FP, &options.tolerances(tolerances)).is_ok());
+}
+
+/// El cálculo que toma posesión de los componentes y factores da los mismos resultados
+#[test]
+fn cte_calculo_sin_copias() {
+    let FP = get_ctefp_peninsula();
+    let options = CalcOptions::new(TESTKEXP, 200.0).load_matching(true);
+    for path in [
+        "test_data/ejemploJ3_basePVexcess.csv",
+        "test_data/ejemploJ7_cogenfuelgasboiler.csv",
+        "test_data/acs_demanda_ren_con_nepb.csv",
+    ] {
+        let comps = components_from_file(path);
+        let ep = energy_performance_with(&comps, &FP, &options).unwrap();
+        let ep_from = energy_performance_from(comps, FP.clone(), &options).unwrap();
+        assert_eq!(ep.to_plain(), ep_from.to_plain(), "{}", path);
+        assert_eq!(
+            ep.whole_building.map(|wb| wb.balance_m2.we.b),
+            ep_from.whole_building.map(|wb| wb.balance_m2.we.b)
+        );
+    }
+}
+
+/// Coherencia de la localización declarada con los factores de paso
+#[test]
+fn cte_localizacion_factores() {
+    let mut FP = get_ctefp_peninsula();
+    assert_eq!(wfactors_locations(&FP), vec!["PENINSULA"]);
+    assert!(check_wfactors_location("PENINSULA", &FP).is_ok());
+    assert!(check_wfactors_location("CANARIAS", &FP).is_err());
+
+    // Sin metadato de localización se usa el factor de la electricidad de red
+    FP.wmeta.retain(|m| m.key != "CTE_LOCALIZACION");
+    assert!(check_wfactors_location(" peninsula ", &FP).is_ok());
+    assert!(check_wfactors_location("BALEARES", &FP).is_err());
+
+    // Factores propios, sin coincidencia con las tablas reglamentarias
+    let FP: Factors = TESTFP.parse().unwrap();
+    assert!(wfactors_locations(&FP).is_empty());
+    assert!(check_wfactors_location("CANARIAS", &FP).is_ok());
+}
+
+/// Análisis incremental de medidas de mejora
+#[test]
+fn cte_medidas_mejora() {
+    let FP = get_ctefp_peninsula();
+    let comps = "#META CTE_AREAREF: 100
+1, CONSUMO, CAL, GASNATURAL, 1000
+1, CONSUMO, ACS, GASNATURAL, 200
+SISTEMA, 1, RENDIMIENTO, CAL, 0.9
+SISTEMA, 1, RENDIMIENTO, ACS, 0.9
+2, CONSUMO, REF, ELECTRICIDAD, 100"
+        .parse::<Components>()
+        .unwrap()
+        .normalize()
+        .unwrap();
+    let sum_for = |comps: &Components, f: &dyn Fn(&Energy) -> bool| -> f32 {
+        comps
+            .data
+            .iter()
+            .filter(|c| f(c))
+            .map(|c| c.values_sum())
+            .sum()
+    };
+
+    // Bomba de calor: misma energía entregada con consumo eléctrico y de energía ambiente
+    let hp = Measure::HeatPump { id: 1, scop: 3.0 };
+    let comps_hp = hp.apply(&comps).unwrap();
+    assert_eq!(
+        sum_for(&comps_hp, &|c| c.is_used()
+            && c.has_carrier(Carrier::GASNATURAL)),
+        0.0
+    );
+    assert_eq!(
+        format!(
+            "{:.1}",
+            sum_for(&comps_hp, &|c| c.is_used()
+                && c.has_carrier(Carrier::ELECTRICIDAD)
+                && c.has_service(Service::CAL))
+        ),
+        "300.0"
+    );
+    let amb_used = sum_for(&comps_hp, &|c| {
+        c.is_used() && c.has_carrier(Carrier::EAMBIENTE)
+    });
+    let amb_prod = sum_for(&comps_hp, &|c| {
+        c.is_generated() && c.has_carrier(Carrier::EAMBIENTE)
+    });
+    assert_eq!(format!("{:.1}", amb_used), "720.0");
+    assert_eq!(format!("{:.1}", amb_prod), "720.0");
+
+    // Reducción de consumos tras la bomba de calor: se ajusta la producción de energía ambiente
+    let reduce = Measure::ReduceUse {
+        service: Service::CAL,
+        fraction: 0.5,
+    };
+    let comps_hp_reduce = reduce.apply(&comps_hp).unwrap();
+    let amb_prod = sum_for(&comps_hp_reduce, &|c| {
+        c.is_generated() && c.has_carrier(Carrier::EAMBIENTE)
+    });
+    assert_eq!(format!("{:.1}", amb_prod), "420.0");
+
+    // Fotovoltaica: producción y potencia pico del nuevo sistema
+    let pv = Measure::AddPv {
+        kwp: 0.2,
+        yield_t: vec![1500.0],
+    };
+    let comps_pv = pv.apply(&comps).unwrap();
+    assert_eq!(
+        sum_for(&comps_pv, &|c| c.is_generated()
+            && c.has_carrier(Carrier::ELECTRICIDAD)),
+        300.0
+    );
+    assert_eq!(comps_pv.peak_powers.len(), 1);
+    assert_eq!(comps_pv.peak_powers[0].id, 3);
+
+    // Errores: sistema sin energía entregada y parámetros fuera de rango
+    assert!(Measure::HeatPump { id: 2, scop: 3.0 }
+        .apply(&comps)
+        .is_err());
+    assert!(Measure::ReduceUse {
+        service: Service::CAL,
+        fraction: 1.5
+    }
+    .apply(&comps)
+    .is_err());
+    assert!(Measure::AddPv {
+        kwp: 2.0,
+        yield_t: vec![100.0; 12]
+    }
+    .apply(&comps)
+    .is_err());
+
+    // Ahorros por medida y del conjunto de medidas
+    let options = CalcOptions::new(TESTKEXP, 100.0);
+    let analysis = measures_analysis(&comps, &FP, &[hp, reduce, pv], &options).unwrap();
+    assert_eq!(analysis.base.savings_m2, RenNrenCo2::default());
+    assert_eq!(analysis.base.savings_nren_pct, 0.0);
+    assert_eq!(analysis.measures.len(), 3);
+    for res in &analysis.measures {
+        assert!(res.savings_m2.nren > 0.0, "{}", res.name);
+        assert!(res.savings_nren_pct > 0.0 && res.savings_nren_pct < 100.0);
+        assert!(analysis.combined.savings_m2.nren > res.savings_m2.nren);
+    }
+    let table = analysis.to_string();
+    assert!(table.contains("Caso base; "));
+    assert!(table.contains("Sustitución del sistema 1 por bomba de calor (SCOP 3.00); "));
+    assert!(table.contains("Conjunto de medidas; "));
+}
+
+/// Reparto de los consumos comunes y la producción fotovoltaica común entre unidades
+#[test]
+fn cte_reparto_entre_unidades() {
+    let FP = get_ctefp_peninsula();
+    let comps_base = "1, CONSUMO, CAL, GASNATURAL, 100.0 # CTEEPBD_UNIDAD=1A
+    2, CONSUMO, CAL, GASNATURAL, 50.0 # CTEEPBD_UNIDAD=1B
+    2, CONSUMO, ACS, ELECTRICIDAD, 20.0 # CTEEPBD_UNIDAD=1B
+    3, CONSUMO, ILU, ELECTRICIDAD, 40.0 # Zonas comunes CTEEPBD_AMBITO=COMUN
+    3, PRODUCCION, EL_INSITU, 30.0 # FV común";
+
+    // Coeficientes de reparto declarados
+    let comps = format!(
+        "#META CTE_REPARTO_1A: 0.6\n#META CTE_REPARTO_1B: 0.4\n{}",
+        comps_base
+    )
+    .parse::<Components>()
+    .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    let a = &ep.unit_shares["1A"];
+    let b = &ep.unit_shares["1B"];
+    assert_eq!(a.coef, 0.6);
+    assert_eq!(a.used_own, 100.0);
+    assert!((a.used_common - 24.0).abs() < 1e-4);
+    assert!((a.prod_el - 18.0).abs() < 1e-4);
+    assert_eq!(b.used_own, 70.0);
+    assert!((b.used_by_cr[&Carrier::ELECTRICIDAD] - 36.0).abs() < 1e-4);
+    // El reparto conserva la energía ponderada del edificio
+    assert!(approx_equal(a.we_b + b.we_b, ep.balance.we.b));
+    assert!(ep
+        .to_plain()
+        .contains("** Reparto entre unidades (informativo)"));
+
+    // Sin coeficientes, el reparto es proporcional al consumo privativo
+    let comps = comps_base.parse::<Components>().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    assert!((ep.unit_shares["1A"].coef - 100.0 / 170.0).abs() < 1e-4);
+    let total = ep.unit_shares["1A"].we_b + ep.unit_shares["1B"].we_b;
+    assert!(approx_equal(total, ep.balance.we.b));
+
+    // Coeficientes que no cubren todas las unidades o no suman la unidad
+    for meta in [
+        "#META CTE_REPARTO_1A: 0.6",
+        "#META CTE_REPARTO_1A: 0.6\n#META CTE_REPARTO_1B: 0.6",
+    ] {
+        let comps = format!("{}\n{}", meta, comps_base)
+            .parse::<Components>()
+            .unwrap();
+        assert!(energy_performance(&comps, &FP, TESTKEXP, 100.0, false).is_err());
+    }
+}
+
+/// Necesidades no cubiertas por los sistemas (SALIDA frente a DEMANDA)
+#[test]
+fn cte_necesidades_no_cubiertas() {
+    let FP = get_ctefp_peninsula();
+    let comps = "DEMANDA, CAL, 100, 80, 60, 0, 0, 0, 0, 0, 0, 0, 50, 100
+    1, CONSUMO, CAL, GASNATURAL, 100, 100, 60, 0, 0, 0, 0, 0, 0, 0, 50, 70
+    1, SALIDA, CAL, 90, 90, 54, 0, 0, 0, 0, 0, 0, 0, 45, 63
+    DEMANDA, ACS, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10
+    2, CONSUMO, ACS, GASNATURAL, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10
+    2, SALIDA, ACS, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let unmet = &ep.unmet_needs[&Service::CAL];
+    assert_eq!(unmet.needs_an, 390.0);
+    assert_eq!(unmet.out_an, 342.0);
+    assert_eq!(unmet.unmet_t[0], 10.0);
+    assert_eq!(unmet.unmet_t[1], 0.0);
+    assert_eq!(unmet.unmet_an, 58.0);
+    assert_eq!(unmet.unmet_steps, 4);
+    assert_eq!(unmet.unmet_hours, Some((31.0 + 31.0 + 30.0 + 31.0) * 24.0));
+    assert!(unmet.exceeds(&ep.tolerances));
+    let unmet_acs = &ep.unmet_needs[&Service::ACS];
+    assert_eq!(unmet_acs.unmet_an, 0.0);
+    assert!(!unmet_acs.exceeds(&ep.tolerances));
+    assert!(!ep.unmet_needs.contains_key(&Service::REF));
+    assert!(ep.to_plain().contains(
+        "- CAL: demanda = 390.00, energía entregada = 342.00, no cubierta = 58.00 (14.9 %), pasos no cubiertos = 4 (2952 h)"
+    ));
+}
+
+/// Tolerancias configurables
+///
+/// Con valores horarios pequeños el umbral de producción por defecto (1e-3) anula el reparto
+/// de la producción por fuentes. Reduciendo la tolerancia se recupera el reparto.
+#[test]
+fn cte_tolerancias_configurables() {
+    let comps = "CONSUMO,CAL,ELECTRICIDAD,0.002,0.002,0.002,0.002
+    PRODUCCION,EL_INSITU,0.0005,0.0005,0.0005,0.0005"
+        .parse::<Components>()
+        .unwrap();
+    let FP: Factors = TESTFP.parse().unwrap();
+
+    // Tolerancias por defecto
+    let ep = energy_performance(&comps, &FP, 1.0, 100.0, false).unwrap();
+    assert_eq!(Tolerances::default(), ep.tolerances);
+    assert_eq!(
+        "0.000000",
+        format!("{:.6}", ep.balance.prod.epus_by_src[&ProdSource::EL_INSITU])
+    );
+
+    // Tolerancias reducidas
+    let tolerances = Tolerances {
+        arearef_min: 1e-6,
+        prod_min: 0.0,
+        ..Default::default()
+    };
+    let ep =
+        energy_performance_with_tolerances(&comps, &FP, 1.0, 1e-4, false, &tolerances).unwrap();
+    assert_eq!(
+        "0.002000",
+        format!("{:.6}", ep.balance.prod.epus_by_src[&ProdSource::EL_INSITU])
+    );
+
+    // Área de referencia por debajo del mínimo
+    assert!(energy_performance(&comps, &FP, 1.0, 1e-4, false).is_err());
+}
+
+/// Compensación simplificada de excedentes
+///
+/// Con k_comp == k_exp se obtiene el resultado estándar y con k_comp == 0 la electricidad
+/// exportada no reduce la energía ponderada (coincide con el paso A en este caso)
+#[test]
+fn cte_J3_compensacion_simplificada() {
+    let comps = components_from_file("test_data/ejemploJ3_basePVexcess.csv");
+    let FP: Factors = TESTFPJ.parse().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+
+    let we_b = compensacion_simplificada(&ep, TESTKEXP).unwrap();
+    assert!(approx_equal(ep.balance.we.b, we_b));
+
+    let we_b = compensacion_simplificada(&ep, 0.0).unwrap();
+    assert!(approx_equal(
+        RenNrenCo2 {
+            ren: 100.0,
+            nren: 0.0,
+            co2: 0.0,
+        },
+        we_b
+    ));
+
+    let we_b = compensacion_simplificada(&ep, 0.5).unwrap();
+    assert!(approx_equal(
+        RenNrenCo2 {
+            ren: 110.0,
+            nren: -40.0,
+            co2: -8.4,
+        },
+        we_b
+    ));
+
+    assert!(compensacion_simplificada(&ep, 1.5).is_err());
+
+    let ep = incorpora_compensacion_simplificada(ep, 0.0);
+    let misc = ep.misc.unwrap();
+    assert_eq!("0.000", misc["compensacion_simplificada_factor"]);
+    assert_eq!("100.000", misc["compensacion_simplificada_tot"]);
+    assert_eq!("1.000", misc["compensacion_simplificada_rer"]);
+}
+
+/// Demandas por zonas y coherencia con la demanda del edificio
+#[test]
+fn cte_demanda_zonas() {
+    let comps = components_from_file("test_data/demanda_zonas.csv");
+    assert_eq!(comps.needs.zones.len(), 2);
+    let FP: Factors = TESTFP.parse().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    let needs = &ep.balance.needs;
+    assert_eq!("360.0", format!("{:.1}", needs.CAL.unwrap()));
+    assert_eq!("200.0", format!("{:.1}", needs.zones[&1][&Service::CAL]));
+    assert_eq!("180.0", format!("{:.1}", needs.zones[&2][&Service::CAL]));
+    assert_eq!("20.0", format!("{:.1}", needs.zones_dev[&Service::CAL]));
+    assert_eq!(
+        "0.200",
+        format!("{:.3}", ep.balance_m2.needs.zones_dev[&Service::CAL])
+    );
+
+    // Desvío relativo del 5,6% respecto a la demanda del edificio
+    let mismatch = needs.zones_mismatch(ep.tolerances.needs_zones_dev_max);
+    assert_eq!(1, mismatch.len());
+    assert_eq!(Service::CAL, mismatch[0].0);
+    assert_eq!("0.056", format!("{:.3}", mismatch[0].1));
+    assert!(needs.zones_mismatch(0.1).is_empty());
+}
+
+/// Cobertura renovable in situ de VEN e ILU
+#[test]
+fn cte_cobertura_renovable_onst_ven_ilu() {
+    let comps = components_from_file("test_data/acs_demanda_ren_con_nepb.csv");
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert_eq!(
+        "0.296",
+        format!(
+            "{:.3}",
+            cobertura_renovable_onst(&ep, Service::ILU).unwrap()
+        )
+    );
+    // Sin consumo de VEN
+    assert!(cobertura_renovable_onst(&ep, Service::VEN).is_err());
+
+    let ep = incorpora_cobertura_renovable_onst(ep);
+    let misc = ep.misc.unwrap();
+    assert_eq!("0.296", misc["cobertura_renovable_onst_ILU"]);
+    assert!(!misc.contains_key("cobertura_renovable_onst_VEN"));
+}
+
+/// Consumos EPB negativos: avisos y tratamiento configurable
+#[test]
+fn cte_consumos_negativos() {
+    let comps = components_from_file("test_data/consumos_negativos.csv");
+    let FP: Factors = TESTFP.parse().unwrap();
+
+    let negative_uses = |ep: &EnergyPerformance| -> Vec<Warning> {
+        ep.warnings
+            .iter()
+            .filter(|w| w.code == WarningCode::CONSUMO_NEGATIVO)
+            .cloned()
+            .collect()
+    };
+
+    // Se permiten, con aviso
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    assert_eq!(
+        vec![Warning::warn(
+            WarningCode::CONSUMO_NEGATIVO,
+            "consumo negativo de ELECTRICIDAD para REF en el sistema con id:2 (-10.00 kWh en 2 pasos de cálculo) (tratamiento: permitir)"
+        )
+        .with_component("2, ELECTRICIDAD, CONSUMO, REF")],
+        negative_uses(&ep)
+    );
+    assert_eq!(
+        "100.00",
+        format!("{:.2}", ep.balance.used.epus_by_srv[&Service::REF])
+    );
+
+    // Se truncan, con aviso
+    let options = CalcOptions::new(TESTKEXP, 100.0).negative_use(NegativeUsePolicy::Truncate);
+    let ep = energy_performance_with(&comps, &FP, &options).unwrap();
+    assert_eq!(1, negative_uses(&ep).len());
+    assert_eq!(
+        "110.00",
+        format!("{:.2}", ep.balance.used.epus_by_srv[&Service::REF])
+    );
+
+    // Error
+    let options = CalcOptions::new(TESTKEXP, 100.0).negative_use(NegativeUsePolicy::Error);
+    assert!(energy_performance_with(&comps, &FP, &options).is_err());
+}
+
+/// Excedentes de solar térmica destinados al calentamiento de piscinas (uso no EPB)
+#[test]
+fn cte_piscina_termosolar() {
+    let comps = components_from_file("test_data/piscina_termosolar.csv");
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    assert!(!Service::PISCINA.is_epb());
+    assert!(Service::PISCINA.is_nepb());
+
+    let bal_cr = &ep.balance_cr[&Carrier::TERMOSOLAR];
+    assert_eq!("580.0", format!("{:.1}", bal_cr.used.nepus_an));
+    assert_eq!(
+        "580.0",
+        format!("{:.1}", bal_cr.used.nepus_by_srv_an[&Service::PISCINA])
+    );
+    assert_eq!(
+        "580.0",
+        format!("{:.1}", bal_cr.exp.nepus_by_srv_an[&Service::PISCINA])
+    );
+
+    // La piscina no computa en el consumo EPB
+    let bal = &ep.balance;
+    assert!(!bal.used.epus_by_srv.contains_key(&Service::PISCINA));
+    assert_eq!("1200.0", format!("{:.1}", bal.used.epus));
+    assert_eq!(
+        "580.0",
+        format!("{:.1}", bal.used.nepus_by_srv[&Service::PISCINA])
+    );
+    assert_eq!(
+        "580.0",
+        format!("{:.1}", bal.exp.nepus_by_srv[&Service::PISCINA])
+    );
+    assert_eq!(
+        "5.80",
+        format!("{:.2}", ep.balance_m2.exp.nepus_by_srv[&Service::PISCINA])
+    );
+    assert_eq!("12.0", format!("{:.1}", ep.balance_m2.we.b.ren));
+}
+
+/// Selección de la versión de las tablas reglamentarias de factores de paso
+#[test]
+fn cte_version_factores_paso() {
+    let version = cte_wf_version(CTE_WF_VERSION_DEFAULT).unwrap();
+    assert_eq!("RITE2014", version.id);
+    assert!(version.valid_to.is_none());
+    assert!(cte_wf_version("rite2014").is_ok());
+    assert!(cte_wf_version("RITE2000").is_err());
+
+    let user_wf = UserWF::default();
+    let FP = wfactors_from_loc_version("PENINSULA", "RITE2014", user_wf, CTE_USERWF).unwrap();
+    assert_eq!(get_ctefp_peninsula().to_string(), FP.to_string());
+    assert_eq!(Some("RITE2014".to_string()), FP.get_meta("CTE_FUENTE"));
+    assert!(wfactors_from_loc_version("PENINSULA", "RITE2000", user_wf, CTE_USERWF).is_err());
+}
+
+/// Balance exergético con factores de calidad exergética del usuario
+#[test]
+fn cte_balance_exergetico() {
+    let xfactors: ExergyFactors = "#META CTE_FUENTE: Prueba
+ELECTRICIDAD, 1.000 # Electricidad
+GASNATURAL, 0.940 # Gas natural"
+        .parse()
+        .unwrap();
+    assert_eq!(Some(0.94), xfactors.get(Carrier::GASNATURAL));
+
+    // Exportación de electricidad (k_exp = 1.0)
+    let comps = components_from_file("test_data/ejemploJ3_basePVexcess.csv");
+    let FP: Factors = TESTFPJ.parse().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let xbal = exergy_balance(&ep, &xfactors).unwrap();
+    assert_eq!("140.0", format!("{:.1}", xbal.del));
+    assert_eq!("40.0", format!("{:.1}", xbal.exp));
+    assert_eq!("100.0", format!("{:.1}", xbal.b));
+
+    // Varios vectores
+    let comps = components_from_file("test_data/ejemploJ5_gasPV.csv");
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 2.0, false).unwrap();
+    let xbal = exergy_balance(&ep, &xfactors).unwrap();
+    assert_eq!("218.6", format!("{:.1}", xbal.del));
+    assert_eq!("20.0", format!("{:.1}", xbal.exp));
+    assert_eq!("198.6", format!("{:.1}", xbal.b));
+    assert_eq!(
+        "178.6",
+        format!("{:.1}", xbal.b_by_cr[&Carrier::GASNATURAL])
+    );
+    assert_eq!("99.3", format!("{:.1}", xbal.b_m2));
+    assert_eq!("0.950", format!("{:.3}", xbal.q_del));
+
+    // Falta el factor de algún vector con energía suministrada
+    let xfactors: ExergyFactors = "ELECTRICIDAD, 1.0".parse().unwrap();
+    assert!(exergy_balance(&ep, &xfactors).is_err());
+    assert!("ELECTRICIDAD, -1.0".parse::<ExergyFactors>().is_err());
+    assert!("ELECTRICIDAD, 1.0\nELECTRICIDAD, 0.9"
+        .parse::<ExergyFactors>()
+        .is_err());
+}
+
+/// Contribución de cada vector a los indicadores de energía ponderada
+#[test]
+fn cte_contribucion_vectores() {
+    let comps = components_from_file("test_data/ejemploJ5_gasPV.csv");
+    let FP: Factors = TESTFPJ.parse().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let we = &ep.balance.we;
+    let gas = we.b_share_by_cr[&Carrier::GASNATURAL];
+    let el = we.b_share_by_cr[&Carrier::ELECTRICIDAD];
+    assert_eq!("1.237", format!("{:.3}", gas.nren));
+    assert_eq!("-0.237", format!("{:.3}", el.nren));
+    assert_eq!("1.251", format!("{:.3}", gas.co2));
+    // Las contribuciones suman la unidad y coinciden con el balance por m2
+    assert_eq!("1.000", format!("{:.3}", gas.tot + el.tot));
+    assert_eq!(gas, ep.balance_m2.we.b_share_by_cr[&Carrier::GASNATURAL]);
+    assert!(approx_equal(
+        we.b,
+        we.b_by_cr[&Carrier::GASNATURAL] + we.b_by_cr[&Carrier::ELECTRICIDAD]
+    ));
+}
+
+/// Uso del edificio, servicios admitidos y valores límite
+#[test]
+fn cte_uso_edificio() {
+    let comps = components_from_file("test_data/uso_residencial.csv");
+    let uso = uso_edificio(&comps).unwrap().unwrap();
+    assert_eq!(UsoEdificio::RESIDENCIAL, uso);
+    assert!(!uso.servicios_epb().contains(&Service::ILU));
+    // Iluminación no admitida en uso residencial privado
+    assert!(valida_servicios_uso_edificio(&comps, uso).is_err());
+    assert!(valida_servicios_uso_edificio(&comps, UsoEdificio::TERCIARIO).is_ok());
+    // Sin metadato
+    assert!(uso_edificio(&get_energydatalist()).unwrap().is_none());
+    assert!("INDUSTRIAL".parse::<UsoEdificio>().is_err());
+
+    // Valores límite
+    assert_eq!((38.0, 76.0), uso.limites_he0("D", 0.0).unwrap());
+    assert_eq!(
+        (52.0, 166.0),
+        UsoEdificio::TERCIARIO.limites_he0("D", 4.0).unwrap()
+    );
+    assert!(uso.limites_he0("F", 0.0).is_err());
+}
+
+/// Energía embebida informativa, sin efecto sobre el balance EPB
+#[test]
+fn cte_energia_embebida() {
+    let comps = components_from_file("test_data/energia_embebida.csv");
+    assert_eq!(2, comps.embodied.len());
+    assert!(approx_equal(
+        RenNrenCo2::new(60.0, 950.0, 250.0),
+        comps.embodied_total()
+    ));
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 100.0, false).unwrap();
+    assert!(approx_equal(
+        RenNrenCo2::new(0.6, 9.5, 2.5),
+        ep.embodied_m2.unwrap()
+    ));
+
+    // El balance EPB no cambia al eliminar la energía embebida
+    let mut comps_epb = comps.clone();
+    comps_epb.embodied.clear();
+    let ep_epb = energy_performance(&comps_epb, &FP, TESTKEXP, 100.0, false).unwrap();
+    assert!(ep_epb.embodied_m2.is_none());
+    assert!(approx_equal(ep.balance_m2.we.b, ep_epb.balance_m2.we.b));
+
+    // Se conserva al serializar los componentes
+    let comps_str = comps.to_string();
+    assert!(comps_str.contains("EMBEBIDA, 10.00, 150.00, 40.00 # Instalaciones (20 años)"));
+    assert_eq!(2, comps_str.parse::<Components>().unwrap().embodied.len());
+}
+
+/// Etiquetas estructuradas (clave=valor) en los comentarios de los componentes
+#[test]
+fn cte_etiquetas_comentarios() {
+    let comps = "1, CONSUMO, CAL, GASNATURAL, 100.0 # Caldera equipo=CAL-01 rend=0.92
+        1, AUX, 2.0 # Bombas CTEEPBD_AUX
+        EMBEBIDA, 1.0, 10.0, 2.0 # Envolvente vida_util=50"
+        .parse::<Components>()
+        .unwrap();
+    let used = comps.data.iter().find(|c| c.is_used()).unwrap();
+    let tags = used.tags();
+    assert_eq!(Some("CAL-01"), tags.get_str("equipo"));
+    assert_eq!(Some(0.92), tags.get_f32("rend"));
+    // El comentario se conserva sin cambios
+    assert_eq!("Caldera equipo=CAL-01 rend=0.92", used.comment());
+    let aux = comps.data.iter().find(|c| c.is_aux()).unwrap();
+    assert!(aux.tags().has("CTEEPBD_AUX"));
+    assert_eq!(Some(50.0), comps.embodied[0].tags().get_f32("vida_util"));
+}
+
+/// Serialización determinista y ordenada de los mapas de resultados
+#[test]
+fn cte_serializacion_determinista() {
+    let comps = components_from_file("test_data/ejemploJ6_HPPV.csv");
+    let FP = get_ctefp_peninsula();
+    let ep1 = energy_performance(&comps, &FP, 0.0, 200.0, false).unwrap();
+    let ep2 = energy_performance(&comps, &FP, 0.0, 200.0, false).unwrap();
+    let json1 = serde_json::to_string_pretty(&ep1).unwrap();
+    let json2 = serde_json::to_string_pretty(&ep2).unwrap();
+    assert_eq!(json1, json2);
+    // Los vectores del balance se recorren en orden
+    let carriers: Vec<_> = ep1.balance_cr.keys().cloned().collect();
+    let mut sorted = carriers.clone();
+    sorted.sort();
+    assert_eq!(sorted, carriers);
+    assert_eq!(vec![Carrier::EAMBIENTE, Carrier::ELECTRICIDAD], carriers);
+}
+
+/// Cálculo reproducible: mismos datos -> mismos resultados bit a bit
+#[test]
+fn cte_calculo_reproducible() {
+    let FP = get_ctefp_peninsula();
+    // Cogeneración (varios vectores consumidos) y reparto de auxiliares entre servicios
+    let comps_cgn = components_from_file("test_data/ejemploJ7_cogenfuelgasboiler.csv");
+    let comps_aux = "1, CONSUMO, CAL, GASNATURAL, 100.0, 50.0
+        1, CONSUMO, ACS, GASNATURAL, 20.0, 30.0
+        1, SALIDA, CAL, 90.0, 45.0
+        1, SALIDA, ACS, 18.0, 27.0
+        1, AUX, 3.0, 2.0
+        2, CONSUMO, REF, ELECTRICIDAD, 10.0, 20.0
+        2, PRODUCCION, EL_INSITU, 5.0, 25.0"
+        .parse::<Components>()
+        .unwrap();
+    for comps in &[comps_cgn, comps_aux] {
+        let ep = energy_performance(comps, &FP, 0.5, 150.0, true).unwrap();
+        let json = serde_json::to_string_pretty(&ep).unwrap();
+        let xml = ep.to_xml();
+        for _ in 0..10 {
+            let ep_i = energy_performance(comps, &FP, 0.5, 150.0, true).unwrap();
+            assert_eq!(json, serde_json::to_string_pretty(&ep_i).unwrap());
+            assert_eq!(xml, ep_i.to_xml());
+            assert_eq!(
+                ep.balance.we.b.tot().to_bits(),
+                ep_i.balance.we.b.tot().to_bits()
+            );
+        }
+    }
+}
+
+/// Balance del edificio completo, incluyendo usos no EPB
+#[test]
+fn cte_balance_edificio_completo() {
+    let FP = get_ctefp_peninsula();
+    let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 100.0
+        2, CONSUMO, NEPB, ELECTRICIDAD, 50.0
+        1, PRODUCCION, EL_INSITU, 60.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    // El balance EPB no cambia
+    assert!(approx_equal(
+        RenNrenCo2::new(76.56, 78.16, 13.24),
+        ep.balance.we.b
+    ));
+    let wb = ep.whole_building.unwrap();
+    assert_eq!(150.0, wb.balance.used.epus);
+    assert_eq!(0.0, wb.balance.used.nepus);
+    assert!(approx_equal(
+        RenNrenCo2::new(97.26, 175.86, 29.79),
+        wb.balance.we.b
+    ));
+    assert!(approx_equal(
+        RenNrenCo2::new(32.42, 58.62, 9.93),
+        wb.balance.we.b_by_srv[&Service::NEPB]
+    ));
+    assert_eq!("0.36", format!("{:.2}", wb.rer));
+
+    // Sin usos no EPB no se calcula el bloque del edificio completo
+    let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 100.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    assert!(ep.whole_building.is_none());
+}
+
+/// Cambio del área de referencia sin recalcular el balance
+#[test]
+fn cte_cambio_area_referencia() {
+    let FP = get_ctefp_peninsula();
+    let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 100.0
+        2, CONSUMO, NEPB, ELECTRICIDAD, 50.0
+        1, PRODUCCION, EL_INSITU, 60.0
+        EMBEBIDA, 10.0, 40.0, 8.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep_200 = energy_performance(&comps, &FP, 0.0, 200.0, false).unwrap();
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false)
+        .unwrap()
+        .with_arearef(200.0)
+        .unwrap();
+    assert_eq!(200.0, ep.arearef);
+    assert!(approx_equal(ep_200.balance_m2.we.b, ep.balance_m2.we.b));
+    assert!(approx_equal(
+        ep_200.whole_building.as_ref().unwrap().balance_m2.we.b,
+        ep.whole_building.as_ref().unwrap().balance_m2.we.b
+    ));
+    assert!(approx_equal(
+        ep_200.embodied_m2.unwrap(),
+        ep.embodied_m2.unwrap()
+    ));
+    assert_eq!(ep_200.rer, ep.rer);
+    assert_eq!(ep_200.to_plain(), ep.to_plain());
+
+    // Área de referencia no válida
+    assert!(ep.clone().with_arearef(0.0).is_err());
+    assert!(ep.with_arearef(f32::NAN).is_err());
+}
+
+/// RER según el criterio de la Directiva (UE) para bombas de calor
+#[test]
+fn cte_rer_directiva_bdc() {
+    let FP = get_ctefp_peninsula();
+    let comps = components_from_file("test_data/bdc_rer_directiva.csv");
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    // SPF estimado (sistema 1) y declarado (sistema 2)
+    let spf = cte::spf_bdc_por_sistema(&ep.components);
+    assert_eq!(Some(&2.5), spf.get(&1));
+    assert_eq!(Some(&3.1), spf.get(&2));
+    // Se excluye la energía ambiente del sistema 1 (SPF < 2.53)
+    let rer_ue = cte::rer_directiva(&ep, cte::ETA_ELECTRICIDAD_UE).unwrap();
+    assert_eq!("0.47", format!("{:.2}", ep.rer));
+    assert_eq!("0.28", format!("{:.2}", rer_ue));
+    // Con un SPF mínimo menor no se excluye ningún sistema
+    let rer_ue = cte::rer_directiva(&ep, 0.5).unwrap();
+    assert_eq!(format!("{:.3}", ep.rer), format!("{:.3}", rer_ue));
+    // Rendimiento fuera de rango
+    assert!(cte::rer_directiva(&ep, 0.0).is_err());
+    let ep = cte::incorpora_rer_directiva(ep, cte::ETA_ELECTRICIDAD_UE);
+    let misc = ep.misc.unwrap();
+    assert_eq!("0.277", misc["rer_directiva"]);
+    assert_eq!("2.527", misc["rer_directiva_spf_min"]);
+}
+
+/// Energía renovable de bombas de calor para calefacción según la Decisión 2013/114/UE
+#[test]
+fn cte_renovable_bdc_calefaccion() {
+    let FP = get_ctefp_peninsula();
+    let comps = "#META CTE_AREAREF: 10.0
+DEMANDA, CAL, 100.0
+1, CONSUMO, CAL, ELECTRICIDAD, 25.0 # Bomba de calor SCOP=4.0
+1, CONSUMO, CAL, EAMBIENTE, 75.0
+2, CONSUMO, CAL, ELECTRICIDAD, 20.0 # Bomba de calor con SPF estimado
+2, CONSUMO, CAL, EAMBIENTE, 30.0
+3, CONSUMO, ACS, ELECTRICIDAD, 10.0 # Aerotermia ACS SCOP=3.1
+3, CONSUMO, ACS, EAMBIENTE, 21.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, 0.0, 10.0, false).unwrap();
+    let by_id = cte::renovable_bdc_calefaccion(&ep, cte::ETA_ELECTRICIDAD_UE).unwrap();
+    // Solo se consideran los sistemas de calefacción
+    assert_eq!(2, by_id.len());
+    // SPF declarado (4.0 > 2.53): se computa la energía renovable
+    let bdc1 = by_id[&1];
+    assert_eq!("4.00", format!("{:.2}", bdc1.spf));
+    assert_eq!("100.00", format!("{:.2}", bdc1.q_usable));
+    assert_eq!("75.00", format!("{:.2}", bdc1.e_res));
+    // SPF estimado (2.5 < 2.53): no se computa la energía renovable
+    let bdc2 = by_id[&2];
+    assert_eq!("2.50", format!("{:.2}", bdc2.spf));
+    assert_eq!("50.00", format!("{:.2}", bdc2.q_usable));
+    assert_eq!("0.00", format!("{:.2}", bdc2.e_res));
+    // Con un SPF mínimo menor se computan ambos sistemas
+    let by_id = cte::renovable_bdc_calefaccion(&ep, 0.5).unwrap();
+    assert_eq!("30.00", format!("{:.2}", by_id[&2].e_res));
+    // Rendimiento fuera de rango
+    assert!(cte::renovable_bdc_calefaccion(&ep, 0.0).is_err());
+
+    let ep = cte::incorpora_renovable_bdc_calefaccion(ep, cte::ETA_ELECTRICIDAD_UE);
+    let misc = ep.misc.as_ref().unwrap();
+    assert_eq!("7.500", misc["renovable_bdc_cal"]);
+    assert_eq!("2.527", misc["renovable_bdc_cal_spf_min"]);
+    assert_eq!("0.750", misc["cobertura_renovable_bdc_cal"]);
+    assert!(ep.to_plain().contains(
+        "E_RES,CAL (BdC con SPF > 2.53, Decisión 2013/114/UE) [kWh/m2.an]: 7.50 (cobertura de la demanda de calefacción: 75.0 %)"
+    ));
+}
+
+/// Producción eléctrica in situ compartida entre sistemas con lista de prioridad
+#[test]
+fn cte_produccion_compartida_con_prioridad() {
+    let FP = get_ctefp_peninsula();
+    let comps = components_from_file("test_data/produccion_compartida.csv");
+    assert_eq!(
+        comps.production_priorities().unwrap().get(&3),
+        Some(&vec![2, 1])
+    );
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    let prod = &ep.balance_cr[&Carrier::ELECTRICIDAD].prod;
+    // El sistema 2 se cubre completamente antes que el sistema 1
+    assert_eq!("40.0", format!("{:.1}", prod.epus_by_sys_an[&2]));
+    assert_eq!("20.0", format!("{:.1}", prod.epus_by_sys_an[&1]));
+    let by_srv = &prod.epus_by_srv_by_src_an[&ProdSource::EL_INSITU];
+    assert_eq!("30.0", format!("{:.1}", by_srv[&Service::ACS]));
+    assert_eq!("10.0", format!("{:.1}", by_srv[&Service::ILU]));
+    assert_eq!("20.0", format!("{:.1}", by_srv[&Service::CAL]));
+
+    // Sin lista de prioridad el reparto es proporcional al consumo y no se desglosa por sistema
+    let mut comps_prop = comps.clone();
+    comps_prop
+        .meta
+        .retain(|m| !m.key.starts_with("CTE_PRIORIDAD_PRODUCCION_"));
+    let ep_prop = energy_performance(&comps_prop, &FP, 0.0, 1.0, false).unwrap();
+    let prod_prop = &ep_prop.balance_cr[&Carrier::ELECTRICIDAD].prod;
+    assert!(prod_prop.epus_by_sys_an.is_empty());
+    let by_srv = &prod_prop.epus_by_srv_by_src_an[&ProdSource::EL_INSITU];
+    assert_eq!("33.3", format!("{:.1}", by_srv[&Service::CAL]));
+    // El balance global no cambia
+    assert_eq!(
+        format!("{:.3}", ep.balance.we.b.tot()),
+        format!("{:.3}", ep_prop.balance.we.b.tot())
+    );
+
+    // Sistema sin producción eléctrica o lista incorrecta
+    let mut comps_err = comps.clone();
+    comps_err.set_meta("CTE_PRIORIDAD_PRODUCCION_1", "2");
+    assert!(energy_performance(&comps_err, &FP, 0.0, 1.0, false).is_err());
+    let mut comps_err = comps;
+    comps_err.set_meta("CTE_PRIORIDAD_PRODUCCION_3", "2, 2");
+    assert!(comps_err.production_priorities().is_err());
+}
+
+/// Huella de carbono marginal por paso de cálculo
+#[test]
+fn cte_huella_carbono_marginal() {
+    let FP = get_ctefp_peninsula();
+    let comps = "CONSUMO, CAL, ELECTRICIDAD, 10.0, 10.0, 10.0
+CONSUMO, CAL, GASNATURAL, 5.0, 5.0, 5.0
+PRODUCCION, EL_INSITU, 0.0, 5.0, 20.0"
+        .parse::<Components>()
+        .unwrap();
+    let mfactors = "ELECTRICIDAD, 0.4, 0.3, 0.2"
+        .parse::<MarginalCo2Factors>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, 0.0, 2.0, false).unwrap();
+    let mbal = marginal_carbon(&ep, &mfactors).unwrap();
+    // Solo se calcula para los vectores con factores marginales
+    assert_eq!(1, mbal.by_cr.len());
+    let m_el = &mbal.by_cr[&Carrier::ELECTRICIDAD];
+    assert_eq!("[4.00, 1.50, 0.00]", format!("{:.2?}", m_el.del_grid_t));
+    assert_eq!("[0.00, 1.50, 2.00]", format!("{:.2?}", m_el.avoided_used_t));
+    assert_eq!("[0.00, 0.00, 2.00]", format!("{:.2?}", m_el.avoided_exp_t));
+    assert_eq!("5.50", format!("{:.2}", mbal.del_grid));
+    assert_eq!("5.50", format!("{:.2}", mbal.avoided));
+    assert_eq!("[0.00, 1.50, 4.00]", format!("{:.2?}", mbal.avoided_t));
+    assert_eq!("0.00", format!("{:.2}", mbal.net_m2));
+
+    // Número de pasos incorrecto
+    let mfactors = "ELECTRICIDAD, 0.4, 0.3"
+        .parse::<MarginalCo2Factors>()
+        .unwrap();
+    assert!(marginal_carbon(&ep, &mfactors).is_err());
+}
+
+/// Emisiones con dos juegos de factores de emisión (oficial y de inventario)
+#[test]
+fn cte_emisiones_dos_juegos_factores() {
+    let FP = get_ctefp_peninsula();
+    let comps = "CONSUMO, CAL, ELECTRICIDAD, 100.0
+CONSUMO, ACS, GASNATURAL, 50.0"
+        .parse::<Components>()
+        .unwrap();
+    let cfactors = "#META CTE_ETIQUETA: GHG market-based
+ELECTRICIDAD, RED, SUMINISTRO, A, 0.000 # Garantía de origen renovable"
+        .parse::<InventoryCo2Factors>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 2.0, false).unwrap();
+    let inventory = co2_inventory(&ep, &cfactors).unwrap();
+    assert_eq!(inventory.official_label, "oficial");
+    assert_eq!(inventory.label, "GHG market-based");
+    assert_eq!(inventory.official, ep.balance.we.b.co2);
+    // Sin emisiones de la electricidad y emisiones oficiales del gas natural
+    assert_eq!(inventory.b_by_cr[&Carrier::ELECTRICIDAD], 0.0);
+    let co2_gn = ep.balance_cr[&Carrier::GASNATURAL].we.b.co2;
+    assert_eq!(inventory.b_by_cr[&Carrier::GASNATURAL], co2_gn);
+    assert_eq!(inventory.b, co2_gn);
+    assert_eq!(inventory.b_by_srv[&Service::CAL], 0.0);
+    assert_eq!(inventory.b_by_srv[&Service::ACS], co2_gn);
+    assert_eq!(inventory.b_m2, co2_gn / 2.0);
+    assert!(inventory.official > inventory.b);
+
+    // La energía primaria no cambia
+    let mut ep_inv = ep.clone();
+    ep_inv.co2_inventory = Some(inventory);
+    assert!(approx_equal(ep_inv.balance.we.b, ep.balance.we.b));
+    let ep_inv = ep_inv.with_arearef(4.0).unwrap();
+    let inventory = ep_inv.co2_inventory.unwrap();
+    assert_eq!(inventory.b_m2, co2_gn / 4.0);
+    assert_eq!(inventory.official_m2, ep.balance.we.b.co2 / 4.0);
+}
+
+/// Errores de interpretación con número de línea y sugerencias de corrección
+#[test]
+fn cte_errores_formato_con_sugerencias() {
+    let err = "#META CTE_AREAREF: 1.0
+        1, CONSUMO, CAL, ELECTRICIDAD, 1.0
+        1, CONSUMO, CALL, ELECTRICIDAD, 1.0"
+        .parse::<Components>()
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        "Error de formato: línea 3: servicio desconocido \"CALL\" (¿quiso decir CAL?)",
+        err
+    );
+
+    let err = "ELECTRICIDAD, RED, SUMINISTRO, A, 0.414, 1.954, 0.331
+        ELECTRICIDAD, INSITU, A_REDD, A, 1.0, 0.0, 0.0"
+        .parse::<Factors>()
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        "Error de formato: línea 2: destino desconocido \"A_REDD\" (¿quiso decir A_RED?)",
+        err
+    );
+
+    // Sin opciones parecidas se indican los valores admitidos
+    let err = "ELECTRICIDAD, RED, SUMINISTRO, X, 0.414, 1.954, 0.331"
+        .parse::<Factors>()
+        .unwrap_err()
+        .to_string();
+    assert_eq!(
+        "Error de formato: línea 1: paso de cálculo desconocido \"X\" (valores admitidos: A, B)",
+        err
+    );
+}
+
+/// Energía entregada generada a partir del rendimiento declarado de los sistemas
+#[test]
+fn cte_salida_desde_rendimiento_sistema() {
+    let FP = get_ctefp_peninsula();
+    let comps_rend = "1, CONSUMO, CAL, GASNATURAL, 100.0, 50.0
+        1, CONSUMO, ACS, GASNATURAL, 20.0, 30.0
+        1, AUX, 4.0, 2.0
+        SISTEMA, 1, RENDIMIENTO, CAL, 0.9
+        SISTEMA, 1, RENDIMIENTO, ACS, 0.8"
+        .parse::<Components>()
+        .unwrap();
+    let comps_salida = "1, CONSUMO, CAL, GASNATURAL, 100.0, 50.0
+        1, CONSUMO, ACS, GASNATURAL, 20.0, 30.0
+        1, SALIDA, CAL, 90.0, 45.0
+        1, SALIDA, ACS, 16.0, 24.0
+        1, AUX, 4.0, 2.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep_rend = energy_performance(&comps_rend, &FP, 0.0, 1.0, false).unwrap();
+    let ep_salida = energy_performance(&comps_salida, &FP, 0.0, 1.0, false).unwrap();
+    for service in [Service::CAL, Service::ACS] {
+        assert!(approx_equal(
+            ep_salida.balance.we.b_by_srv[&service],
+            ep_rend.balance.we.b_by_srv[&service]
+        ));
+    }
+}
+
+#[test]
+fn cte_informe_xml_round_trip() {
+    let FP = get_ctefp_peninsula();
+    let comps = components_from_file("test_data/ejemploJ7_cogenfuelgasboiler.csv");
+    let ep = energy_performance(&comps, &FP, 0.5, 150.0, true).unwrap();
+    let xml = ep.to_xml();
+    let model: xmlmodel::BalanceEpbXml = xmlmodel::from_xml_str(&xml).unwrap();
+    // Los valores se redondean al serializar, por lo que se compara el texto XML
+    assert_eq!(xmlmodel::to_xml_string(&model), xml);
+    assert_eq!(model.arearef.valor, 150.0);
+    // Las magnitudes de resultados incluyen su referencia normativa
+    assert_eq!(model.kexp.referencia, refs::K_EXP);
+    assert_eq!(model.epm2.nren.referencia, refs::C_EP_NREN);
+    assert!(xml.contains(&format!(
+        "<nren ref=\"{}\">{:.1}</nren>",
+        refs::C_EP_NREN,
+        ep.balance_m2.we.b.nren
+    )));
+    assert_eq!(model.componentes.energia.len(), ep.components.data.len());
+    assert_eq!(model.factores.factores.len(), ep.wfactors.wdata.len());
+}
+
+#[test]
+fn cte_potencias_pico() {
+    let FP = get_ctefp_peninsula();
+    let comps = components_from_file("test_data/demanda_zonas.csv");
+    let ep = energy_performance(&comps, &FP, 0.0, 100.0, false).unwrap();
+    let peaks = ep.peaks.as_ref().unwrap();
+    let cal = &peaks.needs[&Service::CAL];
+    assert_eq!((0, "mes 1"), (cal.step, cal.position.as_str()));
+    assert!((90.0 / 744.0 - cal.power.0).abs() < 1e-6);
+    let gas = &peaks.used_by_srv[&Carrier::GASNATURAL][&Service::CAL];
+    assert!((100.0 / 744.0 - gas.power.0).abs() < 1e-6);
+    assert_eq!(peaks.used[&Carrier::GASNATURAL], *gas);
+    assert_eq!(peaks.del_grid[&Carrier::GASNATURAL], *gas);
+
+    // Solo se consideran los usos EPB y el suministro de red
+    let comps = components_from_file("test_data/ejemploJ9_electr.csv");
+    let ep = energy_performance(&comps, &FP, 0.0, 100.0, false).unwrap();
+    let peaks = ep.peaks.as_ref().unwrap();
+    assert!(peaks.needs.is_empty());
+    let el_used = &peaks.used[&Carrier::ELECTRICIDAD];
+    assert!((200.0 / 744.0 - el_used.power.0).abs() < 1e-6);
+    let el_grid = &peaks.del_grid[&Carrier::ELECTRICIDAD];
+    assert!(el_grid.power.0 < el_used.power.0);
+
+    // Sin potencias pico cuando no se conoce la duración de los pasos de cálculo
+    let comps: Components = "CONSUMO, CAL, ELECTRICIDAD, 10, 20".parse().unwrap();
+    let ep = energy_performance(&comps, &FP, 0.0, 100.0, false).unwrap();
+    assert!(ep.peaks.is_none());
+}
+
+#[test]
+fn cte_exportacion_diferida_almacenamiento() {
+    let FP = get_ctefp_peninsula();
+    let comps_sto: Components = "CONSUMO, CAL, ELECTRICIDAD, 10, 10
+        PRODUCCION, EL_INSITU, 20, 10 # Producción fotovoltaica
+        PRODUCCION, EL_INSITU, 10, 0 # Descarga de batería CTEEPBD_ALMACENAMIENTO"
+        .parse()
+        .unwrap();
+    let comps_direct: Components = "CONSUMO, CAL, ELECTRICIDAD, 10, 10
+        PRODUCCION, EL_INSITU, 20, 10 # Producción fotovoltaica
+        PRODUCCION, EL_INSITU, 10, 0 # Descarga de batería"
+        .parse()
+        .unwrap();
+
+    // Sin factores de exportación diferida se usan los de la exportación directa
+    let ep_direct = energy_performance(&comps_direct, &FP, 1.0, 1.0, false).unwrap();
+    let ep_sto = energy_performance(&comps_sto, &FP, 1.0, 1.0, false).unwrap();
+    let exp = &ep_sto.balance_cr[&Carrier::ELECTRICIDAD].exp;
+    assert!((exp.grid_sto_an - 20.0 / 3.0).abs() < 1e-4);
+    assert_eq!(exp.grid_sto_an, ep_sto.balance.exp.grid_sto);
+    assert_eq!(0.0, ep_direct.balance.exp.grid_sto);
+    assert!(approx_equal(ep_direct.balance.we.b, ep_sto.balance.we.b));
+
+    // Factores propios para la exportación diferida (p.e. por las pérdidas del almacenamiento)
+    let mut FP_sto = FP.clone();
+    let f_dif_a = RenNrenCo2::new(0.9, 0.0, 0.0);
+    let f_dif_b = RenNrenCo2::new(0.5, 1.0, 0.2);
+    FP_sto.wdata.push(Factor::new(
+        Carrier::ELECTRICIDAD,
+        Source::INSITU,
+        Dest::A_RED_DIFERIDA,
+        Step::A,
+        f_dif_a,
+        "Recursos usados para producir la electricidad exportada desde almacenamiento",
+    ));
+    FP_sto.wdata.push(Factor::new(
+        Carrier::ELECTRICIDAD,
+        Source::INSITU,
+        Dest::A_RED_DIFERIDA,
+        Step::B,
+        f_dif_b,
+        "Recursos evitados a la red por la electricidad exportada desde almacenamiento",
+    ));
+    let ep_sto_f = energy_performance(&comps_sto, &FP_sto, 1.0, 1.0, false).unwrap();
+    let f_red_a = FP
+        .find(Carrier::ELECTRICIDAD, Source::INSITU, Dest::A_RED, Step::A)
+        .unwrap();
+    let f_red_b = FP
+        .find(Carrier::ELECTRICIDAD, Source::INSITU, Dest::A_RED, Step::B)
+        .unwrap();
+    // El efecto de la exportación con k_exp = 1 solo depende de los factores del paso B
+    let expected = ep_sto.balance.we.b + exp.grid_sto_an * (f_red_b - f_dif_b);
+    assert!(approx_equal(expected, ep_sto_f.balance.we.b));
+    let expected_a = ep_sto.balance.we.a + exp.grid_sto_an * (f_red_a - f_dif_a);
+    assert!(approx_equal(expected_a, ep_sto_f.balance.we.a));
+    // La exportación directa no cambia
+    assert!(approx_equal(ep_sto.balance.we.del, ep_sto_f.balance.we.del));
+
+    // Los nuevos factores se leen desde archivo
+    let factor: Factor = "ELECTRICIDAD, INSITU, A_RED_DIFERIDA, B, 0.5, 1.0, 0.2"
+        .parse()
+        .unwrap();
+    assert_eq!(Dest::A_RED_DIFERIDA, factor.dest);
+}
+
+#[test]
+fn cte_conciliacion_facturas() {
+    let FP = get_ctefp_peninsula();
+    let comps = components_from_file("test_data/ejemploJ9_electr.csv");
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    let bills: Bills = std::fs::read_to_string("test_data/facturas_J9.csv")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let rec = bills_reconciliation(&ep, &bills).unwrap();
+    let el = &rec.by_cr[&Carrier::ELECTRICIDAD];
+    // Incluye los usos no EPB no cubiertos por la energía exportada (30 kWh/mes)
+    assert_eq!(186.0, el.computed[0]);
+    assert_eq!(10.0, el.computed[3]);
+    assert_eq!(Some(0.0), el.dev_pct[3]);
+    assert_eq!(None, el.dev_pct[6]);
+    assert!((el.dev_an_pct.unwrap() - 100.0 * (581.0 - 575.0) / 575.0).abs() < 1e-3);
+    assert!(rec.unbilled.is_empty());
+
+    // Vectores sin facturas
+    let comps = components_from_file("test_data/demanda_zonas.csv");
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    let rec = bills_reconciliation(&ep, &bills).unwrap();
+    assert_eq!(vec![Carrier::GASNATURAL], rec.unbilled);
+    assert_eq!(0.0, rec.by_cr[&Carrier::ELECTRICIDAD].computed_an);
+
+    // Pasos de cálculo no agregables por meses
+    let comps: Components = "CONSUMO, CAL, ELECTRICIDAD, 10, 20".parse().unwrap();
+    let ep = energy_performance(&comps, &FP, 0.0, 1.0, false).unwrap();
+    assert!(bills_reconciliation(&ep, &bills).is_err());
+}
+
+/// Componentes generados a partir de un modelo simplificado del edificio (anteproyecto)
+#[test]
+fn cte_modelo_simplificado_edificio() {
+    let model = BuildingModel {
+        demandas: BTreeMap::from([
+            (Service::CAL, vec![50.0; 12]),
+            (Service::ACS, vec![25.0; 12]),
+        ]),
+        sistemas: vec![
+            ModelSystem {
+                id: 1,
+                servicio: Service::CAL,
+                vector: Carrier::ELECTRICIDAD,
+                rendimiento: 2.5,
+                cobertura: 1.0,
+                comentario: String::new(),
+            },
+            ModelSystem {
+                id: 2,
+                servicio: Service::ACS,
+                vector: Carrier::GASNATURAL,
+                rendimiento: 0.9,
+                cobertura: 1.0,
+                comentario: String::new(),
+            },
+        ],
+        pv_kwp: 0.5,
+        ..Default::default()
+    };
+    let comps = model.to_components().unwrap();
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    // Consumo eléctrico de la bomba de calor = 600 / 2.5 = 240 kWh, cubierto en parte por la PV
+    assert!((ep.balance.used.epus_by_cr[&Carrier::ELECTRICIDAD] - 240.0).abs() < 0.1);
+    assert!(ep.balance.prod.an > 0.0);
+    // La bomba de calor consume energía ambiente y la caldera 300 / 0.9 = 333.3 kWh de gas natural
+    assert!(ep.balance.used.epus_by_cr.contains_key(&Carrier::EAMBIENTE));
+    assert!(ep.balance.used.epus_by_cr[&Carrier::GASNATURAL] > 300.0);
+    assert!(ep.rer > 0.0);
+}
+
+/// Indicadores de rendimiento de la instalación fotovoltaica con potencia pico declarada
+#[test]
+fn cte_rendimiento_instalacion_fv() {
+    let FP = get_ctefp_peninsula();
+    let comps_str = "1, CONSUMO, CAL, ELECTRICIDAD, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50
+2, CONSUMO, NEPB, ELECTRICIDAD, 20, 20, 20, 20, 20, 20, 20, 20, 20, 20, 20, 20
+3, PRODUCCION, EL_INSITU, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100";
+
+    // Sin potencia pico declarada no hay indicadores
+    let comps = comps_str.parse::<Components>().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!(ep.pv.is_none());
+
+    // Potencia pico declarada como sistema
+    let comps = format!(
+        "{}\nSISTEMA, 3, POTENCIA_PICO, 2.0 # FV cubierta",
+        comps_str
+    )
+    .parse::<Components>()
+    .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let pv = ep.pv.clone().unwrap();
+    assert!((pv.prod_an - 1200.0).abs() < 0.01);
+    assert!((pv.specific_yield - 600.0).abs() < 0.01);
+    // Se consumen en el edificio 600 kWh en usos EPB y 240 kWh en usos no EPB
+    assert!((pv.self_an - 840.0).abs() < 0.01);
+    assert!((pv.self_fraction - 0.7).abs() < 1e-4);
+    assert!((pv.exp_grid_an - 360.0).abs() < 0.01);
+    assert!((pv.exp_grid_fraction - 0.3).abs() < 1e-4);
+    assert!((pv.exp_grid_per_kwp - 180.0).abs() < 0.01);
+    assert!(ep.to_plain().contains(
+        "Exportación a la red = 360.0 [kWh] (30.0 %), por potencia pico = 180.0 [kWh/kWp]"
+    ));
+
+    // Potencia pico declarada como metadato
+    let comps = format!("#META CTE_PV_KWP: 4.0\n{}", comps_str)
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!((ep.pv.unwrap().specific_yield - 300.0).abs() < 0.01);
+}
+
+#[test]
+fn cte_cupos_produccion_por_servicio() {
+    let FP = get_ctefp_peninsula();
+    let comps_str = "1, CONSUMO, CAL, ELECTRICIDAD, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50
+2, CONSUMO, ACS, ELECTRICIDAD, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50
+3, PRODUCCION, EL_INSITU, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100, 100";
+    let imputed = |ep: &EnergyPerformance, service: Service| -> f32 {
+        ep.balance_cr[&Carrier::ELECTRICIDAD]
+            .prod
+            .epus_by_srv_by_src_an
+            .values()
+            .filter_map(|by_srv| by_srv.get(&service))
+            .sum()
+    };
+
+    // Sin cupos la producción se reparte en proporción al consumo
+    let comps = comps_str.parse::<Components>().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!((imputed(&ep, Service::ACS) - 600.0).abs() < 0.01);
+    assert!((imputed(&ep, Service::CAL) - 600.0).abs() < 0.01);
+
+    // Cupo del 20% para ACS: el resto de la producción no se puede usar en el edificio
+    let comps = format!("#META CTE_CUPO_PRODUCCION_ACS: 0.2\n{}", comps_str)
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let bal = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    assert!((imputed(&ep, Service::ACS) - 120.0).abs() < 0.01);
+    assert!((imputed(&ep, Service::CAL) - 600.0).abs() < 0.01);
+    assert!((bal.prod.epus_an - 720.0).abs() < 0.01);
+    assert!((bal.exp.an - 480.0).abs() < 0.01);
+    assert!(ep.to_plain().contains(
+        "ACS: cupo = 20.0 [%], consumo = 600.0 [kWh], producción imputada = 120.0 [kWh] (20.0 %)"
+    ));
+
+    // Cupo fuera de rango
+    let comps = format!("#META CTE_CUPO_PRODUCCION_ACS: 1.5\n{}", comps_str)
+        .parse::<Components>()
+        .unwrap();
+    assert!(energy_performance(&comps, &FP, TESTKEXP, 1.0, false).is_err());
+}
+
+#[test]
+fn cte_produccion_imputada_a_servicio() {
+    let FP = get_ctefp_peninsula();
+    let comps_str = "1, CONSUMO, CAL, ELECTRICIDAD, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50
+2, CONSUMO, ACS, ELECTRICIDAD, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50
+3, PRODUCCION, EL_INSITU, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30
+4, PRODUCCION, EL_INSITU, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50, 50";
+    let imputed = |ep: &EnergyPerformance, service: Service| -> f32 {
+        ep.balance_cr[&Carrier::ELECTRICIDAD]
+            .prod
+            .epus_by_srv_by_src_an
+            .values()
+            .filter_map(|by_srv| by_srv.get(&service))
+            .sum()
+    };
+
+    // Sin imputación la producción se reparte en proporción al consumo
+    let comps = comps_str.parse::<Components>().unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!((imputed(&ep, Service::ACS) - 480.0).abs() < 0.01);
+    assert!((imputed(&ep, Service::CAL) - 480.0).abs() < 0.01);
+
+    // Producción fotovoltaica dedicada a ACS: cubre primero su consumo
+    let comps = format!("{} # FV para ACS CTEEPBD_SERVICIO=ACS", comps_str)
+        .parse::<Components>()
+        .unwrap();
+    let ep_ded = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!((imputed(&ep_ded, Service::ACS) - 600.0).abs() < 0.01);
+    assert!((imputed(&ep_ded, Service::CAL) - 360.0).abs() < 0.01);
+    // El balance del vector no cambia
+    let bal = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    let bal_ded = &ep_ded.balance_cr[&Carrier::ELECTRICIDAD];
+    assert!((bal.prod.epus_an - bal_ded.prod.epus_an).abs() < 0.01);
+    assert!(approx_equal(ep.balance.we.b, ep_ded.balance.we.b));
+
+    // Servicio desconocido o no EPB
+    for srv in ["XXX", "NEPB"] {
+        let comps = format!("{} # CTEEPBD_SERVICIO={}", comps_str, srv)
+            .parse::<Components>()
+            .unwrap();
+        assert!(energy_performance(&comps, &FP, TESTKEXP, 1.0, false).is_err());
+    }
+}
+
+#[test]
+fn cte_balance_por_sistema() {
+    let FP = get_ctefp_peninsula();
+    let comps = "1, CONSUMO, CAL, GASNATURAL, 100.0 # Caldera
+1, SALIDA, CAL, 90.0
+2, CONSUMO, CAL, ELECTRICIDAD, 30.0 # Bomba de calor
+2, CONSUMO, ACS, ELECTRICIDAD, 10.0 # Bomba de calor
+3, CONSUMO, ILU, ELECTRICIDAD, 20.0
+4, PRODUCCION, EL_INSITU, 15.0 # FV"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let by_sys = &ep.balance_by_system;
+    assert_eq!(by_sys.keys().copied().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+
+    let sys1 = &by_sys[&1];
+    assert_eq!(sys1.used_by_cr[&Carrier::GASNATURAL], 100.0);
+    assert_eq!(sys1.out_by_srv[&Service::CAL], 90.0);
+    assert!(approx_equal(
+        sys1.we_b,
+        ep.balance_cr[&Carrier::GASNATURAL].we.b
+    ));
+
+    // La energía ponderada de la electricidad se reparte en proporción al consumo
+    let sys2 = &by_sys[&2];
+    assert_eq!(sys2.used, 40.0);
+    assert_eq!(sys2.used_by_srv[&Service::ACS], 10.0);
+    let we_el = ep.balance_cr[&Carrier::ELECTRICIDAD].we.b;
+    assert!(approx_equal(sys2.we_b, (40.0 / 60.0) * we_el));
+
+    // Sistema solo productor
+    let sys4 = &by_sys[&4];
+    assert_eq!(sys4.prod_by_src[&ProdSource::EL_INSITU], 15.0);
+    assert_eq!(sys4.used, 0.0);
+    assert_eq!(sys4.we_b, RenNrenCo2::default());
+
+    // La suma de los sistemas es el balance total
+    let total = by_sys
+        .values()
+        .fold(RenNrenCo2::default(), |acc, sys| acc + sys.we_b);
+    assert!(approx_equal(total, ep.balance.we.b));
+}
+
+#[test]
+fn cte_flujos_vector_origen_servicio() {
+    let FP = get_ctefp_peninsula();
+    let comps = "1, CONSUMO, CAL, GASNATURAL, 100.0
+2, CONSUMO, CAL, ELECTRICIDAD, 30.0
+2, CONSUMO, ACS, ELECTRICIDAD, 10.0
+3, PRODUCCION, EL_INSITU, 20.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let flow = |carrier: Carrier, source: Source, service: Service| {
+        ep.flows
+            .iter()
+            .find(|f| f.carrier == carrier && f.source == source && f.service == service)
+    };
+    assert_eq!(ep.flows.len(), 5);
+    assert_eq!(
+        flow(Carrier::GASNATURAL, Source::RED, Service::CAL)
+            .unwrap()
+            .kwh,
+        100.0
+    );
+    assert!(flow(Carrier::GASNATURAL, Source::INSITU, Service::CAL).is_none());
+    // La producción in situ se reparte en proporción al consumo de cada servicio
+    let el_cal_red = flow(Carrier::ELECTRICIDAD, Source::RED, Service::CAL).unwrap();
+    let el_cal_onst = flow(Carrier::ELECTRICIDAD, Source::INSITU, Service::CAL).unwrap();
+    assert_eq!(format!("{:.2}", el_cal_red.kwh), "15.00");
+    assert_eq!(format!("{:.2}", el_cal_onst.kwh), "15.00");
+    assert!(el_cal_red.we_b.nren > el_cal_onst.we_b.nren);
+
+    // La energía ponderada de los flujos de un servicio es la del servicio
+    let bal_el = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    assert!(approx_equal(
+        el_cal_red.we_b + el_cal_onst.we_b,
+        bal_el.we.b_by_srv[&Service::CAL]
+    ));
+    let total = ep
+        .flows
+        .iter()
+        .fold(RenNrenCo2::default(), |acc, f| acc + f.we_b);
+    assert!(approx_equal(total, ep.balance.we.b));
+
+    // Se incluyen en la salida JSON
+    let json = serde_json::to_string(&ep).unwrap();
+    assert!(json.contains("\"flows\":[{\"carrier\":\"ELECTRICIDAD\""));
+}
+
+#[test]
+fn cte_eambiente_por_tipo_de_fuente() {
+    let FP = get_ctefp_peninsula();
+    // Misma instalación con energía ambiente genérica y desglosada por fuente
+    let comps_generic = "1, CONSUMO, CAL, ELECTRICIDAD, 100 # BdC aire-agua
+1, CONSUMO, CAL, EAMBIENTE, 200 # BdC aire-agua
+2, CONSUMO, ACS, ELECTRICIDAD, 50 # BdC geotérmica
+2, CONSUMO, ACS, EAMBIENTE, 150 # BdC geotérmica"
+        .parse::<Components>()
+        .unwrap();
+    let comps_split = "1, CONSUMO, CAL, ELECTRICIDAD, 100 # BdC aire-agua
+1, CONSUMO, CAL, EAMBIENTE_AIRE, 200 # BdC aire-agua
+2, CONSUMO, ACS, ELECTRICIDAD, 50 # BdC geotérmica
+2, CONSUMO, ACS, EAMBIENTE_GEO, 150 # BdC geotérmica"
+        .parse::<Components>()
+        .unwrap();
+    let ep_generic = energy_performance(&comps_generic, &FP, TESTKEXP, 1.0, false).unwrap();
+    let ep_split = energy_performance(&comps_split, &FP, TESTKEXP, 1.0, false).unwrap();
+
+    // Los subtipos usan por defecto los factores de EAMBIENTE: el balance global no cambia
+    assert!(approx_equal(ep_generic.balance.we.b, ep_split.balance.we.b));
+    assert!((ep_generic.rer - ep_split.rer).abs() < 1e-4);
+
+    // Desglose de la energía capturada por tipo de fuente
+    assert!(!ep_split.balance_cr.contains_key(&Carrier::EAMBIENTE));
+    let prod = |cr: Carrier| ep_split.balance_cr[&cr].prod.an;
+    assert!((prod(Carrier::EAMBIENTE_AIRE) - 200.0).abs() < 0.01);
+    assert!((prod(Carrier::EAMBIENTE_GEO) - 150.0).abs() < 0.01);
+    let plain = ep_split.to_plain();
+    assert!(plain.contains("** Energía ambiente capturada por tipo de fuente"));
+    assert!(plain.contains("EAMBIENTE_GEO (geotermia): capturada = 150.0 [kWh]"));
+    assert!(!ep_generic
+        .to_plain()
+        .contains("** Energía ambiente capturada por tipo de fuente"));
+
+    // Factores de paso propios de un subtipo
+    let FP_GEO = format!(
+        "{}\nEAMBIENTE_GEO, RED, SUMINISTRO, A, 0.900, 0.100, 0.000\nEAMBIENTE_GEO, INSITU, SUMINISTRO, A, 0.900, 0.100, 0.000",
+        FP
+    )
+    .parse::<Factors>()
+    .unwrap();
+    let ep_geo = energy_performance(&comps_split, &FP_GEO, TESTKEXP, 1.0, false).unwrap();
+    assert!((ep_geo.balance_cr[&Carrier::EAMBIENTE_GEO].we.b.nren - 15.0).abs() < 0.01);
+}
+
+#[test]
+fn cte_progreso_y_cancelacion() {
+    let FP = get_ctefp_peninsula();
+    let comps = "1, CONSUMO, CAL, ELECTRICIDAD, 10, 10
+1, CONSUMO, ACS, GASNATURAL, 5, 5
+2, CONSUMO, NEPB, ELECTRICIDAD, 2, 2"
+        .parse::<Components>()
+        .unwrap();
+    let tolerances = Tolerances::default();
+
+    // Progreso: balances por vector y del edificio completo (hay consumos no EPB)
+    let steps = std::cell::RefCell::new(Vec::new());
+    let on_progress = |p: &Progress| steps.borrow_mut().push((p.carrier, p.done, p.total));
+    let hooks = CalcHooks {
+        on_progress: Some(&on_progress),
+        cancel: None,
+    };
+    let ep = energy_performance_with_hooks(&comps, &FP, TESTKEXP, 1.0, false, &tolerances, &hooks)
+        .unwrap();
+    let ep_ref = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!(approx_equal(ep.balance.we.b, ep_ref.balance.we.b));
+    assert_eq!(
+        *steps.borrow(),
+        vec![
+            (Carrier::ELECTRICIDAD, 1, 4),
+            (Carrier::GASNATURAL, 2, 4),
+            (Carrier::ELECTRICIDAD, 3, 4),
+            (Carrier::GASNATURAL, 4, 4)
+        ]
+    );
+
+    // Cancelación desde la función de progreso tras el primer vector
+    let token = CancelToken::new();
+    let cancel_on_progress = |_: &Progress| token.cancel();
+    let hooks = CalcHooks {
+        on_progress: Some(&cancel_on_progress),
+        cancel: Some(&token),
+    };
+    let res = energy_performance_with_hooks(&comps, &FP, TESTKEXP, 1.0, false, &tolerances, &hooks);
+    assert!(matches!(res, Err(cteepbd::error::EpbdError::Cancelled)));
+}
+
+#[test]
+fn cte_balance_paso_diario() {
+    let FP = get_ctefp_peninsula();
+    let series = |value: f32, n: usize| {
+        std::iter::repeat_n(format!("{:.1}", value), n)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let comps_str = format!(
+        "#META CTE_RESOLUCION: DIARIA\n1, CONSUMO, CAL, ELECTRICIDAD, {}\n0, PRODUCCION, EL_INSITU, {}",
+        series(10.0, 365),
+        series(5.0, 365)
+    );
+    let comps = comps_str.parse::<Components>().unwrap();
+    assert_eq!(comps.resolution().unwrap(), Some(Resolution::DIARIA));
+
+    // Sin factor de coincidencia se usa toda la producción
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let bal = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    assert!((bal.prod.epus_an - 1825.0).abs() < 0.1);
+    let plain = ep.to_plain();
+    assert!(plain
+        .contains("** Balance mensual por vector energético (agregado desde resolución DIARIA)"));
+    assert!(plain.contains("  Consumo EPB [kWh]: 310.0, 280.0, 310.0, 300.0"));
+
+    // Con factor de coincidencia estadístico en paso diario (x = 0.5, f_match = 0.6)
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, true).unwrap();
+    let bal = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    assert!((bal.prod.epus_an - 0.6 * 1825.0).abs() < 0.5);
+
+    // En paso horario el factor de coincidencia es la unidad
+    let comps_h = format!(
+        "1, CONSUMO, CAL, ELECTRICIDAD, {}\n0, PRODUCCION, EL_INSITU, {}",
+        series(1.0, 8760),
+        series(0.5, 8760)
+    )
+    .parse::<Components>()
+    .unwrap();
+    let ep_h = energy_performance(&comps_h, &FP, TESTKEXP, 1.0, true).unwrap();
+    assert!((ep_h.balance_cr[&Carrier::ELECTRICIDAD].prod.epus_an - 4380.0).abs() < 0.5);
+    let ep_h = energy_performance(&comps_h, &FP, TESTKEXP, 1.0, LoadMatchingMode::Hourly).unwrap();
+    assert!((ep_h.balance_cr[&Carrier::ELECTRICIDAD].prod.epus_an - 4380.0).abs() < 0.5);
+
+    // Expresión mensual con parámetros k y n (x = 0.5, k = 2, n = 1, f_match = 3.5 / 4.5)
+    let mode = LoadMatchingMode::Monthly { k: 2.0, n: 1.0 };
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, mode).unwrap();
+    let bal = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    assert!((bal.prod.epus_an - 3.5 / 4.5 * 1825.0).abs() < 0.5);
+
+    // El modo horario requiere datos horarios
+    assert!(energy_performance(&comps, &FP, TESTKEXP, 1.0, LoadMatchingMode::Hourly).is_err());
+
+    // Resolución declarada distinta de la de los datos
+    assert!(format!(
+        "#META CTE_RESOLUCION: DIARIA\n1, CONSUMO, CAL, ELECTRICIDAD, {}",
+        series(10.0, 12)
+    )
+    .parse::<Components>()
+    .is_err());
+}
+
+/// Balance con pasos horarios (8760 valores) y desfase diario entre producción y consumo
+#[test]
+fn cte_balance_horario_perfil_diario() {
+    let FP = get_ctefp_peninsula();
+    let hourly = |f: &dyn Fn(usize) -> f32| {
+        (0..8760)
+            .map(|h| format!("{:.2}", f(h % 24)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    // Consumo constante y producción fotovoltaica de 8 a 16 h
+    let comps = format!(
+        "1, CONSUMO, CAL, ELECTRICIDAD, {}\n2, CONSUMO, ACS, ELECTRICIDAD, {}\n0, PRODUCCION, EL_INSITU, {}",
+        hourly(&|_| 0.75),
+        hourly(&|_| 0.25),
+        hourly(&|h| if (8..16).contains(&h) { 3.0 } else { 0.0 })
+    )
+    .parse::<Components>()
+    .unwrap();
+    assert_eq!(comps.num_steps(), 8760);
+    assert_eq!(comps.resolution().unwrap(), Some(Resolution::HORARIA));
+
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, true).unwrap();
+    let bal = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    assert_eq!(bal.used.epus_t.len(), 8760);
+    assert!(bal.f_match.iter().all(|f| *f == 1.0));
+    // Solo se usa la producción de las horas de sol: 8 kWh/día usados, 16 kWh/día exportados
+    assert!((bal.used.epus_an - 8760.0).abs() < 0.5);
+    assert!((bal.prod.epus_an - 2920.0).abs() < 0.5);
+    assert!((bal.exp.an - 5840.0).abs() < 0.5);
+    assert!((bal.del.grid_an - 5840.0).abs() < 0.5);
+    // Reparto de la producción usada proporcional al consumo de cada servicio
+    let prod_by_srv = &bal.prod.epus_by_srv_by_src_an[&ProdSource::EL_INSITU];
+    assert!((prod_by_srv[&Service::CAL] - 2190.0).abs() < 0.5);
+    assert!((prod_by_srv[&Service::ACS] - 730.0).abs() < 0.5);
+
+    // Con datos anuales agregados toda la producción cubre el consumo
+    let comps_an = "1, CONSUMO, CAL, ELECTRICIDAD, 6570.0
+2, CONSUMO, ACS, ELECTRICIDAD, 2190.0
+0, PRODUCCION, EL_INSITU, 8760.0"
+        .parse::<Components>()
+        .unwrap();
+    let ep_an = energy_performance(&comps_an, &FP, TESTKEXP, 1.0, false).unwrap();
+    let bal_an = &ep_an.balance_cr[&Carrier::ELECTRICIDAD];
+    assert!((bal_an.prod.epus_an - 8760.0).abs() < 0.5);
+    assert!(ep.balance.we.a.nren > ep_an.balance.we.a.nren);
+
+    // Resultados mensuales agregados desde la resolución horaria
+    let plain = ep.to_plain();
+    assert!(plain
+        .contains("** Balance mensual por vector energético (agregado desde resolución HORARIA)"));
+}
+
+#[test]
+fn cte_balance_por_temporadas() {
+    let comps = components_from_file("test_data/temporadas_servicios.csv");
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 10.0, false).unwrap();
+    let cal = &ep.seasons[&Service::CAL];
+    assert_eq!(cal.months, vec![1, 2, 3, 11, 12]);
+    assert_eq!(format!("{:.2}", cal.used_in), "450.00");
+    assert_eq!(format!("{:.2}", cal.used_off), "10.00");
+    assert_eq!(format!("{:.2}", cal.used_by_month[9]), "10.00");
+    let ref_season = &ep.seasons[&Service::REF];
+    assert_eq!(format!("{:.2}", ref_season.used_off), "0.00");
+    // La energía ponderada en temporada y fuera de ella suma la del servicio
+    assert_eq!(
+        format!("{:.2}", (cal.we_b_in + cal.we_b_off).tot()),
+        format!("{:.2}", ep.balance.we.b_by_srv[&Service::CAL].tot())
+    );
+
+    // Sin temporadas declaradas
+    let comps = components_from_file("test_data/caldera_gasoleo.csv");
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!(ep.seasons.is_empty());
+}
+
+#[test]
+fn cte_factores_paso_por_pasos_de_calculo() {
+    let comps = "CONSUMO,CAL,ELECTRICIDAD,100,0,0,0,0,0,0,0,0,0,0,0
+PRODUCCION,EL_INSITU,0,0,0,0,0,0,100,0,0,0,0,0"
+        .parse::<Components>()
+        .unwrap();
+    let user_wf = UserWF::default();
+    let fp_with_steps = |values_t: &[&str]| {
+        let fp_str = format!(
+            "ELECTRICIDAD, RED, SUMINISTRO, A, 0.5, 1.5, 0.3, {}",
+            values_t.join(", ")
+        );
+        wfactors_from_str(&fp_str, user_wf, CTE_USERWF).unwrap()
+    };
+    let mut values_t = vec!["0.5, 1.5, 0.3"; 12];
+
+    // Sin serie por pasos de cálculo
+    let fp_an = wfactors_from_str(
+        "ELECTRICIDAD, RED, SUMINISTRO, A, 0.5, 1.5, 0.3",
+        user_wf,
+        CTE_USERWF,
+    )
+    .unwrap();
+    let ep_an = energy_performance(&comps, &fp_an, 1.0, 1.0, false).unwrap();
+    assert!(approx_equal(
+        RenNrenCo2::new(150.0, 150.0, 30.0),
+        ep_an.balance.we.del
+    ));
+    assert!(approx_equal(
+        RenNrenCo2::new(100.0, 0.0, 0.0),
+        ep_an.balance.we.b
+    ));
+
+    // Serie por pasos de cálculo constante e igual a los valores anuales
+    let ep_const = energy_performance(&comps, &fp_with_steps(&values_t), 1.0, 1.0, false).unwrap();
+    assert!(approx_equal(ep_an.balance.we.a, ep_const.balance.we.a));
+    assert!(approx_equal(ep_an.balance.we.b, ep_const.balance.we.b));
+
+    // Serie variable: se pondera el suministro en enero y la exportación en julio
+    values_t[0] = "0.5, 2.0, 0.42";
+    values_t[6] = "0.5, 1.0, 0.18";
+    let ep_t = energy_performance(&comps, &fp_with_steps(&values_t), 1.0, 1.0, false).unwrap();
+    assert!(approx_equal(
+        RenNrenCo2::new(150.0, 200.0, 42.0),
+        ep_t.balance.we.del
+    ));
+    assert!(approx_equal(
+        RenNrenCo2::new(50.0, 200.0, 42.0),
+        ep_t.balance.we.a
+    ));
+    assert!(approx_equal(
+        RenNrenCo2::new(100.0, 100.0, 24.0),
+        ep_t.balance.we.b
+    ));
+
+    // Serie con un número de valores distinto al de pasos de cálculo
+    assert!(energy_performance(&comps, &fp_with_steps(&values_t[..3]), 1.0, 1.0, false).is_err());
+}
+
+#[test]
+fn cte_almacenamiento_electrico() {
+    let comps = components_from_file("test_data/bateria_fv.csv");
+    assert_eq!(comps.storages.len(), 1);
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let el = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    // Se cargan 10 kWh en los pasos con excedente y se descargan 9 kWh en los siguientes
+    assert_eq!(format!("{:.2}", el.exp.an), "0.00");
+    assert_eq!(format!("{:.2}", el.del.grid_an), "6.00");
+    assert_eq!(format!("{:.2}", el.prod.an), "114.00");
+    assert_eq!(format!("{:.2}", el.prod.sto_an), "54.00");
+    assert_eq!(format!("{:.2}", el.prod.epus_an), "114.00");
+
+    // La descarga de la batería es energía liberada desde almacenamiento: con factor de coincidencia
+    // de cargas mensual la parte exportada de la descarga es exportación diferida (A_RED_DIFERIDA)
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, true).unwrap();
+    let el = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    assert_eq!(format!("{:.2}", el.prod.sto_an), "54.00");
+    assert_eq!(format!("{:.2}", el.exp.grid_an), "56.85");
+    assert_eq!(format!("{:.2}", el.exp.grid_sto_an), "26.85");
+    assert_eq!(el.exp.grid_sto_t[0], 0.0);
+
+    // Sin batería se exporta el excedente y se suministra el déficit desde la red
+    let mut comps = comps;
+    comps.storages.clear();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    let el = &ep.balance_cr[&Carrier::ELECTRICIDAD];
+    assert_eq!(format!("{:.2}", el.exp.an), "60.00");
+    assert_eq!(format!("{:.2}", el.del.grid_an), "60.00");
+}
+
+/// Componentes duplicados: detección y tratamiento configurable
+#[test]
+fn cte_componentes_duplicados() {
+    let comps = components_from_file("test_data/componentes_duplicados.csv");
+    let duplicates = comps.duplicates();
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates[0].count, 2);
+    assert!(duplicates[0]
+        .component
+        .starts_with("1, CONSUMO, CAL, GASNATURAL"));
+    let FP = get_ctefp_peninsula();
+
+    // Se suman, sin aviso
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!(ep.duplicates.is_empty());
+    assert_eq!(
+        "880.00",
+        format!("{:.2}", ep.balance.used.epus_by_srv[&Service::CAL])
+    );
+
+    // Se suman, con aviso
+    let tolerances = Tolerances {
+        duplicates: DuplicatePolicy::Warn,
+        ..Default::default()
+    };
+    let ep =
+        energy_performance_with_tolerances(&comps, &FP, TESTKEXP, 1.0, false, &tolerances).unwrap();
+    assert_eq!(ep.duplicates, duplicates);
+    assert_eq!(
+        "880.00",
+        format!("{:.2}", ep.balance.used.epus_by_srv[&Service::CAL])
+    );
+
+    // Error
+    let tolerances = Tolerances {
+        duplicates: DuplicatePolicy::Error,
+        ..Default::default()
+    };
+    assert!(
+        energy_performance_with_tolerances(&comps, &FP, TESTKEXP, 1.0, false, &tolerances).is_err()
+    );
+}
+
+/// Año incompleto: extrapolación de los meses no medidos con perfiles mensuales
+#[test]
+fn cte_anyo_incompleto() {
+    let comps = components_from_file("test_data/anyo_incompleto.csv");
+    assert_eq!(
+        comps.measured_months().unwrap(),
+        Some(vec![1, 2, 3, 4, 5, 6, 7, 8])
+    );
+    let FP = get_ctefp_peninsula();
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+
+    // CAL: 500 kWh medidos con el 50% del perfil -> 1000 kWh anuales
+    // ACS: 80 kWh medidos en 8 meses con perfil uniforme -> 120 kWh anuales
+    assert_eq!(
+        "1000.00",
+        format!("{:.2}", ep.balance.used.epus_by_srv[&Service::CAL])
+    );
+    assert_eq!(
+        "120.00",
+        format!("{:.2}", ep.balance.used.epus_by_srv[&Service::ACS])
+    );
+    let partial = ep.partial_year.as_ref().unwrap();
+    assert_eq!(format!("{:.2}", partial.used_measured), "580.00");
+    assert_eq!(format!("{:.2}", partial.used_estimated), "540.00");
+    assert_eq!(format!("{:.3}", partial.measured_fraction()), "0.518");
+    assert!(ep.to_plain().contains("Indicadores PROVISIONALES"));
+
+    // Sin meses medidos declarados
+    let comps = components_from_file("test_data/caldera_gasoleo.csv");
+    let ep = energy_performance(&comps, &FP, TESTKEXP, 1.0, false).unwrap();
+    assert!(ep.partial_year.is_none());
+
+    // Perfil incorrecto y datos no mensuales
+    let comps = "#META CTE_MESES_MEDIDOS: 1-8
+#META CTE_PERFIL: 1, 2, 3
+CONSUMO, CAL, GASNATURAL, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1"
+        .parse::<Components>()
+        .unwrap();
+    assert!(energy_performance(&comps, &FP, TESTKEXP, 1.0, false).is_err());
+    let comps = format!(
+        "#META CTE_MESES_MEDIDOS: 1-8\nCONSUMO, CAL, GASNATURAL, {}",
+        vec!["1.0"; 365].join(", ")
+    )
+    .parse::<Components>()
+    .unwrap();
+    assert!(energy_performance(&comps, &FP, TESTKEXP, 1.0, false).is_err());
+}